@@ -9,13 +9,15 @@ use quote::quote;
 use syn::Type;
 use syn::{
     parse_macro_input, AngleBracketedGenericArguments, Attribute, Generics, ItemStruct, Lit, Meta,
-    PathArguments,
+    NestedMeta, PathArguments,
 };
 
 // This is used as default when none is specified
 const DEFAULT_DB_OPTIONS_CUSTOM_FN: &str = "typed_store::rocks::default_db_options";
 // Custom function which returns the option and overrides the defaults for this table
 const DB_OPTIONS_CUSTOM_FUNCTION: &str = "default_options_override_fn";
+// Declares a column as a secondary index of another column of the struct
+const INDEX_DECLARATION: &str = "index";
 
 /// Options can either be simplified form or
 enum GeneralTableOptions {
@@ -139,6 +141,126 @@ fn get_options_override_function(attr: &Attribute) -> syn::Result<String> {
     Ok(fn_name.value())
 }
 
+/// Extracts the indexed column and the index key function of an index declaration
+/// The function must have the signature of a `typed_store::sally::index::IndexKeyFn`
+fn get_index_declaration(attr: &Attribute) -> syn::Result<(Ident, syn::Path)> {
+    let meta = attr.parse_meta()?;
+    let format_error = || {
+        syn::Error::new_spanned(
+            &meta,
+            format!("Expected index declaration in format `#[{INDEX_DECLARATION}(of = \"{{column_name}}\", key = \"{{function_name}}\")]`"),
+        )
+    };
+
+    let list = match &meta {
+        Meta::List(list) if list.path.is_ident(INDEX_DECLARATION) => list,
+        _ => return Err(format_error()),
+    };
+
+    let (mut of, mut key) = (None, None);
+    for nested in &list.nested {
+        let val = match nested {
+            NestedMeta::Meta(Meta::NameValue(val)) => val,
+            _ => return Err(format_error()),
+        };
+        let value = match &val.lit {
+            Lit::Str(value) => value,
+            _ => return Err(format_error()),
+        };
+        if val.path.is_ident("of") {
+            of = Some(value.parse::<Ident>()?);
+        } else if val.path.is_ident("key") {
+            key = Some(value.parse::<syn::Path>()?);
+        } else {
+            return Err(format_error());
+        }
+    }
+    match (of, key) {
+        (Some(of), Some(key)) => Ok((of, key)),
+        _ => Err(format_error()),
+    }
+}
+
+/// Generates the accessors of the secondary indexes declared on the columns of the struct, with
+/// `#[index(of = "{column_name}", key = "{function_name}")]`. The columns are converted into
+/// `SallyColumn`s, so they can be `SallyColumn`s or `DBMap`s
+fn generate_index_fns(
+    input: &ItemStruct,
+    field_names: &[Ident],
+    inner_types: &[AngleBracketedGenericArguments],
+) -> Vec<proc_macro2::TokenStream> {
+    // The index declarations, grouped by indexed column in the order of the fields
+    let mut indexes: BTreeMap<usize, Vec<(Ident, syn::Path, &syn::GenericArgument)>> =
+        BTreeMap::new();
+    for (field, inner_type) in input.fields.iter().zip(inner_types) {
+        let attr = match field
+            .attrs
+            .iter()
+            .find(|a| a.path.is_ident(INDEX_DECLARATION))
+        {
+            Some(attr) => attr,
+            None => continue,
+        };
+        let (of, key) = get_index_declaration(attr).unwrap();
+        let position = field_names
+            .iter()
+            .position(|field_name| *field_name == of)
+            .unwrap_or_else(|| panic!("No such column to index: {of}"));
+        indexes.entry(position).or_default().push((
+            field.ident.as_ref().unwrap().clone(),
+            key,
+            inner_type.args.first().unwrap(),
+        ));
+    }
+    let index_bounds_token: proc_macro2::TokenStream =
+        "serde::Serialize + serde::de::DeserializeOwned + Send + Sync + 'static"
+            .parse()
+            .unwrap();
+    indexes.iter().map(|(position, indexes)| {
+        let column_name = &field_names[*position];
+        let inner_type = &inner_types[*position];
+        let (key_name, value_name) = (inner_type.args.first().unwrap(), inner_type.args.last().unwrap());
+        let indexed_fn_name = quote::format_ident!("{column_name}_indexed");
+        let (index_names, index_fn_names): (Vec<_>, Vec<_>) = indexes
+            .iter()
+            .map(|(index_name, _, _)| (index_name, quote::format_ident!("{index_name}_index")))
+            .unzip();
+        let index_key_fns = indexes.iter().map(|(_, key, _)| key);
+        let index_key_names: Vec<_> = indexes
+            .iter()
+            .map(|(_, _, index_key_name)| index_key_name)
+            .collect();
+        quote! {
+            #(
+                /// The secondary index held in the column of the same name
+                pub fn #index_fn_names(&self) -> typed_store::sally::index::SecondaryIndex<#key_name, #value_name, #index_key_names>
+                where
+                    #index_key_names: #index_bounds_token + PartialEq,
+                {
+                    typed_store::sally::index::SecondaryIndex::new(self.#index_names.clone().into(), #index_key_fns)
+                }
+            )*
+
+            /// The column with all the indexes declared on it. The writes of the column are
+            /// serialized by a lock owned by the returned value, so it should be built once and
+            /// shared by all the writers of the column
+            pub fn #indexed_fn_name(&self) -> typed_store::sally::index::IndexedColumn<#key_name, #value_name>
+            where
+                #key_name: #index_bounds_token + Clone,
+                #value_name: #index_bounds_token,
+                #(
+                    #index_key_names: #index_bounds_token + PartialEq,
+                )*
+            {
+                typed_store::sally::index::IndexedColumn::new(self.#column_name.clone().into())
+                    #(
+                        .with_index(&self.#index_fn_names())
+                    )*
+            }
+        }
+    }).collect()
+}
+
 fn extract_generics_names(generics: &Generics) -> Vec<Ident> {
     generics
         .params
@@ -269,6 +391,10 @@ fn extract_generics_names(generics: &Generics) -> Vec<Ident> {
 ///
 /// 5. Other convenience features
 /// `Tables::describe_tables` is used to get a list of the table names and key-value types as string in a BTreeMap
+/// A table can be declared as a secondary index of another table with
+/// `#[index(of = "{table_name}", key = "{function_name}")]`, as with `SallyDB`: `{field_name}_index`
+/// then returns the index, and `{table_name}_indexed` the `IndexedColumn` maintaining all the
+/// indexes of the table
 ///
 /// // Bad usage example
 /// // Structs fields most only be of type Store<K, V> or DMBap<K, V>
@@ -279,7 +405,7 @@ fn extract_generics_names(generics: &Generics) -> Vec<Ident> {
 /// //     bad_field: u32,
 /// // #}
 
-#[proc_macro_derive(DBMapUtils, attributes(default_options_override_fn))]
+#[proc_macro_derive(DBMapUtils, attributes(default_options_override_fn, index))]
 pub fn derive_dbmap_utils_general(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as ItemStruct);
     let name = &input.ident;
@@ -320,6 +446,8 @@ pub fn derive_dbmap_utils_general(input: TokenStream) -> TokenStream {
         "std::fmt::Debug + serde::Serialize + for<'de> serde::de::Deserialize<'de>";
     let generics_bounds_token: proc_macro2::TokenStream = generics_bounds.parse().unwrap();
 
+    let index_fns = generate_index_fns(&input, &field_names, &inner_types);
+
     let config_struct_name_str = format!("{name}Configurator");
     let config_struct_name: proc_macro2::TokenStream = config_struct_name_str.parse().unwrap();
 
@@ -489,6 +617,10 @@ pub fn derive_dbmap_utils_general(input: TokenStream) -> TokenStream {
                 )*].into_iter().collect()
             }
 
+            #(
+                #index_fns
+            )*
+
             /// This opens the DB in read only mode and returns a struct which exposes debug features
             pub fn get_read_only_handle (
                 primary_path: std::path::PathBuf,
@@ -632,7 +764,14 @@ pub fn derive_dbmap_utils_general(input: TokenStream) -> TokenStream {
     })
 }
 
-#[proc_macro_derive(SallyDB, attributes(default_options_override_fn))]
+/// A helper macro to open and debug a struct of `SallyColumn`s, like `DBMapUtils` for `DBMap`s
+///
+/// A column can be declared as a secondary index of another column of the struct with
+/// `#[index(of = "{column_name}", key = "{function_name}")]`: a `SallyColumn<I, K>` indexing a
+/// `SallyColumn<K, V>` maps the key `function_name(&K, &V) -> I` of each entry of the column back
+/// to its key. `{field_name}_index` then returns the index, and `{column_name}_indexed` the
+/// `IndexedColumn` maintaining all the indexes of the column.
+#[proc_macro_derive(SallyDB, attributes(default_options_override_fn, index))]
 pub fn derive_sallydb_general(input: TokenStream) -> TokenStream {
     //log_syntax!("here");
     let input = parse_macro_input!(input as ItemStruct);
@@ -675,6 +814,8 @@ pub fn derive_sallydb_general(input: TokenStream) -> TokenStream {
         "std::fmt::Debug + serde::Serialize + for<'de> serde::de::Deserialize<'de>";
     let generics_bounds_token: proc_macro2::TokenStream = generics_bounds.parse().unwrap();
 
+    let index_fns = generate_index_fns(&input, &field_names, &inner_types);
+
     let config_struct_name_str = format!("{name}SallyConfigurator");
     let sally_config_struct_name: proc_macro2::TokenStream =
         config_struct_name_str.parse().unwrap();
@@ -838,6 +979,10 @@ pub fn derive_sallydb_general(input: TokenStream) -> TokenStream {
                 )*].into_iter().collect()
             }
 
+            #(
+                #index_fns
+            )*
+
             /// This opens the DB in read only mode and returns a struct which exposes debug features
             pub fn get_read_only_handle (
                 db_options: typed_store::sally::SallyReadOnlyDBOptions
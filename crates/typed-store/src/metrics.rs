@@ -710,6 +710,18 @@ impl DBMetrics {
             .tap_err(|_| warn!("DBMetrics registry overwritten"));
         ONCE.get().unwrap()
     }
+    /// Initializes the metrics on `registry`, unless they are already initialized, e.g. on the
+    /// default registry by a `DBMap` opened before. Returns the metrics if they were initialized.
+    pub fn try_init(registry: &Registry) -> Option<&'static Arc<DBMetrics>> {
+        // The metrics are only registered on `registry` by the call initializing them, so that
+        // the registry does not hold metrics which are never recorded.
+        let mut initialized = false;
+        let metrics = ONCE.get_or_init(|| {
+            initialized = true;
+            Arc::new(DBMetrics::new(registry))
+        });
+        initialized.then_some(metrics)
+    }
     pub fn get() -> &'static Arc<DBMetrics> {
         ONCE.get()
             .unwrap_or_else(|| DBMetrics::init(prometheus::default_registry()))
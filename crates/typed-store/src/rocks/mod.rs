@@ -7,12 +7,13 @@ pub(crate) mod safe_iter;
 pub mod util;
 pub(crate) mod values;
 
+use crate::metrics::{DBMetrics, SamplingInterval};
 use crate::traits::{Map, TableSummary};
 use bincode::Options;
 use collectable::TryExtend;
 use rocksdb::{
-    checkpoint::Checkpoint, BlockBasedOptions, BottommostLevelCompaction, Cache, CompactOptions,
-    LiveFile,
+    checkpoint::Checkpoint, properties, BlockBasedOptions, BottommostLevelCompaction, Cache,
+    CompactOptions, LiveFile,
 };
 use rocksdb::{
     AsColumnFamilyRef, CStrLike, ColumnFamilyDescriptor, DBWithThreadMode, Error, ErrorKind,
@@ -28,6 +29,7 @@ use std::{
     marker::PhantomData,
     path::{Path, PathBuf},
     sync::Arc,
+    time::Duration,
 };
 use tap::TapFallible;
 use tracing::{debug, info, instrument};
@@ -35,6 +37,7 @@ use tracing::{debug, info, instrument};
 use self::{iter::Iter, keys::Keys, values::Values};
 use crate::rocks::safe_iter::SafeIter;
 pub use errors::TypedStoreError;
+pub use rocksdb::DBCompressionType;
 use sui_macros::{fail_point, nondeterministic};
 
 // Write buffer size per RocksDB instance can be set via the env var below.
@@ -182,12 +185,14 @@ macro_rules! retry_transaction_forever {
 pub struct DBWithThreadModeWrapper {
     pub underlying: rocksdb::DBWithThreadMode<MultiThreaded>,
     pub db_path: PathBuf,
+    pub db_name: String,
 }
 
 #[derive(Debug)]
 pub struct OptimisticTransactionDBWrapper {
     pub underlying: rocksdb::OptimisticTransactionDB<MultiThreaded>,
     pub db_path: PathBuf,
+    pub db_name: String,
 }
 
 /// Thin wrapper to unify interface across different db types
@@ -280,6 +285,18 @@ impl RocksDB {
         delegate_call!(self.path())
     }
 
+    /// The name of the directory of the database, labelling its metrics.
+    pub fn db_name(&self) -> &str {
+        match self {
+            Self::DBWithThreadMode(d) => &d.db_name,
+            Self::OptimisticTransactionDB(d) => &d.db_name,
+        }
+    }
+
+    pub fn latest_sequence_number(&self) -> u64 {
+        delegate_call!(self.latest_sequence_number())
+    }
+
     pub fn put_cf<K, V>(
         &self,
         cf: &impl AsColumnFamilyRef,
@@ -312,14 +329,22 @@ impl RocksDB {
     }
 
     pub fn write(&self, batch: RocksDBBatch) -> Result<(), TypedStoreError> {
+        self.write_opt(batch, &WriteOptions::default())
+    }
+
+    pub fn write_opt(
+        &self,
+        batch: RocksDBBatch,
+        writeopts: &WriteOptions,
+    ) -> Result<(), TypedStoreError> {
         fail_point!("batch-write-before");
         let ret = match (self, batch) {
             (RocksDB::DBWithThreadMode(db), RocksDBBatch::Regular(batch)) => {
-                db.underlying.write(batch)?;
+                db.underlying.write_opt(batch, writeopts)?;
                 Ok(())
             }
             (RocksDB::OptimisticTransactionDB(db), RocksDBBatch::Transactional(batch)) => {
-                db.underlying.write(batch)?;
+                db.underlying.write_opt(batch, writeopts)?;
                 Ok(())
             }
             _ => Err(TypedStoreError::RocksDBError(
@@ -478,6 +503,10 @@ impl RocksDBBatch {
         delegate_batch_call!(self.merge_cf(cf, key, value))
     }
 
+    pub fn size_in_bytes(&self) -> usize {
+        delegate_batch_call!(self.size_in_bytes())
+    }
+
     pub fn delete_range_cf<K: AsRef<[u8]>>(
         &mut self,
         cf: &impl AsColumnFamilyRef,
@@ -496,6 +525,87 @@ impl RocksDBBatch {
     }
 }
 
+/// Reads the properties of the column family `cf_name` of `rocksdb` into the column family
+/// metrics of `DBMetrics`. The properties RocksDB does not report are skipped.
+pub fn report_cf_metrics(rocksdb: &RocksDB, cf_name: &str) {
+    let Some(cf) = rocksdb.cf_handle(cf_name) else {
+        return;
+    };
+    let cf_metrics = &DBMetrics::get().cf_metrics;
+    let gauges = [
+        (
+            &cf_metrics.rocksdb_total_sst_files_size,
+            properties::TOTAL_SST_FILES_SIZE,
+        ),
+        (
+            &cf_metrics.rocksdb_size_all_mem_tables,
+            properties::SIZE_ALL_MEM_TABLES,
+        ),
+        (&cf_metrics.rocksdb_num_snapshots, properties::NUM_SNAPSHOTS),
+        (
+            &cf_metrics.rocksdb_oldest_snapshot_time,
+            properties::OLDEST_SNAPSHOT_TIME,
+        ),
+        (
+            &cf_metrics.rocksdb_actual_delayed_write_rate,
+            properties::ACTUAL_DELAYED_WRITE_RATE,
+        ),
+        (
+            &cf_metrics.rocksdb_is_write_stopped,
+            properties::IS_WRITE_STOPPED,
+        ),
+        (
+            &cf_metrics.rocksdb_block_cache_capacity,
+            properties::BLOCK_CACHE_CAPACITY,
+        ),
+        (
+            &cf_metrics.rocksdb_block_cache_usage,
+            properties::BLOCK_CACHE_USAGE,
+        ),
+        (
+            &cf_metrics.rocksdb_block_cache_pinned_usage,
+            properties::BLOCK_CACHE_PINNED_USAGE,
+        ),
+        (
+            &cf_metrics.rocskdb_estimate_table_readers_mem,
+            properties::ESTIMATE_TABLE_READERS_MEM,
+        ),
+        (
+            &cf_metrics.rocksdb_mem_table_flush_pending,
+            properties::MEM_TABLE_FLUSH_PENDING,
+        ),
+        (
+            &cf_metrics.rocskdb_compaction_pending,
+            properties::COMPACTION_PENDING,
+        ),
+        (
+            &cf_metrics.rocskdb_num_running_compactions,
+            properties::NUM_RUNNING_COMPACTIONS,
+        ),
+        (
+            &cf_metrics.rocksdb_num_running_flushes,
+            properties::NUM_RUNNING_FLUSHES,
+        ),
+        (
+            &cf_metrics.rocksdb_estimate_oldest_key_time,
+            properties::ESTIMATE_OLDEST_KEY_TIME,
+        ),
+        (
+            &cf_metrics.rocskdb_background_errors,
+            properties::BACKGROUND_ERRORS,
+        ),
+        (
+            &cf_metrics.rocksdb_estimated_num_keys,
+            properties::ESTIMATE_NUM_KEYS,
+        ),
+    ];
+    for (gauge, property) in gauges {
+        if let Ok(Some(value)) = rocksdb.property_int_value_cf(&cf, property) {
+            gauge.with_label_values(&[cf_name]).set(value as i64);
+        }
+    }
+}
+
 /// An interface to a rocksDB database, keyed by a columnfamily
 #[derive(Clone, Debug)]
 pub struct DBMap<K, V> {
@@ -504,6 +614,11 @@ pub struct DBMap<K, V> {
     // the rocksDB ColumnFamily under which the map is stored
     cf: String,
     pub opts: ReadWriteOptions,
+    db_metrics: Arc<DBMetrics>,
+    // the operations recording their latency and size, shared by the clones of the map
+    get_sample_interval: SamplingInterval,
+    multiget_sample_interval: SamplingInterval,
+    write_sample_interval: SamplingInterval,
 }
 
 unsafe impl<K: Send, V: Send> Send for DBMap<K, V> {}
@@ -515,6 +630,10 @@ impl<K, V> DBMap<K, V> {
             opts: opts.clone(),
             _phantom: PhantomData,
             cf: opt_cf.to_string(),
+            db_metrics: DBMetrics::get().clone(),
+            get_sample_interval: SamplingInterval::default(),
+            multiget_sample_interval: SamplingInterval::default(),
+            write_sample_interval: SamplingInterval::default(),
         }
     }
 
@@ -578,7 +697,12 @@ impl<K, V> DBMap<K, V> {
                 RocksDBBatch::Transactional(WriteBatchWithTransaction::<true>::default())
             }
         };
-        DBBatch::new(&self.rocksdb, batch)
+        DBBatch::new(
+            &self.rocksdb,
+            batch,
+            &self.db_metrics,
+            &self.write_sample_interval,
+        )
     }
 
     pub fn compact_range<J: Serialize>(&self, start: &J, end: &J) -> Result<(), TypedStoreError> {
@@ -712,23 +836,52 @@ impl<K, V> DBMap<K, V> {
 pub struct DBBatch {
     rocksdb: Arc<RocksDB>,
     batch: RocksDBBatch,
+    db_metrics: Arc<DBMetrics>,
+    write_sample_interval: SamplingInterval,
 }
 
 impl DBBatch {
     /// Create a new batch associated with a DB reference.
     ///
     /// Use `open_cf` to get the DB reference or an existing open database.
-    pub fn new(dbref: &Arc<RocksDB>, batch: RocksDBBatch) -> Self {
+    pub fn new(
+        dbref: &Arc<RocksDB>,
+        batch: RocksDBBatch,
+        db_metrics: &Arc<DBMetrics>,
+        write_sample_interval: &SamplingInterval,
+    ) -> Self {
         DBBatch {
             rocksdb: dbref.clone(),
             batch,
+            db_metrics: db_metrics.clone(),
+            write_sample_interval: write_sample_interval.clone(),
         }
     }
 
     /// Consume the batch and write its operations to the database
-    #[instrument(level = "trace", skip_all, err)]
     pub fn write(self) -> Result<(), TypedStoreError> {
-        self.rocksdb.write(self.batch)?;
+        self.write_opt(&WriteOptions::default())
+    }
+
+    /// Consume the batch and write its operations to the database with the given options, e.g.
+    /// to sync the write-ahead log before returning
+    #[instrument(level = "trace", skip_all, err)]
+    pub fn write_opt(self, writeopts: &WriteOptions) -> Result<(), TypedStoreError> {
+        let db_name = self.rocksdb.db_name();
+        let timer = self.write_sample_interval.sample().then(|| {
+            self.db_metrics
+                .op_metrics
+                .rocksdb_batch_commit_bytes
+                .with_label_values(&[db_name])
+                .observe(self.batch.size_in_bytes() as f64);
+            self.db_metrics
+                .op_metrics
+                .rocksdb_batch_commit_latency_seconds
+                .with_label_values(&[db_name])
+                .start_timer()
+        });
+        self.rocksdb.write_opt(self.batch, writeopts)?;
+        drop(timer);
         Ok(())
     }
 }
@@ -1109,10 +1262,26 @@ where
 
     #[instrument(level = "trace", skip_all, err)]
     fn get(&self, key: &K) -> Result<Option<V>, TypedStoreError> {
+        let sample = self.get_sample_interval.sample();
+        let timer = sample.then(|| {
+            self.db_metrics
+                .op_metrics
+                .rocksdb_get_latency_seconds
+                .with_label_values(&[&self.cf])
+                .start_timer()
+        });
         let key_buf = be_fix_int_ser(key)?;
         let res = self
             .rocksdb
             .get_pinned_cf(&self.cf(), &key_buf, &self.opts.readopts())?;
+        drop(timer);
+        if sample {
+            self.db_metrics
+                .op_metrics
+                .rocksdb_get_bytes
+                .with_label_values(&[&self.cf])
+                .observe(res.as_ref().map_or(0.0, |data| data.len() as f64));
+        }
         match res {
             Some(data) => Ok(Some(bcs::from_bytes(&data)?)),
             None => Ok(None),
@@ -1135,6 +1304,18 @@ where
     fn insert(&self, key: &K, value: &V) -> Result<(), TypedStoreError> {
         let key_buf = be_fix_int_ser(key)?;
         let value_buf = bcs::to_bytes(value)?;
+        let _timer = self.write_sample_interval.sample().then(|| {
+            self.db_metrics
+                .op_metrics
+                .rocksdb_put_bytes
+                .with_label_values(&[&self.cf])
+                .observe((key_buf.len() + value_buf.len()) as f64);
+            self.db_metrics
+                .op_metrics
+                .rocksdb_put_latency_seconds
+                .with_label_values(&[&self.cf])
+                .start_timer()
+        });
         self.rocksdb
             .put_cf(&self.cf(), key_buf, value_buf, &self.opts.writeopts())?;
         Ok(())
@@ -1142,6 +1323,18 @@ where
 
     #[instrument(level = "trace", skip_all, err)]
     fn remove(&self, key: &K) -> Result<(), TypedStoreError> {
+        let _timer = self.write_sample_interval.sample().then(|| {
+            self.db_metrics
+                .op_metrics
+                .rocksdb_deletes
+                .with_label_values(&[&self.cf])
+                .inc();
+            self.db_metrics
+                .op_metrics
+                .rocksdb_delete_latency_seconds
+                .with_label_values(&[&self.cf])
+                .start_timer()
+        });
         let key_buf = be_fix_int_ser(key)?;
         self.rocksdb
             .delete_cf(&self.cf(), key_buf, &self.opts.writeopts())?;
@@ -1222,6 +1415,14 @@ where
     where
         J: Borrow<K>,
     {
+        let sample = self.multiget_sample_interval.sample();
+        let timer = sample.then(|| {
+            self.db_metrics
+                .op_metrics
+                .rocksdb_multiget_latency_seconds
+                .with_label_values(&[&self.cf])
+                .start_timer()
+        });
         let cf = self.cf();
 
         let keys_bytes: Result<Vec<_>, TypedStoreError> = keys
@@ -1229,10 +1430,26 @@ where
             .map(|k| Ok((&cf, be_fix_int_ser(k.borrow())?)))
             .collect();
 
-        let results = self
+        let results: Vec<Option<Vec<u8>>> = self
             .rocksdb
-            .multi_get_cf(keys_bytes?, &self.opts.readopts());
-        Ok(results.into_iter().collect::<Result<_, _>>()?)
+            .multi_get_cf(keys_bytes?, &self.opts.readopts())
+            .into_iter()
+            .collect::<Result<_, _>>()?;
+        drop(timer);
+        if sample {
+            self.db_metrics
+                .op_metrics
+                .rocksdb_multiget_bytes
+                .with_label_values(&[&self.cf])
+                .observe(
+                    results
+                        .iter()
+                        .flatten()
+                        .map(|data| data.len())
+                        .sum::<usize>() as f64,
+                );
+        }
+        Ok(results)
     }
 
     /// Returns a vector of values corresponding to the keys provided.
@@ -1392,6 +1609,44 @@ impl DBOptions {
         self.options.set_max_bytes_for_level_base(512 * 1024 * 1024);
         self
     }
+
+    // Give the table its own block cache, and bloom filters with `bloom_filter_bits_per_key` bits
+    // per key unless it is zero.
+    pub fn set_block_options(
+        mut self,
+        block_cache_size_mb: usize,
+        bloom_filter_bits_per_key: f64,
+    ) -> DBOptions {
+        let mut block_options = BlockBasedOptions::default();
+        block_options.set_block_cache(&Cache::new_lru_cache(block_cache_size_mb * 1024 * 1024));
+        if bloom_filter_bits_per_key > 0.0 {
+            block_options.set_bloom_filter(bloom_filter_bits_per_key, false);
+        }
+        block_options.set_pin_l0_filter_and_index_blocks_in_cache(true);
+        self.options.set_block_based_table_factory(&block_options);
+        self
+    }
+
+    // Compress every level of the table, including the bottommost one, with `compression`.
+    pub fn set_compression(mut self, compression: DBCompressionType) -> DBOptions {
+        self.options.set_compression_type(compression);
+        self.options.set_bottommost_compression_type(compression);
+        self
+    }
+
+    // Size the memtables of the table.
+    pub fn set_write_buffer_size_mb(mut self, write_buffer_size_mb: usize) -> DBOptions {
+        self.options
+            .set_write_buffer_size(write_buffer_size_mb * 1024 * 1024);
+        self
+    }
+
+    // Compact the files of the table older than `ttl`, to drop the deleted and overwritten data
+    // they still hold.
+    pub fn set_ttl_compaction(mut self, ttl: Duration) -> DBOptions {
+        self.options.set_periodic_compaction_seconds(ttl.as_secs());
+        self
+    }
 }
 
 /// Base options to be used across all rocksdb instances.
@@ -1557,6 +1812,7 @@ pub fn open_cf_opts<P: AsRef<Path>>(
             DBWithThreadModeWrapper {
                 underlying: rocksdb,
                 db_path: PathBuf::from(path),
+                db_name: db_name(path),
             },
         )))
     })
@@ -1584,6 +1840,7 @@ pub fn open_cf_opts_transactional<P: AsRef<Path>>(
             OptimisticTransactionDBWrapper {
                 underlying: rocksdb,
                 db_path: PathBuf::from(path),
+                db_name: db_name(path),
             },
         )))
     })
@@ -1646,11 +1903,47 @@ pub fn open_cf_opts_secondary<P: AsRef<Path>>(
             DBWithThreadModeWrapper {
                 underlying: rocksdb,
                 db_path: secondary_path,
+                db_name: db_name(&primary_path),
             },
         )))
     })
 }
 
+/// Opens an existing database with options in read only mode, for example a checkpoint. Nothing
+/// is written to it, not even its WAL.
+pub fn open_cf_opts_read_only<P: AsRef<Path>>(
+    path: P,
+    db_options: Option<rocksdb::Options>,
+    opt_cfs: &[(&str, &rocksdb::Options)],
+) -> Result<Arc<RocksDB>, TypedStoreError> {
+    let path = path.as_ref();
+    // See comment above for explanation of why nondeterministic is necessary here.
+    nondeterministic!({
+        let options = db_options.unwrap_or_else(|| default_db_options().options);
+        let rocksdb = rocksdb::DBWithThreadMode::<MultiThreaded>::open_cf_descriptors_read_only(
+            &options,
+            path,
+            opt_cfs
+                .iter()
+                .map(|(name, opts)| ColumnFamilyDescriptor::new(*name, (*opts).clone())),
+            false,
+        )?;
+        Ok(Arc::new(RocksDB::DBWithThreadMode(
+            DBWithThreadModeWrapper {
+                underlying: rocksdb,
+                db_path: PathBuf::from(path),
+                db_name: db_name(path),
+            },
+        )))
+    })
+}
+
+/// The name of the directory of the database at `path`, labelling its metrics.
+fn db_name(path: &Path) -> String {
+    path.file_name()
+        .map_or_else(String::new, |name| name.to_string_lossy().into_owned())
+}
+
 pub fn list_tables(path: std::path::PathBuf) -> eyre::Result<Vec<String>> {
     const DB_DEFAULT_CF_NAME: &str = "default";
 
@@ -0,0 +1,489 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Secondary indexes of a sally column. A secondary index is a column mapping a key extracted from
+//! each entry of a primary column back to the key of that entry, to look up or iterate the
+//! primary column in another order than the one of its keys. The indexes declared on an
+//! `IndexedColumn` are maintained in the same write batch as its entries, so they are always
+//! consistent with it, whether the columns are backed by a `DBMap` or a `TestDB`.
+//!
+//! Writing an entry reads the entry it replaces or deletes to find its index keys, so the writes
+//! of an `IndexedColumn` are serialized by a lock shared by its clones: the writers staging
+//! entries in their own batch take it with `IndexedColumn::lock_writes` and hold it until their
+//! batch is written.
+//!
+//! # Examples
+//!
+//! ```
+//! use typed_store::sally::index::{IndexedColumn, SecondaryIndex};
+//! use typed_store::sally::SallyColumn;
+//! use typed_store::test_db::TestDB;
+//! use typed_store::Map;
+//!
+//! // The users by their id, indexed by their name.
+//! let users: SallyColumn<u64, String> = TestDB::open().into();
+//! let by_name: SecondaryIndex<u64, String, String> =
+//!     SecondaryIndex::new(TestDB::open().into(), |_id, name| name.clone());
+//! let users = IndexedColumn::new(users).with_index(&by_name);
+//!
+//! users
+//!     .multi_insert([(1, "bob".to_string()), (2, "alice".to_string())])
+//!     .unwrap();
+//! assert_eq!(by_name.column().get(&"alice".to_string()).unwrap(), Some(2));
+//!
+//! // Removing an entry also removes it from the indexes.
+//! users.multi_remove([2_u64]).unwrap();
+//! assert_eq!(by_name.column().get(&"alice".to_string()).unwrap(), None);
+//! ```
+use crate::rocks::{be_fix_int_ser, TypedStoreError};
+use crate::sally::{SallyColumn, SallyWriteBatch};
+use crate::traits::Map;
+use parking_lot::lock_api::ArcMutexGuard;
+use parking_lot::{Mutex, RawMutex};
+use serde::{de::DeserializeOwned, Serialize};
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Extracts the key of an entry of a secondary index from the key and value of an entry of its
+/// primary column.
+pub type IndexKeyFn<K, V, I> = fn(&K, &V) -> I;
+
+/// A secondary index of a column with keys `K` and values `V`, holding the key of every entry of
+/// the column under the index key `I` extracted from the entry. Index keys must be unique across
+/// the entries of the column.
+pub struct SecondaryIndex<K, V, I> {
+    column: SallyColumn<I, K>,
+    key: IndexKeyFn<K, V, I>,
+}
+
+impl<K, V, I> Clone for SecondaryIndex<K, V, I> {
+    fn clone(&self) -> Self {
+        Self {
+            column: self.column.clone(),
+            key: self.key,
+        }
+    }
+}
+
+impl<K, V, I> SecondaryIndex<K, V, I> {
+    pub fn new(column: SallyColumn<I, K>, key: IndexKeyFn<K, V, I>) -> Self {
+        Self { column, key }
+    }
+
+    /// The column holding the index, to look up and iterate the primary keys by index key. It
+    /// must only be written through the `IndexedColumn` the index is declared on.
+    pub fn column(&self) -> &SallyColumn<I, K> {
+        &self.column
+    }
+
+    /// The index key of an entry of the primary column.
+    pub fn index_key(&self, key: &K, value: &V) -> I {
+        (self.key)(key, value)
+    }
+}
+
+/// The maintenance of a secondary index, independent of the type of its keys.
+trait Index<K, V>: Send + Sync {
+    /// Stages the index keys of `entries`, replacing the ones of the values `replaced` by them.
+    /// The keys of `entries` are unique.
+    fn insert_batch(
+        &self,
+        batch: &mut SallyWriteBatch,
+        entries: &[&(K, V)],
+        replaced: &[Option<V>],
+    ) -> Result<(), TypedStoreError>;
+
+    fn delete_batch(
+        &self,
+        batch: &mut SallyWriteBatch,
+        entries: &[(K, V)],
+    ) -> Result<(), TypedStoreError>;
+
+    fn clear(&self) -> Result<(), TypedStoreError>;
+}
+
+impl<K, V, I> Index<K, V> for SecondaryIndex<K, V, I>
+where
+    K: Serialize + DeserializeOwned + Send + Sync,
+    V: Send + Sync,
+    I: Serialize + DeserializeOwned + PartialEq + Send + Sync,
+{
+    fn insert_batch(
+        &self,
+        batch: &mut SallyWriteBatch,
+        entries: &[&(K, V)],
+        replaced: &[Option<V>],
+    ) -> Result<(), TypedStoreError> {
+        // The stale keys are deleted before any key is inserted, so an entry taking over the
+        // index key of another one in the same batch keeps it.
+        let stale: Vec<I> = entries
+            .iter()
+            .zip(replaced)
+            .filter_map(|((key, value), old)| {
+                let old = self.index_key(key, old.as_ref()?);
+                (old != self.index_key(key, value)).then_some(old)
+            })
+            .collect();
+        batch.delete_batch(&self.column, stale)?;
+        batch.insert_batch(
+            &self.column,
+            entries
+                .iter()
+                .map(|(key, value)| (self.index_key(key, value), key)),
+        )
+    }
+
+    fn delete_batch(
+        &self,
+        batch: &mut SallyWriteBatch,
+        entries: &[(K, V)],
+    ) -> Result<(), TypedStoreError> {
+        batch.delete_batch(
+            &self.column,
+            entries
+                .iter()
+                .map(|(key, value)| self.index_key(key, value)),
+        )
+    }
+
+    fn clear(&self) -> Result<(), TypedStoreError> {
+        self.column.clear()
+    }
+}
+
+/// The exclusive right to write an `IndexedColumn`, from the reads of the entries a write
+/// replaces or deletes until the batch of the write is written. Released when dropped.
+pub struct IndexWriteGuard(ArcMutexGuard<RawMutex, ()>);
+
+/// A column together with the secondary indexes declared on it. Every write of the column also
+/// writes its indexes, in the same batch.
+pub struct IndexedColumn<K, V> {
+    column: SallyColumn<K, V>,
+    indexes: Vec<Arc<dyn Index<K, V>>>,
+    write_lock: Arc<Mutex<()>>,
+}
+
+impl<K, V> Clone for IndexedColumn<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            column: self.column.clone(),
+            indexes: self.indexes.clone(),
+            write_lock: self.write_lock.clone(),
+        }
+    }
+}
+
+impl<K, V> IndexedColumn<K, V> {
+    /// Takes the write lock of the column, blocking until the other writers release it. It must
+    /// be held until the batches staged with it are written.
+    pub fn lock_writes(&self) -> IndexWriteGuard {
+        IndexWriteGuard(self.write_lock.lock_arc())
+    }
+
+    /// Whether `guard` holds the write lock of this column.
+    pub fn is_locked_by(&self, guard: &IndexWriteGuard) -> bool {
+        Arc::ptr_eq(ArcMutexGuard::mutex(&guard.0), &self.write_lock)
+    }
+}
+
+impl<K, V> IndexedColumn<K, V>
+where
+    K: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    V: Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    pub fn new(column: SallyColumn<K, V>) -> Self {
+        Self {
+            column,
+            indexes: Vec::new(),
+            write_lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Declares `index` on the column. The entries already in the column are not indexed, so
+    /// the indexes should be declared before the column is written.
+    pub fn with_index<I>(mut self, index: &SecondaryIndex<K, V, I>) -> Self
+    where
+        I: Serialize + DeserializeOwned + PartialEq + Send + Sync + 'static,
+    {
+        self.indexes.push(Arc::new(index.clone()));
+        self
+    }
+
+    /// The column holding the entries, to read them by key. It must only be written through the
+    /// `IndexedColumn`.
+    pub fn column(&self) -> &SallyColumn<K, V> {
+        &self.column
+    }
+
+    /// Starts a write batch on the backend of the column.
+    pub fn batch(&self) -> SallyWriteBatch {
+        self.column.batch()
+    }
+
+    /// Stages the insertion of `entries` and of their index keys in `batch`. The entries they
+    /// replace are read first, to delete their index keys. A key found several times in
+    /// `entries` is written with its last value. `guard` must hold the write lock of the column.
+    pub fn insert_batch(
+        &self,
+        guard: &IndexWriteGuard,
+        batch: &mut SallyWriteBatch,
+        entries: &[(K, V)],
+    ) -> Result<(), TypedStoreError> {
+        assert!(
+            self.is_locked_by(guard),
+            "writing an indexed column without its write lock"
+        );
+        // The index keys of the earlier values of a key would be left behind, since they are
+        // not found in the column to be replaced. The keys are compared as they are stored.
+        let mut last_positions = HashMap::new();
+        for (position, (key, _)) in entries.iter().enumerate() {
+            last_positions.insert(be_fix_int_ser(key)?, position);
+        }
+        let mut positions: Vec<usize> = last_positions.into_values().collect();
+        positions.sort_unstable();
+        let entries: Vec<&(K, V)> = positions
+            .into_iter()
+            .map(|position| &entries[position])
+            .collect();
+
+        batch.insert_batch(
+            &self.column,
+            entries.iter().map(|(key, value)| (key, value)),
+        )?;
+        if self.indexes.is_empty() {
+            return Ok(());
+        }
+
+        let replaced = self.column.multi_get(entries.iter().map(|(key, _)| key))?;
+        for index in &self.indexes {
+            index.insert_batch(batch, entries, &replaced)?;
+        }
+        Ok(())
+    }
+
+    /// Stages the deletion of the entries at `keys` and of their index keys in `batch`. The
+    /// entries are read first to extract their index keys; the keys not found in the column are
+    /// skipped. `guard` must hold the write lock of the column. Returns the entries to be
+    /// deleted.
+    pub fn delete_batch<J: Borrow<K>>(
+        &self,
+        guard: &IndexWriteGuard,
+        batch: &mut SallyWriteBatch,
+        keys: impl IntoIterator<Item = J>,
+    ) -> Result<Vec<(K, V)>, TypedStoreError> {
+        assert!(
+            self.is_locked_by(guard),
+            "writing an indexed column without its write lock"
+        );
+        let keys: Vec<K> = keys.into_iter().map(|key| key.borrow().clone()).collect();
+        let entries: Vec<(K, V)> = keys
+            .iter()
+            .cloned()
+            .zip(self.column.multi_get(&keys)?)
+            .filter_map(|(key, value)| value.map(|value| (key, value)))
+            .collect();
+        if entries.is_empty() {
+            return Ok(entries);
+        }
+
+        batch.delete_batch(&self.column, entries.iter().map(|(key, _)| key))?;
+        for index in &self.indexes {
+            index.delete_batch(batch, &entries)?;
+        }
+        Ok(entries)
+    }
+
+    /// Inserts `entries` and their index keys atomically.
+    pub fn multi_insert(
+        &self,
+        entries: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<(), TypedStoreError> {
+        let entries: Vec<(K, V)> = entries.into_iter().collect();
+        let guard = self.lock_writes();
+        let mut batch = self.batch();
+        self.insert_batch(&guard, &mut batch, &entries)?;
+        batch.write_sync()
+    }
+
+    /// Deletes the entries at `keys` and their index keys atomically. Returns the deleted
+    /// entries.
+    pub fn multi_remove<J: Borrow<K>>(
+        &self,
+        keys: impl IntoIterator<Item = J>,
+    ) -> Result<Vec<(K, V)>, TypedStoreError> {
+        let guard = self.lock_writes();
+        let mut batch = self.batch();
+        let entries = self.delete_batch(&guard, &mut batch, keys)?;
+        if !entries.is_empty() {
+            batch.write_sync()?;
+        }
+        Ok(entries)
+    }
+
+    /// Clears the column and its indexes. Unlike the other writes, it is not atomic.
+    pub fn clear(&self) -> Result<(), TypedStoreError> {
+        let _guard = self.lock_writes();
+        self.column.clear()?;
+        for index in &self.indexes {
+            index.clear()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rocks::{open_cf, DBMap, ReadWriteOptions, RocksDB};
+    use crate::test_db::TestDB;
+
+    type ByName = SecondaryIndex<u64, (String, u32), String>;
+    type ByAge = SecondaryIndex<u64, (String, u32), (u32, u64)>;
+
+    fn user(name: &str, age: u32) -> (String, u32) {
+        (name.to_string(), age)
+    }
+
+    fn open<K, V>(rocksdb: &Arc<RocksDB>, cf: &str) -> SallyColumn<K, V> {
+        DBMap::reopen(rocksdb, Some(cf), &ReadWriteOptions::default())
+            .unwrap()
+            .into()
+    }
+
+    fn check_indexes(columns: (SallyColumn<u64, (String, u32)>, ByName, ByAge)) {
+        let (users, by_name, by_age) = columns;
+        let users = IndexedColumn::new(users)
+            .with_index(&by_name)
+            .with_index(&by_age);
+
+        users
+            .multi_insert([(1, user("bob", 40)), (2, user("alice", 30))])
+            .unwrap();
+        let guard = users.lock_writes();
+        let mut batch = users.batch();
+        users
+            .insert_batch(&guard, &mut batch, &[(3, user("carol", 30))])
+            .unwrap();
+        batch.write_sync().unwrap();
+        drop(guard);
+
+        assert_eq!(by_name.column().get(&"alice".to_string()).unwrap(), Some(2));
+        assert_eq!(
+            by_age
+                .column()
+                .keys()
+                .map(Result::unwrap)
+                .collect::<Vec<_>>(),
+            vec![(30, 2), (30, 3), (40, 1)]
+        );
+
+        // Overwriting an entry replaces its index keys, an entry taking over the index key of
+        // another one in the same batch keeps it.
+        users
+            .multi_insert([(1, user("dave", 40)), (4, user("bob", 50))])
+            .unwrap();
+        assert_eq!(by_name.column().get(&"bob".to_string()).unwrap(), Some(4));
+        assert_eq!(by_name.column().get(&"dave".to_string()).unwrap(), Some(1));
+        users.multi_insert([(4, user("bob", 20))]).unwrap();
+        assert_eq!(
+            by_age
+                .column()
+                .keys()
+                .map(Result::unwrap)
+                .collect::<Vec<_>>(),
+            vec![(20, 4), (30, 2), (30, 3), (40, 1)]
+        );
+        users.multi_remove([4_u64]).unwrap();
+
+        // A key written twice in a batch is only indexed with its last value.
+        users
+            .multi_insert([(5, user("erin", 60)), (5, user("frank", 70))])
+            .unwrap();
+        assert_eq!(users.column().get(&5).unwrap(), Some(user("frank", 70)));
+        assert_eq!(by_name.column().get(&"erin".to_string()).unwrap(), None);
+        assert_eq!(by_name.column().get(&"frank".to_string()).unwrap(), Some(5));
+        users.multi_remove([5_u64]).unwrap();
+
+        // The entries are deleted from the column and all its indexes, unknown keys are skipped.
+        let deleted = users.multi_remove([2_u64, 4]).unwrap();
+        assert_eq!(deleted, vec![(2, user("alice", 30))]);
+        assert_eq!(users.column().get(&2).unwrap(), None);
+        assert_eq!(by_name.column().get(&"alice".to_string()).unwrap(), None);
+        assert_eq!(
+            by_age
+                .column()
+                .keys()
+                .map(Result::unwrap)
+                .collect::<Vec<_>>(),
+            vec![(30, 3), (40, 1)]
+        );
+
+        users.clear().unwrap();
+        assert!(users.column().is_empty());
+        assert!(by_name.column().is_empty());
+        assert!(by_age.column().is_empty());
+    }
+
+    #[test]
+    fn test_write_lock() {
+        let users: IndexedColumn<u64, String> = IndexedColumn::new(TestDB::open().into());
+        let others: IndexedColumn<u64, String> = IndexedColumn::new(TestDB::open().into());
+
+        // The clones of a column share its lock.
+        let guard = users.clone().lock_writes();
+        assert!(users.is_locked_by(&guard));
+        assert!(!others.is_locked_by(&guard));
+
+        // The other writers wait for the lock to be released.
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let writer = {
+            let users = users.clone();
+            std::thread::spawn(move || {
+                users.multi_insert([(1, "bob".to_string())]).unwrap();
+                sender.send(()).unwrap();
+            })
+        };
+        assert!(receiver
+            .recv_timeout(std::time::Duration::from_millis(100))
+            .is_err());
+        drop(guard);
+        writer.join().unwrap();
+        assert_eq!(users.column().get(&1).unwrap(), Some("bob".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "without its write lock")]
+    fn test_write_with_another_lock() {
+        let users: IndexedColumn<u64, String> = IndexedColumn::new(TestDB::open().into());
+        let others: IndexedColumn<u64, String> = IndexedColumn::new(TestDB::open().into());
+
+        let guard = others.lock_writes();
+        let mut batch = users.batch();
+        let _ = users.insert_batch(&guard, &mut batch, &[(1, "bob".to_string())]);
+    }
+
+    #[test]
+    fn test_indexed_test_db() {
+        check_indexes((
+            TestDB::open().into(),
+            SecondaryIndex::new(TestDB::open().into(), |_, (name, _)| name.clone()),
+            SecondaryIndex::new(TestDB::open().into(), |id, (_, age)| (*age, *id)),
+        ));
+    }
+
+    #[test]
+    fn test_indexed_rocksdb() {
+        let rocksdb = open_cf(
+            tempfile::tempdir().unwrap(),
+            None,
+            &["users", "by_name", "by_age"],
+        )
+        .unwrap();
+        check_indexes((
+            open(&rocksdb, "users"),
+            SecondaryIndex::new(open(&rocksdb, "by_name"), |_, (name, _)| name.clone()),
+            SecondaryIndex::new(open(&rocksdb, "by_age"), |id, (_, age)| (*age, *id)),
+        ));
+    }
+}
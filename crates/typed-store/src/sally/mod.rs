@@ -48,6 +48,8 @@
 //!     Ok(())
 //! }
 //! ```
+pub mod index;
+
 use crate::{
     rocks::{
         default_db_options, keys::Keys, values::Values, DBBatch, DBMap, DBOptions,
@@ -57,16 +59,18 @@ use crate::{
     traits::{AsyncMap, Map},
 };
 
+use crate::rocks::iter::{Iter, RevIter};
 use crate::rocks::safe_iter::{SafeIter as RocksDBIter, SafeRevIter};
 use crate::rocks::DBMapTableConfigMap;
-use crate::test_db::{TestDBIter, TestDBRevIter};
+use crate::test_db::{TestDBIter, TestDBMapIter, TestDBRevIter};
 use async_trait::async_trait;
 use collectable::TryExtend;
-use rocksdb::Options;
+use rocksdb::{Options, WriteOptions};
 use serde::{de::DeserializeOwned, Serialize};
 use std::borrow::Borrow;
 use std::{collections::BTreeMap, path::PathBuf};
 
+#[derive(Clone)]
 pub enum SallyRunMode {
     // Whether Sally should use its own memtable and wal for read/write or just fallback to
     // reading/writing directly from the backend db. When columns in the db are backed by different
@@ -75,6 +79,7 @@ pub enum SallyRunMode {
     FallbackToDB,
 }
 
+#[derive(Clone)]
 pub struct SallyConfig {
     pub mode: SallyRunMode,
 }
@@ -90,6 +95,7 @@ impl Default for SallyConfig {
 /// A Sally column could be anything that implements key value interface. We will eventually have
 /// Sally serve read/writes using its own memtable and wal when columns in the db are backend by more then
 /// one backend store (e.g different rocksdb instances and/or distributed key value stores)
+#[derive(Clone)]
 pub enum SallyColumn<K, V> {
     RocksDB((DBMap<K, V>, SallyConfig)),
     TestDB((TestDB<K, V>, SallyConfig)),
@@ -122,6 +128,18 @@ impl<K, V> SallyColumn<K, V> {
     }
 }
 
+impl<K, V> From<DBMap<K, V>> for SallyColumn<K, V> {
+    fn from(db: DBMap<K, V>) -> Self {
+        SallyColumn::new_single_rocksdb(db)
+    }
+}
+
+impl<K, V> From<TestDB<K, V>> for SallyColumn<K, V> {
+    fn from(db: TestDB<K, V>) -> Self {
+        SallyColumn::new_testdb(db)
+    }
+}
+
 #[async_trait]
 impl<'a, K, V> AsyncMap<'a, K, V> for SallyColumn<K, V>
 where
@@ -285,6 +303,153 @@ where
     }
 }
 
+/// The synchronous interface of a sally column, for the callers which cannot await. It reads and
+/// writes the backend db directly, as in `SallyRunMode::FallbackToDB`.
+impl<'a, K, V> Map<'a, K, V> for SallyColumn<K, V>
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    type Error = TypedStoreError;
+    type Iterator = SallyMapIter<'a, K, V>;
+    type SafeIterator = SallyIter<'a, K, V>;
+    type Keys = SallyKeys<'a, K>;
+    type Values = SallyValues<'a, V>;
+
+    fn contains_key(&self, key: &K) -> Result<bool, TypedStoreError> {
+        match self {
+            SallyColumn::RocksDB((db_map, _)) => db_map.contains_key(key),
+            SallyColumn::TestDB((test_db, _)) => test_db.contains_key(key),
+        }
+    }
+
+    fn get(&self, key: &K) -> Result<Option<V>, TypedStoreError> {
+        match self {
+            SallyColumn::RocksDB((db_map, _)) => db_map.get(key),
+            SallyColumn::TestDB((test_db, _)) => test_db.get(key),
+        }
+    }
+
+    fn get_raw_bytes(&self, key: &K) -> Result<Option<Vec<u8>>, TypedStoreError> {
+        match self {
+            SallyColumn::RocksDB((db_map, _)) => db_map.get_raw_bytes(key),
+            SallyColumn::TestDB((test_db, _)) => test_db.get_raw_bytes(key),
+        }
+    }
+
+    fn insert(&self, key: &K, value: &V) -> Result<(), TypedStoreError> {
+        match self {
+            SallyColumn::RocksDB((db_map, _)) => db_map.insert(key, value),
+            SallyColumn::TestDB((test_db, _)) => test_db.insert(key, value),
+        }
+    }
+
+    fn remove(&self, key: &K) -> Result<(), TypedStoreError> {
+        match self {
+            SallyColumn::RocksDB((db_map, _)) => db_map.remove(key),
+            SallyColumn::TestDB((test_db, _)) => test_db.remove(key),
+        }
+    }
+
+    fn clear(&self) -> Result<(), TypedStoreError> {
+        match self {
+            SallyColumn::RocksDB((db_map, _)) => db_map.clear(),
+            SallyColumn::TestDB((test_db, _)) => test_db.clear(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            SallyColumn::RocksDB((db_map, _)) => db_map.is_empty(),
+            SallyColumn::TestDB((test_db, _)) => test_db.is_empty(),
+        }
+    }
+
+    fn iter(&'a self) -> Self::Iterator {
+        match self {
+            SallyColumn::RocksDB((db_map, _)) => SallyMapIter::RocksDB(db_map.iter()),
+            SallyColumn::TestDB((test_db, _)) => SallyMapIter::TestDB(test_db.iter()),
+        }
+    }
+
+    fn iter_with_bounds(
+        &'a self,
+        lower_bound: Option<K>,
+        upper_bound: Option<K>,
+    ) -> Self::Iterator {
+        match self {
+            SallyColumn::RocksDB((db_map, _)) => {
+                SallyMapIter::RocksDB(db_map.iter_with_bounds(lower_bound, upper_bound))
+            }
+            SallyColumn::TestDB((test_db, _)) => {
+                SallyMapIter::TestDB(test_db.iter_with_bounds(lower_bound, upper_bound))
+            }
+        }
+    }
+
+    fn safe_iter(&'a self) -> Self::SafeIterator {
+        match self {
+            SallyColumn::RocksDB((db_map, _)) => SallyIter::RocksDB(db_map.safe_iter()),
+            SallyColumn::TestDB((test_db, _)) => SallyIter::TestDB(test_db.safe_iter()),
+        }
+    }
+
+    fn keys(&'a self) -> Self::Keys {
+        match self {
+            SallyColumn::RocksDB((db_map, _)) => SallyKeys::RocksDB(db_map.keys()),
+            SallyColumn::TestDB((test_db, _)) => SallyKeys::TestDB(test_db.keys()),
+        }
+    }
+
+    fn values(&'a self) -> Self::Values {
+        match self {
+            SallyColumn::RocksDB((db_map, _)) => SallyValues::RocksDB(db_map.values()),
+            SallyColumn::TestDB((test_db, _)) => SallyValues::TestDB(test_db.values()),
+        }
+    }
+
+    fn multi_get<J>(&self, keys: impl IntoIterator<Item = J>) -> Result<Vec<Option<V>>, Self::Error>
+    where
+        J: Borrow<K>,
+    {
+        match self {
+            SallyColumn::RocksDB((db_map, _)) => db_map.multi_get(keys),
+            SallyColumn::TestDB((test_db, _)) => test_db.multi_get(keys),
+        }
+    }
+
+    fn multi_insert<J, U>(
+        &self,
+        key_val_pairs: impl IntoIterator<Item = (J, U)>,
+    ) -> Result<(), Self::Error>
+    where
+        J: Borrow<K>,
+        U: Borrow<V>,
+    {
+        match self {
+            SallyColumn::RocksDB((db_map, _)) => db_map.multi_insert(key_val_pairs),
+            SallyColumn::TestDB((test_db, _)) => test_db.multi_insert(key_val_pairs),
+        }
+    }
+
+    fn multi_remove<J>(&self, keys: impl IntoIterator<Item = J>) -> Result<(), Self::Error>
+    where
+        J: Borrow<K>,
+    {
+        match self {
+            SallyColumn::RocksDB((db_map, _)) => db_map.multi_remove(keys),
+            SallyColumn::TestDB((test_db, _)) => test_db.multi_remove(keys),
+        }
+    }
+
+    fn try_catch_up_with_primary(&self) -> Result<(), Self::Error> {
+        match self {
+            SallyColumn::RocksDB((db_map, _)) => db_map.try_catch_up_with_primary(),
+            SallyColumn::TestDB((test_db, _)) => test_db.try_catch_up_with_primary(),
+        }
+    }
+}
+
 impl<J, K, U, V> TryExtend<(J, U)> for SallyColumn<K, V>
 where
     J: Borrow<K> + std::clone::Clone,
@@ -343,11 +508,28 @@ pub enum SallyWriteBatch {
 
 impl SallyWriteBatch {
     pub async fn write(self) -> Result<(), TypedStoreError> {
+        self.write_sync()
+    }
+    /// Applies the batch without awaiting, for the callers of the synchronous `Map` interface
+    pub fn write_sync(self) -> Result<(), TypedStoreError> {
         match self {
             SallyWriteBatch::RocksDB(db_batch) => db_batch.write(),
             SallyWriteBatch::TestDB(write_batch) => write_batch.write(),
         }
     }
+    /// Applies the batch without awaiting like `write_sync`, and on RocksDB also syncs the
+    /// write-ahead log to disk before returning, so that the batch survives a crash of the
+    /// machine and not only of the process
+    pub fn write_with_fsync(self) -> Result<(), TypedStoreError> {
+        match self {
+            SallyWriteBatch::RocksDB(db_batch) => {
+                let mut writeopts = WriteOptions::default();
+                writeopts.set_sync(true);
+                db_batch.write_opt(&writeopts)
+            }
+            SallyWriteBatch::TestDB(write_batch) => write_batch.write(),
+        }
+    }
     /// Deletes a set of keys given as an iterator
     pub fn delete_batch<J: Borrow<K>, K: Serialize, V>(
         &mut self,
@@ -401,6 +583,91 @@ impl SallyWriteBatch {
     }
 }
 
+/// A SallyMapIter provides an iterator over all key values in a sally column, skipping the status
+/// checks of `SallyIter` like `DBMap::iter`
+pub enum SallyMapIter<'a, K, V> {
+    RocksDB(Iter<'a, K, V>),
+    TestDB(TestDBMapIter<K, V>),
+}
+
+impl<'a, K: DeserializeOwned, V: DeserializeOwned> Iterator for SallyMapIter<'a, K, V> {
+    type Item = (K, V);
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            SallyMapIter::RocksDB(iter) => iter.next(),
+            SallyMapIter::TestDB(iter) => iter.next(),
+        }
+    }
+}
+
+impl<'a, K: Serialize, V> SallyMapIter<'a, K, V> {
+    /// Skips all the elements that are smaller than the given key,
+    /// and either lands on the key or the first one greater than
+    /// the key.
+    pub fn skip_to(self, key: &K) -> Result<Self, TypedStoreError> {
+        let iter = match self {
+            SallyMapIter::RocksDB(iter) => SallyMapIter::RocksDB(iter.skip_to(key)?),
+            SallyMapIter::TestDB(iter) => SallyMapIter::TestDB(iter.skip_to(key)?),
+        };
+        Ok(iter)
+    }
+
+    /// Skips all the elements whose serialized key is smaller than the given bytes.
+    pub fn skip_to_bytes<T: AsRef<[u8]>>(self, key: T) -> Result<Self, TypedStoreError> {
+        let iter = match self {
+            SallyMapIter::RocksDB(iter) => SallyMapIter::RocksDB(iter.skip_to_bytes(key)?),
+            SallyMapIter::TestDB(iter) => SallyMapIter::TestDB(iter.skip_to_bytes(key)?),
+        };
+        Ok(iter)
+    }
+
+    /// Moves the iterator the element given or
+    /// the one prior to it if it does not exist. If there is
+    /// no element prior to it, it returns an empty iterator.
+    pub fn skip_prior_to(self, key: &K) -> Result<Self, TypedStoreError> {
+        let iter = match self {
+            SallyMapIter::RocksDB(iter) => SallyMapIter::RocksDB(iter.skip_prior_to(key)?),
+            SallyMapIter::TestDB(iter) => SallyMapIter::TestDB(iter.skip_prior_to(key)?),
+        };
+        Ok(iter)
+    }
+
+    /// Seeks to the last key in the database (at this column family).
+    pub fn skip_to_last(self) -> Self {
+        match self {
+            SallyMapIter::RocksDB(iter) => SallyMapIter::RocksDB(iter.skip_to_last()),
+            SallyMapIter::TestDB(iter) => SallyMapIter::TestDB(iter.skip_to_last()),
+        }
+    }
+
+    /// Will make the direction of the iteration reverse and will
+    /// create a new `RevIter` to consume. Every call to `next` method
+    /// will give the next element from the end.
+    pub fn reverse(self) -> SallyMapRevIter<'a, K, V> {
+        match self {
+            SallyMapIter::RocksDB(iter) => SallyMapRevIter::RocksDB(iter.reverse()),
+            SallyMapIter::TestDB(iter) => SallyMapRevIter::TestDB(iter.reverse()),
+        }
+    }
+}
+
+pub enum SallyMapRevIter<'a, K, V> {
+    RocksDB(RevIter<'a, K, V>),
+    TestDB(TestDBMapIter<K, V>),
+}
+
+impl<'a, K: DeserializeOwned, V: DeserializeOwned> Iterator for SallyMapRevIter<'a, K, V> {
+    type Item = (K, V);
+
+    /// Will give the next item backwards
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            SallyMapRevIter::RocksDB(rev_iter) => rev_iter.next(),
+            SallyMapRevIter::TestDB(rev_iter) => rev_iter.next(),
+        }
+    }
+}
+
 /// A SallyIter provides an iterator over all key values in a sally column
 pub enum SallyIter<'a, K, V> {
     // Iter for a rocksdb backed sally column when `fallback_to_db` is true
@@ -6,6 +6,7 @@ use std::{
     borrow::Borrow,
     collections::{btree_map::Iter, BTreeMap, HashMap, VecDeque},
     marker::PhantomData,
+    ops::Bound,
     sync::{Arc, RwLock},
 };
 
@@ -212,13 +213,146 @@ impl<'a, V: DeserializeOwned> Iterator for TestDBValues<'a, V> {
     }
 }
 
+/// An iterator over the key-value pairs of a `TestDB`, behaving like the RocksDB `Iter`. The rows
+/// are only locked while looking up the next pair, so the map can be written during the iteration.
+pub struct TestDBMapIter<K, V> {
+    rows: Arc<RwLock<BTreeMap<Vec<u8>, Vec<u8>>>>,
+    /// The inclusive lower bound of the iteration.
+    lower_bound: Option<Vec<u8>>,
+    /// The exclusive upper bound of the iteration.
+    upper_bound: Option<Vec<u8>>,
+    /// Where the next pair is looked up from, None once the iteration is over.
+    position: Option<Bound<Vec<u8>>>,
+    direction: Direction,
+    _phantom: PhantomData<fn(K) -> V>,
+}
+
+impl<K, V> TestDBMapIter<K, V> {
+    fn new(
+        rows: Arc<RwLock<BTreeMap<Vec<u8>, Vec<u8>>>>,
+        lower_bound: Option<Vec<u8>>,
+        upper_bound: Option<Vec<u8>>,
+    ) -> Self {
+        Self {
+            rows,
+            lower_bound,
+            upper_bound,
+            position: Some(Bound::Unbounded),
+            direction: Direction::Forward,
+            _phantom: PhantomData,
+        }
+    }
+
+    fn is_below_lower_bound(&self, raw_key: &[u8]) -> bool {
+        self.lower_bound
+            .as_ref()
+            .map_or(false, |lower_bound| raw_key < lower_bound.as_slice())
+    }
+
+    fn is_above_upper_bound(&self, raw_key: &[u8]) -> bool {
+        self.upper_bound
+            .as_ref()
+            .map_or(false, |upper_bound| raw_key >= upper_bound.as_slice())
+    }
+
+    /// The first raw key within the bounds after `position`.
+    fn first_after(&self, position: Bound<Vec<u8>>) -> Option<Vec<u8>> {
+        let rows = self.rows.read().unwrap();
+        let raw_key = rows
+            .range((position, Bound::Unbounded))
+            .map(|(raw_key, _)| raw_key)
+            .find(|raw_key| !self.is_below_lower_bound(raw_key))
+            .filter(|raw_key| !self.is_above_upper_bound(raw_key))
+            .cloned();
+        raw_key
+    }
+
+    /// The last raw key within the bounds before `position`.
+    fn last_before(&self, position: Bound<Vec<u8>>) -> Option<Vec<u8>> {
+        let rows = self.rows.read().unwrap();
+        let raw_key = rows
+            .range((Bound::Unbounded, position))
+            .rev()
+            .map(|(raw_key, _)| raw_key)
+            .find(|raw_key| !self.is_above_upper_bound(raw_key))
+            .filter(|raw_key| !self.is_below_lower_bound(raw_key))
+            .cloned();
+        raw_key
+    }
+
+    /// Skips to the first pair whose raw key is at least `raw_key`.
+    pub fn skip_to_bytes<T: AsRef<[u8]>>(mut self, raw_key: T) -> Result<Self, TypedStoreError> {
+        self.position = Some(Bound::Included(raw_key.as_ref().to_vec()));
+        Ok(self)
+    }
+
+    /// Seeks to the last key in the database (at this column family).
+    pub fn skip_to_last(mut self) -> Self {
+        self.position = self.last_before(Bound::Unbounded).map(Bound::Included);
+        self
+    }
+
+    /// Will make the direction of the iteration reverse, starting from the pair the iterator
+    /// would have returned next.
+    pub fn reverse(mut self) -> Self {
+        self.position = match self.position.take() {
+            Some(position) if matches!(self.direction, Direction::Forward) => {
+                self.first_after(position).map(Bound::Included)
+            }
+            position => position,
+        };
+        self.direction = Direction::Reverse;
+        self
+    }
+}
+
+impl<K: Serialize, V> TestDBMapIter<K, V> {
+    /// Skips all the elements that are smaller than the given key,
+    /// and either lands on the key or the first one greater than
+    /// the key.
+    pub fn skip_to(self, key: &K) -> Result<Self, TypedStoreError> {
+        self.skip_to_bytes(be_fix_int_ser(key)?)
+    }
+
+    /// Moves the iterator the element given or
+    /// the one prior to it if it does not exist. If there is
+    /// no element prior to it, it returns an empty iterator.
+    pub fn skip_prior_to(mut self, key: &K) -> Result<Self, TypedStoreError> {
+        self.position = self
+            .last_before(Bound::Included(be_fix_int_ser(key)?))
+            .map(Bound::Included);
+        Ok(self)
+    }
+}
+
+impl<K: DeserializeOwned, V: DeserializeOwned> Iterator for TestDBMapIter<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let position = self.position.take()?;
+        let raw_key = match self.direction {
+            Direction::Forward => self.first_after(position),
+            Direction::Reverse => self.last_before(position),
+        }?;
+        let raw_value = self.rows.read().unwrap().get(&raw_key).cloned()?;
+        self.position = Some(Bound::Excluded(raw_key.clone()));
+
+        let config = bincode::DefaultOptions::new()
+            .with_big_endian()
+            .with_fixint_encoding();
+        let key = config.deserialize(&raw_key).ok();
+        let value = bcs::from_bytes(&raw_value).ok();
+        key.and_then(|k| value.map(|v| (k, v)))
+    }
+}
+
 impl<'a, K, V> Map<'a, K, V> for TestDB<K, V>
 where
     K: Serialize + DeserializeOwned,
     V: Serialize + DeserializeOwned,
 {
     type Error = TypedStoreError;
-    type Iterator = std::iter::Empty<(K, V)>;
+    type Iterator = TestDBMapIter<K, V>;
     type SafeIterator = TestDBIter<'a, K, V>;
     type Keys = TestDBKeys<'a, K>;
     type Values = TestDBValues<'a, V>;
@@ -270,15 +404,19 @@ where
     }
 
     fn iter(&'a self) -> Self::Iterator {
-        unimplemented!("umplemented API");
+        TestDBMapIter::new(self.rows.clone(), None, None)
     }
 
     fn iter_with_bounds(
         &'a self,
-        _lower_bound: Option<K>,
-        _upper_bound: Option<K>,
+        lower_bound: Option<K>,
+        upper_bound: Option<K>,
     ) -> Self::Iterator {
-        unimplemented!("umplemented API");
+        TestDBMapIter::new(
+            self.rows.clone(),
+            lower_bound.map(|key| be_fix_int_ser(&key).unwrap()),
+            upper_bound.map(|key| be_fix_int_ser(&key).unwrap()),
+        )
     }
 
     fn safe_iter(&'a self) -> Self::SafeIterator {
@@ -801,4 +939,46 @@ mod test {
             assert_eq!(Some(v), val);
         }
     }
+
+    #[test]
+    fn test_iter() {
+        let db: TestDB<i32, String> = TestDB::open();
+        db.multi_insert((0..10).map(|i| (i * 2, i.to_string())))
+            .expect("Failed to multi-insert");
+
+        assert_eq!(
+            db.iter().map(|(k, _)| k).collect::<Vec<_>>(),
+            (0..10).map(|i| i * 2).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            db.iter().skip_to(&5).unwrap().next(),
+            Some((6, "3".to_string()))
+        );
+        assert_eq!(
+            db.iter().skip_prior_to(&5).unwrap().next(),
+            Some((4, "2".to_string()))
+        );
+        assert!(db.iter().skip_prior_to(&-1).unwrap().next().is_none());
+        assert_eq!(
+            db.iter()
+                .skip_to_last()
+                .reverse()
+                .map(|(k, _)| k)
+                .take(3)
+                .collect::<Vec<_>>(),
+            vec![18, 16, 14]
+        );
+        assert_eq!(
+            db.iter_with_bounds(Some(3), Some(8))
+                .map(|(k, _)| k)
+                .collect::<Vec<_>>(),
+            vec![4, 6]
+        );
+
+        // The map can be written while it is iterated.
+        for (k, _) in db.iter() {
+            db.remove(&k).expect("Failed to remove");
+        }
+        assert!(db.is_empty());
+    }
 }
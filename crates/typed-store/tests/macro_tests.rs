@@ -244,6 +244,110 @@ async fn test_sallydb() {
     assert_eq!(format!("\"8\""), *m.get(&"\"8\"".to_string()).unwrap());
 }
 
+#[derive(SallyDB)]
+pub struct IndexedSallyDBExample {
+    users: SallyColumn<u64, (String, u32)>,
+    #[index(of = "users", key = "user_name")]
+    users_by_name: SallyColumn<String, u64>,
+    #[index(of = "users", key = "user_age")]
+    users_by_age: SallyColumn<(u32, u64), u64>,
+}
+
+fn user_name(_id: &u64, (name, _): &(String, u32)) -> String {
+    name.clone()
+}
+
+fn user_age(id: &u64, (_, age): &(String, u32)) -> (u32, u64) {
+    (*age, *id)
+}
+
+#[test]
+fn test_sallydb_indexes() {
+    let example_db = IndexedSallyDBExample::init(SallyDBOptions::RocksDB((
+        temp_dir(),
+        RocksDBAccessType::Primary,
+        None,
+        None,
+    )));
+    let users = example_db.users_indexed();
+
+    // Both indexes are maintained by the writes of the indexed column
+    users
+        .multi_insert([(1, ("bob".to_string(), 40)), (2, ("alice".to_string(), 30))])
+        .unwrap();
+    users.multi_insert([(1, ("bob".to_string(), 20))]).unwrap();
+    assert_eq!(
+        example_db.users_by_name.get(&"alice".to_string()).unwrap(),
+        Some(2)
+    );
+    assert_eq!(
+        example_db
+            .users_by_age_index()
+            .column()
+            .keys()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>(),
+        vec![(20, 1), (30, 2)]
+    );
+
+    users.multi_remove([2_u64]).unwrap();
+    assert_eq!(
+        example_db.users_by_name.get(&"alice".to_string()).unwrap(),
+        None
+    );
+    assert_eq!(
+        example_db
+            .users_by_age
+            .keys()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>(),
+        vec![(20, 1)]
+    );
+}
+
+#[derive(DBMapUtils)]
+struct IndexedTables {
+    users: DBMap<u64, (String, u32)>,
+    #[index(of = "users", key = "user_name")]
+    users_by_name: DBMap<String, u64>,
+}
+
+#[tokio::test]
+async fn test_dbmap_indexes() {
+    let tables = IndexedTables::open_tables_read_write(temp_dir(), None, None);
+    let users = tables.users_indexed();
+
+    // The index is maintained by the writes of the indexed table
+    users
+        .multi_insert([(1, ("bob".to_string(), 40)), (2, ("alice".to_string(), 30))])
+        .unwrap();
+    users
+        .multi_insert([(1, ("carol".to_string(), 40))])
+        .unwrap();
+    assert_eq!(
+        tables
+            .users_by_name
+            .keys()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>(),
+        vec!["alice".to_string(), "carol".to_string()]
+    );
+
+    users.multi_remove([2_u64]).unwrap();
+    assert_eq!(
+        tables
+            .users_by_name_index()
+            .column()
+            .get(&"alice".to_string())
+            .unwrap(),
+        None
+    );
+    assert_eq!(
+        tables.users.get(&1).unwrap(),
+        Some(("carol".to_string(), 40))
+    );
+}
+
 #[tokio::test]
 async fn macro_transactional_test() {
     let key = "key".to_string();
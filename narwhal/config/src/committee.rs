@@ -2,7 +2,7 @@
 // Copyright (c) Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::{CommitteeUpdateError, ConfigError, Epoch, Stake};
+use crate::{CommitteeUpdateError, ConfigError, Epoch, ProtocolVersion, Stake};
 use crypto::{EncodeDecodeBase64, NetworkPublicKey, PublicKey};
 use mysten_network::Multiaddr;
 use rand::rngs::StdRng;
@@ -103,6 +103,9 @@ pub struct Committee {
     authorities_by_id: BTreeMap<AuthorityIdentifier, Authority>,
     /// The epoch number of this committee
     epoch: Epoch,
+    /// The version of the protocol run by this committee
+    #[serde(default)]
+    protocol_version: ProtocolVersion,
     /// The quorum threshold (2f+1)
     #[serde(skip)]
     quorum_threshold: Stake,
@@ -127,10 +130,15 @@ impl Display for AuthorityIdentifier {
 impl Committee {
     /// Any committee should be created via the CommitteeBuilder - this is intentionally be marked as
     /// private method.
-    fn new(authorities: BTreeMap<PublicKey, Authority>, epoch: Epoch) -> Self {
+    fn new(
+        authorities: BTreeMap<PublicKey, Authority>,
+        epoch: Epoch,
+        protocol_version: ProtocolVersion,
+    ) -> Self {
         let mut committee = Self {
             authorities,
             epoch,
+            protocol_version,
             authorities_by_id: Default::default(),
             validity_threshold: 0,
             quorum_threshold: 0,
@@ -196,6 +204,11 @@ impl Committee {
         self.epoch
     }
 
+    /// Returns the version of the protocol run by the committee.
+    pub fn protocol_version(&self) -> ProtocolVersion {
+        self.protocol_version
+    }
+
     /// Provided an identifier it returns the corresponding authority
     pub fn authority(&self, identifier: &AuthorityIdentifier) -> Option<&Authority> {
         self.authorities_by_id.get(identifier)
@@ -436,12 +449,13 @@ impl Committee {
     /// Used for testing - not recommended to use for any other case.
     /// It creates a new instance with updated epoch
     pub fn advance_epoch(&self, new_epoch: Epoch) -> Committee {
-        Committee::new(self.authorities.clone(), new_epoch)
+        Committee::new(self.authorities.clone(), new_epoch, self.protocol_version)
     }
 }
 
 pub struct CommitteeBuilder {
     epoch: Epoch,
+    protocol_version: ProtocolVersion,
     authorities: BTreeMap<PublicKey, Authority>,
 }
 
@@ -449,10 +463,16 @@ impl CommitteeBuilder {
     pub fn new(epoch: Epoch) -> Self {
         Self {
             epoch,
+            protocol_version: ProtocolVersion::default(),
             authorities: BTreeMap::new(),
         }
     }
 
+    pub fn protocol_version(mut self, protocol_version: ProtocolVersion) -> Self {
+        self.protocol_version = protocol_version;
+        self
+    }
+
     pub fn add_authority(
         mut self,
         protocol_key: PublicKey,
@@ -466,7 +486,7 @@ impl CommitteeBuilder {
     }
 
     pub fn build(self) -> Committee {
-        Committee::new(self.authorities, self.epoch)
+        Committee::new(self.authorities, self.epoch, self.protocol_version)
     }
 }
 
@@ -501,7 +521,7 @@ mod tests {
             .collect::<BTreeMap<PublicKey, Authority>>();
 
         // WHEN
-        let committee = Committee::new(authorities, 10);
+        let committee = Committee::new(authorities, 10, 0);
 
         // THEN
         assert_eq!(committee.authorities_by_id.len() as u64, num_of_authorities);
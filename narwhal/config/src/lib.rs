@@ -17,6 +17,7 @@ use std::{
     fs::{self, OpenOptions},
     io::{BufWriter, Write as _},
     num::NonZeroU32,
+    path::PathBuf,
     time::Duration,
 };
 use thiserror::Error;
@@ -31,6 +32,10 @@ pub mod utils;
 /// The epoch number.
 pub type Epoch = u64;
 
+/// The version of the protocol run by a committee. The rules introduced by a version only apply
+/// from the epochs whose committee runs it, once every authority supports them.
+pub type ProtocolVersion = u64;
+
 #[derive(Error, Debug)]
 pub enum ConfigError {
     #[error("Node {0} is not in the committee")]
@@ -118,6 +123,12 @@ pub struct Parameters {
     #[serde(default = "Parameters::default_max_header_num_of_batches")]
     pub max_header_num_of_batches: usize,
 
+    /// The maximum number of weak links to older certificates that no committed leader reached,
+    /// included in a header so their payload eventually commits. Zero disables weak links, and
+    /// values above the protocol limit of 100 are capped.
+    #[serde(default = "Parameters::default_max_header_num_of_weak_links")]
+    pub max_header_num_of_weak_links: usize,
+
     /// The maximum delay that the primary should wait between generating two headers, even if
     /// other conditions are not satisfied besides having enough parent stakes.
     #[serde(
@@ -133,9 +144,37 @@ pub struct Parameters {
     )]
     pub min_header_delay: Duration,
 
+    /// The parameters of the adaptive header sizing. When enabled, `header_num_of_batches_threshold`,
+    /// `min_header_delay` and `max_header_delay` become the upper bounds of values tuned at
+    /// runtime based on the observed round latency and the number of pending batch digests.
+    #[serde(default = "AdaptiveHeaderParameters::default")]
+    pub adaptive_header: AdaptiveHeaderParameters,
+
     /// The depth of the garbage collection (Denominated in number of rounds).
     #[serde(default = "Parameters::default_gc_depth")]
     pub gc_depth: u64,
+    /// The parameters of the background pruning of certificates, headers and payload markers
+    /// that are older than the GC round and the last executed sub dag.
+    #[serde(default = "PruningParameters::default")]
+    pub pruning: PruningParameters,
+    /// The parameters of the periodic backups of the node storage.
+    #[serde(default = "BackupParameters::default")]
+    pub backup: BackupParameters,
+    /// The parameters of the observers opening the node storage as a read-only secondary.
+    #[serde(default = "ObserverParameters::default")]
+    pub observer: ObserverParameters,
+    /// The sizes of the caches in front of the node storage.
+    #[serde(default = "CacheParameters::default")]
+    pub cache: CacheParameters,
+    /// The RocksDB tuning of the column families of the node storage.
+    #[serde(default = "StorageParameters::default")]
+    pub storage: StorageParameters,
+    /// The parameters of the consensus state sync of a primary starting with an empty store.
+    #[serde(default = "SnapshotSyncParameters::default")]
+    pub snapshot_sync: SnapshotSyncParameters,
+    /// The parameters of the fetching of the batches of the committed sub dags by the executor.
+    #[serde(default = "ExecutorParameters::default")]
+    pub executor: ExecutorParameters,
     /// The delay after which the synchronizer retries to send sync requests. Denominated in ms.
     #[serde(
         with = "duration_format",
@@ -186,6 +225,10 @@ impl Parameters {
         1_000
     }
 
+    fn default_max_header_num_of_weak_links() -> usize {
+        0
+    }
+
     fn default_max_header_delay() -> Duration {
         Duration::from_secs(2)
     }
@@ -219,6 +262,419 @@ impl Parameters {
     }
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AdaptiveHeaderParameters {
+    /// Whether the proposer tunes the header delays and batches threshold at runtime.
+    #[serde(default = "AdaptiveHeaderParameters::default_enabled")]
+    pub enabled: bool,
+    /// The lower bound of the tuned `header_num_of_batches_threshold`.
+    #[serde(default = "AdaptiveHeaderParameters::default_min_header_num_of_batches_threshold")]
+    pub min_header_num_of_batches_threshold: usize,
+    /// The lower bound of the tuned `min_header_delay`.
+    #[serde(
+        with = "duration_format",
+        default = "AdaptiveHeaderParameters::default_min_header_delay_floor"
+    )]
+    pub min_header_delay_floor: Duration,
+    /// The lower bound of the tuned `max_header_delay`.
+    #[serde(
+        with = "duration_format",
+        default = "AdaptiveHeaderParameters::default_max_header_delay_floor"
+    )]
+    pub max_header_delay_floor: Duration,
+}
+
+impl AdaptiveHeaderParameters {
+    fn default_enabled() -> bool {
+        false
+    }
+    fn default_min_header_num_of_batches_threshold() -> usize {
+        1
+    }
+    fn default_min_header_delay_floor() -> Duration {
+        Duration::from_millis(50)
+    }
+    fn default_max_header_delay_floor() -> Duration {
+        Duration::from_millis(200)
+    }
+}
+
+impl Default for AdaptiveHeaderParameters {
+    fn default() -> Self {
+        Self {
+            enabled: AdaptiveHeaderParameters::default_enabled(),
+            min_header_num_of_batches_threshold:
+                AdaptiveHeaderParameters::default_min_header_num_of_batches_threshold(),
+            min_header_delay_floor: AdaptiveHeaderParameters::default_min_header_delay_floor(),
+            max_header_delay_floor: AdaptiveHeaderParameters::default_max_header_delay_floor(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PruningParameters {
+    /// Whether data is pruned from the store once it falls out of the retention window.
+    #[serde(default = "PruningParameters::default_enabled")]
+    pub enabled: bool,
+    /// The number of rounds kept below both the GC round and the rounds needed to recover the
+    /// last executed sub dag, or with an external consensus below the GC round of the highest
+    /// round of the DAG. Peers lagging behind by less than this can still be served.
+    #[serde(default = "PruningParameters::default_retention_rounds")]
+    pub retention_rounds: u64,
+    /// How often the pruner checks whether there is data to delete.
+    #[serde(
+        with = "duration_format",
+        default = "PruningParameters::default_interval"
+    )]
+    pub interval: Duration,
+    /// The maximum number of certificates, with their headers and payload, deleted in a
+    /// single write batch.
+    #[serde(default = "PruningParameters::default_max_certificates_per_batch")]
+    pub max_certificates_per_batch: usize,
+}
+
+impl PruningParameters {
+    fn default_enabled() -> bool {
+        false
+    }
+    fn default_retention_rounds() -> u64 {
+        1_000
+    }
+    fn default_interval() -> Duration {
+        Duration::from_secs(10)
+    }
+    fn default_max_certificates_per_batch() -> usize {
+        1_000
+    }
+}
+
+impl Default for PruningParameters {
+    fn default() -> Self {
+        Self {
+            enabled: PruningParameters::default_enabled(),
+            retention_rounds: PruningParameters::default_retention_rounds(),
+            interval: PruningParameters::default_interval(),
+            max_certificates_per_batch: PruningParameters::default_max_certificates_per_batch(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct BackupParameters {
+    /// The directory the backups of the node storage are written to, in a `primary` directory for
+    /// the primary and a `worker-<id>` directory for every worker. Backups are disabled when it is
+    /// not set.
+    #[serde(default = "BackupParameters::default_directory")]
+    pub directory: Option<PathBuf>,
+    /// How often a backup is taken. Zero disables the scheduled backups, they can then only be
+    /// taken on demand.
+    #[serde(
+        with = "duration_format",
+        default = "BackupParameters::default_interval"
+    )]
+    pub interval: Duration,
+    /// The number of most recent backups kept, older ones are deleted.
+    #[serde(default = "BackupParameters::default_max_backups")]
+    pub max_backups: usize,
+}
+
+impl BackupParameters {
+    fn default_directory() -> Option<PathBuf> {
+        None
+    }
+    fn default_interval() -> Duration {
+        Duration::from_secs(3_600)
+    }
+    fn default_max_backups() -> usize {
+        3
+    }
+}
+
+impl Default for BackupParameters {
+    fn default() -> Self {
+        Self {
+            directory: BackupParameters::default_directory(),
+            interval: BackupParameters::default_interval(),
+            max_backups: BackupParameters::default_max_backups(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ObserverParameters {
+    /// The directory the secondary instances of the storages of the primary and of the workers
+    /// keep their own state in, under a `primary` and a `worker-<id>` directory. It defaults to a
+    /// `SECONDARY` directory next to each storage.
+    #[serde(default = "ObserverParameters::default_secondary_directory")]
+    pub secondary_directory: Option<PathBuf>,
+    /// How often the observer catches up with the writes of the node.
+    #[serde(
+        with = "duration_format",
+        default = "ObserverParameters::default_catch_up_interval"
+    )]
+    pub catch_up_interval: Duration,
+}
+
+impl ObserverParameters {
+    fn default_secondary_directory() -> Option<PathBuf> {
+        None
+    }
+    fn default_catch_up_interval() -> Duration {
+        Duration::from_millis(1_000)
+    }
+}
+
+impl Default for ObserverParameters {
+    fn default() -> Self {
+        Self {
+            secondary_directory: ObserverParameters::default_secondary_directory(),
+            catch_up_interval: ObserverParameters::default_catch_up_interval(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CacheParameters {
+    /// The number of certificates cached by the primary. By default, the cache holds the
+    /// certificates of the whole committee for the last `gc_depth` rounds.
+    #[serde(default = "CacheParameters::default_certificates")]
+    pub certificates: Option<usize>,
+    /// The number of headers cached by the primary. Zero disables the cache.
+    #[serde(default = "CacheParameters::default_headers")]
+    pub headers: usize,
+    /// The number of batches cached by the worker. Zero disables the cache.
+    #[serde(default = "CacheParameters::default_batches")]
+    pub batches: usize,
+}
+
+impl CacheParameters {
+    fn default_certificates() -> Option<usize> {
+        None
+    }
+    fn default_headers() -> usize {
+        0
+    }
+    fn default_batches() -> usize {
+        0
+    }
+}
+
+impl Default for CacheParameters {
+    fn default() -> Self {
+        Self {
+            certificates: CacheParameters::default_certificates(),
+            headers: CacheParameters::default_headers(),
+            batches: CacheParameters::default_batches(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct StorageParameters {
+    /// The tuning of the column families of the node storage, by name. The column families not
+    /// listed, and the options left unset, keep the default RocksDB options of the node.
+    #[serde(default = "StorageParameters::default_column_families")]
+    pub column_families: BTreeMap<String, ColumnFamilyParameters>,
+    /// The interval at which the RocksDB metrics of the node storage are exported. Zero disables
+    /// the export.
+    #[serde(
+        with = "duration_format",
+        default = "StorageParameters::default_metrics_interval"
+    )]
+    pub metrics_interval: Duration,
+    /// Holds the node storage in memory instead of RocksDB, for the tests and the light
+    /// deployments. Everything is lost once the node stops, and backups are not available.
+    #[serde(default)]
+    pub in_memory: bool,
+}
+
+impl StorageParameters {
+    fn default_column_families() -> BTreeMap<String, ColumnFamilyParameters> {
+        // The batches are large values written once, looked up by digest while they are recent
+        // and deleted by the pruning: larger memtables and block cache, and compactions of the
+        // old files to reclaim the space of the pruned batches.
+        let batches = ColumnFamilyParameters {
+            block_cache_size_mb: Some(256),
+            bloom_filter_bits_per_key: Some(10.0),
+            compression: None,
+            write_buffer_size_mb: Some(512),
+            ttl: Duration::from_secs(24 * 60 * 60),
+        };
+        BTreeMap::from([("batches".to_string(), batches)])
+    }
+
+    fn default_metrics_interval() -> Duration {
+        Duration::from_secs(15)
+    }
+}
+
+impl Default for StorageParameters {
+    fn default() -> Self {
+        Self {
+            column_families: StorageParameters::default_column_families(),
+            metrics_interval: StorageParameters::default_metrics_interval(),
+            in_memory: false,
+        }
+    }
+}
+
+/// The RocksDB options of a column family of the node storage. The options left unset keep their
+/// default.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ColumnFamilyParameters {
+    /// The size of the block cache of the column family, in MiB.
+    pub block_cache_size_mb: Option<usize>,
+    /// The number of bits per key of the bloom filters of the column family. Zero disables them.
+    pub bloom_filter_bits_per_key: Option<f64>,
+    /// The compression of every level of the column family.
+    pub compression: Option<StorageCompression>,
+    /// The size of a memtable of the column family, in MiB.
+    pub write_buffer_size_mb: Option<usize>,
+    /// The age after which the files of the column family are compacted. Zero disables these
+    /// compactions.
+    #[serde(with = "duration_format")]
+    pub ttl: Duration,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageCompression {
+    None,
+    Lz4,
+    Zstd,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SnapshotSyncParameters {
+    /// Whether a primary starting with an empty store syncs the consensus state and the
+    /// certificates above the GC round from its peers, instead of fetching the whole DAG.
+    #[serde(default = "SnapshotSyncParameters::default_enabled")]
+    pub enabled: bool,
+    /// How long to wait for a snapshot agreed on by f+1 peers before starting from the first
+    /// round instead.
+    #[serde(
+        with = "duration_format",
+        default = "SnapshotSyncParameters::default_timeout"
+    )]
+    pub timeout: Duration,
+    /// The delay between two rounds of snapshot requests to the peers.
+    #[serde(
+        with = "duration_format",
+        default = "SnapshotSyncParameters::default_retry_delay"
+    )]
+    pub retry_delay: Duration,
+}
+
+impl SnapshotSyncParameters {
+    fn default_enabled() -> bool {
+        false
+    }
+    fn default_timeout() -> Duration {
+        Duration::from_secs(30)
+    }
+    fn default_retry_delay() -> Duration {
+        Duration::from_secs(1)
+    }
+}
+
+impl Default for SnapshotSyncParameters {
+    fn default() -> Self {
+        Self {
+            enabled: SnapshotSyncParameters::default_enabled(),
+            timeout: SnapshotSyncParameters::default_timeout(),
+            retry_delay: SnapshotSyncParameters::default_retry_delay(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ExecutorParameters {
+    /// The maximum number of committed sub dags whose batches are fetched ahead of their
+    /// execution. The sub dags are still delivered to the execution state in order.
+    #[serde(default = "ExecutorParameters::default_max_pending_sub_dags")]
+    pub max_pending_sub_dags: usize,
+    /// The maximum number of fetch requests in flight to each of our workers, across all the
+    /// pending sub dags.
+    #[serde(default = "ExecutorParameters::default_max_concurrent_fetches_per_worker")]
+    pub max_concurrent_fetches_per_worker: usize,
+    /// The maximum number of batches requested to a worker in a single fetch request. Larger
+    /// payloads are split in several requests fetched concurrently.
+    #[serde(default = "ExecutorParameters::default_max_batches_per_fetch")]
+    pub max_batches_per_fetch: usize,
+    /// The delay before fetching again the batches not returned by our workers. It doubles
+    /// after every failed attempt, up to `max_fetch_retry_delay`.
+    #[serde(
+        with = "duration_format",
+        default = "ExecutorParameters::default_fetch_retry_delay"
+    )]
+    pub fetch_retry_delay: Duration,
+    /// The maximum delay between two attempts to fetch missing batches.
+    #[serde(
+        with = "duration_format",
+        default = "ExecutorParameters::default_max_fetch_retry_delay"
+    )]
+    pub max_fetch_retry_delay: Duration,
+    /// The number of attempts to fetch missing batches from the workers of the certificate
+    /// signers, before asking the workers of every authority. After as many attempts from every
+    /// worker, the batches are reported unavailable and the executor is flagged unhealthy until
+    /// they are fetched.
+    #[serde(default = "ExecutorParameters::default_fetch_attempts_per_source")]
+    pub fetch_attempts_per_source: usize,
+    /// The maximum number of batches fetched as soon as their certificates are certified, and
+    /// cached until the certificates are committed. The oldest are evicted first. Set to 0 to
+    /// only fetch the batches of the committed certificates.
+    #[serde(default = "ExecutorParameters::default_max_cached_batches")]
+    pub max_cached_batches: usize,
+}
+
+impl ExecutorParameters {
+    fn default_max_pending_sub_dags() -> usize {
+        1_000
+    }
+    fn default_max_concurrent_fetches_per_worker() -> usize {
+        8
+    }
+    fn default_max_batches_per_fetch() -> usize {
+        100
+    }
+    fn default_fetch_retry_delay() -> Duration {
+        Duration::from_secs(1)
+    }
+    fn default_max_fetch_retry_delay() -> Duration {
+        Duration::from_secs(30)
+    }
+    fn default_fetch_attempts_per_source() -> usize {
+        3
+    }
+    fn default_max_cached_batches() -> usize {
+        1_000
+    }
+}
+
+impl Default for ExecutorParameters {
+    fn default() -> Self {
+        Self {
+            max_pending_sub_dags: ExecutorParameters::default_max_pending_sub_dags(),
+            max_concurrent_fetches_per_worker:
+                ExecutorParameters::default_max_concurrent_fetches_per_worker(),
+            max_batches_per_fetch: ExecutorParameters::default_max_batches_per_fetch(),
+            fetch_retry_delay: ExecutorParameters::default_fetch_retry_delay(),
+            max_fetch_retry_delay: ExecutorParameters::default_max_fetch_retry_delay(),
+            fetch_attempts_per_source: ExecutorParameters::default_fetch_attempts_per_source(),
+            max_cached_batches: ExecutorParameters::default_max_cached_batches(),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct NetworkAdminServerParameters {
     /// Primary network admin server port number
@@ -418,9 +874,18 @@ impl Default for Parameters {
         Self {
             header_num_of_batches_threshold: Parameters::default_header_num_of_batches_threshold(),
             max_header_num_of_batches: Parameters::default_max_header_num_of_batches(),
+            max_header_num_of_weak_links: Parameters::default_max_header_num_of_weak_links(),
             max_header_delay: Parameters::default_max_header_delay(),
             min_header_delay: Parameters::default_min_header_delay(),
+            adaptive_header: AdaptiveHeaderParameters::default(),
             gc_depth: Parameters::default_gc_depth(),
+            pruning: PruningParameters::default(),
+            backup: BackupParameters::default(),
+            observer: ObserverParameters::default(),
+            cache: CacheParameters::default(),
+            storage: StorageParameters::default(),
+            snapshot_sync: SnapshotSyncParameters::default(),
+            executor: ExecutorParameters::default(),
             sync_retry_delay: Parameters::default_sync_retry_delay(),
             sync_retry_nodes: Parameters::default_sync_retry_nodes(),
             batch_size: Parameters::default_batch_size(),
@@ -453,6 +918,10 @@ impl Parameters {
             "Header max number of batches set to {}",
             self.max_header_num_of_batches
         );
+        info!(
+            "Header max number of weak links set to {}",
+            self.max_header_num_of_weak_links
+        );
         info!(
             "Max header delay set to {} ms",
             self.max_header_delay.as_millis()
@@ -461,7 +930,77 @@ impl Parameters {
             "Min header delay set to {} ms",
             self.min_header_delay.as_millis()
         );
+        info!(
+            "Adaptive header sizing {}",
+            if self.adaptive_header.enabled {
+                "enabled"
+            } else {
+                "disabled"
+            }
+        );
         info!("Garbage collection depth set to {} rounds", self.gc_depth);
+        if self.pruning.enabled {
+            info!(
+                "Pruning enabled with a retention of {} rounds",
+                self.pruning.retention_rounds
+            );
+        } else {
+            info!("Pruning disabled");
+        }
+        match &self.backup.directory {
+            Some(directory) => info!(
+                "Backups written to {:?} every {} ms, keeping the last {}",
+                directory,
+                self.backup.interval.as_millis(),
+                self.backup.max_backups
+            ),
+            None => info!("Backups disabled"),
+        }
+        info!(
+            "Observers catching up with the node storage every {} ms",
+            self.observer.catch_up_interval.as_millis()
+        );
+        match self.cache.certificates {
+            Some(certificates) => info!("Certificate cache holding {certificates} certificates"),
+            None => info!("Certificate cache sized from the committee and the GC depth"),
+        }
+        info!(
+            "Header cache holding {} headers, batch cache holding {} batches",
+            self.cache.headers, self.cache.batches
+        );
+        info!(
+            "Storage tuning the column families {:?}",
+            self.storage.column_families.keys().collect::<Vec<_>>()
+        );
+        info!(
+            "Storage exports its RocksDB metrics every {} ms",
+            self.storage.metrics_interval.as_millis()
+        );
+        if self.storage.in_memory {
+            info!("Storage held in memory, lost once the node stops");
+        }
+        info!(
+            "Snapshot sync {}",
+            if self.snapshot_sync.enabled {
+                "enabled"
+            } else {
+                "disabled"
+            }
+        );
+        info!(
+            "Executor fetching the batches of up to {} sub dags, with up to {} requests of {} \
+            batches in flight per worker",
+            self.executor.max_pending_sub_dags,
+            self.executor.max_concurrent_fetches_per_worker,
+            self.executor.max_batches_per_fetch
+        );
+        info!(
+            "Executor retrying to fetch missing batches after {} ms, up to {} ms, with {} \
+            attempts per source",
+            self.executor.fetch_retry_delay.as_millis(),
+            self.executor.max_fetch_retry_delay.as_millis(),
+            self.executor.fetch_attempts_per_source
+        );
         info!(
             "Sync retry delay set to {} ms",
             self.sync_retry_delay.as_millis()
@@ -544,7 +1083,7 @@ pub struct WorkerInfo {
     pub worker_address: Multiaddr,
 }
 
-#[derive(Clone, Serialize, Deserialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub struct WorkerIndex(pub BTreeMap<WorkerId, WorkerInfo>);
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
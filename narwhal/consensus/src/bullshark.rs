@@ -177,6 +177,8 @@ impl ConsensusProtocol for Bullshark {
             // We resolve the reputation score that should be stored alongside with this sub dag.
             let reputation_score = self.resolve_reputation_score(state, &sequence, sub_dag_index);
 
+            // The sub dag combines the randomness shares of a set of parents of the leader fixed
+            // by the committee into its randomness beacon.
             let sub_dag = CommittedSubDag::new(
                 sequence,
                 leader.clone(),
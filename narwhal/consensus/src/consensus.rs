@@ -10,7 +10,7 @@ use config::{AuthorityIdentifier, Committee};
 use crypto::Hash;
 use std::{
     cmp::{max, Ordering},
-    collections::{BTreeMap, BTreeSet, HashMap},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     sync::Arc,
 };
 use storage::{CertificateStore, ConsensusStore};
@@ -46,6 +46,10 @@ pub struct ConsensusState {
     /// Keeps the latest committed certificate (and its parents) for every authority. Anything older
     /// must be regularly cleaned up through the function `update`.
     pub dag: Dag,
+    /// Keeps the certificates committed above the gc round, by round. Weak links can point to
+    /// certificates below the last committed round of their authority, so this is used to
+    /// ensure we don't commit them twice.
+    pub committed: BTreeMap<Round, HashSet<CertificateDigest>>,
 }
 
 #[allow(clippy::new_without_default)]
@@ -57,14 +61,17 @@ impl ConsensusState {
             last_committed: Default::default(),
             last_committed_sub_dag: None,
             dag: Default::default(),
+            committed: Default::default(),
         }
     }
 
+    /// `recent_sub_dags` are the sub dags committed with a leader above the gc round.
     pub fn new_from_store(
         last_committed_round: Round,
         gc_depth: Round,
         recovered_last_committed: HashMap<AuthorityIdentifier, Round>,
         latest_sub_dag: Option<ConsensusCommit>,
+        recent_sub_dags: Vec<ConsensusCommit>,
         cert_store: CertificateStore,
     ) -> Self {
         let last_round = ConsensusRound::new_with_gc_depth(last_committed_round, gc_depth);
@@ -76,6 +83,22 @@ impl ConsensusState {
         )
         .expect("error when recovering DAG from store");
 
+        // Certificates at or below the gc round are not in the dag and don't need to be tracked.
+        let rounds: HashMap<_, _> = dag
+            .iter()
+            .flat_map(|(round, certificates)| {
+                certificates
+                    .values()
+                    .map(move |(digest, _)| (*digest, *round))
+            })
+            .collect();
+        let mut committed: BTreeMap<Round, HashSet<CertificateDigest>> = BTreeMap::new();
+        for digest in recent_sub_dags.iter().flat_map(|s| s.certificates()) {
+            if let Some(round) = rounds.get(&digest) {
+                committed.entry(*round).or_default().insert(digest);
+            }
+        }
+
         // TODO(metrics): Increment `recovered_consensus_state` metric
 
         let last_committed_sub_dag = if let Some(latest_sub_dag) = latest_sub_dag.as_ref() {
@@ -110,6 +133,7 @@ impl ConsensusState {
             last_committed: recovered_last_committed,
             last_committed_sub_dag,
             dag,
+            committed,
         }
     }
 
@@ -222,8 +246,21 @@ impl ConsensusState {
             certificate.round(),
         );
 
+        self.committed
+            .entry(certificate.round())
+            .or_default()
+            .insert(certificate.digest());
+
         // Purge all certificates past the gc depth.
         self.dag.retain(|r, _| *r > self.last_round.gc_round);
+        self.committed.retain(|r, _| *r > self.last_round.gc_round);
+    }
+
+    /// Whether the certificate has been committed. Only accurate above the gc round.
+    pub fn is_committed(&self, round: Round, digest: &CertificateDigest) -> bool {
+        self.committed
+            .get(&round)
+            .map_or(false, |committed| committed.contains(digest))
     }
 
     // Checks that the provided certificate's parents exist and crashes if not.
@@ -366,11 +403,16 @@ where
             );
         }
 
+        let recent_sub_dags = store
+            .read_committed_sub_dags_after_round(gc_round(last_committed_round, gc_depth))
+            .expect("error when reading recent sub dags from store");
+
         let state = ConsensusState::new_from_store(
             last_committed_round,
             gc_depth,
             recovered_last_committed,
             latest_sub_dag,
+            recent_sub_dags,
             cert_store,
         );
 
@@ -11,7 +11,7 @@ use crate::consensus_utils::*;
 use crate::{Consensus, NUM_SHUTDOWN_RECEIVERS};
 use crypto::Hash;
 #[cfg(test)]
-use std::collections::{BTreeSet, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use test_utils::CommitteeFixture;
 #[allow(unused_imports)]
 use tokio::sync::mpsc::channel;
@@ -52,8 +52,8 @@ async fn commit_one() {
 
     let mut tx_shutdown = PreSubscribedBroadcastSender::new(NUM_SHUTDOWN_RECEIVERS);
 
-    let store = make_consensus_store(&test_utils::temp_dir());
-    let cert_store = make_certificate_store(&test_utils::temp_dir());
+    let store = make_consensus_store();
+    let cert_store = make_certificate_store();
     let gc_depth = 50;
     let bullshark = Bullshark::new(committee.clone(), store.clone(), gc_depth);
 
@@ -126,8 +126,8 @@ async fn dead_node() {
 
     let mut tx_shutdown = PreSubscribedBroadcastSender::new(NUM_SHUTDOWN_RECEIVERS);
 
-    let store = make_consensus_store(&test_utils::temp_dir());
-    let cert_store = make_certificate_store(&test_utils::temp_dir());
+    let store = make_consensus_store();
+    let cert_store = make_certificate_store();
     let gc_depth = 50;
     let bullshark = Bullshark::new(committee.clone(), store.clone(), gc_depth);
 
@@ -271,8 +271,8 @@ async fn not_enough_support() {
 
     let mut tx_shutdown = PreSubscribedBroadcastSender::new(NUM_SHUTDOWN_RECEIVERS);
 
-    let store = make_consensus_store(&test_utils::temp_dir());
-    let cert_store = make_certificate_store(&test_utils::temp_dir());
+    let store = make_consensus_store();
+    let cert_store = make_certificate_store();
     let gc_depth = 50;
     let bullshark = Bullshark::new(committee.clone(), store.clone(), gc_depth);
 
@@ -383,8 +383,8 @@ async fn missing_leader() {
 
     let mut tx_shutdown = PreSubscribedBroadcastSender::new(NUM_SHUTDOWN_RECEIVERS);
 
-    let store = make_consensus_store(&test_utils::temp_dir());
-    let cert_store = make_certificate_store(&test_utils::temp_dir());
+    let store = make_consensus_store();
+    let cert_store = make_certificate_store();
     let gc_depth = 50;
     let bullshark = Bullshark::new(committee.clone(), store.clone(), gc_depth);
 
@@ -448,8 +448,8 @@ async fn committed_round_after_restart() {
     let (certificates, _) =
         test_utils::make_certificates_with_epoch(&committee, 1..=11, epoch, &genesis, &ids);
 
-    let store = make_consensus_store(&test_utils::temp_dir());
-    let cert_store = make_certificate_store(&test_utils::temp_dir());
+    let store = make_consensus_store();
+    let cert_store = make_certificate_store();
 
     for input_round in (1..=11usize).step_by(2) {
         // Spawn consensus and create related channels.
@@ -540,7 +540,7 @@ async fn delayed_certificates_are_rejected() {
     let (certificates, _) =
         test_utils::make_certificates_with_epoch(&committee, 1..=5, epoch, &genesis, &ids);
 
-    let store = make_consensus_store(&test_utils::temp_dir());
+    let store = make_consensus_store();
     let mut state = ConsensusState::new(gc_depth);
     let mut bullshark = Bullshark::new(committee, store, NUM_SUB_DAGS_PER_SCHEDULE);
 
@@ -586,7 +586,7 @@ async fn submitting_equivocating_certificate_should_error() {
     let (certificates, _) =
         test_utils::make_certificates_with_epoch(&committee, 1..=1, epoch, &genesis, &ids);
 
-    let store = make_consensus_store(&test_utils::temp_dir());
+    let store = make_consensus_store();
     let mut state = ConsensusState::new(gc_depth);
     let mut bullshark = Bullshark::new(committee.clone(), store, NUM_SUB_DAGS_PER_SCHEDULE);
 
@@ -643,7 +643,7 @@ async fn reset_consensus_scores_on_every_schedule_change() {
     let (certificates, _) =
         test_utils::make_certificates_with_epoch(&committee, 1..=50, epoch, &genesis, &ids);
 
-    let store = make_consensus_store(&test_utils::temp_dir());
+    let store = make_consensus_store();
     let mut state = ConsensusState::new(gc_depth);
     let mut bullshark = Bullshark::new(committee, store, NUM_SUB_DAGS_PER_SCHEDULE);
 
@@ -708,8 +708,8 @@ async fn restart_with_new_committee() {
             watch::channel(ConsensusRound::new(0, 0));
 
         let mut tx_shutdown = PreSubscribedBroadcastSender::new(NUM_SHUTDOWN_RECEIVERS);
-        let store = make_consensus_store(&test_utils::temp_dir());
-        let cert_store = make_certificate_store(&test_utils::temp_dir());
+        let store = make_consensus_store();
+        let cert_store = make_certificate_store();
         let gc_depth = 50;
         let bullshark = Bullshark::new(committee.clone(), store.clone(), gc_depth);
 
@@ -813,7 +813,7 @@ async fn garbage_collection_basic() {
     );
 
     // Create Bullshark consensus engine
-    let store = make_consensus_store(&test_utils::temp_dir());
+    let store = make_consensus_store();
 
     let mut state = ConsensusState::new(GC_DEPTH);
     let mut bullshark = Bullshark::new(committee, store, NUM_SUB_DAGS_PER_SCHEDULE);
@@ -914,7 +914,7 @@ async fn slow_node() {
     });
 
     // Create Bullshark consensus engine
-    let store = make_consensus_store(&test_utils::temp_dir());
+    let store = make_consensus_store();
     let mut state = ConsensusState::new(GC_DEPTH);
     let mut bullshark = Bullshark::new(committee.clone(), store, NUM_SUB_DAGS_PER_SCHEDULE);
 
@@ -997,6 +997,85 @@ async fn slow_node() {
     assert!(committed, "We expect to have commit for round 8");
 }
 
+// Authority 4 is slow at round 2: no certificate of round 3 references its certificate, which is
+// weakly linked by the leaders of rounds 4 and 6 instead. It should be committed exactly once, with
+// the leader of round 4.
+#[tokio::test]
+async fn weak_links_commit_orphaned_certificates() {
+    let fixture = CommitteeFixture::builder().build();
+    let committee: Committee = fixture.committee();
+    let ids: Vec<_> = fixture.authorities().map(|a| a.id()).collect();
+    let authority = fixture.authorities().next().unwrap();
+    let genesis = Certificate::genesis(&committee, authority.keypair().private())
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+
+    let (mut certificates, round_2_parents) =
+        test_utils::make_optimal_certificates(&committee, 1..=2, &genesis, &ids);
+    let orphan = certificates
+        .iter()
+        .find(|c| c.round() == 2 && c.origin() == ids[3])
+        .unwrap()
+        .digest();
+
+    // Round 3 doesn't reference the orphan.
+    let mut parents = round_2_parents;
+    parents.remove(&orphan);
+    let (round_3, mut parents) =
+        test_utils::make_optimal_certificates(&committee, 3..=3, &parents, &ids);
+    certificates.extend(round_3);
+
+    // The leaders of rounds 4 and 6 weakly link the orphan.
+    for (leader, round) in [(ids[1], 4), (ids[2], 6)] {
+        let mut next_parents = BTreeSet::new();
+        for id in &ids {
+            let (digest, certificate) = if *id == leader {
+                test_utils::mock_certificate_with_weak_links(
+                    &committee,
+                    *id,
+                    round,
+                    parents.clone(),
+                    BTreeMap::from([(orphan, 2)]),
+                )
+            } else {
+                test_utils::mock_certificate(&committee, *id, round, parents.clone())
+            };
+            certificates.push_back(certificate);
+            next_parents.insert(digest);
+        }
+        let (next_round, next_parents) = test_utils::make_optimal_certificates(
+            &committee,
+            round + 1..=round + 1,
+            &next_parents,
+            &ids,
+        );
+        certificates.extend(next_round);
+        parents = next_parents;
+    }
+
+    let store = make_consensus_store();
+    let mut state = ConsensusState::new(50);
+    let mut bullshark = Bullshark::new(committee.clone(), store, NUM_SUB_DAGS_PER_SCHEDULE);
+
+    let mut committed = Vec::new();
+    for certificate in certificates {
+        let (_, sub_dags) = bullshark
+            .process_certificate(&mut state, certificate)
+            .unwrap();
+        committed.extend(sub_dags);
+    }
+
+    let leader_rounds: Vec<_> = committed.iter().map(|s| s.leader_round()).collect();
+    assert_eq!(leader_rounds, vec![2, 4, 6]);
+    let commits_of_orphan: Vec<_> = committed
+        .iter()
+        .filter(|s| s.certificates.iter().any(|c| c.digest() == orphan))
+        .map(|s| s.leader_round())
+        .collect();
+    assert_eq!(commits_of_orphan, vec![4]);
+}
+
 /// This test creates a DAG that:
 /// * contains a leader has not enough support at round 2
 /// * a leader is missing at round 4
@@ -1086,7 +1165,7 @@ async fn not_enough_support_and_missing_leaders_and_gc() {
     certificates.extend(certificates_5_to_7);
 
     // Create Bullshark consensus engine
-    let store = make_consensus_store(&test_utils::temp_dir());
+    let store = make_consensus_store();
     let mut state = ConsensusState::new(GC_DEPTH);
     let mut bullshark = Bullshark::new(committee, store, NUM_SUB_DAGS_PER_SCHEDULE);
 
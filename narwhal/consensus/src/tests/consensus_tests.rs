@@ -7,7 +7,7 @@ use crypto::Hash;
 use std::collections::BTreeSet;
 use storage::NodeStorage;
 use telemetry_subscribers::TelemetryGuards;
-use test_utils::{temp_dir, CommitteeFixture};
+use test_utils::CommitteeFixture;
 use tokio::sync::watch;
 
 use crate::bullshark::Bullshark;
@@ -34,7 +34,7 @@ async fn test_consensus_recovery_with_bullshark() {
     let _guard = setup_tracing();
 
     // GIVEN
-    let storage = NodeStorage::reopen(temp_dir());
+    let storage = NodeStorage::new_for_tests();
 
     let consensus_store = storage.consensus_store;
     let certificate_store = storage.certificate_store;
@@ -152,7 +152,7 @@ async fn test_consensus_recovery_with_bullshark() {
     let (tx_consensus_round_updates, _rx_consensus_round_updates) =
         watch::channel(ConsensusRound::default());
 
-    let storage = NodeStorage::reopen(temp_dir());
+    let storage = NodeStorage::new_for_tests();
 
     let consensus_store = storage.consensus_store;
     let certificate_store = storage.certificate_store;
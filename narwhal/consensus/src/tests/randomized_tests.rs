@@ -166,8 +166,8 @@ fn test_determinism() {
                 // Check the fields that don't rely on a timestamp within the header.
                 // Unfortunately, the parents can't be checked as they are certificate digests and
                 // those rely on the timestamps as well.
-                let Header::V1(header_1) = cert_1.header();
-                let Header::V1(header_2) = cert_2.header();
+                let header_1 = cert_1.header();
+                let header_2 = cert_2.header();
                 assert_eq!(header_1.author(), header_2.author());
                 assert_eq!(header_1.round(), header_2.round());
                 assert_eq!(header_1.epoch(), header_2.epoch());
@@ -422,7 +422,7 @@ fn generate_and_run_execution_plans(
 
     // Create a single store to be re-used across Bullshark instances to avoid hitting
     // a "too many files open" issue.
-    let store = make_consensus_store(&test_utils::temp_dir());
+    let store = make_consensus_store();
 
     for i in 0..test_iterations {
         // clear store before using for next test
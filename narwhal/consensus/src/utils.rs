@@ -60,6 +60,7 @@ fn linked(leader: &Certificate, prev_leader: &Certificate, dag: &Dag) -> bool {
 
 /// Flatten the dag referenced by the input certificate. This is a classic depth-first search (pre-order):
 /// <https://en.wikipedia.org/wiki/Tree_traversal#Pre-order>
+/// Weak links are followed like parents, as long as they are above the GC round.
 pub fn order_dag(leader: &Certificate, state: &ConsensusState) -> Vec<Certificate> {
     debug!("Processing sub-dag of {:?}", leader);
     assert!(leader.round() > 0);
@@ -98,6 +99,33 @@ pub fn order_dag(leader: &Certificate, state: &ConsensusState) -> Vec<Certificat
                 already_ordered.insert(digest);
             }
         }
+        for (weak_link, round) in x.header().weak_links() {
+            if *round <= gc_round {
+                continue;
+            }
+            // The synchronizer only accepts a certificate once its weak links above the GC round
+            // are in the DAG, so a weak link can only be missing if its round is not the one of
+            // the linked certificate. This is the same on every node, so we can skip it.
+            let (digest, certificate) = match state
+                .dag
+                .get(round)
+                .and_then(|x| x.values().find(|(x, _)| x == weak_link))
+            {
+                Some(x) => x,
+                None => {
+                    debug!("Weak link {weak_link:?} not found at round {round} for {x:?}");
+                    continue;
+                }
+            };
+
+            // Unlike parents, weak links are usually below the last committed round of their
+            // authority, so we skip them only if they have been committed themselves.
+            let skip = already_ordered.contains(&digest) || state.is_committed(*round, digest);
+            if !skip {
+                buffer.push(certificate);
+                already_ordered.insert(digest);
+            }
+        }
     }
 
     // Ordering the output by round is not really necessary but it makes the commit sequence prettier.
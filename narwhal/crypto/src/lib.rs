@@ -18,12 +18,14 @@ mod keypair;
 mod network_keypair;
 mod signature;
 mod traits;
+mod vrf;
 
 pub use hash::*;
 pub use keypair::*;
 pub use network_keypair::*;
 pub use signature::*;
 pub use traits::*;
+pub use vrf::*;
 
 type CurrentNetwork = Testnet3;
 
@@ -1,4 +1,4 @@
-use crate::{to_intent_message, CurrentNetwork, Digest, PrivateKey, PublicKey};
+use crate::{to_intent_message, CurrentNetwork, Digest, PrivateKey, PublicKey, VrfProof};
 
 use eyre::eyre;
 use serde::{Deserialize, Serialize};
@@ -14,16 +14,28 @@ pub type Signature = snarkvm_console::account::Signature<CurrentNetwork>;
 // Note: this code is based on the fastcrypto implementation.
 #[derive(Clone)]
 pub struct SignatureService {
-    channel: Sender<(Digest, oneshot::Sender<Signature>)>,
+    channel: Sender<SignatureRequest>,
+}
+
+enum SignatureRequest {
+    Sign(Digest, oneshot::Sender<Signature>),
+    Prove(Digest, oneshot::Sender<VrfProof>),
 }
 
 impl SignatureService {
     pub fn new(pk: PrivateKey) -> Self {
-        let (tx, mut rx): (Sender<(Digest, oneshot::Sender<Signature>)>, _) = channel(100);
+        let (tx, mut rx): (Sender<SignatureRequest>, _) = channel(100);
         tokio::spawn(async move {
-            while let Some((msg, sender)) = rx.recv().await {
-                let signature = Signature::new_secure(&to_intent_message(msg), &pk);
-                let _ = sender.send(signature);
+            while let Some(request) = rx.recv().await {
+                match request {
+                    SignatureRequest::Sign(msg, sender) => {
+                        let signature = Signature::new_secure(&to_intent_message(msg), &pk);
+                        let _ = sender.send(signature);
+                    }
+                    SignatureRequest::Prove(msg, sender) => {
+                        let _ = sender.send(VrfProof::new(&msg, &pk));
+                    }
+                }
             }
         });
 
@@ -32,13 +44,24 @@ impl SignatureService {
 
     pub async fn request_signature(&self, msg: Digest) -> Signature {
         let (tx, rx) = oneshot::channel();
-        if let Err(e) = self.channel.send((msg, tx)).await {
+        if let Err(e) = self.channel.send(SignatureRequest::Sign(msg, tx)).await {
             panic!("failed to send message to signature service: {e}");
         }
 
         rx.await
             .expect("failed to receive signature from signature service")
     }
+
+    /// Computes the verifiable random function output of the key over `msg`.
+    pub async fn request_vrf_proof(&self, msg: Digest) -> VrfProof {
+        let (tx, rx) = oneshot::channel();
+        if let Err(e) = self.channel.send(SignatureRequest::Prove(msg, tx)).await {
+            panic!("failed to send message to signature service: {e}");
+        }
+
+        rx.await
+            .expect("failed to receive proof from signature service")
+    }
 }
 
 // We just concatenate signatures for now. A true aggregate signature is possible with this scheme
@@ -0,0 +1,145 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use crate::{CurrentNetwork, DefaultHashFunction, Digest, PrivateKey, PublicKey};
+
+use serde::{Deserialize, Serialize};
+use snarkvm_console::{
+    account::ViewKey,
+    network::Network,
+    prelude::{ToBytes, ToField, Zero},
+    types::{Field, Group, Scalar},
+};
+
+/// The output of a verifiable random function over a message, with the proof that it was computed
+/// with the key of an authority. Unlike a signature, the output is unique for a key and a
+/// message: its author cannot pick among several valid values.
+///
+/// The output is `x * H(message)`, where `x` is the view key of the authority, whose address is
+/// `x * G`. The proof is a Chaum-Pedersen proof that both use the same `x`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VrfProof {
+    gamma: Group<CurrentNetwork>,
+    challenge: Scalar<CurrentNetwork>,
+    response: Scalar<CurrentNetwork>,
+}
+
+impl Default for VrfProof {
+    /// An invalid proof, only meant for tests which do not verify it.
+    fn default() -> Self {
+        Self {
+            gamma: Group::zero(),
+            challenge: Scalar::zero(),
+            response: Scalar::zero(),
+        }
+    }
+}
+
+impl VrfProof {
+    pub fn new(message: &Digest, secret: &PrivateKey) -> Self {
+        let view_key =
+            ViewKey::try_from(**secret).expect("The view key derivation should not fail");
+        let x = *view_key;
+        let public_key = CurrentNetwork::g_scalar_multiply(&x);
+        let h = Self::hash_to_group(message);
+        let gamma = h * x;
+
+        // The nonce is derived from the secret and the message, so the proof is deterministic.
+        let k = CurrentNetwork::hash_to_scalar_psd4(&[
+            x.to_field().expect("A scalar always fits in a field"),
+            h.to_x_coordinate(),
+            Self::message_field(message),
+        ])
+        .expect("Hashing to a scalar should not fail");
+        let u = CurrentNetwork::g_scalar_multiply(&k);
+        let v = h * k;
+        let challenge = Self::challenge(&public_key, &h, &gamma, &u, &v);
+
+        Self {
+            gamma,
+            challenge,
+            response: k - challenge * x,
+        }
+    }
+
+    /// Verifies that the output was computed over `message` with the key of `public_key`.
+    pub fn verify(&self, message: &Digest, public_key: &PublicKey) -> bool {
+        let y = *public_key.to_group();
+        let h = Self::hash_to_group(message);
+        let u = CurrentNetwork::g_scalar_multiply(&self.response) + y * self.challenge;
+        let v = h * self.response + self.gamma * self.challenge;
+        !self.gamma.is_zero() && Self::challenge(&y, &h, &self.gamma, &u, &v) == self.challenge
+    }
+
+    /// The random output, only trusted once the proof is verified.
+    pub fn output(&self) -> Digest {
+        let mut hasher = DefaultHashFunction::new();
+        hasher.update(b"vrf");
+        hasher.update(
+            self.gamma
+                .to_x_coordinate()
+                .to_bytes_le()
+                .expect("Serialization should not fail"),
+        );
+        hasher.finalize()
+    }
+
+    fn message_field(message: &Digest) -> Field<CurrentNetwork> {
+        Field::from_bytes_le_mod_order(message.as_ref())
+    }
+
+    fn hash_to_group(message: &Digest) -> Group<CurrentNetwork> {
+        CurrentNetwork::hash_to_group_psd2(&[Self::message_field(message)])
+            .expect("Hashing to a group should not fail")
+    }
+
+    fn challenge(
+        public_key: &Group<CurrentNetwork>,
+        h: &Group<CurrentNetwork>,
+        gamma: &Group<CurrentNetwork>,
+        u: &Group<CurrentNetwork>,
+        v: &Group<CurrentNetwork>,
+    ) -> Scalar<CurrentNetwork> {
+        let points = [public_key, h, gamma, u, v].map(|point| point.to_x_coordinate());
+        CurrentNetwork::hash_to_scalar_psd8(&points).expect("Hashing to a scalar should not fail")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::KeyPair;
+    use rand::thread_rng;
+
+    #[test]
+    fn test_vrf_proof() {
+        let keypair = KeyPair::new(&mut thread_rng()).unwrap();
+        let other = KeyPair::new(&mut thread_rng()).unwrap();
+        let message = Digest::new([1u8; 32]);
+        let other_message = Digest::new([2u8; 32]);
+
+        let proof = VrfProof::new(&message, keypair.private());
+        assert!(proof.verify(&message, keypair.public()));
+        assert!(!proof.verify(&other_message, keypair.public()));
+        assert!(!proof.verify(&message, other.public()));
+        assert!(!VrfProof::default().verify(&message, keypair.public()));
+
+        // The output is unique for a key and a message.
+        let again = VrfProof::new(&message, keypair.private());
+        assert_eq!(again.output(), proof.output());
+        assert_ne!(
+            VrfProof::new(&other_message, keypair.private()).output(),
+            proof.output()
+        );
+        assert_ne!(
+            VrfProof::new(&message, other.private()).output(),
+            proof.output()
+        );
+
+        // Another output cannot be proven with the key.
+        let forged = VrfProof {
+            gamma: proof.gamma + proof.gamma,
+            ..proof
+        };
+        assert!(!forged.verify(&message, keypair.public()));
+    }
+}
@@ -4,7 +4,7 @@ use config::WorkerId;
 use std::fmt::Debug;
 use store::StoreError;
 use thiserror::Error;
-use types::CertificateDigest;
+use types::{BatchDigest, CertificateDigest, SequenceNumber};
 
 #[macro_export]
 macro_rules! bail {
@@ -35,6 +35,12 @@ pub enum SubscriberError {
     #[error("Error occurred while retrieving certificate {0} payload: {1}")]
     PayloadRetrieveError(CertificateDigest, String),
 
+    #[error("Batches of sub dag {0} are unavailable from every worker: {1:?}")]
+    BatchesUnavailable(SequenceNumber, Vec<BatchDigest>),
+
+    #[error("Committee change of sub dag {0} could not be persisted: {1}")]
+    CommitteeChangeNotPersisted(SequenceNumber, StoreError),
+
     #[error("Consensus referenced unexpected worker id {0}")]
     UnexpectedWorkerId(WorkerId),
 
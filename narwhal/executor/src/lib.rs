@@ -1,23 +1,36 @@
 // Copyright (c) Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
 mod errors;
+mod pruner;
 mod state;
+mod stream;
 mod subscriber;
 
 pub use errors::{SubscriberError, SubscriberResult};
-pub use state::ExecutionIndices;
+pub use pruner::Pruner;
+pub use state::ExecutionAck;
+pub use stream::ConsensusTransactions;
+pub use types::{ConsensusTransaction, ExecutionIndices, Reconfiguration};
 
+use crate::state::Acknowledgements;
+use crate::stream::{Materialized, OutputHandler, Streamed};
 use crate::subscriber::spawn_subscriber;
 
 use async_trait::async_trait;
-use config::{AuthorityIdentifier, Committee, WorkerCache};
+use config::{AuthorityIdentifier, Committee, ExecutorParameters, WorkerCache};
 use mockall::automock;
 use network::client::NetworkClient;
 use std::sync::Arc;
-use storage::{CertificateStore, ConsensusStore};
-use tokio::{sync::mpsc::Receiver, task::JoinHandle};
+use storage::{CertificateStore, ConsensusStore, ExecutionStore};
+use tokio::{
+    sync::{mpsc::Receiver, watch},
+    task::JoinHandle,
+};
 use tracing::info;
-use types::{CertificateDigest, CommittedSubDag, ConditionalBroadcastReceiver, ConsensusOutput};
+use types::{
+    Certificate, CertificateDigest, CommittedSubDag, ConditionalBroadcastReceiver, ConsensusOutput,
+    SystemTransaction,
+};
 
 /// Convenience type representing a serialized transaction.
 pub type SerializedTransaction = Vec<u8>;
@@ -29,41 +42,158 @@ pub type SerializedTransactionDigest = u64;
 #[async_trait]
 // Important - if you add method with the default implementation here make sure to update impl ExecutionState for Arc<T>
 pub trait ExecutionState {
-    /// Execute the transaction and atomically persist the consensus index.
-    async fn handle_consensus_output(&self, consensus_output: ConsensusOutput);
+    /// Execute the transactions of the consensus output, listed with their execution indices by
+    /// `ConsensusOutput::transactions`, and acknowledge them with `ack` once their effects are
+    /// persisted. The acknowledged transactions are not delivered again after a crash.
+    async fn handle_consensus_output(&self, consensus_output: ConsensusOutput, ack: ExecutionAck);
+}
+
+/// An alternative to `ExecutionState` for large sub dags: their transactions are streamed, and
+/// can be executed while the next batches of the sub dag are still being fetched.
+#[async_trait]
+pub trait TransactionStreamState {
+    /// Execute the transactions of the sub dag in the order they are yielded by `transactions`,
+    /// and acknowledge them with `ack` once their effects are persisted. Call `ack.ack_all()`
+    /// once `transactions` is exhausted, including for sub dags without transactions.
+    async fn handle_consensus_transactions(
+        &self,
+        sub_dag: Arc<CommittedSubDag>,
+        transactions: ConsensusTransactions,
+        ack: ExecutionAck,
+    );
+}
 
-    /// Load the last executed sub-dag index from storage
-    async fn last_executed_sub_dag_index(&self) -> u64;
+/// Whether the executor makes progress.
+#[derive(Clone, Debug)]
+pub enum ExecutorHealth {
+    Healthy,
+    /// The batches of a committed sub dag cannot be fetched. The executor keeps trying, but the
+    /// execution is stalled until it succeeds.
+    Unhealthy(SubscriberError),
 }
 
 /// A client subscribing to the consensus output and executing every transaction.
 pub struct Executor;
 
 impl Executor {
-    /// Spawn a new client subscriber.
+    /// Spawn a new client subscriber. The batches of the certificates received on `rx_certified`
+    /// are fetched ahead of their commit. The committee of the next epoch is published on
+    /// `tx_reconfiguration` once the last sub dag of the current committee is executed.
     pub fn spawn<State>(
         authority_id: AuthorityIdentifier,
         worker_cache: WorkerCache,
         committee: Committee,
         client: NetworkClient,
+        parameters: ExecutorParameters,
         execution_state: State,
+        execution_store: ExecutionStore,
         shutdown_receivers: Vec<ConditionalBroadcastReceiver>,
         rx_sequence: Receiver<CommittedSubDag>,
+        rx_certified: Receiver<Certificate>,
+        tx_health: watch::Sender<ExecutorHealth>,
+        tx_reconfiguration: watch::Sender<Option<Reconfiguration>>,
         restored_consensus_output: Vec<CommittedSubDag>,
     ) -> SubscriberResult<Vec<JoinHandle<()>>>
     where
         State: ExecutionState + Send + Sync + 'static,
     {
+        Self::spawn_with_handler(
+            authority_id,
+            worker_cache,
+            committee,
+            client,
+            parameters,
+            Materialized(execution_state),
+            execution_store,
+            shutdown_receivers,
+            rx_sequence,
+            rx_certified,
+            tx_health,
+            tx_reconfiguration,
+            restored_consensus_output,
+        )
+    }
+
+    /// Spawn a new client subscriber streaming the transactions of each sub dag to the execution
+    /// state as its batches are fetched.
+    pub fn spawn_streaming<State>(
+        authority_id: AuthorityIdentifier,
+        worker_cache: WorkerCache,
+        committee: Committee,
+        client: NetworkClient,
+        parameters: ExecutorParameters,
+        execution_state: State,
+        execution_store: ExecutionStore,
+        shutdown_receivers: Vec<ConditionalBroadcastReceiver>,
+        rx_sequence: Receiver<CommittedSubDag>,
+        rx_certified: Receiver<Certificate>,
+        tx_health: watch::Sender<ExecutorHealth>,
+        tx_reconfiguration: watch::Sender<Option<Reconfiguration>>,
+        restored_consensus_output: Vec<CommittedSubDag>,
+    ) -> SubscriberResult<Vec<JoinHandle<()>>>
+    where
+        State: TransactionStreamState + Send + Sync + 'static,
+    {
+        Self::spawn_with_handler(
+            authority_id,
+            worker_cache,
+            committee,
+            client,
+            parameters,
+            Streamed(execution_state),
+            execution_store,
+            shutdown_receivers,
+            rx_sequence,
+            rx_certified,
+            tx_health,
+            tx_reconfiguration,
+            restored_consensus_output,
+        )
+    }
+
+    fn spawn_with_handler<Handler: OutputHandler>(
+        authority_id: AuthorityIdentifier,
+        worker_cache: WorkerCache,
+        committee: Committee,
+        client: NetworkClient,
+        parameters: ExecutorParameters,
+        handler: Handler,
+        execution_store: ExecutionStore,
+        shutdown_receivers: Vec<ConditionalBroadcastReceiver>,
+        rx_sequence: Receiver<CommittedSubDag>,
+        rx_certified: Receiver<Certificate>,
+        tx_health: watch::Sender<ExecutorHealth>,
+        tx_reconfiguration: watch::Sender<Option<Reconfiguration>>,
+        restored_consensus_output: Vec<CommittedSubDag>,
+    ) -> SubscriberResult<Vec<JoinHandle<()>>> {
+        let committee_changes = execution_store
+            .read_committee_changes()?
+            .into_iter()
+            .map(
+                |(sub_dag_index, SystemTransaction::ScheduleCommitteeChange { change, .. })| {
+                    (sub_dag_index, change)
+                },
+            )
+            .collect();
+        let acknowledgements = Arc::new(Acknowledgements::new(execution_store.clone())?);
+
         // Spawn the subscriber.
         let subscriber_handle = spawn_subscriber(
             authority_id,
             worker_cache,
             committee,
             client,
+            parameters,
             shutdown_receivers,
             rx_sequence,
+            rx_certified,
+            tx_health,
+            tx_reconfiguration,
             restored_consensus_output,
-            execution_state,
+            handler,
+            acknowledgements,
+            execution_store,
+            committee_changes,
         );
 
         // Return the handle.
@@ -73,19 +203,20 @@ impl Executor {
     }
 }
 
-pub async fn get_restored_consensus_output<State: ExecutionState>(
+pub async fn get_restored_consensus_output(
     consensus_store: Arc<ConsensusStore>,
     certificate_store: CertificateStore,
-    execution_state: &State,
+    execution_store: &ExecutionStore,
 ) -> Result<Vec<CommittedSubDag>, SubscriberError> {
-    // We always want to recover at least the last committed sub-dag since we can't know
-    // whether the execution has been interrupted and there are still batches/transactions
-    // that need to be sent for execution.
-
-    let last_executed_sub_dag_index = execution_state.last_executed_sub_dag_index().await;
+    // The sub-dag of the last acknowledged transaction is recovered unless all its transactions
+    // were acknowledged. The subscriber then only delivers the transactions after that one.
+    let from = match execution_store.read_last_executed()? {
+        Some(last_executed) if last_executed.is_end_of_sub_dag() => last_executed.sub_dag_index + 1,
+        Some(last_executed) => last_executed.sub_dag_index,
+        None => 0,
+    };
 
-    let compressed_sub_dags =
-        consensus_store.read_committed_sub_dags_from(&last_executed_sub_dag_index)?;
+    let compressed_sub_dags = consensus_store.read_committed_sub_dags_from(&from)?;
 
     let mut sub_dags = Vec::new();
     for compressed_sub_dag in compressed_sub_dags {
@@ -113,13 +244,23 @@ pub async fn get_restored_consensus_output<State: ExecutionState>(
 
 #[async_trait]
 impl<T: ExecutionState + 'static + Send + Sync> ExecutionState for Arc<T> {
-    async fn handle_consensus_output(&self, consensus_output: ConsensusOutput) {
+    async fn handle_consensus_output(&self, consensus_output: ConsensusOutput, ack: ExecutionAck) {
         self.as_ref()
-            .handle_consensus_output(consensus_output)
+            .handle_consensus_output(consensus_output, ack)
             .await
     }
+}
 
-    async fn last_executed_sub_dag_index(&self) -> u64 {
-        self.as_ref().last_executed_sub_dag_index().await
+#[async_trait]
+impl<T: TransactionStreamState + 'static + Send + Sync> TransactionStreamState for Arc<T> {
+    async fn handle_consensus_transactions(
+        &self,
+        sub_dag: Arc<CommittedSubDag>,
+        transactions: ConsensusTransactions,
+        ack: ExecutionAck,
+    ) {
+        self.as_ref()
+            .handle_consensus_transactions(sub_dag, transactions, ack)
+            .await
     }
 }
@@ -0,0 +1,181 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use config::PruningParameters;
+use consensus::consensus::ConsensusRound;
+use crypto::Hash as _;
+use std::{collections::HashSet, sync::Arc};
+use storage::{
+    CertificateStore, ConsensusStore, ExecutionStore, HeaderStore, NodeStorage, PayloadStore,
+};
+use store::TypedStoreError;
+use tokio::{sync::watch, task::JoinHandle, time::interval};
+use tracing::{debug, error, info};
+use types::{Certificate, CertificateAPI, ConditionalBroadcastReceiver, HeaderAPI, Round};
+
+#[cfg(feature = "metrics")]
+use snarkos_metrics::{gauge, histogram};
+
+#[cfg(feature = "metrics")]
+const PRUNED_ROUND: &str = "narwhal_executor_pruner_pruned_round";
+#[cfg(feature = "metrics")]
+const PRUNED_CERTIFICATES: &str = "narwhal_executor_pruner_pruned_certificates";
+
+/// The pruner deletes from the node storage the certificates, headers and payload markers that are
+/// no longer needed by this node. A round can be pruned once it is more than `retention_rounds`
+/// below both the GC round of consensus and the rounds the executor needs to recover its last
+/// executed sub dag, so peers lagging behind by less than the retention window can still fetch
+/// certificates from us. With an external consensus, the rounds are only bounded by the GC depth
+/// below the highest round of the DAG. The batches are collected by the workers.
+pub struct Pruner {
+    /// The pruning configuration.
+    parameters: PruningParameters,
+    /// The depth of the garbage collection.
+    gc_depth: Round,
+    /// The certificates to prune.
+    certificate_store: CertificateStore,
+    /// The headers of the pruned certificates.
+    header_store: HeaderStore,
+    /// The payload markers of the pruned certificates.
+    payload_store: PayloadStore,
+    /// The committed sub dags, to find the rounds referenced by the last executed one.
+    consensus_store: Arc<ConsensusStore>,
+    /// The position acknowledged by the client executing the transactions.
+    execution_store: ExecutionStore,
+    /// Receiver for shutdown.
+    rx_shutdown: ConditionalBroadcastReceiver,
+    /// Watch channel to get the latest GC round of consensus, none with an external consensus.
+    rx_consensus_round_updates: Option<watch::Receiver<ConsensusRound>>,
+    /// The highest round pruned so far.
+    pruned_round: Round,
+}
+
+impl Pruner {
+    #[must_use]
+    pub fn spawn(
+        parameters: PruningParameters,
+        gc_depth: Round,
+        store: &NodeStorage,
+        rx_shutdown: ConditionalBroadcastReceiver,
+        rx_consensus_round_updates: Option<watch::Receiver<ConsensusRound>>,
+    ) -> JoinHandle<()> {
+        let pruner = Self {
+            parameters,
+            gc_depth,
+            certificate_store: store.certificate_store.clone(),
+            header_store: store.header_store.clone(),
+            payload_store: store.payload_store.clone(),
+            consensus_store: store.consensus_store.clone(),
+            execution_store: store.execution_store.clone(),
+            rx_shutdown,
+            rx_consensus_round_updates,
+            pruned_round: 0,
+        };
+        tokio::spawn(pruner.run())
+    }
+
+    async fn run(mut self) {
+        info!(
+            "Pruner started with a retention of {} rounds",
+            self.parameters.retention_rounds
+        );
+        let mut timer = interval(self.parameters.interval);
+        loop {
+            tokio::select! {
+                _ = timer.tick() => {
+                    let round = self.prunable_round();
+                    if round <= self.pruned_round {
+                        continue;
+                    }
+                    if let Err(e) = self.prune_until_round(round) {
+                        error!("Failed to prune the storage up to round {round}: {e}");
+                    }
+                }
+
+                _ = self.rx_shutdown.receiver.recv() => {
+                    return
+                }
+            }
+        }
+    }
+
+    /// The highest round that can be pruned, zero if none.
+    fn prunable_round(&self) -> Round {
+        let Some(rx_consensus_round_updates) = &self.rx_consensus_round_updates else {
+            // Without the GC round of consensus, nor an executor to recover, the DAG is only
+            // needed above the GC round of its highest round.
+            return self
+                .certificate_store
+                .highest_round_number()
+                .saturating_sub(self.gc_depth)
+                .saturating_sub(self.parameters.retention_rounds);
+        };
+        let gc_round = rx_consensus_round_updates.borrow().gc_round;
+
+        // The executor recovers from the last executed sub dag, so the certificates it references
+        // have to be kept. Those are all above the GC round of its leader.
+        let last_executed = match self.execution_store.read_last_executed() {
+            Ok(last_executed) => last_executed.map_or(0, |indices| indices.sub_dag_index),
+            Err(e) => {
+                error!("Failed to read the last executed indices: {e}");
+                0
+            }
+        };
+        let executed_round = match self.consensus_store.read_committed_sub_dag(&last_executed) {
+            Ok(Some(sub_dag)) => sub_dag.leader_round().saturating_sub(self.gc_depth),
+            Ok(None) => 0,
+            Err(e) => {
+                error!("Failed to read the last executed sub dag {last_executed}: {e}");
+                0
+            }
+        };
+
+        gc_round
+            .min(executed_round)
+            .saturating_sub(self.parameters.retention_rounds)
+    }
+
+    /// Deletes all the data of the certificates up to `round`, in batches of bounded size.
+    fn prune_until_round(&mut self, round: Round) -> Result<(), TypedStoreError> {
+        debug!("Pruning the storage up to round {round}");
+        loop {
+            let certificates = self
+                .certificate_store
+                .prune_until_round(round, self.parameters.max_certificates_per_batch)?;
+            if certificates.is_empty() {
+                break;
+            }
+
+            #[cfg(feature = "metrics")]
+            histogram!(PRUNED_CERTIFICATES, certificates.len() as f64);
+
+            self.prune_payload(round, &certificates)?;
+        }
+
+        self.pruned_round = round;
+
+        #[cfg(feature = "metrics")]
+        gauge!(PRUNED_ROUND, round as f64);
+
+        Ok(())
+    }
+
+    /// Deletes the headers and payload markers referenced by pruned certificates, except the
+    /// payload markers of the batches that a certificate above `round` also includes.
+    fn prune_payload(
+        &self,
+        round: Round,
+        certificates: &[Certificate],
+    ) -> Result<(), TypedStoreError> {
+        self.header_store
+            .remove_all(certificates.iter().map(|c| c.header().digest()))?;
+
+        let mut payload = certificates
+            .iter()
+            .flat_map(|c| c.header().payload())
+            .map(|(digest, (worker_id, _))| (*digest, *worker_id))
+            .collect::<HashSet<_>>();
+        self.certificate_store
+            .retain_unreferenced_payload(round, &mut payload)?;
+        self.payload_store.remove_all(payload)
+    }
+}
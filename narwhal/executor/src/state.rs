@@ -1,47 +1,199 @@
 // Copyright (c) Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
-use serde::{Deserialize, Serialize};
-use types::{Round, SequenceNumber};
-
-/// The state of the subscriber keeping track of the transactions that have already been
-/// executed. It ensures we do not process twice the same transaction despite crash-recovery.
-#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, Copy)]
-pub struct ExecutionIndices {
-    /// The round number of the last committed leader.
-    pub last_committed_round: Round,
-    /// The index of the last sub-DAG that was executed (either fully or partially).
-    pub sub_dag_index: SequenceNumber,
-    /// The index of the last transaction was executed (used for crash-recovery).
-    pub transaction_index: SequenceNumber,
+use crate::errors::SubscriberResult;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use storage::ExecutionStore;
+use tracing::debug;
+use types::{CommittedSubDag, ConsensusOutput, ExecutionIndices};
+
+/// The last acknowledged position in the consensus output, shared by all the acknowledgements
+/// so that it only moves forward.
+pub struct Acknowledgements {
+    execution_store: ExecutionStore,
+    last_executed: Mutex<Option<ExecutionIndices>>,
 }
 
-impl ExecutionIndices {
-    pub fn end_for_commit(commit_round: u64) -> Self {
-        ExecutionIndices {
-            last_committed_round: commit_round,
-            sub_dag_index: SequenceNumber::MAX,
-            transaction_index: SequenceNumber::MAX,
+impl Acknowledgements {
+    pub(crate) fn new(execution_store: ExecutionStore) -> SubscriberResult<Self> {
+        let last_executed = execution_store.read_last_executed()?;
+        Ok(Self {
+            execution_store,
+            last_executed: Mutex::new(last_executed),
+        })
+    }
+
+    /// The indices of the last transaction acknowledged, None if nothing has been executed yet.
+    pub(crate) fn last_executed(&self) -> Option<ExecutionIndices> {
+        *self.last_executed.lock().unwrap()
+    }
+
+    fn ack(&self, indices: ExecutionIndices) -> SubscriberResult<()> {
+        let mut last_executed = self.last_executed.lock().unwrap();
+        if last_executed.map_or(false, |last_executed| indices <= last_executed) {
+            return Ok(());
         }
+        self.execution_store.write_last_executed(&indices)?;
+        *last_executed = Some(indices);
+        Ok(())
     }
 }
 
-impl Ord for ExecutionIndices {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        (
-            self.last_committed_round,
-            self.sub_dag_index,
-            self.transaction_index,
-        )
-            .cmp(&(
-                other.last_committed_round,
-                other.sub_dag_index,
-                other.transaction_index,
-            ))
+/// Acknowledges the execution of the transactions of a consensus output. The acknowledged
+/// position is persisted before `ack` returns, and the transactions up to it are not delivered
+/// again after a crash. Acknowledgements are cumulative: acknowledging a transaction acknowledges
+/// all the transactions delivered before it, so outputs have to be acknowledged in order.
+///
+/// Every output has to be acknowledged, including those without transactions. An execution
+/// state crashing between persisting its effects and acknowledging them receives the same
+/// transactions again, and can use their execution indices to skip them.
+#[derive(Clone)]
+pub struct ExecutionAck {
+    /// The indices of the last transaction of the output, None if it has none.
+    last_transaction: Option<ExecutionIndices>,
+    /// The indices acknowledging the whole output.
+    end: ExecutionIndices,
+    /// Set once all the transactions of a streamed sub dag were delivered, None when the whole
+    /// output is delivered at once.
+    stream_complete: Option<Arc<AtomicBool>>,
+    acknowledgements: Arc<Acknowledgements>,
+}
+
+impl ExecutionAck {
+    pub(crate) fn new(output: &ConsensusOutput, acknowledgements: Arc<Acknowledgements>) -> Self {
+        Self {
+            last_transaction: output.last_transaction_indices(),
+            end: output.end_indices(),
+            stream_complete: None,
+            acknowledgements,
+        }
+    }
+
+    /// Acknowledges the transactions of a streamed sub dag, whose last transaction is only known
+    /// once they are all delivered.
+    pub(crate) fn for_stream(
+        sub_dag: &CommittedSubDag,
+        stream_complete: Arc<AtomicBool>,
+        acknowledgements: Arc<Acknowledgements>,
+    ) -> Self {
+        Self {
+            last_transaction: None,
+            end: ExecutionIndices::end_of_sub_dag(sub_dag.leader_round(), sub_dag.sub_dag_index),
+            stream_complete: Some(stream_complete),
+            acknowledgements,
+        }
+    }
+
+    /// Acknowledges the execution of the transaction at `indices`, and of all the transactions
+    /// before it.
+    pub fn ack(&self, indices: ExecutionIndices) -> SubscriberResult<()> {
+        // Once its last transaction is executed, the output is not delivered again.
+        if Some(indices) == self.last_transaction {
+            return self.acknowledgements.ack(self.end);
+        }
+        self.acknowledgements.ack(indices)
+    }
+
+    /// Acknowledges the execution of all the transactions of the output. For a streamed sub dag,
+    /// only once the stream delivered all its transactions: a stream interrupted by a shutdown
+    /// only keeps the acknowledgements of the transactions it delivered.
+    pub fn ack_all(&self) -> SubscriberResult<()> {
+        if let Some(stream_complete) = &self.stream_complete {
+            if !stream_complete.load(Ordering::SeqCst) {
+                debug!(
+                    "Not acknowledging the interrupted stream of sub dag {}",
+                    self.end.sub_dag_index
+                );
+                return Ok(());
+            }
+        }
+        self.acknowledgements.ack(self.end)
     }
 }
 
-impl PartialOrd for ExecutionIndices {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.cmp(other))
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_utils::{fixture_batch_with_transactions, CommitteeFixture};
+    use types::CommittedSubDag;
+
+    /// An output of the sub dag `sub_dag_index` with two batches of two transactions.
+    fn output(sub_dag_index: u64, last_executed: Option<ExecutionIndices>) -> ConsensusOutput {
+        let fixture = CommitteeFixture::builder().build();
+        let certificate = fixture.certificate(&fixture.header());
+        let sub_dag = CommittedSubDag::new(
+            vec![certificate.clone()],
+            certificate.clone(),
+            sub_dag_index,
+            Default::default(),
+            None,
+        );
+        ConsensusOutput {
+            sub_dag: Arc::new(sub_dag),
+            batches: vec![(
+                certificate,
+                vec![
+                    fixture_batch_with_transactions(2),
+                    fixture_batch_with_transactions(2),
+                ],
+            )],
+            last_executed,
+        }
+    }
+
+    #[test]
+    fn test_acknowledgements() {
+        let execution_store = ExecutionStore::new_for_tests();
+        let acknowledgements = Arc::new(Acknowledgements::new(execution_store.clone()).unwrap());
+
+        let first = output(1, None);
+        let indices: Vec<_> = first.transactions().map(|(indices, _)| indices).collect();
+        assert_eq!(indices.len(), 4);
+        let ack = ExecutionAck::new(&first, acknowledgements.clone());
+
+        ack.ack(indices[1]).unwrap();
+        assert_eq!(
+            execution_store.read_last_executed().unwrap(),
+            Some(indices[1])
+        );
+
+        // Acknowledgements never go backwards.
+        ack.ack(indices[0]).unwrap();
+        assert_eq!(acknowledgements.last_executed(), Some(indices[1]));
+
+        // Acknowledging the last transaction acknowledges the whole output.
+        ack.ack(indices[3]).unwrap();
+        assert!(execution_store
+            .read_last_executed()
+            .unwrap()
+            .unwrap()
+            .is_end_of_sub_dag());
+
+        let second = output(2, None);
+        ExecutionAck::new(&second, acknowledgements.clone())
+            .ack_all()
+            .unwrap();
+        assert_eq!(
+            execution_store.read_last_executed().unwrap(),
+            Some(second.end_indices())
+        );
+    }
+
+    #[test]
+    fn test_resume_partially_executed_output() {
+        let partial = output(1, None);
+        let executed = partial.transactions().nth(1).unwrap().0;
+
+        // After a restart, only the transactions after the acknowledged one are delivered.
+        let resumed = output(1, Some(executed));
+        let indices: Vec<_> = resumed.transactions().map(|(indices, _)| indices).collect();
+        assert_eq!(
+            indices
+                .iter()
+                .map(|i| i.transaction_index)
+                .collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+        assert_eq!(resumed.last_transaction_indices(), indices.last().copied());
     }
 }
@@ -0,0 +1,162 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use crate::state::Acknowledgements;
+use crate::{ExecutionAck, ExecutionState, TransactionStreamState};
+
+use async_trait::async_trait;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::mpsc::Receiver;
+use types::{
+    Batch, BatchAPI, BatchDigest, CertificateAPI, CommittedSubDag, ConsensusOutput,
+    ConsensusTransaction, ExecutionIndices, HeaderAPI, Reconfiguration, SequenceNumber,
+    Transaction,
+};
+
+/// A committed sub dag whose batches are received in execution order as they are fetched, along
+/// with the index of their certificate in the sub dag.
+pub(crate) struct SubDagBatches {
+    pub sub_dag: Arc<CommittedSubDag>,
+    /// The last transaction of the sub dag acknowledged before a restart.
+    pub last_executed: Option<ExecutionIndices>,
+    pub rx_batches: Receiver<(usize, BatchDigest, Batch)>,
+    /// The committee of the next epoch, if it takes over after this sub dag.
+    pub reconfiguration: Option<Reconfiguration>,
+}
+
+impl SubDagBatches {
+    /// Waits for all the batches of the sub dag, None if the fetching was interrupted by a
+    /// shutdown.
+    async fn collect(mut self) -> Option<ConsensusOutput> {
+        let num_batches = self.sub_dag.num_batches();
+        let mut batches = Vec::new();
+        if num_batches > 0 {
+            batches = self
+                .sub_dag
+                .certificates
+                .iter()
+                .map(|certificate| {
+                    let num_batches = certificate.header().payload().len();
+                    (certificate.clone(), Vec::with_capacity(num_batches))
+                })
+                .collect::<Vec<_>>();
+        }
+
+        for _ in 0..num_batches {
+            let (certificate_index, _, batch) = self.rx_batches.recv().await?;
+            batches[certificate_index].1.push(batch);
+        }
+
+        Some(ConsensusOutput {
+            sub_dag: self.sub_dag,
+            batches,
+            last_executed: self.last_executed,
+        })
+    }
+}
+
+/// The transactions of a committed sub dag in execution order. A transaction is yielded as soon
+/// as its batch and the batches before it are fetched, while the next batches of the sub dag are
+/// still being fetched. The transactions acknowledged before a restart are skipped.
+pub struct ConsensusTransactions {
+    sub_dag: Arc<CommittedSubDag>,
+    last_executed: Option<ExecutionIndices>,
+    rx_batches: Receiver<(usize, BatchDigest, Batch)>,
+    /// The number of batches of the sub dag not received yet.
+    remaining_batches: usize,
+    /// The transactions left in the last batch received, with its certificate and digest.
+    current_batch: Option<(usize, BatchDigest, std::vec::IntoIter<Transaction>)>,
+    next_transaction_index: SequenceNumber,
+    /// Set once all the transactions are delivered.
+    complete: Arc<AtomicBool>,
+}
+
+impl ConsensusTransactions {
+    fn new(batches: SubDagBatches, complete: Arc<AtomicBool>) -> Self {
+        let remaining_batches = batches.sub_dag.num_batches();
+        Self {
+            sub_dag: batches.sub_dag,
+            last_executed: batches.last_executed,
+            rx_batches: batches.rx_batches,
+            remaining_batches,
+            current_batch: None,
+            next_transaction_index: 0,
+            complete,
+        }
+    }
+
+    /// Returns the next transaction to execute, or None once all the transactions of the sub dag
+    /// were delivered or the node shuts down.
+    pub async fn next(&mut self) -> Option<ConsensusTransaction> {
+        loop {
+            if let Some((certificate_index, batch_digest, transactions)) = &mut self.current_batch {
+                if let Some(transaction) = transactions.next() {
+                    let indices = ExecutionIndices {
+                        last_committed_round: self.sub_dag.leader_round(),
+                        sub_dag_index: self.sub_dag.sub_dag_index,
+                        transaction_index: self.next_transaction_index,
+                    };
+                    self.next_transaction_index += 1;
+                    if self
+                        .last_executed
+                        .map_or(false, |last_executed| indices <= last_executed)
+                    {
+                        continue;
+                    }
+                    return Some(ConsensusTransaction {
+                        indices,
+                        certificate_origin: self.sub_dag.certificates[*certificate_index].origin(),
+                        batch_digest: *batch_digest,
+                        commit_timestamp: self.sub_dag.commit_timestamp(),
+                        transaction,
+                    });
+                }
+            }
+
+            if self.remaining_batches == 0 {
+                self.complete.store(true, Ordering::SeqCst);
+                return None;
+            }
+            let (certificate_index, batch_digest, mut batch) = self.rx_batches.recv().await?;
+            self.remaining_batches -= 1;
+            let transactions = std::mem::take(batch.transactions_mut());
+            self.current_batch = Some((certificate_index, batch_digest, transactions.into_iter()));
+        }
+    }
+}
+
+/// Hands the committed sub dags to the execution state.
+#[async_trait]
+pub(crate) trait OutputHandler: Send + Sync + 'static {
+    async fn handle(&self, batches: SubDagBatches, acknowledgements: Arc<Acknowledgements>);
+}
+
+/// Delivers each sub dag once all its batches are fetched.
+pub(crate) struct Materialized<State>(pub State);
+
+#[async_trait]
+impl<State: ExecutionState + Send + Sync + 'static> OutputHandler for Materialized<State> {
+    async fn handle(&self, batches: SubDagBatches, acknowledgements: Arc<Acknowledgements>) {
+        let Some(output) = batches.collect().await else {
+            return;
+        };
+        let ack = ExecutionAck::new(&output, acknowledgements);
+        self.0.handle_consensus_output(output, ack).await;
+    }
+}
+
+/// Delivers the transactions of each sub dag as its batches are fetched.
+pub(crate) struct Streamed<State>(pub State);
+
+#[async_trait]
+impl<State: TransactionStreamState + Send + Sync + 'static> OutputHandler for Streamed<State> {
+    async fn handle(&self, batches: SubDagBatches, acknowledgements: Arc<Acknowledgements>) {
+        let sub_dag = batches.sub_dag.clone();
+        let complete = Arc::new(AtomicBool::new(false));
+        let ack = ExecutionAck::for_stream(&sub_dag, complete.clone(), acknowledgements);
+        let transactions = ConsensusTransactions::new(batches, complete);
+        self.0
+            .handle_consensus_transactions(sub_dag, transactions, ack)
+            .await;
+    }
+}
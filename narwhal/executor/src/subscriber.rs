@@ -1,38 +1,56 @@
 // Copyright (c) Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
-use crate::{errors::SubscriberResult, ExecutionState};
+use crate::state::Acknowledgements;
+use crate::stream::{OutputHandler, SubDagBatches};
+use crate::{
+    errors::{SubscriberError, SubscriberResult},
+    ExecutorHealth,
+};
 
-use config::{AuthorityIdentifier, Committee, WorkerCache, WorkerId};
+use config::{AuthorityIdentifier, Committee, ExecutorParameters, WorkerCache, WorkerId};
 use crypto::{Hash, NetworkPublicKey};
-use futures::stream::FuturesOrdered;
-use futures::StreamExt;
+use futures::stream::FuturesUnordered;
+use futures::{Future, FutureExt, StreamExt};
 use network::client::NetworkClient;
 use network::PrimaryToWorkerClient;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::collections::HashMap;
 use std::collections::HashSet;
-use std::{sync::Arc, time::Duration, vec};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::{sync::Arc, vec};
+use storage::ExecutionStore;
 use tokio::{
     sync::mpsc::{channel, Receiver, Sender},
+    sync::{watch, Semaphore},
     task::JoinHandle,
 };
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 use types::FetchBatchesRequest;
 use types::{
-    Batch, BatchDigest, Certificate, CertificateAPI, CommittedSubDag, ConditionalBroadcastReceiver,
-    ConsensusOutput, HeaderAPI, Timestamp,
+    Batch, BatchAPI, BatchDigest, Certificate, CertificateAPI, CommittedSubDag,
+    ConditionalBroadcastReceiver, ExecutionIndices, HeaderAPI, Reconfiguration,
+    ScheduledCommitteeChange, SequenceNumber, SystemTransaction, Timestamp, Transaction,
 };
 
 #[cfg(feature = "metrics")]
 use snarkos_metrics::histogram;
 
-/// The `Subscriber` receives certificates sequenced by the consensus and waits until the
-/// downloaded all the transactions references by the certificates; it then
-/// forward the certificates to the Executor.
+/// The `Subscriber` receives certificates sequenced by the consensus and downloads all the
+/// transactions referenced by the certificates; it forwards them to the Executor in order, as
+/// they are downloaded.
 pub struct Subscriber {
     /// Receiver for shutdown
     rx_shutdown: ConditionalBroadcastReceiver,
     /// A channel to receive sequenced consensus messages.
     rx_sequence: Receiver<CommittedSubDag>,
+    /// A channel to receive the certificates as soon as they are certified, to prefetch their
+    /// batches before they are committed.
+    rx_certified: Receiver<Certificate>,
+    /// The committee changes found in the consensus output, by the index of their sub dag. The
+    /// first one committed schedules the next committee, the next ones are ignored.
+    committee_changes: BTreeMap<SequenceNumber, ScheduledCommitteeChange>,
     /// Inner state.
     inner: Arc<Inner>,
 }
@@ -42,17 +60,139 @@ struct Inner {
     worker_cache: WorkerCache,
     committee: Committee,
     client: NetworkClient,
+    /// The last transaction acknowledged before the restart, to resume a partially executed
+    /// sub dag after it.
+    last_executed: Option<ExecutionIndices>,
+    /// The limits of the batch fetching.
+    parameters: ExecutorParameters,
+    /// The permits of the fetch requests in flight to each of our workers.
+    fetch_permits: Mutex<HashMap<NetworkPublicKey, Arc<Semaphore>>>,
+    /// The batches prefetched for the certified certificates, until they are committed.
+    batch_cache: Mutex<BatchCache>,
+    /// Reports whether the batches of all the pending sub dags can be fetched.
+    tx_health: watch::Sender<ExecutorHealth>,
+    /// The pending sub dags whose batches were reported unavailable.
+    unavailable_sub_dags: Mutex<BTreeMap<SequenceNumber, SubscriberError>>,
+    /// Persists the committee changes found in the consensus output.
+    execution_store: ExecutionStore,
+}
+
+/// The batches to fetch through one of our workers, and the workers that may have them.
+#[derive(Default)]
+struct BatchSources {
+    digests: HashSet<BatchDigest>,
+    /// The workers of the authorities which signed the certificates of the batches.
+    signers: HashSet<NetworkPublicKey>,
+    /// The workers of every authority, asked once the signers failed to return the batches.
+    all_workers: HashSet<NetworkPublicKey>,
+}
+
+/// The batches of the certified certificates, fetched before the certificates are committed. The
+/// oldest are evicted first once the cache is full.
+#[derive(Default)]
+struct BatchCache {
+    batches: HashMap<BatchDigest, Batch>,
+    order: VecDeque<BatchDigest>,
+}
+
+impl BatchCache {
+    fn insert(&mut self, digest: BatchDigest, batch: Batch, capacity: usize) {
+        if capacity == 0 || self.batches.insert(digest, batch).is_some() {
+            return;
+        }
+        self.order.push_back(digest);
+        while self.order.len() > capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.batches.remove(&oldest);
+            }
+        }
+    }
+
+    fn contains(&self, digest: &BatchDigest) -> bool {
+        self.batches.contains_key(digest)
+    }
+
+    /// Removes the cached batches among `digests`, which are now committed.
+    fn take(&mut self, digests: &HashSet<BatchDigest>) -> HashMap<BatchDigest, Batch> {
+        let taken = digests
+            .iter()
+            .filter_map(|digest| Some((*digest, self.batches.remove(digest)?)))
+            .collect::<HashMap<_, _>>();
+        if !taken.is_empty() {
+            self.order.retain(|digest| !taken.contains_key(digest));
+        }
+        taken
+    }
 }
 
-pub fn spawn_subscriber<State: ExecutionState + Send + Sync + 'static>(
+impl Inner {
+    fn own_worker_name(&self, worker_id: &WorkerId) -> NetworkPublicKey {
+        self.worker_cache
+            .worker(
+                self.committee
+                    .authority(&self.authority_id)
+                    .unwrap()
+                    .protocol_key(),
+                worker_id,
+            )
+            .unwrap_or_else(|_| panic!("worker_id {worker_id} is not in the worker cache"))
+            .name
+    }
+
+    fn fetch_permits(&self, worker_name: &NetworkPublicKey) -> Arc<Semaphore> {
+        self.fetch_permits
+            .lock()
+            .unwrap()
+            .entry(worker_name.clone())
+            .or_insert_with(|| {
+                Arc::new(Semaphore::new(
+                    self.parameters.max_concurrent_fetches_per_worker.max(1),
+                ))
+            })
+            .clone()
+    }
+
+    /// Flags the executor unhealthy until the batches of the sub dag are fetched and sent.
+    fn report_unavailable(&self, sub_dag_index: SequenceNumber, error: SubscriberError) {
+        error!("{error}");
+        self.unavailable_sub_dags
+            .lock()
+            .unwrap()
+            .insert(sub_dag_index, error.clone());
+        self.tx_health
+            .send_replace(ExecutorHealth::Unhealthy(error));
+    }
+
+    /// Clears the report of the sub dag, whose batches are all fetched.
+    fn report_available(&self, sub_dag_index: SequenceNumber) {
+        let mut unavailable_sub_dags = self.unavailable_sub_dags.lock().unwrap();
+        if unavailable_sub_dags.remove(&sub_dag_index).is_none() {
+            return;
+        }
+        let health = match unavailable_sub_dags.values().next() {
+            Some(error) => ExecutorHealth::Unhealthy(error.clone()),
+            None => ExecutorHealth::Healthy,
+        };
+        self.tx_health.send_replace(health);
+    }
+}
+
+pub(crate) fn spawn_subscriber<Handler: OutputHandler>(
     authority_id: AuthorityIdentifier,
     worker_cache: WorkerCache,
     committee: Committee,
     client: NetworkClient,
+    parameters: ExecutorParameters,
     mut shutdown_receivers: Vec<ConditionalBroadcastReceiver>,
     rx_sequence: Receiver<CommittedSubDag>,
+    rx_certified: Receiver<Certificate>,
+    tx_health: watch::Sender<ExecutorHealth>,
+    tx_reconfiguration: watch::Sender<Option<Reconfiguration>>,
     restored_consensus_output: Vec<CommittedSubDag>,
-    state: State,
+    handler: Handler,
+    acknowledgements: Arc<Acknowledgements>,
+    execution_store: ExecutionStore,
+    committee_changes: BTreeMap<SequenceNumber, ScheduledCommitteeChange>,
 ) -> Vec<JoinHandle<()>> {
     // This is ugly but has to be done this way for now
     // Currently network incorporate both server and client side of RPC interface
@@ -68,30 +208,68 @@ pub fn spawn_subscriber<State: ExecutionState + Send + Sync + 'static>(
         .pop()
         .unwrap_or_else(|| panic!("Not enough shutdown receivers"));
 
+    let last_executed = acknowledgements.last_executed();
+
+    // The last sub dag of the current committee may have been executed before the restart.
+    if let Some(change) = committee_changes.values().next().filter(|change| {
+        last_executed.map_or(false, |last_executed| {
+            last_executed.sub_dag_index > change.sub_dag_index
+                || (last_executed.sub_dag_index == change.sub_dag_index
+                    && last_executed.is_end_of_sub_dag())
+        })
+    }) {
+        if let Ok(reconfiguration) = change.apply(&committee, &worker_cache) {
+            tx_reconfiguration.send_replace(Some(reconfiguration));
+        }
+    }
+
     vec![
-        tokio::spawn(run_notify(state, rx_notifier, rx_shutdown_notify)),
+        tokio::spawn(run_notify(
+            handler,
+            acknowledgements,
+            rx_notifier,
+            tx_reconfiguration,
+            rx_shutdown_notify,
+        )),
         tokio::spawn(create_and_run_subscriber(
             authority_id,
             worker_cache,
             committee,
             rx_shutdown_subscriber,
             rx_sequence,
+            rx_certified,
             client,
+            parameters,
+            tx_health,
             restored_consensus_output,
+            last_executed,
+            execution_store,
+            committee_changes,
             tx_notifier,
         )),
     ]
 }
 
-async fn run_notify<State: ExecutionState + Send + Sync + 'static>(
-    state: State,
-    mut rx_notify: Receiver<ConsensusOutput>,
+async fn run_notify<Handler: OutputHandler>(
+    handler: Handler,
+    acknowledgements: Arc<Acknowledgements>,
+    mut rx_notify: Receiver<SubDagBatches>,
+    tx_reconfiguration: watch::Sender<Option<Reconfiguration>>,
     mut rx_shutdown: ConditionalBroadcastReceiver,
 ) {
     loop {
         tokio::select! {
-            Some(message) = rx_notify.recv() => {
-                state.handle_consensus_output(message).await;
+            Some(mut message) = rx_notify.recv() => {
+                let reconfiguration = message.reconfiguration.take();
+                handler.handle(message, acknowledgements.clone()).await;
+                if let Some(reconfiguration) = reconfiguration {
+                    info!(
+                        "Sub dag {} executed, the committee of epoch {} takes over",
+                        reconfiguration.sub_dag_index,
+                        reconfiguration.committee.epoch()
+                    );
+                    tx_reconfiguration.send_replace(Some(reconfiguration));
+                }
             }
 
             _ = rx_shutdown.receiver.recv() => {
@@ -108,19 +286,34 @@ async fn create_and_run_subscriber(
     committee: Committee,
     rx_shutdown: ConditionalBroadcastReceiver,
     rx_sequence: Receiver<CommittedSubDag>,
+    rx_certified: Receiver<Certificate>,
     client: NetworkClient,
+    parameters: ExecutorParameters,
+    tx_health: watch::Sender<ExecutorHealth>,
     restored_consensus_output: Vec<CommittedSubDag>,
-    tx_notifier: Sender<ConsensusOutput>,
+    last_executed: Option<ExecutionIndices>,
+    execution_store: ExecutionStore,
+    committee_changes: BTreeMap<SequenceNumber, ScheduledCommitteeChange>,
+    tx_notifier: Sender<SubDagBatches>,
 ) {
     info!("Starting subscriber");
     let subscriber = Subscriber {
         rx_shutdown,
         rx_sequence,
+        rx_certified,
+        committee_changes,
         inner: Arc::new(Inner {
             authority_id,
             committee,
             worker_cache,
             client,
+            last_executed,
+            parameters,
+            fetch_permits: Mutex::new(HashMap::new()),
+            batch_cache: Mutex::new(BatchCache::default()),
+            tx_health,
+            unavailable_sub_dags: Mutex::new(BTreeMap::new()),
+            execution_store,
         }),
     };
     subscriber
@@ -130,48 +323,90 @@ async fn create_and_run_subscriber(
 }
 
 impl Subscriber {
-    /// Returns the max number of sub-dag to fetch payloads concurrently.
-    const MAX_PENDING_PAYLOADS: usize = 1000;
-
     /// Main loop connecting to the consensus to listen to sequence messages.
     async fn run(
         mut self,
         restored_consensus_output: Vec<CommittedSubDag>,
-        tx_notifier: Sender<ConsensusOutput>,
+        tx_notifier: Sender<SubDagBatches>,
     ) -> SubscriberResult<()> {
-        // It's important to have the futures in ordered fashion as we want
-        // to guarantee that will deliver to the executor the certificates
-        // in the same order we received from rx_sequence. So it doesn't
-        // matter if we somehow managed to fetch the batches from a later
-        // certificate. Unless the earlier certificate's payload has been
-        // fetched, no later certificate will be delivered.
-        let mut waiting = FuturesOrdered::new();
+        // The fetching of the batches of the pending sub dags, in any order.
+        let mut waiting = FuturesUnordered::new();
+        // The sub dags waiting for the execution state to take them, in the same order we
+        // received them from rx_sequence. Their batches are received in order as they are fetched,
+        // and the batches of the next sub dags keep being fetched in the meantime.
+        let mut ready = VecDeque::<SubDagBatches>::new();
+        // The sub dags whose batches are still being fetched. A sub dag is only delivered once
+        // the batches of the sub dags before it are all fetched, so that every authority knows
+        // the same committee changes when it is executed.
+        let mut fetching = BTreeSet::new();
+        // The prefetching of the batches of the certified certificates.
+        let mut prefetching = FuturesUnordered::new();
+        let max_pending_sub_dags = self.inner.parameters.max_pending_sub_dags;
+        let prefetch = self.inner.parameters.max_cached_batches > 0;
 
         // First handle any consensus output messages that were restored due to a restart.
         // This needs to happen before we start listening on rx_sequence and receive messages sequenced after these.
         for message in restored_consensus_output {
-            let future = Self::fetch_batches(self.inner.clone(), message);
-            waiting.push_back(future);
+            let (batches, fetch) = Self::fetch(self.inner.clone(), message);
+            fetching.insert(batches.sub_dag.sub_dag_index);
+            waiting.push(fetch);
+            ready.push_back(batches);
 
             // TODO(metrics): Increment `subscriber_recovered_certificates_count`
         }
 
         // Listen to sequenced consensus message and process them.
         loop {
+            let deliverable = ready.front().map_or(false, |batches| {
+                fetching
+                    .first()
+                    .map_or(true, |first| *first >= batches.sub_dag.sub_dag_index)
+            });
+
             tokio::select! {
                 // Receive the ordered sequence of consensus messages from a consensus node.
-                Some(sub_dag) = self.rx_sequence.recv(), if waiting.len() < Self::MAX_PENDING_PAYLOADS => {
-                    // We can schedule more then MAX_PENDING_PAYLOADS payloads but
+                Some(sub_dag) = self.rx_sequence.recv(), if waiting.len() + ready.len() < max_pending_sub_dags => {
+                    // We can schedule more then max_pending_sub_dags payloads but
                     // don't process more consensus messages when more
-                    // then MAX_PENDING_PAYLOADS is pending
-                    waiting.push_back(Self::fetch_batches(self.inner.clone(), sub_dag));
+                    // then max_pending_sub_dags is pending
+                    let (batches, fetch) = Self::fetch(self.inner.clone(), sub_dag);
+                    fetching.insert(batches.sub_dag.sub_dag_index);
+                    waiting.push(fetch);
+                    ready.push_back(batches);
+                },
+
+                // Prefetch the batches of the certificates, until they are committed.
+                Some(certificate) = self.rx_certified.recv(), if prefetch && prefetching.len() < max_pending_sub_dags => {
+                    prefetching.push(Self::prefetch_batches(self.inner.clone(), certificate));
+                },
+
+                Some(()) = prefetching.next() => {},
+
+                // Drive the fetching of the batches, which are sent as they are downloaded.
+                Some((sub_dag_index, committee_change)) = waiting.next() => {
+                    fetching.remove(&sub_dag_index);
+                    if let Some(change) = committee_change {
+                        self.committee_changes.insert(sub_dag_index, change);
+                    }
                 },
 
-                // Receive here consensus messages for which we have downloaded all transactions data.
-                Some(message) = waiting.next() => {
-                    if let Err(e) = tx_notifier.send(message).await {
-                        error!("tx_notifier closed: {}", e);
-                        return Ok(());
+                permit = tx_notifier.reserve(), if deliverable => {
+                    match permit {
+                        Ok(permit) => {
+                            let mut batches = ready.pop_front().unwrap();
+                            let sub_dag_index = batches.sub_dag.sub_dag_index;
+                            batches.reconfiguration = self.reconfiguration(sub_dag_index);
+                            if batches.reconfiguration.is_some() {
+                                // The current committee keeps committing until the node is
+                                // restarted with the next one, so the next sub dags are still sent.
+                                info!("Sub dag {sub_dag_index} is the last of epoch {}", self.inner.committee.epoch());
+                            }
+                            permit.send(batches);
+                        }
+                        Err(e) => {
+                            error!("tx_notifier closed: {}", e);
+                            return Ok(());
+                        }
                     }
                 },
 
@@ -185,92 +420,239 @@ impl Subscriber {
         }
     }
 
-    /// Returns ordered vector of futures for downloading batches for certificates
-    /// Order of futures returned follows order of batches in the certificates.
-    /// See BatchFetcher for more details.
-    async fn fetch_batches(inner: Arc<Inner>, deliver: CommittedSubDag) -> ConsensusOutput {
-        let num_batches = deliver.num_batches();
-        let num_certs = deliver.len();
-        if num_batches == 0 {
-            debug!("No batches to fetch, payload is empty");
-            return ConsensusOutput {
-                sub_dag: Arc::new(deliver),
-                batches: vec![],
-            };
+    /// The committee of the next epoch, if it takes over after the sub dag. Only called once the
+    /// batches of the sub dags before it are all fetched.
+    fn reconfiguration(&self, sub_dag_index: SequenceNumber) -> Option<Reconfiguration> {
+        let (_, change) = self.committee_changes.first_key_value()?;
+        if change.sub_dag_index != sub_dag_index {
+            return None;
         }
+        change
+            .apply(&self.inner.committee, &self.inner.worker_cache)
+            .ok()
+    }
 
-        let sub_dag = Arc::new(deliver);
-        let mut subscriber_output = ConsensusOutput {
+    /// Starts fetching the batches of the sub dag. Returns where they are received, and the
+    /// future fetching them, which yields the committee change found in the batches if any.
+    fn fetch(
+        inner: Arc<Inner>,
+        sub_dag: CommittedSubDag,
+    ) -> (
+        SubDagBatches,
+        impl Future<Output = (SequenceNumber, Option<ScheduledCommitteeChange>)>,
+    ) {
+        let sub_dag = Arc::new(sub_dag);
+        // The channel fits all the batches, so fetching never waits for the execution.
+        let (tx_batches, rx_batches) = channel(sub_dag.num_batches().max(1));
+        let last_executed = inner
+            .last_executed
+            .filter(|last_executed| last_executed.sub_dag_index == sub_dag.sub_dag_index);
+        let batches = SubDagBatches {
             sub_dag: sub_dag.clone(),
-            batches: Vec::with_capacity(num_certs),
+            last_executed,
+            rx_batches,
+            reconfiguration: None,
         };
+        let sub_dag_index = sub_dag.sub_dag_index;
+        let fetch = Self::fetch_batches(inner, sub_dag, tx_batches)
+            .map(move |committee_change| (sub_dag_index, committee_change));
+        (batches, fetch)
+    }
+
+    /// Fetches the batches of the sub dag, and sends each of them as soon as it and the batches
+    /// before it in the certificates are fetched. Returns the first valid committee change of the
+    /// sub dag, which is persisted before its batch is sent. The persistence is retried until it
+    /// succeeds, the executor being reported unhealthy and the batches after it held back in the
+    /// meantime.
+    /// See BatchFetcher for more details.
+    async fn fetch_batches(
+        inner: Arc<Inner>,
+        sub_dag: Arc<CommittedSubDag>,
+        tx_batches: Sender<(usize, BatchDigest, Batch)>,
+    ) -> Option<ScheduledCommitteeChange> {
+        if sub_dag.num_batches() == 0 {
+            debug!("No batches to fetch, payload is empty");
+            return None;
+        }
+
+        let mut batch_sources: HashMap<NetworkPublicKey, BatchSources> = HashMap::new();
+        // The batches in execution order, with the index of their certificate.
+        let mut payload = VecDeque::with_capacity(sub_dag.num_batches());
+
+        for (certificate_index, cert) in sub_dag.certificates.iter().enumerate() {
+            // TODO(metrics): Set `subscriber_current_round` to `cert.round() as i64`
+            // TODO(metrics): Observe `cert.metadata().created_at.elapsed().as_secs_f64()` on `subscriber_certificate_latency`
 
-        let mut batch_digests_and_workers: HashMap<
-            NetworkPublicKey,
-            (HashSet<BatchDigest>, HashSet<NetworkPublicKey>),
-        > = HashMap::new();
+            #[cfg(feature = "metrics")]
+            histogram!(
+                snarkos_metrics::subscribers::CERTIFICATE_LATENCY,
+                cert.metadata().created_at.elapsed().as_secs_f64(),
+            );
 
-        for cert in &sub_dag.certificates {
             for (digest, (worker_id, _)) in cert.header().payload().iter() {
-                let own_worker_name = inner
-                    .worker_cache
-                    .worker(
-                        inner
-                            .committee
-                            .authority(&inner.authority_id)
-                            .unwrap()
-                            .protocol_key(),
-                        worker_id,
-                    )
-                    .unwrap_or_else(|_| panic!("worker_id {worker_id} is not in the worker cache"))
-                    .name;
+                let own_worker_name = inner.own_worker_name(worker_id);
                 let workers = Self::workers_for_certificate(&inner, cert, worker_id);
-                let (batch_set, worker_set) = batch_digests_and_workers
-                    .entry(own_worker_name)
-                    .or_default();
-                batch_set.insert(*digest);
-                worker_set.extend(workers);
+                let sources = batch_sources.entry(own_worker_name).or_default();
+                sources.digests.insert(*digest);
+                sources.signers.extend(workers);
+                sources
+                    .all_workers
+                    .extend(Self::workers_for_id(&inner, worker_id));
+                payload.push_back((certificate_index, *digest));
             }
         }
 
         // TODO(metrics): Start `batch_fetch_for_committed_subdag_total_latency` timer
         // TODO(metrics): Observe `num_batches as f64` on `committed_subdag_batch_count`
-        let fetched_batches =
-            Self::fetch_batches_from_workers(&inner, batch_digests_and_workers).await;
+        let mut fetched_batches = HashMap::new();
+        let mut committee_change = None;
+        let mut result = Ok(());
+        Self::fetch_batches_from_workers(&inner, sub_dag.sub_dag_index, batch_sources, |batches| {
+            fetched_batches.extend(batches);
+            if result.is_ok() {
+                result = Self::send_fetched_batches(
+                    &inner,
+                    &sub_dag,
+                    &mut payload,
+                    &mut fetched_batches,
+                    &mut committee_change,
+                    &tx_batches,
+                );
+            }
+        })
+        .await;
         // TODO(metrics): Stop `batch_fetch_for_committed_subdag_total_latency` timer
 
-        // Map all fetched batches to their respective certificates and submit as
-        // consensus output
-        for cert in &sub_dag.certificates {
-            let mut output_batches = Vec::with_capacity(cert.header().payload().len());
-            let output_cert = cert.clone();
+        // The batches are all fetched, but the committee change could not be persisted.
+        let mut retry_delay = inner.parameters.fetch_retry_delay;
+        while let Err(e) = result {
+            inner.report_unavailable(sub_dag.sub_dag_index, e);
+            tokio::time::sleep(retry_delay).await;
+            retry_delay = (retry_delay * 2).min(inner.parameters.max_fetch_retry_delay);
+            result = Self::send_fetched_batches(
+                &inner,
+                &sub_dag,
+                &mut payload,
+                &mut fetched_batches,
+                &mut committee_change,
+                &tx_batches,
+            );
+        }
+        inner.report_available(sub_dag.sub_dag_index);
+        committee_change
+    }
+
+    /// Sends the fetched batches at the front of the payload, in order. The first valid committee
+    /// change of the sub dag is persisted before its batch is sent. Stops at the first batch which
+    /// is not fetched yet, or fails if the committee change in it cannot be persisted.
+    fn send_fetched_batches(
+        inner: &Inner,
+        sub_dag: &CommittedSubDag,
+        payload: &mut VecDeque<(usize, BatchDigest)>,
+        fetched_batches: &mut HashMap<BatchDigest, Batch>,
+        committee_change: &mut Option<ScheduledCommitteeChange>,
+        tx_batches: &Sender<(usize, BatchDigest, Batch)>,
+    ) -> SubscriberResult<()> {
+        while let Some((certificate_index, digest)) = payload.front().copied() {
+            let Some(batch) = fetched_batches.get(&digest) else {
+                break;
+            };
 
-            // TODO(metrics): Set `subscriber_current_round` to `cert.round() as i64`
-            // TODO(metrics): Observe `cert.metadata().created_at.elapsed().as_secs_f64()` on `subscriber_certificate_latency`
+            if committee_change.is_none() {
+                if let Some(transaction) = batch.transactions().iter().find_map(|transaction| {
+                    Self::committee_change(inner, sub_dag.sub_dag_index, transaction)
+                }) {
+                    inner
+                        .execution_store
+                        .write_committee_change(sub_dag.sub_dag_index, &transaction)
+                        .map_err(|e| {
+                            SubscriberError::CommitteeChangeNotPersisted(sub_dag.sub_dag_index, e)
+                        })?;
+                    let SystemTransaction::ScheduleCommitteeChange { change, .. } = transaction;
+                    *committee_change = Some(change);
+                }
+            }
 
-            #[cfg(feature = "metrics")]
-            histogram!(
-                snarkos_metrics::subscribers::CERTIFICATE_LATENCY,
-                cert.metadata().created_at.elapsed().as_secs_f64(),
+            payload.pop_front();
+            // A batch included twice is only dropped after its last occurrence.
+            let batch = if payload.iter().any(|(_, next)| *next == digest) {
+                fetched_batches[&digest].clone()
+            } else {
+                fetched_batches.remove(&digest).unwrap()
+            };
+
+            // TODO(metrics): Increment `subscriber_processed_batches`
+            debug!(
+                "Adding fetched batch {digest} from certificate {} to consensus output",
+                sub_dag.certificates[certificate_index].digest()
             );
+            // The receiver is only dropped on shutdown.
+            let _ = tx_batches.try_send((certificate_index, digest, batch));
+        }
+        Ok(())
+    }
 
-            for (digest, (_, _)) in cert.header().payload().iter() {
-                // TODO(metrics): Increment `subscriber_processed_batches`
-                let batch = fetched_batches
-                    .get(digest)
-                    .expect("[Protocol violation] Batch not found in fetched batches from workers of certificate signers");
+    /// Fetches the batches of a certified certificate into the cache, so that they are available
+    /// once the certificate is committed. It is only attempted once from the workers of the
+    /// certificate signers: the batches which are not cached are fetched again once committed.
+    async fn prefetch_batches(inner: Arc<Inner>, certificate: Certificate) {
+        let max_batches_per_fetch = inner.parameters.max_batches_per_fetch.max(1);
+        let mut digests_per_worker: HashMap<WorkerId, Vec<BatchDigest>> = HashMap::new();
+        {
+            let batch_cache = inner.batch_cache.lock().unwrap();
+            for (digest, (worker_id, _)) in certificate.header().payload().iter() {
+                if !batch_cache.contains(digest) {
+                    digests_per_worker
+                        .entry(*worker_id)
+                        .or_default()
+                        .push(*digest);
+                }
+            }
+        }
 
-                debug!(
-                    "Adding fetched batch {digest} from certificate {} to consensus output",
-                    cert.digest()
-                );
-                output_batches.push(batch.clone());
+        let mut requests = FuturesUnordered::new();
+        for (worker_id, digests) in &digests_per_worker {
+            let own_worker_name = inner.own_worker_name(worker_id);
+            let known_workers = Self::workers_for_certificate(&inner, &certificate, worker_id)
+                .into_iter()
+                .collect::<HashSet<_>>();
+            for chunk in digests.chunks(max_batches_per_fetch) {
+                let request = FetchBatchesRequest {
+                    digests: chunk.iter().copied().collect(),
+                    known_workers: known_workers.clone(),
+                };
+                requests.push(Self::fetch_from_worker(
+                    &inner,
+                    own_worker_name.clone(),
+                    request,
+                ));
             }
-            subscriber_output
-                .batches
-                .push((output_cert, output_batches));
         }
-        subscriber_output
+
+        while let Some(batches) = requests.next().await {
+            let mut batch_cache = inner.batch_cache.lock().unwrap();
+            for (digest, batch) in batches {
+                batch_cache.insert(digest, batch, inner.parameters.max_cached_batches);
+            }
+        }
+    }
+
+    /// Decodes the system transaction of the sub dag scheduling a committee change. The changes
+    /// which are not signed by a quorum, not scheduled after the sub dag or which cannot be
+    /// applied are ignored. The transaction is executed like any other either way.
+    fn committee_change(
+        inner: &Inner,
+        sub_dag_index: SequenceNumber,
+        transaction: &Transaction,
+    ) -> Option<SystemTransaction> {
+        let transaction = SystemTransaction::from_transaction(transaction)?;
+        if let Err(e) =
+            transaction.verify_scheduled_after(sub_dag_index, &inner.committee, &inner.worker_cache)
+        {
+            warn!("Ignoring the system transaction of sub dag {sub_dag_index}: {e}");
+            return None;
+        }
+        Some(transaction)
     }
 
     fn workers_for_certificate(
@@ -299,54 +681,139 @@ impl Subscriber {
             .collect()
     }
 
+    fn workers_for_id(inner: &Inner, worker_id: &WorkerId) -> Vec<NetworkPublicKey> {
+        inner
+            .committee
+            .authorities()
+            .filter_map(|authority| {
+                inner
+                    .worker_cache
+                    .worker(authority.protocol_key(), worker_id)
+                    .ok()
+            })
+            .map(|worker| worker.name)
+            .collect()
+    }
+
+    /// Fetches the batches from our workers, passing them to `on_fetched` as they are received,
+    /// and only returns once they are all fetched. The batches prefetched when their certificates
+    /// were certified are taken from the cache first. The other digests are split in requests of
+    /// at most `max_batches_per_fetch` batches which are all sent concurrently, but at most
+    /// `max_concurrent_fetches_per_worker` requests are in flight to a worker across all the
+    /// pending sub dags and the prefetching.
+    ///
+    /// The missing batches are fetched again with an exponential backoff, first from the workers
+    /// of the certificate signers, then from the workers of every authority. When none of them
+    /// returned the batches after `fetch_attempts_per_source` attempts each, the batches are
+    /// reported unavailable and the executor unhealthy. The sub dag cannot be skipped without
+    /// breaking the determinism of the execution, so the fetching goes on until it succeeds.
     async fn fetch_batches_from_workers(
         inner: &Inner,
-        batch_digests_and_workers: HashMap<
-            NetworkPublicKey,
-            (HashSet<BatchDigest>, HashSet<NetworkPublicKey>),
-        >,
-    ) -> HashMap<BatchDigest, Batch> {
-        let mut fetched_batches = HashMap::new();
-
-        for (worker_name, (digests, known_workers)) in batch_digests_and_workers {
+        sub_dag_index: SequenceNumber,
+        batch_sources: HashMap<NetworkPublicKey, BatchSources>,
+        mut on_fetched: impl FnMut(HashMap<BatchDigest, Batch>),
+    ) {
+        let max_batches_per_fetch = inner.parameters.max_batches_per_fetch.max(1);
+        let attempts_per_source = inner.parameters.fetch_attempts_per_source.max(1);
+        let mut retry_delay = inner.parameters.fetch_retry_delay;
+        let mut remaining = batch_sources
+            .values()
+            .flat_map(|sources| sources.digests.iter().copied())
+            .collect::<HashSet<_>>();
+
+        let cached = inner.batch_cache.lock().unwrap().take(&remaining);
+        if !cached.is_empty() {
             debug!(
-                "Attempting to fetch {} digests from {} known workers, {worker_name}'s",
-                digests.len(),
-                known_workers.len()
+                "{} batches of sub dag {sub_dag_index} were prefetched",
+                cached.len()
             );
-            // TODO: Can further parallelize this by worker if necessary. Maybe move the logic
-            // to NetworkClient.
-            // Only have one worker for now so will leave this for a future
-            // optimization.
-            let request = FetchBatchesRequest {
-                digests,
-                known_workers,
-            };
-            let batches = loop {
-                match inner
-                    .client
-                    .fetch_batches(worker_name.clone(), request.clone())
-                    .await
-                {
-                    Ok(resp) => break resp.batches,
-                    Err(e) => {
-                        error!("Failed to fetch batches from worker {worker_name}: {e:?}");
-                        // Loop forever on failure. During shutdown, this should get cancelled.
-                        tokio::time::sleep(Duration::from_secs(1)).await;
-                        continue;
-                    }
+            remaining.retain(|digest| !cached.contains_key(digest));
+            on_fetched(cached);
+        }
+
+        for attempt in 1.. {
+            let mut requests = FuturesUnordered::new();
+            for (worker_name, sources) in &batch_sources {
+                let digests = sources
+                    .digests
+                    .iter()
+                    .filter(|digest| remaining.contains(*digest))
+                    .copied()
+                    .collect::<Vec<_>>();
+                let known_workers = if attempt <= attempts_per_source {
+                    &sources.signers
+                } else {
+                    &sources.all_workers
+                };
+                debug!(
+                    "Attempt #{attempt} to fetch {} digests from {} known workers, {worker_name}'s",
+                    digests.len(),
+                    known_workers.len()
+                );
+                for chunk in digests.chunks(max_batches_per_fetch) {
+                    let request = FetchBatchesRequest {
+                        digests: chunk.iter().copied().collect(),
+                        known_workers: known_workers.clone(),
+                    };
+                    requests.push(Self::fetch_from_worker(inner, worker_name.clone(), request));
                 }
-            };
-            for (digest, batch) in batches {
+            }
+
+            while let Some(batches) = requests.next().await {
                 // let batch_fetch_duration = batch.metadata().created_at.elapsed().as_secs_f64();
                 // TODO(metrics): Observe `batch_fetch_duration` on `batch_fetch_latency`
+                on_fetched(
+                    batches
+                        .into_iter()
+                        .filter(|(digest, _)| remaining.remove(digest))
+                        .collect(),
+                );
+            }
 
-                fetched_batches.insert(digest, batch);
+            if remaining.is_empty() {
+                break;
+            }
+            let missing = remaining.iter().copied().collect::<Vec<_>>();
+            if attempt == 2 * attempts_per_source {
+                inner.report_unavailable(
+                    sub_dag_index,
+                    SubscriberError::BatchesUnavailable(sub_dag_index, missing),
+                );
+            } else {
+                warn!(
+                    "Failed to fetch {} batches of sub dag {sub_dag_index} after {attempt} attempts",
+                    missing.len()
+                );
             }
+
+            // Loop forever on failure. During shutdown, this should get cancelled.
+            tokio::time::sleep(retry_delay).await;
+            retry_delay = (retry_delay * 2).min(inner.parameters.max_fetch_retry_delay);
         }
 
-        fetched_batches
+        inner.report_available(sub_dag_index);
     }
-}
 
-// TODO: add a unit test
+    async fn fetch_from_worker(
+        inner: &Inner,
+        worker_name: NetworkPublicKey,
+        request: FetchBatchesRequest,
+    ) -> HashMap<BatchDigest, Batch> {
+        let permits = inner.fetch_permits(&worker_name);
+        let _permit = permits
+            .acquire()
+            .await
+            .expect("The fetch permits are never closed");
+        match inner
+            .client
+            .fetch_batches(worker_name.clone(), request)
+            .await
+        {
+            Ok(resp) => resp.batches,
+            Err(e) => {
+                error!("Failed to fetch batches from worker {worker_name}: {e:?}");
+                HashMap::new()
+            }
+        }
+    }
+}
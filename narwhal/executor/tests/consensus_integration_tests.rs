@@ -6,20 +6,19 @@ use consensus::consensus::ConsensusRound;
 use consensus::Consensus;
 use crypto::Hash;
 use narwhal_executor::get_restored_consensus_output;
-use narwhal_executor::MockExecutionState;
 use primary::NUM_SHUTDOWN_RECEIVERS;
 use std::collections::BTreeSet;
 use storage::NodeStorage;
 use telemetry_subscribers::TelemetryGuards;
-use test_utils::{cluster::Cluster, temp_dir, CommitteeFixture};
+use test_utils::{cluster::Cluster, CommitteeFixture};
 use tokio::sync::watch;
 
-use types::{Certificate, PreSubscribedBroadcastSender, Round, TransactionProto};
+use types::{Certificate, ExecutionIndices, PreSubscribedBroadcastSender, Round, TransactionProto};
 
 #[tokio::test]
 async fn test_recovery() {
     // Create storage
-    let storage = NodeStorage::reopen(temp_dir());
+    let storage = NodeStorage::new_for_tests();
 
     let consensus_store = storage.consensus_store;
     let certificate_store = storage.certificate_store;
@@ -88,11 +87,10 @@ async fn test_recovery() {
 
     // Ensure the first 4 ordered certificates are from round 1 (they are the parents of the committed
     // leader); then the leader's certificate should be committed.
-    let consensus_index_counter = 4;
     let num_of_committed_certificates = 5;
 
     let committed_sub_dag = rx_output.recv().await.unwrap();
-    let mut sequence = committed_sub_dag.certificates.into_iter();
+    let mut sequence = committed_sub_dag.certificates.clone().into_iter();
     for i in 1..=num_of_committed_certificates {
         let output = sequence.next().unwrap();
 
@@ -103,31 +101,46 @@ async fn test_recovery() {
         }
     }
 
-    // Now assume that we want to recover from a crash. We are testing all the recovery cases
-    // from having executed no certificates at all (or certificate with index = 0), up to
-    // have executed the last committed certificate
-    for last_executed_certificate_index in 0..consensus_index_counter {
-        let mut execution_state = MockExecutionState::new();
-        execution_state
-            .expect_last_executed_sub_dag_index()
-            .times(1)
-            .returning(|| 1);
+    // Now assume that we want to recover from a crash. The committed sub-dag is recovered until
+    // the execution state acknowledges all its transactions.
+    let execution_store = storage.execution_store;
+    let last_committed_round = committed_sub_dag.leader_round();
+    let sub_dag_index = committed_sub_dag.sub_dag_index;
+    for (last_executed, expected_sub_dags) in [
+        (None, 1),
+        (
+            Some(ExecutionIndices {
+                last_committed_round,
+                sub_dag_index,
+                transaction_index: 2,
+            }),
+            1,
+        ),
+        (
+            Some(ExecutionIndices::end_of_sub_dag(
+                last_committed_round,
+                sub_dag_index,
+            )),
+            0,
+        ),
+    ] {
+        if let Some(last_executed) = last_executed {
+            execution_store.write_last_executed(&last_executed).unwrap();
+        }
 
         let consensus_output = get_restored_consensus_output(
             consensus_store.clone(),
             certificate_store.clone(),
-            &execution_state,
+            &execution_store,
         )
         .await
         .unwrap();
 
-        // we expect to have recovered all the certificates from the last commit. The Sui executor engine
-        // will not execute twice the same certificate.
-        assert_eq!(consensus_output.len(), 1);
-        assert!(
-            consensus_output[0].len()
-                >= (num_of_committed_certificates - last_executed_certificate_index) as usize
-        );
+        assert_eq!(consensus_output.len(), expected_sub_dags);
+        if let Some(sub_dag) = consensus_output.first() {
+            assert_eq!(sub_dag.sub_dag_index, sub_dag_index);
+            assert_eq!(sub_dag.len(), num_of_committed_certificates);
+        }
     }
 }
 
@@ -0,0 +1,282 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use config::PruningParameters;
+use consensus::consensus::ConsensusRound;
+use crypto::Hash;
+use narwhal_executor::Pruner;
+use primary::NUM_SHUTDOWN_RECEIVERS;
+use std::collections::{BTreeSet, HashMap};
+use std::time::Duration;
+use storage::NodeStorage;
+use test_utils::CommitteeFixture;
+use tokio::sync::watch;
+use types::{
+    Certificate, CertificateAPI, CommittedSubDag, ExecutionIndices, Header, HeaderAPI,
+    PreSubscribedBroadcastSender, ReputationScores, Round,
+};
+
+const GC_DEPTH: Round = 4;
+const RETENTION_ROUNDS: Round = 5;
+
+/// Writes certificates for rounds 1 to 20 with their headers and payload markers, and commits the
+/// sub dag with index 5 whose leader is at round 16.
+fn populate_storage(storage: &NodeStorage) -> Vec<Certificate> {
+    let fixture = CommitteeFixture::builder().build();
+    let committee = fixture.committee();
+    let keypair = fixture.authorities().next().unwrap().keypair().clone();
+    let ids: Vec<_> = fixture.authorities().map(|a| a.id()).collect();
+    let genesis = Certificate::genesis(&committee, keypair.private())
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+    let (certificates, _) =
+        test_utils::make_optimal_certificates(&committee, 1..=20, &genesis, &ids);
+    let certificates: Vec<_> = certificates.into_iter().collect();
+
+    for certificate in &certificates {
+        storage
+            .certificate_store
+            .write(certificate.clone())
+            .unwrap();
+        storage.header_store.write(certificate.header()).unwrap();
+        for (digest, (worker_id, _)) in certificate.header().payload() {
+            storage.payload_store.write(digest, worker_id).unwrap();
+        }
+    }
+
+    let leader = certificates
+        .iter()
+        .find(|c| c.round() == 16)
+        .unwrap()
+        .clone();
+    let sub_dag = CommittedSubDag::new(
+        vec![leader.clone()],
+        leader,
+        5,
+        ReputationScores::default(),
+        None,
+    );
+    storage
+        .consensus_store
+        .write_consensus_state(&HashMap::new(), &sub_dag)
+        .unwrap();
+
+    certificates
+}
+
+fn parameters() -> PruningParameters {
+    PruningParameters {
+        enabled: true,
+        retention_rounds: RETENTION_ROUNDS,
+        interval: Duration::from_millis(10),
+        max_certificates_per_batch: 3,
+    }
+}
+
+fn is_stored(storage: &NodeStorage, certificate: &Certificate) -> bool {
+    let header = certificate.header();
+    let (digest, (worker_id, _)) = header.payload().iter().next().unwrap();
+    let stored = storage
+        .certificate_store
+        .read(certificate.digest())
+        .unwrap()
+        .is_some();
+
+    // The headers and payload markers go along with their certificate.
+    assert_eq!(
+        storage
+            .header_store
+            .read(&header.digest())
+            .unwrap()
+            .is_some(),
+        stored
+    );
+    assert_eq!(
+        storage.payload_store.contains(*digest, *worker_id).unwrap(),
+        stored
+    );
+    stored
+}
+
+#[tokio::test]
+async fn test_prune_below_gc_and_executed_rounds() {
+    let storage = NodeStorage::new_for_tests();
+    let certificates = populate_storage(&storage);
+
+    // The last executed sub dag needs the rounds above 16 - GC_DEPTH, which is below the GC round
+    // of consensus, so the pruning is bounded by execution.
+    storage
+        .execution_store
+        .write_last_executed(&ExecutionIndices::end_of_sub_dag(16, 5))
+        .unwrap();
+    let (_tx_consensus_round_updates, rx_consensus_round_updates) =
+        watch::channel(ConsensusRound::new(18, 14));
+    let mut tx_shutdown = PreSubscribedBroadcastSender::new(NUM_SHUTDOWN_RECEIVERS);
+
+    let handle = Pruner::spawn(
+        parameters(),
+        GC_DEPTH,
+        &storage,
+        tx_shutdown.subscribe(),
+        Some(rx_consensus_round_updates),
+    );
+
+    // Wait for the pruner to catch up with the retention window.
+    let pruned_round = 16 - GC_DEPTH - RETENTION_ROUNDS;
+    let last_pruned = certificates
+        .iter()
+        .find(|c| c.round() == pruned_round)
+        .unwrap();
+    while storage
+        .certificate_store
+        .read(last_pruned.digest())
+        .unwrap()
+        .is_some()
+    {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    for certificate in &certificates {
+        assert_eq!(
+            is_stored(&storage, certificate),
+            certificate.round() > pruned_round
+        );
+    }
+
+    tx_shutdown.send().unwrap();
+    handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_no_pruning_before_execution() {
+    let storage = NodeStorage::new_for_tests();
+    let certificates = populate_storage(&storage);
+
+    // Nothing has been executed yet, so everything is needed for recovery.
+    let (_tx_consensus_round_updates, rx_consensus_round_updates) =
+        watch::channel(ConsensusRound::new(18, 14));
+    let mut tx_shutdown = PreSubscribedBroadcastSender::new(NUM_SHUTDOWN_RECEIVERS);
+
+    let handle = Pruner::spawn(
+        parameters(),
+        GC_DEPTH,
+        &storage,
+        tx_shutdown.subscribe(),
+        Some(rx_consensus_round_updates),
+    );
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    for certificate in &certificates {
+        assert!(is_stored(&storage, certificate));
+    }
+
+    tx_shutdown.send().unwrap();
+    handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_keep_payload_of_retained_certificates() {
+    let storage = NodeStorage::new_for_tests();
+    let certificates = populate_storage(&storage);
+    storage
+        .execution_store
+        .write_last_executed(&ExecutionIndices::end_of_sub_dag(16, 5))
+        .unwrap();
+
+    // A pruned certificate includes the same batch as a retained one.
+    let pruned_round = 16 - GC_DEPTH - RETENTION_ROUNDS;
+    let retained = certificates
+        .iter()
+        .find(|c| c.round() == pruned_round + 1)
+        .unwrap();
+    let (digest, (worker_id, timestamp)) = retained
+        .header()
+        .payload()
+        .iter()
+        .map(|(digest, value)| (*digest, *value))
+        .next()
+        .unwrap();
+    let fixture = CommitteeFixture::builder().build();
+    let committee = fixture.committee();
+    let header = fixture
+        .authorities()
+        .next()
+        .unwrap()
+        .header_builder(&committee)
+        .round(pruned_round)
+        .payload([(digest, (worker_id, timestamp))].into_iter().collect())
+        .build();
+    let duplicate = fixture.certificate(&Header::V2(header));
+    storage.certificate_store.write(duplicate.clone()).unwrap();
+
+    let (_tx_consensus_round_updates, rx_consensus_round_updates) =
+        watch::channel(ConsensusRound::new(18, 14));
+    let mut tx_shutdown = PreSubscribedBroadcastSender::new(NUM_SHUTDOWN_RECEIVERS);
+    let handle = Pruner::spawn(
+        parameters(),
+        GC_DEPTH,
+        &storage,
+        tx_shutdown.subscribe(),
+        Some(rx_consensus_round_updates),
+    );
+
+    while storage
+        .certificate_store
+        .read(duplicate.digest())
+        .unwrap()
+        .is_some()
+    {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    // Let the pruner delete the payload of the pruned certificates.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // The payload marker is still needed by the retained certificate.
+    assert!(storage.payload_store.contains(digest, worker_id).unwrap());
+    assert!(is_stored(&storage, retained));
+
+    tx_shutdown.send().unwrap();
+    handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_prune_with_external_consensus() {
+    let storage = NodeStorage::new_for_tests();
+    let certificates = populate_storage(&storage);
+
+    // Without consensus nor executor, only the highest round of the DAG bounds the pruning.
+    let mut tx_shutdown = PreSubscribedBroadcastSender::new(NUM_SHUTDOWN_RECEIVERS);
+    let handle = Pruner::spawn(
+        parameters(),
+        GC_DEPTH,
+        &storage,
+        tx_shutdown.subscribe(),
+        None,
+    );
+
+    let pruned_round = 20 - GC_DEPTH - RETENTION_ROUNDS;
+    let last_pruned = certificates
+        .iter()
+        .find(|c| c.round() == pruned_round)
+        .unwrap();
+    while storage
+        .certificate_store
+        .read(last_pruned.digest())
+        .unwrap()
+        .is_some()
+    {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    for certificate in &certificates {
+        assert_eq!(
+            is_stored(&storage, certificate),
+            certificate.round() > pruned_round
+        );
+    }
+
+    tx_shutdown.send().unwrap();
+    handle.await.unwrap();
+}
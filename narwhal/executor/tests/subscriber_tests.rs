@@ -0,0 +1,594 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use async_trait::async_trait;
+use config::ExecutorParameters;
+use crypto::{Hash, NetworkPublicKey};
+use narwhal_executor::{
+    ConsensusTransaction, ConsensusTransactions, ExecutionAck, ExecutionState, Executor,
+    ExecutorHealth, Reconfiguration, SubscriberError, TransactionStreamState,
+};
+use network::client::NetworkClient;
+use primary::NUM_SHUTDOWN_RECEIVERS;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use storage::ExecutionStore;
+use test_utils::{fixture_batch_with_transactions, AuthorityFixture, CommitteeFixture};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use types::{
+    Batch, BatchAPI, BatchDigest, Certificate, CommittedSubDag, CommitteeChange, ConsensusOutput,
+    ExecutionIndices, FetchBatchesRequest, FetchBatchesResponse, Header,
+    PreSubscribedBroadcastSender, PrimaryToWorker, ReputationScores, ScheduledCommitteeChange,
+    SystemTransaction, WorkerDeleteBatchesMessage, WorkerRoundUpdateMessage,
+    WorkerSynchronizeMessage,
+};
+
+/// A worker serving its batches slowly, recording how many fetch requests are in flight.
+struct SlowWorker {
+    batches: HashMap<BatchDigest, Batch>,
+    in_flight: AtomicUsize,
+    max_in_flight: AtomicUsize,
+}
+
+#[async_trait]
+impl PrimaryToWorker for SlowWorker {
+    async fn synchronize(
+        &self,
+        _request: anemo::Request<WorkerSynchronizeMessage>,
+    ) -> Result<anemo::Response<()>, anemo::rpc::Status> {
+        unimplemented!()
+    }
+
+    async fn fetch_batches(
+        &self,
+        request: anemo::Request<FetchBatchesRequest>,
+    ) -> Result<anemo::Response<FetchBatchesResponse>, anemo::rpc::Status> {
+        let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+        let batches = request
+            .into_body()
+            .digests
+            .into_iter()
+            .map(|digest| (digest, self.batches[&digest].clone()))
+            .collect();
+        Ok(anemo::Response::new(FetchBatchesResponse { batches }))
+    }
+
+    async fn delete_batches(
+        &self,
+        _request: anemo::Request<WorkerDeleteBatchesMessage>,
+    ) -> Result<anemo::Response<()>, anemo::rpc::Status> {
+        unimplemented!()
+    }
+
+    async fn update_round(
+        &self,
+        _request: anemo::Request<WorkerRoundUpdateMessage>,
+    ) -> Result<anemo::Response<()>, anemo::rpc::Status> {
+        unimplemented!()
+    }
+}
+
+/// A worker which does not find the withheld batches, recording the workers it is asked to fetch
+/// the batches from.
+struct WithholdingWorker {
+    batches: HashMap<BatchDigest, Batch>,
+    withheld: Mutex<HashSet<BatchDigest>>,
+    known_workers: Mutex<Vec<HashSet<NetworkPublicKey>>>,
+}
+
+impl WithholdingWorker {
+    fn new(batches: &[Batch], withheld: &[Batch]) -> Self {
+        Self {
+            batches: batches
+                .iter()
+                .map(|batch| (batch.digest(), batch.clone()))
+                .collect(),
+            withheld: Mutex::new(withheld.iter().map(|batch| batch.digest()).collect()),
+            known_workers: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn release(&self) {
+        self.withheld.lock().unwrap().clear();
+    }
+
+    fn withhold(&self, batches: &[Batch]) {
+        self.withheld
+            .lock()
+            .unwrap()
+            .extend(batches.iter().map(|batch| batch.digest()));
+    }
+}
+
+#[async_trait]
+impl PrimaryToWorker for WithholdingWorker {
+    async fn synchronize(
+        &self,
+        _request: anemo::Request<WorkerSynchronizeMessage>,
+    ) -> Result<anemo::Response<()>, anemo::rpc::Status> {
+        unimplemented!()
+    }
+
+    async fn fetch_batches(
+        &self,
+        request: anemo::Request<FetchBatchesRequest>,
+    ) -> Result<anemo::Response<FetchBatchesResponse>, anemo::rpc::Status> {
+        let request = request.into_body();
+        self.known_workers
+            .lock()
+            .unwrap()
+            .push(request.known_workers);
+
+        let withheld = self.withheld.lock().unwrap().clone();
+        let batches = request
+            .digests
+            .into_iter()
+            .filter(|digest| !withheld.contains(digest))
+            .map(|digest| (digest, self.batches[&digest].clone()))
+            .collect();
+        Ok(anemo::Response::new(FetchBatchesResponse { batches }))
+    }
+
+    async fn delete_batches(
+        &self,
+        _request: anemo::Request<WorkerDeleteBatchesMessage>,
+    ) -> Result<anemo::Response<()>, anemo::rpc::Status> {
+        unimplemented!()
+    }
+
+    async fn update_round(
+        &self,
+        _request: anemo::Request<WorkerRoundUpdateMessage>,
+    ) -> Result<anemo::Response<()>, anemo::rpc::Status> {
+        unimplemented!()
+    }
+}
+
+/// An execution state forwarding the outputs it receives.
+struct ForwardingExecutionState {
+    tx_output: Sender<ConsensusOutput>,
+}
+
+#[async_trait]
+impl ExecutionState for ForwardingExecutionState {
+    async fn handle_consensus_output(&self, consensus_output: ConsensusOutput, ack: ExecutionAck) {
+        self.tx_output.send(consensus_output).await.unwrap();
+        ack.ack_all().unwrap();
+    }
+}
+
+/// An execution state forwarding the transactions streamed to it, and None at the end of each
+/// sub dag.
+struct StreamingExecutionState {
+    tx_transaction: Sender<Option<ConsensusTransaction>>,
+}
+
+#[async_trait]
+impl TransactionStreamState for StreamingExecutionState {
+    async fn handle_consensus_transactions(
+        &self,
+        _sub_dag: Arc<CommittedSubDag>,
+        mut transactions: ConsensusTransactions,
+        ack: ExecutionAck,
+    ) {
+        while let Some(transaction) = transactions.next().await {
+            self.tx_transaction.send(Some(transaction)).await.unwrap();
+        }
+        ack.ack_all().unwrap();
+        self.tx_transaction.send(None).await.unwrap();
+    }
+}
+
+/// Commits a certificate of `author` carrying `batches` as the sub dag `index`.
+fn sub_dag(
+    fixture: &CommitteeFixture,
+    author: &AuthorityFixture,
+    index: u64,
+    batches: &[Batch],
+) -> CommittedSubDag {
+    let committee = fixture.committee();
+    let genesis = Certificate::genesis(&committee, author.keypair().private())
+        .iter()
+        .map(|certificate| certificate.digest())
+        .collect::<BTreeSet<_>>();
+    let mut builder = author
+        .header_builder(&committee)
+        .round(index)
+        .parents(genesis);
+    for batch in batches {
+        builder = builder.with_payload_batch(batch.clone(), 0, 0);
+    }
+    let certificate = fixture.certificate(&Header::V2(builder.build()));
+    CommittedSubDag::new(
+        vec![certificate.clone()],
+        certificate,
+        index,
+        ReputationScores::default(),
+        None,
+    )
+}
+
+/// A client of `primary`, whose worker is served by `worker`.
+fn network_client(primary: &AuthorityFixture, worker: Arc<dyn PrimaryToWorker>) -> NetworkClient {
+    let client = NetworkClient::new_from_keypair(&primary.network_keypair());
+    let worker_peer_id = anemo::PeerId(primary.worker(0).keypair().public().0.to_bytes());
+    client.set_primary_to_worker_local_handler(worker_peer_id, worker);
+    client
+}
+
+/// Spawns the executor of `primary`, whose worker is served by `worker`. The batches of the
+/// certificates received on `rx_certified` are prefetched.
+fn spawn_executor(
+    fixture: &CommitteeFixture,
+    primary: &AuthorityFixture,
+    worker: Arc<dyn PrimaryToWorker>,
+    parameters: ExecutorParameters,
+    rx_certified: Receiver<Certificate>,
+    tx_shutdown: &mut PreSubscribedBroadcastSender,
+) -> (
+    Sender<CommittedSubDag>,
+    Receiver<ConsensusOutput>,
+    watch::Receiver<ExecutorHealth>,
+    watch::Receiver<Option<Reconfiguration>>,
+    Vec<JoinHandle<()>>,
+) {
+    let (tx_sequence, rx_sequence) = channel(10);
+    let (tx_output, rx_output) = channel(10);
+    let (tx_health, rx_health) = watch::channel(ExecutorHealth::Healthy);
+    let (tx_reconfiguration, rx_reconfiguration) = watch::channel(None);
+    let handles = Executor::spawn(
+        primary.id(),
+        fixture.worker_cache(),
+        fixture.committee(),
+        network_client(primary, worker),
+        parameters,
+        ForwardingExecutionState { tx_output },
+        ExecutionStore::new_for_tests(),
+        tx_shutdown.subscribe_n(2),
+        rx_sequence,
+        rx_certified,
+        tx_health,
+        tx_reconfiguration,
+        Vec::new(),
+    )
+    .unwrap();
+    (
+        tx_sequence,
+        rx_output,
+        rx_health,
+        rx_reconfiguration,
+        handles,
+    )
+}
+
+#[tokio::test]
+async fn test_fetch_batches_concurrently_and_deliver_in_order() {
+    const NUM_SUB_DAGS: u64 = 3;
+    const BATCHES_PER_SUB_DAG: usize = 4;
+
+    let fixture = CommitteeFixture::builder().build();
+    let primary = fixture.authorities().next().unwrap();
+
+    // Every sub dag commits a certificate with several batches.
+    let mut batches = HashMap::new();
+    let mut sub_dags = Vec::new();
+    for index in 1..=NUM_SUB_DAGS {
+        let sub_dag_batches = (0..BATCHES_PER_SUB_DAG)
+            .map(|_| fixture_batch_with_transactions(2))
+            .collect::<Vec<_>>();
+        batches.extend(
+            sub_dag_batches
+                .iter()
+                .map(|batch| (batch.digest(), batch.clone())),
+        );
+        sub_dags.push(sub_dag(&fixture, primary, index, &sub_dag_batches));
+    }
+
+    let worker = Arc::new(SlowWorker {
+        batches,
+        in_flight: AtomicUsize::new(0),
+        max_in_flight: AtomicUsize::new(0),
+    });
+    let parameters = ExecutorParameters {
+        max_concurrent_fetches_per_worker: 2,
+        max_batches_per_fetch: 1,
+        ..ExecutorParameters::default()
+    };
+    let mut tx_shutdown = PreSubscribedBroadcastSender::new(NUM_SHUTDOWN_RECEIVERS);
+    let (tx_sequence, mut rx_output, _rx_health, _rx_reconfiguration, _handles) = spawn_executor(
+        &fixture,
+        primary,
+        worker.clone(),
+        parameters,
+        channel(10).1,
+        &mut tx_shutdown,
+    );
+
+    for sub_dag in sub_dags {
+        tx_sequence.send(sub_dag).await.unwrap();
+    }
+
+    // The sub dags are delivered in order, with all their batches.
+    for index in 1..=NUM_SUB_DAGS {
+        let output = rx_output.recv().await.unwrap();
+        assert_eq!(output.sub_dag.sub_dag_index, index);
+        assert_eq!(output.batches.len(), 1);
+        assert_eq!(output.batches[0].1.len(), BATCHES_PER_SUB_DAG);
+    }
+
+    // The batches are fetched concurrently, within the limit of the worker.
+    assert_eq!(worker.max_in_flight.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn test_unavailable_batches() {
+    let fixture = CommitteeFixture::builder().build();
+    let mut authorities = fixture.authorities();
+    let primary = authorities.next().unwrap();
+    let author = authorities.next().unwrap();
+    let author_worker = author.worker(0).info().name.clone();
+
+    let batch = fixture_batch_with_transactions(2);
+    let worker = Arc::new(WithholdingWorker::new(&[batch.clone()], &[batch.clone()]));
+    let parameters = ExecutorParameters {
+        fetch_retry_delay: Duration::from_millis(10),
+        max_fetch_retry_delay: Duration::from_millis(50),
+        fetch_attempts_per_source: 2,
+        ..ExecutorParameters::default()
+    };
+    let mut tx_shutdown = PreSubscribedBroadcastSender::new(NUM_SHUTDOWN_RECEIVERS);
+    let (tx_sequence, mut rx_output, mut rx_health, _rx_reconfiguration, _handles) = spawn_executor(
+        &fixture,
+        primary,
+        worker.clone(),
+        parameters,
+        channel(10).1,
+        &mut tx_shutdown,
+    );
+
+    tx_sequence
+        .send(sub_dag(&fixture, author, 1, &[batch.clone()]))
+        .await
+        .unwrap();
+
+    // Instead of crashing, the executor reports the missing batch once every worker was asked.
+    rx_health.changed().await.unwrap();
+    match &*rx_health.borrow() {
+        ExecutorHealth::Unhealthy(SubscriberError::BatchesUnavailable(index, missing)) => {
+            assert_eq!(*index, 1);
+            assert_eq!(missing, &vec![batch.digest()]);
+        }
+        health => panic!("Unexpected executor health {health:?}"),
+    }
+
+    // The batch is first fetched from the workers of the certificate signers, which do not
+    // include the author, then from the workers of every authority.
+    let known_workers = worker.known_workers.lock().unwrap().clone();
+    assert!(known_workers.len() >= 4);
+    assert!(known_workers[..2]
+        .iter()
+        .all(|workers| !workers.contains(&author_worker)));
+    assert!(known_workers[2..]
+        .iter()
+        .all(|workers| workers.contains(&author_worker)));
+
+    // The executor keeps trying, and recovers once the batch can be fetched.
+    worker.release();
+    let output = rx_output.recv().await.unwrap();
+    assert_eq!(output.sub_dag.sub_dag_index, 1);
+    assert_eq!(output.batches[0].1, vec![batch]);
+    assert!(matches!(*rx_health.borrow(), ExecutorHealth::Healthy));
+}
+
+#[tokio::test]
+async fn test_prefetch_certified_batches() {
+    let fixture = CommitteeFixture::builder().build();
+    let mut authorities = fixture.authorities();
+    let primary = authorities.next().unwrap();
+    let author = authorities.next().unwrap();
+
+    let batch = fixture_batch_with_transactions(2);
+    let worker = Arc::new(WithholdingWorker::new(&[batch.clone()], &[]));
+    let (tx_certified, rx_certified) = channel(10);
+    let mut tx_shutdown = PreSubscribedBroadcastSender::new(NUM_SHUTDOWN_RECEIVERS);
+    let (tx_sequence, mut rx_output, _rx_health, _rx_reconfiguration, _handles) = spawn_executor(
+        &fixture,
+        primary,
+        worker.clone(),
+        ExecutorParameters::default(),
+        rx_certified,
+        &mut tx_shutdown,
+    );
+
+    // The batch is fetched as soon as its certificate is certified.
+    let sub_dag = sub_dag(&fixture, author, 1, &[batch.clone()]);
+    tx_certified
+        .send(sub_dag.certificates[0].clone())
+        .await
+        .unwrap();
+    while worker.known_workers.lock().unwrap().is_empty() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // Once committed, the batch is taken from the cache, and not fetched again.
+    worker.withhold(&[batch.clone()]);
+    tx_sequence.send(sub_dag).await.unwrap();
+    let output = rx_output.recv().await.unwrap();
+    assert_eq!(output.sub_dag.sub_dag_index, 1);
+    assert_eq!(output.batches[0].1, vec![batch]);
+    assert_eq!(worker.known_workers.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_stream_transactions() {
+    let fixture = CommitteeFixture::builder().build();
+    let mut authorities = fixture.authorities();
+    let primary = authorities.next().unwrap();
+    let author = authorities.next().unwrap();
+
+    let batches = vec![
+        fixture_batch_with_transactions(2),
+        fixture_batch_with_transactions(3),
+    ];
+    let worker = Arc::new(WithholdingWorker::new(&batches, &batches[1..]));
+    let parameters = ExecutorParameters {
+        max_batches_per_fetch: 1,
+        fetch_retry_delay: Duration::from_millis(10),
+        max_fetch_retry_delay: Duration::from_millis(10),
+        ..ExecutorParameters::default()
+    };
+    let execution_store = ExecutionStore::new_for_tests();
+    let (tx_sequence, rx_sequence) = channel(10);
+    let (tx_transaction, mut rx_transaction) = channel(10);
+    let (tx_health, _rx_health) = watch::channel(ExecutorHealth::Healthy);
+    let (tx_reconfiguration, _rx_reconfiguration) = watch::channel(None);
+    let mut tx_shutdown = PreSubscribedBroadcastSender::new(NUM_SHUTDOWN_RECEIVERS);
+    let _handles = Executor::spawn_streaming(
+        primary.id(),
+        fixture.worker_cache(),
+        fixture.committee(),
+        network_client(primary, worker.clone()),
+        parameters,
+        StreamingExecutionState { tx_transaction },
+        execution_store.clone(),
+        tx_shutdown.subscribe_n(2),
+        rx_sequence,
+        channel(10).1,
+        tx_health,
+        tx_reconfiguration,
+        Vec::new(),
+    )
+    .unwrap();
+
+    let sub_dag = sub_dag(&fixture, author, 1, &batches);
+    let leader_round = sub_dag.leader_round();
+    let commit_timestamp = sub_dag.commit_timestamp();
+    tx_sequence.send(sub_dag).await.unwrap();
+
+    // The transactions of the first batch are streamed while the second one is not fetched yet.
+    let mut received = Vec::new();
+    for _ in 0..batches[0].transactions().len() {
+        received.push(rx_transaction.recv().await.unwrap().unwrap());
+    }
+    assert!(rx_transaction.try_recv().is_err());
+
+    worker.release();
+    while let Some(transaction) = rx_transaction.recv().await.unwrap() {
+        received.push(transaction);
+    }
+
+    // The transactions are streamed in the order of the payload, with their metadata.
+    let expected = batches
+        .iter()
+        .flat_map(|batch| {
+            batch
+                .transactions()
+                .iter()
+                .map(|transaction| (batch.digest(), transaction.clone()))
+        })
+        .enumerate()
+        .collect::<Vec<_>>();
+    assert_eq!(received.len(), expected.len());
+    for (transaction, (index, (digest, expected_transaction))) in received.iter().zip(expected) {
+        assert_eq!(
+            transaction.indices,
+            ExecutionIndices {
+                last_committed_round: leader_round,
+                sub_dag_index: 1,
+                transaction_index: index as u64,
+            }
+        );
+        assert_eq!(transaction.certificate_origin, author.id());
+        assert_eq!(transaction.batch_digest, digest);
+        assert_eq!(transaction.commit_timestamp, commit_timestamp);
+        assert_eq!(transaction.transaction, expected_transaction);
+    }
+
+    // The whole sub dag was acknowledged once the stream was exhausted.
+    assert_eq!(
+        execution_store.read_last_executed().unwrap(),
+        Some(ExecutionIndices::end_of_sub_dag(leader_round, 1))
+    );
+}
+
+#[tokio::test]
+async fn test_reconfiguration() {
+    let fixture = CommitteeFixture::builder().build();
+    let committee = fixture.committee();
+    let authorities = fixture.authorities().collect::<Vec<_>>();
+    let primary = authorities[0];
+
+    // A quorum of the committee schedules a change after the sub dag 3.
+    let change = ScheduledCommitteeChange {
+        epoch: committee.epoch(),
+        sub_dag_index: 3,
+        changes: vec![CommitteeChange::UpdateStake {
+            protocol_key: authorities[1].public_key(),
+            stake: 2,
+        }],
+    };
+    let system_transaction = SystemTransaction::ScheduleCommitteeChange {
+        change: change.clone(),
+        signatures: authorities[..3]
+            .iter()
+            .map(|authority| (authority.id(), change.sign(authority.keypair().private())))
+            .collect(),
+    };
+    let mut batches = vec![Batch::new(vec![system_transaction.to_transaction()])];
+    batches.extend((2..=4).map(|_| fixture_batch_with_transactions(2)));
+
+    let worker = Arc::new(WithholdingWorker::new(&batches, &[]));
+    let mut tx_shutdown = PreSubscribedBroadcastSender::new(NUM_SHUTDOWN_RECEIVERS);
+    let (tx_sequence, mut rx_output, _rx_health, mut rx_reconfiguration, _handles) = spawn_executor(
+        &fixture,
+        primary,
+        worker,
+        ExecutorParameters::default(),
+        channel(10).1,
+        &mut tx_shutdown,
+    );
+
+    for (index, batch) in batches.iter().enumerate() {
+        tx_sequence
+            .send(sub_dag(
+                &fixture,
+                primary,
+                index as u64 + 1,
+                &[batch.clone()],
+            ))
+            .await
+            .unwrap();
+    }
+
+    // The sub dags up to the last one of the current committee are executed.
+    for index in 1..=3 {
+        let output = rx_output.recv().await.unwrap();
+        assert_eq!(output.sub_dag.sub_dag_index, index);
+    }
+
+    // The committee of the next epoch takes over.
+    rx_reconfiguration.changed().await.unwrap();
+    let reconfiguration = rx_reconfiguration.borrow().clone().unwrap();
+    assert_eq!(reconfiguration.sub_dag_index, 3);
+    assert_eq!(reconfiguration.committee.epoch(), committee.epoch() + 1);
+    assert_eq!(
+        reconfiguration
+            .committee
+            .stake(&authorities[1].public_key()),
+        2
+    );
+    assert_eq!(reconfiguration.worker_cache.epoch(), committee.epoch() + 1);
+
+    // The sub dags committed until the node is restarted with the next committee are still
+    // executed.
+    let output = rx_output.recv().await.unwrap();
+    assert_eq!(output.sub_dag.sub_dag_index, 4);
+}
@@ -1,6 +1,8 @@
 // Copyright (c) Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
+#[cfg(feature = "fault-injection")]
+use crate::failpoints::{FaultInjector, FaultRule};
 use axum::{extract::Extension, http::StatusCode, routing::get, Json, Router};
 use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener};
 use std::time::Duration;
@@ -12,7 +14,7 @@ use types::ConditionalBroadcastReceiver;
 pub fn start_admin_server(
     port: u16,
     network: anemo::Network,
-    mut tr_shutdown: ConditionalBroadcastReceiver,
+    tr_shutdown: ConditionalBroadcastReceiver,
 ) -> Vec<JoinHandle<()>> {
     let mut router = Router::new()
         .route("/peers", get(get_peers))
@@ -20,6 +22,39 @@ pub fn start_admin_server(
 
     router = router.layer(Extension(network));
 
+    serve(router, port, tr_shutdown)
+}
+
+/// Same as `start_admin_server`, with the `/faults` routes to read and change the rules of
+/// `injector` at runtime.
+#[cfg(feature = "fault-injection")]
+pub fn start_admin_server_with_faults(
+    port: u16,
+    network: anemo::Network,
+    injector: FaultInjector,
+    tr_shutdown: ConditionalBroadcastReceiver,
+) -> Vec<JoinHandle<()>> {
+    let mut router = Router::new()
+        .route("/peers", get(get_peers))
+        .route("/known_peers", get(get_known_peers))
+        .route(
+            "/faults",
+            get(get_faults)
+                .put(set_faults)
+                .post(add_fault)
+                .delete(clear_faults),
+        );
+
+    router = router.layer(Extension(network)).layer(Extension(injector));
+
+    serve(router, port, tr_shutdown)
+}
+
+fn serve(
+    router: Router,
+    port: u16,
+    mut tr_shutdown: ConditionalBroadcastReceiver,
+) -> Vec<JoinHandle<()>> {
     let socket_address = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port);
     info!(
         address =% socket_address,
@@ -100,3 +135,37 @@ async fn get_known_peers(
         ),
     )
 }
+
+#[cfg(feature = "fault-injection")]
+async fn get_faults(
+    Extension(injector): Extension<FaultInjector>,
+) -> (StatusCode, Json<Vec<FaultRule>>) {
+    (StatusCode::OK, Json(injector.rules()))
+}
+
+#[cfg(feature = "fault-injection")]
+async fn set_faults(
+    Extension(injector): Extension<FaultInjector>,
+    Json(rules): Json<Vec<FaultRule>>,
+) -> StatusCode {
+    info!("Replacing fault injection rules with {rules:?}");
+    injector.set_rules(rules);
+    StatusCode::OK
+}
+
+#[cfg(feature = "fault-injection")]
+async fn add_fault(
+    Extension(injector): Extension<FaultInjector>,
+    Json(rule): Json<FaultRule>,
+) -> StatusCode {
+    info!("Adding fault injection rule {rule:?}");
+    injector.add_rule(rule);
+    StatusCode::OK
+}
+
+#[cfg(feature = "fault-injection")]
+async fn clear_faults(Extension(injector): Extension<FaultInjector>) -> StatusCode {
+    info!("Clearing all the fault injection rules");
+    injector.heal();
+    StatusCode::OK
+}
@@ -12,9 +12,12 @@ use tokio::{select, time::sleep};
 use tracing::debug;
 use types::{
     error::LocalClientError, FetchBatchesRequest, FetchBatchesResponse, PrimaryToWorker,
-    WorkerOthersBatchMessage, WorkerOurBatchMessage, WorkerSynchronizeMessage, WorkerToPrimary,
+    WorkerOthersBatchMessage, WorkerOurBatchMessage, WorkerRoundUpdateMessage,
+    WorkerSynchronizeMessage, WorkerToPrimary,
 };
 
+#[cfg(feature = "fault-injection")]
+use crate::failpoints::FaultInjector;
 use crate::traits::{PrimaryToWorkerClient, WorkerToPrimaryClient};
 
 /// NetworkClient provides the interface to send requests to other nodes, and call other components
@@ -28,6 +31,9 @@ use crate::traits::{PrimaryToWorkerClient, WorkerToPrimaryClient};
 pub struct NetworkClient {
     inner: Arc<RwLock<Inner>>,
     shutdown_notify: Arc<NotifyOnce>,
+    /// The faults injected in the messages of the primary and workers of this authority.
+    #[cfg(feature = "fault-injection")]
+    fault_injector: FaultInjector,
 }
 
 struct Inner {
@@ -51,6 +57,8 @@ impl NetworkClient {
                 shutdown: false,
             })),
             shutdown_notify: Arc::new(NotifyOnce::new()),
+            #[cfg(feature = "fault-injection")]
+            fault_injector: FaultInjector::new(),
         }
     }
 
@@ -63,6 +71,12 @@ impl NetworkClient {
         Self::new(empty_peer_id())
     }
 
+    /// The fault injector shared by the primary and the workers of this authority.
+    #[cfg(feature = "fault-injection")]
+    pub fn fault_injector(&self) -> FaultInjector {
+        self.fault_injector.clone()
+    }
+
     pub fn set_worker_to_primary_local_handler(&self, handler: Arc<dyn WorkerToPrimary>) {
         let mut inner = self.inner.write();
         inner.worker_to_primary_handler = Some(handler);
@@ -169,6 +183,25 @@ impl PrimaryToWorkerClient for NetworkClient {
             },
         }
     }
+
+    async fn update_round(
+        &self,
+        worker_name: NetworkPublicKey,
+        request: WorkerRoundUpdateMessage,
+    ) -> Result<(), LocalClientError> {
+        let c = self
+            .get_primary_to_worker_handler(PeerId(worker_name.to_bytes()))
+            .await?;
+        select! {
+            resp = c.update_round(Request::new(request)) => {
+                resp.map_err(|e| LocalClientError::Internal(format!("{e:?}")))?;
+                Ok(())
+            },
+            () = self.shutdown_notify.wait() => {
+                Err(LocalClientError::ShuttingDown)
+            },
+        }
+    }
 }
 
 #[async_trait]
@@ -1,32 +1,534 @@
 // Copyright (c) Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
-use anemo_tower::callback::{MakeCallbackHandler, ResponseHandler};
-// use sui_macros::fail_point;
 
+//! Fault injection for anemo services, compiled with the `fault-injection` feature only. A
+//! [`FaultInjectionLayer`] is installed on both the inbound service and the outbound request
+//! layer of a node's network. Every request is matched against the [`FaultRule`]s of the
+//! authority's [`FaultInjector`], which can drop, delay, duplicate or reorder it. The rules can
+//! be changed at runtime, from tests via [`NetworkClient::fault_injector`] or through the network
+//! admin server.
+//!
+//! [`NetworkClient::fault_injector`]: crate::client::NetworkClient::fault_injector
+
+use anemo::{PeerId, Request, Response};
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use parking_lot::{Mutex, RwLock};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::sync::oneshot;
+use tower::{Layer, Service, ServiceExt};
+use tracing::debug;
+
+/// The faults that can be injected on a request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Fault {
+    /// Lose the request: it is never delivered and never answered, so the caller only learns
+    /// about it from its own timeout.
+    Drop,
+    /// Delay the request by a random duration between `min_ms` and `max_ms`.
+    Delay { min_ms: u64, max_ms: u64 },
+    /// Deliver the request twice. The response of the duplicate is discarded.
+    Duplicate,
+    /// Hold the request for up to `window_ms`. If another request is sent on the same route
+    /// between the same peers meanwhile, that one is delivered and answered first, and the held
+    /// request is only delivered afterwards.
+    Reorder { window_ms: u64 },
+}
+
+/// A fault to inject on the requests sent by `from` to `to` on the routes starting with
+/// `route`. A `None` matches any peer, respectively any route.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FaultRule {
+    pub from: Option<PeerId>,
+    pub to: Option<PeerId>,
+    /// The route prefix, e.g. `/narwhal.PrimaryToPrimary` or
+    /// `/narwhal.PrimaryToPrimary/SendCertificate`.
+    pub route: Option<String>,
+    pub fault: Fault,
+    /// The probability, between 0 and 1, to inject the fault on a matching request.
+    #[serde(default = "FaultRule::default_probability")]
+    pub probability: f64,
+}
+
+impl FaultRule {
+    pub fn new(fault: Fault) -> Self {
+        Self {
+            from: None,
+            to: None,
+            route: None,
+            fault,
+            probability: Self::default_probability(),
+        }
+    }
+
+    pub fn from(mut self, peer: PeerId) -> Self {
+        self.from = Some(peer);
+        self
+    }
+
+    pub fn to(mut self, peer: PeerId) -> Self {
+        self.to = Some(peer);
+        self
+    }
+
+    pub fn route(mut self, route: impl Into<String>) -> Self {
+        self.route = Some(route.into());
+        self
+    }
+
+    pub fn probability(mut self, probability: f64) -> Self {
+        self.probability = probability;
+        self
+    }
+
+    fn default_probability() -> f64 {
+        1.0
+    }
+
+    fn matches(&self, from: Option<&PeerId>, to: Option<&PeerId>, route: &str) -> bool {
+        let peer_matches =
+            |expected: &Option<PeerId>, actual: Option<&PeerId>| match (expected, actual) {
+                (None, _) => true,
+                (Some(expected), Some(actual)) => expected == actual,
+                (Some(_), None) => false,
+            };
+
+        peer_matches(&self.from, from)
+            && peer_matches(&self.to, to)
+            && self
+                .route
+                .as_ref()
+                .map_or(true, |prefix| route.starts_with(prefix.as_str()))
+    }
+}
+
+/// The requests held by a [`Fault::Reorder`], by sender, recipient and route.
+type ReorderKey = (Option<PeerId>, Option<PeerId>, String);
+
+/// The runtime configurable set of fault rules of an authority. Cloning it gives a handle to the
+/// same set of rules.
 #[derive(Clone, Default)]
-pub struct FailpointsMakeCallbackHandler {}
+pub struct FaultInjector {
+    rules: Arc<RwLock<Vec<FaultRule>>>,
+    /// The release signal of the request held for reordering, per route between two peers.
+    held: Arc<Mutex<HashMap<ReorderKey, oneshot::Sender<()>>>>,
+}
+
+/// What happens to a request subject to a [`Fault::Reorder`].
+enum Reordering {
+    /// The request is held until it is overtaken, or for the window at most.
+    Held(oneshot::Receiver<()>, Duration),
+    /// The request overtakes a held one, which is released once it has been answered.
+    Overtaking(oneshot::Sender<()>),
+}
 
-impl FailpointsMakeCallbackHandler {
+impl FaultInjector {
     pub fn new() -> Self {
-        Self {}
+        Self::default()
+    }
+
+    pub fn add_rule(&self, rule: FaultRule) {
+        debug!("Adding fault rule {rule:?}");
+        self.rules.write().push(rule);
+    }
+
+    pub fn rules(&self) -> Vec<FaultRule> {
+        self.rules.read().clone()
+    }
+
+    pub fn set_rules(&self, rules: Vec<FaultRule>) {
+        *self.rules.write() = rules;
+    }
+
+    /// Drops all the requests sent between any peer of `a` and any peer of `b`, in both
+    /// directions.
+    pub fn partition(&self, a: &[PeerId], b: &[PeerId]) {
+        let mut rules = self.rules.write();
+        for x in a {
+            for y in b {
+                rules.push(FaultRule::new(Fault::Drop).from(*x).to(*y));
+                rules.push(FaultRule::new(Fault::Drop).from(*y).to(*x));
+            }
+        }
+    }
+
+    /// Removes all the rules, which heals any partition.
+    pub fn heal(&self) {
+        self.rules.write().clear();
+    }
+
+    /// Holds the request sent on `key`, unless a request sent on it earlier is still held, in
+    /// which case the request overtakes it.
+    fn reorder(&self, key: ReorderKey, window: Duration) -> Reordering {
+        let mut held = self.held.lock();
+        match held.remove(&key) {
+            // A closed channel means the held request was released by its window.
+            Some(release) if !release.is_closed() => Reordering::Overtaking(release),
+            _ => {
+                let (release, rx_release) = oneshot::channel();
+                held.insert(key, release);
+                Reordering::Held(rx_release, window)
+            }
+        }
+    }
+
+    /// Returns the faults to inject on a request sent by `from` to `to` on `route`, sampling
+    /// the probability of every matching rule.
+    fn faults(&self, from: Option<&PeerId>, to: Option<&PeerId>, route: &str) -> Vec<Fault> {
+        let rules = self.rules.read();
+        if rules.is_empty() {
+            return Vec::new();
+        }
+
+        let mut rng = rand::thread_rng();
+        rules
+            .iter()
+            .filter(|rule| rule.matches(from, to, route))
+            .filter(|rule| rng.gen_bool(rule.probability.clamp(0.0, 1.0)))
+            .map(|rule| rule.fault)
+            .collect()
+    }
+}
+
+/// Whether the layer wraps the requests we send or the ones we receive.
+#[derive(Clone, Copy, Debug)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+/// A layer injecting the faults configured on a [`FaultInjector`]. `own_peer_id` is the peer
+/// id of the node the layer is installed on: it's the sender of the outbound requests and the
+/// recipient of the inbound ones.
+#[derive(Clone)]
+pub struct FaultInjectionLayer {
+    own_peer_id: PeerId,
+    direction: Direction,
+    injector: FaultInjector,
+}
+
+impl FaultInjectionLayer {
+    pub fn new(own_peer_id: PeerId, direction: Direction, injector: FaultInjector) -> Self {
+        Self {
+            own_peer_id,
+            direction,
+            injector,
+        }
     }
 }
 
-impl MakeCallbackHandler for FailpointsMakeCallbackHandler {
-    type Handler = FailpointsResponseHandler;
+impl<S> Layer<S> for FaultInjectionLayer {
+    type Service = FaultInjection<S>;
 
-    fn make_handler(&self, _request: &anemo::Request<bytes::Bytes>) -> Self::Handler {
-        FailpointsResponseHandler {}
+    fn layer(&self, inner: S) -> Self::Service {
+        FaultInjection {
+            inner,
+            own_peer_id: self.own_peer_id,
+            direction: self.direction,
+            injector: self.injector.clone(),
+        }
     }
 }
 
-pub struct FailpointsResponseHandler {}
+#[derive(Clone)]
+pub struct FaultInjection<S> {
+    inner: S,
+    own_peer_id: PeerId,
+    direction: Direction,
+    injector: FaultInjector,
+}
+
+impl<S> Service<Request<Bytes>> for FaultInjection<S>
+where
+    S: Service<Request<Bytes>, Response = Response<Bytes>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Send + 'static,
+{
+    type Response = Response<Bytes>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Bytes>) -> Self::Future {
+        // The remote peer is the recipient of the outbound requests and the sender of the
+        // inbound ones.
+        let remote = request.peer_id().copied();
+        let (from, to) = match self.direction {
+            Direction::Outbound => (Some(self.own_peer_id), remote),
+            Direction::Inbound => (remote, Some(self.own_peer_id)),
+        };
+        let faults = self
+            .injector
+            .faults(from.as_ref(), to.as_ref(), request.route());
+
+        // Take the service that was driven to readiness and leave a clone in its place.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        if faults.is_empty() {
+            return Box::pin(inner.call(request));
+        }
+
+        if faults.contains(&Fault::Drop) {
+            debug!(
+                "Dropping request {} from {from:?} to {to:?}",
+                request.route()
+            );
+            // The request is lost: the inner service never sees it and nothing is answered.
+            return Box::pin(futures::future::pending());
+        }
+
+        let mut rng = rand::thread_rng();
+        let mut delay = Duration::ZERO;
+        let mut duplicate = false;
+        let mut reordering = None;
+        for fault in faults {
+            match fault {
+                Fault::Drop => unreachable!("dropped requests have been handled"),
+                Fault::Delay { min_ms, max_ms } => {
+                    delay += Duration::from_millis(rng.gen_range(min_ms..=max_ms.max(min_ms)));
+                }
+                Fault::Reorder { window_ms } => {
+                    let key = (from, to, request.route().to_owned());
+                    reordering = Some(self.injector.reorder(key, Duration::from_millis(window_ms)));
+                }
+                Fault::Duplicate => duplicate = true,
+            }
+        }
+
+        let copy = duplicate.then(|| {
+            let mut copy = Request::new(request.body().clone()).with_route(request.route());
+            for (key, value) in request.headers() {
+                copy = copy.with_header(key, value);
+            }
+            if let Some(peer_id) = request.peer_id() {
+                copy.extensions_mut().insert(*peer_id);
+            }
+            copy
+        });
+
+        Box::pin(async move {
+            if !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
+
+            let release = match reordering {
+                Some(Reordering::Held(rx_release, window)) => {
+                    // Released either when overtaken, or at the end of the window.
+                    let _ = tokio::time::timeout(window, rx_release).await;
+                    None
+                }
+                Some(Reordering::Overtaking(release)) => Some(release),
+                None => None,
+            };
+
+            if let Some(copy) = copy {
+                // The response of the duplicate is of no interest, only its side effects.
+                let _ = inner.call(copy).await;
+                inner.ready().await?;
+            }
+
+            let response = inner.call(request).await;
+            if let Some(release) = release {
+                let _ = release.send(());
+            }
+            response
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anemo::types::response::StatusCode;
+    use tower::{BoxError, ServiceBuilder};
+
+    fn peer(byte: u8) -> PeerId {
+        PeerId([byte; 32])
+    }
+
+    fn request(from: PeerId, route: &str) -> Request<Bytes> {
+        let mut request = Request::new(Bytes::from("foobar")).with_route(route);
+        request.extensions_mut().insert(from);
+        request
+    }
+
+    /// Calls `svc` with `request`, giving up after a second.
+    async fn call<S>(svc: &mut S, request: Request<Bytes>) -> Option<Response<Bytes>>
+    where
+        S: Service<Request<Bytes>, Response = Response<Bytes>, Error = BoxError>,
+    {
+        let response = svc.ready().await.unwrap().call(request);
+        tokio::time::timeout(Duration::from_secs(1), response)
+            .await
+            .ok()
+            .map(Result::unwrap)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn drops_requests_across_a_partition() {
+        let injector = FaultInjector::new();
+        let received = Arc::new(Mutex::new(0));
+        let counter = received.clone();
+        let mut svc = ServiceBuilder::new()
+            .layer(FaultInjectionLayer::new(
+                peer(0),
+                Direction::Inbound,
+                injector.clone(),
+            ))
+            .service_fn(move |req: Request<Bytes>| {
+                let counter = counter.clone();
+                async move {
+                    *counter.lock() += 1;
+                    echo(req).await
+                }
+            });
+
+        injector.partition(&[peer(0)], &[peer(1)]);
+
+        // The request is lost: it is neither delivered nor answered.
+        let route = "/narwhal.PrimaryToPrimary/SendCertificate";
+        assert!(call(&mut svc, request(peer(1), route)).await.is_none());
+        assert_eq!(*received.lock(), 0);
+
+        // Peers outside of the partition are not affected.
+        let response = call(&mut svc, request(peer(2), route)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::Success);
+
+        injector.heal();
+
+        let response = call(&mut svc, request(peer(1), route)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::Success);
+        assert_eq!(response.inner(), "foobar");
+        assert_eq!(*received.lock(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn faults_apply_per_route() {
+        let injector = FaultInjector::new();
+        let mut svc = ServiceBuilder::new()
+            .layer(FaultInjectionLayer::new(
+                peer(0),
+                Direction::Outbound,
+                injector.clone(),
+            ))
+            .service_fn(echo);
+
+        injector.add_rule(
+            FaultRule::new(Fault::Drop)
+                .from(peer(0))
+                .route("/narwhal.PrimaryToPrimary/RequestVote"),
+        );
+
+        assert!(call(
+            &mut svc,
+            request(peer(1), "/narwhal.PrimaryToPrimary/RequestVote")
+        )
+        .await
+        .is_none());
 
-impl ResponseHandler for FailpointsResponseHandler {
-    fn on_response(self, _response: &anemo::Response<bytes::Bytes>) {
-        // TODO: Figure out how to make this work
-        // fail_point!("narwhal-rpc-response");
+        let response = call(
+            &mut svc,
+            request(peer(1), "/narwhal.PrimaryToPrimary/FetchCertificates"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::Success);
     }
 
-    fn on_error<E>(self, _error: &E) {}
+    #[tokio::test(start_paused = true)]
+    async fn delays_and_duplicates_requests() {
+        let injector = FaultInjector::new();
+        let received = Arc::new(Mutex::new(0));
+        let counter = received.clone();
+        let mut svc = ServiceBuilder::new()
+            .layer(FaultInjectionLayer::new(
+                peer(0),
+                Direction::Inbound,
+                injector.clone(),
+            ))
+            .service_fn(move |req: Request<Bytes>| {
+                let counter = counter.clone();
+                async move {
+                    *counter.lock() += 1;
+                    echo(req).await
+                }
+            });
+
+        injector.add_rule(FaultRule::new(Fault::Delay {
+            min_ms: 1_000,
+            max_ms: 1_000,
+        }));
+        injector.add_rule(FaultRule::new(Fault::Duplicate).from(peer(1)));
+
+        let start = tokio::time::Instant::now();
+        let response = svc
+            .ready()
+            .await
+            .unwrap()
+            .call(request(peer(1), "/narwhal.WorkerToWorker/ReportBatch"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::Success);
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        assert_eq!(*received.lock(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reorders_requests() {
+        let injector = FaultInjector::new();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let log = received.clone();
+        let svc = ServiceBuilder::new()
+            .layer(FaultInjectionLayer::new(
+                peer(0),
+                Direction::Inbound,
+                injector.clone(),
+            ))
+            .service_fn(move |req: Request<Bytes>| {
+                let log = log.clone();
+                async move {
+                    log.lock().push(req.body().clone());
+                    echo(req).await
+                }
+            });
+
+        injector.add_rule(FaultRule::new(Fault::Reorder { window_ms: 10_000 }));
+
+        let route = "/narwhal.WorkerToWorker/ReportBatch";
+        let send = |body: &'static str| {
+            let mut svc = svc.clone();
+            let mut request = Request::new(Bytes::from(body)).with_route(route);
+            request.extensions_mut().insert(peer(1));
+            tokio::spawn(async move { svc.ready().await.unwrap().call(request).await })
+        };
+
+        // The first request is held until the second one has been answered.
+        let first = send("first");
+        tokio::task::yield_now().await;
+        let second = send("second");
+        second.await.unwrap().unwrap();
+        first.await.unwrap().unwrap();
+        assert_eq!(*received.lock(), vec!["second", "first"]);
+
+        // A request which is not overtaken is delivered at the end of the window.
+        let start = tokio::time::Instant::now();
+        send("third").await.unwrap().unwrap();
+        assert!(start.elapsed() >= Duration::from_secs(10));
+        assert_eq!(received.lock().last().unwrap(), "third");
+    }
+
+    async fn echo(req: Request<Bytes>) -> Result<Response<Bytes>, BoxError> {
+        Ok(Response::new(req.into_body()))
+    }
 }
@@ -14,6 +14,7 @@ pub mod anemo_ext;
 pub mod client;
 pub mod connectivity;
 pub mod epoch_filter;
+#[cfg(feature = "fault-injection")]
 pub mod failpoints;
 mod p2p;
 mod retry;
@@ -15,9 +15,10 @@ use std::time::Duration;
 use tokio::task::JoinHandle;
 use types::{
     Batch, BatchDigest, FetchCertificatesRequest, FetchCertificatesResponse,
-    GetCertificatesRequest, GetCertificatesResponse, PrimaryToPrimaryClient, PrimaryToWorkerClient,
-    RequestBatchRequest, RequestBatchesRequest, RequestBatchesResponse, WorkerBatchMessage,
-    WorkerDeleteBatchesMessage, WorkerSynchronizeMessage, WorkerToWorkerClient,
+    FetchConsensusSnapshotRequest, FetchConsensusSnapshotResponse, GetCertificatesRequest,
+    GetCertificatesResponse, PrimaryToPrimaryClient, PrimaryToWorkerClient, RequestBatchRequest,
+    RequestBatchesRequest, RequestBatchesResponse, WorkerBatchMessage, WorkerDeleteBatchesMessage,
+    WorkerSynchronizeMessage, WorkerToWorkerClient,
 };
 
 fn unreliable_send<F, R, Fut>(
@@ -121,6 +122,22 @@ impl PrimaryToPrimaryRpc for anemo::Network {
             .map_err(|e| format_err!("Network error {:?}", e))?;
         Ok(response.into_body())
     }
+
+    async fn fetch_consensus_snapshot(
+        &self,
+        peer: &NetworkPublicKey,
+        request: impl anemo::types::request::IntoRequest<FetchConsensusSnapshotRequest> + Send,
+    ) -> Result<FetchConsensusSnapshotResponse> {
+        let peer_id = PeerId(peer.0.to_bytes());
+        let peer = self
+            .peer(peer_id)
+            .ok_or_else(|| format_err!("Network has no connection with peer {peer_id}"))?;
+        let response = PrimaryToPrimaryClient::new(peer)
+            .fetch_consensus_snapshot(request)
+            .await
+            .map_err(|e| format_err!("Network error {:?}", e))?;
+        Ok(response.into_body())
+    }
 }
 
 //
@@ -7,9 +7,10 @@ use crypto::NetworkPublicKey;
 use tokio::task::JoinHandle;
 use types::{
     error::LocalClientError, Batch, BatchDigest, FetchBatchesRequest, FetchBatchesResponse,
-    FetchCertificatesRequest, FetchCertificatesResponse, GetCertificatesRequest,
-    GetCertificatesResponse, RequestBatchesRequest, RequestBatchesResponse,
-    WorkerOthersBatchMessage, WorkerOurBatchMessage, WorkerSynchronizeMessage,
+    FetchCertificatesRequest, FetchCertificatesResponse, FetchConsensusSnapshotRequest,
+    FetchConsensusSnapshotResponse, GetCertificatesRequest, GetCertificatesResponse,
+    RequestBatchesRequest, RequestBatchesResponse, WorkerOthersBatchMessage, WorkerOurBatchMessage,
+    WorkerRoundUpdateMessage, WorkerSynchronizeMessage,
 };
 
 pub trait UnreliableNetwork<Request: Clone + Send + Sync> {
@@ -72,6 +73,11 @@ pub trait PrimaryToPrimaryRpc {
         peer: &NetworkPublicKey,
         request: impl anemo::types::request::IntoRequest<FetchCertificatesRequest> + Send,
     ) -> Result<FetchCertificatesResponse>;
+    async fn fetch_consensus_snapshot(
+        &self,
+        peer: &NetworkPublicKey,
+        request: impl anemo::types::request::IntoRequest<FetchConsensusSnapshotRequest> + Send,
+    ) -> Result<FetchConsensusSnapshotResponse>;
 }
 
 #[async_trait]
@@ -93,6 +99,12 @@ pub trait PrimaryToWorkerClient {
         worker_name: NetworkPublicKey,
         request: FetchBatchesRequest,
     ) -> Result<FetchBatchesResponse, LocalClientError>;
+
+    async fn update_round(
+        &self,
+        worker_name: NetworkPublicKey,
+        request: WorkerRoundUpdateMessage,
+    ) -> Result<(), LocalClientError>;
 }
 
 #[async_trait]
@@ -0,0 +1,73 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use crate::NodeError;
+use config::{BackupParameters, WorkerId};
+use std::path::{Path, PathBuf};
+use storage::{prune_backups, Backup, NodeStorage};
+use tokio::task::JoinHandle;
+use tokio::time::{interval_at, Instant};
+use tracing::{error, info};
+use types::ConditionalBroadcastReceiver;
+
+/// The directory of the backups of the primary, in the configured backup directory shared with
+/// its workers.
+pub(crate) fn primary_backups_dir(directory: &Path) -> PathBuf {
+    directory.join("primary")
+}
+
+/// The directory of the backups of the worker `id`, in the configured backup directory shared
+/// with its primary.
+pub(crate) fn worker_backups_dir(directory: &Path, id: WorkerId) -> PathBuf {
+    directory.join(format!("worker-{id}"))
+}
+
+/// Backs up `store` to `directory` and deletes the backups beyond the `max_backups` most recent
+/// ones. The checkpoint hard links the RocksDB files, so it is done on a blocking thread.
+pub(crate) async fn take_backup(
+    store: NodeStorage,
+    directory: PathBuf,
+    max_backups: usize,
+) -> Result<Backup, NodeError> {
+    tokio::task::spawn_blocking(move || {
+        let backup = store.backup(&directory)?;
+        prune_backups(&directory, max_backups)?;
+        Ok(backup)
+    })
+    .await
+    .map_err(|e| NodeError::BackupFailed(e.to_string()))?
+}
+
+/// Spawns the task taking a backup of the node storage every `parameters.interval`.
+pub(crate) fn spawn_scheduled_backups(
+    parameters: BackupParameters,
+    directory: PathBuf,
+    store: NodeStorage,
+    mut rx_shutdown: ConditionalBroadcastReceiver,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        info!(
+            "Backing up the node storage to {directory:?} every {} ms",
+            parameters.interval.as_millis()
+        );
+        let mut timer = interval_at(Instant::now() + parameters.interval, parameters.interval);
+        loop {
+            tokio::select! {
+                _ = timer.tick() => {
+                    if let Err(e) = take_backup(
+                        store.clone(),
+                        directory.clone(),
+                        parameters.max_backups,
+                    )
+                    .await
+                    {
+                        error!("Scheduled backup of the node storage failed: {e}");
+                    }
+                }
+
+                _ = rx_shutdown.receiver.recv() => {
+                    return
+                }
+            }
+        }
+    })
+}
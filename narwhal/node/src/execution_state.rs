@@ -1,9 +1,9 @@
 // Copyright (c) Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
 use async_trait::async_trait;
-use executor::ExecutionState;
+use executor::{ExecutionAck, ExecutionState};
 use tokio::sync::mpsc::Sender;
-use types::{BatchAPI, ConsensusOutput};
+use types::ConsensusOutput;
 
 /// A simple/dumb execution engine.
 pub struct SimpleExecutionState {
@@ -20,23 +20,18 @@ impl SimpleExecutionState {
 
 #[async_trait]
 impl ExecutionState for SimpleExecutionState {
-    async fn handle_consensus_output(&self, consensus_output: ConsensusOutput) {
-        for (_, batches) in consensus_output.batches {
-            for batch in batches {
-                for transaction in batch.transactions().iter() {
-                    if let Err(err) = self
-                        .tx_transaction_confirmation
-                        .send(transaction.clone())
-                        .await
-                    {
-                        eprintln!("Failed to send txn in SimpleExecutionState: {}", err);
-                    }
-                }
+    async fn handle_consensus_output(&self, consensus_output: ConsensusOutput, ack: ExecutionAck) {
+        for (_, transaction) in consensus_output.transactions() {
+            if let Err(err) = self
+                .tx_transaction_confirmation
+                .send(transaction.clone())
+                .await
+            {
+                eprintln!("Failed to send txn in SimpleExecutionState: {}", err);
             }
         }
-    }
-
-    async fn last_executed_sub_dag_index(&self) -> u64 {
-        0
+        if let Err(err) = ack.ack_all() {
+            eprintln!("Failed to acknowledge the consensus output: {}", err);
+        }
     }
 }
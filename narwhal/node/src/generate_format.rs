@@ -9,7 +9,8 @@ use std::{fs::File, io::Write};
 use structopt::{clap::arg_enum, StructOpt};
 use types::{
     Batch, BatchDigest, Certificate, CertificateDigest, Header, HeaderDigest, HeaderV1Builder,
-    Metadata, WorkerOthersBatchMessage, WorkerOurBatchMessage, WorkerSynchronizeMessage,
+    HeaderV2Builder, Metadata, WorkerOthersBatchMessage, WorkerOurBatchMessage,
+    WorkerSynchronizeMessage,
 };
 
 #[allow(clippy::mutable_key_type)]
@@ -70,21 +71,36 @@ fn get_registry() -> Result<Registry> {
         )
         .parents(certificates.iter().map(|x| x.digest()).collect())
         .build();
+    let header_v2 = HeaderV2Builder::default()
+        .author(authority.id())
+        .epoch(0)
+        .created_at(0)
+        .round(3)
+        .payload(
+            (0..4u32)
+                .map(|wid| (BatchDigest::default(), (wid, 0u64)))
+                .collect(),
+        )
+        .parents(certificates.iter().map(|x| x.digest()).collect())
+        .weak_links([(CertificateDigest::default(), 1)].into_iter().collect())
+        .signed(private)
+        .build();
 
     let worker_pk = network_keys[0].public();
     let certificate =
-        Certificate::new_unsigned(&committee, Header::V1(header.clone()), vec![]).unwrap();
+        Certificate::new_unsigned(&committee, Header::V2(header_v2.clone()), vec![]).unwrap();
     let signature = private
         .sign_bytes(certificate.digest().as_ref(), &mut thread_rng())
         .unwrap();
     let certificate = Certificate::new_unsigned(
         &committee,
-        Header::V1(header.clone()),
+        Header::V2(header_v2.clone()),
         vec![(authority.id(), signature)],
     )
     .unwrap();
 
-    tracer.trace_value(&mut samples, &header)?;
+    tracer.trace_value(&mut samples, &Header::V1(header))?;
+    tracer.trace_value(&mut samples, &Header::V2(header_v2))?;
     tracer.trace_value(&mut samples, &certificate)?;
 
     // WorkerIndex & WorkerInfo will be present in a protocol message once dynamic
@@ -4,11 +4,14 @@ use config::WorkerId;
 use executor::SubscriberError;
 use futures::future::try_join_all;
 use futures::stream::FuturesUnordered;
+use storage::BackupError;
 pub use storage::NodeStorage;
 use thiserror::Error;
 
+mod backup;
 pub mod execution_state;
 pub mod keypair_file;
+pub mod observer_node;
 pub mod primary_node;
 pub mod worker_node;
 
@@ -22,4 +25,22 @@ pub enum NodeError {
 
     #[error("Worker nodes with ids {0:?} already running")]
     WorkerNodesAlreadyRunning(Vec<WorkerId>),
+
+    #[error("Node is not running")]
+    NodeNotRunning,
+
+    #[error("Backups are disabled, no backup directory is configured")]
+    BackupsDisabled,
+
+    #[error("Failed to back up the node storage: {0}")]
+    BackupFailed(String),
+
+    #[error("Failed to start the observer gRPC server: {0}")]
+    ObserverServerFailed(String),
+}
+
+impl From<BackupError> for NodeError {
+    fn from(e: BackupError) -> Self {
+        NodeError::BackupFailed(e.to_string())
+    }
 }
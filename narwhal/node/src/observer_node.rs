@@ -0,0 +1,509 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use crate::{try_join_all, FuturesUnordered, NodeError};
+use bytes::Bytes;
+use config::{AuthorityIdentifier, Committee, ObserverParameters, WorkerId};
+use crypto::{Hash, PublicKey};
+use mysten_network::Multiaddr;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use storage::{NodeStorage, StoreResult};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tokio::time::{interval_at, timeout, Instant};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+use tracing::{error, info, warn};
+use types::{
+    BatchAPI, BlockError, BlockErrorKind, Certificate, CertificateAPI, CertificateDigest,
+    CertificateDigestProto, Collection, CollectionRetrievalResult, CommittedSubDag,
+    CommittedSubDagProto, ConditionalBroadcastReceiver, ConsensusCommit, Empty,
+    GetCollectionsRequest, GetCollectionsResponse, HeaderAPI, NodeReadCausalRequest,
+    NodeReadCausalResponse, PreSubscribedBroadcastSender, Proposer, ProposerServer, PublicKeyProto,
+    ReadCausalRequest, ReadCausalResponse, ReadCommittedSubDagsRequest, RemoveCollectionsRequest,
+    RetrievalResult, RoundsRequest, RoundsResponse, TransactionProto, Validator, ValidatorServer,
+};
+
+/// A read-only process serving the storage of a running node. The storages of the primary and of
+/// the workers are opened as RocksDB secondary instances, which catch up with the writes of the
+/// node every `catch_up_interval`, so the observer never touches the hot path of the validator.
+pub struct ObserverNode {
+    // The catch up and gRPC server tasks.
+    handles: FuturesUnordered<JoinHandle<()>>,
+    // The shutdown signal channel.
+    tx_shutdown: PreSubscribedBroadcastSender,
+}
+
+impl ObserverNode {
+    /// The number of tasks listening to the shutdown signal.
+    const NUM_SHUTDOWN_RECEIVERS: u64 = 2;
+
+    /// Opens the storage of the primary at `primary_store_path` and the storages of its workers
+    /// at `worker_store_paths` as secondaries, and serves the `Validator` and `Proposer` gRPC APIs
+    /// on `socket_address`.
+    pub async fn start(
+        committee: Committee,
+        primary_store_path: &Path,
+        worker_store_paths: BTreeMap<WorkerId, PathBuf>,
+        parameters: ObserverParameters,
+        socket_address: Multiaddr,
+    ) -> Result<Self, NodeError> {
+        let secondary_path = |name: String| {
+            parameters
+                .secondary_directory
+                .as_ref()
+                .map(|directory| directory.join(name))
+        };
+        let storage = ObservedStorage {
+            primary: NodeStorage::reopen_secondary(
+                primary_store_path.to_path_buf(),
+                secondary_path("primary".to_string()),
+            ),
+            workers: worker_store_paths
+                .into_iter()
+                .map(|(worker_id, path)| {
+                    let secondary = secondary_path(format!("worker-{worker_id}"));
+                    (worker_id, NodeStorage::reopen_secondary(path, secondary))
+                })
+                .collect(),
+        };
+
+        let mut tx_shutdown = PreSubscribedBroadcastSender::new(Self::NUM_SHUTDOWN_RECEIVERS);
+        let mut shutdown_receivers = tx_shutdown.subscribe_n(Self::NUM_SHUTDOWN_RECEIVERS);
+        let (tx_caught_up, rx_caught_up) = watch::channel(());
+
+        let server_handle = spawn_server(
+            socket_address,
+            ObserverValidator::new(storage.clone(), rx_caught_up),
+            ObserverProposer::new(storage.clone(), committee),
+            shutdown_receivers.pop().unwrap(),
+        )
+        .await?;
+        let catch_up_handle = spawn_catch_up(
+            storage,
+            parameters.catch_up_interval,
+            tx_caught_up,
+            shutdown_receivers.pop().unwrap(),
+        );
+
+        Ok(Self {
+            handles: FuturesUnordered::from_iter([server_handle, catch_up_handle]),
+            tx_shutdown,
+        })
+    }
+
+    /// Stops serving the storage and waits until all the tasks of the observer are done.
+    pub async fn shutdown(mut self) {
+        self.tx_shutdown
+            .send()
+            .expect("Couldn't send the shutdown signal to downstream components");
+        try_join_all(&mut self.handles).await.unwrap();
+        info!("Narwhal observer shutdown is complete");
+    }
+}
+
+/// The storages of the primary and of the workers of the node, opened as secondaries.
+#[derive(Clone)]
+struct ObservedStorage {
+    primary: NodeStorage,
+    workers: BTreeMap<WorkerId, NodeStorage>,
+}
+
+impl ObservedStorage {
+    fn try_catch_up_with_primary(&self) -> StoreResult<()> {
+        self.primary.try_catch_up_with_primary()?;
+        for worker in self.workers.values() {
+            worker.try_catch_up_with_primary()?;
+        }
+        Ok(())
+    }
+
+    /// Returns the transactions of the certificate `id`, read from the stores of the workers.
+    fn read_collection(&self, id: CertificateDigest) -> StoreResult<CollectionRetrievalResult> {
+        let not_found = CollectionRetrievalResult {
+            retrieval_result: Some(RetrievalResult::Error(
+                BlockError {
+                    digest: id,
+                    error: BlockErrorKind::BlockNotFound,
+                }
+                .into(),
+            )),
+        };
+        let Some(certificate) = self.primary.certificate_store.read(id)? else {
+            return Ok(not_found);
+        };
+
+        let mut transactions = Vec::new();
+        for (digest, (worker_id, _)) in certificate.header().payload() {
+            let batch = match self.workers.get(worker_id) {
+                Some(worker) => worker.batch_store.get(digest)?,
+                None => None,
+            };
+            let Some(batch) = batch else {
+                return Ok(not_found);
+            };
+            transactions.extend(
+                batch
+                    .transactions()
+                    .iter()
+                    .cloned()
+                    .map(TransactionProto::from),
+            );
+        }
+
+        Ok(CollectionRetrievalResult {
+            retrieval_result: Some(RetrievalResult::Collection(Collection {
+                id: Some(id.into()),
+                transactions,
+            })),
+        })
+    }
+
+    /// Returns a breadth first traversal of the stored certificates, starting with `start`. The
+    /// walk stops at the garbage collected certificates.
+    fn read_causal(&self, start: CertificateDigest) -> Result<Vec<CertificateDigest>, Status> {
+        let certificate_store = &self.primary.certificate_store;
+        let mut digests = Vec::new();
+        let mut visited = HashSet::from([start]);
+        let mut queue = VecDeque::from([start]);
+        while let Some(digest) = queue.pop_front() {
+            let Some(certificate) = certificate_store.read(digest).map_err(internal)? else {
+                continue;
+            };
+            digests.push(digest);
+            for parent in certificate.header().parents() {
+                if visited.insert(*parent) {
+                    queue.push_back(*parent);
+                }
+            }
+        }
+
+        if digests.is_empty() {
+            return Err(Status::not_found(format!("Certificate {start} not found")));
+        }
+        Ok(digests)
+    }
+
+    /// Returns the committed sub dag with its certificates, or an error if they are pruned.
+    fn read_committed_sub_dag(&self, commit: ConsensusCommit) -> Result<CommittedSubDag, Status> {
+        let certificate_store = &self.primary.certificate_store;
+        let pruned = || {
+            Status::not_found(format!(
+                "The certificates of the sub dag {} are pruned",
+                commit.sub_dag_index()
+            ))
+        };
+
+        let certificates = certificate_store
+            .read_all(commit.certificates())
+            .map_err(internal)?
+            .into_iter()
+            .collect::<Option<Vec<Certificate>>>()
+            .ok_or_else(pruned)?;
+        let leader = certificate_store
+            .read(commit.leader())
+            .map_err(internal)?
+            .ok_or_else(pruned)?;
+
+        Ok(CommittedSubDag::from_commit(commit, certificates, leader))
+    }
+}
+
+/// Spawns the task catching up with the writes of the node every `interval`, and signaling
+/// `tx_caught_up` after each catch up.
+fn spawn_catch_up(
+    storage: ObservedStorage,
+    interval: Duration,
+    tx_caught_up: watch::Sender<()>,
+    mut rx_shutdown: ConditionalBroadcastReceiver,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        info!(
+            "Catching up with the node storage every {} ms",
+            interval.as_millis()
+        );
+        let mut timer = interval_at(Instant::now() + interval, interval);
+        loop {
+            tokio::select! {
+                _ = timer.tick() => {
+                    let storage = storage.clone();
+                    match tokio::task::spawn_blocking(move || storage.try_catch_up_with_primary())
+                        .await
+                    {
+                        Ok(Ok(())) => {
+                            tx_caught_up.send_replace(());
+                        }
+                        Ok(Err(e)) => error!("Failed to catch up with the node storage: {e}"),
+                        Err(e) => error!("Failed to catch up with the node storage: {e}"),
+                    }
+                }
+
+                _ = rx_shutdown.receiver.recv() => {
+                    return
+                }
+            }
+        }
+    })
+}
+
+/// Binds the gRPC server of the observer to `socket_address`, and spawns the task serving it
+/// until the shutdown signal.
+async fn spawn_server(
+    socket_address: Multiaddr,
+    validator: ObserverValidator,
+    proposer: ObserverProposer,
+    mut rx_shutdown: ConditionalBroadcastReceiver,
+) -> Result<JoinHandle<()>, NodeError> {
+    const GRACEFUL_SHUTDOWN_DURATION: Duration = Duration::from_millis(2_000);
+
+    let config = mysten_network::config::Config::default();
+    let mut server = config
+        .server_builder()
+        .add_service(ValidatorServer::new(validator))
+        .add_service(ProposerServer::new(proposer))
+        .bind(&socket_address)
+        .await
+        .map_err(|e| NodeError::ObserverServerFailed(e.to_string()))?;
+    info!("Observer gRPC Server listening on {}", server.local_addr());
+
+    let shutdown_handle = server.take_cancel_handle().unwrap();
+    Ok(tokio::spawn(async move {
+        let server_handle = tokio::spawn(server.serve());
+
+        let _ = rx_shutdown.receiver.recv().await;
+        shutdown_handle.send(()).unwrap();
+
+        if let Err(err) = timeout(GRACEFUL_SHUTDOWN_DURATION, server_handle).await {
+            warn!("Time out while waiting to gracefully shutdown the observer gRPC server: {err}")
+        }
+    }))
+}
+
+/// The `Validator` gRPC API, served from the secondary instances of the node storage. The
+/// collections cannot be removed by an observer.
+struct ObserverValidator {
+    storage: ObservedStorage,
+    /// Changes after each catch up of the storage.
+    rx_caught_up: watch::Receiver<()>,
+}
+
+impl ObserverValidator {
+    /// The number of committed sub dags buffered per stream.
+    const STREAM_CAPACITY: usize = 100;
+
+    fn new(storage: ObservedStorage, rx_caught_up: watch::Receiver<()>) -> Self {
+        Self {
+            storage,
+            rx_caught_up,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Validator for ObserverValidator {
+    async fn get_collections(
+        &self,
+        request: Request<GetCollectionsRequest>,
+    ) -> Result<Response<GetCollectionsResponse>, Status> {
+        let collection_ids = request.into_inner().collection_ids;
+        if collection_ids.is_empty() {
+            return Err(Status::invalid_argument(
+                "Attempted fetch of no collections!",
+            ));
+        }
+
+        let result = parse_certificate_digests(collection_ids)?
+            .into_iter()
+            .map(|id| self.storage.read_collection(id))
+            .collect::<StoreResult<Vec<_>>>()
+            .map_err(internal)?;
+        Ok(Response::new(GetCollectionsResponse { result }))
+    }
+
+    async fn remove_collections(
+        &self,
+        _request: Request<RemoveCollectionsRequest>,
+    ) -> Result<Response<Empty>, Status> {
+        Err(Status::unimplemented(
+            "The observer serves the node storage read-only",
+        ))
+    }
+
+    async fn read_causal(
+        &self,
+        request: Request<ReadCausalRequest>,
+    ) -> Result<Response<ReadCausalResponse>, Status> {
+        let collection_id = request
+            .into_inner()
+            .collection_id
+            .ok_or_else(|| Status::invalid_argument("No collection id has been provided"))?;
+        let ids = parse_certificate_digests(vec![collection_id])?;
+
+        let digests = self.storage.read_causal(ids[0])?;
+        Ok(Response::new(ReadCausalResponse {
+            collection_ids: digests.into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    type ReadCommittedSubDagsStream = ReceiverStream<Result<CommittedSubDagProto, Status>>;
+
+    /// Streams the committed sub dags with their certificates from the requested sequence
+    /// number, then the ones committed afterwards as the storage catches up with the node.
+    async fn read_committed_sub_dags(
+        &self,
+        request: Request<ReadCommittedSubDagsRequest>,
+    ) -> Result<Response<Self::ReadCommittedSubDagsStream>, Status> {
+        let mut next = request.into_inner().from;
+        let storage = self.storage.clone();
+        let mut rx_caught_up = self.rx_caught_up.clone();
+        let (tx, rx) = mpsc::channel(Self::STREAM_CAPACITY);
+
+        tokio::spawn(async move {
+            loop {
+                let commits = match storage
+                    .primary
+                    .consensus_store
+                    .read_committed_sub_dags_from(&next)
+                {
+                    Ok(commits) => commits,
+                    Err(err) => {
+                        let _ = tx.send(Err(internal(err))).await;
+                        return;
+                    }
+                };
+
+                for commit in commits {
+                    let sub_dag_index = commit.sub_dag_index();
+                    let message = storage.read_committed_sub_dag(commit).map(|sub_dag| {
+                        CommittedSubDagProto {
+                            sub_dag_index,
+                            sub_dag: Bytes::from(
+                                bcs::to_bytes(&sub_dag).expect("Failed to serialize a sub dag"),
+                            ),
+                        }
+                    });
+                    let failed = message.is_err();
+                    if tx.send(message).await.is_err() || failed {
+                        // The client closed the stream, or the sub dag cannot be read.
+                        return;
+                    }
+                    next = sub_dag_index + 1;
+                }
+
+                tokio::select! {
+                    result = rx_caught_up.changed() => {
+                        if result.is_err() {
+                            // The observer shut down.
+                            return;
+                        }
+                    }
+
+                    _ = tx.closed() => {
+                        return
+                    }
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+/// The `Proposer` gRPC API, served from the secondary instance of the primary storage.
+struct ObserverProposer {
+    storage: ObservedStorage,
+    committee: Committee,
+}
+
+impl ObserverProposer {
+    fn new(storage: ObservedStorage, committee: Committee) -> Self {
+        Self { storage, committee }
+    }
+
+    /// Parses the public key of the request into the identifier of an authority of the committee.
+    fn get_authority_id(
+        &self,
+        request: Option<PublicKeyProto>,
+    ) -> Result<AuthorityIdentifier, Status> {
+        let proto_key = request
+            .ok_or_else(|| Status::invalid_argument("Invalid public key: no key provided"))?;
+        let key = PublicKey::from_bytes(proto_key.bytes.as_ref())
+            .map_err(|_| Status::invalid_argument("Invalid public key: couldn't parse"))?;
+
+        self.committee
+            .authority_by_key(&key)
+            .map(|authority| authority.id())
+            .ok_or_else(|| Status::invalid_argument("Invalid public key: unknown authority"))
+    }
+}
+
+#[tonic::async_trait]
+impl Proposer for ObserverProposer {
+    /// Retrieves the oldest and newest rounds of the stored certificates of the authority with
+    /// the provided public key.
+    async fn rounds(
+        &self,
+        request: Request<RoundsRequest>,
+    ) -> Result<Response<RoundsResponse>, Status> {
+        let id = self.get_authority_id(request.into_inner().public_key)?;
+
+        let certificate_store = &self.storage.primary.certificate_store;
+        let oldest = certificate_store
+            .next_round_number(id, 0)
+            .map_err(internal)?;
+        let newest = certificate_store.last_round_number(id).map_err(internal)?;
+        match (oldest, newest) {
+            (Some(oldest_round), Some(newest_round)) => Ok(Response::new(RoundsResponse {
+                oldest_round,
+                newest_round,
+            })),
+            _ => Err(Status::internal(format!(
+                "Couldn't retrieve rounds: no certificate of authority {id}"
+            ))),
+        }
+    }
+
+    async fn node_read_causal(
+        &self,
+        request: Request<NodeReadCausalRequest>,
+    ) -> Result<Response<NodeReadCausalResponse>, Status> {
+        let node_read_causal_request = request.into_inner();
+
+        let id = self.get_authority_id(node_read_causal_request.public_key)?;
+        let round = node_read_causal_request.round;
+
+        let start = self
+            .storage
+            .primary
+            .certificate_store
+            .read_by_index(id, round)
+            .map_err(internal)?
+            .ok_or_else(|| {
+                Status::internal(format!(
+                    "Couldn't read causal for provided key & round: no certificate of authority {id} at round {round}"
+                ))
+            })?;
+
+        let digests = self.storage.read_causal(start.digest())?;
+        Ok(Response::new(NodeReadCausalResponse {
+            collection_ids: digests.into_iter().map(Into::into).collect(),
+        }))
+    }
+}
+
+fn parse_certificate_digests(
+    collection_ids: Vec<CertificateDigestProto>,
+) -> Result<Vec<CertificateDigest>, Status> {
+    collection_ids
+        .into_iter()
+        .map(|id| {
+            id.try_into()
+                .map_err(|err| Status::invalid_argument(format!("Could not serialize: {err:?}")))
+        })
+        .collect()
+}
+
+fn internal(err: impl std::fmt::Display) -> Status {
+    Status::internal(format!("Couldn't read the node storage: {err}"))
+}
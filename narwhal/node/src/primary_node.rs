@@ -1,5 +1,6 @@
 // Copyright (c) Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
+use crate::backup::{primary_backups_dir, spawn_scheduled_backups, take_backup};
 use crate::{try_join_all, FuturesUnordered, NodeError};
 use anemo::PeerId;
 use config::{AuthorityIdentifier, Committee, Parameters, WorkerCache};
@@ -8,12 +9,15 @@ use consensus::consensus::ConsensusRound;
 use consensus::dag::Dag;
 use consensus::Consensus;
 use crypto::{KeyPair, NetworkKeyPair};
-use executor::{get_restored_consensus_output, ExecutionState, Executor, SubscriberResult};
+use executor::{
+    get_restored_consensus_output, ExecutionState, Executor, ExecutorHealth, Pruner,
+    Reconfiguration, SubscriberResult,
+};
 use network::client::NetworkClient;
-use primary::{NetworkModel, Primary, NUM_SHUTDOWN_RECEIVERS};
+use primary::{NetworkModel, Primary, PrimaryNetwork, SnapshotSync, NUM_SHUTDOWN_RECEIVERS};
 use std::sync::Arc;
 use std::time::Instant;
-use storage::NodeStorage;
+use storage::{spawn_db_metrics_export, Backup, NodeStorage};
 use tokio::sync::{mpsc, watch, RwLock};
 use tokio::task::JoinHandle;
 use tracing::{debug, info, instrument};
@@ -36,6 +40,12 @@ struct PrimaryNodeInner {
     tx_shutdown: Option<PreSubscribedBroadcastSender>,
     // Peer ID used for local connections.
     own_peer_id: Option<PeerId>,
+    // The node's storage while it runs, to back it up on demand.
+    store: Option<NodeStorage>,
+    // The health of the executor while the node runs.
+    rx_executor_health: Option<watch::Receiver<ExecutorHealth>>,
+    // The committee of the next epoch, once the node reaches it.
+    rx_reconfiguration: Option<watch::Receiver<Option<Reconfiguration>>>,
 }
 
 impl PrimaryNodeInner {
@@ -78,6 +88,9 @@ impl PrimaryNodeInner {
         // create the channel to send the shutdown signal
         let mut tx_shutdown = PreSubscribedBroadcastSender::new(NUM_SHUTDOWN_RECEIVERS);
 
+        let (tx_executor_health, rx_executor_health) = watch::channel(ExecutorHealth::Healthy);
+        let (tx_reconfiguration, rx_reconfiguration) = watch::channel(None);
+
         // spawn primary if not already running
         let handles = Self::spawn_primary(
             keypair,
@@ -89,6 +102,8 @@ impl PrimaryNodeInner {
             self.parameters.clone(),
             self.internal_consensus,
             execution_state,
+            tx_executor_health,
+            tx_reconfiguration,
             &mut tx_shutdown,
         )
         .await?;
@@ -97,6 +112,9 @@ impl PrimaryNodeInner {
         self.handles.clear();
         self.handles.extend(handles);
         self.tx_shutdown = Some(tx_shutdown);
+        self.store = Some(store.clone());
+        self.rx_executor_health = Some(rx_executor_health);
+        self.rx_reconfiguration = Some(rx_reconfiguration);
 
         Ok(())
     }
@@ -124,6 +142,9 @@ impl PrimaryNodeInner {
                 .expect("Couldn't send the shutdown signal to downstream components");
             self.tx_shutdown = None
         }
+        self.store = None;
+        self.rx_executor_health = None;
+        self.rx_reconfiguration = None;
 
         // Now wait until handles have been completed
         try_join_all(&mut self.handles).await.unwrap();
@@ -169,6 +190,10 @@ impl PrimaryNodeInner {
         internal_consensus: bool,
         // The state used by the client to execute transactions.
         execution_state: Arc<State>,
+        // The channel to report the health of the executor.
+        tx_executor_health: watch::Sender<ExecutorHealth>,
+        // The channel to publish the committee of the next epoch.
+        tx_reconfiguration: watch::Sender<Option<Reconfiguration>>,
         // The channel to send the shutdown signal
         tx_shutdown: &mut PreSubscribedBroadcastSender,
     ) -> SubscriberResult<Vec<JoinHandle<()>>>
@@ -194,6 +219,59 @@ impl PrimaryNodeInner {
 
         let genesis_certs = Certificate::genesis(&committee, keypair.private());
 
+        let network = PrimaryNetwork::bind(authority, network_keypair, &committee, &client);
+
+        // Consensus and the primary recover from the store, so a fresh node has to sync it
+        // before they start.
+        if internal_consensus && parameters.snapshot_sync.enabled {
+            if let Some(index) = SnapshotSync::new(
+                authority.id(),
+                committee.clone(),
+                worker_cache.clone(),
+                parameters.gc_depth,
+                parameters.snapshot_sync.clone(),
+                store.certificate_store.clone(),
+                store.consensus_store.clone(),
+                store.execution_store.clone(),
+                genesis_certs.clone(),
+            )
+            .run(network.network())
+            .await
+            {
+                info!("Synced the consensus state up to sub dag {index} from the peers");
+            }
+        }
+
+        if let Some(directory) = parameters.backup.directory.clone() {
+            if !parameters.backup.interval.is_zero() {
+                handles.push(spawn_scheduled_backups(
+                    parameters.backup.clone(),
+                    primary_backups_dir(&directory),
+                    store.clone(),
+                    tx_shutdown.subscribe(),
+                ));
+            }
+        }
+
+        if !parameters.storage.metrics_interval.is_zero() {
+            handles.push(spawn_db_metrics_export(
+                store.clone(),
+                parameters.storage.metrics_interval,
+                tx_shutdown.subscribe(),
+            ));
+        }
+
+        // With an external consensus, the `BlockRemover` also deletes data on request.
+        if parameters.pruning.enabled {
+            handles.push(Pruner::spawn(
+                parameters.pruning.clone(),
+                parameters.gc_depth,
+                store,
+                tx_shutdown.subscribe(),
+                internal_consensus.then(|| rx_consensus_round_updates.clone()),
+            ));
+        }
+
         let (dag, network_model) = if !internal_consensus {
             debug!("Consensus is disabled: the primary will run w/o Bullshark");
             let (handle, dag) = Dag::new(
@@ -214,10 +292,12 @@ impl PrimaryNodeInner {
                 store,
                 parameters.clone(),
                 execution_state,
-                tx_shutdown.subscribe_n(3),
+                tx_shutdown.subscribe_n(4),
                 rx_new_certificates,
                 tx_committed_certificates.clone(),
                 tx_consensus_round_updates,
+                tx_executor_health,
+                tx_reconfiguration,
             )
             .await?;
 
@@ -233,7 +313,7 @@ impl PrimaryNodeInner {
         let primary_handles = Primary::spawn(
             authority.clone(),
             keypair,
-            network_keypair,
+            network,
             committee.clone(),
             worker_cache.clone(),
             parameters.clone(),
@@ -243,6 +323,8 @@ impl PrimaryNodeInner {
             store.proposer_store.clone(),
             store.payload_store.clone(),
             store.vote_digest_store.clone(),
+            store.consensus_store.clone(),
+            store.execution_store.clone(),
             tx_new_certificates,
             rx_committed_certificates,
             rx_consensus_round_updates,
@@ -267,9 +349,11 @@ impl PrimaryNodeInner {
         parameters: Parameters,
         execution_state: State,
         mut shutdown_receivers: Vec<ConditionalBroadcastReceiver>,
-        rx_new_certificates: mpsc::Receiver<Certificate>,
+        mut rx_new_certificates: mpsc::Receiver<Certificate>,
         tx_committed_certificates: mpsc::Sender<(Round, Vec<Certificate>)>,
         tx_consensus_round_updates: watch::Sender<ConsensusRound>,
+        tx_executor_health: watch::Sender<ExecutorHealth>,
+        tx_reconfiguration: watch::Sender<Option<Reconfiguration>>,
     ) -> SubscriberResult<Vec<JoinHandle<()>>>
     where
         State: ExecutionState + Send + Sync + 'static,
@@ -280,7 +364,7 @@ impl PrimaryNodeInner {
         let restored_consensus_output = get_restored_consensus_output(
             store.consensus_store.clone(),
             store.certificate_store.clone(),
-            &execution_state,
+            &store.execution_store,
         )
         .await?;
 
@@ -293,6 +377,29 @@ impl PrimaryNodeInner {
 
         // TODO(metrics): Increment `recovered_consensus_output` by `num_sub_dags`
 
+        // The executor fetches the batches of the certificates as soon as they are certified, so
+        // they are available locally once the certificates are committed.
+        let (tx_consensus_certificates, rx_consensus_certificates) =
+            mpsc::channel(Self::CHANNEL_CAPACITY);
+        let (tx_certified, rx_certified) = mpsc::channel(Self::CHANNEL_CAPACITY);
+        let mut rx_shutdown = shutdown_receivers.pop().unwrap();
+        let certified_handle = tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    Some(certificate) = rx_new_certificates.recv() => {
+                        // The prefetching never holds consensus back.
+                        let _ = tx_certified.try_send(certificate.clone());
+                        if tx_consensus_certificates.send(certificate).await.is_err() {
+                            return;
+                        }
+                    },
+                    _ = rx_shutdown.receiver.recv() => {
+                        return;
+                    }
+                }
+            }
+        });
+
         // Spawn the consensus core who only sequences transactions.
         let ordering_engine = Bullshark::new(
             committee.clone(),
@@ -305,7 +412,7 @@ impl PrimaryNodeInner {
             store.consensus_store.clone(),
             store.certificate_store.clone(),
             shutdown_receivers.pop().unwrap(),
-            rx_new_certificates,
+            rx_consensus_certificates,
             tx_committed_certificates,
             tx_consensus_round_updates,
             tx_sequence,
@@ -319,15 +426,20 @@ impl PrimaryNodeInner {
             worker_cache,
             committee.clone(),
             client,
+            parameters.executor.clone(),
             execution_state,
+            store.execution_store.clone(),
             shutdown_receivers,
             rx_sequence,
+            rx_certified,
+            tx_executor_health,
+            tx_reconfiguration,
             restored_consensus_output,
         )?;
 
         Ok(executor_handles
             .into_iter()
-            .chain(std::iter::once(consensus_handles))
+            .chain([consensus_handles, certified_handle])
             .collect())
     }
 }
@@ -346,6 +458,9 @@ impl PrimaryNode {
             client: None,
             tx_shutdown: None,
             own_peer_id: None,
+            store: None,
+            rx_executor_health: None,
+            rx_reconfiguration: None,
         };
 
         Self {
@@ -402,4 +517,45 @@ impl PrimaryNode {
         let mut guard = self.internal.write().await;
         guard.wait().await
     }
+
+    /// Returns the health of the executor of the running node. It stays healthy when the node
+    /// runs without internal consensus.
+    pub async fn executor_health(&self) -> Result<watch::Receiver<ExecutorHealth>, NodeError> {
+        let guard = self.internal.read().await;
+        guard
+            .rx_executor_health
+            .clone()
+            .ok_or(NodeError::NodeNotRunning)
+    }
+
+    /// Returns the committee and the worker cache of the next epoch, published once the last sub
+    /// dag of the current committee is executed. The node must then be shut down and started
+    /// again with them, on a fresh storage. The sub dags committed after the last one are still
+    /// executed until the node is shut down.
+    pub async fn reconfiguration(
+        &self,
+    ) -> Result<watch::Receiver<Option<Reconfiguration>>, NodeError> {
+        let guard = self.internal.read().await;
+        guard
+            .rx_reconfiguration
+            .clone()
+            .ok_or(NodeError::NodeNotRunning)
+    }
+
+    /// Takes a consistent backup of the storage of the running node to the `primary` directory of
+    /// the configured backup directory, and deletes the oldest backups beyond `max_backups`.
+    pub async fn backup(&self) -> Result<Backup, NodeError> {
+        let (store, parameters) = {
+            let guard = self.internal.read().await;
+            let store = guard.store.clone().ok_or(NodeError::NodeNotRunning)?;
+            (store, guard.parameters.backup.clone())
+        };
+        let directory = parameters.directory.ok_or(NodeError::BackupsDisabled)?;
+        take_backup(
+            store,
+            primary_backups_dir(&directory),
+            parameters.max_backups,
+        )
+        .await
+    }
 }
@@ -1,6 +1,7 @@
 // Copyright (c) Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
+use crate::backup::{spawn_scheduled_backups, take_backup, worker_backups_dir};
 use crate::{try_join_all, FuturesUnordered, NodeError};
 use anemo::PeerId;
 use arc_swap::{ArcSwap, ArcSwapOption};
@@ -10,13 +11,17 @@ use network::client::NetworkClient;
 use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Instant;
-use storage::NodeStorage;
+use storage::{spawn_db_metrics_export, Backup, NodeStorage};
 use tokio::sync::RwLock;
 use tokio::task::JoinHandle;
 use tracing::{info, instrument};
 use types::PreSubscribedBroadcastSender;
 use worker::{TransactionValidator, Worker, NUM_SHUTDOWN_RECEIVERS};
 
+/// The number of shutdown receivers of the tasks the worker node runs next to the worker: the
+/// export of the storage metrics and the scheduled backups.
+const NUM_NODE_SHUTDOWN_RECEIVERS: u64 = 2;
+
 pub struct WorkerNodeInner {
     // The worker's id
     id: WorkerId,
@@ -28,6 +33,8 @@ pub struct WorkerNodeInner {
     tx_shutdown: Option<PreSubscribedBroadcastSender>,
     // Peer ID used for local connections.
     own_peer_id: Option<PeerId>,
+    // The node's storage while it runs, to back it up on demand.
+    store: Option<NodeStorage>,
 }
 
 impl WorkerNodeInner {
@@ -58,7 +65,8 @@ impl WorkerNodeInner {
 
         self.own_peer_id = Some(PeerId(network_keypair.public().0.to_bytes()));
 
-        let mut tx_shutdown = PreSubscribedBroadcastSender::new(NUM_SHUTDOWN_RECEIVERS);
+        let mut tx_shutdown =
+            PreSubscribedBroadcastSender::new(NUM_SHUTDOWN_RECEIVERS + NUM_NODE_SHUTDOWN_RECEIVERS);
 
         let authority = committee
             .authority_by_key(&primary_name)
@@ -69,7 +77,7 @@ impl WorkerNodeInner {
                 )
             });
 
-        let handles = Worker::spawn(
+        let mut handles = Worker::spawn(
             authority.clone(),
             network_keypair,
             self.id,
@@ -82,10 +90,30 @@ impl WorkerNodeInner {
             &mut tx_shutdown,
         );
 
+        if !self.parameters.storage.metrics_interval.is_zero() {
+            handles.push(spawn_db_metrics_export(
+                store.clone(),
+                self.parameters.storage.metrics_interval,
+                tx_shutdown.subscribe(),
+            ));
+        }
+
+        if let Some(directory) = &self.parameters.backup.directory {
+            if !self.parameters.backup.interval.is_zero() {
+                handles.push(spawn_scheduled_backups(
+                    self.parameters.backup.clone(),
+                    worker_backups_dir(directory, self.id),
+                    store.clone(),
+                    tx_shutdown.subscribe(),
+                ));
+            }
+        }
+
         // now keep the handlers
         self.handles.clear();
         self.handles.extend(handles);
         self.tx_shutdown = Some(tx_shutdown);
+        self.store = Some(store.clone());
 
         Ok(())
     }
@@ -106,6 +134,7 @@ impl WorkerNodeInner {
                 .expect("Couldn't send the shutdown signal to downstream components");
             self.tx_shutdown = None;
         }
+        self.store = None;
 
         // Now wait until handles have been completed
         try_join_all(&mut self.handles).await.unwrap();
@@ -142,6 +171,7 @@ impl WorkerNode {
             handles: FuturesUnordered::new(),
             tx_shutdown: None,
             own_peer_id: None,
+            store: None,
         };
 
         Self {
@@ -195,6 +225,24 @@ impl WorkerNode {
         let mut guard = self.internal.write().await;
         guard.wait().await
     }
+
+    /// Takes a consistent backup of the storage of the running worker to its `worker-<id>`
+    /// directory of the configured backup directory, and deletes the oldest backups beyond
+    /// `max_backups`.
+    pub async fn backup(&self) -> Result<Backup, NodeError> {
+        let (id, store, parameters) = {
+            let guard = self.internal.read().await;
+            let store = guard.store.clone().ok_or(NodeError::NodeNotRunning)?;
+            (guard.id, store, guard.parameters.backup.clone())
+        };
+        let directory = parameters.directory.ok_or(NodeError::BackupsDisabled)?;
+        take_backup(
+            store,
+            worker_backups_dir(&directory, id),
+            parameters.max_backups,
+        )
+        .await
+    }
 }
 
 pub struct WorkerNodes {
@@ -296,4 +344,16 @@ impl WorkerNodes {
 
         worker_ids
     }
+
+    /// Takes a backup of the storage of every running worker, see `WorkerNode::backup`.
+    pub async fn backup(&self) -> Result<Vec<(WorkerId, Backup)>, NodeError> {
+        let mut backups = Vec::new();
+        for (id, worker) in self.workers.load_full().as_ref() {
+            if worker.is_running().await {
+                backups.push((*id, worker.backup().await?));
+            }
+        }
+        backups.sort_by_key(|(id, _)| *id);
+        Ok(backups)
+    }
 }
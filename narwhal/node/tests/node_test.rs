@@ -1,18 +1,29 @@
 // Copyright (c) Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
-use config::Parameters;
+use config::utils::get_available_port;
+use config::{ObserverParameters, Parameters};
+use crypto::Hash;
+use mysten_network::Multiaddr;
 use narwhal_node::execution_state::SimpleExecutionState;
+use narwhal_node::observer_node::ObserverNode;
 use narwhal_node::primary_node::PrimaryNode;
 use narwhal_node::worker_node::WorkerNodes;
 use network::client::NetworkClient;
+use std::collections::{BTreeMap, HashMap};
 use std::num::NonZeroUsize;
 use std::sync::Arc;
 use std::time::Duration;
 use storage::NodeStorage;
-use test_utils::{temp_dir, CommitteeFixture};
+use test_utils::CommitteeFixture;
 use tokio::sync::mpsc::channel;
-use tokio::time::sleep;
+use tokio::time::{sleep, timeout};
+use types::{
+    BatchAPI, CertificateDigest, CertificateDigestProto, CommittedSubDag, GetCollectionsRequest,
+    Header, ProposerClient, ReadCausalRequest, ReadCommittedSubDagsRequest,
+    RemoveCollectionsRequest, ReputationScores, RetrievalResult, RoundsRequest, Transaction,
+    ValidatorClient,
+};
 use worker::TrivialTransactionValidator;
 
 #[tokio::test]
@@ -33,7 +44,7 @@ async fn simple_primary_worker_node_start_stop() {
     let network_key_pair = authority.network_keypair();
     let client = NetworkClient::new_from_keypair(&network_key_pair);
 
-    let store = NodeStorage::reopen(temp_dir());
+    let store = NodeStorage::new_for_tests();
 
     let (tx_confirmation, _rx_confirmation) = channel(10);
     let execution_state = Arc::new(SimpleExecutionState::new(tx_confirmation));
@@ -114,7 +125,7 @@ async fn primary_node_restart() {
     let network_key_pair = authority.network_keypair();
     let client = NetworkClient::new_from_keypair(&network_key_pair);
 
-    let store = NodeStorage::reopen(temp_dir());
+    let store = NodeStorage::new_for_tests();
 
     let (tx_confirmation, _rx_confirmation) = channel(10);
     let execution_state = Arc::new(SimpleExecutionState::new(tx_confirmation));
@@ -175,3 +186,167 @@ async fn primary_node_restart() {
 
     assert_ne!(result, "");
 }
+
+#[tokio::test]
+async fn observer_serves_node_storage() {
+    telemetry_subscribers::init_for_testing();
+
+    // GIVEN the storages of a primary and its worker holding a certificate, its batch and a
+    // committed sub dag
+    let fixture = CommitteeFixture::builder().build();
+    let committee = fixture.committee();
+    let author = fixture.authorities().next().unwrap();
+    let batch = test_utils::fixture_batch_with_transactions(10);
+    let header = Header::V2(
+        author
+            .header_builder(&committee)
+            .with_payload_batch(batch.clone(), 0, 0)
+            .signed(author.keypair().private())
+            .build(),
+    );
+    let certificate = fixture.certificate(&header);
+    let first_sub_dag = CommittedSubDag::new(
+        vec![certificate.clone()],
+        certificate.clone(),
+        1,
+        ReputationScores::default(),
+        None,
+    );
+
+    let primary_path = test_utils::temp_dir();
+    let primary_store = NodeStorage::reopen(&primary_path);
+    primary_store
+        .certificate_store
+        .write(certificate.clone())
+        .unwrap();
+    primary_store
+        .consensus_store
+        .write_consensus_state(&HashMap::new(), &first_sub_dag)
+        .unwrap();
+    let worker_path = test_utils::temp_dir();
+    let worker_store = NodeStorage::reopen(&worker_path);
+    worker_store
+        .batch_store
+        .insert(&batch.digest(), &batch)
+        .unwrap();
+
+    // WHEN an observer opens the storages
+    let socket_addr: Multiaddr = format!(
+        "/ip4/127.0.0.1/tcp/{}/http",
+        get_available_port("127.0.0.1")
+    )
+    .parse()
+    .unwrap();
+    let parameters = ObserverParameters {
+        secondary_directory: Some(test_utils::temp_dir()),
+        catch_up_interval: Duration::from_millis(100),
+    };
+    let observer = ObserverNode::start(
+        committee,
+        &primary_path,
+        BTreeMap::from([(0, worker_path)]),
+        parameters,
+        socket_addr.clone(),
+    )
+    .await
+    .unwrap();
+
+    let channel = mysten_network::config::Config::new()
+        .connect_lazy(&socket_addr)
+        .unwrap();
+    let mut validator = ValidatorClient::new(channel.clone());
+    let mut proposer = ProposerClient::new(channel);
+
+    // THEN the collections are served with the transactions of the worker store
+    let response = validator
+        .get_collections(GetCollectionsRequest {
+            collection_ids: vec![
+                certificate.digest().into(),
+                CertificateDigest::default().into(),
+            ],
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(response.result.len(), 2);
+    match response.result[0].retrieval_result.clone() {
+        Some(RetrievalResult::Collection(collection)) => {
+            let transactions: Vec<Transaction> = collection
+                .transactions
+                .into_iter()
+                .map(Into::into)
+                .collect();
+            assert_eq!(&transactions, batch.transactions());
+        }
+        other => panic!("Expected the collection, got {other:?}"),
+    }
+    assert!(matches!(
+        response.result[1].retrieval_result,
+        Some(RetrievalResult::Error(_))
+    ));
+
+    // AND the DAG of the stored certificates is served
+    let response = validator
+        .read_causal(ReadCausalRequest {
+            collection_id: Some(certificate.digest().into()),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(
+        response.collection_ids,
+        vec![CertificateDigestProto::from(certificate.digest())]
+    );
+    let response = proposer
+        .rounds(RoundsRequest {
+            public_key: Some(author.public_key().into()),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(
+        (response.oldest_round, response.newest_round),
+        (certificate.round(), certificate.round())
+    );
+
+    // AND the collections cannot be removed
+    assert!(validator
+        .remove_collections(RemoveCollectionsRequest {
+            collection_ids: vec![certificate.digest().into()],
+        })
+        .await
+        .is_err());
+
+    // AND the committed sub dags are streamed with their certificates, including the ones
+    // committed afterwards
+    let mut stream = validator
+        .read_committed_sub_dags(ReadCommittedSubDagsRequest { from: 0 })
+        .await
+        .unwrap()
+        .into_inner();
+    let message = stream.message().await.unwrap().unwrap();
+    assert_eq!(message.sub_dag_index, 1);
+    let sub_dag: CommittedSubDag = bcs::from_bytes(&message.sub_dag).unwrap();
+    assert_eq!(sub_dag.certificates, vec![certificate.clone()]);
+    assert_eq!(sub_dag.leader, certificate);
+
+    let second_sub_dag = CommittedSubDag::new(
+        vec![],
+        certificate.clone(),
+        2,
+        ReputationScores::default(),
+        Some(&first_sub_dag),
+    );
+    primary_store
+        .consensus_store
+        .write_consensus_state(&HashMap::new(), &second_sub_dag)
+        .unwrap();
+    let message = timeout(Duration::from_secs(5), stream.message())
+        .await
+        .expect("The sub dag committed after the stream started was not streamed")
+        .unwrap()
+        .unwrap();
+    assert_eq!(message.sub_dag_index, 2);
+
+    observer.shutdown().await;
+}
@@ -55,11 +55,12 @@ async fn test_successful_headers_synchronization() {
         let batch_1 = fixture_batch_with_transactions(10);
         let batch_2 = fixture_batch_with_transactions(10);
 
-        let header = Header::V1(
+        let header = Header::V2(
             author
                 .header_builder(&committee)
                 .with_payload_batch(batch_1.clone(), worker_id_0, 0)
                 .with_payload_batch(batch_2.clone(), worker_id_1, 0)
+                .signed(author.keypair().private())
                 .build(),
         );
 
@@ -205,11 +206,12 @@ async fn test_successful_payload_synchronization() {
         let batch_1 = fixture_batch_with_transactions(10);
         let batch_2 = fixture_batch_with_transactions(10);
 
-        let header = Header::V1(
+        let header = Header::V2(
             author
                 .header_builder(&committee)
                 .with_payload_batch(batch_1.clone(), worker_id_0, 0)
                 .with_payload_batch(batch_2.clone(), worker_id_1, 0)
+                .signed(author.keypair().private())
                 .build(),
         );
 
@@ -384,10 +386,11 @@ async fn test_timeout_while_waiting_for_certificates() {
     // AND some random block digests
     let digests: Vec<CertificateDigest> = (0..10)
         .map(|_| {
-            let header = Header::V1(
+            let header = Header::V2(
                 author
                     .header_builder(&committee)
                     .with_payload_batch(fixture_batch_with_transactions(10), 0, 0)
+                    .signed(author.keypair().private())
                     .build(),
             );
 
@@ -536,10 +539,11 @@ async fn test_reply_with_certificates_already_in_storage() {
     for i in 1..=8 {
         let batch = fixture_batch_with_transactions(10);
 
-        let header = Header::V1(
+        let header = Header::V2(
             author
                 .header_builder(&committee)
                 .with_payload_batch(batch.clone(), 0, 0)
+                .signed(author.keypair().private())
                 .build(),
         );
 
@@ -644,10 +648,11 @@ async fn test_reply_with_payload_already_in_storage() {
     for i in 1..=8 {
         let batch = fixture_batch_with_transactions(10);
 
-        let header = Header::V1(
+        let header = Header::V2(
             author
                 .header_builder(&committee)
                 .with_payload_batch(batch.clone(), 0, 0)
+                .signed(author.keypair().private())
                 .build(),
         );
 
@@ -756,10 +761,11 @@ async fn test_reply_with_payload_already_in_storage_for_own_certificates() {
     for _ in 0..5 {
         let batch = fixture_batch_with_transactions(10);
 
-        let header = Header::V1(
+        let header = Header::V2(
             primary
                 .header_builder(&committee)
                 .with_payload_batch(batch.clone(), 0, 0)
+                .signed(primary.keypair().private())
                 .build(),
         );
 
@@ -33,10 +33,11 @@ async fn test_get_and_synchronize_block_headers_when_fetched_from_storage() {
     let author = fixture.authorities().next().unwrap();
 
     // AND dummy certificate
-    let header = Header::V1(
+    let header = Header::V2(
         author
             .header_builder(&committee)
             .payload(fixture_payload(1))
+            .signed(author.keypair().private())
             .build(),
     );
     let certificate = fixture.certificate(&header);
@@ -92,20 +93,22 @@ async fn test_get_and_synchronize_block_headers_when_fetched_from_peers() {
     let author = fixture.authorities().next().unwrap();
 
     // AND a certificate stored
-    let header = Header::V1(
+    let header = Header::V2(
         author
             .header_builder(&committee)
             .payload(fixture_payload(1))
+            .signed(author.keypair().private())
             .build(),
     );
     let cert_stored = fixture.certificate(&header);
     certificate_store.write(cert_stored.clone()).unwrap();
 
     // AND a certificate NOT stored
-    let header = Header::V1(
+    let header = Header::V2(
         author
             .header_builder(&committee)
             .payload(fixture_payload(2))
+            .signed(author.keypair().private())
             .build(),
     );
     let cert_missing = fixture.certificate(&header);
@@ -193,20 +196,22 @@ async fn test_get_and_synchronize_block_headers_timeout_on_causal_completion() {
     let author = fixture.authorities().next().unwrap();
 
     // AND a certificate stored
-    let header = Header::V1(
+    let header = Header::V2(
         author
             .header_builder(&committee)
             .payload(fixture_payload(1))
+            .signed(author.keypair().private())
             .build(),
     );
     let cert_stored = fixture.certificate(&header);
     certificate_store.write(cert_stored.clone()).unwrap();
 
     // AND a certificate NOT stored
-    let header = Header::V1(
+    let header = Header::V2(
         author
             .header_builder(&committee)
             .payload(fixture_payload(2))
+            .signed(author.keypair().private())
             .build(),
     );
     let cert_missing = fixture.certificate(&header);
@@ -276,10 +281,11 @@ async fn test_synchronize_block_payload() {
     let author = fixture.authorities().next().unwrap();
 
     // AND a certificate with payload already available
-    let header = Header::V1(
+    let header = Header::V2(
         author
             .header_builder(&committee)
             .payload(fixture_payload(1))
+            .signed(author.keypair().private())
             .build(),
     );
     let cert_stored = fixture.certificate(&header);
@@ -288,10 +294,11 @@ async fn test_synchronize_block_payload() {
     }
 
     // AND a certificate with payload NOT available
-    let header = Header::V1(
+    let header = Header::V2(
         author
             .header_builder(&committee)
             .payload(fixture_payload(2))
+            .signed(author.keypair().private())
             .build(),
     );
     let cert_missing = fixture.certificate(&header);
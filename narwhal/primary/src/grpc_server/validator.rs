@@ -6,12 +6,14 @@ use crate::{
     block_synchronizer::handler::Handler, block_waiter::GetBlockResponse, BlockRemover, BlockWaiter,
 };
 use consensus::dag::Dag;
+use futures::stream::BoxStream;
 use tokio::time::timeout;
 use tonic::{Request, Response, Status};
 use types::{
     BatchAPI, BlockError, CertificateDigest, CertificateDigestProto, Collection,
-    CollectionRetrievalResult, Empty, GetCollectionsRequest, GetCollectionsResponse,
-    ReadCausalRequest, ReadCausalResponse, RemoveCollectionsRequest, TransactionProto, Validator,
+    CollectionRetrievalResult, CommittedSubDagProto, Empty, GetCollectionsRequest,
+    GetCollectionsResponse, ReadCausalRequest, ReadCausalResponse, ReadCommittedSubDagsRequest,
+    RemoveCollectionsRequest, TransactionProto, Validator,
 };
 
 pub struct NarwhalValidator<SynchronizerHandler: Handler + Send + Sync + 'static> {
@@ -134,6 +136,18 @@ impl<SynchronizerHandler: Handler + Send + Sync + 'static> Validator
         };
         get_collections_response.map(Response::new)
     }
+
+    type ReadCommittedSubDagsStream = BoxStream<'static, Result<CommittedSubDagProto, Status>>;
+
+    async fn read_committed_sub_dags(
+        &self,
+        _request: Request<ReadCommittedSubDagsRequest>,
+    ) -> Result<Response<Self::ReadCommittedSubDagsStream>, Status> {
+        // The API is served with an external consensus, so no sub dag is committed by Narwhal.
+        Err(Status::unimplemented(
+            "The committed sub dags are streamed by the observers of a node running the internal consensus",
+        ))
+    }
 }
 
 fn get_collection_retrieval_results(
@@ -0,0 +1,188 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use config::AdaptiveHeaderParameters;
+use tokio::time::{Duration, Instant};
+use tracing::debug;
+
+#[cfg(feature = "metrics")]
+use snarkos_metrics::gauge;
+
+#[cfg(test)]
+#[path = "tests/header_sizer_tests.rs"]
+pub mod header_sizer_tests;
+
+/// The weight given to the latest observation in the moving averages.
+const EWMA_WEIGHT: f64 = 0.2;
+
+#[cfg(feature = "metrics")]
+const ROUND_LATENCY_MS: &str = "narwhal_primary_proposer_round_latency_ms";
+#[cfg(feature = "metrics")]
+const MIN_HEADER_DELAY_MS: &str = "narwhal_primary_proposer_min_header_delay_ms";
+#[cfg(feature = "metrics")]
+const MAX_HEADER_DELAY_MS: &str = "narwhal_primary_proposer_max_header_delay_ms";
+#[cfg(feature = "metrics")]
+const HEADER_NUM_OF_BATCHES_THRESHOLD: &str =
+    "narwhal_primary_proposer_header_num_of_batches_threshold";
+
+/// Decides how long the `Proposer` waits before proposing a header and how many batch digests
+/// are enough to propose it early. With the adaptive mode disabled, the configured values are
+/// used as is. Otherwise they are the upper bounds of values tuned every time the round
+/// advances:
+/// * the batches threshold follows the number of digests we expect to receive during a round,
+///   so headers are neither under-filled nor wait for digests that won't come in time,
+/// * the minimum delay shrinks as the queue of pending digests fills up, so a load spike is
+///   drained by proposing as soon as the parents are available,
+/// * the maximum delay follows the observed round latency, so we don't wait much longer than
+///   the network needs to advance.
+pub struct HeaderSizer {
+    params: AdaptiveHeaderParameters,
+    /// The configured values, the upper bounds of the tuned ones.
+    header_num_of_batches_threshold: usize,
+    max_header_num_of_batches: usize,
+    min_header_delay: Duration,
+    max_header_delay: Duration,
+
+    /// The moving average of the time between two rounds.
+    round_latency: Option<Duration>,
+    /// The moving average of the number of digests received per second.
+    digests_rate: f64,
+    /// When the round last advanced.
+    last_round_advance: Option<Instant>,
+    /// The number of digests received since the round last advanced.
+    digests_since_last_round: usize,
+
+    /// The currently chosen values.
+    current_threshold: usize,
+    current_min_delay: Duration,
+    current_max_delay: Duration,
+}
+
+impl HeaderSizer {
+    pub fn new(
+        params: AdaptiveHeaderParameters,
+        header_num_of_batches_threshold: usize,
+        max_header_num_of_batches: usize,
+        min_header_delay: Duration,
+        max_header_delay: Duration,
+    ) -> Self {
+        Self {
+            params,
+            header_num_of_batches_threshold,
+            max_header_num_of_batches,
+            min_header_delay,
+            max_header_delay,
+            round_latency: None,
+            digests_rate: 0.0,
+            last_round_advance: None,
+            digests_since_last_round: 0,
+            current_threshold: header_num_of_batches_threshold,
+            current_min_delay: min_header_delay,
+            current_max_delay: max_header_delay,
+        }
+    }
+
+    /// The number of digests that is enough to propose a header before `min_header_delay`.
+    pub fn header_num_of_batches_threshold(&self) -> usize {
+        self.current_threshold
+    }
+
+    /// The delay after which a header can be proposed even without enough digests.
+    pub fn min_header_delay(&self) -> Duration {
+        self.current_min_delay
+    }
+
+    /// The delay after which a header is proposed regardless of the other conditions.
+    pub fn max_header_delay(&self) -> Duration {
+        self.current_max_delay
+    }
+
+    /// Records a digest received from our workers.
+    pub fn digest_received(&mut self) {
+        self.digests_since_last_round += 1;
+    }
+
+    /// Records that the round advanced at `now`, with `pending_digests` digests waiting to be
+    /// included in a header, and tunes the values for the next round.
+    pub fn round_advanced(&mut self, now: Instant, pending_digests: usize) {
+        let last_round_advance = self.last_round_advance.replace(now);
+        let received = std::mem::take(&mut self.digests_since_last_round);
+
+        if !self.params.enabled {
+            return;
+        }
+        let Some(last_round_advance) = last_round_advance else {
+            return;
+        };
+
+        let latency = now.saturating_duration_since(last_round_advance);
+        let round_latency = match self.round_latency {
+            Some(average) => average.mul_f64(1.0 - EWMA_WEIGHT) + latency.mul_f64(EWMA_WEIGHT),
+            None => latency,
+        };
+        self.round_latency = Some(round_latency);
+
+        if !latency.is_zero() {
+            let rate = received as f64 / latency.as_secs_f64();
+            self.digests_rate = self.digests_rate * (1.0 - EWMA_WEIGHT) + rate * EWMA_WEIGHT;
+        }
+
+        // Propose early once we have what is expected to arrive within a round.
+        let min_threshold = self
+            .params
+            .min_header_num_of_batches_threshold
+            .min(self.max_header_num_of_batches);
+        let expected_per_round = (self.digests_rate * round_latency.as_secs_f64()).ceil() as usize;
+        self.current_threshold = expected_per_round.clamp(
+            min_threshold,
+            self.max_header_num_of_batches
+                .min(self.header_num_of_batches_threshold)
+                .max(min_threshold),
+        );
+
+        // The fuller the queue, the sooner we propose: with a full header worth of pending
+        // digests we don't wait longer than the floor.
+        let min_floor = self
+            .params
+            .min_header_delay_floor
+            .min(self.min_header_delay);
+        let pressure =
+            (pending_digests as f64 / self.max_header_num_of_batches.max(1) as f64).min(1.0);
+        self.current_min_delay =
+            self.min_header_delay - (self.min_header_delay - min_floor).mul_f64(pressure);
+
+        // Don't wait much longer than the network needs to advance the round.
+        let max_floor = self
+            .params
+            .max_header_delay_floor
+            .min(self.max_header_delay)
+            .max(self.current_min_delay);
+        self.current_max_delay =
+            (round_latency * 2).clamp(max_floor, self.max_header_delay.max(max_floor));
+
+        debug!(
+            "Adaptive header sizing: round latency {:?}, pending digests {}, threshold {}, min delay {:?}, max delay {:?}",
+            round_latency,
+            pending_digests,
+            self.current_threshold,
+            self.current_min_delay,
+            self.current_max_delay
+        );
+
+        #[cfg(feature = "metrics")]
+        {
+            gauge!(ROUND_LATENCY_MS, round_latency.as_millis() as f64);
+            gauge!(
+                MIN_HEADER_DELAY_MS,
+                self.current_min_delay.as_millis() as f64
+            );
+            gauge!(
+                MAX_HEADER_DELAY_MS,
+                self.current_max_delay.as_millis() as f64
+            );
+            gauge!(
+                HEADER_NUM_OF_BATCHES_THRESHOLD,
+                self.current_threshold as f64
+            );
+        }
+    }
+}
@@ -0,0 +1,45 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use tokio::time::Duration;
+
+#[cfg(test)]
+#[path = "tests/leader_timeout_tests.rs"]
+pub mod leader_timeout_tests;
+
+/// The weight given to the latest observation in the moving average.
+const EWMA_WEIGHT: f64 = 0.2;
+
+/// Decides how long the `Proposer` waits, in partial synchrony, for the leader certificate on
+/// even rounds and for the votes on the leader on odd rounds. The timeout follows the latency
+/// between proposing our own headers and seeing them committed: a leader that takes longer than
+/// a whole commit to show up is most likely slow or crashed, and waiting for it only delays
+/// the following leaders.
+#[derive(Default)]
+pub struct LeaderTimeout {
+    /// The moving average of the latency to commit our own headers.
+    commit_latency: Option<Duration>,
+}
+
+impl LeaderTimeout {
+    /// Records that one of our own headers got committed `latency` after being created.
+    pub fn commit_observed(&mut self, latency: Duration) {
+        self.commit_latency = Some(match self.commit_latency {
+            Some(average) => average.mul_f64(1.0 - EWMA_WEIGHT) + latency.mul_f64(EWMA_WEIGHT),
+            None => latency,
+        });
+    }
+
+    /// The moving average of the commit latency, if any commit has been observed yet.
+    pub fn commit_latency(&self) -> Option<Duration> {
+        self.commit_latency
+    }
+
+    /// The time to wait for the leader or its votes, within `[min, max]`. Until some commit is
+    /// observed we wait up to `max`.
+    pub fn timeout(&self, min: Duration, max: Duration) -> Duration {
+        match self.commit_latency {
+            Some(latency) => latency.clamp(min, max.max(min)),
+            None => max,
+        }
+    }
+}
@@ -15,8 +15,11 @@ mod block_waiter;
 mod certificate_fetcher;
 mod certifier;
 mod grpc_server;
+mod header_sizer;
+mod leader_timeout;
 mod primary;
 mod proposer;
+mod snapshot_sync;
 mod state_handler;
 mod synchronizer;
 mod utils;
@@ -33,5 +36,6 @@ pub use crate::{
     block_remover::BlockRemover,
     block_synchronizer::{mock::MockBlockSynchronizer, BlockHeader},
     block_waiter::{BlockWaiter, GetBlockResponse},
-    primary::{NetworkModel, Primary, CHANNEL_CAPACITY, NUM_SHUTDOWN_RECEIVERS},
+    primary::{NetworkModel, Primary, PrimaryNetwork, CHANNEL_CAPACITY, NUM_SHUTDOWN_RECEIVERS},
+    snapshot_sync::SnapshotSync,
 };
@@ -8,6 +8,7 @@ use crate::{
     certifier::Certifier,
     grpc_server::ConsensusAPIGrpc,
     proposer::{OurDigestMessage, Proposer},
+    snapshot_sync::MAX_SNAPSHOT_CERTIFICATES,
     state_handler::StateHandler,
     synchronizer::Synchronizer,
     BlockRemover,
@@ -18,35 +19,41 @@ use anemo_tower::auth::RequireAuthorizationLayer;
 use anemo_tower::set_header::SetResponseHeaderLayer;
 use anemo_tower::{
     auth::AllowedPeers,
-    callback::CallbackLayer,
     inflight_limit, rate_limit,
     set_header::SetRequestHeaderLayer,
     trace::{DefaultMakeSpan, DefaultOnFailure, TraceLayer},
 };
 use async_trait::async_trait;
+use bytes::Bytes;
 use config::{Authority, AuthorityIdentifier, Committee, Parameters, WorkerCache};
 use consensus::consensus::ConsensusRound;
 use consensus::dag::Dag;
+use consensus::utils::gc_round;
 use crypto::{
     EncodeDecodeBase64, Hash, KeyPair, NetworkKeyPair, NetworkPublicKey, SignatureService,
 };
 use futures::{stream::FuturesUnordered, StreamExt};
 use mysten_network::{multiaddr::Protocol, Multiaddr};
-use network::failpoints::FailpointsMakeCallbackHandler;
+#[cfg(feature = "fault-injection")]
+use network::failpoints::{Direction, FaultInjectionLayer};
 use network::{
     client::NetworkClient,
     epoch_filter::{AllowedEpoch, EPOCH_HEADER_KEY},
 };
+use once_cell::sync::OnceCell;
 use std::collections::HashMap;
 use std::{
     cmp::Reverse,
-    collections::{BTreeSet, BinaryHeap},
+    collections::{BTreeSet, BinaryHeap, HashSet},
     net::Ipv4Addr,
     sync::Arc,
     thread::sleep,
     time::Duration,
 };
-use storage::{CertificateStore, HeaderStore, PayloadStore, ProposerStore, VoteDigestStore};
+use storage::{
+    CertificateStore, ConsensusStore, ExecutionStore, HeaderStore, PayloadStore, ProposerStore,
+    VoteDigestStore,
+};
 use tokio::sync::mpsc::{Receiver, Sender};
 use tokio::{sync::watch, task::JoinHandle};
 use tokio::{
@@ -56,17 +63,18 @@ use tokio::{
     },
     time::Instant,
 };
-use tower::ServiceBuilder;
+use tower::{ServiceBuilder, ServiceExt};
 use tracing::{debug, error, info, instrument, warn};
 use types::{
     ensure,
     error::{DagError, DagResult},
     now, Certificate, CertificateAPI, CertificateDigest, FetchCertificatesRequest,
-    FetchCertificatesResponse, GetCertificatesRequest, GetCertificatesResponse, HeaderAPI,
-    PayloadAvailabilityRequest, PayloadAvailabilityResponse, PreSubscribedBroadcastSender,
-    PrimaryToPrimary, PrimaryToPrimaryServer, RequestVoteRequest, RequestVoteResponse, Round,
-    SendCertificateRequest, SendCertificateResponse, Vote, VoteInfoAPI, WorkerOthersBatchMessage,
-    WorkerOurBatchMessage, WorkerToPrimary, WorkerToPrimaryServer,
+    FetchCertificatesResponse, FetchConsensusSnapshotRequest, FetchConsensusSnapshotResponse,
+    GetCertificatesRequest, GetCertificatesResponse, HeaderAPI, PayloadAvailabilityRequest,
+    PayloadAvailabilityResponse, PreSubscribedBroadcastSender, PrimaryToPrimary,
+    PrimaryToPrimaryServer, RequestVoteRequest, RequestVoteResponse, Round, SendCertificateRequest,
+    SendCertificateResponse, SequenceNumber, SubDagDigest, Vote, VoteInfoAPI,
+    WorkerOthersBatchMessage, WorkerOurBatchMessage, WorkerToPrimary, WorkerToPrimaryServer,
 };
 
 #[cfg(any(test))]
@@ -77,7 +85,7 @@ pub mod primary_tests;
 pub const CHANNEL_CAPACITY: usize = 1_000;
 
 /// The number of shutdown receivers to create on startup. We need one per component loop.
-pub const NUM_SHUTDOWN_RECEIVERS: u64 = 27;
+pub const NUM_SHUTDOWN_RECEIVERS: u64 = 30;
 
 /// Maximum duration to fetch certificates from local storage.
 const FETCH_CERTIFICATES_MAX_HANDLER_TIME: Duration = Duration::from_secs(10);
@@ -88,6 +96,154 @@ pub enum NetworkModel {
     Asynchronous,
 }
 
+/// The anemo network of a primary. It is bound before the primary is spawned on it, so that a
+/// primary with an empty store can sync a consensus snapshot from its peers first. Until then, the
+/// requests of the peers find no route.
+#[derive(Clone)]
+pub struct PrimaryNetwork {
+    network: Network,
+    routes: Arc<OnceCell<anemo::Router>>,
+}
+
+impl PrimaryNetwork {
+    /// Binds the network on the primary address of `authority`.
+    #[cfg_attr(not(feature = "fault-injection"), allow(unused_variables))]
+    pub fn bind(
+        authority: &Authority,
+        network_signer: NetworkKeyPair,
+        committee: &Committee,
+        client: &NetworkClient,
+    ) -> Self {
+        #[cfg(feature = "fault-injection")]
+        let own_peer_id = PeerId(network_signer.public().0.to_bytes());
+        let address = authority.primary_address();
+        let address = address
+            .replace(0, |_protocol| Some(Protocol::Ip4(Ipv4Addr::UNSPECIFIED)))
+            .unwrap();
+        let addr = address.to_anemo_address().unwrap();
+
+        let epoch_string: String = committee.epoch().to_string();
+        let routes = Arc::new(OnceCell::<anemo::Router>::new());
+        let spawned_routes = routes.clone();
+
+        let service = ServiceBuilder::new().layer(
+            TraceLayer::new_for_server_errors()
+                .make_span_with(DefaultMakeSpan::new().level(tracing::Level::INFO))
+                .on_failure(DefaultOnFailure::new().level(tracing::Level::WARN)),
+        );
+        #[cfg(feature = "fault-injection")]
+        let service = service.layer(FaultInjectionLayer::new(
+            own_peer_id,
+            Direction::Inbound,
+            client.fault_injector(),
+        ));
+        // No route is found until the primary is spawned on the network.
+        let service = service
+            .layer(SetResponseHeaderLayer::overriding(
+                EPOCH_HEADER_KEY.parse().unwrap(),
+                epoch_string.clone(),
+            ))
+            .service_fn(move |request: anemo::Request<Bytes>| {
+                spawned_routes
+                    .get()
+                    .cloned()
+                    .unwrap_or_else(anemo::Router::new)
+                    .oneshot(request)
+            });
+
+        let outbound_layer = ServiceBuilder::new().layer(
+            TraceLayer::new_for_client_and_server_errors()
+                .make_span_with(DefaultMakeSpan::new().level(tracing::Level::INFO))
+                .on_failure(DefaultOnFailure::new().level(tracing::Level::WARN)),
+        );
+        #[cfg(feature = "fault-injection")]
+        let outbound_layer = outbound_layer.layer(FaultInjectionLayer::new(
+            own_peer_id,
+            Direction::Outbound,
+            client.fault_injector(),
+        ));
+        let outbound_layer = outbound_layer
+            .layer(SetRequestHeaderLayer::overriding(
+                EPOCH_HEADER_KEY.parse().unwrap(),
+                epoch_string,
+            ))
+            .into_inner();
+
+        let anemo_config = {
+            let mut quic_config = anemo::QuicConfig::default();
+            // Allow more concurrent streams for burst activity.
+            quic_config.max_concurrent_bidi_streams = Some(10_000);
+            // Increase send and receive buffer sizes on the primary, since the primary also
+            // needs to fetch payloads.
+            // With 200MiB buffer size and ~500ms RTT, the max throughput ~400MiB/s.
+            quic_config.stream_receive_window = Some(100 << 20);
+            quic_config.receive_window = Some(200 << 20);
+            quic_config.send_window = Some(200 << 20);
+            quic_config.crypto_buffer_size = Some(1 << 20);
+            // Enable keep alives every 5s
+            quic_config.keep_alive_interval_ms = Some(5_000);
+            let mut config = anemo::Config::default();
+            config.quic = Some(quic_config);
+            // Set the max_frame_size to be 2 GB to work around the issue of there being too many
+            // delegation events in the epoch change txn.
+            config.max_frame_size = Some(2 << 30);
+            // Set a default timeout of 300s for all RPC requests
+            config.inbound_request_timeout_ms = Some(300_000);
+            config.outbound_request_timeout_ms = Some(300_000);
+            config.shutdown_idle_timeout_ms = Some(1_000);
+            config.connectivity_check_interval_ms = Some(2_000);
+            config.connection_backoff_ms = Some(1_000);
+            config.max_connection_backoff_ms = Some(20_000);
+            config
+        };
+
+        let network;
+        let mut retries_left = 90;
+
+        loop {
+            let network_result = anemo::Network::bind(addr.clone())
+                .server_name("narwhal")
+                .private_key(network_signer.copy().private().to_bytes())
+                .config(anemo_config.clone())
+                .outbound_request_layer(outbound_layer.clone())
+                .start(service.clone());
+            match network_result {
+                Ok(n) => {
+                    network = n;
+                    break;
+                }
+                Err(e) => {
+                    retries_left -= 1;
+
+                    if retries_left <= 0 {
+                        panic!("Failed to initialize Network!");
+                    }
+                    error!(
+                        "Address {} should be available for the primary Narwhal service, retrying in one second (err: {e:?})",
+                        addr
+                    );
+                    sleep(Duration::from_secs(1));
+                }
+            }
+        }
+        info!("Primary {} listening on {}", authority.id(), address);
+
+        Self { network, routes }
+    }
+
+    pub fn network(&self) -> &Network {
+        &self.network
+    }
+
+    /// Serves `routes` to the peers.
+    fn serve(self, routes: anemo::Router) -> Network {
+        if self.routes.set(routes).is_err() {
+            panic!("A primary is already spawned on the network!");
+        }
+        self.network
+    }
+}
+
 pub struct Primary;
 
 impl Primary {
@@ -96,7 +252,7 @@ impl Primary {
     pub fn spawn(
         authority: Authority,
         signer: KeyPair,
-        network_signer: NetworkKeyPair,
+        network: PrimaryNetwork,
         committee: Committee,
         worker_cache: WorkerCache,
         parameters: Parameters,
@@ -106,6 +262,8 @@ impl Primary {
         proposer_store: ProposerStore,
         payload_store: PayloadStore,
         vote_digest_store: VoteDigestStore,
+        consensus_store: Arc<ConsensusStore>,
+        execution_store: ExecutionStore,
         tx_new_certificates: Sender<Certificate>,
         rx_committed_certificates: Receiver<(Round, Vec<Certificate>)>,
         rx_consensus_round_updates: watch::Receiver<ConsensusRound>,
@@ -119,20 +277,32 @@ impl Primary {
         parameters.tracing();
 
         // Some info statements
-        let own_peer_id = PeerId(network_signer.public().0.to_bytes());
+        let own_peer_id = network.network().peer_id();
         info!(
             "Boot primary node with peer id {} and public key {}",
             own_peer_id,
             authority.protocol_key().encode_base64(),
         );
 
+        // Drop the headers committed just before a crash, which the proposer did not hear of, so
+        // that their batches are not included again.
+        let committed_rounds = proposer_store
+            .remove_committed_headers(&certificate_store, &consensus_store)
+            .expect("Failed to drop the committed headers from the proposer store");
+        if !committed_rounds.is_empty() {
+            info!(
+                "Dropped the proposed headers committed before a restart at rounds {:?}",
+                committed_rounds
+            );
+        }
+
         let (tx_our_digests, rx_our_digests) = channel(CHANNEL_CAPACITY);
         let (tx_parents, rx_parents) = channel(CHANNEL_CAPACITY);
         let (tx_headers, rx_headers) = channel(CHANNEL_CAPACITY);
         let (tx_certificate_fetcher, rx_certificate_fetcher) = channel(CHANNEL_CAPACITY);
         let (tx_block_synchronizer_commands, rx_block_synchronizer_commands) =
             channel(CHANNEL_CAPACITY);
-        let (tx_committed_own_headers, rx_committed_own_headers) = channel(CHANNEL_CAPACITY);
+        let (tx_committed_headers, rx_committed_headers) = channel(CHANNEL_CAPACITY);
 
         let (tx_narwhal_round_updates, rx_narwhal_round_updates) = watch::channel(0u64);
         let (tx_synchronizer_network, rx_synchronizer_network) = oneshot::channel();
@@ -143,6 +313,7 @@ impl Primary {
             worker_cache.clone(),
             parameters.gc_depth,
             client.clone(),
+            header_store.clone(),
             certificate_store.clone(),
             payload_store.clone(),
             tx_certificate_fetcher,
@@ -156,11 +327,6 @@ impl Primary {
 
         let signature_service = SignatureService::new(*signer.private());
 
-        // Spawn the network receiver listening to messages from the other primaries.
-        let address = authority.primary_address();
-        let address = address
-            .replace(0, |_protocol| Some(Protocol::Ip4(Ipv4Addr::UNSPECIFIED)))
-            .unwrap();
         let mut primary_service = PrimaryToPrimaryServer::new(PrimaryReceiverHandler {
             authority_id: authority.id(),
             committee: committee.clone(),
@@ -171,6 +337,9 @@ impl Primary {
             certificate_store: certificate_store.clone(),
             payload_store: payload_store.clone(),
             vote_digest_store,
+            consensus_store,
+            execution_store,
+            gc_depth: parameters.gc_depth,
             rx_narwhal_round_updates,
             genesis_certs: genesis_certs.clone(),
         })
@@ -183,6 +352,10 @@ impl Primary {
         // These are already a batch request; an individual peer should never need more than one.
         .add_layer_for_fetch_certificates(InboundRequestLayer::new(
             inflight_limit::InflightLimitLayer::new(1, inflight_limit::WaitMode::ReturnError),
+        ))
+        // Snapshots are only requested by primaries starting with an empty store.
+        .add_layer_for_fetch_consensus_snapshot(InboundRequestLayer::new(
+            inflight_limit::InflightLimitLayer::new(1, inflight_limit::WaitMode::ReturnError),
         ));
 
         // Apply other rate limits from configuration as needed.
@@ -220,8 +393,6 @@ impl Primary {
 
         let worker_service = WorkerToPrimaryServer::new(worker_receiver_handler);
 
-        let addr = address.to_anemo_address().unwrap();
-
         let epoch_string: String = committee.epoch().to_string();
 
         let our_worker_peer_ids = worker_cache
@@ -246,95 +417,11 @@ impl Primary {
             )))
             .merge(worker_to_primary_router);
 
-        let service = ServiceBuilder::new()
-            .layer(
-                TraceLayer::new_for_server_errors()
-                    .make_span_with(DefaultMakeSpan::new().level(tracing::Level::INFO))
-                    .on_failure(DefaultOnFailure::new().level(tracing::Level::WARN)),
-            )
-            .layer(CallbackLayer::new(FailpointsMakeCallbackHandler::new()))
-            .layer(SetResponseHeaderLayer::overriding(
-                EPOCH_HEADER_KEY.parse().unwrap(),
-                epoch_string.clone(),
-            ))
-            .service(routes);
-
-        let outbound_layer = ServiceBuilder::new()
-            .layer(
-                TraceLayer::new_for_client_and_server_errors()
-                    .make_span_with(DefaultMakeSpan::new().level(tracing::Level::INFO))
-                    .on_failure(DefaultOnFailure::new().level(tracing::Level::WARN)),
-            )
-            .layer(CallbackLayer::new(FailpointsMakeCallbackHandler::new()))
-            .layer(SetRequestHeaderLayer::overriding(
-                EPOCH_HEADER_KEY.parse().unwrap(),
-                epoch_string,
-            ))
-            .into_inner();
-
-        let anemo_config = {
-            let mut quic_config = anemo::QuicConfig::default();
-            // Allow more concurrent streams for burst activity.
-            quic_config.max_concurrent_bidi_streams = Some(10_000);
-            // Increase send and receive buffer sizes on the primary, since the primary also
-            // needs to fetch payloads.
-            // With 200MiB buffer size and ~500ms RTT, the max throughput ~400MiB/s.
-            quic_config.stream_receive_window = Some(100 << 20);
-            quic_config.receive_window = Some(200 << 20);
-            quic_config.send_window = Some(200 << 20);
-            quic_config.crypto_buffer_size = Some(1 << 20);
-            // Enable keep alives every 5s
-            quic_config.keep_alive_interval_ms = Some(5_000);
-            let mut config = anemo::Config::default();
-            config.quic = Some(quic_config);
-            // Set the max_frame_size to be 2 GB to work around the issue of there being too many
-            // delegation events in the epoch change txn.
-            config.max_frame_size = Some(2 << 30);
-            // Set a default timeout of 300s for all RPC requests
-            config.inbound_request_timeout_ms = Some(300_000);
-            config.outbound_request_timeout_ms = Some(300_000);
-            config.shutdown_idle_timeout_ms = Some(1_000);
-            config.connectivity_check_interval_ms = Some(2_000);
-            config.connection_backoff_ms = Some(1_000);
-            config.max_connection_backoff_ms = Some(20_000);
-            config
-        };
-
-        let network;
-        let mut retries_left = 90;
-
-        loop {
-            let network_result = anemo::Network::bind(addr.clone())
-                .server_name("narwhal")
-                .private_key(network_signer.copy().private().to_bytes())
-                .config(anemo_config.clone())
-                .outbound_request_layer(outbound_layer.clone())
-                .start(service.clone());
-            match network_result {
-                Ok(n) => {
-                    network = n;
-                    break;
-                }
-                Err(e) => {
-                    retries_left -= 1;
-
-                    if retries_left <= 0 {
-                        panic!("Failed to initialize Network!");
-                    }
-                    error!(
-                        "Address {} should be available for the primary Narwhal service, retrying in one second (err: {e:?})",
-                        addr
-                    );
-                    sleep(Duration::from_secs(1));
-                }
-            }
-        }
+        let network = network.serve(routes);
         if tx_synchronizer_network.send(network.clone()).is_err() {
             panic!("Failed to send Network to Synchronizer!");
         }
 
-        info!("Primary {} listening on {}", authority.id(), address);
-
         let mut peer_types = HashMap::new();
 
         // Add my workers
@@ -388,6 +475,7 @@ impl Primary {
                 .primary_network_admin_server_port
         );
 
+        #[cfg(not(feature = "fault-injection"))]
         let admin_handles = network::admin::start_admin_server(
             parameters
                 .network_admin_server
@@ -395,6 +483,15 @@ impl Primary {
             network.clone(),
             tx_shutdown.subscribe(),
         );
+        #[cfg(feature = "fault-injection")]
+        let admin_handles = network::admin::start_admin_server_with_faults(
+            parameters
+                .network_admin_server
+                .primary_network_admin_server_port,
+            network.clone(),
+            client.fault_injector(),
+            tx_shutdown.subscribe(),
+        );
 
         let core_handle = Certifier::spawn(
             authority.id(),
@@ -402,7 +499,7 @@ impl Primary {
             header_store.clone(),
             certificate_store.clone(),
             synchronizer.clone(),
-            signature_service,
+            signature_service.clone(),
             tx_shutdown.subscribe(),
             rx_headers,
             network.clone(),
@@ -415,7 +512,7 @@ impl Primary {
             committee.clone(),
             network.clone(),
             certificate_store.clone(),
-            rx_consensus_round_updates,
+            rx_consensus_round_updates.clone(),
             tx_shutdown.subscribe(),
             rx_certificate_fetcher,
             synchronizer.clone(),
@@ -427,19 +524,23 @@ impl Primary {
         let proposer_handle = Proposer::spawn(
             authority.id(),
             committee.clone(),
-            proposer_store,
+            proposer_store.clone(),
+            signature_service,
             parameters.header_num_of_batches_threshold,
             parameters.max_header_num_of_batches,
+            parameters.max_header_num_of_weak_links,
+            parameters.gc_depth,
             parameters.max_header_delay,
             parameters.min_header_delay,
             None,
+            parameters.adaptive_header.clone(),
             network_model,
             tx_shutdown.subscribe(),
             rx_parents,
             rx_our_digests,
             tx_headers,
             tx_narwhal_round_updates,
-            rx_committed_own_headers,
+            rx_committed_headers,
             genesis_certs.clone(),
         );
 
@@ -506,7 +607,7 @@ impl Primary {
             let block_remover = BlockRemover::new(
                 authority.id(),
                 committee.clone(),
-                worker_cache,
+                worker_cache.clone(),
                 certificate_store,
                 header_store,
                 payload_store,
@@ -537,7 +638,18 @@ impl Primary {
             authority.id(),
             rx_committed_certificates,
             tx_shutdown.subscribe(),
-            Some(tx_committed_own_headers),
+            Some(tx_committed_headers),
+            rx_consensus_round_updates,
+            client,
+            worker_cache
+                .workers
+                .get(authority.protocol_key())
+                .expect("Our public key is not in the worker cache")
+                .0
+                .iter()
+                .map(|(worker_id, worker)| (*worker_id, worker.name.clone()))
+                .collect(),
+            proposer_store,
             network,
         );
         handles.push(state_handler_handle);
@@ -585,6 +697,12 @@ struct PrimaryReceiverHandler {
     payload_store: PayloadStore,
     /// The store to persist the last voted round per authority, used to ensure idempotence.
     vote_digest_store: VoteDigestStore,
+    /// The consensus state served to the primaries syncing a snapshot.
+    consensus_store: Arc<ConsensusStore>,
+    /// The committee changes served to the primaries syncing a snapshot.
+    execution_store: ExecutionStore,
+    /// The depth of the garbage collection.
+    gc_depth: Round,
     /// Get a signal when the round changes.
     rx_narwhal_round_updates: watch::Receiver<Round>,
     #[allow(dead_code)]
@@ -613,6 +731,139 @@ impl PrimaryReceiverHandler {
         Ok(None)
     }
 
+    /// Reads the digests of our recent sub dags, for a primary with an empty store to agree on a
+    /// snapshot with its peers. They go back to the last sub dag at or below the GC round of our
+    /// GC round, so that they cover the snapshot of any sub dag with a leader above our GC round.
+    fn read_sub_dag_digests(&self) -> Result<Vec<SubDagDigest>, anemo::rpc::Status> {
+        let latest = match self.consensus_store.get_latest_sub_dag() {
+            Some(latest) => latest,
+            None => return Ok(Vec::new()),
+        };
+        let gc_round = gc_round(
+            gc_round(latest.leader_round(), self.gc_depth),
+            self.gc_depth,
+        );
+        let mut sub_dags = self
+            .consensus_store
+            .read_committed_sub_dags_after_round(gc_round)
+            .map_err(|e| anemo::rpc::Status::from_error(Box::new(e)))?;
+        let oldest = sub_dags
+            .last()
+            .map_or(latest.sub_dag_index(), |s| s.sub_dag_index());
+        if let Some(sub_dag) = oldest
+            .checked_sub(1)
+            .map(|index| self.consensus_store.read_committed_sub_dag(&index))
+            .transpose()
+            .map_err(|e| anemo::rpc::Status::from_error(Box::new(e)))?
+            .flatten()
+        {
+            sub_dags.push(sub_dag);
+        }
+        sub_dags.reverse();
+
+        Ok(sub_dags
+            .iter()
+            .map(|sub_dag| SubDagDigest {
+                sub_dag_index: sub_dag.sub_dag_index(),
+                leader_round: sub_dag.leader_round(),
+                digest: sub_dag.digest(),
+            })
+            .collect())
+    }
+
+    /// Reads the sub dags and the certificates a primary with an empty store needs to resume from
+    /// the sub dag `sub_dag_index`, with at most `max_items` certificates.
+    fn read_consensus_snapshot(
+        &self,
+        sub_dag_index: SequenceNumber,
+        max_items: usize,
+    ) -> Result<FetchConsensusSnapshotResponse, anemo::rpc::Status> {
+        let latest = self
+            .consensus_store
+            .read_committed_sub_dag(&sub_dag_index)
+            .map_err(|e| anemo::rpc::Status::from_error(Box::new(e)))?
+            .ok_or_else(|| anemo::rpc::Status::not_found("Unknown sub dag"))?;
+        let gc_round = gc_round(latest.leader_round(), self.gc_depth);
+        // The sub dags committed after the requested one have a higher leader round, and are
+        // skipped.
+        let mut sub_dags = self
+            .consensus_store
+            .read_committed_sub_dags_after_round(gc_round)
+            .map_err(|e| anemo::rpc::Status::from_error(Box::new(e)))?
+            .into_iter()
+            .filter(|sub_dag| sub_dag.sub_dag_index() <= sub_dag_index)
+            .collect::<Vec<_>>();
+        sub_dags.reverse();
+
+        // The certificates of the sub dags are needed to recover consensus, even those at or
+        // below the GC round.
+        let sub_dag_certificates = sub_dags
+            .iter()
+            .flat_map(|sub_dag| sub_dag.certificates())
+            .collect::<HashSet<_>>();
+        let mut certificates = Vec::new();
+        for certificate in self
+            .certificate_store
+            .read_all(sub_dag_certificates.iter().cloned())
+            .map_err(|e| anemo::rpc::Status::from_error(Box::new(e)))?
+        {
+            let certificate = certificate.ok_or_else(|| {
+                anemo::rpc::Status::internal("A certificate of a recent sub dag is missing")
+            })?;
+            if certificate.round() <= gc_round {
+                certificates.push(certificate);
+            }
+        }
+        certificates.sort_by_key(|c| c.round());
+        certificates.extend(
+            self.certificate_store
+                .after_round(gc_round + 1)
+                .map_err(|e| anemo::rpc::Status::from_error(Box::new(e)))?,
+        );
+        certificates.truncate(max_items.min(MAX_SNAPSHOT_CERTIFICATES));
+
+        let sent = certificates
+            .iter()
+            .filter(|certificate| sub_dag_certificates.contains(&certificate.digest()))
+            .count();
+        if sent < sub_dag_certificates.len() {
+            return Err(anemo::rpc::Status::internal(
+                "The certificates of the sub dags exceed the size of a snapshot",
+            ));
+        }
+
+        // The committee changes are found as the batches are fetched, so they are only all known
+        // once the requested sub dag is executed.
+        let last_executed = self
+            .execution_store
+            .read_last_executed()
+            .map_err(|e| anemo::rpc::Status::from_error(Box::new(e)))?;
+        let executed = last_executed.map_or(false, |last_executed| {
+            last_executed.sub_dag_index > sub_dag_index
+                || (last_executed.sub_dag_index == sub_dag_index
+                    && last_executed.is_end_of_sub_dag())
+        });
+        if !executed {
+            return Err(anemo::rpc::Status::internal(
+                "The requested sub dag is not executed yet",
+            ));
+        }
+        let committee_changes = self
+            .execution_store
+            .read_committee_changes()
+            .map_err(|e| anemo::rpc::Status::from_error(Box::new(e)))?
+            .into_iter()
+            .filter(|(index, _)| *index <= sub_dag_index)
+            .collect();
+
+        Ok(FetchConsensusSnapshotResponse {
+            sub_dag_digests: Vec::new(),
+            sub_dags,
+            certificates,
+            committee_changes,
+        })
+    }
+
     #[allow(clippy::mutable_key_type)]
     async fn process_request_vote(
         &self,
@@ -697,11 +948,13 @@ impl PrimaryReceiverHandler {
             }
         }
 
-        // Ensure we have the parents. If any are missing, the requester should provide them on retry.
+        // Ensure we have the parents and weak links. If any are missing, the requester should provide them on retry.
         // This check is necessary for correctness, because it is possible that the list of missing
         // parents in the request is incomplete, or wait_notifications get notified on shut down
         // without actually having the parents available.
-        let (parents, missing) = self.synchronizer.get_parents(header)?;
+        let (parents, mut missing) = self.synchronizer.get_parents(header)?;
+        let (weak_links, missing_weak_links) = self.synchronizer.get_weak_links(header)?;
+        missing.extend(missing_weak_links);
         if !missing.is_empty() {
             return Ok(RequestVoteResponse {
                 vote: None,
@@ -743,6 +996,14 @@ impl PrimaryReceiverHandler {
             DagError::HeaderRequiresQuorum(header.digest())
         );
 
+        // Check the weakly linked certificates are from the round they are linked with.
+        for weak_link in weak_links.iter() {
+            ensure!(
+                header.weak_links().get(&weak_link.digest()) == Some(&weak_link.round()),
+                DagError::HeaderHasInvalidWeakLinks(header.digest())
+            );
+        }
+
         // Synchronize all batches referenced in the header.
         self.synchronizer
             .sync_header_batches(header, /* max_age */ 0)
@@ -1002,6 +1263,31 @@ impl PrimaryToPrimary for PrimaryReceiverHandler {
         Ok(anemo::Response::new(response))
     }
 
+    #[instrument(level = "debug", skip_all, peer = ?request.peer_id())]
+    async fn fetch_consensus_snapshot(
+        &self,
+        request: anemo::Request<FetchConsensusSnapshotRequest>,
+    ) -> Result<anemo::Response<FetchConsensusSnapshotResponse>, anemo::rpc::Status> {
+        let FetchConsensusSnapshotRequest {
+            sub_dag_index,
+            max_items,
+        } = request.body().clone();
+        let response = match sub_dag_index {
+            Some(sub_dag_index) => self.read_consensus_snapshot(sub_dag_index, max_items)?,
+            None => FetchConsensusSnapshotResponse {
+                sub_dag_digests: self.read_sub_dag_digests()?,
+                ..Default::default()
+            },
+        };
+        debug!(
+            "Sending a consensus snapshot with {} sub dags and {} certificates to peer {:?}",
+            response.sub_dags.len(),
+            response.certificates.len(),
+            request.peer_id(),
+        );
+        Ok(anemo::Response::new(response))
+    }
+
     async fn get_payload_availability(
         &self,
         request: anemo::Request<PayloadAvailabilityRequest>,
@@ -1,28 +1,29 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 // Copyright (c) Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
-use crate::NetworkModel;
-use config::{AuthorityIdentifier, Committee, Epoch, WorkerId};
-use crypto::Hash as _;
+use crate::{header_sizer::HeaderSizer, leader_timeout::LeaderTimeout, NetworkModel};
+use config::{AdaptiveHeaderParameters, AuthorityIdentifier, Committee, Epoch, WorkerId};
+use crypto::{Hash as _, SignatureService};
 use std::cmp::Ordering;
-use std::collections::{BTreeMap, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use storage::ProposerStore;
 use tokio::sync::mpsc::{Receiver, Sender};
 use tokio::time::{sleep_until, Instant};
 use tokio::{
     sync::{oneshot, watch},
-    task::JoinHandle,
+    task::{spawn_blocking, JoinHandle},
     time::{sleep, Duration},
 };
 use tracing::{debug, enabled, error, info, trace};
 use types::{
     error::{DagError, DagResult},
-    BatchDigest, Certificate, CertificateAPI, Header, HeaderAPI, Round, TimestampMs,
+    BatchDigest, Certificate, CertificateAPI, CertificateDigest, Header, HeaderAPI, Round,
+    TimestampMs, MAX_HEADER_NUM_OF_WEAK_LINKS,
 };
 use types::{now, ConditionalBroadcastReceiver};
 
 #[cfg(feature = "metrics")]
-use snarkos_metrics::gauge;
+use snarkos_metrics::{gauge, increment_counter};
 
 /// Messages sent to the proposer about our own batch digests
 #[derive(Debug)]
@@ -40,23 +41,33 @@ pub mod proposer_tests;
 
 const DEFAULT_HEADER_RESEND_TIMEOUT: Duration = Duration::from_secs(60);
 
+#[cfg(feature = "metrics")]
+const COMMIT_LATENCY_MS: &str = "narwhal_primary_proposer_commit_latency_ms";
+#[cfg(feature = "metrics")]
+const LEADER_TIMEOUT_MS: &str = "narwhal_primary_proposer_leader_timeout_ms";
+#[cfg(feature = "metrics")]
+const LEADER_TIMEOUTS: &str = "narwhal_primary_proposer_leader_timeouts";
+
 /// The proposer creates new headers and send them to the core for broadcasting and further processing.
 pub struct Proposer {
     /// The id of this primary.
     authority_id: AuthorityIdentifier,
     /// The committee information.
     committee: Committee,
-    /// The threshold number of batches that can trigger
-    /// a header creation. When there are available at least
-    /// `header_num_of_batches_threshold` batches we are ok
-    /// to try and propose a header
-    header_num_of_batches_threshold: usize,
     /// The maximum number of batches in header.
     max_header_num_of_batches: usize,
-    /// The maximum delay to wait for conditions like having leader in parents.
-    max_header_delay: Duration,
-    /// The minimum delay between generating headers.
-    min_header_delay: Duration,
+    /// The maximum number of weak links in header, capped by `MAX_HEADER_NUM_OF_WEAK_LINKS`.
+    /// Zero disables weak links.
+    max_header_num_of_weak_links: usize,
+    /// The depth of the garbage collection, certificates older than that are not weakly linked.
+    gc_depth: Round,
+    /// Decides the threshold number of batches that can trigger a header creation, the
+    /// maximum delay to wait for conditions like having leader in parents and the minimum
+    /// delay between generating headers.
+    header_sizer: HeaderSizer,
+    /// Decides how long to wait for the leader or its votes. This is only relevant in
+    /// partial synchrony.
+    leader_timeout: LeaderTimeout,
     /// The delay to wait until resending the last proposed header if proposer
     /// hasn't proposed anything new since then. If None is provided then the
     /// default value will be used instead.
@@ -73,8 +84,10 @@ pub struct Proposer {
     /// Sends newly created headers to the `Certifier`.
     tx_headers: Sender<Header>,
 
-    /// The proposer store for persisting the last header.
+    /// The proposer store for persisting the last header and the history of proposed headers.
     proposer_store: ProposerStore,
+    /// Service to sign the randomness share of our headers.
+    signature_service: SignatureService,
     /// The current round of the dag.
     round: Round,
     /// Last time the round has been updated
@@ -88,13 +101,19 @@ pub struct Proposer {
     /// Holds the batches' digests waiting to be included in the next header.
     /// Digests are roughly oldest to newest, and popped in FIFO order from the front.
     digests: VecDeque<OurDigestMessage>,
+    /// Holds the certificates, by round, that we have not seen committed, and that none of our
+    /// headers pending commit weakly links.
+    uncommitted: BTreeMap<Round, BTreeSet<CertificateDigest>>,
+    /// The round of the last leader committed by consensus. The certificates at or below it
+    /// which are not committed were not reached by any committed leader.
+    committed_round: Round,
 
     /// Holds the map of proposed previous round headers and their digest messages, to ensure that
     /// all batches' digest included will eventually be re-sent.
     proposed_headers: BTreeMap<Round, (Header, VecDeque<OurDigestMessage>)>,
-    /// Committed headers channel on which we get updates on which of
-    /// our own headers have been committed.
-    rx_committed_own_headers: Receiver<(Round, Vec<Round>)>,
+    /// Receives the certificates committed by consensus, along with the round of their leader,
+    /// to learn which of our own headers and of the uncommitted certificates are committed.
+    rx_committed_headers: Receiver<(Round, Vec<Certificate>)>,
 
     #[allow(dead_code)]
     genesis_certs: Vec<Certificate>,
@@ -107,28 +126,40 @@ impl Proposer {
         authority_id: AuthorityIdentifier,
         committee: Committee,
         proposer_store: ProposerStore,
+        signature_service: SignatureService,
         header_num_of_batches_threshold: usize,
         max_header_num_of_batches: usize,
+        max_header_num_of_weak_links: usize,
+        gc_depth: Round,
         max_header_delay: Duration,
         min_header_delay: Duration,
         header_resend_timeout: Option<Duration>,
+        adaptive_header: AdaptiveHeaderParameters,
         network_model: NetworkModel,
         rx_shutdown: ConditionalBroadcastReceiver,
         rx_parents: Receiver<(Vec<Certificate>, Round, Epoch)>,
         rx_our_digests: Receiver<OurDigestMessage>,
         tx_headers: Sender<Header>,
         tx_narwhal_round_updates: watch::Sender<Round>,
-        rx_committed_own_headers: Receiver<(Round, Vec<Round>)>,
+        rx_committed_headers: Receiver<(Round, Vec<Certificate>)>,
         genesis_certs: Vec<Certificate>,
     ) -> JoinHandle<()> {
         tokio::spawn(async move {
             Self {
                 authority_id,
                 committee,
-                header_num_of_batches_threshold,
                 max_header_num_of_batches,
-                max_header_delay,
-                min_header_delay,
+                max_header_num_of_weak_links: max_header_num_of_weak_links
+                    .min(MAX_HEADER_NUM_OF_WEAK_LINKS),
+                gc_depth,
+                header_sizer: HeaderSizer::new(
+                    adaptive_header,
+                    header_num_of_batches_threshold,
+                    max_header_num_of_batches,
+                    min_header_delay,
+                    max_header_delay,
+                ),
+                leader_timeout: LeaderTimeout::default(),
                 header_resend_timeout,
                 network_model,
                 rx_shutdown,
@@ -137,13 +168,16 @@ impl Proposer {
                 tx_headers,
                 tx_narwhal_round_updates,
                 proposer_store,
+                signature_service,
                 round: 0,
                 last_round_timestamp: None,
                 last_parents: genesis_certs.clone(),
                 last_leader: None,
                 digests: VecDeque::with_capacity(2 * max_header_num_of_batches),
+                uncommitted: BTreeMap::new(),
+                committed_round: 0,
                 proposed_headers: BTreeMap::new(),
-                rx_committed_own_headers,
+                rx_committed_headers,
                 genesis_certs,
             }
             .run()
@@ -159,7 +193,8 @@ impl Proposer {
         let header = self.create_new_header().await?;
 
         // Store the last header.
-        self.proposer_store.write_last_proposed(&header)?;
+        self.proposer_store
+            .write_last_proposed(&header, self.gc_depth)?;
 
         #[cfg(feature = "benchmark")]
         for digest in header.payload().keys() {
@@ -199,6 +234,7 @@ impl Proposer {
         let num_of_digests = self.digests.len().min(self.max_header_num_of_batches);
         let header_digests: VecDeque<_> = self.digests.drain(..num_of_digests).collect();
         let parents: Vec<_> = self.last_parents.drain(..).collect();
+        let weak_links = self.take_weak_links(this_round);
 
         // Here we check that the timestamp we will include in the header is consistent with the
         // parents, ie our current time is *after* the timestamp in all the included headers. If
@@ -228,6 +264,8 @@ impl Proposer {
                 .map(|m| (m.digest, (m.worker_id, m.timestamp)))
                 .collect(),
             parents.iter().map(|x| x.digest()).collect(),
+            weak_links,
+            &self.signature_service,
         )
         .await;
 
@@ -288,7 +326,7 @@ impl Proposer {
                     total_inclusion_secs / header_digests.len() as f64,
                 )
             } else {
-                (self.max_header_delay.as_secs_f64(), 0.0)
+                (self.header_sizer.max_header_delay().as_secs_f64(), 0.0)
             };
         debug!(
             "Header {:?} was created in {} seconds. Contains {} batches, with average delay {} seconds.",
@@ -306,6 +344,118 @@ impl Proposer {
         Ok(header)
     }
 
+    /// Recovers the batch digests acknowledged to our workers before a restart which were not
+    /// included in a header, so that they are included in the next ones.
+    fn recover_pending_digests(&mut self) -> DagResult<()> {
+        for (digest, worker_id, timestamp) in self.proposer_store.read_pending_digests()? {
+            self.digests.push_back(OurDigestMessage {
+                digest,
+                worker_id,
+                timestamp,
+                ack_channel: None,
+            });
+        }
+
+        if !self.digests.is_empty() {
+            info!(
+                "Proposer recovered {} pending batch digests",
+                self.digests.len()
+            );
+        }
+        Ok(())
+    }
+
+    /// Recovers the headers proposed before a restart which were not committed, so that their
+    /// batches are included again if they do not get committed. Returns the last one, to be
+    /// re-broadcast.
+    fn recover_proposed_headers(&mut self) -> DagResult<Option<Header>> {
+        let epoch = self.committee.epoch();
+        for header in self.proposer_store.read_proposed_headers()? {
+            if header.epoch() != epoch {
+                continue;
+            }
+            let digests = header
+                .payload()
+                .iter()
+                .map(|(digest, (worker_id, timestamp))| OurDigestMessage {
+                    digest: *digest,
+                    worker_id: *worker_id,
+                    timestamp: *timestamp,
+                    ack_channel: None,
+                })
+                .collect();
+            self.proposed_headers
+                .insert(header.round(), (header, digests));
+        }
+
+        if !self.proposed_headers.is_empty() {
+            info!(
+                "Proposer recovered {} uncommitted headers at rounds {:?}",
+                self.proposed_headers.len(),
+                self.proposed_headers.keys().collect::<Vec<_>>()
+            );
+        }
+        Ok(self
+            .proposed_headers
+            .last_key_value()
+            .map(|(_, (header, _))| header.clone()))
+    }
+
+    /// Records the given certificates as uncommitted, until consensus commits them.
+    fn track_uncommitted(&mut self, certificates: &[Certificate]) {
+        if self.max_header_num_of_weak_links == 0 {
+            return;
+        }
+        for certificate in certificates {
+            self.uncommitted
+                .entry(certificate.round())
+                .or_default()
+                .insert(certificate.digest());
+        }
+    }
+
+    /// Forgets the given certificates, committed by consensus with a leader of the given round.
+    fn track_committed(&mut self, commit_round: Round, certificates: &[Certificate]) {
+        self.committed_round = self.committed_round.max(commit_round);
+        for certificate in certificates {
+            if let Some(uncommitted) = self.uncommitted.get_mut(&certificate.round()) {
+                uncommitted.remove(&certificate.digest());
+            }
+        }
+    }
+
+    /// Picks the weak links of a header of the given round, oldest first, among the certificates
+    /// which were not reached by a committed leader. Certificates above the last committed round
+    /// may still be committed by the next leaders, and the ones of the two rounds before the
+    /// header's are either our parents or may still be referenced by them.
+    fn take_weak_links(&mut self, round: Round) -> BTreeMap<CertificateDigest, Round> {
+        let gc_round = round.saturating_sub(self.gc_depth);
+        self.uncommitted = self.uncommitted.split_off(&(gc_round + 1));
+
+        let mut weak_links = BTreeMap::new();
+        for (certificate_round, uncommitted) in self.uncommitted.iter_mut() {
+            if *certificate_round > self.committed_round || certificate_round + 2 >= round {
+                break;
+            }
+            while weak_links.len() < self.max_header_num_of_weak_links {
+                let Some(digest) = uncommitted.pop_first() else {
+                    break;
+                };
+                weak_links.insert(digest, *certificate_round);
+            }
+        }
+        self.uncommitted
+            .retain(|_, uncommitted| !uncommitted.is_empty());
+
+        if !weak_links.is_empty() {
+            debug!(
+                "Weakly linking {} uncommitted certificates at round {round}",
+                weak_links.len()
+            );
+        }
+        weak_links
+    }
+
     fn max_delay(&self) -> Duration {
         match self.network_model {
             // In partial synchrony, if this node is going to be the leader of the next
@@ -314,11 +464,11 @@ impl Proposer {
             NetworkModel::PartiallySynchronous
                 if self.committee.leader(self.round + 1).id() == self.authority_id =>
             {
-                self.max_header_delay / 2
+                self.header_sizer.max_header_delay() / 2
             }
 
             // Otherwise we keep the default timeout value.
-            _ => self.max_header_delay,
+            _ => self.header_sizer.max_header_delay(),
         }
     }
 
@@ -335,10 +485,27 @@ impl Proposer {
             }
 
             // Otherwise we keep the default timeout value.
-            _ => self.min_header_delay,
+            _ => self.header_sizer.min_header_delay(),
         }
     }
 
+    /// The time to wait for the leader certificate on even rounds, or for enough votes on the
+    /// leader on odd rounds, before advancing anyway. This is only relevant in partial synchrony.
+    fn leader_delay(&self) -> Duration {
+        let leader_delay = match self.network_model {
+            NetworkModel::PartiallySynchronous => self
+                .leader_timeout
+                .timeout(self.min_delay(), self.max_delay()),
+            // In asynchrony we never wait for the leader.
+            NetworkModel::Asynchronous => self.max_delay(),
+        };
+
+        #[cfg(feature = "metrics")]
+        gauge!(LEADER_TIMEOUT_MS, leader_delay.as_millis() as f64);
+
+        leader_delay
+    }
+
     /// Update the last leader certificate. This is only relevant in partial synchrony.
     fn update_leader(&mut self) -> bool {
         let leader = self.committee.leader(self.round);
@@ -364,8 +531,9 @@ impl Proposer {
         }
 
         let leader = match &self.last_leader {
-            Some(x) => x.digest(),
-            None => return true,
+            Some(x) if x.round() + 1 == self.round => x.digest(),
+            // We don't know the leader of the previous round, e.g. because we jumped ahead.
+            _ => return true,
         };
 
         let mut votes_for_leader = 0;
@@ -414,17 +582,31 @@ impl Proposer {
         let mut advance = true;
 
         let timer_start = Instant::now();
-        let max_delay_timer = sleep_until(timer_start + self.max_header_delay);
-        let min_delay_timer = sleep_until(timer_start + self.min_header_delay);
+        let max_delay_timer = sleep_until(timer_start + self.header_sizer.max_header_delay());
+        let min_delay_timer = sleep_until(timer_start + self.header_sizer.min_header_delay());
+        let leader_delay_timer = sleep_until(timer_start + self.header_sizer.max_header_delay());
 
         let header_resend_timeout = self
             .header_resend_timeout
             .unwrap_or(DEFAULT_HEADER_RESEND_TIMEOUT);
         let mut header_repeat_timer = Box::pin(sleep(header_resend_timeout));
-        let mut opt_latest_header = None;
+
+        self.recover_pending_digests()
+            .expect("Failed recovering the pending batch digests");
+        // Re-broadcast the last header proposed before a restart, in case it was not certified.
+        let mut opt_latest_header = self
+            .recover_proposed_headers()
+            .expect("Failed recovering the proposed headers");
+        if let Some(header) = &opt_latest_header {
+            debug!("Re-broadcasting recovered header {:?}", header);
+            if self.tx_headers.send(header.clone()).await.is_err() {
+                debug!("{}", DagError::ShuttingDown);
+            }
+        }
 
         tokio::pin!(max_delay_timer);
         tokio::pin!(min_delay_timer);
+        tokio::pin!(leader_delay_timer);
 
         info!(
             "Proposer on node {} has started successfully with header resend timeout {:?}.",
@@ -438,21 +620,32 @@ impl Proposer {
             // the leader or the leader has enough votes to enable a commit). The latter condition only matters
             // in partially synchrony. We guarantee that no more than max_header_num_of_batches are included in
             let enough_parents = !self.last_parents.is_empty();
-            let enough_digests = self.digests.len() >= self.header_num_of_batches_threshold;
+            let enough_digests =
+                self.digests.len() >= self.header_sizer.header_num_of_batches_threshold();
             let max_delay_timed_out = max_delay_timer.is_elapsed();
             let min_delay_timed_out = min_delay_timer.is_elapsed();
+            let leader_delay_timed_out = leader_delay_timer.is_elapsed();
 
-            if (max_delay_timed_out || ((enough_digests || min_delay_timed_out) && advance))
+            if (max_delay_timed_out
+                || ((enough_digests || min_delay_timed_out) && (advance || leader_delay_timed_out)))
                 && enough_parents
             {
-                if max_delay_timed_out
+                if (max_delay_timed_out || leader_delay_timed_out)
+                    && !advance
                     && matches!(self.network_model, NetworkModel::PartiallySynchronous)
                 {
                     // It is expected that this timer expires from time to time. If it expires too often, it
                     // either means some validators are Byzantine or that the network is experiencing periods
-                    // of asynchrony. In practice, the latter scenario means we misconfigured the parameter
-                    // called `max_header_delay`.
-                    debug!("Timer expired for round {}", self.round);
+                    // of asynchrony. The leader timeout follows the commit latency, so in practice the latter
+                    // scenario means we misconfigured the parameters called `min_header_delay` and
+                    // `max_header_delay` which bound it.
+                    debug!(
+                        "Timer expired waiting for the leader at round {}",
+                        self.round
+                    );
+
+                    #[cfg(feature = "metrics")]
+                    increment_counter!(LEADER_TIMEOUTS);
                 }
 
                 // Advance to the next round.
@@ -466,6 +659,8 @@ impl Proposer {
                 let current_timestamp = now();
                 let _reason = if max_delay_timed_out {
                     "max_timeout"
+                } else if !advance {
+                    "leader_timeout"
                 } else if enough_digests {
                     "threshold_size_reached"
                 } else {
@@ -475,6 +670,8 @@ impl Proposer {
                     // TODO(metrics): Observe `Duration::from_millis(current_timestamp - t).as_secs_f64()` on `proposal_latency`
                 }
                 self.last_round_timestamp = Some(current_timestamp);
+                self.header_sizer
+                    .round_advanced(Instant::now(), self.digests.len());
                 debug!("Dag moved to round {}", self.round);
 
                 // Make a new header.
@@ -498,6 +695,9 @@ impl Proposer {
                 min_delay_timer
                     .as_mut()
                     .reset(timer_start + self.min_delay());
+                leader_delay_timer
+                    .as_mut()
+                    .reset(timer_start + self.leader_delay());
             }
 
             tokio::select! {
@@ -518,16 +718,35 @@ impl Proposer {
                     }
                 }
 
-                Some((commit_round, commit_headers)) = self.rx_committed_own_headers.recv() => {
+                Some((commit_round, certificates)) = self.rx_committed_headers.recv() => {
+                    let commit_headers: Vec<_> = certificates
+                        .iter()
+                        .filter(|certificate| certificate.origin() == self.authority_id)
+                        .map(|certificate| certificate.round())
+                        .collect();
+                    debug!("Own committed rounds {commit_headers:?} at round {commit_round}");
+
                     // Remove committed headers from the list of pending
                     let mut max_committed_round = 0;
+                    let committed_at = now();
+                    if let Err(e) = self.proposer_store.remove_proposed_headers(commit_headers.iter().copied()) {
+                        error!("Failed to remove the committed headers {commit_headers:?} from the proposer store: {e}");
+                    }
                     for round in commit_headers {
                         max_committed_round = max_committed_round.max(round);
-                        let Some(_) = self.proposed_headers.remove(&round) else {
+                        let Some((header, _)) = self.proposed_headers.remove(&round) else {
                             info!("Own committed header not found at round {round}, probably because of restarts.");
                             // There can still be later committed headers in proposed_headers.
                             continue;
                         };
+                        self.leader_timeout.commit_observed(Duration::from_millis(
+                            committed_at.saturating_sub(*header.created_at()),
+                        ));
+                    }
+
+                    #[cfg(feature = "metrics")]
+                    if let Some(commit_latency) = self.leader_timeout.commit_latency() {
+                        gauge!(COMMIT_LATENCY_MS, commit_latency.as_millis() as f64);
                     }
 
                     // Now for any round below the current commit round we re-insert
@@ -539,7 +758,7 @@ impl Proposer {
                     let mut retransmit_rounds = Vec::new();
 
                     // Iterate in order of rounds of our own headers.
-                    for (header_round, (_header, included_digests)) in &mut self.proposed_headers {
+                    for (header_round, (header, included_digests)) in &mut self.proposed_headers {
                         // Stop once we have processed headers at and below last committed round.
                         if *header_round > max_committed_round {
                             break;
@@ -547,6 +766,11 @@ impl Proposer {
                         // Add payloads from oldest to newest.
                         digests_to_resend.append(included_digests);
                         retransmit_rounds.push(*header_round);
+                        // The weakly linked certificates are uncommitted again, unless committed
+                        // with another header.
+                        for (weak_link, round) in header.weak_links() {
+                            self.uncommitted.entry(*round).or_default().insert(*weak_link);
+                        }
                     }
 
                     if !retransmit_rounds.is_empty() {
@@ -560,6 +784,9 @@ impl Proposer {
                         for round in &retransmit_rounds {
                             self.proposed_headers.remove(round);
                         }
+                        if let Err(e) = self.proposer_store.requeue_proposed_headers(retransmit_rounds.iter().copied()) {
+                            error!("Failed to re-queue the batches of the re-transmitted headers {retransmit_rounds:?} in the proposer store: {e}");
+                        }
 
                         debug!(
                             "Retransmit {} batches in undelivered headers {:?} at commit round {:?}, remaining headers {}",
@@ -572,6 +799,7 @@ impl Proposer {
                         // TODO(metrics): Increment `proposer_resend_headers` by `retransmit_rounds.len() as u64`
                         // TODO(metrics): Increment `proposer_resend_batches` by `num_to_resend as u64`
                     }
+                    self.track_committed(commit_round, &certificates);
                 },
 
                 Some((parents, round, epoch)) = self.rx_parents.recv() => {
@@ -592,6 +820,10 @@ impl Proposer {
                         }
                     }
 
+                    // Certificates from older rounds are not parents anymore, but they can be
+                    // weakly linked if no leader commits them.
+                    self.track_uncommitted(&parents);
+
                     // Compare the parents' round number with our current round.
                     match round.cmp(&self.round) {
                         Ordering::Greater => {
@@ -611,6 +843,9 @@ impl Proposer {
                             min_delay_timer
                                 .as_mut()
                                 .reset(timer_start + self.min_delay());
+                            leader_delay_timer
+                                .as_mut()
+                                .reset(timer_start + self.leader_delay());
                         },
                         Ordering::Less => {
                             // Ignore parents from older rounds.
@@ -637,15 +872,44 @@ impl Proposer {
                 }
 
                 // Receive digests from our workers.
-                Some(mut message) = self.rx_our_digests.recv() => {
-                    // Signal back to the worker that the batch is recorded on the
-                    // primary, and will be tracked until inclusion. This means that
-                    // if the primary does not fail it will attempt to send the digest
-                    // (and re-send if necessary) until it is sequenced, or the end of
-                    // the epoch is reached. For the moment this does not persist primary
-                    // crashes and re-starts.
-                    let _ = message.ack_channel.take().unwrap().send(());
-                    self.digests.push_back(message);
+                Some(message) = self.rx_our_digests.recv() => {
+                    // The digests are persisted before the ack, so that they survive a crash of
+                    // the primary. Without an ack, the worker fails the transactions of the
+                    // batch, and their submitters retry them. The digests already queued are
+                    // persisted in the same synced write, on a blocking thread.
+                    let mut messages = vec![message];
+                    while messages.len() < self.max_header_num_of_batches {
+                        match self.rx_our_digests.try_recv() {
+                            Ok(message) => messages.push(message),
+                            Err(_) => break,
+                        }
+                    }
+                    let digests: Vec<_> = messages
+                        .iter()
+                        .map(|message| (message.digest, message.worker_id, message.timestamp))
+                        .collect();
+                    let proposer_store = self.proposer_store.clone();
+                    match spawn_blocking(move || proposer_store.write_pending_digests(&digests)).await {
+                        Ok(Ok(())) => (),
+                        Ok(Err(e)) => {
+                            error!("Failed to persist {} digests of our workers: {e}", messages.len());
+                            continue;
+                        }
+                        Err(e) => {
+                            error!("The task persisting {} digests of our workers failed: {e}", messages.len());
+                            continue;
+                        }
+                    }
+                    for mut message in messages {
+                        // Signal back to the worker that the batch is recorded on the
+                        // primary, and will be tracked until inclusion. This means that
+                        // the primary will attempt to send the digest (and re-send if
+                        // necessary) until it is sequenced, or the end of the epoch is
+                        // reached, even across crashes and re-starts.
+                        let _ = message.ack_channel.take().unwrap().send(());
+                        self.digests.push_back(message);
+                        self.header_sizer.digest_received();
+                    }
                 }
 
                 // Check whether any timer expired.
@@ -655,6 +919,9 @@ impl Proposer {
                 () = &mut min_delay_timer, if !min_delay_timed_out => {
                     // Continue to next iteration of the loop.
                 }
+                () = &mut leader_delay_timer, if !leader_delay_timed_out => {
+                    // Continue to next iteration of the loop.
+                }
 
                 _ = self.rx_shutdown.receiver.recv() => {
                     return
@@ -0,0 +1,455 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use anemo::PeerId;
+use config::{AuthorityIdentifier, Committee, SnapshotSyncParameters, Stake, WorkerCache};
+use consensus::utils::gc_round;
+use crypto::Hash;
+use futures::{stream::FuturesUnordered, StreamExt};
+use network::PrimaryToPrimaryRpc;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
+use storage::{CertificateStore, ConsensusStore, ExecutionStore};
+use tokio::time::{sleep, timeout, Instant};
+use tracing::{debug, info, warn};
+use types::{
+    ensure,
+    error::{DagError, DagResult},
+    Certificate, CertificateAPI, ConsensusCommit, ExecutionIndices, FetchConsensusSnapshotRequest,
+    FetchConsensusSnapshotResponse, HeaderAPI, Round, SequenceNumber, SubDagDigest,
+    SystemTransaction,
+};
+
+#[cfg(test)]
+#[path = "tests/snapshot_sync_tests.rs"]
+pub mod snapshot_sync_tests;
+
+// The timeout of a snapshot request to a single peer.
+const SNAPSHOT_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The maximum number of certificates of a snapshot.
+pub(crate) const MAX_SNAPSHOT_CERTIFICATES: usize = 10_000;
+
+/// A consensus state agreed on by f+1 peers, ready to be written to the store.
+#[derive(Debug)]
+struct Snapshot {
+    last_committed: HashMap<AuthorityIdentifier, Round>,
+    sub_dags: Vec<ConsensusCommit>,
+    certificates: Vec<Certificate>,
+    committee_changes: Vec<(SequenceNumber, SystemTransaction)>,
+}
+
+/// The `SnapshotSync` lets a primary starting with an empty store join from the last committed
+/// sub dag of its peers, instead of fetching every certificate from the first round. It requests
+/// the digests of the recent sub dags from the other primaries, and picks the most recent sub dag
+/// that f+1 of them agree on along with the sub dags before it above its GC round. It then
+/// fetches these sub dags and the certificates above the GC round from one of those peers, and
+/// writes them to the store once they match the agreed digests, so that consensus and the
+/// executor recover from there as they do after a crash.
+///
+/// It runs on the network of the primary before the primary is spawned on it. The executor
+/// resumes after the synced sub dag, the state of the application up to it has to be synced
+/// separately. The committee changes found by the peer up to the synced sub dag come with the
+/// snapshot, so that the executor knows the next committee without executing the sub dags before.
+pub struct SnapshotSync {
+    /// The id of this primary.
+    authority_id: AuthorityIdentifier,
+    /// The committee information.
+    committee: Committee,
+    /// The worker information cache.
+    worker_cache: WorkerCache,
+    /// The depth of the garbage collection.
+    gc_depth: Round,
+    /// The snapshot sync configuration.
+    parameters: SnapshotSyncParameters,
+    /// The persistent storage of the synced certificates.
+    certificate_store: CertificateStore,
+    /// The persistent storage of the synced consensus state.
+    consensus_store: Arc<ConsensusStore>,
+    /// The persistent storage of the execution progress, moved to the synced sub dag.
+    execution_store: ExecutionStore,
+    /// The genesis certificates.
+    genesis_certs: Vec<Certificate>,
+}
+
+impl SnapshotSync {
+    pub fn new(
+        authority_id: AuthorityIdentifier,
+        committee: Committee,
+        worker_cache: WorkerCache,
+        gc_depth: Round,
+        parameters: SnapshotSyncParameters,
+        certificate_store: CertificateStore,
+        consensus_store: Arc<ConsensusStore>,
+        execution_store: ExecutionStore,
+        genesis_certs: Vec<Certificate>,
+    ) -> Self {
+        Self {
+            authority_id,
+            committee,
+            worker_cache,
+            gc_depth,
+            parameters,
+            certificate_store,
+            consensus_store,
+            execution_store,
+            genesis_certs,
+        }
+    }
+
+    /// Syncs the consensus state from the peers if nothing has been committed locally. Returns
+    /// the index of the last synced sub dag, or None if nothing was synced: the store already had
+    /// committed sub dags, the peers have not committed anything yet, or they did not agree on a
+    /// snapshot before the timeout.
+    pub async fn run(&self, network: &anemo::Network) -> Option<SequenceNumber> {
+        if self.consensus_store.get_latest_sub_dag().is_some() {
+            debug!("The store already has committed sub dags, skipping the snapshot sync");
+            return None;
+        }
+
+        let deadline = Instant::now() + self.parameters.timeout;
+        loop {
+            let responses = self.request_sub_dag_digests(network, deadline).await;
+            match self.select_sub_dags(responses) {
+                Ok(Some((sub_dags, peers))) => {
+                    if let Some(snapshot) = self
+                        .fetch_snapshot(network, &sub_dags, peers, deadline)
+                        .await
+                    {
+                        match self.write_snapshot(snapshot) {
+                            Ok(index) => return Some(index),
+                            Err(e) => {
+                                warn!("Failed to write the consensus snapshot: {e}");
+                                return None;
+                            }
+                        }
+                    }
+                }
+                Ok(None) => {
+                    info!("The peers have not committed anything yet, no snapshot to sync");
+                    return None;
+                }
+                Err(e) => debug!("No consensus snapshot agreed on yet: {e}"),
+            }
+
+            if Instant::now() + self.parameters.retry_delay >= deadline {
+                warn!(
+                    "No consensus snapshot agreed on by f+1 peers after {} ms, \
+                    starting from the first round",
+                    self.parameters.timeout.as_millis()
+                );
+                return None;
+            }
+            sleep(self.parameters.retry_delay).await;
+        }
+    }
+
+    /// Sends `request` to the primary `id`.
+    async fn request(
+        &self,
+        network: &anemo::Network,
+        id: AuthorityIdentifier,
+        request: FetchConsensusSnapshotRequest,
+    ) -> anyhow::Result<FetchConsensusSnapshotResponse> {
+        let authority = self
+            .committee
+            .authority(&id)
+            .ok_or_else(|| anyhow::anyhow!("Unknown authority {id}"))?;
+        let address = authority
+            .primary_address()
+            .to_anemo_address()
+            .map_err(|_| anyhow::anyhow!("Invalid address {}", authority.primary_address()))?;
+        let network_key = authority.network_key();
+        let request = async {
+            network
+                .connect_with_peer_id(address, PeerId(network_key.0.to_bytes()))
+                .await?;
+            network
+                .fetch_consensus_snapshot(&network_key, request)
+                .await
+        };
+        timeout(SNAPSHOT_REQUEST_TIMEOUT, request)
+            .await
+            .map_err(|_| anyhow::anyhow!("Timed out"))?
+    }
+
+    /// Requests the digests of the recent sub dags from every other primary, and returns the
+    /// digests received before the deadline along with the authority that sent them.
+    async fn request_sub_dag_digests(
+        &self,
+        network: &anemo::Network,
+        deadline: Instant,
+    ) -> Vec<(AuthorityIdentifier, Vec<SubDagDigest>)> {
+        let mut requests = self
+            .committee
+            .others_primaries_by_id(self.authority_id)
+            .into_iter()
+            .map(|(id, _, _)| async move {
+                let request = FetchConsensusSnapshotRequest {
+                    sub_dag_index: None,
+                    max_items: 0,
+                };
+                (id, self.request(network, id, request).await)
+            })
+            .collect::<FuturesUnordered<_>>();
+
+        let mut responses = Vec::new();
+        loop {
+            match timeout(
+                deadline.saturating_duration_since(Instant::now()),
+                requests.next(),
+            )
+            .await
+            {
+                Ok(Some((id, Ok(response)))) => responses.push((id, response.sub_dag_digests)),
+                Ok(Some((id, Err(e)))) => {
+                    debug!("Failed to fetch the sub dag digests of {id}: {e}")
+                }
+                Ok(None) | Err(_) => return responses,
+            }
+        }
+    }
+
+    /// Returns the digests of the sub dags of the most recent snapshot agreed on by peers with at
+    /// least f+1 stake, along with these peers, or None if they agree that nothing has been
+    /// committed yet. The snapshot of a sub dag holds the sub dags up to it committed with a
+    /// leader above its GC round, so all of them have to be agreed on, as well as the sub dag
+    /// before them, which shows that none is missing.
+    fn select_sub_dags(
+        &self,
+        responses: Vec<(AuthorityIdentifier, Vec<SubDagDigest>)>,
+    ) -> DagResult<Option<(Vec<SubDagDigest>, Vec<AuthorityIdentifier>)>> {
+        let mut senders: HashMap<SubDagDigest, (Stake, Vec<AuthorityIdentifier>)> = HashMap::new();
+        let mut nothing_committed = 0;
+        for (id, digests) in responses {
+            let stake = self.committee.stake_by_id(id);
+            if digests.is_empty() {
+                nothing_committed += stake;
+            }
+            for digest in digests.into_iter().collect::<HashSet<_>>() {
+                let (total, ids) = senders.entry(digest).or_default();
+                *total += stake;
+                ids.push(id);
+            }
+        }
+
+        let agreed = senders
+            .into_iter()
+            .filter(|(_, (stake, _))| *stake >= self.committee.validity_threshold())
+            .map(|(digest, (_, ids))| (digest.sub_dag_index, (digest, ids)))
+            .collect::<BTreeMap<_, _>>();
+        for (digest, ids) in agreed.values().rev() {
+            let gc_round = gc_round(digest.leader_round, self.gc_depth);
+            let mut sub_dags = Vec::new();
+            let mut index = digest.sub_dag_index;
+            let complete = loop {
+                // The first sub dag has the index 1.
+                if index == 0 {
+                    break true;
+                }
+                match agreed.get(&index) {
+                    Some((sub_dag, _)) if sub_dag.leader_round > gc_round => {
+                        sub_dags.push(*sub_dag);
+                        index -= 1;
+                    }
+                    Some(_) => break true,
+                    None => break false,
+                }
+            };
+            if complete {
+                sub_dags.reverse();
+                return Ok(Some((sub_dags, ids.clone())));
+            }
+        }
+
+        ensure!(
+            nothing_committed >= self.committee.validity_threshold(),
+            DagError::InvalidConsensusSnapshot(
+                "no snapshot is agreed on by peers with f+1 stake".to_string()
+            )
+        );
+        Ok(None)
+    }
+
+    /// Fetches the snapshot of the agreed `sub_dags` from one of the `peers` that agreed on them.
+    async fn fetch_snapshot(
+        &self,
+        network: &anemo::Network,
+        sub_dags: &[SubDagDigest],
+        peers: Vec<AuthorityIdentifier>,
+        deadline: Instant,
+    ) -> Option<Snapshot> {
+        let latest = sub_dags.last()?;
+        for id in peers {
+            if Instant::now() >= deadline {
+                return None;
+            }
+            let request = FetchConsensusSnapshotRequest {
+                sub_dag_index: Some(latest.sub_dag_index),
+                max_items: MAX_SNAPSHOT_CERTIFICATES,
+            };
+            match self.request(network, id, request).await {
+                Ok(response) => match self.verify_snapshot(response, sub_dags) {
+                    Ok(snapshot) => return Some(snapshot),
+                    Err(e) => warn!("Invalid consensus snapshot from {id}: {e}"),
+                },
+                Err(e) => debug!("Failed to fetch a consensus snapshot from {id}: {e}"),
+            }
+        }
+        None
+    }
+
+    /// Checks that a snapshot holds the agreed `sub_dags`, valid certificates and committee changes
+    /// signed by a quorum. A faulty sender could omit parents, so only the certificates whose
+    /// parents are known are kept. The last committed round of each authority is computed from the
+    /// certificates of the sub dags.
+    fn verify_snapshot(
+        &self,
+        response: FetchConsensusSnapshotResponse,
+        sub_dags: &[SubDagDigest],
+    ) -> DagResult<Snapshot> {
+        ensure!(
+            response.sub_dags.len() == sub_dags.len()
+                && response
+                    .sub_dags
+                    .iter()
+                    .zip(sub_dags)
+                    .all(
+                        |(sub_dag, digest)| sub_dag.sub_dag_index() == digest.sub_dag_index
+                            && sub_dag.leader_round() == digest.leader_round
+                            && sub_dag.digest() == digest.digest
+                    ),
+            DagError::InvalidConsensusSnapshot(
+                "the sub dags do not match the agreed digests".to_string()
+            )
+        );
+        ensure!(
+            response.certificates.len() <= MAX_SNAPSHOT_CERTIFICATES,
+            DagError::InvalidConsensusSnapshot(format!(
+                "{} certificates exceed the size of a snapshot",
+                response.certificates.len()
+            ))
+        );
+
+        let mut certificates = HashMap::new();
+        for certificate in response.certificates {
+            ensure!(
+                certificate.epoch() == self.committee.epoch(),
+                DagError::InvalidEpoch {
+                    expected: self.committee.epoch(),
+                    received: certificate.epoch()
+                }
+            );
+            let digest = certificate.digest();
+            if !certificates.contains_key(&digest) {
+                certificate.verify(&self.committee, &self.worker_cache, &self.genesis_certs)?;
+                certificates.insert(digest, certificate);
+            }
+        }
+        let mut certificates = certificates.into_values().collect::<Vec<_>>();
+        certificates.sort_by_key(|certificate| certificate.round());
+
+        let latest = response
+            .sub_dags
+            .last()
+            .expect("A snapshot has at least one sub dag");
+        for (sub_dag_index, transaction) in &response.committee_changes {
+            ensure!(
+                *sub_dag_index <= latest.sub_dag_index(),
+                DagError::InvalidConsensusSnapshot(format!(
+                    "a committee change is found in sub dag {sub_dag_index}, after the snapshot"
+                ))
+            );
+            transaction
+                .verify_scheduled_after(*sub_dag_index, &self.committee, &self.worker_cache)
+                .map_err(|e| {
+                    DagError::InvalidConsensusSnapshot(format!(
+                        "invalid committee change of sub dag {sub_dag_index}: {e}"
+                    ))
+                })?;
+        }
+
+        let gc_round = gc_round(latest.leader_round(), self.gc_depth);
+        let sub_dag_certificates = response
+            .sub_dags
+            .iter()
+            .flat_map(|sub_dag| sub_dag.certificates())
+            .chain(response.sub_dags.iter().map(|sub_dag| sub_dag.leader()))
+            .collect::<HashSet<_>>();
+        let mut known = HashSet::new();
+        let certificates = certificates
+            .into_iter()
+            .filter(|certificate| {
+                let keep = if certificate.round() <= gc_round {
+                    sub_dag_certificates.contains(&certificate.digest())
+                } else {
+                    certificate.round() == gc_round + 1
+                        || certificate
+                            .header()
+                            .parents()
+                            .iter()
+                            .all(|parent| known.contains(parent))
+                };
+                if keep {
+                    known.insert(certificate.digest());
+                }
+                keep
+            })
+            .collect::<Vec<_>>();
+        ensure!(
+            sub_dag_certificates
+                .iter()
+                .all(|digest| known.contains(digest)),
+            DagError::InvalidConsensusSnapshot(
+                "a certificate of a recent sub dag is missing".to_string()
+            )
+        );
+
+        let mut last_committed = HashMap::new();
+        for certificate in &certificates {
+            if sub_dag_certificates.contains(&certificate.digest()) {
+                let round = last_committed.entry(certificate.origin()).or_insert(0);
+                *round = certificate.round().max(*round);
+            }
+        }
+
+        Ok(Snapshot {
+            last_committed,
+            sub_dags: response.sub_dags,
+            certificates,
+            committee_changes: response.committee_changes,
+        })
+    }
+
+    fn write_snapshot(&self, snapshot: Snapshot) -> DagResult<SequenceNumber> {
+        let latest = snapshot
+            .sub_dags
+            .last()
+            .expect("A snapshot has at least one sub dag");
+        let index = latest.sub_dag_index();
+        info!(
+            "Syncing the consensus state at sub dag {index} and leader round {} with {} certificates",
+            latest.leader_round(),
+            snapshot.certificates.len()
+        );
+
+        // The consensus state goes last, since consensus and the executor recover from the
+        // committed sub dags. A node crashing before has no committed sub dag yet and syncs again
+        // on restart.
+        self.certificate_store.write_all(snapshot.certificates)?;
+        for (sub_dag_index, transaction) in &snapshot.committee_changes {
+            self.execution_store
+                .write_committee_change(*sub_dag_index, transaction)?;
+        }
+        self.execution_store
+            .write_last_executed(&ExecutionIndices::end_of_sub_dag(
+                latest.leader_round(),
+                index,
+            ))?;
+        self.consensus_store
+            .write_consensus_snapshot(&snapshot.last_committed, &snapshot.sub_dags)?;
+        Ok(index)
+    }
+}
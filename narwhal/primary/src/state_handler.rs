@@ -1,14 +1,26 @@
 // Copyright (c) 2021, Facebook, Inc. and its affiliates
 // Copyright (c) Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
-use config::AuthorityIdentifier;
+use config::{AuthorityIdentifier, WorkerId};
+use consensus::consensus::ConsensusRound;
+use crypto::NetworkPublicKey;
+use futures::future::join_all;
+use network::{client::NetworkClient, PrimaryToWorkerClient};
+use std::collections::HashMap;
+use storage::{ProposerStore, StoreResult};
 use tap::TapFallible;
 use tokio::{
-    sync::mpsc::{Receiver, Sender},
-    task::JoinHandle,
+    sync::{
+        mpsc::{Receiver, Sender},
+        watch,
+    },
+    task::{spawn_blocking, JoinHandle},
+};
+use tracing::{error, info, warn};
+use types::{
+    BatchDigest, Certificate, ConditionalBroadcastReceiver, HeaderAPI, Round,
+    WorkerRoundUpdateMessage,
 };
-use tracing::{debug, error, info, warn};
-use types::{Certificate, CertificateAPI, ConditionalBroadcastReceiver, HeaderAPI, Round};
 
 /// Receives the highest round reached by consensus and update it for all tasks.
 pub struct StateHandler {
@@ -18,8 +30,18 @@ pub struct StateHandler {
     rx_committed_certificates: Receiver<(Round, Vec<Certificate>)>,
     /// Channel to signal committee changes.
     rx_shutdown: ConditionalBroadcastReceiver,
-    /// A channel to update the committed rounds
-    tx_committed_own_headers: Option<Sender<(Round, Vec<Round>)>>,
+    /// A channel to forward the committed certificates to the proposer.
+    tx_committed_headers: Option<Sender<(Round, Vec<Certificate>)>>,
+    /// Watch channel to get the latest rounds reached by consensus.
+    rx_consensus_round_updates: watch::Receiver<ConsensusRound>,
+    /// The client to forward the rounds reached by consensus to our workers.
+    client: NetworkClient,
+    /// The ids and network keys of our workers.
+    workers: Vec<(WorkerId, NetworkPublicKey)>,
+    /// The store of the proposer, to tell our workers which of their batches are still pending.
+    proposer_store: ProposerStore,
+    /// The batches committed since the last update of our workers, by worker.
+    committed_batches: HashMap<WorkerId, Vec<BatchDigest>>,
 
     network: anemo::Network,
 }
@@ -30,7 +52,11 @@ impl StateHandler {
         authority_id: AuthorityIdentifier,
         rx_committed_certificates: Receiver<(Round, Vec<Certificate>)>,
         rx_shutdown: ConditionalBroadcastReceiver,
-        tx_committed_own_headers: Option<Sender<(Round, Vec<Round>)>>,
+        tx_committed_headers: Option<Sender<(Round, Vec<Certificate>)>>,
+        rx_consensus_round_updates: watch::Receiver<ConsensusRound>,
+        client: NetworkClient,
+        workers: Vec<(WorkerId, NetworkPublicKey)>,
+        proposer_store: ProposerStore,
         network: anemo::Network,
     ) -> JoinHandle<()> {
         tokio::spawn(async move {
@@ -38,7 +64,12 @@ impl StateHandler {
                 authority_id,
                 rx_committed_certificates,
                 rx_shutdown,
-                tx_committed_own_headers,
+                tx_committed_headers,
+                rx_consensus_round_updates,
+                client,
+                workers,
+                proposer_store,
+                committed_batches: HashMap::new(),
                 network,
             }
             .run()
@@ -47,27 +78,74 @@ impl StateHandler {
     }
 
     async fn handle_sequenced(&mut self, commit_round: Round, certificates: Vec<Certificate>) {
-        // Now we are going to signal which of our own batches have been committed.
-        let own_rounds_committed: Vec<_> = certificates
+        for certificate in &certificates {
+            for (digest, (worker_id, _)) in certificate.header().payload() {
+                self.committed_batches
+                    .entry(*worker_id)
+                    .or_default()
+                    .push(*digest);
+            }
+        }
+
+        // If a reporting channel is available send the committed certificates to it, so the
+        // proposer learns which of our own headers and of the weak links have been committed.
+        if let Some(sender) = &self.tx_committed_headers {
+            let _ = sender.send((commit_round, certificates)).await;
+        }
+    }
+
+    /// Forwards the rounds reached by consensus to our workers, with their batches committed
+    /// since the last update and those still pending in the proposer, so they can collect the
+    /// batches which are no longer needed. The proposer store is read off the runtime, and the
+    /// updates are sent without waiting for the workers.
+    fn handle_round_update(&mut self) {
+        let rounds = *self.rx_consensus_round_updates.borrow();
+        let mut committed_batches = std::mem::take(&mut self.committed_batches);
+        let messages: Vec<_> = self
+            .workers
             .iter()
-            .filter_map(|cert| {
-                if cert.header().author() == self.authority_id {
-                    Some(cert.header().round())
-                } else {
-                    None
-                }
+            .map(|(worker_id, worker)| {
+                let message = WorkerRoundUpdateMessage {
+                    committed_round: rounds.committed_round,
+                    gc_round: rounds.gc_round,
+                    committed_batches: committed_batches.remove(worker_id).unwrap_or_default(),
+                    pending_batches: Vec::new(),
+                };
+                (*worker_id, worker.clone(), message)
             })
             .collect();
-        debug!(
-            "Own committed rounds {:?} at round {:?}",
-            own_rounds_committed, commit_round
-        );
 
-        // If a reporting channel is available send the committed own
-        // headers to it.
-        if let Some(sender) = &self.tx_committed_own_headers {
-            let _ = sender.send((commit_round, own_rounds_committed)).await;
-        }
+        let client = self.client.clone();
+        let proposer_store = self.proposer_store.clone();
+        tokio::spawn(async move {
+            let mut pending_batches =
+                match spawn_blocking(move || read_pending_batches(&proposer_store)).await {
+                    Ok(Ok(pending_batches)) => pending_batches,
+                    Ok(Err(e)) => {
+                        error!("Failed to read the pending batches of the proposer: {e}");
+                        return;
+                    }
+                    Err(e) => {
+                        error!("Failed to read the pending batches of the proposer: {e}");
+                        return;
+                    }
+                };
+            join_all(
+                messages
+                    .into_iter()
+                    .map(|(worker_id, worker, mut message)| {
+                        message.pending_batches =
+                            pending_batches.remove(&worker_id).unwrap_or_default();
+                        let client = client.clone();
+                        async move {
+                            if let Err(e) = client.update_round(worker, message).await {
+                                warn!("Failed to send the consensus rounds to our worker: {e}");
+                            }
+                        }
+                    }),
+            )
+            .await;
+        });
     }
 
     async fn run(mut self) {
@@ -81,6 +159,10 @@ impl StateHandler {
                     self.handle_sequenced(commit_round, certificates).await;
                 },
 
+                Ok(()) = self.rx_consensus_round_updates.changed() => {
+                    self.handle_round_update();
+                },
+
                 _ = self.rx_shutdown.receiver.recv() => {
                     // shutdown network
                     let _ = self.network.shutdown().await.tap_err(|err|{
@@ -95,3 +177,20 @@ impl StateHandler {
         }
     }
 }
+
+/// The batches not included in a header yet by the proposer, or included in one of its headers
+/// which is not committed yet, by worker.
+fn read_pending_batches(
+    proposer_store: &ProposerStore,
+) -> StoreResult<HashMap<WorkerId, Vec<BatchDigest>>> {
+    let mut pending_batches: HashMap<WorkerId, Vec<BatchDigest>> = HashMap::new();
+    for (digest, worker_id, _) in proposer_store.read_pending_digests()? {
+        pending_batches.entry(worker_id).or_default().push(digest);
+    }
+    for header in proposer_store.read_proposed_headers()? {
+        for (digest, (worker_id, _)) in header.payload() {
+            pending_batches.entry(*worker_id).or_default().push(*digest);
+        }
+    }
+    Ok(pending_batches)
+}
@@ -24,10 +24,10 @@ use std::{
     },
     time::Duration,
 };
-use storage::{CertificateStore, PayloadStore};
+use storage::{CertificateStore, HeaderStore, PayloadStore};
 use tokio::{
     sync::{broadcast, mpsc, oneshot, watch, MutexGuard},
-    task::JoinSet,
+    task::{spawn_blocking, JoinSet},
     time::sleep,
 };
 use tracing::{debug, error, trace, warn};
@@ -48,6 +48,10 @@ pub mod synchronizer_tests;
 /// locally highest processed round.
 const NEW_CERTIFICATE_ROUND_LIMIT: Round = 100;
 
+/// The maximum number of headers deleted in a single write batch when the headers below the GC
+/// round are pruned.
+const MAX_PRUNED_HEADERS_PER_BATCH: usize = 1_000;
+
 struct Inner {
     /// The id of this primary.
     authority_id: AuthorityIdentifier,
@@ -65,6 +69,8 @@ struct Inner {
     highest_received_round: AtomicU64,
     /// Client for fetching payloads.
     client: NetworkClient,
+    /// The persistent store of the headers of the accepted certificates.
+    header_store: HeaderStore,
     /// The persistent storage tables.
     certificate_store: CertificateStore,
     /// The persistent store of the available batch digests produced either via our own workers
@@ -98,6 +104,21 @@ struct Inner {
     state: tokio::sync::Mutex<State>,
 }
 
+/// Deletes the headers with a round lower or equal to `gc_round`, in batches of bounded size.
+/// The writes block, so this runs on a blocking thread.
+fn prune_headers(header_store: &HeaderStore, gc_round: Round) {
+    loop {
+        match header_store.prune_until_round(gc_round, MAX_PRUNED_HEADERS_PER_BATCH) {
+            Ok(0) => return,
+            Ok(pruned) => debug!("Pruned {pruned} headers up to the GC round {gc_round}"),
+            Err(e) => {
+                error!("Failed to prune the headers up to the GC round {gc_round}: {e}");
+                return;
+            }
+        }
+    }
+}
+
 impl Inner {
     async fn append_certificate_in_aggregator(&self, certificate: Certificate) -> DagResult<()> {
         // Check if we have enough certificates to enter a new dag round and propose a header.
@@ -150,9 +171,18 @@ impl Inner {
             }
         }
 
-        // Store the certificate and make it available as parent to other certificates.
+        // Store the certificate and make it available as parent to other certificates. Its header
+        // is written in the same unit of work, so that a crash cannot leave a certificate without
+        // its header in storage. The payload markers are only written once our workers have the
+        // batches, see `sync_batches_internal`.
+        let mut unit = self.certificate_store.unit_of_work();
+        self.header_store
+            .write_in(&mut unit, certificate.header())
+            .expect("Writing header to storage cannot fail!");
         self.certificate_store
-            .write(certificate.clone())
+            .write_in(&mut unit, certificate.clone())
+            .expect("Writing certificate to storage cannot fail!");
+        unit.commit()
             .expect("Writing certificate to storage cannot fail!");
 
         // From this point, the certificate must be sent to consensus or Narwhal needs to shutdown,
@@ -219,6 +249,13 @@ impl Inner {
                 result.push(*digest);
             }
         }
+        // Weak links must be in the DAG as well, unless they have been garbage collected.
+        let gc_round = self.gc_round.load(Ordering::Acquire);
+        for (digest, round) in certificate.header().weak_links() {
+            if *round > gc_round && !self.has_processed_certificate(*digest).await? {
+                result.push(*digest);
+            }
+        }
         if !result.is_empty() {
             self.tx_certificate_fetcher
                 .send(certificate.clone())
@@ -260,6 +297,7 @@ impl Synchronizer {
         worker_cache: WorkerCache,
         gc_depth: Round,
         client: NetworkClient,
+        header_store: HeaderStore,
         certificate_store: CertificateStore,
         payload_store: PayloadStore,
         tx_certificate_fetcher: mpsc::Sender<Certificate>,
@@ -288,6 +326,7 @@ impl Synchronizer {
             highest_processed_round: AtomicU64::new(highest_processed_round),
             highest_received_round: AtomicU64::new(0),
             client,
+            header_store,
             certificate_store,
             payload_store,
             tx_certificate_fetcher,
@@ -322,6 +361,23 @@ impl Synchronizer {
             }
         });
 
+        // Start a task to prune the headers below the GC round, which will not be voted on nor
+        // certified anymore. It is separate from the GC of the in-memory data, so that the
+        // writes do not hold the acceptance of the suspended certificates back.
+        let header_store = inner.header_store.clone();
+        let mut rx_gc_round_updates = rx_consensus_round_updates.clone();
+        tokio::spawn(async move {
+            while rx_gc_round_updates.changed().await.is_ok() {
+                let gc_round = rx_gc_round_updates.borrow().gc_round;
+                let header_store = header_store.clone();
+                if let Err(e) = spawn_blocking(move || prune_headers(&header_store, gc_round)).await
+                {
+                    error!("Failed to prune the headers up to the GC round {gc_round}: {e}");
+                }
+            }
+            debug!("Synchronizer is shutting down.");
+        });
+
         // Start a task to update gc_round and gc in-memory data.
         let weak_inner = Arc::downgrade(&inner);
         tokio::spawn(async move {
@@ -954,6 +1010,28 @@ impl Synchronizer {
         Ok((parents, missing))
     }
 
+    /// Returns the weakly linked certificates of the given header, and a list of digests for any
+    /// that are missing.
+    pub fn get_weak_links(
+        &self,
+        header: &Header,
+    ) -> DagResult<(Vec<Certificate>, Vec<CertificateDigest>)> {
+        let gc_round = self.inner.gc_round.load(Ordering::Acquire);
+        let mut missing = Vec::new();
+        let mut weak_links = Vec::new();
+        for (digest, round) in header.weak_links() {
+            if *round <= gc_round {
+                continue;
+            }
+            match self.inner.certificate_store.read(*digest)? {
+                Some(certificate) => weak_links.push(certificate),
+                None => missing.push(*digest),
+            };
+        }
+
+        Ok((weak_links, missing))
+    }
+
     /// Tries to get all missing parents of the certificate. If there is any, sends the
     /// certificate to `CertificateFetcher` which will trigger range fetching of missing
     /// certificates.
@@ -1023,8 +1101,21 @@ impl State {
         allow_reinsert: bool,
     ) -> AcceptNotification {
         let digest = certificate.digest();
-        let missing_round = certificate.round() - 1;
         let missing_parents_map: HashSet<_> = missing_parents.iter().cloned().collect();
+        // Weak links are tracked at their own round, which is older than the parents'.
+        let missing_rounds: Vec<_> = missing_parents
+            .iter()
+            .map(|d| {
+                let round = certificate
+                    .header()
+                    .weak_links()
+                    .get(d)
+                    .filter(|_| !certificate.header().parents().contains(d))
+                    .copied()
+                    .unwrap_or(certificate.round() - 1);
+                (round, *d)
+            })
+            .collect();
         if allow_reinsert {
             if let Some(suspended_cert) = self.suspended.get(&digest) {
                 assert_eq!(
@@ -1047,12 +1138,8 @@ impl State {
                 }
             )
             .is_none());
-        for d in missing_parents {
-            assert!(self
-                .missing
-                .entry((missing_round, d))
-                .or_default()
-                .insert(digest));
+        for key in missing_rounds {
+            assert!(self.missing.entry(key).or_default().insert(digest));
         }
         notify
     }
@@ -1096,7 +1183,8 @@ impl State {
         to_accept
     }
 
-    /// Runs GC on the suspended certificates, returns a list that can be accepted at gc round + 1.
+    /// Runs GC on the suspended certificates, returns a list that can be accepted at gc round + 1,
+    /// along with the certificates that were only missing garbage collected weak links.
     /// It is caller's responsibility to check if some children of the returned certificates can
     /// also be accepted.
     fn run_gc(&mut self, gc_round: Round) -> Vec<SuspendedCertificate> {
@@ -1104,6 +1192,7 @@ impl State {
         // above the gc round.
         let mut gc_certificates = Vec::new();
         let mut certificates_above_gc_round = HashSet::new();
+        let mut weakly_linked_certificates = Vec::new();
         while let Some(((round, digest), children)) = self.missing.iter().next() {
             if *round > gc_round {
                 break;
@@ -1111,6 +1200,19 @@ impl State {
             if *round == gc_round {
                 certificates_above_gc_round.extend(children.iter().cloned());
             }
+            // Children above gc round + 1 are missing this certificate as a weak link, which
+            // is no longer required.
+            for child in children {
+                let Some(suspended_child) = self.suspended.get_mut(child) else {
+                    continue;
+                };
+                if suspended_child.certificate.round() > gc_round + 1 {
+                    suspended_child.missing_parents.remove(digest);
+                    if suspended_child.missing_parents.is_empty() {
+                        weakly_linked_certificates.push(*child);
+                    }
+                }
+            }
             // It is ok to notify waiters here (via Drop). The certificate will never and does
             // not need to get into certificate store.
             if let Some(suspended) = self.suspended.remove(digest) {
@@ -1135,6 +1237,11 @@ impl State {
             suspended_cert.missing_parents.clear();
             to_accept.push(suspended_cert);
         }
+        for digest in weakly_linked_certificates {
+            if let Some(suspended_cert) = self.suspended.remove(&digest) {
+                to_accept.push(suspended_cert);
+            }
+        }
         to_accept
     }
 
@@ -62,11 +62,12 @@ async fn test_successful_blocks_delete() {
         let batch_1 = test_utils::fixture_batch_with_transactions(10);
         let batch_2 = test_utils::fixture_batch_with_transactions(10);
 
-        let header = Header::V1(
+        let header = Header::V2(
             author
                 .header_builder(&committee)
                 .with_payload_batch(batch_1.clone(), worker_id_0, 0)
                 .with_payload_batch(batch_2.clone(), worker_id_1, 0)
+                .signed(author.keypair().private())
                 .build(),
         );
 
@@ -224,11 +225,12 @@ async fn test_failed_blocks_delete() {
         let batch_1 = test_utils::fixture_batch_with_transactions(10);
         let batch_2 = test_utils::fixture_batch_with_transactions(10);
 
-        let header = Header::V1(
+        let header = Header::V2(
             author
                 .header_builder(&committee)
                 .with_payload_batch(batch_1.clone(), worker_id_0, 0)
                 .with_payload_batch(batch_2.clone(), worker_id_1, 0)
+                .signed(author.keypair().private())
                 .build(),
         );
 
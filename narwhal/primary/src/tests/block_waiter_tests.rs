@@ -28,10 +28,11 @@ async fn test_successfully_retrieve_block() {
     let id = primary.id();
 
     // AND store certificate
-    let header = Header::V1(
+    let header = Header::V2(
         author
             .header_builder(&committee)
             .payload(fixture_payload(2))
+            .signed(author.keypair().private())
             .build(),
     );
     let certificate = fixture.certificate(&header);
@@ -187,7 +188,7 @@ async fn test_successfully_retrieve_multiple_blocks() {
         // sort the batches to make sure that the response is the expected one.
         batches.sort_by(|a, b| a.digest.cmp(&b.digest));
 
-        let header = Header::V1(builder.build());
+        let header = Header::V2(builder.signed(author.keypair().private()).build());
 
         let certificate = fixture.certificate(&header);
         certificates.push(certificate.clone());
@@ -16,7 +16,7 @@ use storage::NodeStorage;
 use tokio::sync::oneshot;
 
 use consensus::consensus::ConsensusRound;
-use test_utils::{temp_dir, CommitteeFixture};
+use test_utils::CommitteeFixture;
 use tokio::{
     sync::{
         mpsc::{self, error::TryRecvError, Receiver, Sender},
@@ -26,10 +26,11 @@ use tokio::{
 };
 use types::{
     BatchDigest, Certificate, CertificateAPI, CertificateDigest, FetchCertificatesRequest,
-    FetchCertificatesResponse, GetCertificatesRequest, GetCertificatesResponse, Header, HeaderAPI,
-    HeaderDigest, Metadata, PayloadAvailabilityRequest, PayloadAvailabilityResponse,
-    PreSubscribedBroadcastSender, PrimaryToPrimary, PrimaryToPrimaryServer, RequestVoteRequest,
-    RequestVoteResponse, Round, SendCertificateRequest, SendCertificateResponse,
+    FetchCertificatesResponse, FetchConsensusSnapshotRequest, FetchConsensusSnapshotResponse,
+    GetCertificatesRequest, GetCertificatesResponse, Header, HeaderAPI, HeaderDigest, Metadata,
+    PayloadAvailabilityRequest, PayloadAvailabilityResponse, PreSubscribedBroadcastSender,
+    PrimaryToPrimary, PrimaryToPrimaryServer, RequestVoteRequest, RequestVoteResponse, Round,
+    SendCertificateRequest, SendCertificateResponse,
 };
 
 pub struct NetworkProxy {
@@ -73,6 +74,13 @@ impl PrimaryToPrimary for NetworkProxy {
         ))
     }
 
+    async fn fetch_consensus_snapshot(
+        &self,
+        _request: anemo::Request<FetchConsensusSnapshotRequest>,
+    ) -> Result<anemo::Response<FetchConsensusSnapshotResponse>, anemo::rpc::Status> {
+        unimplemented!()
+    }
+
     async fn get_payload_availability(
         &self,
         _request: anemo::Request<PayloadAvailabilityRequest>,
@@ -158,7 +166,8 @@ async fn fetch_certificates_basic() {
     let (tx_fetch_resp, rx_fetch_resp) = mpsc::channel(1000);
 
     // Create test stores.
-    let store = NodeStorage::reopen(temp_dir());
+    let store = NodeStorage::new_for_tests();
+    let header_store = store.header_store.clone();
     let certificate_store = store.certificate_store.clone();
     let payload_store = store.payload_store.clone();
 
@@ -174,6 +183,7 @@ async fn fetch_certificates_basic() {
         worker_cache.clone(),
         gc_depth,
         client,
+        header_store.clone(),
         certificate_store.clone(),
         payload_store.clone(),
         tx_certificate_fetcher,
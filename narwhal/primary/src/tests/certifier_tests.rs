@@ -105,6 +105,7 @@ async fn propose_header() {
         worker_cache.clone(),
         /* gc_depth */ 50,
         client,
+        header_store.clone(),
         certificate_store.clone(),
         payload_store.clone(),
         tx_certificate_fetcher,
@@ -205,6 +206,7 @@ async fn propose_header_failure() {
         worker_cache.clone(),
         /* gc_depth */ 50,
         client,
+        header_store.clone(),
         certificate_store.clone(),
         payload_store.clone(),
         tx_certificate_fetcher,
@@ -338,6 +340,7 @@ async fn run_vote_aggregator_with_param(
         worker_cache.clone(),
         /* gc_depth */ 50,
         client,
+        header_store.clone(),
         certificate_store.clone(),
         payload_store.clone(),
         tx_certificate_fetcher,
@@ -409,6 +412,7 @@ async fn shutdown_core() {
         worker_cache.clone(),
         /* gc_depth */ 50,
         client,
+        header_store.clone(),
         certificate_store.clone(),
         payload_store.clone(),
         tx_certificate_fetcher,
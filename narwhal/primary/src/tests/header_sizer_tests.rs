@@ -0,0 +1,94 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use super::*;
+
+fn sizer(enabled: bool) -> HeaderSizer {
+    HeaderSizer::new(
+        AdaptiveHeaderParameters {
+            enabled,
+            min_header_num_of_batches_threshold: 2,
+            min_header_delay_floor: Duration::from_millis(50),
+            max_header_delay_floor: Duration::from_millis(200),
+        },
+        /* header_num_of_batches_threshold */ 32,
+        /* max_header_num_of_batches */ 100,
+        /* min_header_delay */ Duration::from_millis(500),
+        /* max_header_delay */ Duration::from_secs(2),
+    )
+}
+
+/// Advances `rounds` rounds of `latency` each, receiving `digests` digests per round.
+fn advance(
+    sizer: &mut HeaderSizer,
+    now: &mut Instant,
+    rounds: usize,
+    latency: Duration,
+    digests: usize,
+    pending: usize,
+) {
+    for _ in 0..rounds {
+        for _ in 0..digests {
+            sizer.digest_received();
+        }
+        *now += latency;
+        sizer.round_advanced(*now, pending);
+    }
+}
+
+#[test]
+fn disabled_uses_configured_values() {
+    let mut sizer = sizer(false);
+    let mut now = Instant::now();
+
+    advance(&mut sizer, &mut now, 10, Duration::from_millis(100), 5, 100);
+
+    assert_eq!(sizer.header_num_of_batches_threshold(), 32);
+    assert_eq!(sizer.min_header_delay(), Duration::from_millis(500));
+    assert_eq!(sizer.max_header_delay(), Duration::from_secs(2));
+}
+
+#[test]
+fn threshold_follows_digests_per_round() {
+    let mut sizer = sizer(true);
+    let mut now = Instant::now();
+    sizer.round_advanced(now, 0);
+
+    // Low load: few digests per round, don't wait for the configured 32.
+    advance(&mut sizer, &mut now, 30, Duration::from_millis(300), 4, 0);
+    let threshold = sizer.header_num_of_batches_threshold();
+    assert!((2..=5).contains(&threshold), "threshold {threshold}");
+
+    // High load: many digests per round, bounded by the configured threshold.
+    advance(&mut sizer, &mut now, 30, Duration::from_millis(300), 200, 0);
+    assert_eq!(sizer.header_num_of_batches_threshold(), 32);
+}
+
+#[test]
+fn delays_adapt_to_round_latency_and_queue_depth() {
+    let mut sizer = sizer(true);
+    let mut now = Instant::now();
+    sizer.round_advanced(now, 0);
+
+    // An empty queue keeps the configured min delay, a fast network shortens the max delay
+    // down to its floor.
+    advance(&mut sizer, &mut now, 30, Duration::from_millis(50), 1, 0);
+    assert_eq!(sizer.min_header_delay(), Duration::from_millis(500));
+    assert_eq!(sizer.max_header_delay(), Duration::from_millis(500));
+
+    // A full queue drains by proposing as soon as possible.
+    advance(&mut sizer, &mut now, 1, Duration::from_millis(50), 1, 100);
+    assert_eq!(sizer.min_header_delay(), Duration::from_millis(50));
+    assert_eq!(sizer.max_header_delay(), Duration::from_millis(200));
+
+    // Half full is in between.
+    advance(&mut sizer, &mut now, 1, Duration::from_millis(50), 1, 50);
+    let min_delay = sizer.min_header_delay();
+    assert!(
+        (min_delay.as_secs_f64() - 0.275).abs() < 1e-6,
+        "min delay {min_delay:?}"
+    );
+
+    // A slow network never exceeds the configured max delay.
+    advance(&mut sizer, &mut now, 30, Duration::from_secs(5), 1, 0);
+    assert_eq!(sizer.max_header_delay(), Duration::from_secs(2));
+}
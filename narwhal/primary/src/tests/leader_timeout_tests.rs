@@ -0,0 +1,48 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use super::*;
+
+const MIN: Duration = Duration::from_millis(100);
+const MAX: Duration = Duration::from_secs(2);
+
+#[test]
+fn waits_max_until_commits_are_observed() {
+    let timeout = LeaderTimeout::default();
+
+    assert_eq!(timeout.commit_latency(), None);
+    assert_eq!(timeout.timeout(MIN, MAX), MAX);
+}
+
+#[test]
+fn follows_commit_latency_within_bounds() {
+    let mut timeout = LeaderTimeout::default();
+
+    timeout.commit_observed(Duration::from_millis(500));
+    assert_eq!(timeout.timeout(MIN, MAX), Duration::from_millis(500));
+
+    // A fast network doesn't go below the min delay.
+    for _ in 0..30 {
+        timeout.commit_observed(Duration::from_millis(10));
+    }
+    assert_eq!(timeout.timeout(MIN, MAX), MIN);
+
+    // A slow network doesn't go above the max delay.
+    for _ in 0..30 {
+        timeout.commit_observed(Duration::from_secs(10));
+    }
+    assert_eq!(timeout.timeout(MIN, MAX), MAX);
+}
+
+#[test]
+fn smooths_commit_latency_spikes() {
+    let mut timeout = LeaderTimeout::default();
+
+    timeout.commit_observed(Duration::from_millis(500));
+    timeout.commit_observed(Duration::from_millis(1500));
+
+    let latency = timeout.commit_latency().unwrap();
+    assert!(
+        (latency.as_secs_f64() - 0.7).abs() < 1e-6,
+        "latency {latency:?}"
+    );
+}
@@ -1,6 +1,6 @@
 // Copyright (c) Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
-use super::{NetworkModel, Primary, PrimaryReceiverHandler, CHANNEL_CAPACITY};
+use super::{NetworkModel, Primary, PrimaryNetwork, PrimaryReceiverHandler, CHANNEL_CAPACITY};
 use crate::{common::create_db_stores, synchronizer::Synchronizer, NUM_SHUTDOWN_RECEIVERS};
 use bincode::Options;
 use config::{AuthorityIdentifier, Committee, Parameters, WorkerId};
@@ -16,7 +16,7 @@ use std::{
     sync::Arc,
     time::Duration,
 };
-use storage::{CertificateStore, VoteDigestStore};
+use storage::{CertificateStore, ConsensusStore, ExecutionStore, HeaderStore, VoteDigestStore};
 use storage::{CertificateStoreCache, PayloadToken};
 use storage::{NodeStorage, PayloadStore};
 use store::rocks::{DBMap, ReadWriteOptions};
@@ -28,9 +28,12 @@ use tokio::{
 };
 
 use types::{
-    now, BatchDigest, Certificate, CertificateAPI, CertificateDigest, FetchCertificatesRequest,
-    Header, HeaderAPI, MockPrimaryToWorker, PayloadAvailabilityRequest,
-    PreSubscribedBroadcastSender, PrimaryToPrimary, RequestVoteRequest, Round,
+    now, BatchDigest, Certificate, CertificateAPI, CertificateDigest, CommittedSubDag,
+    ConsensusCommit, ConsensusCommitV2, ExecutionIndices, FetchCertificatesRequest,
+    FetchConsensusSnapshotRequest, FetchConsensusSnapshotResponse, Header, HeaderAPI, HeaderDigest,
+    MockPrimaryToWorker, PayloadAvailabilityRequest, PreSubscribedBroadcastSender,
+    PrimaryToPrimary, ReputationScores, RequestVoteRequest, Round, ScheduledCommitteeChange,
+    SubDagDigest, SystemTransaction,
 };
 use worker::{TrivialTransactionValidator, Worker};
 
@@ -51,7 +54,7 @@ async fn get_network_peers_from_admin_server() {
     let worker_1_keypair = authority_1.worker(worker_id).keypair();
 
     // Make the data store.
-    let store = NodeStorage::reopen(temp_dir());
+    let store = NodeStorage::new_for_tests();
     let client_1 = NetworkClient::new_from_keypair(&authority_1.network_keypair());
 
     let (tx_new_certificates, rx_new_certificates) = mpsc::channel(CHANNEL_CAPACITY);
@@ -68,7 +71,12 @@ async fn get_network_peers_from_admin_server() {
     Primary::spawn(
         authority_1.authority().clone(),
         signer_1,
-        authority_1.network_keypair(),
+        PrimaryNetwork::bind(
+            authority_1.authority(),
+            authority_1.network_keypair(),
+            &committee,
+            &client_1,
+        ),
         committee.clone(),
         worker_cache.clone(),
         primary_1_parameters.clone(),
@@ -78,6 +86,8 @@ async fn get_network_peers_from_admin_server() {
         store.proposer_store.clone(),
         store.payload_store.clone(),
         store.vote_digest_store.clone(),
+        store.consensus_store.clone(),
+        store.execution_store.clone(),
         tx_new_certificates,
         rx_feedback,
         rx_consensus_round_updates,
@@ -176,7 +186,12 @@ async fn get_network_peers_from_admin_server() {
     Primary::spawn(
         authority_2.authority().clone(),
         signer_2,
-        authority_2.network_keypair(),
+        PrimaryNetwork::bind(
+            authority_2.authority(),
+            authority_2.network_keypair(),
+            &committee,
+            &client_2,
+        ),
         committee.clone(),
         worker_cache.clone(),
         primary_2_parameters.clone(),
@@ -186,6 +201,8 @@ async fn get_network_peers_from_admin_server() {
         store.proposer_store.clone(),
         store.payload_store.clone(),
         store.vote_digest_store.clone(),
+        store.consensus_store.clone(),
+        store.execution_store.clone(),
         /* tx_consensus */ tx_new_certificates_2,
         /* rx_consensus */ rx_feedback_2,
         rx_consensus_round_updates,
@@ -288,6 +305,7 @@ async fn test_request_vote_send_missing_parents() {
         worker_cache.clone(),
         /* gc_depth */ 50,
         client,
+        header_store.clone(),
         certificate_store.clone(),
         payload_store.clone(),
         tx_certificate_fetcher,
@@ -308,6 +326,9 @@ async fn test_request_vote_send_missing_parents() {
         certificate_store: certificate_store.clone(),
         payload_store: payload_store.clone(),
         vote_digest_store: VoteDigestStore::new_for_tests(),
+        consensus_store: Arc::new(ConsensusStore::new_for_tests()),
+        execution_store: ExecutionStore::new_for_tests(),
+        gc_depth: 50,
         rx_narwhal_round_updates,
         genesis_certs,
     };
@@ -330,13 +351,14 @@ async fn test_request_vote_send_missing_parents() {
     let round_2_missing = round_2_certs[(NUM_PARENTS / 2)..].to_vec();
 
     // Create a test header.
-    let test_header = Header::V1(
+    let test_header = Header::V2(
         author
             .header_builder(&fixture.committee())
             .author(author_id)
             .round(3)
             .parents(round_2_certs.iter().map(|c| c.digest()).collect())
             .with_payload_batch(test_utils::fixture_batch_with_transactions(10), 0, 0)
+            .signed(author.keypair().private())
             .build(),
     );
 
@@ -441,6 +463,7 @@ async fn test_request_vote_accept_missing_parents() {
         worker_cache.clone(),
         /* gc_depth */ 50,
         client,
+        header_store.clone(),
         certificate_store.clone(),
         payload_store.clone(),
         tx_certificate_fetcher,
@@ -461,6 +484,9 @@ async fn test_request_vote_accept_missing_parents() {
         certificate_store: certificate_store.clone(),
         payload_store: payload_store.clone(),
         vote_digest_store: VoteDigestStore::new_for_tests(),
+        consensus_store: Arc::new(ConsensusStore::new_for_tests()),
+        execution_store: ExecutionStore::new_for_tests(),
+        gc_depth: 50,
         rx_narwhal_round_updates,
         genesis_certs,
     };
@@ -484,13 +510,14 @@ async fn test_request_vote_accept_missing_parents() {
     let round_2_missing = round_2_certs[(NUM_PARENTS / 2)..].to_vec();
 
     // Create a test header.
-    let test_header = Header::V1(
+    let test_header = Header::V2(
         author
             .header_builder(&fixture.committee())
             .author(author_id)
             .round(3)
             .parents(round_2_certs.iter().map(|c| c.digest()).collect())
             .with_payload_batch(test_utils::fixture_batch_with_transactions(10), 0, 0)
+            .signed(author.keypair().private())
             .build(),
     );
 
@@ -585,6 +612,7 @@ async fn test_request_vote_missing_batches() {
         worker_cache.clone(),
         /* gc_depth */ 50,
         client.clone(),
+        header_store.clone(),
         certificate_store.clone(),
         payload_store.clone(),
         tx_certificate_fetcher,
@@ -605,6 +633,9 @@ async fn test_request_vote_missing_batches() {
         certificate_store: certificate_store.clone(),
         payload_store: payload_store.clone(),
         vote_digest_store: VoteDigestStore::new_for_tests(),
+        consensus_store: Arc::new(ConsensusStore::new_for_tests()),
+        execution_store: ExecutionStore::new_for_tests(),
+        gc_depth: 50,
         rx_narwhal_round_updates,
         genesis_certs,
     };
@@ -612,10 +643,11 @@ async fn test_request_vote_missing_batches() {
     // Make some mock certificates that are parents of our new header.
     let mut certificates = HashMap::new();
     for primary in fixture.authorities().filter(|a| a.id() != authority_id) {
-        let header = Header::V1(
+        let header = Header::V2(
             primary
                 .header_builder(&fixture.committee())
                 .with_payload_batch(test_utils::fixture_batch_with_transactions(10), 0, 0)
+                .signed(primary.keypair().private())
                 .build(),
         );
 
@@ -628,12 +660,13 @@ async fn test_request_vote_missing_batches() {
             payload_store.write(digest, worker_id).unwrap();
         }
     }
-    let test_header = Header::V1(
+    let test_header = Header::V2(
         author
             .header_builder(&fixture.committee())
             .round(2)
             .parents(certificates.keys().cloned().collect())
             .with_payload_batch(test_utils::fixture_batch_with_transactions(10), 1, 0)
+            .signed(author.keypair().private())
             .build(),
     );
     let test_digests: HashSet<_> = test_header
@@ -718,6 +751,7 @@ async fn test_request_vote_already_voted() {
         worker_cache.clone(),
         /* gc_depth */ 50,
         client.clone(),
+        header_store.clone(),
         certificate_store.clone(),
         payload_store.clone(),
         tx_certificate_fetcher,
@@ -739,6 +773,9 @@ async fn test_request_vote_already_voted() {
         certificate_store: certificate_store.clone(),
         payload_store: payload_store.clone(),
         vote_digest_store: VoteDigestStore::new_for_tests(),
+        consensus_store: Arc::new(ConsensusStore::new_for_tests()),
+        execution_store: ExecutionStore::new_for_tests(),
+        gc_depth: 50,
         rx_narwhal_round_updates,
         genesis_certs,
     };
@@ -746,10 +783,11 @@ async fn test_request_vote_already_voted() {
     // Make some mock certificates that are parents of our new header.
     let mut certificates = HashMap::new();
     for primary in fixture.authorities().filter(|a| a.id() != id) {
-        let header = Header::V1(
+        let header = Header::V2(
             primary
                 .header_builder(&fixture.committee())
                 .with_payload_batch(test_utils::fixture_batch_with_transactions(10), 0, 0)
+                .signed(primary.keypair().private())
                 .build(),
         );
 
@@ -783,12 +821,13 @@ async fn test_request_vote_already_voted() {
         .unwrap();
 
     // Verify Handler generates a Vote.
-    let test_header = Header::V1(
+    let test_header = Header::V2(
         author
             .header_builder(&fixture.committee())
             .round(2)
             .parents(certificates.keys().cloned().collect())
             .with_payload_batch(test_utils::fixture_batch_with_transactions(10), 1, 0)
+            .signed(author.keypair().private())
             .build(),
     );
     let mut request = anemo::Request::new(RequestVoteRequest {
@@ -827,12 +866,13 @@ async fn test_request_vote_already_voted() {
     assert_eq!(vote.digest(), response.into_body().vote.unwrap().digest());
 
     // Verify a different request for the same round receives an error.
-    let test_header = Header::V1(
+    let test_header = Header::V2(
         author
             .header_builder(&fixture.committee())
             .round(2)
             .parents(certificates.keys().cloned().collect())
             .with_payload_batch(test_utils::fixture_batch_with_transactions(10), 1, 0)
+            .signed(author.keypair().private())
             .build(),
     );
     let mut request = anemo::Request::new(RequestVoteRequest {
@@ -886,6 +926,7 @@ async fn test_fetch_certificates_handler() {
         worker_cache.clone(),
         /* gc_depth */ 50,
         client,
+        header_store.clone(),
         certificate_store.clone(),
         payload_store.clone(),
         tx_certificate_fetcher,
@@ -906,6 +947,9 @@ async fn test_fetch_certificates_handler() {
         certificate_store: certificate_store.clone(),
         payload_store: payload_store.clone(),
         vote_digest_store: VoteDigestStore::new_for_tests(),
+        consensus_store: Arc::new(ConsensusStore::new_for_tests()),
+        execution_store: ExecutionStore::new_for_tests(),
+        gc_depth: 50,
         rx_narwhal_round_updates,
         genesis_certs,
     };
@@ -1027,6 +1071,244 @@ async fn test_fetch_certificates_handler() {
     }
 }
 
+#[tokio::test]
+async fn test_fetch_consensus_snapshot_handler() {
+    let fixture = CommitteeFixture::builder()
+        .randomize_ports(true)
+        .committee_size(NonZeroUsize::new(4).unwrap())
+        .build();
+    let id = fixture.authorities().next().unwrap().id();
+    let worker_cache = fixture.worker_cache();
+    let primary = fixture.authorities().next().unwrap();
+    let signature_service = SignatureService::new(*primary.keypair().private());
+    let client = NetworkClient::new_from_keypair(&primary.network_keypair());
+
+    let (header_store, certificate_store, payload_store) = create_db_stores();
+    let consensus_store = Arc::new(ConsensusStore::new_for_tests());
+    let execution_store = ExecutionStore::new_for_tests();
+    let (tx_certificate_fetcher, _rx_certificate_fetcher) = test_utils::test_channel!(1);
+    let (tx_new_certificates, _rx_new_certificates) = test_utils::test_channel!(100);
+    let (tx_parents, _rx_parents) = test_utils::test_channel!(100);
+    let (_tx_consensus_round_updates, rx_consensus_round_updates) =
+        watch::channel(ConsensusRound::default());
+    let (_tx_narwhal_round_updates, rx_narwhal_round_updates) = watch::channel(1u64);
+    let (_tx_synchronizer_network, rx_synchronizer_network) = oneshot::channel();
+
+    let keypair = primary.keypair().clone();
+    let genesis_certs = Certificate::genesis(&fixture.committee(), keypair.private());
+
+    let synchronizer = Arc::new(Synchronizer::new(
+        id,
+        fixture.committee(),
+        worker_cache.clone(),
+        /* gc_depth */ 2,
+        client,
+        header_store.clone(),
+        certificate_store.clone(),
+        payload_store.clone(),
+        tx_certificate_fetcher,
+        tx_new_certificates,
+        tx_parents,
+        rx_consensus_round_updates.clone(),
+        rx_synchronizer_network,
+        None,
+        genesis_certs.clone(),
+    ));
+    let handler = PrimaryReceiverHandler {
+        authority_id: id,
+        committee: fixture.committee(),
+        worker_cache: worker_cache.clone(),
+        synchronizer: synchronizer.clone(),
+        signature_service,
+        header_store: header_store.clone(),
+        certificate_store: certificate_store.clone(),
+        payload_store: payload_store.clone(),
+        vote_digest_store: VoteDigestStore::new_for_tests(),
+        consensus_store: consensus_store.clone(),
+        execution_store: execution_store.clone(),
+        gc_depth: 2,
+        rx_narwhal_round_updates,
+        genesis_certs: genesis_certs.clone(),
+    };
+
+    // Nothing committed yet.
+    let response = handler
+        .fetch_consensus_snapshot(anemo::Request::new(FetchConsensusSnapshotRequest::default()))
+        .await
+        .unwrap()
+        .into_body();
+    assert_eq!(response, FetchConsensusSnapshotResponse::default());
+    assert!(handler
+        .fetch_consensus_snapshot(anemo::Request::new(FetchConsensusSnapshotRequest {
+            sub_dag_index: Some(1),
+            max_items: 100,
+        }))
+        .await
+        .is_err());
+
+    let genesis = genesis_certs
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+    let ids: Vec<_> = fixture
+        .authorities()
+        .map(|a| (a.id(), a.keypair().clone()))
+        .collect();
+    let (certificates, _) =
+        make_optimal_signed_certificates(1..=6, &genesis, &fixture.committee(), ids.as_slice());
+    let certificates = certificates.into_iter().collect_vec();
+    certificate_store.write_all(certificates.clone()).unwrap();
+
+    // Commit a leader at round 2 and at round 4.
+    let mut last_committed = HashMap::new();
+    let mut sub_dags = Vec::new();
+    for (index, leader_round) in [(1, 2), (2, 4)] {
+        let leader = certificates
+            .iter()
+            .find(|c| c.round() == leader_round)
+            .unwrap()
+            .clone();
+        let mut sub_dag_certificates = certificates
+            .iter()
+            .filter(|c| c.round() >= leader_round - 1 && c.round() < leader_round)
+            .cloned()
+            .collect_vec();
+        if leader_round == 4 {
+            sub_dag_certificates.extend(
+                certificates
+                    .iter()
+                    .filter(|c| c.round() == 2 && c.digest() != sub_dags[0].leader.digest())
+                    .cloned(),
+            );
+        }
+        sub_dag_certificates.push(leader.clone());
+        for certificate in &sub_dag_certificates {
+            let round = last_committed.entry(certificate.origin()).or_insert(0);
+            *round = (*round).max(certificate.round());
+        }
+        let sub_dag = CommittedSubDag::new(
+            sub_dag_certificates,
+            leader,
+            index,
+            ReputationScores::default(),
+            None,
+        );
+        consensus_store
+            .write_consensus_state(&last_committed, &sub_dag)
+            .unwrap();
+        sub_dags.push(sub_dag);
+    }
+
+    // The digests of both sub dags are returned, to cover the snapshot of the first one.
+    let response = handler
+        .fetch_consensus_snapshot(anemo::Request::new(FetchConsensusSnapshotRequest::default()))
+        .await
+        .unwrap()
+        .into_body();
+    assert_eq!(
+        response.sub_dag_digests,
+        sub_dags
+            .iter()
+            .map(|sub_dag| {
+                let commit = ConsensusCommit::V2(ConsensusCommitV2::from_sub_dag(sub_dag));
+                SubDagDigest {
+                    sub_dag_index: commit.sub_dag_index(),
+                    leader_round: commit.leader_round(),
+                    digest: commit.digest(),
+                }
+            })
+            .collect_vec()
+    );
+    assert!(response.sub_dags.is_empty());
+    assert!(response.certificates.is_empty());
+
+    // The snapshot is only served once the sub dag is executed, with the committee changes found
+    // up to it.
+    let request = FetchConsensusSnapshotRequest {
+        sub_dag_index: Some(2),
+        max_items: 100,
+    };
+    assert!(handler
+        .fetch_consensus_snapshot(anemo::Request::new(request.clone()))
+        .await
+        .is_err());
+    let committee_change = |sub_dag_index| SystemTransaction::ScheduleCommitteeChange {
+        change: ScheduledCommitteeChange {
+            epoch: 0,
+            sub_dag_index,
+            changes: Vec::new(),
+        },
+        signatures: Vec::new(),
+    };
+    execution_store
+        .write_committee_change(1, &committee_change(5))
+        .unwrap();
+    execution_store
+        .write_committee_change(3, &committee_change(6))
+        .unwrap();
+    execution_store
+        .write_last_executed(&ExecutionIndices::end_of_sub_dag(4, 2))
+        .unwrap();
+
+    // Only the sub dag above the GC round is returned, with its certificates at or below the GC
+    // round and all the certificates above it.
+    let response = handler
+        .fetch_consensus_snapshot(anemo::Request::new(request))
+        .await
+        .unwrap()
+        .into_body();
+    assert!(response.sub_dag_digests.is_empty());
+    assert_eq!(response.committee_changes, vec![(1, committee_change(5))]);
+    assert_eq!(
+        response.sub_dags,
+        vec![ConsensusCommit::V2(ConsensusCommitV2::from_sub_dag(
+            &sub_dags[1]
+        ))]
+    );
+    assert_eq!(
+        response
+            .certificates
+            .iter()
+            .map(|cert| cert.round())
+            .collect_vec(),
+        vec![2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 6, 6, 6, 6]
+    );
+
+    // The snapshot of an older sub dag skips the sub dags committed after it, and its number of
+    // certificates is bounded.
+    let response = handler
+        .fetch_consensus_snapshot(anemo::Request::new(FetchConsensusSnapshotRequest {
+            sub_dag_index: Some(1),
+            max_items: 10,
+        }))
+        .await
+        .unwrap()
+        .into_body();
+    assert_eq!(
+        response.sub_dags,
+        vec![ConsensusCommit::V2(ConsensusCommitV2::from_sub_dag(
+            &sub_dags[0]
+        ))]
+    );
+    assert_eq!(
+        response
+            .certificates
+            .iter()
+            .map(|cert| cert.round())
+            .collect_vec(),
+        vec![1, 1, 1, 1, 2, 2, 2, 2, 3, 3]
+    );
+
+    // The certificates of the sub dags do not fit in the snapshot.
+    assert!(handler
+        .fetch_consensus_snapshot(anemo::Request::new(FetchConsensusSnapshotRequest {
+            sub_dag_index: Some(1),
+            max_items: 3,
+        }))
+        .await
+        .is_err());
+}
+
 #[tokio::test]
 async fn test_process_payload_availability_success() {
     let fixture = CommitteeFixture::builder()
@@ -1059,6 +1341,7 @@ async fn test_process_payload_availability_success() {
         worker_cache.clone(),
         /* gc_depth */ 50,
         client,
+        header_store.clone(),
         certificate_store.clone(),
         payload_store.clone(),
         tx_certificate_fetcher,
@@ -1079,6 +1362,9 @@ async fn test_process_payload_availability_success() {
         certificate_store: certificate_store.clone(),
         payload_store: payload_store.clone(),
         vote_digest_store: VoteDigestStore::new_for_tests(),
+        consensus_store: Arc::new(ConsensusStore::new_for_tests()),
+        execution_store: ExecutionStore::new_for_tests(),
+        gc_depth: 50,
         rx_narwhal_round_updates,
         genesis_certs,
     };
@@ -1088,10 +1374,11 @@ async fn test_process_payload_availability_success() {
     let mut missing_certificates = HashSet::new();
 
     for i in 0..10 {
-        let header = Header::V1(
+        let header = Header::V2(
             author
                 .header_builder(&fixture.committee())
                 .with_payload_batch(test_utils::fixture_batch_with_transactions(10), 0, 0)
+                .signed(author.keypair().private())
                 .build(),
         );
 
@@ -1154,10 +1441,13 @@ async fn test_process_payload_availability_when_failures() {
     // GIVEN
     // We initialise the test stores manually to allow us
     // inject some wrongly serialised values to cause data store errors.
+    storage::init_db_metrics();
     let rocksdb = store::rocks::open_cf(
         temp_dir(),
         None,
         &[
+            test_utils::HEADERS_CF,
+            test_utils::HEADER_DIGEST_BY_ROUND_CF,
             test_utils::CERTIFICATES_CF,
             test_utils::CERTIFICATE_DIGEST_BY_ROUND_CF,
             test_utils::CERTIFICATE_DIGEST_BY_ORIGIN_CF,
@@ -1167,23 +1457,28 @@ async fn test_process_payload_availability_when_failures() {
     .expect("Failed creating database");
 
     let (
+        header_map,
+        header_digest_by_round_map,
         certificate_map,
         certificate_digest_by_round_map,
         certificate_digest_by_origin_map,
         payload_map,
     ) = store::reopen!(&rocksdb,
+        test_utils::HEADERS_CF;<HeaderDigest, Header>,
+        test_utils::HEADER_DIGEST_BY_ROUND_CF;<(Round, HeaderDigest), HeaderDigest>,
         test_utils::CERTIFICATES_CF;<CertificateDigest, Certificate>,
         test_utils::CERTIFICATE_DIGEST_BY_ROUND_CF;<(Round, AuthorityIdentifier), CertificateDigest>,
         test_utils::CERTIFICATE_DIGEST_BY_ORIGIN_CF;<(AuthorityIdentifier, Round), CertificateDigest>,
         test_utils::PAYLOAD_CF;<(BatchDigest, WorkerId), PayloadToken>);
 
     let certificate_store = CertificateStore::new(
-        certificate_map,
-        certificate_digest_by_round_map,
-        certificate_digest_by_origin_map,
+        certificate_map.into(),
+        certificate_digest_by_round_map.into(),
+        certificate_digest_by_origin_map.into(),
         CertificateStoreCache::new(NonZeroUsize::new(100).unwrap()),
     );
-    let payload_store = PayloadStore::new(payload_map);
+    let payload_store = PayloadStore::new(payload_map.into());
+    let header_store = HeaderStore::new(header_map.into(), header_digest_by_round_map.into(), 0);
 
     let fixture = CommitteeFixture::builder()
         .randomize_ports(true)
@@ -1197,7 +1492,6 @@ async fn test_process_payload_availability_when_failures() {
     let signature_service = SignatureService::new(*primary.keypair().private());
     let client = NetworkClient::new_from_keypair(&primary.network_keypair());
 
-    let (header_store, _, _) = create_db_stores();
     let (tx_certificate_fetcher, _rx_certificate_fetcher) = test_utils::test_channel!(1);
     let (tx_new_certificates, _rx_new_certificates) = test_utils::test_channel!(100);
     let (tx_parents, _rx_parents) = test_utils::test_channel!(100);
@@ -1215,6 +1509,7 @@ async fn test_process_payload_availability_when_failures() {
         worker_cache.clone(),
         /* gc_depth */ 50,
         client,
+        header_store.clone(),
         certificate_store.clone(),
         payload_store.clone(),
         tx_certificate_fetcher,
@@ -1235,6 +1530,9 @@ async fn test_process_payload_availability_when_failures() {
         certificate_store: certificate_store.clone(),
         payload_store: payload_store.clone(),
         vote_digest_store: VoteDigestStore::new_for_tests(),
+        consensus_store: Arc::new(ConsensusStore::new_for_tests()),
+        execution_store: ExecutionStore::new_for_tests(),
+        gc_depth: 50,
         rx_narwhal_round_updates,
         genesis_certs,
     };
@@ -1242,10 +1540,11 @@ async fn test_process_payload_availability_when_failures() {
     // AND some mock certificates
     let mut certificate_digests = Vec::new();
     for _ in 0..10 {
-        let header = Header::V1(
+        let header = Header::V2(
             author
                 .header_builder(&committee)
                 .with_payload_batch(test_utils::fixture_batch_with_transactions(10), 0, 0)
+                .signed(author.keypair().private())
                 .build(),
         );
 
@@ -1319,6 +1618,7 @@ async fn test_request_vote_created_at_in_future() {
         worker_cache.clone(),
         /* gc_depth */ 50,
         client.clone(),
+        header_store.clone(),
         certificate_store.clone(),
         payload_store.clone(),
         tx_certificate_fetcher,
@@ -1339,6 +1639,9 @@ async fn test_request_vote_created_at_in_future() {
         certificate_store: certificate_store.clone(),
         payload_store: payload_store.clone(),
         vote_digest_store: VoteDigestStore::new_for_tests(),
+        consensus_store: Arc::new(ConsensusStore::new_for_tests()),
+        execution_store: ExecutionStore::new_for_tests(),
+        gc_depth: 50,
         rx_narwhal_round_updates,
         genesis_certs,
     };
@@ -1346,10 +1649,11 @@ async fn test_request_vote_created_at_in_future() {
     // Make some mock certificates that are parents of our new header.
     let mut certificates = HashMap::new();
     for primary in fixture.authorities().filter(|a| a.id() != id) {
-        let header = Header::V1(
+        let header = Header::V2(
             primary
                 .header_builder(&fixture.committee())
                 .with_payload_batch(test_utils::fixture_batch_with_transactions(10), 0, 0)
+                .signed(primary.keypair().private())
                 .build(),
         );
 
@@ -1387,13 +1691,14 @@ async fn test_request_vote_created_at_in_future() {
     // Set the creation time to be deep in the future (an hour)
     let created_at = now() + 60 * 60 * 1000;
 
-    let test_header = Header::V1(
+    let test_header = Header::V2(
         author
             .header_builder(&fixture.committee())
             .round(2)
             .parents(certificates.keys().cloned().collect())
             .with_payload_batch(test_utils::fixture_batch_with_transactions(10), 1, 0)
             .created_at(created_at)
+            .signed(author.keypair().private())
             .build(),
     );
 
@@ -1424,10 +1729,11 @@ async fn test_request_vote_created_at_in_future() {
         .parents(certificates.keys().cloned().collect())
         .with_payload_batch(test_utils::fixture_batch_with_transactions(10), 1, 0)
         .created_at(created_at)
+        .signed(author.keypair().private())
         .build();
 
     let mut request = anemo::Request::new(RequestVoteRequest {
-        header: Header::V1(test_header.clone()),
+        header: Header::V2(test_header.clone()),
         parents: Vec::new(),
     });
     assert!(request
@@ -3,7 +3,7 @@
 // SPDX-License-Identifier: Apache-2.0
 use super::*;
 use crate::NUM_SHUTDOWN_RECEIVERS;
-use crypto::Digest;
+use crypto::{Digest, SignatureService};
 use indexmap::IndexMap;
 use test_utils::{fixture_payload, CommitteeFixture};
 use types::PreSubscribedBroadcastSender;
@@ -20,7 +20,7 @@ async fn propose_empty() {
 
     let mut tx_shutdown = PreSubscribedBroadcastSender::new(NUM_SHUTDOWN_RECEIVERS);
     let (_tx_parents, rx_parents) = test_utils::test_channel!(1);
-    let (_tx_committed_own_headers, rx_committed_own_headers) = test_utils::test_channel!(1);
+    let (_tx_committed_headers, rx_committed_headers) = test_utils::test_channel!(1);
     let (_tx_our_digests, rx_our_digests) = test_utils::test_channel!(1);
     let (tx_headers, mut rx_headers) = test_utils::test_channel!(1);
     let (tx_narwhal_round_updates, _rx_narwhal_round_updates) = watch::channel(0u64);
@@ -30,18 +30,22 @@ async fn propose_empty() {
         name,
         committee.clone(),
         ProposerStore::new_for_tests(),
+        SignatureService::new(*primary.keypair().private()),
         /* header_num_of_batches_threshold */ 32,
         /* max_header_num_of_batches */ 100,
+        /* max_header_num_of_weak_links */ 0,
+        /* gc_depth */ 50,
         /* max_header_delay */ Duration::from_millis(20),
         /* min_header_delay */ Duration::from_millis(20),
         None,
+        AdaptiveHeaderParameters::default(),
         NetworkModel::PartiallySynchronous,
         tx_shutdown.subscribe(),
         /* rx_core */ rx_parents,
         /* rx_workers */ rx_our_digests,
         /* tx_core */ tx_headers,
         tx_narwhal_round_updates,
-        rx_committed_own_headers,
+        rx_committed_headers,
         genesis_certs,
     );
 
@@ -49,6 +53,7 @@ async fn propose_empty() {
     let header = rx_headers.recv().await.unwrap();
     assert_eq!(header.round(), 1);
     assert!(header.payload().is_empty());
+    assert!(header.randomness_share().is_some());
     assert!(header.validate(&committee, &worker_cache).is_ok());
 }
 
@@ -66,7 +71,7 @@ async fn propose_payload_and_repropose_after_n_seconds() {
     let mut tx_shutdown = PreSubscribedBroadcastSender::new(NUM_SHUTDOWN_RECEIVERS);
     let (tx_parents, rx_parents) = test_utils::test_channel!(1);
     let (tx_our_digests, rx_our_digests) = test_utils::test_channel!(1);
-    let (_tx_committed_own_headers, rx_committed_own_headers) = test_utils::test_channel!(1);
+    let (_tx_committed_headers, rx_committed_headers) = test_utils::test_channel!(1);
     let (tx_headers, mut rx_headers) = test_utils::test_channel!(1);
     let (tx_narwhal_round_updates, _rx_narwhal_round_updates) = watch::channel(0u64);
 
@@ -77,20 +82,24 @@ async fn propose_payload_and_repropose_after_n_seconds() {
         name,
         committee.clone(),
         ProposerStore::new_for_tests(),
+        SignatureService::new(*primary.keypair().private()),
         /* header_num_of_batches_threshold */ 1,
         /* max_header_num_of_batches */ max_num_of_batches,
+        /* max_header_num_of_weak_links */ 0,
+        /* gc_depth */ 50,
         /* max_header_delay */
         Duration::from_millis(1_000_000), // Ensure it is not triggered.
         /* min_header_delay */
         Duration::from_millis(1_000_000), // Ensure it is not triggered.
         Some(header_resend_delay),
+        AdaptiveHeaderParameters::default(),
         NetworkModel::PartiallySynchronous,
         tx_shutdown.subscribe(),
         /* rx_core */ rx_parents,
         /* rx_workers */ rx_our_digests,
         /* tx_core */ tx_headers,
         tx_narwhal_round_updates,
-        rx_committed_own_headers,
+        rx_committed_headers,
         genesis_certs,
     );
 
@@ -193,27 +202,31 @@ async fn equivocation_protection() {
     let (tx_our_digests, rx_our_digests) = test_utils::test_channel!(1);
     let (tx_headers, mut rx_headers) = test_utils::test_channel!(1);
     let (tx_narwhal_round_updates, _rx_narwhal_round_updates) = watch::channel(0u64);
-    let (_tx_committed_own_headers, rx_committed_own_headers) = test_utils::test_channel!(1);
+    let (_tx_committed_headers, rx_committed_headers) = test_utils::test_channel!(1);
 
     // Spawn the proposer.
     let proposer_handle = Proposer::spawn(
         authority_id,
         committee.clone(),
         proposer_store.clone(),
+        SignatureService::new(*primary.keypair().private()),
         /* header_num_of_batches_threshold */ 1,
         /* max_header_num_of_batches */ 10,
+        /* max_header_num_of_weak_links */ 0,
+        /* gc_depth */ 50,
         /* max_header_delay */
         Duration::from_millis(1_000_000), // Ensure it is not triggered.
         /* min_header_delay */
         Duration::from_millis(1_000_000), // Ensure it is not triggered.
         None,
+        AdaptiveHeaderParameters::default(),
         NetworkModel::PartiallySynchronous,
         tx_shutdown.subscribe(),
         /* rx_core */ rx_parents,
         /* rx_workers */ rx_our_digests,
         /* tx_core */ tx_headers,
         tx_narwhal_round_updates,
-        rx_committed_own_headers,
+        rx_committed_headers,
         genesis_certs.clone(),
     );
 
@@ -265,26 +278,30 @@ async fn equivocation_protection() {
     let (tx_our_digests, rx_our_digests) = test_utils::test_channel!(1);
     let (tx_headers, mut rx_headers) = test_utils::test_channel!(1);
     let (tx_narwhal_round_updates, _rx_narwhal_round_updates) = watch::channel(0u64);
-    let (_tx_committed_own_headers, rx_committed_own_headers) = test_utils::test_channel!(1);
+    let (_tx_committed_headers, rx_committed_headers) = test_utils::test_channel!(1);
 
     let _proposer_handle = Proposer::spawn(
         authority_id,
         committee.clone(),
         proposer_store,
+        SignatureService::new(*primary.keypair().private()),
         /* header_num_of_batches_threshold */ 1,
         /* max_header_num_of_batches */ 10,
+        /* max_header_num_of_weak_links */ 0,
+        /* gc_depth */ 50,
         /* max_header_delay */
         Duration::from_millis(1_000_000), // Ensure it is not triggered.
         /* min_header_delay */
         Duration::from_millis(1_000_000), // Ensure it is not triggered.
         None,
+        AdaptiveHeaderParameters::default(),
         NetworkModel::PartiallySynchronous,
         tx_shutdown.subscribe(),
         /* rx_core */ rx_parents,
         /* rx_workers */ rx_our_digests,
         /* tx_core */ tx_headers,
         tx_narwhal_round_updates,
-        rx_committed_own_headers,
+        rx_committed_headers,
         genesis_certs,
     );
 
@@ -324,3 +341,355 @@ async fn equivocation_protection() {
         assert_eq!(header, new_header);
     }
 }
+
+#[tokio::test]
+async fn recover_uncommitted_headers() {
+    let fixture = CommitteeFixture::builder().build();
+    let committee = fixture.committee();
+    let primary = fixture.authorities().next().unwrap();
+    let genesis_certs = Certificate::genesis(&committee, primary.keypair().private());
+    let authority_id = primary.id();
+
+    // A header proposed before a restart, which was never committed.
+    let proposer_store = ProposerStore::new_for_tests();
+    let header = Header::V2(
+        primary
+            .header_builder(&committee)
+            .round(1)
+            .with_payload_batch(test_utils::fixture_batch_with_transactions(10), 0, 0)
+            .signed(primary.keypair().private())
+            .build(),
+    );
+    let digest = *header.payload().keys().next().unwrap();
+    proposer_store.write_last_proposed(&header, 50).unwrap();
+
+    let mut tx_shutdown = PreSubscribedBroadcastSender::new(NUM_SHUTDOWN_RECEIVERS);
+    let (tx_parents, rx_parents) = test_utils::test_channel!(1);
+    let (_tx_our_digests, rx_our_digests) = test_utils::test_channel!(1);
+    let (tx_headers, mut rx_headers) = test_utils::test_channel!(1);
+    let (tx_narwhal_round_updates, _rx_narwhal_round_updates) = watch::channel(0u64);
+    let (tx_committed_headers, rx_committed_headers) = test_utils::test_channel!(1);
+
+    let _proposer_handle = Proposer::spawn(
+        authority_id,
+        committee.clone(),
+        proposer_store.clone(),
+        SignatureService::new(*primary.keypair().private()),
+        /* header_num_of_batches_threshold */ 1,
+        /* max_header_num_of_batches */ 10,
+        /* max_header_num_of_weak_links */ 0,
+        /* gc_depth */ 50,
+        /* max_header_delay */
+        Duration::from_millis(1_000_000), // Ensure it is not triggered.
+        /* min_header_delay */
+        Duration::from_millis(1_000_000), // Ensure it is not triggered.
+        None,
+        AdaptiveHeaderParameters::default(),
+        NetworkModel::PartiallySynchronous,
+        tx_shutdown.subscribe(),
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        /* tx_core */ tx_headers,
+        tx_narwhal_round_updates,
+        rx_committed_headers,
+        genesis_certs,
+    );
+
+    // The recovered header is re-broadcast.
+    assert_eq!(rx_headers.recv().await.unwrap(), header);
+
+    // A later header of ours is committed, so the batch of the recovered header is included
+    // again in the next header.
+    let committed = fixture.certificate(&Header::V2(
+        primary
+            .header_builder(&committee)
+            .round(2)
+            .signed(primary.keypair().private())
+            .build(),
+    ));
+    tx_committed_headers
+        .send((3, vec![committed]))
+        .await
+        .unwrap();
+    let parents: Vec<_> = fixture
+        .headers()
+        .iter()
+        .take(3)
+        .map(|h| fixture.certificate(h))
+        .collect();
+    tx_parents.send((parents, 1, 0)).await.unwrap();
+
+    let new_header = rx_headers.recv().await.unwrap();
+    assert_eq!(new_header.round(), 2);
+    assert!(new_header.payload().contains_key(&digest));
+
+    // Only the new header is left in the history.
+    assert_eq!(
+        proposer_store.read_proposed_headers().unwrap(),
+        vec![new_header]
+    );
+}
+
+#[tokio::test]
+async fn recover_pending_digests_after_crash() {
+    let fixture = CommitteeFixture::builder().build();
+    let committee = fixture.committee();
+    let primary = fixture.authorities().next().unwrap();
+    let genesis_certs = Certificate::genesis(&committee, primary.keypair().private());
+    let authority_id = primary.id();
+    let proposer_store = ProposerStore::new_for_tests();
+
+    let spawn_proposer = |header_num_of_batches_threshold,
+                          tx_shutdown: &mut PreSubscribedBroadcastSender| {
+        let (tx_parents, rx_parents) = test_utils::test_channel!(1);
+        let (tx_our_digests, rx_our_digests) = test_utils::test_channel!(1);
+        let (tx_headers, rx_headers) = test_utils::test_channel!(1);
+        let (tx_narwhal_round_updates, _rx_narwhal_round_updates) = watch::channel(0u64);
+        let (tx_committed_headers, rx_committed_headers) = test_utils::test_channel!(1);
+        let handle = Proposer::spawn(
+            authority_id,
+            committee.clone(),
+            proposer_store.clone(),
+            SignatureService::new(*primary.keypair().private()),
+            header_num_of_batches_threshold,
+            /* max_header_num_of_batches */ 10,
+            /* max_header_num_of_weak_links */ 0,
+            /* gc_depth */ 50,
+            /* max_header_delay */
+            Duration::from_millis(1_000_000), // Ensure it is not triggered.
+            /* min_header_delay */
+            Duration::from_millis(1_000_000), // Ensure it is not triggered.
+            None,
+            AdaptiveHeaderParameters::default(),
+            NetworkModel::PartiallySynchronous,
+            tx_shutdown.subscribe(),
+            /* rx_core */ rx_parents,
+            /* rx_workers */ rx_our_digests,
+            /* tx_core */ tx_headers,
+            tx_narwhal_round_updates,
+            rx_committed_headers,
+            genesis_certs.clone(),
+        );
+        (
+            handle,
+            tx_parents,
+            tx_our_digests,
+            rx_headers,
+            tx_committed_headers,
+        )
+    };
+
+    // The first proposer acknowledges the digests of our workers, but does not have enough of
+    // them to propose a header before it crashes.
+    let mut tx_shutdown = PreSubscribedBroadcastSender::new(NUM_SHUTDOWN_RECEIVERS);
+    let (handle, _tx_parents, tx_our_digests, _rx_headers, _tx_committed_headers) =
+        spawn_proposer(10, &mut tx_shutdown);
+    let batches = fixture_payload(3);
+    for (digest, (worker_id, timestamp)) in &batches {
+        let (tx_ack, rx_ack) = tokio::sync::oneshot::channel();
+        tx_our_digests
+            .send(OurDigestMessage {
+                digest: *digest,
+                worker_id: *worker_id,
+                timestamp: *timestamp,
+                ack_channel: Some(tx_ack),
+            })
+            .await
+            .unwrap();
+        rx_ack.await.unwrap();
+    }
+    tx_shutdown.send().unwrap();
+    handle.await.unwrap();
+
+    // After the restart, the acknowledged digests are included in the next header.
+    let mut tx_shutdown = PreSubscribedBroadcastSender::new(NUM_SHUTDOWN_RECEIVERS);
+    let (_handle, _tx_parents, _tx_our_digests, mut rx_headers, _tx_committed_headers) =
+        spawn_proposer(3, &mut tx_shutdown);
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round(), 1);
+    assert_eq!(header.payload(), &batches);
+
+    // The digests are not pending anymore once the header is stored.
+    assert!(proposer_store.read_pending_digests().unwrap().is_empty());
+    assert_eq!(
+        proposer_store.read_proposed_headers().unwrap(),
+        vec![header]
+    );
+}
+
+#[tokio::test]
+async fn stops_waiting_for_leader_after_commit_latency() {
+    let fixture = CommitteeFixture::builder().build();
+    let committee = fixture.committee();
+    let ids: Vec<_> = fixture.authorities().map(|a| a.id()).collect();
+    let leader = committee.leader(2).id();
+    // Neither the leader of round 2, nor of round 3 which would not wait for the leader.
+    let primary = fixture
+        .authorities()
+        .find(|a| a.id() != leader && a.id() != committee.leader(3).id())
+        .unwrap();
+    let genesis_certs = Certificate::genesis(&committee, primary.keypair().private());
+    let name = primary.id();
+
+    let mut tx_shutdown = PreSubscribedBroadcastSender::new(NUM_SHUTDOWN_RECEIVERS);
+    let (tx_parents, rx_parents) = test_utils::test_channel!(1);
+    let (tx_our_digests, rx_our_digests) = test_utils::test_channel!(1);
+    let (tx_committed_headers, rx_committed_headers) = test_utils::test_channel!(1);
+    let (tx_headers, mut rx_headers) = test_utils::test_channel!(1);
+    let (tx_narwhal_round_updates, _rx_narwhal_round_updates) = watch::channel(0u64);
+
+    // Spawn the proposer.
+    let _proposer_handle = Proposer::spawn(
+        name,
+        committee.clone(),
+        ProposerStore::new_for_tests(),
+        SignatureService::new(*primary.keypair().private()),
+        /* header_num_of_batches_threshold */ 1,
+        /* max_header_num_of_batches */ 10,
+        /* max_header_num_of_weak_links */ 0,
+        /* gc_depth */ 50,
+        /* max_header_delay */
+        Duration::from_millis(1_000_000), // Ensure it is not triggered.
+        /* min_header_delay */ Duration::from_millis(200),
+        None,
+        AdaptiveHeaderParameters::default(),
+        NetworkModel::PartiallySynchronous,
+        tx_shutdown.subscribe(),
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        /* tx_core */ tx_headers,
+        tx_narwhal_round_updates,
+        rx_committed_headers,
+        genesis_certs.clone(),
+    );
+
+    // Propose a header for round 1.
+    let (tx_ack, rx_ack) = tokio::sync::oneshot::channel();
+    tx_our_digests
+        .send(OurDigestMessage {
+            digest: BatchDigest::default(),
+            worker_id: 0,
+            timestamp: 0,
+            ack_channel: Some(tx_ack),
+        })
+        .await
+        .unwrap();
+    assert!(rx_ack.await.is_ok());
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round(), 1);
+
+    // Commit it right away, so the leader timeout drops to the min header delay.
+    tx_committed_headers
+        .send((1, vec![fixture.certificate(&header)]))
+        .await
+        .unwrap();
+    // Give the proposer time to process the commit before the parents.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // Send a quorum of round 2 parents without the leader of round 2.
+    let genesis = genesis_certs.iter().map(|c| c.digest()).collect();
+    let (certificates, _) =
+        test_utils::make_optimal_certificates(&committee, 1..=2, &genesis, &ids);
+    let parents: Vec<_> = certificates
+        .into_iter()
+        .filter(|c| c.round() == 2 && c.origin() != leader)
+        .collect();
+    tx_parents.send((parents, 2, 0)).await.unwrap();
+
+    // The proposer gives up on the leader well before the max header delay.
+    let header = tokio::time::timeout(Duration::from_secs(10), rx_headers.recv())
+        .await
+        .expect("proposer kept waiting for the leader")
+        .unwrap();
+    assert_eq!(header.round(), 3);
+    assert!(header.parents().len() >= committee.quorum_threshold() as usize);
+}
+
+#[tokio::test]
+async fn weakly_links_uncommitted_certificates() {
+    let fixture = CommitteeFixture::builder().build();
+    let committee = fixture.committee();
+    let ids: Vec<_> = fixture.authorities().map(|a| a.id()).collect();
+    let primary = fixture.authorities().next().unwrap();
+    let genesis_certs = Certificate::genesis(&committee, primary.keypair().private());
+    let name = primary.id();
+
+    let mut tx_shutdown = PreSubscribedBroadcastSender::new(NUM_SHUTDOWN_RECEIVERS);
+    let (tx_parents, rx_parents) = test_utils::test_channel!(1);
+    let (_tx_our_digests, rx_our_digests) = test_utils::test_channel!(1);
+    let (tx_committed_headers, rx_committed_headers) = test_utils::test_channel!(1);
+    let (tx_headers, mut rx_headers) = test_utils::test_channel!(1);
+    let (tx_narwhal_round_updates, _rx_narwhal_round_updates) = watch::channel(0u64);
+
+    // Spawn the proposer.
+    let _proposer_handle = Proposer::spawn(
+        name,
+        committee.clone(),
+        ProposerStore::new_for_tests(),
+        SignatureService::new(*primary.keypair().private()),
+        /* header_num_of_batches_threshold */ 32,
+        /* max_header_num_of_batches */ 100,
+        /* max_header_num_of_weak_links */ 10,
+        /* gc_depth */ 50,
+        /* max_header_delay */ Duration::from_millis(20),
+        /* min_header_delay */ Duration::from_millis(20),
+        None,
+        AdaptiveHeaderParameters::default(),
+        NetworkModel::Asynchronous,
+        tx_shutdown.subscribe(),
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        /* tx_core */ tx_headers,
+        tx_narwhal_round_updates,
+        rx_committed_headers,
+        genesis_certs.clone(),
+    );
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round(), 1);
+
+    // The certificate of the last authority at round 1 is not referenced by round 2, so the
+    // leader of round 2 does not reach it.
+    let genesis = genesis_certs.iter().map(|c| c.digest()).collect();
+    let (round_1, parents) =
+        test_utils::make_optimal_certificates(&committee, 1..=1, &genesis, &ids);
+    let orphan = round_1
+        .iter()
+        .find(|c| c.origin() == ids[3])
+        .unwrap()
+        .digest();
+    let parents = parents.into_iter().filter(|d| *d != orphan).collect();
+    let (round_2, parents) =
+        test_utils::make_optimal_certificates(&committee, 2..=2, &parents, &ids);
+    let (round_3, _) = test_utils::make_optimal_certificates(&committee, 3..=3, &parents, &ids);
+    let leader = committee.leader(2).id();
+    let committed: Vec<_> = round_1
+        .iter()
+        .filter(|c| c.digest() != orphan)
+        .chain(round_2.iter().filter(|c| c.origin() == leader))
+        .cloned()
+        .collect();
+
+    for (round, certificates) in [(1, round_1), (2, round_2)] {
+        tx_parents
+            .send((certificates.into_iter().collect(), round, 0))
+            .await
+            .unwrap();
+        let header = rx_headers.recv().await.unwrap();
+        assert_eq!(header.round(), round + 1);
+
+        // Nothing is linked before consensus commits the leader of round 2.
+        assert!(header.weak_links().is_empty());
+    }
+
+    // Once the leader of round 2 is committed without it, the orphan is weakly linked.
+    tx_committed_headers.send((2, committed)).await.unwrap();
+    // Give the proposer time to process the commit before the parents.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    tx_parents
+        .send((round_3.into_iter().collect(), 3, 0))
+        .await
+        .unwrap();
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round(), 4);
+    assert_eq!(header.weak_links(), &BTreeMap::from([(orphan, 1)]));
+}
@@ -0,0 +1,337 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use super::*;
+use crate::common::create_db_stores;
+use std::collections::BTreeSet;
+use test_utils::{make_optimal_certificates, make_optimal_signed_certificates, CommitteeFixture};
+use types::{
+    CommittedSubDag, CommitteeChange, ConsensusCommitV2, ReputationScores, ScheduledCommitteeChange,
+};
+
+const GC_DEPTH: Round = 50;
+
+fn snapshot_sync(fixture: &CommitteeFixture, gc_depth: Round) -> SnapshotSync {
+    let committee = fixture.committee();
+    let (_, certificate_store, _) = create_db_stores();
+    SnapshotSync::new(
+        fixture.authorities().next().unwrap().id(),
+        committee.clone(),
+        fixture.worker_cache(),
+        gc_depth,
+        SnapshotSyncParameters::default(),
+        certificate_store,
+        Arc::new(ConsensusStore::new_for_tests()),
+        ExecutionStore::new_for_tests(),
+        genesis(fixture),
+    )
+}
+
+fn genesis(fixture: &CommitteeFixture) -> Vec<Certificate> {
+    let keypair = fixture.authorities().next().unwrap().keypair().copy();
+    Certificate::genesis(&fixture.committee(), keypair.private())
+}
+
+/// Returns the signed certificates of `rounds`, each round referencing all the certificates of
+/// the previous one.
+fn certificates(fixture: &CommitteeFixture, rounds: Round) -> Vec<Certificate> {
+    let committee = fixture.committee();
+    let genesis = genesis(fixture)
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+    let keys: Vec<_> = fixture
+        .authorities()
+        .map(|a| (a.id(), a.keypair().clone()))
+        .collect();
+    let (certificates, _) =
+        make_optimal_signed_certificates(1..=rounds, &genesis, &committee, keys.as_slice());
+    certificates.into_iter().collect()
+}
+
+/// Returns the sub dags of a peer which committed a leader at every even round up to
+/// `last_leader_round`, along with the last committed round of each authority.
+fn sub_dags(
+    certificates: &[Certificate],
+    last_leader_round: Round,
+) -> (Vec<ConsensusCommit>, HashMap<AuthorityIdentifier, Round>) {
+    let mut sub_dags = Vec::new();
+    let mut last_committed = HashMap::new();
+    let mut committed_round = 0;
+    for leader_round in (2..=last_leader_round).step_by(2) {
+        let leader = certificates
+            .iter()
+            .find(|c| c.round() == leader_round)
+            .unwrap()
+            .clone();
+        let mut sub_dag_certificates = certificates
+            .iter()
+            .filter(|c| c.round() > committed_round && c.round() < leader_round)
+            .cloned()
+            .collect::<Vec<_>>();
+        sub_dag_certificates.push(leader.clone());
+        for certificate in &sub_dag_certificates {
+            let round = last_committed.entry(certificate.origin()).or_insert(0);
+            *round = (*round).max(certificate.round());
+        }
+
+        let sub_dag = CommittedSubDag::new(
+            sub_dag_certificates,
+            leader,
+            sub_dags.len() as SequenceNumber + 1,
+            ReputationScores::default(),
+            None,
+        );
+        sub_dags.push(ConsensusCommit::V2(ConsensusCommitV2::from_sub_dag(
+            &sub_dag,
+        )));
+        committed_round = leader_round;
+    }
+    (sub_dags, last_committed)
+}
+
+/// Returns a change of the committee scheduled at `sub_dag_index`, signed by the `signers`.
+fn committee_change(
+    fixture: &CommitteeFixture,
+    sub_dag_index: SequenceNumber,
+    signers: usize,
+) -> SystemTransaction {
+    let authorities = fixture.authorities().collect::<Vec<_>>();
+    let change = ScheduledCommitteeChange {
+        epoch: fixture.committee().epoch(),
+        sub_dag_index,
+        changes: vec![CommitteeChange::UpdateStake {
+            protocol_key: authorities[0].public_key(),
+            stake: 2,
+        }],
+    };
+    SystemTransaction::ScheduleCommitteeChange {
+        signatures: authorities[..signers]
+            .iter()
+            .map(|authority| (authority.id(), change.sign(authority.keypair().private())))
+            .collect(),
+        change,
+    }
+}
+
+fn digests(sub_dags: &[ConsensusCommit]) -> Vec<SubDagDigest> {
+    sub_dags
+        .iter()
+        .map(|sub_dag| SubDagDigest {
+            sub_dag_index: sub_dag.sub_dag_index(),
+            leader_round: sub_dag.leader_round(),
+            digest: sub_dag.digest(),
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_select_sub_dags_agreed_by_validity_threshold() {
+    let fixture = CommitteeFixture::builder().build();
+    let ids: Vec<_> = fixture.authorities().map(|a| a.id()).collect();
+    let sync = snapshot_sync(&fixture, GC_DEPTH);
+    let (sub_dags, _) = sub_dags(&certificates(&fixture, 6), 4);
+    let digests = digests(&sub_dags);
+
+    // A single peer is not trusted.
+    assert!(sync
+        .select_sub_dags(vec![(ids[1], digests.clone())])
+        .is_err());
+
+    let (selected, mut peers) = sync
+        .select_sub_dags(vec![(ids[1], digests.clone()), (ids[2], digests.clone())])
+        .unwrap()
+        .unwrap();
+    assert_eq!(selected, digests);
+    peers.sort();
+    assert_eq!(peers, vec![ids[1], ids[2]]);
+}
+
+#[tokio::test]
+async fn test_select_most_recent_sub_dags() {
+    let fixture = CommitteeFixture::builder().build();
+    let ids: Vec<_> = fixture.authorities().map(|a| a.id()).collect();
+    let sync = snapshot_sync(&fixture, GC_DEPTH);
+    let certificates = certificates(&fixture, 6);
+    let behind = digests(&sub_dags(&certificates, 2).0);
+    let ahead = digests(&sub_dags(&certificates, 6).0);
+
+    let (selected, mut peers) = sync
+        .select_sub_dags(vec![
+            (ids[0], behind.clone()),
+            (ids[1], ahead.clone()),
+            (ids[2], behind),
+            (ids[3], ahead.clone()),
+        ])
+        .unwrap()
+        .unwrap();
+    assert_eq!(selected, ahead);
+    peers.sort();
+    assert_eq!(peers, vec![ids[1], ids[3]]);
+}
+
+#[tokio::test]
+async fn test_select_complete_sub_dags() {
+    let fixture = CommitteeFixture::builder().build();
+    let ids: Vec<_> = fixture.authorities().map(|a| a.id()).collect();
+    let certificates = certificates(&fixture, 6);
+    let digests = digests(&sub_dags(&certificates, 6).0);
+    let partial = digests[1..].to_vec();
+
+    // The first sub dag is above the GC round of the last one, but nobody sent it.
+    let sync = snapshot_sync(&fixture, GC_DEPTH);
+    assert!(sync
+        .select_sub_dags(vec![(ids[1], partial.clone()), (ids[2], partial.clone())])
+        .is_err());
+
+    // With a shorter GC depth, the snapshot of the last sub dag only holds it, and the sub dag
+    // before it shows that none is missing.
+    let sync = snapshot_sync(&fixture, 2);
+    let (selected, _) = sync
+        .select_sub_dags(vec![(ids[1], partial.clone()), (ids[2], partial)])
+        .unwrap()
+        .unwrap();
+    assert_eq!(selected, digests[2..].to_vec());
+}
+
+#[tokio::test]
+async fn test_select_nothing_committed() {
+    let fixture = CommitteeFixture::builder().build();
+    let ids: Vec<_> = fixture.authorities().map(|a| a.id()).collect();
+    let sync = snapshot_sync(&fixture, GC_DEPTH);
+
+    let selected = sync
+        .select_sub_dags(vec![(ids[1], Vec::new()), (ids[2], Vec::new())])
+        .unwrap();
+    assert!(selected.is_none());
+}
+
+#[tokio::test]
+async fn test_verify_and_write_snapshot() {
+    let fixture = CommitteeFixture::builder().build();
+    let sync = snapshot_sync(&fixture, GC_DEPTH);
+    let certificates = certificates(&fixture, 6);
+    let (sub_dags, last_committed) = sub_dags(&certificates, 4);
+    let change = committee_change(&fixture, 10, 3);
+    let response = FetchConsensusSnapshotResponse {
+        sub_dag_digests: Vec::new(),
+        sub_dags: sub_dags.clone(),
+        certificates: certificates.clone(),
+        committee_changes: vec![(1, change.clone())],
+    };
+
+    let snapshot = sync.verify_snapshot(response, &digests(&sub_dags)).unwrap();
+    assert_eq!(snapshot.last_committed, last_committed);
+    assert_eq!(sync.write_snapshot(snapshot).unwrap(), 2);
+
+    assert_eq!(
+        sync.consensus_store.get_latest_sub_dag(),
+        sub_dags.last().cloned()
+    );
+    assert_eq!(sync.consensus_store.read_last_committed(), last_committed);
+    for certificate in &certificates {
+        assert!(sync
+            .certificate_store
+            .read(certificate.digest())
+            .unwrap()
+            .is_some());
+    }
+    // The executor resumes after the synced sub dag, knowing the committee change found before.
+    assert_eq!(
+        sync.execution_store.read_last_executed().unwrap(),
+        Some(ExecutionIndices::end_of_sub_dag(4, 2))
+    );
+    assert_eq!(
+        sync.execution_store
+            .read_committee_changes()
+            .unwrap()
+            .into_iter()
+            .collect::<Vec<_>>(),
+        vec![(1, change)]
+    );
+}
+
+#[tokio::test]
+async fn test_reject_invalid_snapshots() {
+    let fixture = CommitteeFixture::builder().build();
+    let committee = fixture.committee();
+    let ids: Vec<_> = fixture.authorities().map(|a| a.id()).collect();
+    let sync = snapshot_sync(&fixture, GC_DEPTH);
+    let certificates = certificates(&fixture, 6);
+    let (sub_dags, _) = sub_dags(&certificates, 4);
+    let digests = digests(&sub_dags);
+    let valid = FetchConsensusSnapshotResponse {
+        sub_dag_digests: Vec::new(),
+        sub_dags: sub_dags.clone(),
+        certificates: certificates.clone(),
+        committee_changes: Vec::new(),
+    };
+    assert!(sync.verify_snapshot(valid.clone(), &digests).is_ok());
+
+    // The leader of the last sub dag is missing.
+    let mut missing_leader = valid.clone();
+    let leader = sub_dags.last().unwrap().leader();
+    missing_leader
+        .certificates
+        .retain(|certificate| certificate.digest() != leader);
+
+    // The last sub dag does not match the agreed digest.
+    let mut tampered = valid.clone();
+    if let Some(ConsensusCommit::V2(sub_dag)) = tampered.sub_dags.last_mut() {
+        sub_dag.reputation_score.add_score(ids[0], 1);
+    }
+
+    // A sub dag is missing.
+    let mut missing_sub_dag = valid.clone();
+    missing_sub_dag.sub_dags.remove(0);
+
+    // A committee change is not signed by a quorum, or is found after the snapshot.
+    let mut unsigned_change = valid.clone();
+    unsigned_change.committee_changes = vec![(1, committee_change(&fixture, 10, 2))];
+    let mut late_change = valid.clone();
+    late_change.committee_changes = vec![(3, committee_change(&fixture, 10, 3))];
+
+    // The certificates are not signed.
+    let genesis = genesis(&fixture)
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+    let (unsigned, _) = make_optimal_certificates(&committee, 1..=6, &genesis, &ids);
+    let unsigned = FetchConsensusSnapshotResponse {
+        certificates: unsigned.into_iter().collect(),
+        ..valid
+    };
+
+    for invalid in [
+        missing_leader,
+        tampered,
+        missing_sub_dag,
+        unsigned_change,
+        late_change,
+        unsigned,
+    ] {
+        assert!(sync.verify_snapshot(invalid, &digests).is_err());
+    }
+}
+
+#[tokio::test]
+async fn test_verify_drops_certificates_with_missing_parents() {
+    let fixture = CommitteeFixture::builder().build();
+    let ids: Vec<_> = fixture.authorities().map(|a| a.id()).collect();
+    let sync = snapshot_sync(&fixture, GC_DEPTH);
+    let mut certificates = certificates(&fixture, 6);
+    certificates.retain(|certificate| certificate.round() != 5);
+    let (sub_dags, _) = sub_dags(&certificates, 4);
+    let response = FetchConsensusSnapshotResponse {
+        sub_dag_digests: Vec::new(),
+        sub_dags: sub_dags.clone(),
+        certificates,
+        committee_changes: Vec::new(),
+    };
+
+    let snapshot = sync.verify_snapshot(response, &digests(&sub_dags)).unwrap();
+    assert!(snapshot
+        .certificates
+        .iter()
+        .all(|certificate| certificate.round() <= 4));
+    assert_eq!(snapshot.certificates.len(), 4 * ids.len());
+}
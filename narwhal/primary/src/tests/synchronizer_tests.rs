@@ -15,7 +15,9 @@ use std::{
     sync::Arc,
     time::Duration,
 };
-use test_utils::{make_optimal_signed_certificates, mock_signed_certificate, CommitteeFixture};
+use test_utils::{
+    fixture_payload, make_optimal_signed_certificates, mock_signed_certificate, CommitteeFixture,
+};
 use tokio::sync::{oneshot, watch};
 use types::{
     error::DagError, Certificate, CertificateAPI, Header, HeaderAPI, PreSubscribedBroadcastSender,
@@ -41,7 +43,7 @@ async fn accept_certificates() {
     let (tx_synchronizer_network, rx_synchronizer_network) = oneshot::channel();
 
     // Create test stores.
-    let (_, certificate_store, payload_store) = create_db_stores();
+    let (header_store, certificate_store, payload_store) = create_db_stores();
 
     // Make a synchronizer.
     let synchronizer = Arc::new(Synchronizer::new(
@@ -50,6 +52,7 @@ async fn accept_certificates() {
         worker_cache.clone(),
         /* gc_depth */ 50,
         client,
+        header_store.clone(),
         certificate_store.clone(),
         payload_store.clone(),
         tx_certificate_fetcher,
@@ -73,12 +76,19 @@ async fn accept_certificates() {
         .unwrap();
     let _ = tx_synchronizer_network.send(network.clone());
 
-    // Send 3 certificates to the Synchronizer.
+    // Send 3 certificates with payloads to the Synchronizer.
     let certificates: Vec<_> = fixture
-        .headers()
-        .iter()
+        .authorities()
         .take(3)
-        .map(|h| fixture.certificate(h))
+        .map(|a| {
+            let header = Header::V2(
+                a.header_builder(&committee)
+                    .payload(fixture_payload(2))
+                    .signed(a.keypair().private())
+                    .build(),
+            );
+            fixture.certificate(&header)
+        })
         .collect();
     for cert in certificates.clone() {
         synchronizer.try_accept_certificate(cert).await.unwrap();
@@ -101,10 +111,16 @@ async fn accept_certificates() {
         assert_eq!(received, x);
     }
 
-    // Ensure the certificates are stored.
+    // Ensure the certificates are stored, along with their headers. The payload markers are only
+    // written once our workers synchronized the batches.
     for x in &certificates {
         let stored = certificate_store.read(x.digest()).unwrap();
         assert_eq!(stored, Some(x.clone()));
+        let stored = header_store.read(&x.header().digest()).unwrap();
+        assert_eq!(stored, Some(x.header().clone()));
+        for (digest, (worker_id, _)) in x.header().payload() {
+            assert!(!payload_store.contains(*digest, *worker_id).unwrap());
+        }
     }
 }
 
@@ -122,7 +138,7 @@ async fn accept_suspended_certificates() {
     let client = NetworkClient::new_from_keypair(&primary.network_keypair());
     let genesis_certs = Certificate::genesis(&fixture.committee(), primary.keypair().private());
 
-    let (_header_store, certificate_store, payload_store) = create_db_stores();
+    let (header_store, certificate_store, payload_store) = create_db_stores();
     let (tx_certificate_fetcher, _rx_certificate_fetcher) = test_utils::test_channel!(100);
     let (tx_new_certificates, _rx_new_certificates) = test_utils::test_channel!(100);
     let (tx_parents, _rx_parents) = test_utils::test_channel!(100);
@@ -136,6 +152,7 @@ async fn accept_suspended_certificates() {
         worker_cache.clone(),
         /* gc_depth */ 50,
         client,
+        header_store.clone(),
         certificate_store.clone(),
         payload_store.clone(),
         tx_certificate_fetcher,
@@ -228,7 +245,7 @@ async fn synchronizer_recover_basic() {
     let (tx_synchronizer_network, rx_synchronizer_network) = oneshot::channel();
 
     // Create test stores.
-    let (_, certificate_store, payload_store) = create_db_stores();
+    let (header_store, certificate_store, payload_store) = create_db_stores();
 
     // Make Synchronizer.
     let synchronizer = Arc::new(Synchronizer::new(
@@ -237,6 +254,7 @@ async fn synchronizer_recover_basic() {
         worker_cache.clone(),
         /* gc_depth */ 50,
         client.clone(),
+        header_store.clone(),
         certificate_store.clone(),
         payload_store.clone(),
         tx_certificate_fetcher,
@@ -287,6 +305,7 @@ async fn synchronizer_recover_basic() {
         worker_cache.clone(),
         /* gc_depth */ 50,
         client,
+        header_store.clone(),
         certificate_store.clone(),
         payload_store.clone(),
         tx_certificate_fetcher,
@@ -336,7 +355,7 @@ async fn synchronizer_recover_partial_certs() {
     let (tx_synchronizer_network, rx_synchronizer_network) = oneshot::channel();
 
     // Create test stores.
-    let (_, certificate_store, payload_store) = create_db_stores();
+    let (header_store, certificate_store, payload_store) = create_db_stores();
 
     // Make a synchronizer.
     let synchronizer = Arc::new(Synchronizer::new(
@@ -345,6 +364,7 @@ async fn synchronizer_recover_partial_certs() {
         worker_cache.clone(),
         /* gc_depth */ 50,
         client.clone(),
+        header_store.clone(),
         certificate_store.clone(),
         payload_store.clone(),
         tx_certificate_fetcher,
@@ -397,6 +417,7 @@ async fn synchronizer_recover_partial_certs() {
         worker_cache.clone(),
         /* gc_depth */ 50,
         client,
+        header_store.clone(),
         certificate_store.clone(),
         payload_store.clone(),
         tx_certificate_fetcher,
@@ -449,7 +470,7 @@ async fn synchronizer_recover_previous_round() {
     let (tx_synchronizer_network, rx_synchronizer_network) = oneshot::channel();
 
     // Create test stores.
-    let (_, certificate_store, payload_store) = create_db_stores();
+    let (header_store, certificate_store, payload_store) = create_db_stores();
 
     // Make a synchronizer.
     let synchronizer = Arc::new(Synchronizer::new(
@@ -458,6 +479,7 @@ async fn synchronizer_recover_previous_round() {
         worker_cache.clone(),
         /* gc_depth */ 50,
         client.clone(),
+        header_store.clone(),
         certificate_store.clone(),
         payload_store.clone(),
         tx_certificate_fetcher,
@@ -523,6 +545,7 @@ async fn synchronizer_recover_previous_round() {
         worker_cache.clone(),
         /* gc_depth */ 50,
         client,
+        header_store.clone(),
         certificate_store.clone(),
         payload_store.clone(),
         tx_certificate_fetcher,
@@ -555,7 +578,7 @@ async fn deliver_certificate_using_dag() {
     let committee = fixture.committee();
     let worker_cache = fixture.worker_cache();
 
-    let (_, certificates_store, payload_store) = create_db_stores();
+    let (header_store, certificates_store, payload_store) = create_db_stores();
     let (tx_certificate_fetcher, _rx_certificate_fetcher) = test_utils::test_channel!(1);
     let (tx_new_certificates, _rx_new_certificates) = test_utils::test_channel!(100);
     let (tx_parents, _rx_parents) = test_utils::test_channel!(100);
@@ -576,6 +599,7 @@ async fn deliver_certificate_using_dag() {
         worker_cache.clone(),
         /* gc_depth */ 50,
         client,
+        header_store,
         certificates_store,
         payload_store,
         tx_certificate_fetcher,
@@ -627,7 +651,7 @@ async fn deliver_certificate_using_store() {
     let committee = fixture.committee();
     let worker_cache = fixture.worker_cache();
 
-    let (_, certificates_store, payload_store) = create_db_stores();
+    let (header_store, certificates_store, payload_store) = create_db_stores();
     let (tx_certificate_fetcher, _rx_certificate_fetcher) = test_utils::test_channel!(1);
     let (tx_new_certificates, _rx_new_certificates) = test_utils::test_channel!(100);
     let (tx_parents, _rx_parents) = test_utils::test_channel!(100);
@@ -644,6 +668,7 @@ async fn deliver_certificate_using_store() {
         worker_cache.clone(),
         /* gc_depth */ 50,
         client,
+        header_store.clone(),
         certificates_store.clone(),
         payload_store.clone(),
         tx_certificate_fetcher,
@@ -695,7 +720,7 @@ async fn deliver_certificate_not_found_parents() {
     let committee = fixture.committee();
     let worker_cache = fixture.worker_cache();
 
-    let (_, certificates_store, payload_store) = create_db_stores();
+    let (header_store, certificates_store, payload_store) = create_db_stores();
     let (tx_certificate_fetcher, mut rx_certificate_fetcher) = test_utils::test_channel!(1);
     let (tx_new_certificates, _rx_new_certificates) = test_utils::test_channel!(100);
     let (tx_parents, _rx_parents) = test_utils::test_channel!(100);
@@ -712,6 +737,7 @@ async fn deliver_certificate_not_found_parents() {
         worker_cache.clone(),
         /* gc_depth */ 50,
         client,
+        header_store,
         certificates_store,
         payload_store,
         tx_certificate_fetcher,
@@ -767,7 +793,7 @@ async fn sync_batches_drops_old() {
     let author = fixture.authorities().nth(2).unwrap();
     let client = NetworkClient::new_from_keypair(&primary.network_keypair());
 
-    let (_header_store, certificate_store, payload_store) = create_db_stores();
+    let (header_store, certificate_store, payload_store) = create_db_stores();
     let (tx_certificate_fetcher, _rx_certificate_fetcher) = test_utils::test_channel!(1);
     let (tx_new_certificates, _rx_new_certificates) = test_utils::test_channel!(100);
     let (tx_parents, _rx_parents) = test_utils::test_channel!(100);
@@ -784,6 +810,7 @@ async fn sync_batches_drops_old() {
         worker_cache.clone(),
         /* gc_depth */ 50,
         client,
+        header_store.clone(),
         certificate_store.clone(),
         payload_store.clone(),
         tx_certificate_fetcher,
@@ -797,7 +824,7 @@ async fn sync_batches_drops_old() {
 
     let mut certificates = HashMap::new();
     for _ in 0..3 {
-        let header = Header::V1(
+        let header = Header::V2(
             author
                 .header_builder(&fixture.committee())
                 .with_payload_batch(test_utils::fixture_batch_with_transactions(10), 0, 0)
@@ -814,7 +841,7 @@ async fn sync_batches_drops_old() {
             payload_store.write(digest, worker_id).unwrap();
         }
     }
-    let test_header = Header::V1(
+    let test_header = Header::V2(
         author
             .header_builder(&fixture.committee())
             .round(2)
@@ -849,7 +876,7 @@ async fn gc_suspended_certificates() {
     let client = NetworkClient::new_from_keypair(&primary.network_keypair());
     let genesis_certs = Certificate::genesis(&fixture.committee(), primary.keypair().private());
 
-    let (_header_store, certificate_store, payload_store) = create_db_stores();
+    let (header_store, certificate_store, payload_store) = create_db_stores();
     let (tx_certificate_fetcher, _rx_certificate_fetcher) = test_utils::test_channel!(100);
     let (tx_new_certificates, mut rx_new_certificates) = test_utils::test_channel!(100);
     let (tx_parents, _rx_parents) = test_utils::test_channel!(100);
@@ -863,6 +890,7 @@ async fn gc_suspended_certificates() {
         worker_cache.clone(),
         /* gc_depth */ GC_DEPTH,
         client,
+        header_store.clone(),
         certificate_store.clone(),
         payload_store.clone(),
         tx_certificate_fetcher,
@@ -915,6 +943,11 @@ async fn gc_suspended_certificates() {
         }
     }
 
+    // The headers of all the rounds were received.
+    for cert in &certificates {
+        header_store.write(cert.header()).unwrap();
+    }
+
     // At commit round 8, round 3 becomes the GC round. Round 4 and 5 will be accepted.
     let _ = tx_consensus_round_updates.send(ConsensusRound::new(8, gc_round(8, GC_DEPTH)));
 
@@ -932,4 +965,11 @@ async fn gc_suspended_certificates() {
         .map(|cert| (cert.digest(), cert.clone()))
         .collect();
     assert_eq!(received_certificates, expected_certificates);
+
+    // The headers up to the GC round are pruned in the background.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    for cert in &certificates {
+        let header = header_store.read(&cert.header().digest()).unwrap();
+        assert_eq!(header.is_some(), cert.round() > 3);
+    }
 }
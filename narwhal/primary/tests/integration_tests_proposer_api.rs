@@ -9,13 +9,11 @@ use crypto::{Hash, KeyPair};
 use narwhal_primary as primary;
 use narwhal_primary::NUM_SHUTDOWN_RECEIVERS;
 use network::client::NetworkClient;
-use primary::{NetworkModel, Primary, CHANNEL_CAPACITY};
+use primary::{NetworkModel, Primary, PrimaryNetwork, CHANNEL_CAPACITY};
 use rand::thread_rng;
 use std::{collections::BTreeSet, sync::Arc, time::Duration};
 use storage::NodeStorage;
-use test_utils::{
-    make_optimal_certificates, make_optimal_signed_certificates, temp_dir, CommitteeFixture,
-};
+use test_utils::{make_optimal_certificates, make_optimal_signed_certificates, CommitteeFixture};
 use tokio::sync::watch;
 use tonic::transport::Channel;
 use types::{
@@ -71,7 +69,7 @@ async fn test_rounds_errors() {
     };
 
     // AND create separate data stores
-    let store_primary = NodeStorage::reopen(temp_dir());
+    let store_primary = NodeStorage::new_for_tests();
 
     // Spawn the primary
     let (tx_new_certificates, rx_new_certificates) =
@@ -101,7 +99,7 @@ async fn test_rounds_errors() {
     Primary::spawn(
         author.authority().clone(),
         keypair.clone(),
-        network_keypair,
+        PrimaryNetwork::bind(author.authority(), network_keypair, &committee, &client),
         committee.clone(),
         worker_cache,
         parameters.clone(),
@@ -111,6 +109,8 @@ async fn test_rounds_errors() {
         store_primary.proposer_store,
         store_primary.payload_store,
         store_primary.vote_digest_store,
+        store_primary.consensus_store.clone(),
+        store_primary.execution_store.clone(),
         tx_new_certificates,
         rx_feedback,
         rx_consensus_round_updates,
@@ -171,7 +171,7 @@ async fn test_rounds_return_successful_response() {
     };
 
     // AND create separate data stores
-    let store_primary = NodeStorage::reopen(temp_dir());
+    let store_primary = NodeStorage::new_for_tests();
 
     // Spawn the primary
     let (tx_new_certificates, rx_new_certificates) =
@@ -197,7 +197,12 @@ async fn test_rounds_return_successful_response() {
     Primary::spawn(
         author.authority().clone(),
         keypair.clone(),
-        author.network_keypair().copy(),
+        PrimaryNetwork::bind(
+            author.authority(),
+            author.network_keypair().copy(),
+            &committee,
+            &client,
+        ),
         committee.clone(),
         worker_cache,
         parameters.clone(),
@@ -207,6 +212,8 @@ async fn test_rounds_return_successful_response() {
         store_primary.proposer_store,
         store_primary.payload_store,
         store_primary.vote_digest_store,
+        store_primary.consensus_store.clone(),
+        store_primary.execution_store.clone(),
         tx_new_certificates,
         rx_feedback,
         rx_consensus_round_updates,
@@ -270,8 +277,8 @@ async fn test_node_read_causal_signed_certificates() {
     let authority_2 = fixture.authorities().nth(1).unwrap();
 
     // Make the data store.
-    let primary_store_1 = NodeStorage::reopen(temp_dir());
-    let primary_store_2: NodeStorage = NodeStorage::reopen(temp_dir());
+    let primary_store_1 = NodeStorage::new_for_tests();
+    let primary_store_2: NodeStorage = NodeStorage::new_for_tests();
 
     let client_1 = NetworkClient::new_from_keypair(&authority_1.network_keypair());
     let client_2 = NetworkClient::new_from_keypair(&authority_2.network_keypair());
@@ -358,7 +365,12 @@ async fn test_node_read_causal_signed_certificates() {
     Primary::spawn(
         authority_1.authority().clone(),
         keypair_1.clone(),
-        authority_1.network_keypair().copy(),
+        PrimaryNetwork::bind(
+            authority_1.authority(),
+            authority_1.network_keypair().copy(),
+            &committee,
+            &client_1,
+        ),
         committee.clone(),
         worker_cache.clone(),
         primary_1_parameters.clone(),
@@ -368,6 +380,8 @@ async fn test_node_read_causal_signed_certificates() {
         primary_store_1.proposer_store.clone(),
         primary_store_1.payload_store.clone(),
         primary_store_1.vote_digest_store.clone(),
+        primary_store_1.consensus_store.clone(),
+        primary_store_1.execution_store.clone(),
         tx_new_certificates,
         rx_feedback,
         rx_consensus_round_updates,
@@ -396,7 +410,12 @@ async fn test_node_read_causal_signed_certificates() {
     Primary::spawn(
         authority_2.authority().clone(),
         keypair_2.clone(),
-        authority_2.network_keypair().copy(),
+        PrimaryNetwork::bind(
+            authority_2.authority(),
+            authority_2.network_keypair().copy(),
+            &committee,
+            &client_2,
+        ),
         committee.clone(),
         worker_cache.clone(),
         primary_2_parameters.clone(),
@@ -406,6 +425,8 @@ async fn test_node_read_causal_signed_certificates() {
         primary_store_2.proposer_store,
         primary_store_2.payload_store,
         primary_store_2.vote_digest_store,
+        primary_store_2.consensus_store.clone(),
+        primary_store_2.execution_store.clone(),
         tx_new_certificates_2,
         rx_feedback_2,
         rx_consensus_round_updates_2,
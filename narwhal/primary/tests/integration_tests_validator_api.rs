@@ -9,23 +9,22 @@ use indexmap::IndexMap;
 use narwhal_primary as primary;
 use narwhal_primary::NUM_SHUTDOWN_RECEIVERS;
 use network::client::NetworkClient;
-use primary::{NetworkModel, Primary, CHANNEL_CAPACITY};
+use primary::{NetworkModel, Primary, PrimaryNetwork, CHANNEL_CAPACITY};
 use std::{
     collections::{BTreeSet, HashMap},
     sync::Arc,
     time::Duration,
 };
-use storage::{CertificateStore, HeaderStore};
+use storage::{BatchStore, CertificateStore, HeaderStore};
 use storage::{NodeStorage, PayloadStore};
-use store::{rocks::DBMap, Map};
 use test_utils::{
     fixture_batch_with_transactions, make_optimal_certificates, make_optimal_signed_certificates,
-    temp_dir, AuthorityFixture, CommitteeFixture,
+    AuthorityFixture, CommitteeFixture,
 };
 use tokio::sync::watch;
 use tonic::transport::Channel;
 use types::{
-    Batch, BatchAPI, BatchDigest, Certificate, CertificateDigest, CertificateDigestProto,
+    Batch, BatchAPI, Certificate, CertificateDigest, CertificateDigestProto,
     CollectionRetrievalResult, Empty, GetCollectionsRequest, Header, PreSubscribedBroadcastSender,
     ReadCausalRequest, RemoveCollectionsRequest, RetrievalResult, Transaction, ValidatorClient,
 };
@@ -48,7 +47,7 @@ async fn test_get_collections() {
     let worker_keypair = author.worker(worker_id).keypair().copy();
 
     // Make the data store.
-    let store = NodeStorage::reopen(temp_dir());
+    let store = NodeStorage::new_for_tests();
 
     let mut header_digests = Vec::new();
     // Blocks/Collections
@@ -59,10 +58,11 @@ async fn test_get_collections() {
     for n in 0..5 {
         let batch = fixture_batch_with_transactions(10);
 
-        let header = Header::V1(
+        let header = Header::V2(
             author
                 .header_builder(&committee)
                 .with_payload_batch(batch.clone(), worker_id, 0)
+                .signed(author.keypair().private())
                 .build(),
         );
 
@@ -105,7 +105,12 @@ async fn test_get_collections() {
     Primary::spawn(
         author.authority().clone(),
         signer.clone(),
-        author.network_keypair(),
+        PrimaryNetwork::bind(
+            author.authority(),
+            author.network_keypair(),
+            &committee,
+            &client,
+        ),
         committee.clone(),
         worker_cache.clone(),
         parameters.clone(),
@@ -115,6 +120,8 @@ async fn test_get_collections() {
         store.proposer_store.clone(),
         store.payload_store.clone(),
         store.vote_digest_store,
+        store.consensus_store.clone(),
+        store.execution_store.clone(),
         tx_new_certificates,
         rx_feedback,
         rx_consensus_round_updates,
@@ -245,7 +252,7 @@ async fn test_remove_collections() {
     let genesis_certs = Certificate::genesis(&committee.clone(), &signer.private().clone());
 
     // Make the data store.
-    let store = NodeStorage::reopen(temp_dir());
+    let store = NodeStorage::new_for_tests();
     let mut header_digests = Vec::new();
     // Blocks/Collections
     let mut collection_digests = Vec::new();
@@ -269,10 +276,11 @@ async fn test_remove_collections() {
     for n in 0..5 {
         let batch = fixture_batch_with_transactions(10);
 
-        let header = Header::V1(
+        let header = Header::V2(
             author
                 .header_builder(&committee)
                 .with_payload_batch(batch.clone(), worker_id, 0)
+                .signed(author.keypair().private())
                 .build(),
         );
 
@@ -308,7 +316,12 @@ async fn test_remove_collections() {
     Primary::spawn(
         author.authority().clone(),
         signer.clone(),
-        author.network_keypair().copy(),
+        PrimaryNetwork::bind(
+            author.authority(),
+            author.network_keypair().copy(),
+            &committee,
+            &network_client,
+        ),
         committee.clone(),
         worker_cache.clone(),
         parameters.clone(),
@@ -318,6 +331,8 @@ async fn test_remove_collections() {
         store.proposer_store.clone(),
         store.payload_store.clone(),
         store.vote_digest_store.clone(),
+        store.consensus_store.clone(),
+        store.execution_store.clone(),
         tx_new_certificates,
         rx_feedback,
         rx_consensus_round_updates,
@@ -469,8 +484,8 @@ async fn test_read_causal_signed_certificates() {
     let authority_2 = fixture.authorities().nth(1).unwrap();
 
     // Make the data store.
-    let primary_store_1 = NodeStorage::reopen(temp_dir());
-    let primary_store_2: NodeStorage = NodeStorage::reopen(temp_dir());
+    let primary_store_1 = NodeStorage::new_for_tests();
+    let primary_store_2: NodeStorage = NodeStorage::new_for_tests();
 
     let client_1 = NetworkClient::new_from_keypair(&authority_1.network_keypair());
     let client_2 = NetworkClient::new_from_keypair(&authority_2.network_keypair());
@@ -557,7 +572,12 @@ async fn test_read_causal_signed_certificates() {
     Primary::spawn(
         authority_1.authority().clone(),
         keypair_1.clone(),
-        authority_1.network_keypair().copy(),
+        PrimaryNetwork::bind(
+            authority_1.authority(),
+            authority_1.network_keypair().copy(),
+            &committee,
+            &client_1,
+        ),
         committee.clone(),
         worker_cache.clone(),
         primary_1_parameters.clone(),
@@ -567,6 +587,8 @@ async fn test_read_causal_signed_certificates() {
         primary_store_1.proposer_store.clone(),
         primary_store_1.payload_store.clone(),
         primary_store_1.vote_digest_store.clone(),
+        primary_store_1.consensus_store.clone(),
+        primary_store_1.execution_store.clone(),
         tx_new_certificates,
         rx_feedback,
         rx_consensus_round_updates,
@@ -596,7 +618,12 @@ async fn test_read_causal_signed_certificates() {
     Primary::spawn(
         authority_2.authority().clone(),
         keypair_2.clone(),
-        authority_2.network_keypair(),
+        PrimaryNetwork::bind(
+            authority_2.authority(),
+            authority_2.network_keypair(),
+            &committee,
+            &client_2,
+        ),
         committee.clone(),
         worker_cache.clone(),
         primary_2_parameters.clone(),
@@ -606,6 +633,8 @@ async fn test_read_causal_signed_certificates() {
         primary_store_2.proposer_store,
         primary_store_2.payload_store,
         primary_store_2.vote_digest_store,
+        primary_store_2.consensus_store.clone(),
+        primary_store_2.execution_store.clone(),
         tx_new_certificates_2,
         rx_feedback_2,
         rx_consensus_round_updates_2,
@@ -698,8 +727,8 @@ async fn test_read_causal_unsigned_certificates() {
     let network_keypair_2 = authority_2.network_keypair();
 
     // Make the data store.
-    let primary_store_1 = NodeStorage::reopen(temp_dir());
-    let primary_store_2: NodeStorage = NodeStorage::reopen(temp_dir());
+    let primary_store_1 = NodeStorage::new_for_tests();
+    let primary_store_2: NodeStorage = NodeStorage::new_for_tests();
 
     let client_1 = NetworkClient::new_from_keypair(&authority_1.network_keypair());
     let client_2 = NetworkClient::new_from_keypair(&authority_2.network_keypair());
@@ -779,7 +808,12 @@ async fn test_read_causal_unsigned_certificates() {
     Primary::spawn(
         authority_1.authority().clone(),
         keypair_1.clone(),
-        authority_1.network_keypair(),
+        PrimaryNetwork::bind(
+            authority_1.authority(),
+            authority_1.network_keypair(),
+            &committee,
+            &client_1,
+        ),
         committee.clone(),
         worker_cache.clone(),
         primary_1_parameters.clone(),
@@ -789,6 +823,8 @@ async fn test_read_causal_unsigned_certificates() {
         primary_store_1.proposer_store.clone(),
         primary_store_1.payload_store.clone(),
         primary_store_1.vote_digest_store.clone(),
+        primary_store_1.consensus_store.clone(),
+        primary_store_1.execution_store.clone(),
         tx_new_certificates,
         rx_feedback,
         rx_consensus_round_updates,
@@ -812,7 +848,12 @@ async fn test_read_causal_unsigned_certificates() {
     Primary::spawn(
         authority_2.authority().clone(),
         keypair_2.clone(),
-        network_keypair_2,
+        PrimaryNetwork::bind(
+            authority_2.authority(),
+            network_keypair_2,
+            &committee,
+            &client_2,
+        ),
         committee.clone(),
         worker_cache.clone(),
         primary_2_parameters.clone(),
@@ -822,6 +863,8 @@ async fn test_read_causal_unsigned_certificates() {
         primary_store_2.proposer_store,
         primary_store_2.payload_store,
         primary_store_2.vote_digest_store,
+        primary_store_2.consensus_store.clone(),
+        primary_store_2.execution_store.clone(),
         tx_new_certificates_2,
         rx_feedback_2,
         rx_consensus_round_updates_2,
@@ -938,8 +981,8 @@ async fn test_get_collections_with_missing_certificates() {
     };
 
     // AND create separate data stores for the 2 primaries
-    let store_primary_1 = NodeStorage::reopen(temp_dir());
-    let store_primary_2 = NodeStorage::reopen(temp_dir());
+    let store_primary_1 = NodeStorage::new_for_tests();
+    let store_primary_2 = NodeStorage::new_for_tests();
 
     // AND create separate networks for the 2 primaries
     let client_1 = NetworkClient::new_from_keypair(&authority_1.network_keypair());
@@ -993,7 +1036,12 @@ async fn test_get_collections_with_missing_certificates() {
     Primary::spawn(
         authority_1.authority().clone(),
         authority_1.keypair().clone(),
-        authority_1.network_keypair(),
+        PrimaryNetwork::bind(
+            authority_1.authority(),
+            authority_1.network_keypair(),
+            &committee,
+            &client_1,
+        ),
         committee.clone(),
         worker_cache.clone(),
         parameters_1.clone(),
@@ -1003,6 +1051,8 @@ async fn test_get_collections_with_missing_certificates() {
         store_primary_1.proposer_store,
         store_primary_1.payload_store,
         store_primary_1.vote_digest_store,
+        store_primary_1.consensus_store.clone(),
+        store_primary_1.execution_store.clone(),
         tx_new_certificates_1,
         rx_feedback_1,
         rx_consensus_round_updates,
@@ -1061,7 +1111,12 @@ async fn test_get_collections_with_missing_certificates() {
     Primary::spawn(
         authority_2.authority().clone(),
         authority_2.keypair().clone(),
-        authority_2.network_keypair(),
+        PrimaryNetwork::bind(
+            authority_2.authority(),
+            authority_2.network_keypair(),
+            &committee,
+            &client_2,
+        ),
         committee.clone(),
         worker_cache.clone(),
         parameters_2.clone(),
@@ -1071,6 +1126,8 @@ async fn test_get_collections_with_missing_certificates() {
         store_primary_2.proposer_store,
         store_primary_2.payload_store,
         store_primary_2.vote_digest_store,
+        store_primary_2.consensus_store.clone(),
+        store_primary_2.execution_store.clone(),
         tx_new_certificates_2,
         rx_feedback_2,
         rx_consensus_round_updates,
@@ -1168,7 +1225,7 @@ async fn fixture_certificate(
     header_store: HeaderStore,
     certificate_store: CertificateStore,
     payload_store: PayloadStore,
-    batch_store: DBMap<BatchDigest, Batch>,
+    batch_store: BatchStore,
 ) -> (Certificate, Batch) {
     let batch = fixture_batch_with_transactions(10);
     let worker_id = 0;
@@ -1178,7 +1235,13 @@ async fn fixture_certificate(
     let mut payload = IndexMap::new();
     payload.insert(batch_digest, (worker_id, 0));
 
-    let header = Header::V1(authority.header_builder(committee).payload(payload).build());
+    let header = Header::V2(
+        authority
+            .header_builder(committee)
+            .payload(payload)
+            .signed(authority.keypair().private())
+            .build(),
+    );
 
     let certificate = fixture.certificate(&header);
 
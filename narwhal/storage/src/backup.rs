@@ -0,0 +1,313 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use crate::NodeStorage;
+use config::Committee;
+use std::fs;
+use std::path::{Path, PathBuf};
+use store::sally::SallyColumn;
+use store::TypedStoreError;
+use thiserror::Error;
+use tracing::info;
+use types::{Round, SequenceNumber};
+
+/// The prefix of the backup directories, followed by the index of the last committed sub dag and
+/// the sequence number of the last write.
+const BACKUP_PREFIX: &str = "commit-";
+/// The directory a checkpoint is written to before its commit index is known.
+const PENDING_BACKUP: &str = "pending";
+
+#[derive(Debug, Error)]
+pub enum BackupError {
+    #[error(transparent)]
+    Store(#[from] TypedStoreError),
+    #[error("I/O error on the backups: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Cannot restore a backup into the non empty directory {0:?}")]
+    StoreNotEmpty(PathBuf),
+    #[error("Cannot back up an in-memory storage")]
+    InMemory,
+}
+
+/// A consistent point-in-time copy of every column family of a `NodeStorage`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Backup {
+    /// The directory of the RocksDB checkpoint.
+    pub path: PathBuf,
+    /// The index of the last sub dag committed in the backup. It stays 0 in the backups of a
+    /// worker, which commits nothing.
+    pub sub_dag_index: SequenceNumber,
+    /// The RocksDB sequence number of the last write in the backup.
+    pub sequence_number: u64,
+}
+
+impl Backup {
+    fn open(path: PathBuf) -> Option<Self> {
+        let name = path.file_name()?.to_str()?;
+        let (sub_dag_index, sequence_number) = name.strip_prefix(BACKUP_PREFIX)?.split_once('-')?;
+        Some(Self {
+            sub_dag_index: sub_dag_index.parse().ok()?,
+            sequence_number: sequence_number.parse().ok()?,
+            path,
+        })
+    }
+}
+
+impl NodeStorage {
+    /// Creates a backup of the storage in `backups_dir` while the node runs. The backup is named
+    /// after the last committed sub dag and the last write it contains; if a backup of the same
+    /// write already exists it is returned instead.
+    pub fn backup(&self, backups_dir: &Path) -> Result<Backup, BackupError> {
+        fs::create_dir_all(backups_dir)?;
+        let pending = backups_dir.join(PENDING_BACKUP);
+        if pending.exists() {
+            fs::remove_dir_all(&pending)?;
+        }
+
+        // All the stores share a single RocksDB instance, so checkpointing through any of them
+        // captures every column family at the same sequence number.
+        let SallyColumn::RocksDB((batch_store, _)) = self.batch_store.column() else {
+            return Err(BackupError::InMemory);
+        };
+        batch_store.checkpoint_db(&pending)?;
+
+        // Read the commit index from the checkpoint itself, since the node keeps committing. The
+        // checkpoint is opened read only, so the backup is left as it was taken.
+        let (sub_dag_index, sequence_number) = {
+            let checkpoint = NodeStorage::reopen_read_only(&pending);
+            let SallyColumn::RocksDB((checkpoint_store, _)) = checkpoint.batch_store.column()
+            else {
+                unreachable!("A checkpoint is opened on RocksDB");
+            };
+            (
+                checkpoint.consensus_store.get_latest_sub_dag_index(),
+                checkpoint_store.rocksdb.latest_sequence_number(),
+            )
+        };
+
+        let path = backups_dir.join(format!("{BACKUP_PREFIX}{sub_dag_index}-{sequence_number}"));
+        if path.exists() {
+            fs::remove_dir_all(&pending)?;
+        } else {
+            fs::rename(&pending, &path)?;
+            info!("Backed up the node storage at commit {sub_dag_index} to {path:?}");
+        }
+
+        Ok(Backup {
+            path,
+            sub_dag_index,
+            sequence_number,
+        })
+    }
+
+    /// Copies `backup` to the empty `store_path` and opens it. A worker started on the restored
+    /// storage fetches the batches it misses from its peers. The storage of a primary is restored
+    /// with `restore_primary`.
+    pub fn restore(backup: &Backup, store_path: &Path) -> Result<Self, BackupError> {
+        if store_path.exists() && fs::read_dir(store_path)?.next().is_some() {
+            return Err(BackupError::StoreNotEmpty(store_path.to_path_buf()));
+        }
+        copy_dir(&backup.path, store_path)?;
+        info!(
+            "Restored the node storage at commit {} from {:?}",
+            backup.sub_dag_index, backup.path
+        );
+        Ok(NodeStorage::reopen(store_path))
+    }
+
+    /// Same as `restore`, for the storage of a primary. A primary started on the restored storage
+    /// recovers like after a crash, and fetches what it misses from its peers.
+    /// The votes cast after the backup was taken are lost, so the primary could vote twice for
+    /// headers of an equivocating authority. It therefore does not vote for any header up to
+    /// `vote_floor`, which must be at least the highest round the committee reached when the
+    /// primary stopped, for example the highest round of the certificates of its peers.
+    pub fn restore_primary(
+        backup: &Backup,
+        store_path: &Path,
+        committee: &Committee,
+        vote_floor: Round,
+    ) -> Result<Self, BackupError> {
+        let storage = Self::restore(backup, store_path)?;
+        storage
+            .vote_digest_store
+            .raise_vote_floor(committee, vote_floor)?;
+        info!("The restored primary does not vote for headers up to round {vote_floor}");
+        Ok(storage)
+    }
+}
+
+/// Lists the backups in `backups_dir`, oldest first.
+pub fn list_backups(backups_dir: &Path) -> Result<Vec<Backup>, BackupError> {
+    if !backups_dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut backups = fs::read_dir(backups_dir)?
+        .filter_map(|entry| Backup::open(entry.ok()?.path()))
+        .collect::<Vec<_>>();
+    backups.sort_by_key(|backup| (backup.sub_dag_index, backup.sequence_number));
+    Ok(backups)
+}
+
+/// Deletes the oldest backups in `backups_dir`, keeping the `keep` most recent ones.
+pub fn prune_backups(backups_dir: &Path, keep: usize) -> Result<(), BackupError> {
+    let backups = list_backups(backups_dir)?;
+    for backup in &backups[..backups.len().saturating_sub(keep)] {
+        fs::remove_dir_all(&backup.path)?;
+    }
+    Ok(())
+}
+
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crypto::Hash;
+    use std::collections::HashMap;
+    use test_utils::{temp_dir, CommitteeFixture};
+    use types::{Certificate, CommittedSubDag, ReputationScores, VoteAPI, VoteInfoAPI};
+
+    fn commit(storage: &NodeStorage, certificate: &Certificate, sub_dag_index: SequenceNumber) {
+        let sub_dag = CommittedSubDag::new(
+            vec![certificate.clone()],
+            certificate.clone(),
+            sub_dag_index,
+            ReputationScores::default(),
+            None,
+        );
+        storage
+            .consensus_store
+            .write_consensus_state(&HashMap::new(), &sub_dag)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_backup_and_restore() {
+        let fixture = CommitteeFixture::builder().build();
+        let certificate = fixture.certificate(&fixture.header());
+        let storage = NodeStorage::reopen(temp_dir());
+        let backups_dir = temp_dir();
+
+        storage
+            .certificate_store
+            .write(certificate.clone())
+            .unwrap();
+        commit(&storage, &certificate, 1);
+        let backup = storage.backup(&backups_dir).unwrap();
+        assert_eq!(backup.sub_dag_index, 1);
+
+        // Writes after the backup are not part of it.
+        let batch = test_utils::fixture_batch_with_transactions(1);
+        storage.batch_store.insert(&batch.digest(), &batch).unwrap();
+        commit(&storage, &certificate, 2);
+
+        // Backing up twice the same writes returns the existing backup.
+        let later = storage.backup(&backups_dir).unwrap();
+        assert_eq!(later.sub_dag_index, 2);
+        assert!(later.sequence_number > backup.sequence_number);
+        assert_eq!(storage.backup(&backups_dir).unwrap(), later);
+
+        // Writes without a commit, like the ones of a worker, make a new backup.
+        let other_batch = test_utils::fixture_batch_with_transactions(2);
+        storage
+            .batch_store
+            .insert(&other_batch.digest(), &other_batch)
+            .unwrap();
+        let latest = storage.backup(&backups_dir).unwrap();
+        assert_eq!(latest.sub_dag_index, 2);
+        assert!(latest.sequence_number > later.sequence_number);
+        assert_eq!(
+            list_backups(&backups_dir).unwrap(),
+            vec![backup.clone(), later, latest.clone()]
+        );
+
+        let restored = NodeStorage::restore(&backup, &temp_dir().join("restored")).unwrap();
+        assert_eq!(restored.consensus_store.get_latest_sub_dag_index(), 1);
+        assert_eq!(
+            restored
+                .vote_digest_store
+                .read(&certificate.origin())
+                .unwrap(),
+            None
+        );
+        assert!(restored
+            .certificate_store
+            .read(certificate.digest())
+            .unwrap()
+            .is_some());
+        assert!(restored.batch_store.is_empty());
+
+        prune_backups(&backups_dir, 1).unwrap();
+        assert_eq!(list_backups(&backups_dir).unwrap(), vec![latest]);
+    }
+
+    #[test]
+    fn test_restore_primary_raises_the_vote_floor() {
+        let fixture = CommitteeFixture::builder().build();
+        let committee = fixture.committee();
+        let storage = NodeStorage::reopen(temp_dir());
+        let mut authorities = fixture.authorities();
+        let author = authorities.next().unwrap();
+        let voter = authorities.next().unwrap();
+
+        // The node voted for a header of round 5 before the backup.
+        let header = author.header_with_round(&committee, 5);
+        let vote = voter.vote(&header);
+        storage.vote_digest_store.write(&vote).unwrap();
+        let backup = storage.backup(&temp_dir()).unwrap();
+
+        let restored =
+            NodeStorage::restore_primary(&backup, &temp_dir().join("restored"), &committee, 3)
+                .unwrap();
+
+        // The newer vote is kept, the headers of the other authorities are not voted for up to
+        // round 3.
+        for authority in fixture.authorities() {
+            let vote_info = restored
+                .vote_digest_store
+                .read(&authority.id())
+                .unwrap()
+                .unwrap();
+            if authority.id() == vote.origin() {
+                assert_eq!(vote_info.round(), 5);
+                assert_eq!(vote_info.vote_digest(), vote.digest());
+            } else {
+                assert_eq!(vote_info.epoch(), committee.epoch());
+                assert_eq!(vote_info.round(), 3);
+            }
+        }
+    }
+
+    #[test]
+    fn test_restore_into_non_empty_store() {
+        let storage = NodeStorage::reopen(temp_dir());
+        let backup = storage.backup(&temp_dir()).unwrap();
+        let store_path = temp_dir();
+        drop(NodeStorage::reopen(&store_path));
+
+        assert!(matches!(
+            NodeStorage::restore(&backup, &store_path),
+            Err(BackupError::StoreNotEmpty(_))
+        ));
+    }
+
+    #[test]
+    fn test_backup_in_memory_storage() {
+        let storage = NodeStorage::in_memory();
+        assert!(matches!(
+            storage.backup(&temp_dir()),
+            Err(BackupError::InMemory)
+        ));
+    }
+}
@@ -0,0 +1,304 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use crate::{CacheOperation, Columns, NodeStorage, StoreCache, StoreResult};
+use std::borrow::Borrow;
+use std::collections::{HashMap, HashSet};
+use store::sally::index::{IndexedColumn, SecondaryIndex};
+use store::sally::SallyColumn;
+use store::Map;
+use types::{now, Batch, BatchDigest, TimestampMs};
+
+/// The batches of the workers, by their digest.
+#[derive(Clone)]
+pub struct BatchStore {
+    store: SallyColumn<BatchDigest, Batch>,
+    /// The local time at which each batch was last stored. The creation time in the metadata of
+    /// a batch is set by its author, so it cannot tell how long the batch has been kept.
+    stored_at: IndexedColumn<BatchDigest, TimestampMs>,
+    /// The digests of the batches by the time they were stored, to find the old batches without
+    /// a scan of the batches.
+    by_stored_at: SecondaryIndex<BatchDigest, TimestampMs, (TimestampMs, BatchDigest)>,
+    /// An LRU cache to keep recent batches, disabled when its size is zero.
+    cache: StoreCache<BatchDigest, Batch>,
+}
+
+impl BatchStore {
+    /// The name of the batch store in the cache metrics.
+    const STORE_NAME: &'static str = "batches";
+
+    pub fn new(
+        batch_store: SallyColumn<BatchDigest, Batch>,
+        batch_stored_at: SallyColumn<BatchDigest, TimestampMs>,
+        batch_digest_by_stored_at: SallyColumn<(TimestampMs, BatchDigest), BatchDigest>,
+        cache_size: usize,
+    ) -> Self {
+        let by_stored_at = SecondaryIndex::new(batch_digest_by_stored_at, |digest, stored_at| {
+            (*stored_at, *digest)
+        });
+        Self {
+            store: batch_store,
+            stored_at: IndexedColumn::new(batch_stored_at).with_index(&by_stored_at),
+            by_stored_at,
+            cache: StoreCache::new(Self::STORE_NAME, cache_size),
+        }
+    }
+
+    pub fn new_for_tests() -> Self {
+        let columns = Columns::for_tests(&[
+            NodeStorage::BATCHES_CF,
+            NodeStorage::BATCH_STORED_AT_CF,
+            NodeStorage::BATCH_DIGEST_BY_STORED_AT_CF,
+        ]);
+        Self::new(
+            columns.open(NodeStorage::BATCHES_CF),
+            columns.open(NodeStorage::BATCH_STORED_AT_CF),
+            columns.open(NodeStorage::BATCH_DIGEST_BY_STORED_AT_CF),
+            0,
+        )
+    }
+
+    /// The column holding the batches, shared by all the stores of the node.
+    pub(crate) fn column(&self) -> &SallyColumn<BatchDigest, Batch> {
+        &self.store
+    }
+
+    pub fn get(&self, digest: &BatchDigest) -> StoreResult<Option<Batch>> {
+        if let Some(batch) = self.cache.get(CacheOperation::Read, digest) {
+            return Ok(Some(batch));
+        }
+
+        let batch = self.column().get(digest)?;
+        if let Some(batch) = &batch {
+            self.cache
+                .insert(CacheOperation::Read, *digest, batch.clone());
+        }
+        Ok(batch)
+    }
+
+    /// Retrieves multiple batches by their digests. The results are returned in the same
+    /// sequence as the provided digests.
+    pub fn multi_get<J: Borrow<BatchDigest>>(
+        &self,
+        digests: impl IntoIterator<Item = J>,
+    ) -> StoreResult<Vec<Option<Batch>>> {
+        let digests: Vec<BatchDigest> = digests.into_iter().map(|d| *d.borrow()).collect();
+        let mut batches = self.cache.get_all(CacheOperation::ReadAll, &digests);
+
+        // Fall back on the storage for the misses, and cache what is found there.
+        let missing: Vec<_> = digests
+            .iter()
+            .zip(&batches)
+            .filter(|(_, batch)| batch.is_none())
+            .map(|(digest, _)| *digest)
+            .collect();
+        if missing.is_empty() {
+            return Ok(batches);
+        }
+        let mut from_store = missing
+            .iter()
+            .zip(self.column().multi_get(&missing)?)
+            .filter_map(|(digest, batch)| batch.map(|batch| (*digest, batch)))
+            .collect::<HashMap<_, _>>();
+        self.cache.insert_all(
+            CacheOperation::ReadAll,
+            from_store
+                .iter()
+                .map(|(digest, batch)| (*digest, batch.clone())),
+        );
+        for (digest, batch) in digests.iter().zip(batches.iter_mut()) {
+            if batch.is_none() {
+                *batch = from_store.remove(digest);
+            }
+        }
+        Ok(batches)
+    }
+
+    pub fn contains_key(&self, digest: &BatchDigest) -> StoreResult<bool> {
+        if self.cache.contains(CacheOperation::Contains, digest) {
+            return Ok(true);
+        }
+        self.column().contains_key(digest)
+    }
+
+    pub fn insert(&self, digest: &BatchDigest, batch: &Batch) -> StoreResult<()> {
+        self.multi_insert([(digest, batch)])
+    }
+
+    /// Inserts multiple batches atomically, stored at the current time.
+    pub fn multi_insert<J: Borrow<BatchDigest>, U: Borrow<Batch>>(
+        &self,
+        batches: impl IntoIterator<Item = (J, U)>,
+    ) -> StoreResult<()> {
+        let batches: Vec<(BatchDigest, Batch)> = batches
+            .into_iter()
+            .map(|(digest, batch)| (*digest.borrow(), batch.borrow().clone()))
+            .collect();
+        let stored_at = now();
+        let times: Vec<_> = batches
+            .iter()
+            .map(|(digest, _)| (*digest, stored_at))
+            .collect();
+
+        let guard = self.stored_at.lock_writes();
+        let mut write_batch = self.store.batch();
+        write_batch.insert_batch(&self.store, batches.iter().map(|(d, b)| (d, b)))?;
+        self.stored_at
+            .insert_batch(&guard, &mut write_batch, &times)?;
+        write_batch.write_sync()?;
+        self.cache.insert_all(CacheOperation::Write, batches);
+        Ok(())
+    }
+
+    pub fn remove(&self, digest: &BatchDigest) -> StoreResult<()> {
+        self.multi_remove([digest])
+    }
+
+    pub fn multi_remove<J: Borrow<BatchDigest>>(
+        &self,
+        digests: impl IntoIterator<Item = J>,
+    ) -> StoreResult<()> {
+        let digests: Vec<BatchDigest> = digests.into_iter().map(|d| *d.borrow()).collect();
+        let guard = self.stored_at.lock_writes();
+        let mut write_batch = self.store.batch();
+        write_batch.delete_batch(&self.store, &digests)?;
+        self.stored_at
+            .delete_batch(&guard, &mut write_batch, &digests)?;
+        write_batch.write_sync()?;
+        self.cache.remove_all(&digests);
+        Ok(())
+    }
+
+    /// Deletes up to `max_batches` of the oldest batches stored before `stored_at`, except the
+    /// `retained` ones, and returns the number of batches deleted.
+    pub fn remove_stored_before(
+        &self,
+        stored_at: TimestampMs,
+        max_batches: usize,
+        retained: &HashSet<BatchDigest>,
+    ) -> StoreResult<usize> {
+        let digests = self
+            .by_stored_at
+            .column()
+            .keys()
+            .take_while(|key| !matches!(key, Ok((time, _)) if *time >= stored_at))
+            .filter(|key| !matches!(key, Ok((_, digest)) if retained.contains(digest)))
+            .take(max_batches)
+            .map(|key| key.map(|(_, digest)| digest))
+            .collect::<StoreResult<Vec<_>>>()?;
+        if !digests.is_empty() {
+            self.multi_remove(&digests)?;
+        }
+        Ok(digests.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.column().is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crypto::Hash;
+    use std::thread::sleep;
+    use std::time::Duration;
+    use test_utils::fixture_batch_with_transactions;
+    use types::BatchAPI;
+
+    #[test]
+    fn test_cached_batch_store() {
+        let columns = Columns::for_tests(&[
+            NodeStorage::BATCHES_CF,
+            NodeStorage::BATCH_STORED_AT_CF,
+            NodeStorage::BATCH_DIGEST_BY_STORED_AT_CF,
+        ]);
+        let column: SallyColumn<BatchDigest, Batch> = columns.open(NodeStorage::BATCHES_CF);
+        let store = BatchStore::new(
+            column.clone(),
+            columns.open(NodeStorage::BATCH_STORED_AT_CF),
+            columns.open(NodeStorage::BATCH_DIGEST_BY_STORED_AT_CF),
+            10,
+        );
+        let batches: Vec<_> = (1..=3).map(fixture_batch_with_transactions).collect();
+        let digests: Vec<_> = batches.iter().map(|b| b.digest()).collect();
+
+        // Batches written behind the store are read through the cache.
+        column.insert(&digests[0], &batches[0]).unwrap();
+        store.insert(&digests[1], &batches[1]).unwrap();
+        assert_eq!(
+            store.multi_get(&digests).unwrap(),
+            vec![Some(batches[0].clone()), Some(batches[1].clone()), None]
+        );
+
+        // Once cached, a batch is served without reading the storage.
+        column.remove(&digests[0]).unwrap();
+        assert_eq!(store.get(&digests[0]).unwrap(), Some(batches[0].clone()));
+
+        // Removing a batch through the store also evicts it from the cache.
+        store.multi_remove(&digests[..2]).unwrap();
+        assert_eq!(store.multi_get(&digests).unwrap(), vec![None, None, None]);
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn test_remove_stored_before() {
+        let store = BatchStore::new_for_tests();
+        // The batches are ordered by the local time at which they are stored, whatever the
+        // creation time set by their author.
+        let batches: Vec<_> = (1..=4)
+            .map(|size| {
+                let mut batch = fixture_batch_with_transactions(size);
+                batch.metadata_mut().created_at = 10 - size as TimestampMs;
+                batch
+            })
+            .collect();
+        let digests: Vec<_> = batches.iter().map(|batch| batch.digest()).collect();
+        let mut stored_at = Vec::new();
+        for (digest, batch) in digests.iter().zip(&batches) {
+            store.insert(digest, batch).unwrap();
+            sleep(Duration::from_millis(2));
+            stored_at.push(now());
+        }
+
+        // The batches are deleted from the oldest, up to the given number, except the retained
+        // ones.
+        let retained = HashSet::from([digests[1]]);
+        assert_eq!(
+            store
+                .remove_stored_before(stored_at[2], 1, &retained)
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            store
+                .remove_stored_before(stored_at[2], 10, &retained)
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            store
+                .remove_stored_before(stored_at[2], 10, &retained)
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            store.multi_get(&digests).unwrap(),
+            vec![
+                None,
+                Some(batches[1].clone()),
+                None,
+                Some(batches[3].clone())
+            ]
+        );
+
+        // Storing a batch again restarts its time.
+        store.insert(&digests[1], &batches[1]).unwrap();
+        assert_eq!(
+            store
+                .remove_stored_before(stored_at[3], 10, &HashSet::new())
+                .unwrap(),
+            1
+        );
+        assert!(store.contains_key(&digests[1]).unwrap());
+    }
+}
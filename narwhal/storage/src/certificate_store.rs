@@ -1,28 +1,34 @@
 // Copyright (c) Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
 use crypto::Hash;
-use lru::LruCache;
-use parking_lot::Mutex;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::num::NonZeroUsize;
 use std::sync::Arc;
 use std::{cmp::Ordering, collections::BTreeMap, iter};
 use sui_macros::fail_point;
 
-use crate::StoreResult;
-use config::AuthorityIdentifier;
+use crate::{CacheOperation, StoreCache, StoreResult, UnitOfWork};
+use config::{AuthorityIdentifier, WorkerId};
 use mysten_common::sync::notify_read::NotifyRead;
 use store::{
-    rocks::{be_fix_int_ser, DBMap, TypedStoreError::RocksDBError},
+    rocks::{be_fix_int_ser, TypedStoreError::RocksDBError},
+    sally::{
+        index::{IndexedColumn, SecondaryIndex},
+        SallyColumn,
+    },
     Map,
 };
-use types::{Certificate, CertificateDigest, Round};
+use types::{BatchDigest, Certificate, CertificateAPI, CertificateDigest, HeaderAPI, Round};
+
+/// The number of retained certificates read at once when looking for references to the payload
+/// of pruned ones.
+const RETAINED_CERTIFICATES_CHUNK: usize = 1_000;
 
 /// A cache trait to be used as temporary in-memory store when accessing the underlying
 /// certificate_store. Using the cache allows to skip rocksdb access giving us benefits
 /// both on less disk access (when value not in db's cache) and also avoiding any additional
 /// deserialization costs.
-pub trait Cache {
+pub trait Cache: Send + Sync + 'static {
     fn write(&self, certificate: Certificate);
     fn write_all(&self, certificate: Vec<Certificate>);
     fn read(&self, digest: &CertificateDigest) -> Option<Certificate>;
@@ -37,40 +43,45 @@ pub trait Cache {
     fn contains(&self, digest: &CertificateDigest) -> bool;
     fn remove(&self, digest: &CertificateDigest);
     fn remove_all(&self, digests: Vec<CertificateDigest>);
+
+    /// Caches the certificates read from the storage after a miss of `operation`.
+    fn fill(&self, operation: CacheOperation, certificates: Vec<Certificate>);
 }
 
 /// An LRU cache for the certificate store.
 #[derive(Clone)]
 pub struct CertificateStoreCache {
-    cache: Arc<Mutex<LruCache<CertificateDigest, Certificate>>>,
+    cache: StoreCache<CertificateDigest, Certificate>,
 }
 
 impl CertificateStoreCache {
+    /// The name of the certificate store in the cache metrics.
+    const STORE_NAME: &'static str = "certificates";
+
     pub fn new(size: NonZeroUsize) -> Self {
         Self {
-            cache: Arc::new(Mutex::new(LruCache::new(size))),
+            cache: StoreCache::new(Self::STORE_NAME, size.get()),
         }
     }
 }
 
 impl Cache for CertificateStoreCache {
     fn write(&self, certificate: Certificate) {
-        let mut guard = self.cache.lock();
-        guard.put(certificate.digest(), certificate);
+        self.cache
+            .insert(CacheOperation::Write, certificate.digest(), certificate);
     }
 
     fn write_all(&self, certificate: Vec<Certificate>) {
-        let mut guard = self.cache.lock();
-        for cert in certificate {
-            guard.put(cert.digest(), cert);
-        }
+        self.cache.insert_all(
+            CacheOperation::Write,
+            certificate.into_iter().map(|cert| (cert.digest(), cert)),
+        );
     }
 
     /// Fetches the certificate for the provided digest. This method will update the LRU record
     /// and mark it as "last accessed".
     fn read(&self, digest: &CertificateDigest) -> Option<Certificate> {
-        let mut guard = self.cache.lock();
-        guard.get(digest).cloned()
+        self.cache.get(CacheOperation::Read, digest)
     }
 
     /// Fetches the certificates for the provided digests. This method will update the LRU records
@@ -79,30 +90,29 @@ impl Cache for CertificateStoreCache {
         &self,
         digests: Vec<CertificateDigest>,
     ) -> Vec<(CertificateDigest, Option<Certificate>)> {
-        let mut guard = self.cache.lock();
-        digests
-            .into_iter()
-            .map(move |id| (id, guard.get(&id).cloned()))
-            .collect()
+        let certificates = self.cache.get_all(CacheOperation::ReadAll, &digests);
+        digests.into_iter().zip(certificates).collect()
     }
 
     /// Checks whether the value exists in the LRU cache. The method does not update the LRU record, thus
     /// it will not count as a "last access" for the provided digest.
     fn contains(&self, digest: &CertificateDigest) -> bool {
-        let guard = self.cache.lock();
-        guard.contains(digest)
+        self.cache.contains(CacheOperation::Contains, digest)
     }
 
     fn remove(&self, digest: &CertificateDigest) {
-        let mut guard = self.cache.lock();
-        let _ = guard.pop(digest);
+        self.cache.remove(digest);
     }
 
     fn remove_all(&self, digests: Vec<CertificateDigest>) {
-        let mut guard = self.cache.lock();
-        for digest in digests {
-            let _ = guard.pop(&digest);
-        }
+        self.cache.remove_all(&digests);
+    }
+
+    fn fill(&self, operation: CacheOperation, certificates: Vec<Certificate>) {
+        self.cache.insert_all(
+            operation,
+            certificates.into_iter().map(|cert| (cert.digest(), cert)),
+        );
     }
 }
 
@@ -141,29 +151,35 @@ impl Cache for NoCache {
     fn remove_all(&self, _digests: Vec<CertificateDigest>) {
         // no-op
     }
+
+    fn fill(&self, _operation: CacheOperation, _certificates: Vec<Certificate>) {
+        // no-op
+    }
 }
 
 /// The main storage when we have to deal with certificates. It maintains
-/// two storages, one main which saves the certificates by their ids, and a
-/// secondary one which acts as an index to allow us fast retrieval based
-/// for queries based in certificate rounds.
+/// one main storage which saves the certificates by their ids, and two
+/// secondary indexes, written together with it, to allow us fast retrieval
+/// for queries based in certificate rounds and origins.
 /// It also offers pub/sub capabilities in write events. By using the
 /// `notify_read` someone can wait to hear until a certificate by a specific
 /// id has been written in storage.
 #[derive(Clone)]
 pub struct CertificateStore<T: Cache = CertificateStoreCache> {
-    /// Holds the certificates by their digest id
-    certificates_by_id: DBMap<CertificateDigest, Certificate>,
+    /// Holds the certificates by their digest id, and maintains both secondary indexes
+    certificates_by_id: IndexedColumn<CertificateDigest, Certificate>,
     /// A secondary index that keeps the certificate digest ids
     /// by the certificate rounds. Certificate origin is used to produce unique keys.
     /// This helps us to perform range requests based on rounds. We avoid storing again the
     /// certificate here to not waste space. To dereference we use the certificates_by_id storage.
-    certificate_id_by_round: DBMap<(Round, AuthorityIdentifier), CertificateDigest>,
+    certificate_id_by_round:
+        SecondaryIndex<CertificateDigest, Certificate, (Round, AuthorityIdentifier)>,
     /// A secondary index that keeps the certificate digest ids
     /// by the certificate origins. Certificate rounds are used to produce unique keys.
     /// This helps us to perform range requests based on rounds. We avoid storing again the
     /// certificate here to not waste space. To dereference we use the certificates_by_id storage.
-    certificate_id_by_origin: DBMap<(AuthorityIdentifier, Round), CertificateDigest>,
+    certificate_id_by_origin:
+        SecondaryIndex<CertificateDigest, Certificate, (AuthorityIdentifier, Round)>,
     /// The pub/sub to notify for a write that happened for a certificate digest id
     notify_subscribers: Arc<NotifyRead<CertificateDigest, Certificate>>,
     /// An LRU cache to keep recent certificates
@@ -172,11 +188,23 @@ pub struct CertificateStore<T: Cache = CertificateStoreCache> {
 
 impl<T: Cache> CertificateStore<T> {
     pub fn new(
-        certificates_by_id: DBMap<CertificateDigest, Certificate>,
-        certificate_id_by_round: DBMap<(Round, AuthorityIdentifier), CertificateDigest>,
-        certificate_id_by_origin: DBMap<(AuthorityIdentifier, Round), CertificateDigest>,
+        certificates_by_id: SallyColumn<CertificateDigest, Certificate>,
+        certificate_id_by_round: SallyColumn<(Round, AuthorityIdentifier), CertificateDigest>,
+        certificate_id_by_origin: SallyColumn<(AuthorityIdentifier, Round), CertificateDigest>,
         certificate_store_cache: T,
     ) -> CertificateStore<T> {
+        let certificate_id_by_round =
+            SecondaryIndex::new(certificate_id_by_round, |_, certificate: &Certificate| {
+                (certificate.round(), certificate.origin())
+            });
+        let certificate_id_by_origin =
+            SecondaryIndex::new(certificate_id_by_origin, |_, certificate: &Certificate| {
+                (certificate.origin(), certificate.round())
+            });
+        let certificates_by_id = IndexedColumn::new(certificates_by_id)
+            .with_index(&certificate_id_by_round)
+            .with_index(&certificate_id_by_origin);
+
         Self {
             certificates_by_id,
             certificate_id_by_round,
@@ -190,38 +218,40 @@ impl<T: Cache> CertificateStore<T> {
     pub fn write(&self, certificate: Certificate) -> StoreResult<()> {
         fail_point!("narwhal-store-before-write");
 
-        let mut batch = self.certificates_by_id.batch();
+        let mut unit = self.unit_of_work();
+        self.write_in(&mut unit, certificate)?;
 
-        let id = certificate.digest();
+        // execute the batch (atomically) and return the result
+        let result = unit.commit();
 
-        // write the certificate by its id
-        batch.insert_batch(
-            &self.certificates_by_id,
-            iter::once((id, certificate.clone())),
-        )?;
-
-        // Index the certificate id by its round and origin.
-        batch.insert_batch(
-            &self.certificate_id_by_round,
-            iter::once(((certificate.round(), certificate.origin()), id)),
-        )?;
-        batch.insert_batch(
-            &self.certificate_id_by_origin,
-            iter::once(((certificate.origin(), certificate.round()), id)),
-        )?;
+        fail_point!("narwhal-store-after-write");
+        result
+    }
 
-        // execute the batch (atomically) and return the result
-        let result = batch.write();
+    /// Starts a unit of work over the stores of the node this store belongs to.
+    pub fn unit_of_work(&self) -> UnitOfWork {
+        UnitOfWork::new(self.certificates_by_id.batch())
+    }
 
-        if result.is_ok() {
-            self.notify_subscribers.notify(&id, &certificate);
-        }
+    /// Stages the insertion of a certificate in `unit`. The subscribers are notified and the
+    /// cache is updated once the unit is committed.
+    pub fn write_in(&self, unit: &mut UnitOfWork, certificate: Certificate) -> StoreResult<()> {
+        let id = certificate.digest();
 
-        // insert in cache
-        self.cache.write(certificate);
+        // write the certificate by its id, along with its secondary indexes
+        let (guard, batch) = unit.locked_batch(&self.certificates_by_id);
+        self.certificates_by_id
+            .insert_batch(guard, batch, &[(id, certificate.clone())])?;
 
-        fail_point!("narwhal-store-after-write");
-        result
+        let notify_subscribers = self.notify_subscribers.clone();
+        let cache = self.cache.clone();
+        unit.on_commit(move || {
+            notify_subscribers.notify(&id, &certificate);
+
+            // insert in cache
+            cache.write(certificate);
+        });
+        Ok(())
     }
 
     /// Inserts multiple certificates in the storage. This is an atomic operation.
@@ -233,6 +263,8 @@ impl<T: Cache> CertificateStore<T> {
     ) -> StoreResult<()> {
         fail_point!("narwhal-store-before-write");
 
+        // held until the cache is updated, so concurrent writes reach it in order
+        let guard = self.certificates_by_id.lock_writes();
         let mut batch = self.certificates_by_id.batch();
 
         let certificates: Vec<_> = certificates
@@ -240,27 +272,12 @@ impl<T: Cache> CertificateStore<T> {
             .map(|certificate| (certificate.digest(), certificate))
             .collect();
 
-        // write the certificates by their ids
-        batch.insert_batch(&self.certificates_by_id, certificates.clone())?;
-
-        // write the certificates id by their rounds
-        let values = certificates.iter().map(|(digest, c)| {
-            let key = (c.round(), c.origin());
-            let value = digest;
-            (key, value)
-        });
-        batch.insert_batch(&self.certificate_id_by_round, values)?;
-
-        // write the certificates id by their origins
-        let values = certificates.iter().map(|(digest, c)| {
-            let key = (c.origin(), c.round());
-            let value = digest;
-            (key, value)
-        });
-        batch.insert_batch(&self.certificate_id_by_origin, values)?;
+        // write the certificates by their ids, along with their secondary indexes
+        self.certificates_by_id
+            .insert_batch(&guard, &mut batch, &certificates)?;
 
         // execute the batch (atomically) and return the result
-        let result = batch.write();
+        let result = batch.write_sync();
 
         if result.is_ok() {
             for (_id, certificate) in &certificates {
@@ -287,7 +304,12 @@ impl<T: Cache> CertificateStore<T> {
             return Ok(Some(certificate));
         }
 
-        self.certificates_by_id.get(&id)
+        let certificate = self.certificates_by_id.column().get(&id)?;
+        if let Some(certificate) = &certificate {
+            self.cache
+                .fill(CacheOperation::Read, vec![certificate.clone()]);
+        }
+        Ok(certificate)
     }
 
     /// Retrieves a certificate from the store by round and authority.
@@ -297,7 +319,11 @@ impl<T: Cache> CertificateStore<T> {
         origin: AuthorityIdentifier,
         round: Round,
     ) -> StoreResult<Option<Certificate>> {
-        match self.certificate_id_by_origin.get(&(origin, round))? {
+        match self
+            .certificate_id_by_origin
+            .column()
+            .get(&(origin, round))?
+        {
             Some(d) => self.read(d),
             None => Ok(None),
         }
@@ -310,7 +336,7 @@ impl<T: Cache> CertificateStore<T> {
             return Ok(true);
         }
 
-        self.certificates_by_id.contains_key(id)
+        self.certificates_by_id.column().contains_key(id)
     }
 
     /// Retrieves multiple certificates by their provided ids. The results
@@ -332,16 +358,19 @@ impl<T: Cache> CertificateStore<T> {
             }
         }
 
-        // then fallback for all the misses on the storage
-        let from_store = self.certificates_by_id.multi_get(&missing)?;
+        // then fallback for all the misses on the storage, and cache what is found there
+        let from_store = self.certificates_by_id.column().multi_get(&missing)?;
+        let mut filled = Vec::new();
         from_store
-            .iter()
+            .into_iter()
             .zip(missing)
             .for_each(|(certificate, id)| {
                 if let Some(certificate) = certificate {
                     found.insert(id, certificate.clone());
+                    filled.push(certificate);
                 }
             });
+        self.cache.fill(CacheOperation::ReadAll, filled);
 
         Ok(ids.into_iter().map(|id| found.get(&id).cloned()).collect())
     }
@@ -370,25 +399,20 @@ impl<T: Cache> CertificateStore<T> {
     /// Deletes a single certificate by its digest.
     pub fn delete(&self, id: CertificateDigest) -> StoreResult<()> {
         fail_point!("narwhal-store-before-write");
-        // first read the certificate to get the round - we'll need in order
-        // to delete the secondary index
-        let cert = match self.read(id)? {
-            Some(cert) => cert,
-            None => return Ok(()),
-        };
 
+        // delete the certificate by its id, along with its secondary indexes
+        let guard = self.certificates_by_id.lock_writes();
         let mut batch = self.certificates_by_id.batch();
-
-        // write the certificate by its id
-        batch.delete_batch(&self.certificates_by_id, iter::once(id))?;
-
-        // write the certificate index by its round
-        let key = (cert.round(), cert.origin());
-
-        batch.delete_batch(&self.certificate_id_by_round, iter::once(key))?;
+        if self
+            .certificates_by_id
+            .delete_batch(&guard, &mut batch, iter::once(id))?
+            .is_empty()
+        {
+            return Ok(());
+        }
 
         // execute the batch (atomically) and return the result
-        let result = batch.write();
+        let result = batch.write_sync();
 
         if result.is_ok() {
             self.cache.remove(&id);
@@ -401,35 +425,102 @@ impl<T: Cache> CertificateStore<T> {
     /// Deletes multiple certificates in an atomic way.
     pub fn delete_all(&self, ids: impl IntoIterator<Item = CertificateDigest>) -> StoreResult<()> {
         fail_point!("narwhal-store-before-write");
-        // first read the certificates to get their rounds - we'll need in order
-        // to delete the secondary index
+
+        // delete the certificates by their ids, along with their secondary indexes
         let ids: Vec<CertificateDigest> = ids.into_iter().collect();
-        let certs = self.read_all(ids.clone())?;
-        let keys_by_round = certs
-            .into_iter()
-            .filter_map(|c| c.map(|cert| (cert.round(), cert.origin())))
-            .collect::<Vec<_>>();
-        if keys_by_round.is_empty() {
+        let guard = self.certificates_by_id.lock_writes();
+        let mut batch = self.certificates_by_id.batch();
+        if self
+            .certificates_by_id
+            .delete_batch(&guard, &mut batch, &ids)?
+            .is_empty()
+        {
             return Ok(());
         }
 
-        let mut batch = self.certificates_by_id.batch();
+        // execute the batch (atomically) and return the result
+        let result = batch.write_sync();
+
+        if result.is_ok() {
+            self.cache.remove_all(ids);
+        }
 
-        // delete the certificates from the secondary index
-        batch.delete_batch(&self.certificate_id_by_round, keys_by_round)?;
+        fail_point!("narwhal-store-after-write");
+        result
+    }
+
+    /// Deletes, in a single atomic batch, at most `limit` certificates of the lowest rounds that
+    /// are not above `round`, together with both their secondary indexes. The deleted certificates
+    /// are returned so the caller can also prune the headers and payload they reference. An empty
+    /// vector means there is nothing left to delete up to `round`.
+    pub fn prune_until_round(&self, round: Round, limit: usize) -> StoreResult<Vec<Certificate>> {
+        fail_point!("narwhal-store-before-write");
+
+        // The round index is sorted by round, so the oldest certificates come first.
+        let ids = self
+            .certificate_id_by_round
+            .column()
+            .iter()
+            .take_while(|((r, _), _)| *r <= round)
+            .take(limit)
+            .map(|(_, id)| id)
+            .collect::<Vec<_>>();
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
 
-        // delete the certificates by its ids
-        batch.delete_batch(&self.certificates_by_id, ids.clone())?;
+        // delete the certificates by their ids, along with their secondary indexes
+        let guard = self.certificates_by_id.lock_writes();
+        let mut batch = self.certificates_by_id.batch();
+        let certificates = self
+            .certificates_by_id
+            .delete_batch(&guard, &mut batch, &ids)?
+            .into_iter()
+            .map(|(_, certificate)| certificate)
+            .collect::<Vec<_>>();
 
         // execute the batch (atomically) and return the result
-        let result = batch.write();
+        let result = batch.write_sync();
 
         if result.is_ok() {
             self.cache.remove_all(ids);
         }
 
         fail_point!("narwhal-store-after-write");
-        result
+        result.map(|_| certificates)
+    }
+
+    /// Removes from `payload` the batches referenced by a certificate above `round`, so only the
+    /// payload markers that no retained certificate needs are pruned. The same batch can be
+    /// included again in a later header when the first one is not committed.
+    pub fn retain_unreferenced_payload(
+        &self,
+        round: Round,
+        payload: &mut HashSet<(BatchDigest, WorkerId)>,
+    ) -> StoreResult<()> {
+        // Skip to a row at or before the first retained round, see `after_round`.
+        let low_lex_addr = "aleo1aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+        let key = be_fix_int_ser(&(round, low_lex_addr))?;
+        let mut ids = self
+            .certificate_id_by_round
+            .column()
+            .iter()
+            .skip_to_bytes(key)?
+            .filter(|((r, _), _)| *r > round)
+            .map(|(_, id)| id)
+            .peekable();
+
+        // The retained certificates are read in chunks, until none of the batches is left.
+        while ids.peek().is_some() && !payload.is_empty() {
+            let chunk: Vec<_> = ids.by_ref().take(RETAINED_CERTIFICATES_CHUNK).collect();
+            let certificates = self.certificates_by_id.column().multi_get(chunk)?;
+            for certificate in certificates.into_iter().flatten() {
+                for (digest, (worker_id, _)) in certificate.header().payload() {
+                    payload.remove(&(*digest, *worker_id));
+                }
+            }
+        }
+        Ok(())
     }
 
     /// Retrieves all the certificates with round >= the provided round.
@@ -437,7 +528,7 @@ impl<T: Cache> CertificateStore<T> {
     pub fn after_round(&self, round: Round) -> StoreResult<Vec<Certificate>> {
         // Skip to a row at or before the requested round.
         // TODO: Add a more efficient seek method to typed store.
-        let mut iter = self.certificate_id_by_round.iter();
+        let mut iter = self.certificate_id_by_round.column().iter();
         if round > 0 {
             // Using a zeroed key here triggers a search by lexicographical ordering since there
             // won't be an exact match. The tuple is serialized for the lookup.
@@ -460,6 +551,7 @@ impl<T: Cache> CertificateStore<T> {
 
         // Fetch all those certificates from main storage, return an error if any one is missing.
         self.certificates_by_id
+            .column()
             .multi_get(digests.clone())?
             .into_iter()
             .map(|opt_cert| {
@@ -480,7 +572,7 @@ impl<T: Cache> CertificateStore<T> {
     ) -> StoreResult<BTreeMap<Round, Vec<AuthorityIdentifier>>> {
         // Skip to a row at or before the requested round.
         // TODO: Add a more efficient seek method to typed store.
-        let mut iter = self.certificate_id_by_round.iter();
+        let mut iter = self.certificate_id_by_round.column().iter();
         if round > 0 {
             let low_lex_addr = "aleo1aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
             let key = be_fix_int_ser(&(round - 1, low_lex_addr))?;
@@ -501,7 +593,12 @@ impl<T: Cache> CertificateStore<T> {
     pub fn last_two_rounds_certs(&self) -> StoreResult<Vec<Certificate>> {
         // starting from the last element - hence the last round - move backwards until
         // we find certificates of different round.
-        let certificates_reverse = self.certificate_id_by_round.iter().skip_to_last().reverse();
+        let certificates_reverse = self
+            .certificate_id_by_round
+            .column()
+            .iter()
+            .skip_to_last()
+            .reverse();
 
         let mut round = 0;
         let mut certificates = Vec::new();
@@ -519,12 +616,16 @@ impl<T: Cache> CertificateStore<T> {
                 break;
             }
 
-            let certificate = self.certificates_by_id.get(&digest)?.ok_or_else(|| {
-                RocksDBError(format!(
-                    "Certificate with id {} not found in main storage although it should",
-                    digest
-                ))
-            })?;
+            let certificate = self
+                .certificates_by_id
+                .column()
+                .get(&digest)?
+                .ok_or_else(|| {
+                    RocksDBError(format!(
+                        "Certificate with id {} not found in main storage although it should",
+                        digest
+                    ))
+                })?;
 
             certificates.push(certificate);
         }
@@ -538,6 +639,7 @@ impl<T: Cache> CertificateStore<T> {
         let key = (origin, Round::MAX);
         if let Some(((name, _round), digest)) = self
             .certificate_id_by_origin
+            .column()
             .iter()
             .skip_prior_to(&key)?
             .next()
@@ -554,6 +656,7 @@ impl<T: Cache> CertificateStore<T> {
     pub fn highest_round_number(&self) -> Round {
         if let Some(((round, _), _)) = self
             .certificate_id_by_round
+            .column()
             .iter()
             .skip_to_last()
             .reverse()
@@ -571,6 +674,7 @@ impl<T: Cache> CertificateStore<T> {
         let key = (origin, Round::MAX);
         if let Some(((name, round), _)) = self
             .certificate_id_by_origin
+            .column()
             .iter()
             .skip_prior_to(&key)?
             .next()
@@ -590,7 +694,12 @@ impl<T: Cache> CertificateStore<T> {
         round: Round,
     ) -> StoreResult<Option<Round>> {
         let key = (origin, round + 1);
-        if let Some(((name, round), _)) = self.certificate_id_by_origin.iter().skip_to(&key)?.next()
+        if let Some(((name, round), _)) = self
+            .certificate_id_by_origin
+            .column()
+            .iter()
+            .skip_to(&key)?
+            .next()
         {
             if name == origin {
                 return Ok(Some(round));
@@ -604,8 +713,6 @@ impl<T: Cache> CertificateStore<T> {
         fail_point!("narwhal-store-before-write");
 
         self.certificates_by_id.clear()?;
-        self.certificate_id_by_round.clear()?;
-        self.certificate_id_by_origin.clear()?;
 
         fail_point!("narwhal-store-after-write");
         Ok(())
@@ -614,7 +721,7 @@ impl<T: Cache> CertificateStore<T> {
     /// Checks whether the storage is empty. The main storage is
     /// being used to determine this.
     pub fn is_empty(&self) -> bool {
-        self.certificates_by_id.is_empty()
+        self.certificates_by_id.column().is_empty()
     }
 }
 
@@ -622,7 +729,8 @@ impl<T: Cache> CertificateStore<T> {
 mod test {
     use crate::certificate_store::{CertificateStore, NoCache};
     use crate::{Cache, CertificateStoreCache};
-    use config::AuthorityIdentifier;
+    use crate::{Columns, NodeStorage};
+    use config::{AuthorityIdentifier, WorkerId};
     use crypto::Hash;
     use futures::future::join_all;
     use std::num::NonZeroUsize;
@@ -630,16 +738,13 @@ mod test {
         collections::{BTreeSet, HashSet},
         time::Instant,
     };
-    use store::{
-        reopen,
-        rocks::{open_cf, DBMap, ReadWriteOptions},
-    };
-    use test_utils::{temp_dir, CommitteeFixture};
-    use types::{Certificate, CertificateAPI, CertificateDigest, HeaderAPI, Round};
+    use store::sally::SallyColumn;
+    use test_utils::CommitteeFixture;
+    use types::{BatchDigest, Certificate, CertificateAPI, CertificateDigest, HeaderAPI, Round};
 
-    fn new_store(path: std::path::PathBuf) -> CertificateStore {
+    fn new_store() -> CertificateStore {
         let (certificate_map, certificate_id_by_round_map, certificate_id_by_origin_map) =
-            create_db_maps(path);
+            create_db_maps();
 
         let store_cache = CertificateStoreCache::new(NonZeroUsize::new(100).unwrap());
 
@@ -651,9 +756,9 @@ mod test {
         )
     }
 
-    fn new_store_no_cache(path: std::path::PathBuf) -> CertificateStore<NoCache> {
+    fn new_store_no_cache() -> CertificateStore<NoCache> {
         let (certificate_map, certificate_id_by_round_map, certificate_id_by_origin_map) =
-            create_db_maps(path);
+            create_db_maps();
 
         CertificateStore::new(
             certificate_map,
@@ -663,32 +768,21 @@ mod test {
         )
     }
 
-    fn create_db_maps(
-        path: std::path::PathBuf,
-    ) -> (
-        DBMap<CertificateDigest, Certificate>,
-        DBMap<(Round, AuthorityIdentifier), CertificateDigest>,
-        DBMap<(AuthorityIdentifier, Round), CertificateDigest>,
+    fn create_db_maps() -> (
+        SallyColumn<CertificateDigest, Certificate>,
+        SallyColumn<(Round, AuthorityIdentifier), CertificateDigest>,
+        SallyColumn<(AuthorityIdentifier, Round), CertificateDigest>,
     ) {
-        const CERTIFICATES_CF: &str = "certificates";
-        const CERTIFICATE_ID_BY_ROUND_CF: &str = "certificate_id_by_round";
-        const CERTIFICATE_ID_BY_ORIGIN_CF: &str = "certificate_id_by_origin";
-
-        let rocksdb = open_cf(
-            path,
-            None,
-            &[
-                CERTIFICATES_CF,
-                CERTIFICATE_ID_BY_ROUND_CF,
-                CERTIFICATE_ID_BY_ORIGIN_CF,
-            ],
-        )
-        .expect("Cannot open database");
-
-        reopen!(&rocksdb,
-            CERTIFICATES_CF;<CertificateDigest, Certificate>,
-            CERTIFICATE_ID_BY_ROUND_CF;<(Round, AuthorityIdentifier), CertificateDigest>,
-            CERTIFICATE_ID_BY_ORIGIN_CF;<(AuthorityIdentifier, Round), CertificateDigest>
+        let columns = Columns::for_tests(&[
+            NodeStorage::CERTIFICATES_CF,
+            NodeStorage::CERTIFICATE_DIGEST_BY_ROUND_CF,
+            NodeStorage::CERTIFICATE_DIGEST_BY_ORIGIN_CF,
+        ]);
+
+        (
+            columns.open(NodeStorage::CERTIFICATES_CF),
+            columns.open(NodeStorage::CERTIFICATE_DIGEST_BY_ROUND_CF),
+            columns.open(NodeStorage::CERTIFICATE_DIGEST_BY_ORIGIN_CF),
         )
     }
 
@@ -721,8 +815,8 @@ mod test {
 
     #[tokio::test]
     async fn test_write_and_read() {
-        test_write_and_read_by_store_type(new_store(temp_dir())).await;
-        test_write_and_read_by_store_type(new_store_no_cache(temp_dir())).await;
+        test_write_and_read_by_store_type(new_store()).await;
+        test_write_and_read_by_store_type(new_store_no_cache()).await;
     }
 
     async fn test_write_and_read_by_store_type<T: Cache>(store: CertificateStore<T>) {
@@ -744,8 +838,8 @@ mod test {
 
     #[tokio::test]
     async fn test_write_all_and_read_all() {
-        test_write_all_and_read_all_by_store_type(new_store(temp_dir())).await;
-        test_write_all_and_read_all_by_store_type(new_store_no_cache(temp_dir())).await;
+        test_write_all_and_read_all_by_store_type(new_store()).await;
+        test_write_all_and_read_all_by_store_type(new_store_no_cache()).await;
     }
 
     async fn test_write_all_and_read_all_by_store_type<T: Cache>(store: CertificateStore<T>) {
@@ -782,7 +876,7 @@ mod test {
     #[tokio::test]
     async fn test_next_round_number() {
         // GIVEN
-        let store = new_store(temp_dir());
+        let store = new_store();
 
         // Create certificates for round 1, 2, 4, 6, 9, 10.
         let cert = certificates(1).first().unwrap().clone();
@@ -810,7 +904,7 @@ mod test {
     #[tokio::test]
     async fn test_last_two_rounds() {
         // GIVEN
-        let store = new_store(temp_dir());
+        let store = new_store();
 
         // create certificates for 50 rounds
         let certs = certificates(50);
@@ -844,7 +938,7 @@ mod test {
     #[tokio::test]
     async fn test_last_round_in_empty_store() {
         // GIVEN
-        let store = new_store(temp_dir());
+        let store = new_store();
 
         // WHEN
         let result = store.last_two_rounds_certs().unwrap();
@@ -864,7 +958,7 @@ mod test {
     #[tokio::test]
     async fn test_after_round() {
         // GIVEN
-        let store = new_store(temp_dir());
+        let store = new_store();
         let total_rounds = 100;
 
         // create certificates for 50 rounds
@@ -944,7 +1038,7 @@ mod test {
 
     #[tokio::test]
     async fn test_notify_read() {
-        let store = new_store(temp_dir());
+        let store = new_store();
 
         // run the tests a few times
         for _ in 0..10 {
@@ -1002,7 +1096,7 @@ mod test {
 
     #[tokio::test]
     async fn test_write_all_and_clear() {
-        let store = new_store(temp_dir());
+        let store = new_store();
 
         // create certificates for 10 rounds
         let certs = certificates(10);
@@ -1022,8 +1116,8 @@ mod test {
 
     #[tokio::test]
     async fn test_delete_by_store_type() {
-        test_delete(new_store(temp_dir())).await;
-        test_delete(new_store_no_cache(temp_dir())).await;
+        test_delete(new_store()).await;
+        test_delete(new_store_no_cache()).await;
     }
 
     async fn test_delete<T: Cache>(store: CertificateStore<T>) {
@@ -1043,12 +1137,19 @@ mod test {
         // THEN
         assert!(store.read(to_delete[0]).unwrap().is_none());
         assert!(store.read(to_delete[1]).unwrap().is_none());
+
+        // AND the deleted certificates are gone from the index by origin
+        let deleted = certs[0].clone();
+        assert_ne!(
+            store.next_round_number(deleted.origin(), 0).unwrap(),
+            Some(deleted.round())
+        );
     }
 
     #[tokio::test]
     async fn test_delete_all_by_store_type() {
-        test_delete_all(new_store(temp_dir())).await;
-        test_delete_all(new_store_no_cache(temp_dir())).await;
+        test_delete_all(new_store()).await;
+        test_delete_all(new_store_no_cache()).await;
     }
 
     async fn test_delete_all<T: Cache>(store: CertificateStore<T>) {
@@ -1069,6 +1170,75 @@ mod test {
         assert!(store.read(to_delete[1]).unwrap().is_none());
     }
 
+    #[tokio::test]
+    async fn test_prune_until_round_by_store_type() {
+        test_prune_until_round(new_store()).await;
+        test_prune_until_round(new_store_no_cache()).await;
+    }
+
+    async fn test_prune_until_round<T: Cache>(store: CertificateStore<T>) {
+        // GIVEN
+        // create certificates for 10 rounds
+        let certs = certificates(10);
+        store.write_all(certs.clone()).unwrap();
+
+        // WHEN we prune up to round 5, a few certificates at a time
+        let mut pruned = Vec::new();
+        loop {
+            let batch = store.prune_until_round(5, 3).unwrap();
+            if batch.is_empty() {
+                break;
+            }
+            assert!(batch.len() <= 3);
+            pruned.extend(batch);
+        }
+
+        // THEN only the certificates up to round 5 are gone, including from the indexes
+        let expected: HashSet<_> = certs
+            .iter()
+            .filter(|c| c.round() <= 5)
+            .map(|c| c.digest())
+            .collect();
+        let pruned: HashSet<_> = pruned.iter().map(|c| c.digest()).collect();
+        assert_eq!(pruned, expected);
+
+        for cert in &certs {
+            assert_eq!(
+                store.read(cert.digest()).unwrap().is_some(),
+                cert.round() > 5
+            );
+            assert_eq!(
+                store.last_round_number(cert.origin()).unwrap(),
+                Some(10),
+                "the latest round of each origin is kept"
+            );
+        }
+        assert!(store.after_round(0).unwrap().iter().all(|c| c.round() > 5));
+        assert!(store.origins_after_round(0).unwrap().keys().all(|r| *r > 5));
+
+        // AND the payload of the pruned certificates is only referenced by them
+        let mut payload: HashSet<_> = pruned_payload(&certs, 5);
+        store.retain_unreferenced_payload(5, &mut payload).unwrap();
+        assert_eq!(payload, pruned_payload(&certs, 5));
+
+        // unless a retained certificate includes the same batch
+        let retained = certs.iter().find(|c| c.round() == 6).unwrap();
+        let shared = *retained.header().payload().keys().next().unwrap();
+        let worker_id = retained.header().payload()[&shared].0;
+        payload.insert((shared, worker_id));
+        store.retain_unreferenced_payload(5, &mut payload).unwrap();
+        assert_eq!(payload, pruned_payload(&certs, 5));
+    }
+
+    fn pruned_payload(certs: &[Certificate], round: Round) -> HashSet<(BatchDigest, WorkerId)> {
+        certs
+            .iter()
+            .filter(|c| c.round() <= round)
+            .flat_map(|c| c.header().payload())
+            .map(|(digest, (worker_id, _))| (*digest, *worker_id))
+            .collect()
+    }
+
     #[test]
     fn test_cache() {
         // cache should hold up to 5 elements
@@ -1105,4 +1275,36 @@ mod test {
             }
         }
     }
+    #[test]
+    fn test_read_through_cache() {
+        let (certificate_map, certificate_id_by_round_map, certificate_id_by_origin_map) =
+            create_db_maps();
+        let writer = CertificateStore::new(
+            certificate_map.clone(),
+            certificate_id_by_round_map.clone(),
+            certificate_id_by_origin_map.clone(),
+            NoCache {},
+        );
+        let cache = CertificateStoreCache::new(NonZeroUsize::new(100).unwrap());
+        let store = CertificateStore::new(
+            certificate_map,
+            certificate_id_by_round_map,
+            certificate_id_by_origin_map,
+            cache.clone(),
+        );
+
+        let certificates = certificates(2);
+        writer.write_all(certificates.clone()).unwrap();
+        let ids: Vec<_> = certificates.iter().map(|c| c.digest()).collect();
+        assert!(ids.iter().all(|id| !cache.contains(id)));
+
+        // The certificates missed by the cache are read from the storage, then cached.
+        assert_eq!(store.read(ids[0]).unwrap().as_ref(), Some(&certificates[0]));
+        assert!(cache.contains(&ids[0]));
+        assert!(!cache.contains(&ids[1]));
+
+        let read = store.read_all(ids.clone()).unwrap();
+        assert_eq!(read.into_iter().flatten().count(), certificates.len());
+        assert!(ids.iter().all(|id| cache.contains(id)));
+    }
 }
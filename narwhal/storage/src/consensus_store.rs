@@ -1,11 +1,11 @@
 // Copyright (c) Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::{NodeStorage, StoreResult};
+use crate::{Columns, NodeStorage, StoreResult, UnitOfWork};
 use config::AuthorityIdentifier;
 use std::collections::HashMap;
-use store::rocks::{open_cf, DBMap, ReadWriteOptions};
-use store::{reopen, Map, TypedStoreError};
+use store::sally::SallyColumn;
+use store::{Map, TypedStoreError};
 use types::{
     CommittedSubDag, CommittedSubDagShell, ConsensusCommit, ConsensusCommitV2, Round,
     SequenceNumber,
@@ -14,20 +14,20 @@ use types::{
 /// The persistent storage of the sequencer.
 pub struct ConsensusStore {
     /// The latest committed round of each validator.
-    last_committed: DBMap<AuthorityIdentifier, Round>,
+    last_committed: SallyColumn<AuthorityIdentifier, Round>,
     /// TODO: remove once released to validators
     /// The global consensus sequence.
-    committed_sub_dags_by_index: DBMap<SequenceNumber, CommittedSubDagShell>,
+    committed_sub_dags_by_index: SallyColumn<SequenceNumber, CommittedSubDagShell>,
     /// The global consensus sequence
-    committed_sub_dags_by_index_v2: DBMap<SequenceNumber, ConsensusCommit>,
+    committed_sub_dags_by_index_v2: SallyColumn<SequenceNumber, ConsensusCommit>,
 }
 
 impl ConsensusStore {
     /// Create a new consensus store structure by using already loaded maps.
     pub fn new(
-        last_committed: DBMap<AuthorityIdentifier, Round>,
-        sequence: DBMap<SequenceNumber, CommittedSubDagShell>,
-        committed_sub_dags_map: DBMap<SequenceNumber, ConsensusCommit>,
+        last_committed: SallyColumn<AuthorityIdentifier, Round>,
+        sequence: SallyColumn<SequenceNumber, CommittedSubDagShell>,
+        committed_sub_dags_map: SallyColumn<SequenceNumber, ConsensusCommit>,
     ) -> Self {
         Self {
             last_committed,
@@ -37,18 +37,16 @@ impl ConsensusStore {
     }
 
     pub fn new_for_tests() -> Self {
-        let rocksdb = open_cf(
-            tempfile::tempdir().unwrap(),
-            None,
-            &[
-                NodeStorage::LAST_COMMITTED_CF,
-                NodeStorage::SUB_DAG_INDEX_CF,
-                NodeStorage::COMMITTED_SUB_DAG_INDEX_CF,
-            ],
+        let columns = Columns::for_tests(&[
+            NodeStorage::LAST_COMMITTED_CF,
+            NodeStorage::SUB_DAG_INDEX_CF,
+            NodeStorage::COMMITTED_SUB_DAG_INDEX_CF,
+        ]);
+        Self::new(
+            columns.open(NodeStorage::LAST_COMMITTED_CF),
+            columns.open(NodeStorage::SUB_DAG_INDEX_CF),
+            columns.open(NodeStorage::COMMITTED_SUB_DAG_INDEX_CF),
         )
-        .expect("Cannot open database");
-        let (last_committed_map, sub_dag_index_map, committed_sub_dag_map) = reopen!(&rocksdb, NodeStorage::LAST_COMMITTED_CF;<AuthorityIdentifier, Round>, NodeStorage::SUB_DAG_INDEX_CF;<SequenceNumber, CommittedSubDagShell>, NodeStorage::COMMITTED_SUB_DAG_INDEX_CF;<SequenceNumber, ConsensusCommit>);
-        Self::new(last_committed_map, sub_dag_index_map, committed_sub_dag_map)
     }
 
     /// Clear the store.
@@ -64,16 +62,45 @@ impl ConsensusStore {
         &self,
         last_committed: &HashMap<AuthorityIdentifier, Round>,
         sub_dag: &CommittedSubDag,
+    ) -> Result<(), TypedStoreError> {
+        let mut unit = UnitOfWork::new(self.last_committed.batch());
+        self.write_consensus_state_in(&mut unit, last_committed, sub_dag)?;
+        unit.commit()
+    }
+
+    /// Stages the persistence of the consensus state in `unit`.
+    pub fn write_consensus_state_in(
+        &self,
+        unit: &mut UnitOfWork,
+        last_committed: &HashMap<AuthorityIdentifier, Round>,
+        sub_dag: &CommittedSubDag,
     ) -> Result<(), TypedStoreError> {
         let commit = ConsensusCommit::V2(ConsensusCommitV2::from_sub_dag(sub_dag));
 
+        unit.batch()
+            .insert_batch(&self.last_committed, last_committed.iter())?;
+        unit.batch().insert_batch(
+            &self.committed_sub_dags_by_index_v2,
+            std::iter::once((sub_dag.sub_dag_index, commit)),
+        )
+    }
+
+    /// Persist a consensus state synced from peers: the last committed round of each validator
+    /// and the most recent committed sub dags.
+    pub fn write_consensus_snapshot(
+        &self,
+        last_committed: &HashMap<AuthorityIdentifier, Round>,
+        sub_dags: &[ConsensusCommit],
+    ) -> Result<(), TypedStoreError> {
         let mut write_batch = self.last_committed.batch();
         write_batch.insert_batch(&self.last_committed, last_committed.iter())?;
         write_batch.insert_batch(
             &self.committed_sub_dags_by_index_v2,
-            std::iter::once((sub_dag.sub_dag_index, commit)),
+            sub_dags
+                .iter()
+                .map(|sub_dag| (sub_dag.sub_dag_index(), sub_dag.clone())),
         )?;
-        write_batch.write()
+        write_batch.write_sync()
     }
 
     /// Load the last committed round of each validator.
@@ -127,6 +154,37 @@ impl ConsensusStore {
             .map(|(_, sub_dag)| ConsensusCommit::V1(sub_dag))
     }
 
+    /// Load the sub dag committed with the sequence number `index`, if any.
+    pub fn read_committed_sub_dag(
+        &self,
+        index: &SequenceNumber,
+    ) -> StoreResult<Option<ConsensusCommit>> {
+        if let Some(sub_dag) = self.committed_sub_dags_by_index_v2.get(index)? {
+            return Ok(Some(sub_dag));
+        }
+
+        // TODO: remove once this has been released to the validators
+        Ok(self
+            .committed_sub_dags_by_index
+            .get(index)?
+            .map(ConsensusCommit::V1))
+    }
+
+    /// Load the sub dags committed with a leader above `round`, newest first.
+    pub fn read_committed_sub_dags_after_round(
+        &self,
+        round: Round,
+    ) -> StoreResult<Vec<ConsensusCommit>> {
+        Ok(self
+            .committed_sub_dags_by_index_v2
+            .iter()
+            .skip_to_last()
+            .reverse()
+            .map(|(_, sub_dag)| sub_dag)
+            .take_while(|sub_dag| sub_dag.leader_round() > round)
+            .collect())
+    }
+
     /// Load all the sub dags committed with sequence number of at least `from`.
     pub fn read_committed_sub_dags_from(
         &self,
@@ -217,5 +275,55 @@ mod test {
         // Read the last sub dag index
         let index = store.get_latest_sub_dag_index();
         assert_eq!(index, 5);
+
+        // Read single sub dags from both tables
+        assert_eq!(
+            store
+                .read_committed_sub_dag(&1)
+                .unwrap()
+                .unwrap()
+                .sub_dag_index(),
+            1
+        );
+        assert_eq!(
+            store
+                .read_committed_sub_dag(&4)
+                .unwrap()
+                .unwrap()
+                .commit_timestamp(),
+            4
+        );
+        assert!(store.read_committed_sub_dag(&6).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_read_committed_sub_dags_after_round() {
+        let store = ConsensusStore::new_for_tests();
+
+        for i in 1..=5 {
+            let s = ConsensusCommitV2 {
+                certificates: vec![],
+                leader: Default::default(),
+                leader_round: 2 * i,
+                sub_dag_index: i,
+                reputation_score: Default::default(),
+                commit_timestamp: i,
+            };
+
+            store
+                .committed_sub_dags_by_index_v2
+                .insert(&s.sub_dag_index.clone(), &ConsensusCommit::V2(s))
+                .unwrap();
+        }
+
+        // Only the sub dags with a leader above round 5 are returned, newest first.
+        let sub_dags = store.read_committed_sub_dags_after_round(5).unwrap();
+        let rounds: Vec<_> = sub_dags.iter().map(|s| s.leader_round()).collect();
+        assert_eq!(rounds, vec![10, 8, 6]);
+
+        assert!(store
+            .read_committed_sub_dags_after_round(10)
+            .unwrap()
+            .is_empty());
     }
 }
@@ -0,0 +1,192 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use crate::NodeStorage;
+use once_cell::sync::OnceCell;
+use prometheus::Registry;
+use std::time::Duration;
+use store::metrics::DBMetrics;
+use tokio::task::JoinHandle;
+use tokio::time::{interval_at, Instant};
+use tracing::{info, warn};
+use types::ConditionalBroadcastReceiver;
+
+#[cfg(feature = "metrics")]
+use metrics::{absolute_counter, gauge, Label};
+use prometheus::proto::MetricType;
+
+/// The registry holding the `DBMetrics` of typed-store, which records them on prometheus while the
+/// node exports its metrics through snarkos-metrics.
+static DB_METRICS_REGISTRY: OnceCell<Registry> = OnceCell::new();
+
+/// Initializes the `DBMetrics` on a registry of their own, once per process. It has to run before
+/// the first column is opened, otherwise typed-store records its metrics on the default prometheus
+/// registry: they are then exported from it, along with the other metrics it holds.
+pub fn init_db_metrics() -> &'static Registry {
+    DB_METRICS_REGISTRY.get_or_init(|| {
+        let registry = Registry::new();
+        if DBMetrics::try_init(&registry).is_some() {
+            return registry;
+        }
+        warn!("A column was opened before init_db_metrics, exporting the default registry");
+        prometheus::default_registry().clone()
+    })
+}
+
+/// Spawns the task which, every `period`, reads the properties of the column families of `store`
+/// and exports them with the latencies and sizes of the operations on the storage, next to the
+/// other metrics of the node.
+pub fn spawn_db_metrics_export(
+    store: NodeStorage,
+    period: Duration,
+    mut rx_shutdown: ConditionalBroadcastReceiver,
+) -> JoinHandle<()> {
+    let registry = init_db_metrics();
+    tokio::spawn(async move {
+        info!(
+            "Exporting the RocksDB metrics of the node storage every {} ms",
+            period.as_millis()
+        );
+        let mut timer = interval_at(Instant::now() + period, period);
+        loop {
+            tokio::select! {
+                _ = timer.tick() => {
+                    store.report_metrics();
+                    export(registry);
+                }
+
+                _ = rx_shutdown.receiver.recv() => {
+                    return
+                }
+            }
+        }
+    })
+}
+
+/// A value of a metric of `registry`, as exported to snarkos-metrics.
+#[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+#[derive(Clone, Debug, PartialEq)]
+struct Sample {
+    name: String,
+    labels: Vec<(String, String)>,
+    value: SampleValue,
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+#[derive(Clone, Debug, PartialEq)]
+enum SampleValue {
+    Gauge(f64),
+    Counter(u64),
+}
+
+/// Reads the metrics of `registry` under their names and labels. The buckets of the histograms
+/// are not read, only their count and sum.
+#[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+fn samples(registry: &Registry) -> Vec<Sample> {
+    let mut samples = Vec::new();
+    for family in registry.gather() {
+        let name = family.get_name();
+        for metric in family.get_metric() {
+            let labels: Vec<(String, String)> = metric
+                .get_label()
+                .iter()
+                .map(|pair| (pair.get_name().to_string(), pair.get_value().to_string()))
+                .collect();
+            let mut sample = |name: String, value| {
+                samples.push(Sample {
+                    name,
+                    labels: labels.clone(),
+                    value,
+                })
+            };
+            match family.get_field_type() {
+                MetricType::GAUGE => sample(
+                    name.to_string(),
+                    SampleValue::Gauge(metric.get_gauge().get_value()),
+                ),
+                MetricType::COUNTER => sample(
+                    name.to_string(),
+                    SampleValue::Counter(metric.get_counter().get_value() as u64),
+                ),
+                MetricType::HISTOGRAM => {
+                    let histogram = metric.get_histogram();
+                    sample(
+                        format!("{name}_count"),
+                        SampleValue::Counter(histogram.get_sample_count()),
+                    );
+                    sample(
+                        format!("{name}_sum"),
+                        SampleValue::Gauge(histogram.get_sample_sum()),
+                    );
+                }
+                _ => (),
+            }
+        }
+    }
+    samples
+}
+
+/// Copies the metrics of `registry` to snarkos-metrics.
+#[cfg(feature = "metrics")]
+fn export(registry: &Registry) {
+    for sample in samples(registry) {
+        let labels: Vec<Label> = sample
+            .labels
+            .into_iter()
+            .map(|(name, value)| Label::new(name, value))
+            .collect();
+        match sample.value {
+            SampleValue::Gauge(value) => gauge!(sample.name, value, labels),
+            SampleValue::Counter(value) => absolute_counter!(sample.name, value, labels),
+        }
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+fn export(_registry: &Registry) {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crypto::Hash;
+    use store::Map;
+
+    /// The exported value of the metric `name` with the label `cf_name`.
+    fn exported(registry: &Registry, name: &str, cf_name: &str) -> Option<SampleValue> {
+        samples(registry)
+            .into_iter()
+            .find(|sample| {
+                sample.name == name
+                    && sample
+                        .labels
+                        .contains(&("cf_name".to_string(), cf_name.to_string()))
+            })
+            .map(|sample| sample.value)
+    }
+
+    #[test]
+    fn test_db_metrics() {
+        let registry = init_db_metrics();
+        let path = tempfile::tempdir().unwrap();
+        let store = NodeStorage::reopen(path.path());
+        let batch = test_utils::fixture_batch_with_transactions(1);
+        store.batch_store.insert(&batch.digest(), &batch).unwrap();
+
+        // The operations are sampled, one in a hundred records its latency.
+        for _ in 0..100 {
+            store.batch_store.column().get(&batch.digest()).unwrap();
+        }
+        store.report_metrics();
+
+        // The operations and the column family properties are exported.
+        let cf = NodeStorage::BATCHES_CF;
+        assert!(matches!(
+            exported(registry, "rocksdb_get_latency_seconds_count", cf),
+            Some(SampleValue::Counter(count)) if count > 0
+        ));
+        assert!(matches!(
+            exported(registry, "rocksdb_estimated_num_keys", cf),
+            Some(SampleValue::Gauge(keys)) if keys > 0.0
+        ));
+        export(registry);
+    }
+}
@@ -0,0 +1,130 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{Columns, NodeStorage, StoreResult};
+use std::collections::BTreeMap;
+use store::sally::SallyColumn;
+use store::Map;
+use types::{ExecutionIndices, SequenceNumber, SystemTransaction};
+
+/// The key of the last executed indices in their column family.
+const LAST_EXECUTED_KEY: u8 = 0;
+
+/// The storage of the position in the consensus output acknowledged by the execution state.
+#[derive(Clone)]
+pub struct ExecutionStore {
+    last_executed: SallyColumn<u8, ExecutionIndices>,
+    /// The signed committee changes found in the consensus output, by the index of their sub dag.
+    /// They are kept with their signatures, to be served to the primaries syncing a snapshot.
+    committee_changes: SallyColumn<SequenceNumber, SystemTransaction>,
+}
+
+impl ExecutionStore {
+    pub fn new(
+        last_executed: SallyColumn<u8, ExecutionIndices>,
+        committee_changes: SallyColumn<SequenceNumber, SystemTransaction>,
+    ) -> Self {
+        Self {
+            last_executed,
+            committee_changes,
+        }
+    }
+
+    pub fn new_for_tests() -> Self {
+        let columns = Columns::for_tests(&[
+            NodeStorage::LAST_EXECUTED_CF,
+            NodeStorage::COMMITTEE_CHANGES_CF,
+        ]);
+        Self::new(
+            columns.open(NodeStorage::LAST_EXECUTED_CF),
+            columns.open(NodeStorage::COMMITTEE_CHANGES_CF),
+        )
+    }
+
+    /// Persists the indices of the last transaction acknowledged by the execution state.
+    pub fn write_last_executed(&self, indices: &ExecutionIndices) -> StoreResult<()> {
+        self.last_executed.insert(&LAST_EXECUTED_KEY, indices)
+    }
+
+    /// Reads the indices of the last transaction acknowledged by the execution state, None if
+    /// nothing has been executed yet.
+    pub fn read_last_executed(&self) -> StoreResult<Option<ExecutionIndices>> {
+        self.last_executed.get(&LAST_EXECUTED_KEY)
+    }
+
+    /// Persists a committee change found in the sub dag `sub_dag_index`.
+    pub fn write_committee_change(
+        &self,
+        sub_dag_index: SequenceNumber,
+        transaction: &SystemTransaction,
+    ) -> StoreResult<()> {
+        self.committee_changes.insert(&sub_dag_index, transaction)
+    }
+
+    /// Reads the committee changes found so far, by the index of their sub dag.
+    pub fn read_committee_changes(
+        &self,
+    ) -> StoreResult<BTreeMap<SequenceNumber, SystemTransaction>> {
+        Ok(self.committee_changes.iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ExecutionStore;
+    use types::{CommitteeChange, ExecutionIndices, ScheduledCommitteeChange, SystemTransaction};
+
+    #[test]
+    fn test_read_write_last_executed() {
+        let store = ExecutionStore::new_for_tests();
+        assert_eq!(store.read_last_executed().unwrap(), None);
+
+        let indices = ExecutionIndices {
+            last_committed_round: 4,
+            sub_dag_index: 2,
+            transaction_index: 7,
+        };
+        store.write_last_executed(&indices).unwrap();
+        assert_eq!(store.read_last_executed().unwrap(), Some(indices));
+
+        let end = ExecutionIndices::end_of_sub_dag(4, 2);
+        store.write_last_executed(&end).unwrap();
+        assert!(store
+            .read_last_executed()
+            .unwrap()
+            .unwrap()
+            .is_end_of_sub_dag());
+    }
+
+    #[test]
+    fn test_read_write_committee_changes() {
+        let store = ExecutionStore::new_for_tests();
+        assert!(store.read_committee_changes().unwrap().is_empty());
+
+        let change = |sub_dag_index| SystemTransaction::ScheduleCommitteeChange {
+            change: ScheduledCommitteeChange {
+                epoch: 0,
+                sub_dag_index,
+                changes: vec![CommitteeChange::RemoveAuthority {
+                    protocol_key: Default::default(),
+                }],
+            },
+            signatures: Vec::new(),
+        };
+        store.write_committee_change(7, &change(12)).unwrap();
+        store.write_committee_change(3, &change(10)).unwrap();
+
+        let changes = store.read_committee_changes().unwrap();
+        assert_eq!(
+            changes
+                .iter()
+                .map(
+                    |(index, SystemTransaction::ScheduleCommitteeChange { change, .. })| {
+                        (*index, change.sub_dag_index)
+                    }
+                )
+                .collect::<Vec<_>>(),
+            vec![(3, 10), (7, 12)]
+        );
+    }
+}
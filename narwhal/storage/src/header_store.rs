@@ -1,54 +1,159 @@
 // Copyright (c) Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::NodeStorage;
-use store::rocks::ReadWriteOptions;
-use store::rocks::{open_cf, DBMap};
-use store::{reopen, Map, TypedStoreError};
+use crate::{CacheOperation, Columns, NodeStorage, StoreCache, UnitOfWork};
+use store::sally::{
+    index::{IndexedColumn, SecondaryIndex},
+    SallyColumn,
+};
+use store::{Map, TypedStoreError};
 use sui_macros::fail_point;
-use types::{Header, HeaderDigest};
+use types::{Header, HeaderAPI, HeaderDigest, Round};
 
 #[derive(Clone)]
 pub struct HeaderStore {
-    store: DBMap<HeaderDigest, Header>,
+    /// The headers by their digest, along with their secondary index.
+    store: IndexedColumn<HeaderDigest, Header>,
+    /// A secondary index that keeps the header digests by round, to find the headers that fell
+    /// below the GC round.
+    header_id_by_round: SecondaryIndex<HeaderDigest, Header, (Round, HeaderDigest)>,
+    /// An LRU cache to keep recent headers, disabled when its size is zero.
+    cache: StoreCache<HeaderDigest, Header>,
 }
 
 impl HeaderStore {
-    pub fn new(header_store: DBMap<HeaderDigest, Header>) -> Self {
+    /// The name of the header store in the cache metrics.
+    const STORE_NAME: &'static str = "headers";
+
+    pub fn new(
+        header_store: SallyColumn<HeaderDigest, Header>,
+        header_id_by_round: SallyColumn<(Round, HeaderDigest), HeaderDigest>,
+        cache_size: usize,
+    ) -> Self {
+        let header_id_by_round =
+            SecondaryIndex::new(header_id_by_round, |digest, header: &Header| {
+                (header.round(), *digest)
+            });
         Self {
-            store: header_store,
+            store: IndexedColumn::new(header_store).with_index(&header_id_by_round),
+            header_id_by_round,
+            cache: StoreCache::new(Self::STORE_NAME, cache_size),
         }
     }
 
     pub fn new_for_tests() -> Self {
-        let rocksdb = open_cf(
-            tempfile::tempdir().unwrap(),
-            None,
-            &[NodeStorage::HEADERS_CF],
+        let columns = Columns::for_tests(&[
+            NodeStorage::HEADERS_CF,
+            NodeStorage::HEADER_DIGEST_BY_ROUND_CF,
+        ]);
+        Self::new(
+            columns.open(NodeStorage::HEADERS_CF),
+            columns.open(NodeStorage::HEADER_DIGEST_BY_ROUND_CF),
+            0,
         )
-        .expect("Cannot open database");
-        let map = reopen!(&rocksdb, NodeStorage::HEADERS_CF;<HeaderDigest, Header>);
-        Self::new(map)
     }
 
     pub fn read(&self, id: &HeaderDigest) -> Result<Option<Header>, TypedStoreError> {
-        self.store.get(id)
+        if let Some(header) = self.cache.get(CacheOperation::Read, id) {
+            return Ok(Some(header));
+        }
+
+        let header = self.store.column().get(id)?;
+        if let Some(header) = &header {
+            self.cache.insert(CacheOperation::Read, *id, header.clone());
+        }
+        Ok(header)
     }
 
-    #[allow(clippy::let_and_return)]
     pub fn write(&self, header: &Header) -> Result<(), TypedStoreError> {
         fail_point!("narwhal-store-before-write");
 
-        let result = self.store.insert(&header.digest(), header);
+        let result = self.store.multi_insert([(header.digest(), header.clone())]);
+        if result.is_ok() {
+            self.cache
+                .insert(CacheOperation::Write, header.digest(), header.clone());
+        }
 
         fail_point!("narwhal-store-after-write");
         result
     }
 
+    /// Stages the insertion of a header in `unit`. The header is cached once the unit is
+    /// committed.
+    pub fn write_in(&self, unit: &mut UnitOfWork, header: &Header) -> Result<(), TypedStoreError> {
+        let (guard, batch) = unit.locked_batch(&self.store);
+        self.store
+            .insert_batch(guard, batch, &[(header.digest(), header.clone())])?;
+
+        let cache = self.cache.clone();
+        let header = header.clone();
+        unit.on_commit(move || cache.insert(CacheOperation::Write, header.digest(), header));
+        Ok(())
+    }
+
     pub fn remove_all(
         &self,
         keys: impl IntoIterator<Item = HeaderDigest>,
     ) -> Result<(), TypedStoreError> {
-        self.store.multi_remove(keys)
+        let keys: Vec<_> = keys.into_iter().collect();
+        self.store.multi_remove(&keys)?;
+        self.cache.remove_all(&keys);
+        Ok(())
+    }
+
+    /// Deletes up to `limit` of the headers with a round lower or equal to `round`, the oldest
+    /// first, and returns the number of headers deleted.
+    pub fn prune_until_round(&self, round: Round, limit: usize) -> Result<usize, TypedStoreError> {
+        fail_point!("narwhal-store-before-write");
+
+        // The round index is sorted by round, so the oldest headers come first.
+        let ids = self
+            .header_id_by_round
+            .column()
+            .iter()
+            .take_while(|((r, _), _)| *r <= round)
+            .take(limit)
+            .map(|(_, id)| id)
+            .collect::<Vec<_>>();
+        if !ids.is_empty() {
+            self.remove_all(ids.iter().copied())?;
+        }
+
+        fail_point!("narwhal-store-after-write");
+        Ok(ids.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_utils::CommitteeFixture;
+
+    #[test]
+    fn test_prune_until_round() {
+        let store = HeaderStore::new_for_tests();
+        let fixture = CommitteeFixture::builder().build();
+        let committee = fixture.committee();
+        let headers: Vec<Header> = (1..=3)
+            .flat_map(|round| {
+                fixture.authorities().map(move |authority| {
+                    Header::V2(authority.header_builder(&committee).round(round).build())
+                })
+            })
+            .collect();
+        for header in &headers {
+            store.write(header).unwrap();
+        }
+
+        // The headers are deleted from the oldest round, up to the given number.
+        assert_eq!(store.prune_until_round(2, 6).unwrap(), 6);
+        assert_eq!(store.prune_until_round(2, 6).unwrap(), 2);
+        assert_eq!(store.prune_until_round(2, 6).unwrap(), 0);
+        for header in &headers {
+            assert_eq!(
+                store.read(&header.digest()).unwrap().is_some(),
+                header.round() > 2
+            );
+        }
     }
 }
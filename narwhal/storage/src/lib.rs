@@ -1,21 +1,35 @@
 // Copyright (c) Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
+mod backup;
+mod batch_store;
 mod certificate_store;
 mod consensus_store;
+mod db_metrics;
+mod execution_store;
 mod header_store;
 mod node_store;
 mod payload_store;
 mod proposer_store;
+mod schema;
+mod store_cache;
+mod unit_of_work;
 mod vote_digest_store;
 
+pub use backup::*;
+pub use batch_store::*;
 pub use certificate_store::*;
 pub use consensus_store::*;
+pub use db_metrics::*;
+pub use execution_store::*;
 pub use header_store::*;
 pub use node_store::*;
 pub use payload_store::*;
 pub use proposer_store::*;
+pub use schema::*;
 use store::TypedStoreError;
+pub use store_cache::*;
+pub use unit_of_work::*;
 pub use vote_digest_store::*;
 
 /// Convenience type to propagate store errors.
@@ -1,23 +1,131 @@
 // Copyright (c) Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
+use crate::db_metrics::init_db_metrics;
 use crate::payload_store::PayloadStore;
 use crate::proposer_store::ProposerKey;
+use crate::schema::{migrate, read_schema_version, SCHEMA_VERSION};
 use crate::vote_digest_store::VoteDigestStore;
-use crate::{CertificateStore, CertificateStoreCache, ConsensusStore, HeaderStore, ProposerStore};
-use config::{AuthorityIdentifier, WorkerId};
+use crate::{
+    BatchStore, CertificateStore, CertificateStoreCache, ConsensusStore, ExecutionStore,
+    HeaderStore, ProposerStore, StoreResult,
+};
+use config::{
+    AuthorityIdentifier, ColumnFamilyParameters, Committee, Parameters, StorageCompression,
+    StorageParameters, WorkerId,
+};
+use std::collections::BTreeMap;
 use std::num::NonZeroUsize;
 use std::sync::Arc;
-use store::reopen;
-use store::rocks::{default_db_options, DBMap};
-use store::rocks::{open_cf, ReadWriteOptions};
+use store::rocks::{
+    default_db_options, DBCompressionType, DBMap, DBMapTableConfigMap, DBOptions, RocksDB,
+};
+use store::rocks::{
+    open_cf, open_cf_opts, open_cf_opts_read_only, open_cf_opts_secondary, report_cf_metrics,
+    ReadWriteOptions,
+};
+use store::sally::SallyColumn;
+use store::test_db::TestDB;
+use store::Map;
+use tempfile::TempDir;
 use types::{
     Batch, BatchDigest, Certificate, CertificateDigest, CommittedSubDagShell, ConsensusCommit,
-    Header, HeaderDigest, Round, SequenceNumber, VoteInfo,
+    ExecutionIndices, Header, HeaderDigest, Round, SequenceNumber, SystemTransaction, TimestampMs,
+    VoteInfo,
 };
 
 // A type alias marking the "payload" tokens sent by workers to their primary as batch acknowledgements
 pub type PayloadToken = u8;
 
+/// The backend holding the column families of the node storage.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StorageBackend {
+    /// A RocksDB instance in the storage directory.
+    RocksDB,
+    /// In-memory maps, lost once the node stops.
+    InMemory,
+}
+
+impl StorageBackend {
+    /// The environment variable selecting the backend of the storage opened by the tests.
+    pub const TEST_BACKEND_VAR: &'static str = "NARWHAL_TEST_STORAGE";
+
+    /// The backend of the storage opened by the tests: in memory when `NARWHAL_TEST_STORAGE` is
+    /// set to `memory`, RocksDB otherwise.
+    pub fn for_tests() -> Self {
+        match std::env::var(Self::TEST_BACKEND_VAR) {
+            Ok(backend) if backend.eq_ignore_ascii_case("memory") => StorageBackend::InMemory,
+            _ => StorageBackend::RocksDB,
+        }
+    }
+}
+
+/// Opens the columns of the node storage on their backend.
+pub(crate) enum Columns {
+    RocksDB(Arc<RocksDB>),
+    InMemory,
+}
+
+impl Columns {
+    /// Opens the column families `cfs` of a single store on the backend of the tests.
+    pub(crate) fn for_tests(cfs: &[&str]) -> Self {
+        init_db_metrics();
+        match StorageBackend::for_tests() {
+            StorageBackend::RocksDB => Columns::RocksDB(
+                open_cf(tempfile::tempdir().unwrap(), None, cfs).expect("Cannot open database"),
+            ),
+            StorageBackend::InMemory => Columns::InMemory,
+        }
+    }
+
+    pub(crate) fn open<K, V>(&self, cf: &str) -> SallyColumn<K, V> {
+        match self {
+            Columns::RocksDB(rocksdb) => {
+                DBMap::reopen(rocksdb, Some(cf), &ReadWriteOptions::default())
+                    .unwrap_or_else(|_| panic!("Cannot open {cf} CF."))
+                    .into()
+            }
+            Columns::InMemory => TestDB::open().into(),
+        }
+    }
+}
+
+/// The capacities of the caches in front of the stores of the node, in number of entries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CacheSizes {
+    /// The certificate cache always holds at least one certificate.
+    pub certificates: usize,
+    /// Zero disables the header cache.
+    pub headers: usize,
+    /// Zero disables the batch cache.
+    pub batches: usize,
+}
+
+impl CacheSizes {
+    /// Sizes the certificate cache to hold the certificates of the whole committee for the last
+    /// `gc_depth` rounds, unless `parameters` sets the size explicitly.
+    pub fn new(committee: &Committee, parameters: &Parameters) -> Self {
+        let certificates = parameters
+            .cache
+            .certificates
+            .unwrap_or(committee.size() * parameters.gc_depth as usize);
+        Self {
+            certificates,
+            headers: parameters.cache.headers,
+            batches: parameters.cache.batches,
+        }
+    }
+}
+
+impl Default for CacheSizes {
+    fn default() -> Self {
+        Self {
+            certificates: NodeStorage::CERTIFICATE_STORE_CACHE_SIZE,
+            headers: 0,
+            batches: 0,
+        }
+    }
+}
+
 /// All the data stores of the node.
 #[derive(Clone)]
 pub struct NodeStorage {
@@ -26,84 +134,288 @@ pub struct NodeStorage {
     pub header_store: HeaderStore,
     pub certificate_store: CertificateStore<CertificateStoreCache>,
     pub payload_store: PayloadStore,
-    pub batch_store: DBMap<BatchDigest, Batch>,
+    pub batch_store: BatchStore,
     pub consensus_store: Arc<ConsensusStore>,
+    pub execution_store: ExecutionStore,
+    /// The directory of a storage opened by `new_for_tests`, removed once the last clone of the
+    /// storage is dropped.
+    temp_dir: Option<Arc<TempDir>>,
 }
 
 impl NodeStorage {
     /// The datastore column family names.
     pub(crate) const LAST_PROPOSED_CF: &'static str = "last_proposed";
+    pub(crate) const PROPOSED_HEADERS_CF: &'static str = "proposed_headers";
+    pub(crate) const PENDING_DIGESTS_CF: &'static str = "pending_digests";
     pub(crate) const VOTES_CF: &'static str = "votes";
     pub(crate) const HEADERS_CF: &'static str = "headers";
+    pub(crate) const HEADER_DIGEST_BY_ROUND_CF: &'static str = "header_digest_by_round";
     pub(crate) const CERTIFICATES_CF: &'static str = "certificates";
     pub(crate) const CERTIFICATE_DIGEST_BY_ROUND_CF: &'static str = "certificate_digest_by_round";
     pub(crate) const CERTIFICATE_DIGEST_BY_ORIGIN_CF: &'static str = "certificate_digest_by_origin";
     pub(crate) const PAYLOAD_CF: &'static str = "payload";
     pub(crate) const BATCHES_CF: &'static str = "batches";
+    pub(crate) const BATCH_STORED_AT_CF: &'static str = "batch_stored_at";
+    pub(crate) const BATCH_DIGEST_BY_STORED_AT_CF: &'static str = "batch_digest_by_stored_at";
     pub(crate) const LAST_COMMITTED_CF: &'static str = "last_committed";
     pub(crate) const SUB_DAG_INDEX_CF: &'static str = "sub_dag";
     pub(crate) const COMMITTED_SUB_DAG_INDEX_CF: &'static str = "committed_sub_dag";
+    pub(crate) const SCHEMA_VERSION_CF: &'static str = "schema_version";
+    pub(crate) const LAST_EXECUTED_CF: &'static str = "last_executed";
+    pub(crate) const COMMITTEE_CHANGES_CF: &'static str = "committee_changes";
+
+    const COLUMN_FAMILIES: &'static [&'static str] = &[
+        Self::LAST_PROPOSED_CF,
+        Self::PROPOSED_HEADERS_CF,
+        Self::PENDING_DIGESTS_CF,
+        Self::VOTES_CF,
+        Self::HEADERS_CF,
+        Self::HEADER_DIGEST_BY_ROUND_CF,
+        Self::CERTIFICATES_CF,
+        Self::CERTIFICATE_DIGEST_BY_ROUND_CF,
+        Self::CERTIFICATE_DIGEST_BY_ORIGIN_CF,
+        Self::PAYLOAD_CF,
+        Self::BATCHES_CF,
+        Self::BATCH_STORED_AT_CF,
+        Self::BATCH_DIGEST_BY_STORED_AT_CF,
+        Self::LAST_COMMITTED_CF,
+        Self::SUB_DAG_INDEX_CF,
+        Self::COMMITTED_SUB_DAG_INDEX_CF,
+        Self::SCHEMA_VERSION_CF,
+        Self::LAST_EXECUTED_CF,
+        Self::COMMITTEE_CHANGES_CF,
+    ];
 
     // 100 nodes * 60 rounds (assuming 1 round/sec this will hold data for about the last 1 minute
-    // which should be more than enough for advancing the protocol and also help other nodes)
-    // TODO: take into account committee size instead of having fixed 100.
+    // which should be more than enough for advancing the protocol and also help other nodes).
+    // Used when the committee is not known, see `CacheSizes::new` otherwise.
     pub(crate) const CERTIFICATE_STORE_CACHE_SIZE: usize = 100 * 60;
 
-    /// Open or reopen all the storage of the node.
+    /// Open or reopen all the storage of the node. The database is first migrated to the current
+    /// schema version; opening a database written with a newer schema panics.
     pub fn reopen<Path: AsRef<std::path::Path> + Send>(store_path: Path) -> Self {
+        Self::reopen_with(
+            store_path,
+            &StorageParameters::default(),
+            CacheSizes::default(),
+        )
+    }
+
+    /// Same as `reopen`, with the column families tuned by `storage` and the caches of the stores
+    /// sized by `cache_sizes`. The storage is held in memory instead if `storage` says so, and
+    /// `store_path` is then unused.
+    pub fn reopen_with<Path: AsRef<std::path::Path> + Send>(
+        store_path: Path,
+        storage: &StorageParameters,
+        cache_sizes: CacheSizes,
+    ) -> Self {
+        if storage.in_memory {
+            return Self::open_columns(Columns::InMemory, cache_sizes);
+        }
+        init_db_metrics();
         let db_options = default_db_options().optimize_db_for_write_throughput(2);
-        let rocksdb = open_cf(
+        let table_options = Self::table_options(storage).to_map();
+        let cf_options: Vec<_> = table_options
+            .iter()
+            .map(|(cf, options)| (cf.as_str(), &options.options))
+            .collect();
+        let rocksdb = open_cf_opts(store_path, Some(db_options.options), &cf_options)
+            .expect("Cannot open database");
+
+        if let Err(e) = migrate(&rocksdb) {
+            panic!("Cannot migrate database: {e}");
+        }
+
+        Self::open_columns(Columns::RocksDB(rocksdb), cache_sizes)
+    }
+
+    /// The options of every column family of the node storage: the default options of the node,
+    /// overridden by the ones set in `storage`.
+    pub fn table_options(storage: &StorageParameters) -> DBMapTableConfigMap {
+        let tables = Self::COLUMN_FAMILIES
+            .iter()
+            .map(|cf| {
+                let options = default_db_options().optimize_db_for_write_throughput(2);
+                let options = match storage.column_families.get(*cf) {
+                    Some(parameters) => Self::override_options(options, parameters),
+                    None => options,
+                };
+                (cf.to_string(), options)
+            })
+            .collect::<BTreeMap<_, _>>();
+        DBMapTableConfigMap::new(tables)
+    }
+
+    fn override_options(mut options: DBOptions, parameters: &ColumnFamilyParameters) -> DBOptions {
+        // The block cache and the bloom filters are set together, the one left unset keeps the
+        // value of `default_db_options`.
+        if parameters.block_cache_size_mb.is_some()
+            || parameters.bloom_filter_bits_per_key.is_some()
+        {
+            options = options.set_block_options(
+                parameters.block_cache_size_mb.unwrap_or(64),
+                parameters.bloom_filter_bits_per_key.unwrap_or(10.0),
+            );
+        }
+        if let Some(compression) = parameters.compression {
+            options = options.set_compression(match compression {
+                StorageCompression::None => DBCompressionType::None,
+                StorageCompression::Lz4 => DBCompressionType::Lz4,
+                StorageCompression::Zstd => DBCompressionType::Zstd,
+            });
+        }
+        if let Some(write_buffer_size_mb) = parameters.write_buffer_size_mb {
+            options = options.set_write_buffer_size_mb(write_buffer_size_mb);
+        }
+        if !parameters.ttl.is_zero() {
+            options = options.set_ttl_compaction(parameters.ttl);
+        }
+        options
+    }
+
+    /// Opens the storage of a running node at `store_path` as a read-only RocksDB secondary
+    /// instance, which keeps its own state in `secondary_path` (by default a `SECONDARY`
+    /// directory next to `store_path`). The secondary sees the writes of the node up to the last
+    /// call to `try_catch_up_with_primary`. The database is not migrated, so its schema must
+    /// already be the current one.
+    pub fn reopen_secondary<Path: AsRef<std::path::Path> + Send>(
+        store_path: Path,
+        secondary_path: Option<Path>,
+    ) -> Self {
+        init_db_metrics();
+        let db_options = default_db_options();
+        let cf_options: Vec<_> = Self::COLUMN_FAMILIES
+            .iter()
+            .map(|cf| (*cf, &db_options.options))
+            .collect();
+        let rocksdb = open_cf_opts_secondary(
             store_path,
-            Some(db_options.options),
-            &[
-                Self::LAST_PROPOSED_CF,
-                Self::VOTES_CF,
-                Self::HEADERS_CF,
-                Self::CERTIFICATES_CF,
-                Self::CERTIFICATE_DIGEST_BY_ROUND_CF,
-                Self::CERTIFICATE_DIGEST_BY_ORIGIN_CF,
-                Self::PAYLOAD_CF,
-                Self::BATCHES_CF,
-                Self::LAST_COMMITTED_CF,
-                Self::SUB_DAG_INDEX_CF,
-                Self::COMMITTED_SUB_DAG_INDEX_CF,
-            ],
+            secondary_path,
+            Some(db_options.options.clone()),
+            &cf_options,
         )
         .expect("Cannot open database");
 
-        let (
-            last_proposed_map,
-            votes_map,
-            header_map,
-            certificate_map,
-            certificate_digest_by_round_map,
-            certificate_digest_by_origin_map,
-            payload_map,
-            batch_map,
-            last_committed_map,
-            sub_dag_index_map,
-            committed_sub_dag_map,
-        ) = reopen!(&rocksdb,
-            Self::LAST_PROPOSED_CF;<ProposerKey, Header>,
-            Self::VOTES_CF;<AuthorityIdentifier, VoteInfo>,
-            Self::HEADERS_CF;<HeaderDigest, Header>,
-            Self::CERTIFICATES_CF;<CertificateDigest, Certificate>,
-            Self::CERTIFICATE_DIGEST_BY_ROUND_CF;<(Round, AuthorityIdentifier), CertificateDigest>,
-            Self::CERTIFICATE_DIGEST_BY_ORIGIN_CF;<(AuthorityIdentifier, Round), CertificateDigest>,
-            Self::PAYLOAD_CF;<(BatchDigest, WorkerId), PayloadToken>,
-            Self::BATCHES_CF;<BatchDigest, Batch>,
-            Self::LAST_COMMITTED_CF;<AuthorityIdentifier, Round>,
-            Self::SUB_DAG_INDEX_CF;<SequenceNumber, CommittedSubDagShell>,
-            Self::COMMITTED_SUB_DAG_INDEX_CF;<SequenceNumber, ConsensusCommit>
-        );
+        Self::check_schema_version(&rocksdb);
+        Self::open_columns(Columns::RocksDB(rocksdb), CacheSizes::default())
+    }
+
+    /// Opens the storage at `store_path` in read only mode, for example a checkpoint of the
+    /// storage of a node. Nothing is written to the database, so it is not migrated and its schema
+    /// must already be the current one.
+    pub fn reopen_read_only<Path: AsRef<std::path::Path> + Send>(store_path: Path) -> Self {
+        init_db_metrics();
+        let db_options = default_db_options();
+        let cf_options: Vec<_> = Self::COLUMN_FAMILIES
+            .iter()
+            .map(|cf| (*cf, &db_options.options))
+            .collect();
+        let rocksdb =
+            open_cf_opts_read_only(store_path, Some(db_options.options.clone()), &cf_options)
+                .expect("Cannot open database");
+
+        Self::check_schema_version(&rocksdb);
+        Self::open_columns(Columns::RocksDB(rocksdb), CacheSizes::default())
+    }
+
+    fn check_schema_version(rocksdb: &Arc<RocksDB>) {
+        match read_schema_version(rocksdb) {
+            Ok(SCHEMA_VERSION) => (),
+            Ok(found) => {
+                panic!("Cannot open database: schema version {found}, expected {SCHEMA_VERSION}")
+            }
+            Err(e) => panic!("Cannot read the database schema version: {e}"),
+        }
+    }
+
+    /// Catches up with the writes of the node when the storage is a secondary instance opened
+    /// with `reopen_secondary`. It does nothing otherwise.
+    pub fn try_catch_up_with_primary(&self) -> StoreResult<()> {
+        // All the columns share the same database.
+        self.batch_store.column().try_catch_up_with_primary()
+    }
+
+    /// Reads the properties of the column families of the storage into the `DBMetrics` of
+    /// typed-store. It does nothing when the storage is held in memory.
+    pub fn report_metrics(&self) {
+        if let SallyColumn::RocksDB((db_map, _)) = self.batch_store.column() {
+            for cf in Self::COLUMN_FAMILIES {
+                report_cf_metrics(&db_map.rocksdb, cf);
+            }
+        }
+    }
+
+    /// Opens an empty storage held in memory, for the tests and the nodes which do not need their
+    /// data to survive a restart.
+    pub fn in_memory() -> Self {
+        Self::open_columns(Columns::InMemory, CacheSizes::default())
+    }
+
+    /// Opens an empty storage on the backend selected by `StorageBackend::for_tests`.
+    pub fn new_for_tests() -> Self {
+        match StorageBackend::for_tests() {
+            StorageBackend::RocksDB => {
+                let temp_dir = tempfile::tempdir().unwrap();
+                let storage = Self::reopen(temp_dir.path());
+                Self {
+                    temp_dir: Some(Arc::new(temp_dir)),
+                    ..storage
+                }
+            }
+            StorageBackend::InMemory => Self::in_memory(),
+        }
+    }
+
+    /// The backend of the storage.
+    pub fn backend(&self) -> StorageBackend {
+        match self.batch_store.column() {
+            SallyColumn::RocksDB(_) => StorageBackend::RocksDB,
+            SallyColumn::TestDB(_) => StorageBackend::InMemory,
+        }
+    }
+
+    fn open_columns(columns: Columns, cache_sizes: CacheSizes) -> Self {
+        let last_proposed_map = columns.open::<ProposerKey, Header>(Self::LAST_PROPOSED_CF);
+        let proposed_headers_map = columns.open::<Round, Header>(Self::PROPOSED_HEADERS_CF);
+        let pending_digests_map =
+            columns.open::<(TimestampMs, BatchDigest), WorkerId>(Self::PENDING_DIGESTS_CF);
+        let votes_map = columns.open::<AuthorityIdentifier, VoteInfo>(Self::VOTES_CF);
+        let header_map = columns.open::<HeaderDigest, Header>(Self::HEADERS_CF);
+        let header_digest_by_round_map =
+            columns.open::<(Round, HeaderDigest), HeaderDigest>(Self::HEADER_DIGEST_BY_ROUND_CF);
+        let certificate_map = columns.open::<CertificateDigest, Certificate>(Self::CERTIFICATES_CF);
+        let certificate_digest_by_round_map = columns
+            .open::<(Round, AuthorityIdentifier), CertificateDigest>(
+                Self::CERTIFICATE_DIGEST_BY_ROUND_CF,
+            );
+        let certificate_digest_by_origin_map = columns
+            .open::<(AuthorityIdentifier, Round), CertificateDigest>(
+                Self::CERTIFICATE_DIGEST_BY_ORIGIN_CF,
+            );
+        let payload_map = columns.open::<(BatchDigest, WorkerId), PayloadToken>(Self::PAYLOAD_CF);
+        let batch_map = columns.open::<BatchDigest, Batch>(Self::BATCHES_CF);
+        let batch_stored_at_map =
+            columns.open::<BatchDigest, TimestampMs>(Self::BATCH_STORED_AT_CF);
+        let batch_digest_by_stored_at_map = columns
+            .open::<(TimestampMs, BatchDigest), BatchDigest>(Self::BATCH_DIGEST_BY_STORED_AT_CF);
+        let last_committed_map =
+            columns.open::<AuthorityIdentifier, Round>(Self::LAST_COMMITTED_CF);
+        let sub_dag_index_map =
+            columns.open::<SequenceNumber, CommittedSubDagShell>(Self::SUB_DAG_INDEX_CF);
+        let committed_sub_dag_map =
+            columns.open::<SequenceNumber, ConsensusCommit>(Self::COMMITTED_SUB_DAG_INDEX_CF);
+        let last_executed_map = columns.open::<u8, ExecutionIndices>(Self::LAST_EXECUTED_CF);
+        let committee_changes_map =
+            columns.open::<SequenceNumber, SystemTransaction>(Self::COMMITTEE_CHANGES_CF);
 
-        let proposer_store = ProposerStore::new(last_proposed_map);
+        let proposer_store =
+            ProposerStore::new(last_proposed_map, proposed_headers_map, pending_digests_map);
         let vote_digest_store = VoteDigestStore::new(votes_map);
-        let header_store = HeaderStore::new(header_map);
+        let header_store =
+            HeaderStore::new(header_map, header_digest_by_round_map, cache_sizes.headers);
 
-        let certificate_store_cache = CertificateStoreCache::new(
-            NonZeroUsize::new(Self::CERTIFICATE_STORE_CACHE_SIZE).unwrap(),
-        );
+        let certificate_store_cache =
+            CertificateStoreCache::new(NonZeroUsize::new(cache_sizes.certificates.max(1)).unwrap());
         let certificate_store = CertificateStore::<CertificateStoreCache>::new(
             certificate_map,
             certificate_digest_by_round_map,
@@ -111,12 +423,18 @@ impl NodeStorage {
             certificate_store_cache,
         );
         let payload_store = PayloadStore::new(payload_map);
-        let batch_store = batch_map;
+        let batch_store = BatchStore::new(
+            batch_map,
+            batch_stored_at_map,
+            batch_digest_by_stored_at_map,
+            cache_sizes.batches,
+        );
         let consensus_store = Arc::new(ConsensusStore::new(
             last_committed_map,
             sub_dag_index_map,
             committed_sub_dag_map,
         ));
+        let execution_store = ExecutionStore::new(last_executed_map, committee_changes_map);
 
         Self {
             proposer_store,
@@ -126,6 +444,196 @@ impl NodeStorage {
             payload_store,
             batch_store,
             consensus_store,
+            execution_store,
+            temp_dir: None,
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crypto::Hash;
+    use test_utils::{temp_dir, CommitteeFixture};
+
+    #[test]
+    fn test_in_memory_storage() {
+        let fixture = CommitteeFixture::builder().build();
+        let certificate = fixture.certificate(&fixture.header());
+        let origin = certificate.origin();
+        let round = certificate.round();
+
+        let storage = NodeStorage::in_memory();
+        assert_eq!(storage.backend(), StorageBackend::InMemory);
+        storage
+            .certificate_store
+            .write(certificate.clone())
+            .unwrap();
+        let batch = test_utils::fixture_batch_with_transactions(1);
+        storage.batch_store.insert(&batch.digest(), &batch).unwrap();
+
+        // The secondary indexes are iterated like on RocksDB.
+        assert_eq!(
+            storage.certificate_store.last_round_number(origin).unwrap(),
+            Some(round)
+        );
+        assert_eq!(
+            storage.certificate_store.after_round(round).unwrap(),
+            vec![certificate.clone()]
+        );
+
+        // Every in-memory storage starts empty.
+        let other = NodeStorage::in_memory();
+        assert!(other
+            .certificate_store
+            .read(certificate.digest())
+            .unwrap()
+            .is_none());
+        assert!(other.batch_store.is_empty());
+
+        // A node opens its storage in memory when its parameters say so.
+        let path = std::env::temp_dir().join("narwhal-in-memory-storage");
+        let storage = StorageParameters {
+            in_memory: true,
+            ..StorageParameters::default()
+        };
+        let node_storage = NodeStorage::reopen_with(&path, &storage, CacheSizes::default());
+        assert_eq!(node_storage.backend(), StorageBackend::InMemory);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_new_for_tests_removes_its_directory() {
+        let storage = NodeStorage::new_for_tests();
+        let path = storage
+            .temp_dir
+            .as_ref()
+            .map(|dir| dir.path().to_path_buf());
+        let clone = storage.clone();
+        drop(storage);
+        assert!(path.as_ref().map_or(true, |path| path.exists()));
+        drop(clone);
+        assert!(path.map_or(true, |path| !path.exists()));
+    }
+
+    #[test]
+    fn test_cache_sizes() {
+        let fixture = CommitteeFixture::builder().build();
+        let committee = fixture.committee();
+        let mut parameters = Parameters {
+            gc_depth: 10,
+            ..Parameters::default()
+        };
+
+        // The certificate cache holds the last `gc_depth` rounds of the committee by default.
+        let sizes = CacheSizes::new(&committee, &parameters);
+        assert_eq!(sizes.certificates, committee.size() * 10);
+        assert_eq!((sizes.headers, sizes.batches), (0, 0));
+
+        parameters.cache.certificates = Some(7);
+        parameters.cache.headers = 3;
+        let sizes = CacheSizes::new(&committee, &parameters);
+        assert_eq!(sizes.certificates, 7);
+        assert_eq!(sizes.headers, 3);
+    }
+
+    #[test]
+    fn test_tuned_storage() {
+        // Every column family gets its options, tuned or not.
+        let storage = StorageParameters::default();
+        let tables = NodeStorage::table_options(&storage).to_map();
+        assert_eq!(tables.len(), NodeStorage::COLUMN_FAMILIES.len());
+        assert!(storage
+            .column_families
+            .keys()
+            .all(|cf| tables.contains_key(cf)));
+
+        let mut storage = StorageParameters::default();
+        storage.column_families.insert(
+            NodeStorage::HEADERS_CF.to_string(),
+            ColumnFamilyParameters {
+                bloom_filter_bits_per_key: Some(0.0),
+                compression: Some(StorageCompression::None),
+                ..Default::default()
+            },
+        );
+        let path = temp_dir();
+        let store = NodeStorage::reopen_with(&path, &storage, CacheSizes::default());
+        let batch = test_utils::fixture_batch_with_transactions(1);
+        store.batch_store.insert(&batch.digest(), &batch).unwrap();
+        drop(store);
+
+        // The data survives a reopen with other options.
+        let store = NodeStorage::reopen(&path);
+        assert_eq!(store.batch_store.get(&batch.digest()).unwrap(), Some(batch));
+    }
+
+    #[test]
+    fn test_secondary_storage() {
+        let fixture = CommitteeFixture::builder().build();
+        let mut certificates = fixture
+            .headers()
+            .into_iter()
+            .map(|header| fixture.certificate(&header));
+        let first = certificates.next().unwrap();
+        let second = certificates.next().unwrap();
+
+        let path = temp_dir();
+        let storage = NodeStorage::reopen(&path);
+        storage.certificate_store.write(first.clone()).unwrap();
+
+        let secondary = NodeStorage::reopen_secondary(path, Some(temp_dir()));
+        assert_eq!(
+            secondary.certificate_store.read(first.digest()).unwrap(),
+            Some(first)
+        );
+
+        // The writes of the node are only seen once the secondary catches up.
+        storage.certificate_store.write(second.clone()).unwrap();
+        assert!(secondary
+            .certificate_store
+            .read(second.digest())
+            .unwrap()
+            .is_none());
+        secondary.try_catch_up_with_primary().unwrap();
+        assert_eq!(
+            secondary.certificate_store.read(second.digest()).unwrap(),
+            Some(second)
+        );
+
+        // The secondary is read-only.
+        let batch = test_utils::fixture_batch_with_transactions(1);
+        assert!(secondary
+            .batch_store
+            .insert(&batch.digest(), &batch)
+            .is_err());
+    }
+
+    #[test]
+    fn test_read_only_storage() {
+        let fixture = CommitteeFixture::builder().build();
+        let certificate = fixture.certificate(&fixture.header());
+
+        let path = temp_dir();
+        let storage = NodeStorage::reopen(&path);
+        storage
+            .certificate_store
+            .write(certificate.clone())
+            .unwrap();
+        drop(storage);
+
+        let read_only = NodeStorage::reopen_read_only(&path);
+        assert_eq!(
+            read_only
+                .certificate_store
+                .read(certificate.digest())
+                .unwrap(),
+            Some(certificate)
+        );
+        let batch = test_utils::fixture_batch_with_transactions(1);
+        assert!(read_only
+            .batch_store
+            .insert(&batch.digest(), &batch)
+            .is_err());
+    }
+}
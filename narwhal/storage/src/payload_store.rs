@@ -1,27 +1,25 @@
 // Copyright (c) Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::{NodeStorage, PayloadToken};
+use crate::{Columns, NodeStorage, PayloadToken, UnitOfWork};
 use config::WorkerId;
 use mysten_common::sync::notify_read::NotifyRead;
 use std::sync::Arc;
-use store::reopen;
-use store::rocks::{open_cf, ReadWriteOptions};
-use store::{rocks::DBMap, Map, TypedStoreError};
+use store::{sally::SallyColumn, Map, TypedStoreError};
 use sui_macros::fail_point;
 use types::BatchDigest;
 
 /// Store of the batch digests for the primary node for the own created batches.
 #[derive(Clone)]
 pub struct PayloadStore {
-    store: DBMap<(BatchDigest, WorkerId), PayloadToken>,
+    store: SallyColumn<(BatchDigest, WorkerId), PayloadToken>,
 
     /// Senders to notify for a write that happened for the specified batch digest and worker id
     notify_subscribers: Arc<NotifyRead<(BatchDigest, WorkerId), ()>>,
 }
 
 impl PayloadStore {
-    pub fn new(payload_store: DBMap<(BatchDigest, WorkerId), PayloadToken>) -> Self {
+    pub fn new(payload_store: SallyColumn<(BatchDigest, WorkerId), PayloadToken>) -> Self {
         Self {
             store: payload_store,
             notify_subscribers: Arc::new(NotifyRead::new()),
@@ -29,15 +27,8 @@ impl PayloadStore {
     }
 
     pub fn new_for_tests() -> Self {
-        let rocksdb = open_cf(
-            tempfile::tempdir().unwrap(),
-            None,
-            &[NodeStorage::PAYLOAD_CF],
-        )
-        .expect("Cannot open database");
-        let map =
-            reopen!(&rocksdb, NodeStorage::PAYLOAD_CF;<(BatchDigest, WorkerId), PayloadToken>);
-        PayloadStore::new(map)
+        let columns = Columns::for_tests(&[NodeStorage::PAYLOAD_CF]);
+        PayloadStore::new(columns.open(NodeStorage::PAYLOAD_CF))
     }
 
     pub fn write(&self, digest: &BatchDigest, worker_id: &WorkerId) -> Result<(), TypedStoreError> {
@@ -69,6 +60,25 @@ impl PayloadStore {
         Ok(())
     }
 
+    /// Stages the insertion of the provided values in `unit`. The subscribers are notified once
+    /// the unit is committed.
+    pub fn write_all_in(
+        &self,
+        unit: &mut UnitOfWork,
+        keys: impl IntoIterator<Item = (BatchDigest, WorkerId)>,
+    ) -> Result<(), TypedStoreError> {
+        let keys: Vec<_> = keys.into_iter().collect();
+        unit.batch()
+            .insert_batch(&self.store, keys.iter().map(|key| (key, 0u8)))?;
+
+        let notify_subscribers = self.notify_subscribers.clone();
+        unit.on_commit(move || {
+            keys.iter()
+                .for_each(|key| notify_subscribers.notify(key, &()));
+        });
+        Ok(())
+    }
+
     /// Queries the store whether the batch with provided `digest` and `worker_id` exists. It returns
     /// `true` if exists, `false` otherwise.
     pub fn contains(
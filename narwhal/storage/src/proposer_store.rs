@@ -1,11 +1,14 @@
 // Copyright (c) Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::StoreResult;
-use store::rocks::open_cf;
-use store::{reopen, rocks::DBMap, rocks::ReadWriteOptions, Map};
+use crate::{CertificateStore, Columns, ConsensusStore, NodeStorage, StoreResult};
+use config::WorkerId;
+use crypto::Hash;
+use std::collections::HashSet;
+use std::iter;
+use store::{sally::SallyColumn, Map};
 use sui_macros::fail_point;
-use types::Header;
+use types::{BatchDigest, CertificateAPI, Header, HeaderAPI, Round, TimestampMs};
 
 pub type ProposerKey = u32;
 
@@ -15,48 +18,211 @@ pub const LAST_PROPOSAL_KEY: ProposerKey = 0;
 #[derive(Clone)]
 pub struct ProposerStore {
     /// Holds the Last Header that was proposed by the Proposer.
-    last_proposed: DBMap<ProposerKey, Header>,
+    last_proposed: SallyColumn<ProposerKey, Header>,
+    /// Holds the headers proposed by the Proposer within the GC depth of the last one that were
+    /// not committed yet, by round.
+    proposed_headers: SallyColumn<Round, Header>,
+    /// Holds the batch digests acknowledged to our workers and not included in a header yet, by
+    /// the creation time of the batch.
+    pending_digests: SallyColumn<(TimestampMs, BatchDigest), WorkerId>,
 }
 
 impl ProposerStore {
-    pub fn new(last_proposed: DBMap<ProposerKey, Header>) -> ProposerStore {
-        Self { last_proposed }
+    pub fn new(
+        last_proposed: SallyColumn<ProposerKey, Header>,
+        proposed_headers: SallyColumn<Round, Header>,
+        pending_digests: SallyColumn<(TimestampMs, BatchDigest), WorkerId>,
+    ) -> ProposerStore {
+        Self {
+            last_proposed,
+            proposed_headers,
+            pending_digests,
+        }
     }
 
     pub fn new_for_tests() -> ProposerStore {
-        const LAST_PROPOSED_CF: &str = "last_proposed";
-        let rocksdb = open_cf(tempfile::tempdir().unwrap(), None, &[LAST_PROPOSED_CF])
-            .expect("Cannot open database");
-        let last_proposed_map = reopen!(&rocksdb, LAST_PROPOSED_CF;<ProposerKey, Header>);
-        ProposerStore::new(last_proposed_map)
+        let columns = Columns::for_tests(&[
+            NodeStorage::LAST_PROPOSED_CF,
+            NodeStorage::PROPOSED_HEADERS_CF,
+            NodeStorage::PENDING_DIGESTS_CF,
+        ]);
+        ProposerStore::new(
+            columns.open(NodeStorage::LAST_PROPOSED_CF),
+            columns.open(NodeStorage::PROPOSED_HEADERS_CF),
+            columns.open(NodeStorage::PENDING_DIGESTS_CF),
+        )
     }
 
-    /// Inserts a proposed header into the store
+    /// Inserts a proposed header into the store, as the last one and in the history of proposed
+    /// headers, and drops the batch digests it includes from the pending ones. The headers
+    /// `gc_depth` rounds older or more are dropped from the history, since they cannot be
+    /// committed anymore.
     #[allow(clippy::let_and_return)]
-    pub fn write_last_proposed(&self, header: &Header) -> StoreResult<()> {
+    pub fn write_last_proposed(&self, header: &Header, gc_depth: Round) -> StoreResult<()> {
         fail_point!("narwhal-store-before-write");
 
-        let result = self.last_proposed.insert(&LAST_PROPOSAL_KEY, header);
+        let result = self.write_proposed(header, gc_depth);
 
         fail_point!("narwhal-store-after-write");
         result
     }
 
+    fn write_proposed(&self, header: &Header, gc_depth: Round) -> StoreResult<()> {
+        let expired: Vec<_> = self
+            .proposed_headers
+            .keys()
+            .take_while(|round| !matches!(round, Ok(round) if round + gc_depth > header.round()))
+            .collect::<StoreResult<_>>()?;
+
+        let mut batch = self.last_proposed.batch();
+        batch.insert_batch(
+            &self.last_proposed,
+            iter::once((LAST_PROPOSAL_KEY, header.clone())),
+        )?;
+        batch.insert_batch(
+            &self.proposed_headers,
+            iter::once((header.round(), header.clone())),
+        )?;
+        batch.delete_batch(&self.proposed_headers, expired)?;
+        batch.delete_batch(
+            &self.pending_digests,
+            header
+                .payload()
+                .iter()
+                .map(|(digest, (_, timestamp))| (*timestamp, *digest)),
+        )?;
+        batch.write_sync()
+    }
+
     /// Get the last header
     pub fn get_last_proposed(&self) -> StoreResult<Option<Header>> {
         self.last_proposed.get(&LAST_PROPOSAL_KEY)
     }
+
+    /// The proposed headers of the history, from the oldest round to the newest.
+    pub fn read_proposed_headers(&self) -> StoreResult<Vec<Header>> {
+        Ok(self
+            .proposed_headers
+            .iter()
+            .map(|(_, header)| header)
+            .collect())
+    }
+
+    /// Drops from the history the proposed headers of `rounds`, once committed or once their
+    /// batches were included in a later header.
+    pub fn remove_proposed_headers(
+        &self,
+        rounds: impl IntoIterator<Item = Round>,
+    ) -> StoreResult<()> {
+        let rounds: Vec<_> = rounds.into_iter().collect();
+        self.proposed_headers.multi_remove(&rounds)
+    }
+
+    /// Drops from the history the proposed headers whose certificates were committed, and returns
+    /// their rounds. The proposer drops the committed headers once consensus reports them, so this
+    /// catches up with the commits persisted by consensus before a crash of the primary.
+    pub fn remove_committed_headers(
+        &self,
+        certificate_store: &CertificateStore,
+        consensus_store: &ConsensusStore,
+    ) -> StoreResult<Vec<Round>> {
+        let headers = self.read_proposed_headers()?;
+        let Some(first_round) = headers.first().map(|header| header.round()) else {
+            return Ok(Vec::new());
+        };
+
+        // A header can only be committed by a leader of its round or later.
+        let committed: HashSet<_> = consensus_store
+            .read_committed_sub_dags_after_round(first_round.saturating_sub(1))?
+            .iter()
+            .flat_map(|sub_dag| sub_dag.certificates())
+            .collect();
+        let mut rounds = Vec::new();
+        for header in headers {
+            let Some(certificate) =
+                certificate_store.read_by_index(header.author(), header.round())?
+            else {
+                continue;
+            };
+            if certificate.header().digest() == header.digest()
+                && committed.contains(&certificate.digest())
+            {
+                rounds.push(header.round());
+            }
+        }
+
+        self.remove_proposed_headers(rounds.iter().copied())?;
+        Ok(rounds)
+    }
+
+    /// Drops from the history the proposed headers of `rounds` which will not be committed, and
+    /// makes the batch digests they include pending again, to be included in a later header.
+    pub fn requeue_proposed_headers(
+        &self,
+        rounds: impl IntoIterator<Item = Round>,
+    ) -> StoreResult<()> {
+        let rounds: Vec<_> = rounds.into_iter().collect();
+        let mut digests = Vec::new();
+        for header in self
+            .proposed_headers
+            .multi_get(&rounds)?
+            .into_iter()
+            .flatten()
+        {
+            digests.extend(
+                header
+                    .payload()
+                    .iter()
+                    .map(|(digest, (worker_id, timestamp))| ((*timestamp, *digest), *worker_id)),
+            );
+        }
+
+        let mut batch = self.proposed_headers.batch();
+        batch.insert_batch(&self.pending_digests, digests)?;
+        batch.delete_batch(&self.proposed_headers, rounds)?;
+        batch.write_sync()
+    }
+
+    /// Records the batch digests acknowledged to our workers, with the worker and the creation
+    /// time of their batch, until a header includes them. The write-ahead log is synced to disk
+    /// before returning, since the digests are acknowledged once it returns.
+    pub fn write_pending_digests(
+        &self,
+        digests: &[(BatchDigest, WorkerId, TimestampMs)],
+    ) -> StoreResult<()> {
+        let mut batch = self.pending_digests.batch();
+        batch.insert_batch(
+            &self.pending_digests,
+            digests
+                .iter()
+                .map(|(digest, worker_id, timestamp)| ((*timestamp, *digest), *worker_id)),
+        )?;
+        batch.write_with_fsync()
+    }
+
+    /// The batch digests not included in a header yet, with the worker and the creation time of
+    /// their batch, from the oldest batch to the newest.
+    pub fn read_pending_digests(&self) -> StoreResult<Vec<(BatchDigest, WorkerId, TimestampMs)>> {
+        Ok(self
+            .pending_digests
+            .iter()
+            .map(|((timestamp, digest), worker_id)| (digest, worker_id, timestamp))
+            .collect())
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::{ProposerStore, LAST_PROPOSAL_KEY};
+    use crate::{NodeStorage, ProposerStore, LAST_PROPOSAL_KEY};
+    use std::collections::HashMap;
     use store::Map;
-    use test_utils::{fixture_batch_with_transactions, CommitteeFixture};
-    use types::{CertificateDigest, Header, Round};
+    use test_utils::{fixture_batch_with_transactions, temp_dir, CommitteeFixture};
+    use types::{CertificateDigest, CommittedSubDag, Header, HeaderAPI, ReputationScores, Round};
+
+    const GC_DEPTH: Round = 50;
 
     pub fn create_header_for_round(round: Round) -> Header {
-        let builder = types::HeaderV1Builder::default();
+        let builder = types::HeaderV2Builder::default();
         let fixture = CommitteeFixture::builder().randomize_ports(true).build();
         let primary = fixture.authorities().next().unwrap();
         let id = primary.id();
@@ -67,7 +233,7 @@ mod test {
             .parents([CertificateDigest::default()].iter().cloned().collect())
             .with_payload_batch(fixture_batch_with_transactions(10), 0, 0)
             .build(); // primary.keypair().private()
-        Header::V1(header)
+        Header::V2(header)
     }
 
     #[tokio::test]
@@ -75,14 +241,14 @@ mod test {
         let store = ProposerStore::new_for_tests();
         let header_1 = create_header_for_round(1);
 
-        let out = store.write_last_proposed(&header_1);
+        let out = store.write_last_proposed(&header_1, GC_DEPTH);
         assert!(out.is_ok());
 
         let result = store.last_proposed.get(&LAST_PROPOSAL_KEY).unwrap();
         assert_eq!(result.unwrap(), header_1);
 
         let header_2 = create_header_for_round(2);
-        let out = store.write_last_proposed(&header_2);
+        let out = store.write_last_proposed(&header_2, GC_DEPTH);
         assert!(out.is_ok());
 
         let should_exist = store.last_proposed.get(&LAST_PROPOSAL_KEY).unwrap();
@@ -97,10 +263,114 @@ mod test {
         assert_eq!(should_not_exist, None);
 
         let header_1 = create_header_for_round(1);
-        let out = store.write_last_proposed(&header_1);
+        let out = store.write_last_proposed(&header_1, GC_DEPTH);
         assert!(out.is_ok());
 
         let should_exist = store.get_last_proposed().unwrap();
         assert_eq!(should_exist.unwrap(), header_1);
     }
+
+    #[tokio::test]
+    async fn test_proposed_headers_history() {
+        let store = ProposerStore::new_for_tests();
+        let headers: Vec<_> = (1..=3).map(create_header_for_round).collect();
+        for header in &headers {
+            store.write_last_proposed(header, GC_DEPTH).unwrap();
+        }
+        assert_eq!(store.read_proposed_headers().unwrap(), headers);
+
+        // The committed headers are dropped from the history.
+        store.remove_proposed_headers([2]).unwrap();
+        assert_eq!(
+            store.read_proposed_headers().unwrap(),
+            vec![headers[0].clone(), headers[2].clone()]
+        );
+
+        // The history is bounded.
+        let header = create_header_for_round(1 + GC_DEPTH);
+        store.write_last_proposed(&header, GC_DEPTH).unwrap();
+        assert_eq!(
+            store.read_proposed_headers().unwrap(),
+            vec![headers[2].clone(), header]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pending_digests() {
+        let store = ProposerStore::new_for_tests();
+        let header = create_header_for_round(1);
+        let pending: Vec<_> = header
+            .payload()
+            .iter()
+            .map(|(digest, (worker_id, timestamp))| (*digest, *worker_id, *timestamp))
+            .collect();
+        store.write_pending_digests(&pending).unwrap();
+        assert_eq!(store.read_pending_digests().unwrap(), pending);
+
+        // The digests included in a proposed header are not pending anymore.
+        store.write_last_proposed(&header, GC_DEPTH).unwrap();
+        assert!(store.read_pending_digests().unwrap().is_empty());
+
+        // The digests of a re-queued header are pending again.
+        store.requeue_proposed_headers([1]).unwrap();
+        assert_eq!(store.read_pending_digests().unwrap(), pending);
+        assert!(store.read_proposed_headers().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_remove_committed_headers() {
+        let storage = NodeStorage::reopen(temp_dir());
+        let store = &storage.proposer_store;
+        let fixture = CommitteeFixture::builder().build();
+        let committee = fixture.committee();
+        let primary = fixture.authorities().next().unwrap();
+        let headers: Vec<_> = (1..=3)
+            .map(|round| {
+                Header::V2(
+                    primary
+                        .header_builder(&committee)
+                        .round(round)
+                        .with_payload_batch(fixture_batch_with_transactions(10), 0, 0)
+                        .signed(primary.keypair().private())
+                        .build(),
+                )
+            })
+            .collect();
+        let certificates: Vec<_> = headers
+            .iter()
+            .map(|header| fixture.certificate(header))
+            .collect();
+        for header in &headers {
+            store.write_last_proposed(header, GC_DEPTH).unwrap();
+        }
+
+        // The headers were certified, and consensus committed the first two before a crash,
+        // without the proposer hearing of it.
+        storage
+            .certificate_store
+            .write_all(certificates.clone())
+            .unwrap();
+        let sub_dag = CommittedSubDag::new(
+            certificates[..2].to_vec(),
+            certificates[1].clone(),
+            1,
+            ReputationScores::default(),
+            None,
+        );
+        storage
+            .consensus_store
+            .write_consensus_state(&HashMap::new(), &sub_dag)
+            .unwrap();
+
+        assert_eq!(
+            store
+                .remove_committed_headers(&storage.certificate_store, &storage.consensus_store)
+                .unwrap(),
+            vec![1, 2]
+        );
+        assert_eq!(
+            store.read_proposed_headers().unwrap(),
+            vec![headers[2].clone()]
+        );
+    }
 }
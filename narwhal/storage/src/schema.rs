@@ -0,0 +1,512 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use crate::{NodeStorage, ProposerKey, StoreResult, LAST_PROPOSAL_KEY};
+use config::AuthorityIdentifier;
+use std::sync::Arc;
+use store::rocks::{DBMap, ReadWriteOptions, RocksDB};
+use store::{reopen, Map, TypedStoreError};
+use thiserror::Error;
+use tracing::info;
+use types::{
+    now, Batch, BatchDigest, CertificateDigest, CommittedSubDagShell, ConsensusCommit, Header,
+    HeaderAPI, HeaderDigest, Round, SequenceNumber, TimestampMs,
+};
+
+/// The version of the on-disk layout of the `NodeStorage`. Databases created before the version
+/// was recorded are at version 0.
+pub type SchemaVersion = u64;
+
+/// The schema version written by this code. Bump it along with a new entry in `MIGRATIONS`.
+pub const SCHEMA_VERSION: SchemaVersion = 4;
+
+/// The key of the schema version record in its column family.
+const SCHEMA_VERSION_KEY: u8 = 0;
+
+/// The number of entries written per batch when a migration rewrites a column family.
+const MIGRATION_BATCH_SIZE: usize = 1_000;
+
+#[derive(Debug, Error)]
+pub enum SchemaError {
+    #[error("The database schema version {found} is newer than the supported version {supported}")]
+    UnsupportedVersion {
+        found: SchemaVersion,
+        supported: SchemaVersion,
+    },
+    #[error(transparent)]
+    Store(#[from] TypedStoreError),
+}
+
+/// A step upgrading the database from `from` to `from + 1`.
+struct Migration {
+    from: SchemaVersion,
+    description: &'static str,
+    /// Migrations must be idempotent: the version is only bumped once they are done, so they run
+    /// again if the node crashes in the middle.
+    run: fn(&Arc<RocksDB>) -> StoreResult<()>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        from: 0,
+        description: "move the legacy committed sub dags to the committed_sub_dag column family",
+        run: move_legacy_sub_dags,
+    },
+    Migration {
+        from: 1,
+        description: "record and index the time at which the stored batches were stored",
+        run: record_batches_stored_at,
+    },
+    Migration {
+        from: 2,
+        description: "index the stored headers by round and start the history of proposed headers",
+        run: index_headers_by_round,
+    },
+    Migration {
+        from: 3,
+        description: "remove the deleted certificates from the certificate index by origin",
+        run: repair_certificate_digest_by_origin,
+    },
+];
+
+/// Reads the schema version recorded in the database.
+pub fn read_schema_version(rocksdb: &Arc<RocksDB>) -> StoreResult<SchemaVersion> {
+    let versions = schema_version_map(rocksdb);
+    Ok(versions.get(&SCHEMA_VERSION_KEY)?.unwrap_or_default())
+}
+
+/// Upgrades the database to `SCHEMA_VERSION` one migration at a time, and returns the version
+/// found when opening it. Databases with a newer schema are refused, since this code would
+/// misread them.
+pub fn migrate(rocksdb: &Arc<RocksDB>) -> Result<SchemaVersion, SchemaError> {
+    let versions = schema_version_map(rocksdb);
+    let found = versions.get(&SCHEMA_VERSION_KEY)?.unwrap_or_default();
+    if found > SCHEMA_VERSION {
+        return Err(SchemaError::UnsupportedVersion {
+            found,
+            supported: SCHEMA_VERSION,
+        });
+    }
+
+    for migration in MIGRATIONS.iter().filter(|m| m.from >= found) {
+        info!(
+            "Migrating the database schema from version {} to {}: {}",
+            migration.from,
+            migration.from + 1,
+            migration.description
+        );
+        (migration.run)(rocksdb)?;
+        versions.insert(&SCHEMA_VERSION_KEY, &(migration.from + 1))?;
+    }
+
+    Ok(found)
+}
+
+fn schema_version_map(rocksdb: &Arc<RocksDB>) -> DBMap<u8, SchemaVersion> {
+    reopen!(rocksdb, NodeStorage::SCHEMA_VERSION_CF;<u8, SchemaVersion>)
+}
+
+/// Version 0 to 1: the `ConsensusStore` wrote `CommittedSubDagShell` to the `sub_dag` column
+/// family before `ConsensusCommit` was introduced. Those are moved to `committed_sub_dag`.
+fn move_legacy_sub_dags(rocksdb: &Arc<RocksDB>) -> StoreResult<()> {
+    let (legacy_map, sub_dag_map) = reopen!(rocksdb,
+        NodeStorage::SUB_DAG_INDEX_CF;<SequenceNumber, CommittedSubDagShell>,
+        NodeStorage::COMMITTED_SUB_DAG_INDEX_CF;<SequenceNumber, ConsensusCommit>
+    );
+
+    let legacy = legacy_map.iter().collect::<Vec<_>>();
+    for chunk in legacy.chunks(MIGRATION_BATCH_SIZE) {
+        let mut batch = sub_dag_map.batch();
+        batch.insert_batch(
+            &sub_dag_map,
+            chunk
+                .iter()
+                .map(|(index, sub_dag)| (*index, ConsensusCommit::V1(sub_dag.clone()))),
+        )?;
+        batch.delete_batch(&legacy_map, chunk.iter().map(|(index, _)| index))?;
+        batch.write()?;
+    }
+    Ok(())
+}
+
+/// Version 1 to 2: the `BatchStore` records the local time at which each batch is stored, and
+/// indexes the batches by that time, so the workers can find the old batches without a scan. The
+/// batches stored before are recorded as stored at the time of the migration.
+fn record_batches_stored_at(rocksdb: &Arc<RocksDB>) -> StoreResult<()> {
+    let (batch_map, stored_at_map, by_stored_at_map) = reopen!(rocksdb,
+        NodeStorage::BATCHES_CF;<BatchDigest, Batch>,
+        NodeStorage::BATCH_STORED_AT_CF;<BatchDigest, TimestampMs>,
+        NodeStorage::BATCH_DIGEST_BY_STORED_AT_CF;<(TimestampMs, BatchDigest), BatchDigest>
+    );
+
+    // The batches recorded by an interrupted run of the migration keep their time.
+    let stored_at = now();
+    let digests = batch_map
+        .keys()
+        .filter(|digest| !matches!(stored_at_map.contains_key(digest), Ok(true)))
+        .collect::<Vec<_>>();
+    for chunk in digests.chunks(MIGRATION_BATCH_SIZE) {
+        let mut batch = stored_at_map.batch();
+        batch.insert_batch(
+            &stored_at_map,
+            chunk.iter().map(|digest| (*digest, stored_at)),
+        )?;
+        batch.insert_batch(
+            &by_stored_at_map,
+            chunk.iter().map(|digest| ((stored_at, *digest), *digest)),
+        )?;
+        batch.write()?;
+    }
+    Ok(())
+}
+
+/// Version 2 to 3: the `HeaderStore` indexes the headers by round, so the headers below the GC
+/// round can be pruned, and the `ProposerStore` keeps a history of the proposed headers. The
+/// headers stored before are indexed, and the history starts with the last proposed header.
+fn index_headers_by_round(rocksdb: &Arc<RocksDB>) -> StoreResult<()> {
+    let (header_map, by_round_map, last_proposed_map, proposed_headers_map) = reopen!(rocksdb,
+        NodeStorage::HEADERS_CF;<HeaderDigest, Header>,
+        NodeStorage::HEADER_DIGEST_BY_ROUND_CF;<(Round, HeaderDigest), HeaderDigest>,
+        NodeStorage::LAST_PROPOSED_CF;<ProposerKey, Header>,
+        NodeStorage::PROPOSED_HEADERS_CF;<Round, Header>
+    );
+
+    let keys = header_map
+        .iter()
+        .map(|(digest, header)| ((header.round(), digest), digest))
+        .collect::<Vec<_>>();
+    for chunk in keys.chunks(MIGRATION_BATCH_SIZE) {
+        by_round_map.multi_insert(chunk.iter().cloned())?;
+    }
+
+    if let Some(header) = last_proposed_map.get(&LAST_PROPOSAL_KEY)? {
+        proposed_headers_map.insert(&header.round(), &header)?;
+    }
+    Ok(())
+}
+
+/// Version 3 to 4: `CertificateStore::delete` and `delete_all` used to leave the deleted
+/// certificates in the index by origin, so `last_round_number` could return a round the store no
+/// longer has. The entries without a match in the index by round are removed.
+fn repair_certificate_digest_by_origin(rocksdb: &Arc<RocksDB>) -> StoreResult<()> {
+    let (by_round_map, by_origin_map) = reopen!(rocksdb,
+        NodeStorage::CERTIFICATE_DIGEST_BY_ROUND_CF;<(Round, AuthorityIdentifier), CertificateDigest>,
+        NodeStorage::CERTIFICATE_DIGEST_BY_ORIGIN_CF;<(AuthorityIdentifier, Round), CertificateDigest>
+    );
+
+    let mut stale = Vec::new();
+    for ((origin, round), digest) in by_origin_map.iter() {
+        if by_round_map.get(&(round, origin))? != Some(digest) {
+            stale.push((origin, round));
+        }
+    }
+    for chunk in stale.chunks(MIGRATION_BATCH_SIZE) {
+        by_origin_map.multi_remove(chunk)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::init_db_metrics;
+    use crate::NodeStorage;
+    use crypto::Digest;
+    use std::collections::HashSet;
+    use std::path::Path;
+    use test_utils::temp_dir;
+    use types::BatchAPI;
+
+    /// Opens the database at `path` with the column families `cfs`, recording its metrics like
+    /// the node storage does.
+    fn open_cf(path: impl AsRef<Path>, cfs: &[&str]) -> Arc<RocksDB> {
+        init_db_metrics();
+        store::rocks::open_cf(path, None, cfs).expect("Cannot open database")
+    }
+
+    /// The column families of a database created before the schema version was recorded.
+    const VERSION_0_CFS: &[&str] = &[
+        NodeStorage::LAST_PROPOSED_CF,
+        NodeStorage::VOTES_CF,
+        NodeStorage::HEADERS_CF,
+        NodeStorage::CERTIFICATES_CF,
+        NodeStorage::CERTIFICATE_DIGEST_BY_ROUND_CF,
+        NodeStorage::CERTIFICATE_DIGEST_BY_ORIGIN_CF,
+        NodeStorage::PAYLOAD_CF,
+        NodeStorage::BATCHES_CF,
+        NodeStorage::LAST_COMMITTED_CF,
+        NodeStorage::SUB_DAG_INDEX_CF,
+        NodeStorage::COMMITTED_SUB_DAG_INDEX_CF,
+    ];
+
+    fn current_cfs() -> Vec<&'static str> {
+        let mut cfs = VERSION_0_CFS.to_vec();
+        cfs.push(NodeStorage::SCHEMA_VERSION_CF);
+        cfs.push(NodeStorage::BATCH_STORED_AT_CF);
+        cfs.push(NodeStorage::BATCH_DIGEST_BY_STORED_AT_CF);
+        cfs.push(NodeStorage::PROPOSED_HEADERS_CF);
+        cfs.push(NodeStorage::HEADER_DIGEST_BY_ROUND_CF);
+        cfs.push(NodeStorage::PENDING_DIGESTS_CF);
+        cfs
+    }
+
+    fn put_raw(rocksdb: &Arc<RocksDB>, cf_name: &str, key: &[u8], value: &[u8]) {
+        let cf = rocksdb.cf_handle(cf_name).unwrap();
+        rocksdb
+            .put_cf(&cf, key, value, &ReadWriteOptions::default().writeopts())
+            .unwrap();
+    }
+
+    /// The bcs encoding of a `Header::V1` as written by version 0, with an empty payload and a
+    /// single parent.
+    fn version_0_header(author: u16, round: Round, parent: [u8; 32]) -> Vec<u8> {
+        let mut bytes = vec![0]; // Header::V1
+        bytes.extend(author.to_le_bytes());
+        bytes.extend(round.to_le_bytes());
+        bytes.extend(0u64.to_le_bytes()); // epoch
+        bytes.extend(1_000u64.to_le_bytes()); // created_at
+        bytes.push(0); // payload
+        bytes.push(1); // parents
+        bytes.extend(parent);
+        bytes
+    }
+
+    /// The bcs encoding of a `Batch::V1` as written by version 0.
+    fn version_0_batch(transaction: &[u8], created_at: TimestampMs) -> Vec<u8> {
+        let mut bytes = vec![0]; // Batch::V1
+        bytes.push(1); // transactions
+        bytes.push(transaction.len() as u8);
+        bytes.extend(transaction);
+        bytes.extend(created_at.to_le_bytes());
+        bytes
+    }
+
+    /// The bcs encoding of a `CommittedSubDagShell` as written by version 0.
+    fn version_0_sub_dag(leader: [u8; 32], leader_round: Round, index: SequenceNumber) -> Vec<u8> {
+        let mut bytes = vec![1]; // certificates
+        bytes.extend(leader);
+        bytes.extend(leader);
+        bytes.extend(leader_round.to_le_bytes());
+        bytes.extend(index.to_le_bytes());
+        bytes.push(1); // scores_per_authority
+        bytes.extend(0u16.to_le_bytes());
+        bytes.extend(index.to_le_bytes());
+        bytes.push(0); // final_of_schedule
+        bytes
+    }
+
+    fn header_digest(author: u16, round: Round) -> [u8; 32] {
+        [(round * 10) as u8 + author as u8; 32]
+    }
+
+    /// Writes the bytes of a database the way version 0 did: legacy sub dags, batches without the
+    /// time they were stored, and headers without an index by round nor a history of the
+    /// proposed ones. The bytes are spelled out so the fixture does not follow later changes of
+    /// the types.
+    fn write_version_0_fixture(path: &Path) {
+        let rocksdb = open_cf(path, VERSION_0_CFS);
+
+        put_raw(
+            &rocksdb,
+            NodeStorage::BATCHES_CF,
+            &[7; 32],
+            &version_0_batch(&[1, 2, 3], 42),
+        );
+
+        for round in 1..=3 {
+            for author in 0..3 {
+                put_raw(
+                    &rocksdb,
+                    NodeStorage::HEADERS_CF,
+                    &header_digest(author, round),
+                    &version_0_header(author, round, header_digest(author, round - 1)),
+                );
+            }
+        }
+        put_raw(
+            &rocksdb,
+            NodeStorage::LAST_PROPOSED_CF,
+            &LAST_PROPOSAL_KEY.to_be_bytes(),
+            &version_0_header(0, 3, header_digest(0, 2)),
+        );
+
+        for i in 1..=3 {
+            put_raw(
+                &rocksdb,
+                NodeStorage::SUB_DAG_INDEX_CF,
+                &i.to_be_bytes(),
+                &version_0_sub_dag([i as u8; 32], 2 * i, i),
+            );
+        }
+    }
+
+    #[test]
+    fn test_new_database_has_current_version() {
+        let path = temp_dir();
+        let rocksdb = open_cf(&path, &current_cfs());
+
+        assert_eq!(migrate(&rocksdb).unwrap(), 0);
+        assert_eq!(read_schema_version(&rocksdb).unwrap(), SCHEMA_VERSION);
+
+        // Migrating again is a no-op.
+        assert_eq!(migrate(&rocksdb).unwrap(), SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn test_migrate_version_0() {
+        let path = temp_dir();
+        write_version_0_fixture(&path);
+
+        let migrated_at = now();
+        let storage = NodeStorage::reopen(&path);
+
+        // The legacy sub dags are read from the current column family.
+        let sub_dags = storage
+            .consensus_store
+            .read_committed_sub_dags_from(&1)
+            .unwrap();
+        let indexes: Vec<_> = sub_dags.iter().map(|s| s.sub_dag_index()).collect();
+        assert_eq!(indexes, vec![1, 2, 3]);
+        let leader = CertificateDigest::new(Digest::new([2; 32]));
+        assert_eq!(sub_dags[1].leader(), leader);
+        assert_eq!(sub_dags[1].leader_round(), 4);
+        assert_eq!(sub_dags[1].certificates(), vec![leader]);
+        assert_eq!(
+            sub_dags[1].reputation_score().scores_per_authority,
+            [(AuthorityIdentifier(0), 2)].into_iter().collect()
+        );
+        assert_eq!(storage.consensus_store.get_latest_sub_dag_index(), 3);
+
+        // The batch is read back, and recorded as stored at the time of the migration rather than
+        // its creation time.
+        let digest = BatchDigest::new(Digest::new([7; 32]));
+        let batch = storage.batch_store.get(&digest).unwrap().unwrap();
+        assert_eq!(batch.transactions(), &vec![vec![1, 2, 3]]);
+        assert_eq!(batch.metadata().created_at, 42);
+        assert_eq!(
+            storage
+                .batch_store
+                .remove_stored_before(migrated_at, 10, &HashSet::new())
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            storage
+                .batch_store
+                .remove_stored_before(now() + 1, 10, &HashSet::new())
+                .unwrap(),
+            1
+        );
+
+        // The headers are read back as `Header::V1`, indexed by round, and the last proposed one
+        // starts the history.
+        let header = storage.proposer_store.get_last_proposed().unwrap().unwrap();
+        assert!(matches!(header, Header::V1(_)));
+        assert_eq!(header.author(), AuthorityIdentifier(0));
+        assert_eq!(header.round(), 3);
+        assert_eq!(*header.created_at(), 1_000);
+        assert!(header.payload().is_empty());
+        assert_eq!(
+            header.parents().iter().collect::<Vec<_>>(),
+            vec![&CertificateDigest::new(Digest::new(header_digest(0, 2)))]
+        );
+        assert!(header.weak_links().is_empty());
+
+        let proposed = storage.proposer_store.read_proposed_headers().unwrap();
+        assert_eq!(proposed, vec![header]);
+        assert_eq!(storage.header_store.prune_until_round(2, 100).unwrap(), 6);
+        assert_eq!(storage.header_store.prune_until_round(3, 100).unwrap(), 3);
+        drop(storage);
+
+        let rocksdb = open_cf(&path, &current_cfs());
+        assert_eq!(read_schema_version(&rocksdb).unwrap(), SCHEMA_VERSION);
+    }
+
+    /// The key of the certificate indexes, as encoded by version 3: the round and the origin in
+    /// big endian, in either order.
+    fn certificate_index_key(first: &[u8], second: &[u8]) -> Vec<u8> {
+        [first, second].concat()
+    }
+
+    #[test]
+    fn test_repair_certificate_index_by_origin() {
+        let path = temp_dir();
+        let rocksdb = open_cf(&path, &current_cfs());
+        schema_version_map(&rocksdb)
+            .insert(&SCHEMA_VERSION_KEY, &3)
+            .unwrap();
+
+        // Authority 1 has certificates at rounds 1 and 2, but the one at round 3 was deleted
+        // without its entry in the index by origin.
+        for round in 1..=3u64 {
+            let origin = 1u16.to_be_bytes();
+            let digest = [round as u8; 32];
+            if round < 3 {
+                put_raw(
+                    &rocksdb,
+                    NodeStorage::CERTIFICATE_DIGEST_BY_ROUND_CF,
+                    &certificate_index_key(&round.to_be_bytes(), &origin),
+                    &digest,
+                );
+            }
+            put_raw(
+                &rocksdb,
+                NodeStorage::CERTIFICATE_DIGEST_BY_ORIGIN_CF,
+                &certificate_index_key(&origin, &round.to_be_bytes()),
+                &digest,
+            );
+        }
+        // An entry of the index by origin pointing to another certificate of the round is stale
+        // too.
+        put_raw(
+            &rocksdb,
+            NodeStorage::CERTIFICATE_DIGEST_BY_ROUND_CF,
+            &certificate_index_key(&4u64.to_be_bytes(), &2u16.to_be_bytes()),
+            &[4; 32],
+        );
+        put_raw(
+            &rocksdb,
+            NodeStorage::CERTIFICATE_DIGEST_BY_ORIGIN_CF,
+            &certificate_index_key(&2u16.to_be_bytes(), &4u64.to_be_bytes()),
+            &[5; 32],
+        );
+
+        assert_eq!(migrate(&rocksdb).unwrap(), 3);
+        assert_eq!(read_schema_version(&rocksdb).unwrap(), SCHEMA_VERSION);
+
+        let by_origin_map = reopen!(&rocksdb,
+            NodeStorage::CERTIFICATE_DIGEST_BY_ORIGIN_CF;<(AuthorityIdentifier, Round), CertificateDigest>
+        );
+        let entries = by_origin_map.iter().collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            vec![
+                (
+                    (AuthorityIdentifier(1), 1),
+                    CertificateDigest::new(Digest::new([1; 32]))
+                ),
+                (
+                    (AuthorityIdentifier(1), 2),
+                    CertificateDigest::new(Digest::new([2; 32]))
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_refuse_newer_version() {
+        let path = temp_dir();
+        let rocksdb = open_cf(&path, &current_cfs());
+        schema_version_map(&rocksdb)
+            .insert(&SCHEMA_VERSION_KEY, &(SCHEMA_VERSION + 1))
+            .unwrap();
+
+        match migrate(&rocksdb) {
+            Err(SchemaError::UnsupportedVersion { found, supported }) => {
+                assert_eq!(found, SCHEMA_VERSION + 1);
+                assert_eq!(supported, SCHEMA_VERSION);
+            }
+            result => panic!("Unexpected migration result {result:?}"),
+        }
+        assert_eq!(read_schema_version(&rocksdb).unwrap(), SCHEMA_VERSION + 1);
+    }
+}
@@ -0,0 +1,192 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use lru::LruCache;
+use parking_lot::Mutex;
+use std::hash::Hash;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+
+#[cfg(feature = "metrics")]
+use snarkos_metrics::counter;
+
+const CACHE_HITS: &str = "narwhal_storage_cache_hits";
+const CACHE_MISSES: &str = "narwhal_storage_cache_misses";
+const CACHE_EVICTIONS: &str = "narwhal_storage_cache_evictions";
+
+/// The operation of a store going through its cache, labelling the metrics of the cache.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CacheOperation {
+    Read,
+    ReadAll,
+    Contains,
+    Write,
+}
+
+impl CacheOperation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CacheOperation::Read => "read",
+            CacheOperation::ReadAll => "read_all",
+            CacheOperation::Contains => "contains",
+            CacheOperation::Write => "write",
+        }
+    }
+}
+
+/// An LRU cache in front of a store, which the store fills with the values it writes and the
+/// values it reads on a miss. The hits, misses and evictions are counted per store and per
+/// operation. A cache with a zero capacity is disabled: it holds nothing and every lookup misses.
+pub struct StoreCache<K: Hash + Eq, V> {
+    /// The name of the store, labelling the metrics.
+    store: &'static str,
+    cache: Option<Arc<Mutex<LruCache<K, V>>>>,
+}
+
+impl<K: Hash + Eq, V> Clone for StoreCache<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store,
+            cache: self.cache.clone(),
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> StoreCache<K, V> {
+    pub fn new(store: &'static str, capacity: usize) -> Self {
+        Self {
+            store,
+            cache: NonZeroUsize::new(capacity)
+                .map(|capacity| Arc::new(Mutex::new(LruCache::new(capacity)))),
+        }
+    }
+
+    /// The maximum number of values held by the cache.
+    pub fn capacity(&self) -> usize {
+        self.cache
+            .as_ref()
+            .map_or(0, |cache| cache.lock().cap().get())
+    }
+
+    /// Fetches the value of `key`, marking it as "last accessed".
+    pub fn get(&self, operation: CacheOperation, key: &K) -> Option<V> {
+        let value = self
+            .cache
+            .as_ref()
+            .and_then(|cache| cache.lock().get(key).cloned());
+        self.record_lookups(operation, value.is_some() as usize, 1);
+        value
+    }
+
+    /// Fetches the values of `keys`, in the same order, marking them as "last accessed".
+    pub fn get_all(&self, operation: CacheOperation, keys: &[K]) -> Vec<Option<V>> {
+        let values: Vec<_> = match &self.cache {
+            Some(cache) => {
+                let mut guard = cache.lock();
+                keys.iter().map(|key| guard.get(key).cloned()).collect()
+            }
+            None => keys.iter().map(|_| None).collect(),
+        };
+        let hits = values.iter().filter(|value| value.is_some()).count();
+        self.record_lookups(operation, hits, keys.len());
+        values
+    }
+
+    /// Checks whether the cache holds `key`. It does not count as a "last access" of the value.
+    pub fn contains(&self, operation: CacheOperation, key: &K) -> bool {
+        let found = self
+            .cache
+            .as_ref()
+            .map_or(false, |cache| cache.lock().contains(key));
+        self.record_lookups(operation, found as usize, 1);
+        found
+    }
+
+    /// Caches the value of `key`, written or read by `operation`.
+    pub fn insert(&self, operation: CacheOperation, key: K, value: V) {
+        self.insert_all(operation, std::iter::once((key, value)));
+    }
+
+    /// Caches the values of `entries`, written or read by `operation`.
+    pub fn insert_all(&self, operation: CacheOperation, entries: impl IntoIterator<Item = (K, V)>) {
+        let Some(cache) = &self.cache else {
+            return;
+        };
+        let mut evictions = 0;
+        let mut guard = cache.lock();
+        for (key, value) in entries {
+            // The replaced value is returned when the key is already cached.
+            if let Some((evicted, _)) = guard.push(key.clone(), value) {
+                if evicted != key {
+                    evictions += 1;
+                }
+            }
+        }
+        drop(guard);
+        self.record(CACHE_EVICTIONS, operation, evictions);
+    }
+
+    pub fn remove(&self, key: &K) {
+        if let Some(cache) = &self.cache {
+            cache.lock().pop(key);
+        }
+    }
+
+    pub fn remove_all<'a>(&self, keys: impl IntoIterator<Item = &'a K>)
+    where
+        K: 'a,
+    {
+        if let Some(cache) = &self.cache {
+            let mut guard = cache.lock();
+            for key in keys {
+                guard.pop(key);
+            }
+        }
+    }
+
+    fn record_lookups(&self, operation: CacheOperation, hits: usize, lookups: usize) {
+        self.record(CACHE_HITS, operation, hits);
+        self.record(CACHE_MISSES, operation, lookups - hits);
+    }
+
+    fn record(&self, metric: &'static str, operation: CacheOperation, count: usize) {
+        if count == 0 {
+            return;
+        }
+        #[cfg(feature = "metrics")]
+        counter!(metric, count as u64, "store" => self.store, "operation" => operation.as_str());
+        #[cfg(not(feature = "metrics"))]
+        let _ = (metric, self.store, operation);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_store_cache() {
+        let cache = StoreCache::new("test", 2);
+        cache.insert_all(CacheOperation::Write, [(1, "a"), (2, "b")]);
+        assert_eq!(cache.get(CacheOperation::Read, &1), Some("a"));
+
+        // The least recently used value is evicted.
+        cache.insert(CacheOperation::Read, 3, "c");
+        assert_eq!(
+            cache.get_all(CacheOperation::ReadAll, &[1, 2, 3]),
+            vec![Some("a"), None, Some("c")]
+        );
+
+        cache.remove(&1);
+        assert!(!cache.contains(CacheOperation::Contains, &1));
+        assert!(cache.contains(CacheOperation::Contains, &3));
+    }
+
+    #[test]
+    fn test_disabled_store_cache() {
+        let cache = StoreCache::new("test", 0);
+        assert_eq!(cache.capacity(), 0);
+        cache.insert(CacheOperation::Write, 1, "a");
+        assert_eq!(cache.get(CacheOperation::Read, &1), None);
+        assert!(!cache.contains(CacheOperation::Contains, &1));
+    }
+}
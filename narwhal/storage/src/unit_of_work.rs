@@ -0,0 +1,254 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{NodeStorage, StoreResult};
+use store::sally::index::{IndexWriteGuard, IndexedColumn};
+use store::sally::SallyWriteBatch;
+use sui_macros::fail_point;
+
+/// A set of related writes across the stores of a `NodeStorage`, committed atomically.
+///
+/// The stores stage their writes in the unit through their `*_in` methods (e.g.
+/// `CertificateStore::write_in`), and apply the side effects of those writes, like notifying the
+/// readers waiting on them, only once the unit is committed. All the stores of a `NodeStorage`
+/// share a single database, so after a crash either every write of a unit is found or none is.
+/// Staging writes for the stores of two different `NodeStorage` fails.
+///
+/// The write locks of the indexed columns written in the unit are held until it is committed or
+/// dropped, so the unit should be committed right after its writes are staged.
+pub struct UnitOfWork {
+    batch: SallyWriteBatch,
+    locks: Vec<IndexWriteGuard>,
+    on_commit: Vec<Box<dyn FnOnce() + Send>>,
+}
+
+impl UnitOfWork {
+    pub(crate) fn new(batch: SallyWriteBatch) -> Self {
+        Self {
+            batch,
+            locks: Vec::new(),
+            on_commit: Vec::new(),
+        }
+    }
+
+    /// The write batch the stores stage their writes in.
+    pub(crate) fn batch(&mut self) -> &mut SallyWriteBatch {
+        &mut self.batch
+    }
+
+    /// The write batch to stage writes of the indexed `column` in, with the write lock of the
+    /// column, taken on its first write in the unit.
+    pub(crate) fn locked_batch<K, V>(
+        &mut self,
+        column: &IndexedColumn<K, V>,
+    ) -> (&IndexWriteGuard, &mut SallyWriteBatch) {
+        let position = match self
+            .locks
+            .iter()
+            .position(|guard| column.is_locked_by(guard))
+        {
+            Some(position) => position,
+            None => {
+                self.locks.push(column.lock_writes());
+                self.locks.len() - 1
+            }
+        };
+        (&self.locks[position], &mut self.batch)
+    }
+
+    /// Registers a side effect of a staged write, applied once the unit is committed.
+    pub(crate) fn on_commit(&mut self, callback: impl FnOnce() + Send + 'static) {
+        self.on_commit.push(Box::new(callback));
+    }
+
+    /// Writes all the staged writes atomically, then applies their side effects. Nothing is
+    /// written if the unit is dropped without being committed.
+    pub fn commit(self) -> StoreResult<()> {
+        fail_point!("narwhal-store-before-commit");
+
+        self.batch.write_sync()?;
+
+        fail_point!("narwhal-store-after-commit");
+
+        // The write locks are released after the side effects, so that the caches are updated
+        // in the order of the writes.
+        for callback in self.on_commit {
+            callback();
+        }
+        drop(self.locks);
+        Ok(())
+    }
+}
+
+impl NodeStorage {
+    /// Starts a unit of work over the stores of the node.
+    pub fn unit_of_work(&self) -> UnitOfWork {
+        UnitOfWork::new(self.batch_store.column().batch())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crypto::Hash;
+    use std::path::Path;
+    use test_utils::{fixture_batch_with_transactions, temp_dir, CommitteeFixture};
+    use types::{Certificate, CertificateAPI, Header, HeaderAPI};
+
+    fn certificate_with_payload() -> Certificate {
+        let fixture = CommitteeFixture::builder().build();
+        let committee = fixture.committee();
+        let header = Header::V2(
+            fixture
+                .authorities()
+                .next()
+                .unwrap()
+                .header_builder(&committee)
+                .with_payload_batch(fixture_batch_with_transactions(10), 0, 0)
+                .build(),
+        );
+        fixture.certificate(&header)
+    }
+
+    /// Stages the certificate, its header and its payload markers in a unit of work.
+    fn stage(storage: &NodeStorage, certificate: &Certificate) -> UnitOfWork {
+        let mut unit = storage.certificate_store.unit_of_work();
+        storage
+            .header_store
+            .write_in(&mut unit, certificate.header())
+            .unwrap();
+        storage
+            .certificate_store
+            .write_in(&mut unit, certificate.clone())
+            .unwrap();
+        storage
+            .payload_store
+            .write_all_in(
+                &mut unit,
+                certificate
+                    .header()
+                    .payload()
+                    .iter()
+                    .map(|(digest, (worker_id, _))| (*digest, *worker_id)),
+            )
+            .unwrap();
+        unit
+    }
+
+    /// Checks that the certificate, its header and its payload markers are either all found in
+    /// the storage at `path` or none is, and returns whether they are found.
+    fn is_stored(path: &Path, certificate: &Certificate) -> bool {
+        let storage = NodeStorage::reopen(path);
+        let header = certificate.header();
+        let mut found = vec![
+            storage
+                .certificate_store
+                .contains(&certificate.digest())
+                .unwrap(),
+            storage
+                .header_store
+                .read(&header.digest())
+                .unwrap()
+                .is_some(),
+        ];
+        for (digest, (worker_id, _)) in header.payload() {
+            found.push(storage.payload_store.contains(*digest, *worker_id).unwrap());
+        }
+        assert!(
+            found.iter().all(|stored| *stored == found[0]),
+            "Partially stored unit of work: {found:?}"
+        );
+        found[0]
+    }
+
+    #[test]
+    fn test_commit() {
+        let certificate = certificate_with_payload();
+        let path = temp_dir();
+        let storage = NodeStorage::reopen(&path);
+
+        // Nothing is written until the unit is committed.
+        let unit = stage(&storage, &certificate);
+        assert!(storage
+            .certificate_store
+            .read(certificate.digest())
+            .unwrap()
+            .is_none());
+        drop(unit);
+        drop(storage);
+        assert!(!is_stored(&path, &certificate));
+
+        let storage = NodeStorage::reopen(&path);
+        stage(&storage, &certificate).commit().unwrap();
+        drop(storage);
+        assert!(is_stored(&path, &certificate));
+    }
+
+    #[test]
+    fn test_commit_across_storages() {
+        let certificate = certificate_with_payload();
+        let storage = NodeStorage::reopen(temp_dir());
+        let other = NodeStorage::reopen(temp_dir());
+
+        let mut unit = storage.unit_of_work();
+        assert!(other
+            .header_store
+            .write_in(&mut unit, certificate.header())
+            .is_err());
+    }
+
+    #[cfg(any(msim, fail_points))]
+    mod crash {
+        use super::*;
+        use std::cell::Cell;
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+        use std::sync::Once;
+
+        const FAIL_POINTS: [&str; 2] =
+            ["narwhal-store-before-commit", "narwhal-store-after-commit"];
+
+        thread_local! {
+            /// The fail point of the unit of work crashing on this thread.
+            static CRASH_AT: Cell<Option<&'static str>> = Cell::new(None);
+        }
+
+        /// Commits the unit of work of the certificate, simulating a crash of the node at
+        /// `fail_point`. The fail points only crash the test thread, so the other tests going
+        /// through them are not affected.
+        fn commit_and_crash_at(fail_point: &'static str, path: &Path, certificate: &Certificate) {
+            static REGISTER: Once = Once::new();
+            REGISTER.call_once(|| {
+                for fail_point in FAIL_POINTS {
+                    sui_macros::register_fail_point(fail_point, move || {
+                        if CRASH_AT.with(|crash_at| crash_at.get()) == Some(fail_point) {
+                            panic!("crash at {fail_point}");
+                        }
+                    });
+                }
+            });
+
+            let storage = NodeStorage::reopen(path);
+            let unit = stage(&storage, certificate);
+            CRASH_AT.with(|crash_at| crash_at.set(Some(fail_point)));
+            let result = catch_unwind(AssertUnwindSafe(|| unit.commit()));
+            CRASH_AT.with(|crash_at| crash_at.set(None));
+            assert!(result.is_err(), "The node did not crash at {fail_point}");
+        }
+
+        #[test]
+        fn test_crash_before_commit() {
+            let certificate = certificate_with_payload();
+            let path = temp_dir();
+            commit_and_crash_at("narwhal-store-before-commit", &path, &certificate);
+            assert!(!is_stored(&path, &certificate));
+        }
+
+        #[test]
+        fn test_crash_after_commit() {
+            let certificate = certificate_with_payload();
+            let path = temp_dir();
+            commit_and_crash_at("narwhal-store-after-commit", &path, &certificate);
+            assert!(is_stored(&path, &certificate));
+        }
+    }
+}
@@ -1,32 +1,29 @@
 // Copyright (c) Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::NodeStorage;
-use config::AuthorityIdentifier;
-use store::reopen;
-use store::rocks::{open_cf, ReadWriteOptions};
-use store::{rocks::DBMap, Map, TypedStoreError};
+use crate::{Columns, NodeStorage, UnitOfWork};
+use config::{AuthorityIdentifier, Committee};
+use std::iter;
+use store::{sally::SallyColumn, Map, TypedStoreError};
 use sui_macros::fail_point;
-use types::{Vote, VoteAPI, VoteInfo};
+use types::{Round, Vote, VoteAPI, VoteDigest, VoteInfo, VoteInfoAPI, VoteInfoV1};
 
 /// The storage for the last votes digests per authority
 #[derive(Clone)]
 pub struct VoteDigestStore {
-    store: DBMap<AuthorityIdentifier, VoteInfo>,
+    store: SallyColumn<AuthorityIdentifier, VoteInfo>,
 }
 
 impl VoteDigestStore {
-    pub fn new(vote_digest_store: DBMap<AuthorityIdentifier, VoteInfo>) -> VoteDigestStore {
+    pub fn new(vote_digest_store: SallyColumn<AuthorityIdentifier, VoteInfo>) -> VoteDigestStore {
         Self {
             store: vote_digest_store,
         }
     }
 
     pub fn new_for_tests() -> VoteDigestStore {
-        let rocksdb = open_cf(tempfile::tempdir().unwrap(), None, &[NodeStorage::VOTES_CF])
-            .expect("Cannot open database");
-        let map = reopen!(&rocksdb, NodeStorage::VOTES_CF;<AuthorityIdentifier, VoteInfo>);
-        VoteDigestStore::new(map)
+        let columns = Columns::for_tests(&[NodeStorage::VOTES_CF]);
+        VoteDigestStore::new(columns.open(NodeStorage::VOTES_CF))
     }
 
     /// Insert the vote's basic details into the database for the corresponding
@@ -41,6 +38,40 @@ impl VoteDigestStore {
         result
     }
 
+    /// Stages the insertion of the vote's basic details in `unit`.
+    pub fn write_in(&self, unit: &mut UnitOfWork, vote: &Vote) -> Result<(), TypedStoreError> {
+        let vote_info: VoteInfo = vote.into();
+        unit.batch()
+            .insert_batch(&self.store, iter::once((vote.origin(), vote_info)))
+    }
+
+    /// Records a vote at `round` of the epoch of `committee` for every authority last voted for
+    /// at an older round, so the node does not vote for any header up to `round` anymore.
+    pub fn raise_vote_floor(
+        &self,
+        committee: &Committee,
+        round: Round,
+    ) -> Result<(), TypedStoreError> {
+        let mut floors = Vec::new();
+        for authority in committee.authorities() {
+            let newer = match self.store.get(&authority.id())? {
+                Some(vote_info) => {
+                    (vote_info.epoch(), vote_info.round()) >= (committee.epoch(), round)
+                }
+                None => false,
+            };
+            if !newer {
+                let floor = VoteInfo::V1(VoteInfoV1 {
+                    epoch: committee.epoch(),
+                    round,
+                    vote_digest: VoteDigest::default(),
+                });
+                floors.push((authority.id(), floor));
+            }
+        }
+        self.store.multi_insert(floors)
+    }
+
     /// Read the vote info based on the provided corresponding header author key
     pub fn read(
         &self,
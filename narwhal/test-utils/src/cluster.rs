@@ -1,17 +1,28 @@
 // Copyright (c) Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
-use crate::{temp_dir, CommitteeFixture};
-use config::{AuthorityIdentifier, Committee, Parameters, WorkerCache, WorkerId};
+use crate::{safety::ConsensusOutputChecker, temp_dir, CommitteeFixture};
+use anemo::{async_trait, PeerId};
+use config::{
+    AuthorityIdentifier, Committee, Parameters, StorageParameters, WorkerCache, WorkerId,
+};
 use crypto::{KeyPair, NetworkKeyPair, PublicKey};
-use executor::SerializedTransaction;
+use executor::{ExecutionAck, ExecutionState, SerializedTransaction};
 use itertools::Itertools;
 use mysten_network::multiaddr::Multiaddr;
-use network::client::NetworkClient;
+use network::{client::NetworkClient, failpoints::FaultInjector};
 use node::execution_state::SimpleExecutionState;
 use node::primary_node::PrimaryNode;
 use node::worker_node::WorkerNode;
-use std::{cell::RefCell, collections::HashMap, path::PathBuf, rc::Rc, sync::Arc, time::Duration};
-use storage::NodeStorage;
+use node::NodeError;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    path::PathBuf,
+    rc::Rc,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use storage::{Backup, CacheSizes, NodeStorage};
 use telemetry_subscribers::TelemetryGuards;
 use tokio::{
     sync::{broadcast::Sender, mpsc::channel, RwLock},
@@ -19,7 +30,10 @@ use tokio::{
 };
 use tonic::transport::Channel;
 use tracing::info;
-use types::{ConfigurationClient, ProposerClient, TransactionsClient};
+use types::{
+    CommittedSubDag, ConfigurationClient, ConsensusOutput, ProposerClient, Round,
+    TransactionsClient,
+};
 use worker::TrivialTransactionValidator;
 
 #[cfg(test)]
@@ -35,7 +49,6 @@ pub struct Cluster {
     authorities: HashMap<usize, AuthorityDetails>,
     pub committee: Committee,
     pub worker_cache: WorkerCache,
-    #[allow(dead_code)]
     parameters: Parameters,
 }
 
@@ -242,6 +255,48 @@ impl Cluster {
         rounds
     }
 
+    /// This method asserts the safety of the cluster: all the sub-dags that have been delivered
+    /// to the execution state of every authority (running or not) are checked for total order
+    /// agreement, duplicate commits, causal completeness and monotonic commit timestamps. On
+    /// failure it panics with a report describing where the nodes diverged.
+    /// Authorities that have been started with a fresh storage only report the sub-dags they
+    /// have executed since then.
+    pub async fn assert_safety(&self) {
+        let mut checker = ConsensusOutputChecker::new(self.parameters.gc_depth);
+
+        for (id, authority) in self.authorities.iter() {
+            checker.record_all(*id, authority.primary().await.committed_sub_dags());
+        }
+
+        checker.assert_safe();
+    }
+
+    /// Partitions the network: all the messages between the authorities (primaries & workers)
+    /// of `a` and those of `b` are dropped, in both directions, until `heal` is called.
+    pub async fn partition(&self, a: &[usize], b: &[usize]) {
+        let mut peers_a = Vec::new();
+        for id in a {
+            peers_a.extend(self.authority(*id).peer_ids().await);
+        }
+        let mut peers_b = Vec::new();
+        for id in b {
+            peers_b.extend(self.authority(*id).peer_ids().await);
+        }
+
+        for id in a.iter().chain(b.iter()) {
+            self.authority(*id)
+                .fault_injector()
+                .partition(&peers_a, &peers_b);
+        }
+    }
+
+    /// Removes all the faults injected on the network of every authority.
+    pub fn heal(&self) {
+        for authority in self.authorities.values() {
+            authority.fault_injector().heal();
+        }
+    }
+
     async fn authorities_latest_commit_round(&self) -> HashMap<usize, f64> {
         let authorities_latest_commit = HashMap::new();
 
@@ -263,7 +318,7 @@ impl Cluster {
         authorities_latest_commit
     }
 
-    fn parameters() -> Parameters {
+    pub(crate) fn parameters() -> Parameters {
         Parameters {
             batch_size: 200,
             max_header_delay: Duration::from_secs(2),
@@ -280,6 +335,7 @@ pub struct PrimaryNodeDetails {
     pub network_key_pair: Arc<NetworkKeyPair>,
     pub tx_transaction_confirmation: Sender<SerializedTransaction>,
     node: PrimaryNode,
+    committed_sub_dags: Arc<Mutex<Vec<Arc<CommittedSubDag>>>>,
     store_path: PathBuf,
     parameters: Parameters,
     committee: Committee,
@@ -316,10 +372,17 @@ impl PrimaryNodeDetails {
             handlers: Rc::new(RefCell::new(Vec::new())),
             internal_consensus_enabled,
             node,
+            committed_sub_dags: Arc::new(Mutex::new(Vec::new())),
             parameters,
         }
     }
 
+    /// Returns all the sub-dags delivered to the execution state of the node so far, in the
+    /// order they have been delivered (including the ones re-delivered after a restart).
+    pub fn committed_sub_dags(&self) -> Vec<Arc<CommittedSubDag>> {
+        self.committed_sub_dags.lock().unwrap().clone()
+    }
+
     /// Returns the metric - if exists - identified by the provided name.
     /// If metric has not been found then None is returned instead.
     pub async fn metric(&self, _name: &str) -> Option<Metric> {
@@ -349,8 +412,17 @@ impl PrimaryNodeDetails {
         // The channel returning the result for each transaction's execution.
         let (tx_transaction_confirmation, mut rx_transaction_confirmation) = channel(100);
 
+        // A fresh storage means a fresh commit sequence.
+        if !preserve_store {
+            self.committed_sub_dags.lock().unwrap().clear();
+        }
+
         // Primary node
-        let primary_store: NodeStorage = NodeStorage::reopen(store_path.clone());
+        let primary_store: NodeStorage = NodeStorage::reopen_with(
+            store_path.clone(),
+            &self.parameters.storage,
+            CacheSizes::new(&self.committee, &self.parameters),
+        );
 
         self.node
             .start(
@@ -360,7 +432,10 @@ impl PrimaryNodeDetails {
                 self.worker_cache.clone(),
                 client,
                 &primary_store,
-                Arc::new(SimpleExecutionState::new(tx_transaction_confirmation)),
+                Arc::new(RecordingExecutionState {
+                    inner: SimpleExecutionState::new(tx_transaction_confirmation),
+                    committed_sub_dags: self.committed_sub_dags.clone(),
+                }),
             )
             .await
             .unwrap();
@@ -383,6 +458,27 @@ impl PrimaryNodeDetails {
         self.tx_transaction_confirmation = tx;
     }
 
+    /// Starts the node on a copy of `backup` in place of its storage. The node resumes the
+    /// execution after the last transaction acknowledged in the backup, then catches up from its
+    /// peers. It does not vote for headers up to `vote_floor`.
+    async fn restore(&mut self, client: NetworkClient, backup: &Backup, vote_floor: Round) {
+        let store_path = temp_dir();
+        NodeStorage::restore_primary(backup, &store_path, &self.committee, vote_floor).unwrap();
+        info!(
+            "Primary Node {} restored from the backup at commit {}",
+            self.id, backup.sub_dag_index
+        );
+
+        self.store_path = store_path;
+        self.committed_sub_dags.lock().unwrap().clear();
+        self.start(client, true).await;
+    }
+
+    /// Takes a backup of the storage of the running node.
+    pub async fn backup(&self) -> Result<Backup, NodeError> {
+        self.node.backup().await
+    }
+
     async fn stop(&self) {
         self.node.shutdown().await;
         self.handlers.borrow().iter().for_each(|h| h.abort());
@@ -398,6 +494,26 @@ impl PrimaryNodeDetails {
     }
 }
 
+/// Wraps the execution state of a primary to keep a record of every sub-dag delivered to it,
+/// so the outputs of the nodes can be checked against each other.
+struct RecordingExecutionState {
+    inner: SimpleExecutionState,
+    committed_sub_dags: Arc<Mutex<Vec<Arc<CommittedSubDag>>>>,
+}
+
+#[async_trait]
+impl ExecutionState for RecordingExecutionState {
+    async fn handle_consensus_output(&self, consensus_output: ConsensusOutput, ack: ExecutionAck) {
+        self.committed_sub_dags
+            .lock()
+            .unwrap()
+            .push(consensus_output.sub_dag.clone());
+        self.inner
+            .handle_consensus_output(consensus_output, ack)
+            .await
+    }
+}
+
 #[derive(Clone)]
 pub struct WorkerNodeDetails {
     pub id: WorkerId,
@@ -409,6 +525,7 @@ pub struct WorkerNodeDetails {
     committee: Committee,
     worker_cache: WorkerCache,
     store_path: PathBuf,
+    storage: StorageParameters,
 }
 
 impl WorkerNodeDetails {
@@ -421,6 +538,7 @@ impl WorkerNodeDetails {
         committee: Committee,
         worker_cache: WorkerCache,
     ) -> Self {
+        let storage = parameters.storage.clone();
         let node = WorkerNode::new(id, parameters);
 
         Self {
@@ -428,6 +546,7 @@ impl WorkerNodeDetails {
             name,
             primary_key,
             store_path: temp_dir(),
+            storage,
             transactions_address,
             committee,
             worker_cache,
@@ -456,7 +575,8 @@ impl WorkerNodeDetails {
             temp_dir()
         };
 
-        let worker_store = NodeStorage::reopen(store_path.clone());
+        let worker_store =
+            NodeStorage::reopen_with(store_path.clone(), &self.storage, CacheSizes::default());
 
         self.node
             .start(
@@ -474,6 +594,24 @@ impl WorkerNodeDetails {
         self.store_path = store_path;
     }
 
+    /// Starts the node on a copy of `backup` in place of its storage.
+    async fn restore(&mut self, keypair: NetworkKeyPair, client: NetworkClient, backup: &Backup) {
+        let store_path = temp_dir();
+        NodeStorage::restore(backup, &store_path).unwrap();
+        info!(
+            "Worker {} restored from the backup at commit {}",
+            self.id, backup.sub_dag_index
+        );
+
+        self.store_path = store_path;
+        self.start(keypair, client, true).await;
+    }
+
+    /// Takes a backup of the storage of the running node.
+    pub async fn backup(&self) -> Result<Backup, NodeError> {
+        self.node.backup().await
+    }
+
     async fn stop(&self) {
         self.node.shutdown().await;
         info!("Aborted worker node for id {}", self.id);
@@ -526,6 +664,14 @@ impl AuthorityDetails {
         // Create network client.
         let client = NetworkClient::new_from_keypair(&network_key_pair);
 
+        // Each authority needs its own backup directory, shared by its primary and workers. Unless
+        // configured otherwise, backups are only taken on demand.
+        let mut parameters = parameters;
+        if parameters.backup.directory.is_none() {
+            parameters.backup.directory = Some(temp_dir());
+            parameters.backup.interval = Duration::ZERO;
+        }
+
         // Create all the nodes we have in the committee
         let public_key = key_pair.public().clone();
         let primary = PrimaryNodeDetails::new(
@@ -609,6 +755,17 @@ impl AuthorityDetails {
         internal.primary.stop().await;
     }
 
+    /// Starts the primary node from the provided backup of its storage. The primary must have
+    /// been stopped first, and does not vote for headers up to `vote_floor`.
+    pub async fn restore_primary(&self, backup: &Backup, vote_floor: Round) {
+        let mut internal = self.internal.write().await;
+
+        internal
+            .primary
+            .restore(self.client.clone(), backup, vote_floor)
+            .await;
+    }
+
     pub async fn start_all_workers(&self, preserve_store: bool) {
         let mut internal = self.internal.write().await;
         let worker_keypairs = internal
@@ -642,6 +799,19 @@ impl AuthorityDetails {
             .await;
     }
 
+    /// Starts the worker node by the provided id from the provided backup of its storage. The
+    /// worker must have been stopped first.
+    pub async fn restore_worker(&self, id: WorkerId, backup: &Backup) {
+        let mut internal = self.internal.write().await;
+        let keypair = internal.worker_keypairs.get(id as usize).unwrap().copy();
+        let worker = internal
+            .workers
+            .get_mut(&id)
+            .unwrap_or_else(|| panic!("Worker with id {} not found ", id));
+
+        worker.restore(keypair, self.client.clone(), backup).await;
+    }
+
     pub async fn stop_worker(&self, id: WorkerId) {
         let internal = self.internal.read().await;
 
@@ -703,6 +873,22 @@ impl AuthorityDetails {
             .clone()
     }
 
+    /// Returns the anemo peer ids of the primary and of all the workers of the authority.
+    pub async fn peer_ids(&self) -> Vec<PeerId> {
+        let internal = self.internal.read().await;
+
+        std::iter::once(&*internal.primary.network_key_pair)
+            .chain(internal.worker_keypairs.iter())
+            .map(|kp| PeerId(kp.public().0.to_bytes()))
+            .collect()
+    }
+
+    /// Returns the fault injector of the authority's primary and workers, to drop, delay,
+    /// duplicate or reorder the messages they send and receive.
+    pub fn fault_injector(&self) -> FaultInjector {
+        self.client.fault_injector()
+    }
+
     /// Helper method to return transaction addresses of
     /// all the worker nodes.
     /// Important: only the addresses of the running workers will
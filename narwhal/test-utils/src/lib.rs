@@ -5,7 +5,7 @@
 use anemo::async_trait;
 use config::{
     utils::get_available_port, Authority, AuthorityIdentifier, Committee, CommitteeBuilder, Epoch,
-    Stake, WorkerCache, WorkerId, WorkerIndex, WorkerInfo,
+    ProtocolVersion, Stake, WorkerCache, WorkerId, WorkerIndex, WorkerInfo,
 };
 use crypto::{
     to_intent_message, Hash, KeyPair, NarwhalAuthoritySignature, NetworkKeyPair, NetworkPublicKey,
@@ -25,26 +25,28 @@ use std::{
     num::NonZeroUsize,
     ops::RangeInclusive,
 };
-use store::rocks::DBMap;
-use store::rocks::ReadWriteOptions;
+use storage::BatchStore;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 use tracing::info;
 use types::{
     Batch, BatchDigest, Certificate, CertificateAPI, CertificateDigest, FetchBatchesRequest,
     FetchBatchesResponse, FetchCertificatesRequest, FetchCertificatesResponse,
-    GetCertificatesRequest, GetCertificatesResponse, Header, HeaderAPI, HeaderV1Builder,
-    PayloadAvailabilityRequest, PayloadAvailabilityResponse, PrimaryToPrimary,
-    PrimaryToPrimaryServer, PrimaryToWorker, PrimaryToWorkerServer, RequestBatchRequest,
-    RequestBatchResponse, RequestBatchesRequest, RequestBatchesResponse, RequestVoteRequest,
-    RequestVoteResponse, Round, SendCertificateRequest, SendCertificateResponse, TimestampMs,
-    Transaction, Vote, VoteAPI, WorkerBatchMessage, WorkerDeleteBatchesMessage,
-    WorkerSynchronizeMessage, WorkerToWorker, WorkerToWorkerServer,
+    FetchConsensusSnapshotRequest, FetchConsensusSnapshotResponse, GetCertificatesRequest,
+    GetCertificatesResponse, Header, HeaderAPI, HeaderV2Builder, PayloadAvailabilityRequest,
+    PayloadAvailabilityResponse, PrimaryToPrimary, PrimaryToPrimaryServer, PrimaryToWorker,
+    PrimaryToWorkerServer, RequestBatchRequest, RequestBatchResponse, RequestBatchesRequest,
+    RequestBatchesResponse, RequestVoteRequest, RequestVoteResponse, Round, SendCertificateRequest,
+    SendCertificateResponse, TimestampMs, Transaction, Vote, VoteAPI, WorkerBatchMessage,
+    WorkerDeleteBatchesMessage, WorkerRoundUpdateMessage, WorkerSynchronizeMessage, WorkerToWorker,
+    WorkerToWorkerServer,
 };
 
 pub mod cluster;
+pub mod safety;
 
 pub const VOTES_CF: &str = "votes";
 pub const HEADERS_CF: &str = "headers";
+pub const HEADER_DIGEST_BY_ROUND_CF: &str = "header_digest_by_round";
 pub const CERTIFICATES_CF: &str = "certificates";
 pub const CERTIFICATE_DIGEST_BY_ROUND_CF: &str = "certificate_digest_by_round";
 pub const CERTIFICATE_DIGEST_BY_ORIGIN_CF: &str = "certificate_digest_by_origin";
@@ -222,6 +224,13 @@ impl PrimaryToPrimary for PrimaryToPrimaryMockServer {
         unimplemented!()
     }
 
+    async fn fetch_consensus_snapshot(
+        &self,
+        _request: anemo::Request<FetchConsensusSnapshotRequest>,
+    ) -> Result<anemo::Response<FetchConsensusSnapshotResponse>, anemo::rpc::Status> {
+        unimplemented!()
+    }
+
     async fn get_payload_availability(
         &self,
         _request: anemo::Request<PayloadAvailabilityRequest>,
@@ -282,6 +291,13 @@ impl PrimaryToWorker for PrimaryToWorkerMockServer {
         tracing::error!("Not implemented PrimaryToWorkerMockServer::delete_batches");
         Err(anemo::rpc::Status::internal("Unimplemented"))
     }
+
+    async fn update_round(
+        &self,
+        _request: anemo::Request<WorkerRoundUpdateMessage>,
+    ) -> Result<anemo::Response<()>, anemo::rpc::Status> {
+        Ok(anemo::Response::new(()))
+    }
 }
 
 pub struct WorkerToWorkerMockServer {
@@ -368,16 +384,8 @@ pub fn batch_with_transactions(num_of_transactions: usize) -> Batch {
     Batch::new(transactions)
 }
 
-const BATCHES_CF: &str = "batches";
-
-pub fn create_batch_store() -> DBMap<BatchDigest, Batch> {
-    DBMap::<BatchDigest, Batch>::open(
-        temp_dir(),
-        None,
-        Some(BATCHES_CF),
-        &ReadWriteOptions::default(),
-    )
-    .unwrap()
+pub fn create_batch_store() -> BatchStore {
+    BatchStore::new_for_tests()
 }
 
 // Creates one certificate per authority starting and finishing at the specified rounds (inclusive).
@@ -597,7 +605,7 @@ pub fn mock_certificate_with_rand<R: RngCore + ?Sized>(
     parents: BTreeSet<CertificateDigest>,
     rand: &mut R,
 ) -> (CertificateDigest, Certificate) {
-    let header_builder = HeaderV1Builder::default();
+    let header_builder = HeaderV2Builder::default();
     let header = header_builder
         .author(origin)
         .round(round)
@@ -605,7 +613,7 @@ pub fn mock_certificate_with_rand<R: RngCore + ?Sized>(
         .parents(parents)
         .payload(fixture_payload_with_rand(1, rand))
         .build();
-    let certificate = Certificate::new_unsigned(committee, Header::V1(header), Vec::new()).unwrap();
+    let certificate = Certificate::new_unsigned(committee, Header::V2(header), Vec::new()).unwrap();
     (certificate.digest(), certificate)
 }
 
@@ -629,7 +637,7 @@ pub fn mock_certificate_with_epoch(
     epoch: Epoch,
     parents: BTreeSet<CertificateDigest>,
 ) -> (CertificateDigest, Certificate) {
-    let header_builder = HeaderV1Builder::default();
+    let header_builder = HeaderV2Builder::default();
     let header = header_builder
         .author(origin)
         .round(round)
@@ -638,7 +646,29 @@ pub fn mock_certificate_with_epoch(
         .payload(fixture_payload(1))
         .build();
     // TODO: sign: KeyPair::new(&mut rand::thread_rng()).unwrap().private()
-    let certificate = Certificate::new_unsigned(committee, Header::V1(header), Vec::new()).unwrap();
+    let certificate = Certificate::new_unsigned(committee, Header::V2(header), Vec::new()).unwrap();
+    (certificate.digest(), certificate)
+}
+
+// Creates a badly signed certificate from its given round, origin, parents and weak links,
+// Note: the certificate is signed by a random key rather than its author
+pub fn mock_certificate_with_weak_links(
+    committee: &Committee,
+    origin: AuthorityIdentifier,
+    round: Round,
+    parents: BTreeSet<CertificateDigest>,
+    weak_links: BTreeMap<CertificateDigest, Round>,
+) -> (CertificateDigest, Certificate) {
+    let header_builder = HeaderV2Builder::default();
+    let header = header_builder
+        .author(origin)
+        .round(round)
+        .epoch(0)
+        .parents(parents)
+        .weak_links(weak_links)
+        .payload(fixture_payload(1))
+        .build();
+    let certificate = Certificate::new_unsigned(committee, Header::V2(header), Vec::new()).unwrap();
     (certificate.digest(), certificate)
 }
 
@@ -650,17 +680,21 @@ pub fn mock_signed_certificate(
     parents: BTreeSet<CertificateDigest>,
     committee: &Committee,
 ) -> (CertificateDigest, Certificate) {
-    let header_builder = HeaderV1Builder::default()
+    let (_, author) = signers
+        .iter()
+        .find(|(name, _)| *name == origin)
+        .expect("The signers must include the origin");
+    let header_builder = HeaderV2Builder::default()
         .author(origin)
         .payload(fixture_payload(1))
         .round(round)
         .epoch(0)
         .parents(parents);
 
-    let header = header_builder.build(); // TODO: sign with author's key
+    let header = header_builder.signed(author.private()).build();
 
     let cert =
-        Certificate::new_unsigned(committee, Header::V1(header.clone()), Vec::new()).unwrap();
+        Certificate::new_unsigned(committee, Header::V2(header.clone()), Vec::new()).unwrap();
 
     let mut votes = Vec::new();
     for (name, signer) in signers {
@@ -668,7 +702,7 @@ pub fn mock_signed_certificate(
             Signature::new_secure(&to_intent_message(cert.header().digest()), signer.private());
         votes.push((*name, sig))
     }
-    let cert = Certificate::new_unverified(committee, Header::V1(header), votes).unwrap();
+    let cert = Certificate::new_unverified(committee, Header::V2(header), votes).unwrap();
     (cert.digest(), cert)
 }
 
@@ -678,6 +712,7 @@ pub struct Builder<R = OsRng> {
     number_of_workers: NonZeroUsize,
     randomize_ports: bool,
     epoch: Epoch,
+    protocol_version: ProtocolVersion,
     stake: VecDeque<Stake>,
 }
 
@@ -691,6 +726,7 @@ impl Builder {
     pub fn new() -> Self {
         Self {
             epoch: Epoch::default(),
+            protocol_version: ProtocolVersion::default(),
             rng: OsRng,
             committee_size: NonZeroUsize::new(4).unwrap(),
             number_of_workers: NonZeroUsize::new(4).unwrap(),
@@ -721,6 +757,11 @@ impl<R> Builder<R> {
         self
     }
 
+    pub fn protocol_version(mut self, protocol_version: ProtocolVersion) -> Self {
+        self.protocol_version = protocol_version;
+        self
+    }
+
     pub fn stake_distribution(mut self, stake: VecDeque<Stake>) -> Self {
         self.stake = stake;
         self
@@ -730,6 +771,7 @@ impl<R> Builder<R> {
         Builder {
             rng,
             epoch: self.epoch,
+            protocol_version: self.protocol_version,
             committee_size: self.committee_size,
             number_of_workers: self.number_of_workers,
             randomize_ports: self.randomize_ports,
@@ -765,7 +807,8 @@ impl<R: rand::RngCore + rand::CryptoRng> Builder<R> {
         authorities.sort_by_key(|a1| a1.public_key());
 
         // create the committee in order to assign the ids to the authorities
-        let mut committee_builder = CommitteeBuilder::new(self.epoch);
+        let mut committee_builder =
+            CommitteeBuilder::new(self.epoch).protocol_version(self.protocol_version);
         for a in authorities.iter() {
             committee_builder = committee_builder.add_authority(
                 a.public_key().clone(),
@@ -865,15 +908,16 @@ impl CommitteeFixture {
             .authorities
             .iter()
             .map(|a| {
-                let builder = types::HeaderV1Builder::default();
+                let builder = types::HeaderV2Builder::default();
                 let header = builder
                     .author(a.id())
                     .round(round)
                     .epoch(0)
                     .parents(parents.clone())
                     .with_payload_batch(fixture_batch_with_transactions(10), 0, 0)
+                    .signed(a.keypair().private())
                     .build();
-                Header::V1(header)
+                Header::V2(header)
             })
             .collect();
 
@@ -975,8 +1019,9 @@ impl AuthorityFixture {
         let header = self
             .header_builder(committee)
             .payload(Default::default())
+            .signed(self.keypair().private())
             .build();
-        Header::V1(header)
+        Header::V2(header)
     }
 
     pub fn header_with_round(&self, committee: &Committee, round: Round) -> Header {
@@ -984,12 +1029,13 @@ impl AuthorityFixture {
             .header_builder(committee)
             .payload(Default::default())
             .round(round)
+            .signed(self.keypair().private())
             .build();
-        Header::V1(header)
+        Header::V2(header)
     }
 
-    pub fn header_builder(&self, committee: &Committee) -> types::HeaderV1Builder {
-        types::HeaderV1Builder::default()
+    pub fn header_builder(&self, committee: &Committee) -> types::HeaderV2Builder {
+        types::HeaderV2Builder::default()
             .author(self.id())
             .round(1)
             .epoch(committee.epoch())
@@ -0,0 +1,416 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use config::AuthorityIdentifier;
+use crypto::Hash;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    sync::Arc,
+};
+use thiserror::Error;
+use types::{
+    Certificate, CertificateAPI, CertificateDigest, CommittedSubDag, HeaderAPI, Round,
+    SequenceNumber, TimestampMs,
+};
+
+#[cfg(test)]
+#[path = "tests/safety_tests.rs"]
+pub mod safety_tests;
+
+/// A violation of one of the safety properties of the committed sub-dag sequence.
+#[derive(Debug, Clone, Error, PartialEq, Eq)]
+pub enum SafetyViolation {
+    #[error("Node {node} committed sub-dag {index} after {previous}: sub-dag indexes must be contiguous")]
+    NonContiguousIndex {
+        node: usize,
+        index: SequenceNumber,
+        previous: SequenceNumber,
+    },
+
+    #[error("Node {node} re-delivered sub-dag {index} with a different content:\n{divergence}")]
+    InconsistentReplay {
+        node: usize,
+        index: SequenceNumber,
+        divergence: SubDagDivergence,
+    },
+
+    #[error("Nodes {first} and {second} disagree on sub-dag {index}:\n{divergence}")]
+    OrderDivergence {
+        first: usize,
+        second: usize,
+        index: SequenceNumber,
+        divergence: SubDagDivergence,
+    },
+
+    #[error("Node {node} committed certificate {certificate} twice: in sub-dag {first_index} and in sub-dag {second_index}")]
+    DuplicateCommit {
+        node: usize,
+        certificate: CertificateDigest,
+        first_index: SequenceNumber,
+        second_index: SequenceNumber,
+    },
+
+    #[error("Node {node} committed certificate {certificate} (round {round}) in sub-dag {index} without its parent {parent} (round {parent_round})")]
+    MissingParent {
+        node: usize,
+        index: SequenceNumber,
+        certificate: CertificateDigest,
+        round: Round,
+        parent: CertificateDigest,
+        parent_round: Round,
+    },
+
+    #[error("Node {node} committed sub-dag {index} with timestamp {timestamp} which is older than the timestamp {previous} of sub-dag {previous_index}")]
+    NonMonotonicTimestamp {
+        node: usize,
+        index: SequenceNumber,
+        timestamp: TimestampMs,
+        previous_index: SequenceNumber,
+        previous: TimestampMs,
+    },
+}
+
+/// A compact, digest based view of a committed sub-dag used to compare the commits of
+/// different nodes and to describe how they diverge.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubDagSummary {
+    pub leader: CertificateDigest,
+    pub leader_round: Round,
+    pub certificates: Vec<(CertificateDigest, Round)>,
+    pub commit_timestamp: TimestampMs,
+}
+
+impl SubDagSummary {
+    pub fn from_sub_dag(sub_dag: &CommittedSubDag) -> Self {
+        Self {
+            leader: sub_dag.leader.digest(),
+            leader_round: sub_dag.leader_round(),
+            certificates: sub_dag
+                .certificates
+                .iter()
+                .map(|c| (c.digest(), c.round()))
+                .collect(),
+            commit_timestamp: sub_dag.commit_timestamp(),
+        }
+    }
+}
+
+/// The two sides of a disagreement on the content of the same sub-dag index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubDagDivergence {
+    pub expected: SubDagSummary,
+    pub actual: SubDagSummary,
+}
+
+impl fmt::Display for SubDagDivergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (expected, actual) = (&self.expected, &self.actual);
+
+        if expected.leader != actual.leader {
+            writeln!(
+                f,
+                "  leader: {} (round {}) != {} (round {})",
+                expected.leader, expected.leader_round, actual.leader, actual.leader_round
+            )?;
+        }
+        if expected.commit_timestamp != actual.commit_timestamp {
+            writeln!(
+                f,
+                "  commit timestamp: {} != {}",
+                expected.commit_timestamp, actual.commit_timestamp
+            )?;
+        }
+        if expected.certificates != actual.certificates {
+            writeln!(
+                f,
+                "  certificates ({} vs {}):",
+                expected.certificates.len(),
+                actual.certificates.len()
+            )?;
+            let len = expected.certificates.len().max(actual.certificates.len());
+            for i in 0..len {
+                let left = expected.certificates.get(i);
+                let right = actual.certificates.get(i);
+                let marker = if left == right { " " } else { "!" };
+                writeln!(
+                    f,
+                    "  {marker} #{i}: {} | {}",
+                    format_entry(left),
+                    format_entry(right)
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn format_entry(entry: Option<&(CertificateDigest, Round)>) -> String {
+    entry.map_or_else(
+        || "-".to_string(),
+        |(digest, round)| format!("{digest} (round {round})"),
+    )
+}
+
+/// Collects the sequence of committed sub-dags delivered to the execution state of every
+/// node of a cluster and checks the safety properties that all nodes must agree on:
+/// * total order: the same sub-dag index maps to the same leader, certificate list and commit
+///   timestamp on every node,
+/// * no certificate is committed twice by the same node,
+/// * causal completeness: every committed certificate has its parents committed before or
+///   within the same sub-dag, unless the parent has been garbage collected or is not
+///   expected to be committed any more (an equal or higher round of the same origin has
+///   already been committed),
+/// * the commit timestamp never decreases.
+///
+/// Sub-dags re-delivered after a crash recovery are accepted as long as they are identical to
+/// the ones initially delivered.
+pub struct ConsensusOutputChecker {
+    gc_depth: Round,
+    outputs: BTreeMap<usize, Vec<Arc<CommittedSubDag>>>,
+}
+
+impl ConsensusOutputChecker {
+    pub fn new(gc_depth: Round) -> Self {
+        Self {
+            gc_depth,
+            outputs: BTreeMap::new(),
+        }
+    }
+
+    /// Records a sub-dag delivered to the execution state of the node `node`, in the order
+    /// it has been delivered.
+    pub fn record(&mut self, node: usize, sub_dag: Arc<CommittedSubDag>) {
+        self.outputs.entry(node).or_default().push(sub_dag);
+    }
+
+    /// Records all the sub-dags delivered to the execution state of the node `node`, in the
+    /// order they have been delivered.
+    pub fn record_all(
+        &mut self,
+        node: usize,
+        sub_dags: impl IntoIterator<Item = Arc<CommittedSubDag>>,
+    ) {
+        self.outputs.entry(node).or_default().extend(sub_dags);
+    }
+
+    /// Runs all the checks and returns every violation found. An empty vector means the
+    /// recorded outputs are safe.
+    pub fn violations(&self) -> Vec<SafetyViolation> {
+        let mut violations = Vec::new();
+
+        // The de-duplicated (replays removed) sequence of every node.
+        let mut sequences: BTreeMap<usize, BTreeMap<SequenceNumber, Arc<CommittedSubDag>>> =
+            BTreeMap::new();
+
+        let mut known = KnownCertificates::default();
+        for sub_dag in self.outputs.values().flatten() {
+            sub_dag.certificates.iter().for_each(|c| known.insert(c));
+        }
+
+        for (node, outputs) in &self.outputs {
+            let sequence = self.check_node(*node, outputs, &known, &mut violations);
+            sequences.insert(*node, sequence);
+        }
+
+        // Total order agreement: compare every sub-dag with the first node that committed
+        // the same index.
+        let mut reference: HashMap<SequenceNumber, (usize, SubDagSummary)> = HashMap::new();
+        for (node, sequence) in &sequences {
+            for (index, sub_dag) in sequence {
+                let summary = SubDagSummary::from_sub_dag(sub_dag);
+                match reference.get(index) {
+                    Some((first, expected)) if *expected != summary => {
+                        violations.push(SafetyViolation::OrderDivergence {
+                            first: *first,
+                            second: *node,
+                            index: *index,
+                            divergence: SubDagDivergence {
+                                expected: expected.clone(),
+                                actual: summary,
+                            },
+                        });
+                    }
+                    Some(_) => {}
+                    None => {
+                        reference.insert(*index, (*node, summary));
+                    }
+                }
+            }
+        }
+
+        violations
+    }
+
+    /// Returns an error with a human readable report of all the violations, if any.
+    pub fn check(&self) -> Result<(), String> {
+        let violations = self.violations();
+        if violations.is_empty() {
+            return Ok(());
+        }
+
+        let mut report = format!(
+            "Found {} safety violation(s) across {} node(s):\n",
+            violations.len(),
+            self.outputs.len()
+        );
+        for violation in violations {
+            report.push_str(&format!("- {violation}\n"));
+        }
+        Err(report)
+    }
+
+    /// Panics with a human readable report if any of the safety properties is violated.
+    pub fn assert_safe(&self) {
+        if let Err(report) = self.check() {
+            panic!("{report}");
+        }
+    }
+
+    /// Checks the properties local to a single node and returns its sequence of sub-dags
+    /// with the crash-recovery replays removed.
+    fn check_node(
+        &self,
+        node: usize,
+        outputs: &[Arc<CommittedSubDag>],
+        known: &KnownCertificates,
+        violations: &mut Vec<SafetyViolation>,
+    ) -> BTreeMap<SequenceNumber, Arc<CommittedSubDag>> {
+        let mut sequence: BTreeMap<SequenceNumber, Arc<CommittedSubDag>> = BTreeMap::new();
+        let mut committed: HashMap<CertificateDigest, SequenceNumber> = HashMap::new();
+        let mut last_committed: HashMap<AuthorityIdentifier, Round> = HashMap::new();
+        // Leader round of the first sub-dag we have seen. Parents at or below it might have been
+        // committed before the node started recording, so we can't reason about them.
+        let mut first_leader_round = None;
+
+        for sub_dag in outputs {
+            let index = sub_dag.sub_dag_index;
+
+            if let Some((last_index, last)) = sequence.last_key_value() {
+                if index <= *last_index {
+                    // A replay after a crash recovery, it has to be identical.
+                    if let Some(delivered) = sequence.get(&index) {
+                        let expected = SubDagSummary::from_sub_dag(delivered);
+                        let actual = SubDagSummary::from_sub_dag(sub_dag);
+                        if expected != actual {
+                            violations.push(SafetyViolation::InconsistentReplay {
+                                node,
+                                index,
+                                divergence: SubDagDivergence { expected, actual },
+                            });
+                        }
+                    }
+                    continue;
+                }
+
+                if index != last_index + 1 {
+                    violations.push(SafetyViolation::NonContiguousIndex {
+                        node,
+                        index,
+                        previous: *last_index,
+                    });
+                }
+
+                if sub_dag.commit_timestamp() < last.commit_timestamp() {
+                    violations.push(SafetyViolation::NonMonotonicTimestamp {
+                        node,
+                        index,
+                        timestamp: sub_dag.commit_timestamp(),
+                        previous_index: *last_index,
+                        previous: last.commit_timestamp(),
+                    });
+                }
+            }
+
+            for certificate in &sub_dag.certificates {
+                if let Some(first_index) = committed.insert(certificate.digest(), index) {
+                    violations.push(SafetyViolation::DuplicateCommit {
+                        node,
+                        certificate: certificate.digest(),
+                        first_index,
+                        second_index: index,
+                    });
+                }
+            }
+
+            let first_leader_round = *first_leader_round.get_or_insert(sub_dag.leader_round());
+            let gc_round = sub_dag.leader_round().saturating_sub(self.gc_depth);
+
+            for certificate in &sub_dag.certificates {
+                let parent_round = certificate.round().saturating_sub(1);
+                if parent_round <= gc_round || parent_round <= first_leader_round {
+                    continue;
+                }
+
+                for parent in certificate.header().parents() {
+                    if committed.get(parent).map_or(false, |i| *i <= index) {
+                        continue;
+                    }
+                    if known.superseded(parent, parent_round, &last_committed) {
+                        continue;
+                    }
+                    violations.push(SafetyViolation::MissingParent {
+                        node,
+                        index,
+                        certificate: certificate.digest(),
+                        round: certificate.round(),
+                        parent: *parent,
+                        parent_round,
+                    });
+                }
+            }
+
+            for certificate in &sub_dag.certificates {
+                let round = last_committed.entry(certificate.origin()).or_default();
+                *round = (*round).max(certificate.round());
+            }
+
+            sequence.insert(index, sub_dag.clone());
+        }
+
+        sequence
+    }
+}
+
+/// The certificates committed by any of the nodes, used to find out the origin of the
+/// parents a node has not committed (yet).
+#[derive(Default)]
+struct KnownCertificates {
+    origins: HashMap<CertificateDigest, AuthorityIdentifier>,
+    by_origin_and_round: HashMap<(AuthorityIdentifier, Round), CertificateDigest>,
+}
+
+impl KnownCertificates {
+    fn insert(&mut self, certificate: &Certificate) {
+        self.origins
+            .insert(certificate.digest(), certificate.origin());
+        self.by_origin_and_round.insert(
+            (certificate.origin(), certificate.round()),
+            certificate.digest(),
+        );
+    }
+
+    /// Consensus never commits a certificate whose origin already had a certificate of an
+    /// equal or higher round committed. Returns true if that can be the case for `parent`,
+    /// given the `last_committed` rounds per authority. When the origin of the parent is not
+    /// known (no node committed it) any authority without a known certificate for that round
+    /// is a candidate origin.
+    fn superseded(
+        &self,
+        parent: &CertificateDigest,
+        parent_round: Round,
+        last_committed: &HashMap<AuthorityIdentifier, Round>,
+    ) -> bool {
+        if let Some(origin) = self.origins.get(parent) {
+            return last_committed
+                .get(origin)
+                .map_or(false, |r| *r >= parent_round);
+        }
+
+        last_committed.iter().any(|(origin, r)| {
+            *r >= parent_round
+                && !self
+                    .by_origin_and_round
+                    .contains_key(&(*origin, parent_round))
+        })
+    }
+}
@@ -65,3 +65,144 @@ async fn cluster_setup_with_consensus_disabled() {
     assert_eq!(0, r.oldest_round);
     assert_eq!(0, r.newest_round);
 }
+
+#[tokio::test]
+async fn cluster_commits_are_safe() {
+    ensure_test_environment();
+    let mut cluster = Cluster::new(None, true);
+
+    // start the cluster will all the possible nodes
+    cluster.start(Some(4), Some(1), None).await;
+
+    // give some time for nodes to commit a few sub-dags
+    tokio::time::sleep(Duration::from_secs(10)).await;
+
+    // restart one node preserving its store, so it resumes the execution after its last
+    // acknowledged transaction
+    cluster
+        .authority(3)
+        .restart(true, Duration::from_secs(1))
+        .await;
+
+    tokio::time::sleep(Duration::from_secs(10)).await;
+
+    assert!(!cluster
+        .authority(0)
+        .primary()
+        .await
+        .committed_sub_dags()
+        .is_empty());
+
+    // all the nodes should agree on the committed sequence
+    cluster.assert_safety().await;
+
+    for id in 0..4 {
+        cluster.stop_node(id).await;
+    }
+}
+
+#[tokio::test]
+async fn cluster_commits_in_memory() {
+    ensure_test_environment();
+    let mut parameters = Cluster::parameters();
+    parameters.storage.in_memory = true;
+    let mut cluster = Cluster::new(Some(parameters), true);
+
+    cluster.start(Some(4), Some(1), None).await;
+
+    // the nodes commit without touching the disk
+    tokio::time::sleep(Duration::from_secs(10)).await;
+
+    assert!(!cluster
+        .authority(0)
+        .primary()
+        .await
+        .committed_sub_dags()
+        .is_empty());
+    cluster.assert_safety().await;
+
+    for id in 0..4 {
+        cluster.stop_node(id).await;
+    }
+}
+
+#[tokio::test]
+async fn cluster_recovers_from_partition() {
+    ensure_test_environment();
+    let mut cluster = Cluster::new(None, true);
+
+    cluster.start(Some(4), Some(1), None).await;
+
+    // isolate the last node, the rest of the cluster still has a quorum
+    cluster.partition(&[3], &[0, 1, 2]).await;
+
+    tokio::time::sleep(Duration::from_secs(10)).await;
+
+    let isolated_commits = cluster
+        .authority(3)
+        .primary()
+        .await
+        .committed_sub_dags()
+        .len();
+
+    // once healed the isolated node fetches the missing certificates and catches up
+    cluster.heal();
+
+    tokio::time::sleep(Duration::from_secs(15)).await;
+
+    assert!(
+        cluster
+            .authority(3)
+            .primary()
+            .await
+            .committed_sub_dags()
+            .len()
+            > isolated_commits
+    );
+    cluster.assert_safety().await;
+
+    for id in 0..4 {
+        cluster.stop_node(id).await;
+    }
+}
+
+#[tokio::test]
+async fn cluster_node_restores_from_backup() {
+    ensure_test_environment();
+    let mut cluster = Cluster::new(None, true);
+
+    cluster.start(Some(4), Some(1), None).await;
+
+    tokio::time::sleep(Duration::from_secs(10)).await;
+
+    // back up the node while it runs, then keep committing without it
+    let authority = cluster.authority(3);
+    let backup = authority.primary().await.backup().await.unwrap();
+    assert!(backup.sub_dag_index > 0);
+    let worker_backup = authority.worker(0).await.backup().await.unwrap();
+    authority.stop_all().await;
+
+    tokio::time::sleep(Duration::from_secs(5)).await;
+
+    // the restored node does not vote up to the rounds its peers committed since it stopped
+    let mut vote_floor = 0;
+    for id in 0..3 {
+        let committed_sub_dags = cluster.authority(id).primary().await.committed_sub_dags();
+        vote_floor = vote_floor.max(committed_sub_dags.last().unwrap().leader_round());
+    }
+
+    // the restored node resumes the execution from the backup and catches up with its peers
+    authority.restore_worker(0, &worker_backup).await;
+    authority.restore_primary(&backup, vote_floor).await;
+
+    tokio::time::sleep(Duration::from_secs(15)).await;
+
+    let committed_sub_dags = cluster.authority(3).primary().await.committed_sub_dags();
+    assert!(committed_sub_dags.first().unwrap().sub_dag_index <= backup.sub_dag_index + 1);
+    assert!(committed_sub_dags.last().unwrap().sub_dag_index > backup.sub_dag_index);
+    cluster.assert_safety().await;
+
+    for id in 0..4 {
+        cluster.stop_node(id).await;
+    }
+}
@@ -0,0 +1,227 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use super::*;
+use crate::{make_optimal_certificates, CommitteeFixture};
+use config::Committee;
+use indexmap::IndexMap;
+use std::collections::BTreeSet;
+use types::{Header, HeaderV2Builder, ReputationScores};
+
+const GC_DEPTH: Round = 50;
+
+/// Creates the certificates of rounds 1 to 6 and the three sub-dags committed by the leaders of
+/// rounds 2, 4 and 6 (always the certificate of the first authority).
+fn committed_sub_dags() -> Vec<Arc<CommittedSubDag>> {
+    let fixture = CommitteeFixture::builder().build();
+    let committee: Committee = fixture.committee();
+    let ids: Vec<_> = fixture.authorities().map(|a| a.id()).collect();
+    let (certificates, _) = make_optimal_certificates(&committee, 1..=6, &BTreeSet::new(), &ids);
+
+    let mut sub_dags: Vec<Arc<CommittedSubDag>> = Vec::new();
+    let mut committed_round = 0;
+    for leader_round in [2, 4, 6] {
+        let leader = certificates
+            .iter()
+            .find(|c| c.round() == leader_round && c.origin() == ids[0])
+            .unwrap()
+            .clone();
+        let mut sub_dag: Vec<Certificate> = certificates
+            .iter()
+            .filter(|c| c.round() > committed_round && c.round() < leader_round)
+            .cloned()
+            .collect();
+        // The other certificates of the previous leader round are committed now.
+        sub_dag.extend(
+            certificates
+                .iter()
+                .filter(|c| c.round() == committed_round && c.origin() != ids[0])
+                .cloned(),
+        );
+        sub_dag.push(leader.clone());
+        sub_dag.sort_by_key(|c| c.round());
+
+        let sub_dag = CommittedSubDag::new(
+            sub_dag,
+            leader,
+            sub_dags.len() as SequenceNumber + 1,
+            ReputationScores::default(),
+            sub_dags.last().map(|s| s.as_ref()),
+        );
+        sub_dags.push(Arc::new(sub_dag));
+        committed_round = leader_round;
+    }
+    sub_dags
+}
+
+#[test]
+fn agreeing_nodes_are_safe() {
+    let sub_dags = committed_sub_dags();
+
+    let mut checker = ConsensusOutputChecker::new(GC_DEPTH);
+    checker.record_all(0, sub_dags.clone());
+    checker.record_all(1, sub_dags.clone());
+    // A node lagging behind is fine.
+    checker.record_all(2, sub_dags[..2].to_vec());
+    // A node re-delivering sub-dags after a crash recovery is fine too.
+    checker.record_all(3, sub_dags[..2].to_vec());
+    checker.record_all(3, sub_dags[1..].to_vec());
+
+    assert_eq!(checker.violations(), vec![]);
+    checker.assert_safe();
+}
+
+#[test]
+fn detects_order_divergence() {
+    let sub_dags = committed_sub_dags();
+
+    // Node 1 commits the sub-dags of round 4 and 6 in swapped positions.
+    let mut swapped = (*sub_dags[2]).clone();
+    swapped.sub_dag_index = 2;
+
+    let mut checker = ConsensusOutputChecker::new(GC_DEPTH);
+    checker.record_all(0, sub_dags.clone());
+    checker.record_all(1, vec![sub_dags[0].clone(), Arc::new(swapped)]);
+
+    let violations = checker.violations();
+    assert!(violations.iter().any(|v| matches!(
+        v,
+        SafetyViolation::OrderDivergence {
+            first: 0,
+            second: 1,
+            index: 2,
+            ..
+        }
+    )));
+
+    let report = checker.check().unwrap_err();
+    assert!(report.contains("Nodes 0 and 1 disagree on sub-dag 2"));
+    assert!(report.contains("leader:"));
+}
+
+#[test]
+fn detects_duplicate_commits() {
+    let sub_dags = committed_sub_dags();
+
+    // Commit again the leader of round 2 in the sub-dag of round 4.
+    let mut duplicate = (*sub_dags[1]).clone();
+    duplicate.certificates.insert(0, sub_dags[0].leader.clone());
+
+    let mut checker = ConsensusOutputChecker::new(GC_DEPTH);
+    checker.record_all(0, vec![sub_dags[0].clone(), Arc::new(duplicate)]);
+
+    let violations = checker.violations();
+    assert_eq!(
+        violations,
+        vec![SafetyViolation::DuplicateCommit {
+            node: 0,
+            certificate: sub_dags[0].leader.digest(),
+            first_index: 1,
+            second_index: 2,
+        }]
+    );
+}
+
+#[test]
+fn detects_missing_parents() {
+    let sub_dags = committed_sub_dags();
+
+    // Drop one of the round 4 certificates from the sub-dag of round 6.
+    let mut incomplete = (*sub_dags[2]).clone();
+    let position = incomplete
+        .certificates
+        .iter()
+        .position(|c| c.round() == 4 && c.digest() != sub_dags[1].leader.digest())
+        .unwrap();
+    let dropped = incomplete.certificates.remove(position);
+
+    let mut checker = ConsensusOutputChecker::new(GC_DEPTH);
+    checker.record_all(0, vec![sub_dags[0].clone(), sub_dags[1].clone()]);
+    checker.record(0, Arc::new(incomplete));
+
+    let violations = checker.violations();
+    assert!(!violations.is_empty());
+    assert!(violations.iter().all(|v| matches!(
+        v,
+        SafetyViolation::MissingParent { node: 0, index: 3, parent, parent_round: 4, .. } if *parent == dropped.digest()
+    )));
+}
+
+#[test]
+fn detects_gaps_and_inconsistent_replays() {
+    let sub_dags = committed_sub_dags();
+
+    let mut altered = (*sub_dags[1]).clone();
+    altered.certificates.pop();
+
+    let mut checker = ConsensusOutputChecker::new(GC_DEPTH);
+    checker.record_all(0, vec![sub_dags[0].clone(), sub_dags[2].clone()]);
+    checker.record_all(1, sub_dags.clone());
+    checker.record(1, Arc::new(altered));
+
+    let violations = checker.violations();
+    assert!(violations.contains(&SafetyViolation::NonContiguousIndex {
+        node: 0,
+        index: 3,
+        previous: 1,
+    }));
+    assert!(violations.iter().any(|v| matches!(
+        v,
+        SafetyViolation::InconsistentReplay {
+            node: 1,
+            index: 2,
+            ..
+        }
+    )));
+}
+
+#[test]
+fn detects_non_monotonic_timestamps() {
+    let fixture = CommitteeFixture::builder().build();
+    let committee = fixture.committee();
+
+    let leader = |round: Round, created_at| {
+        let header = HeaderV2Builder::default()
+            .author(AuthorityIdentifier(0))
+            .round(round)
+            .epoch(0)
+            .created_at(created_at)
+            .payload(IndexMap::new())
+            .parents(BTreeSet::new())
+            .build();
+        Certificate::new_unsigned(&committee, Header::V2(header), Vec::new()).unwrap()
+    };
+
+    let first = leader(2, 100);
+    let second = leader(4, 50);
+    // Build both sub-dags without any previous one, so the timestamp is not auto-corrected.
+    let sub_dags = vec![
+        Arc::new(CommittedSubDag::new(
+            vec![first.clone()],
+            first,
+            1,
+            ReputationScores::default(),
+            None,
+        )),
+        Arc::new(CommittedSubDag::new(
+            vec![second.clone()],
+            second,
+            2,
+            ReputationScores::default(),
+            None,
+        )),
+    ];
+
+    let mut checker = ConsensusOutputChecker::new(GC_DEPTH);
+    checker.record_all(0, sub_dags);
+
+    assert_eq!(
+        checker.violations(),
+        vec![SafetyViolation::NonMonotonicTimestamp {
+            node: 0,
+            index: 2,
+            timestamp: 50,
+            previous_index: 1,
+            previous: 100,
+        }]
+    );
+}
@@ -97,6 +97,15 @@ fn build_anemo_services(out_dir: &Path) {
                 .codec_path(codec_path)
                 .build(),
         )
+        .method(
+            anemo_build::manual::Method::builder()
+                .name("fetch_consensus_snapshot")
+                .route_name("FetchConsensusSnapshot")
+                .request_type("crate::FetchConsensusSnapshotRequest")
+                .response_type("crate::FetchConsensusSnapshotResponse")
+                .codec_path(codec_path)
+                .build(),
+        )
         .build();
 
     let primary_to_worker = anemo_build::manual::Service::builder()
@@ -130,6 +139,15 @@ fn build_anemo_services(out_dir: &Path) {
                 .codec_path(codec_path)
                 .build(),
         )
+        .method(
+            anemo_build::manual::Method::builder()
+                .name("update_round")
+                .route_name("UpdateRound")
+                .request_type("crate::WorkerRoundUpdateMessage")
+                .response_type("()")
+                .codec_path(codec_path)
+                .build(),
+        )
         .build();
 
     let worker_to_primary = anemo_build::manual::Service::builder()
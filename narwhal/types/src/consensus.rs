@@ -2,12 +2,16 @@
 // SPDX-License-Identifier: Apache-2.0
 #![allow(clippy::mutable_key_type)]
 
-use crate::{Batch, Certificate, CertificateAPI, CertificateDigest, HeaderAPI, Round, TimestampMs};
-use config::{AuthorityIdentifier, Committee};
-use crypto::Hash;
+use crate::{
+    error::{DagError, DagResult},
+    randomness_message, Batch, BatchAPI, BatchDigest, Certificate, CertificateAPI,
+    CertificateDigest, HeaderAPI, Round, TimestampMs, Transaction,
+};
+use config::{AuthorityIdentifier, Committee, Stake};
+use crypto::{Digest, Hash};
 use enum_dispatch::enum_dispatch;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 use tokio::sync::mpsc;
 use tracing::warn;
@@ -21,6 +25,127 @@ pub type SequenceNumber = u64;
 pub struct ConsensusOutput {
     pub sub_dag: Arc<CommittedSubDag>,
     pub batches: Vec<(Certificate, Vec<Batch>)>,
+    /// The indices of the last transaction of this sub dag acknowledged before a restart, if the
+    /// sub dag was partially executed. The transactions up to it are not delivered again.
+    pub last_executed: Option<ExecutionIndices>,
+}
+
+impl ConsensusOutput {
+    /// Returns the transactions to execute in order, along with their execution indices. The
+    /// transactions acknowledged before a restart are skipped.
+    pub fn transactions(&self) -> impl Iterator<Item = (ExecutionIndices, &Transaction)> + '_ {
+        let last_committed_round = self.sub_dag.leader_round();
+        let sub_dag_index = self.sub_dag.sub_dag_index;
+        self.batches
+            .iter()
+            .flat_map(|(_, batches)| batches)
+            .flat_map(|batch| batch.transactions())
+            .enumerate()
+            .map(move |(transaction_index, transaction)| {
+                let indices = ExecutionIndices {
+                    last_committed_round,
+                    sub_dag_index,
+                    transaction_index: transaction_index as SequenceNumber,
+                };
+                (indices, transaction)
+            })
+            .filter(|(indices, _)| {
+                self.last_executed
+                    .map_or(true, |last_executed| *indices > last_executed)
+            })
+    }
+
+    /// The execution indices of the last transaction of the sub dag, or None if it has none.
+    pub fn last_transaction_indices(&self) -> Option<ExecutionIndices> {
+        let num_transactions = self
+            .batches
+            .iter()
+            .flat_map(|(_, batches)| batches)
+            .map(|batch| batch.transactions().len())
+            .sum::<usize>();
+        (num_transactions > 0).then(|| ExecutionIndices {
+            last_committed_round: self.sub_dag.leader_round(),
+            sub_dag_index: self.sub_dag.sub_dag_index,
+            transaction_index: num_transactions as SequenceNumber - 1,
+        })
+    }
+
+    /// The execution indices acknowledging all the transactions of the sub dag.
+    pub fn end_indices(&self) -> ExecutionIndices {
+        ExecutionIndices::end_of_sub_dag(self.sub_dag.leader_round(), self.sub_dag.sub_dag_index)
+    }
+}
+
+/// A transaction of the consensus output, with its position and the metadata of its commit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConsensusTransaction {
+    /// The position of the transaction, to acknowledge its execution.
+    pub indices: ExecutionIndices,
+    /// The author of the certificate including the batch of the transaction.
+    pub certificate_origin: AuthorityIdentifier,
+    /// The batch of the transaction.
+    pub batch_digest: BatchDigest,
+    /// The timestamp of the commit of the sub dag.
+    pub commit_timestamp: TimestampMs,
+    pub transaction: Transaction,
+}
+
+/// The position of a transaction in the consensus output. The executor persists the indices
+/// acknowledged by the execution state, to not deliver twice the same transaction despite
+/// crash-recovery.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, Copy)]
+pub struct ExecutionIndices {
+    /// The round number of the last committed leader.
+    pub last_committed_round: Round,
+    /// The index of the last sub-DAG that was executed (either fully or partially).
+    pub sub_dag_index: SequenceNumber,
+    /// The index of the last transaction executed in the sub-DAG, or `SequenceNumber::MAX` once
+    /// all its transactions are executed.
+    pub transaction_index: SequenceNumber,
+}
+
+impl ExecutionIndices {
+    pub fn end_for_commit(commit_round: u64) -> Self {
+        ExecutionIndices {
+            last_committed_round: commit_round,
+            sub_dag_index: SequenceNumber::MAX,
+            transaction_index: SequenceNumber::MAX,
+        }
+    }
+
+    pub fn end_of_sub_dag(last_committed_round: Round, sub_dag_index: SequenceNumber) -> Self {
+        ExecutionIndices {
+            last_committed_round,
+            sub_dag_index,
+            transaction_index: SequenceNumber::MAX,
+        }
+    }
+
+    /// Whether all the transactions of the sub-DAG are executed.
+    pub fn is_end_of_sub_dag(&self) -> bool {
+        self.transaction_index == SequenceNumber::MAX
+    }
+}
+
+impl Ord for ExecutionIndices {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (
+            self.last_committed_round,
+            self.sub_dag_index,
+            self.transaction_index,
+        )
+            .cmp(&(
+                other.last_committed_round,
+                other.sub_dag_index,
+                other.transaction_index,
+            ))
+    }
+}
+
+impl PartialOrd for ExecutionIndices {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -118,6 +243,104 @@ impl CommittedSubDag {
         }
         self.commit_timestamp
     }
+
+    /// The randomness beacon of this commit, derived from the sub dag itself so it does not need
+    /// to be persisted. None if the parents of the leader miss the share of one of the
+    /// authorities it combines. The beacon is only trusted once checked with `verify_beacon`.
+    pub fn beacon(&self, committee: &Committee) -> Option<RandomnessBeacon> {
+        RandomnessBeacon::shares(committee, &self.leader, &self.certificates)
+            .map(|shares| RandomnessBeacon::combine(&self.leader, &shares))
+    }
+
+    /// Verifies the randomness beacon against the public keys of the committee: each share it
+    /// combines must be computed by its author.
+    pub fn verify_beacon(&self, committee: &Committee) -> DagResult<RandomnessBeacon> {
+        let error = DagError::InvalidRandomnessBeacon(self.sub_dag_index);
+        let shares = RandomnessBeacon::shares(committee, &self.leader, &self.certificates)
+            .ok_or_else(|| error.clone())?;
+
+        for certificate in &shares {
+            let authority = committee
+                .authority(&certificate.origin())
+                .ok_or_else(|| error.clone())?;
+            let message = randomness_message(certificate.epoch(), certificate.round());
+            let share = certificate.header().randomness_share().unwrap();
+            ensure!(share.verify(&message, authority.protocol_key()), error);
+        }
+        Ok(RandomnessBeacon::combine(&self.leader, &shares))
+    }
+}
+
+/// An unpredictable value committed with a sub dag, combining the randomness shares of a set of
+/// authorities among the parents of the leader. Every header but the genesis ones carries the
+/// share of its author, the output of its verifiable random function over the epoch and round,
+/// which is unique for an authority and a round.
+///
+/// The authorities whose shares are combined are fixed by the committee and the round of the
+/// leader, and hold at least f+1 stake, so at least one of them is honest. Neither the leader nor
+/// any other authority picks them, so the beacon cannot be ground by choosing the parents of the
+/// leader: leaving one of the shares out only leaves the commit without a beacon.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RandomnessBeacon(Digest);
+
+impl RandomnessBeacon {
+    pub fn value(&self) -> Digest {
+        self.0
+    }
+
+    /// The authorities whose shares the beacon of a leader of `round` combines: the shortest run
+    /// of authorities by index holding f+1 stake, starting from the authority of index `round`
+    /// modulo the size of the committee, so the set rotates with the rounds.
+    fn authorities(committee: &Committee, round: Round) -> Vec<AuthorityIdentifier> {
+        let ids: Vec<_> = committee
+            .authorities()
+            .map(|authority| authority.id())
+            .collect();
+        let start = round as usize % ids.len();
+
+        let mut authorities = Vec::new();
+        let mut stake: Stake = 0;
+        for id in ids[start..].iter().chain(&ids[..start]) {
+            if stake >= committee.validity_threshold() {
+                break;
+            }
+            authorities.push(*id);
+            stake += committee.stake_by_id(*id);
+        }
+        authorities
+    }
+
+    /// The parents of the leader carrying the shares of the authorities combined by the beacon,
+    /// in their order, None if one of them is missing.
+    fn shares<'a>(
+        committee: &Committee,
+        leader: &Certificate,
+        certificates: &'a [Certificate],
+    ) -> Option<Vec<&'a Certificate>> {
+        let parents = leader.header().parents();
+        let by_author: BTreeMap<_, _> = certificates
+            .iter()
+            .filter(|certificate| certificate.header().randomness_share().is_some())
+            .filter(|certificate| parents.contains(&certificate.digest()))
+            .map(|certificate| (certificate.origin(), certificate))
+            .collect();
+        Self::authorities(committee, leader.round())
+            .iter()
+            .map(|id| by_author.get(id).copied())
+            .collect()
+    }
+
+    /// Hashes the shares of the given parents of the leader, in their order.
+    fn combine(leader: &Certificate, shares: &[&Certificate]) -> Self {
+        let mut hasher = crypto::DefaultHashFunction::new();
+        hasher.update(leader.epoch().to_le_bytes());
+        hasher.update(leader.round().to_le_bytes());
+        for certificate in shares {
+            let share = certificate.header().randomness_share().unwrap();
+            hasher.update(share.output());
+        }
+        Self(hasher.finalize())
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
@@ -172,7 +395,7 @@ trait ConsensusCommitAPI {
 
 // TODO: remove once the upgrade has been rolled out. We want to keep only the
 // CommittedSubDag
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
 pub struct CommittedSubDagShell {
     /// The sequence of committed certificates' digests.
     pub certificates: Vec<CertificateDigest>,
@@ -214,7 +437,7 @@ impl ConsensusCommitAPI for CommittedSubDagShell {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
 pub struct ConsensusCommitV2 {
     /// The sequence of committed certificates' digests.
     pub certificates: Vec<CertificateDigest>,
@@ -270,7 +493,7 @@ impl ConsensusCommitAPI for ConsensusCommitV2 {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
 #[enum_dispatch(ConsensusCommitAPI)]
 pub enum ConsensusCommit {
     V1(CommittedSubDagShell),
@@ -319,6 +542,31 @@ impl ConsensusCommit {
             ConsensusCommit::V2(sub_dag) => sub_dag.commit_timestamp(),
         }
     }
+
+    /// The digest of the commit. Every authority commits the same sub dag at a given index, so
+    /// the digests let a primary check that its peers agree on a sub dag without fetching it.
+    pub fn digest(&self) -> Digest {
+        let mut hasher = crypto::DefaultHashFunction::new();
+        hasher.update(self.sub_dag_index().to_le_bytes());
+        hasher.update(self.leader_round().to_le_bytes());
+        hasher.update(Digest::from(self.leader()));
+        for certificate in self.certificates() {
+            hasher.update(Digest::from(certificate));
+        }
+        // The scores are hashed by authority, not in the order of the map.
+        let reputation_score = self.reputation_score();
+        let scores = reputation_score
+            .scores_per_authority
+            .iter()
+            .collect::<BTreeMap<_, _>>();
+        for (authority, score) in scores {
+            hasher.update(authority.0.to_le_bytes());
+            hasher.update(score.to_le_bytes());
+        }
+        hasher.update([reputation_score.final_of_schedule as u8]);
+        hasher.update(self.commit_timestamp().to_le_bytes());
+        hasher.finalize()
+    }
 }
 
 impl CommittedSubDagShell {
@@ -338,9 +586,10 @@ pub type ShutdownToken = mpsc::Sender<()>;
 
 #[cfg(test)]
 mod tests {
-    use crate::{Certificate, Header, HeaderV1Builder};
-    use crate::{CommittedSubDag, ReputationScores};
+    use crate::{Certificate, CertificateAPI, Header, HeaderAPI, HeaderV2Builder, Round};
+    use crate::{CommittedSubDag, ConsensusCommit, ConsensusCommitV2, ReputationScores};
     use config::AuthorityIdentifier;
+    use crypto::Hash;
     use indexmap::IndexMap;
     use std::collections::BTreeSet;
     use test_utils::CommitteeFixture;
@@ -350,7 +599,7 @@ mod tests {
         let fixture = CommitteeFixture::builder().build();
         let committee = fixture.committee();
 
-        let header_builder = HeaderV1Builder::default();
+        let header_builder = HeaderV2Builder::default();
         let header = header_builder
             .author(AuthorityIdentifier(1u16))
             .round(2)
@@ -361,7 +610,7 @@ mod tests {
             .build();
 
         let certificate =
-            Certificate::new_unsigned(&committee, Header::V1(header), Vec::new()).unwrap();
+            Certificate::new_unsigned(&committee, Header::V2(header), Vec::new()).unwrap();
 
         // AND we initialise the sub dag via the "restore" way
         let sub_dag_round = CommittedSubDag {
@@ -385,7 +634,7 @@ mod tests {
         let fixture = CommitteeFixture::builder().build();
         let committee = fixture.committee();
 
-        let header_builder = HeaderV1Builder::default();
+        let header_builder = HeaderV2Builder::default();
         let header = header_builder
             .author(AuthorityIdentifier(1u16))
             .round(2)
@@ -396,7 +645,7 @@ mod tests {
             .build();
 
         let certificate =
-            Certificate::new_unsigned(&committee, Header::V1(header), Vec::new()).unwrap();
+            Certificate::new_unsigned(&committee, Header::V2(header), Vec::new()).unwrap();
 
         // AND
         let sub_dag_round_2 = CommittedSubDag::new(
@@ -411,7 +660,7 @@ mod tests {
         assert_eq!(sub_dag_round_2.commit_timestamp, newer_timestamp);
 
         // Now create the leader of round 4 with the older timestamp
-        let header_builder = HeaderV1Builder::default();
+        let header_builder = HeaderV2Builder::default();
         let header = header_builder
             .author(AuthorityIdentifier(1u16))
             .round(4)
@@ -422,7 +671,7 @@ mod tests {
             .build();
 
         let certificate =
-            Certificate::new_unsigned(&committee, Header::V1(header), Vec::new()).unwrap();
+            Certificate::new_unsigned(&committee, Header::V2(header), Vec::new()).unwrap();
 
         // WHEN create the sub dag based on the "previously committed" sub dag.
         let sub_dag_round_4 = CommittedSubDag::new(
@@ -440,4 +689,99 @@ mod tests {
             sub_dag_round_2.commit_timestamp
         );
     }
+
+    #[test]
+    fn test_randomness_beacon() {
+        let fixture = CommitteeFixture::builder().build();
+        let committee = fixture.committee();
+        let authorities = fixture.authorities().collect::<Vec<_>>();
+
+        // The certificates of round 1, where the second authority computed its share with the key
+        // of the first one.
+        let parents = authorities
+            .iter()
+            .enumerate()
+            .map(|(i, authority)| {
+                let signer = if i == 1 { authorities[0] } else { authority };
+                let header = authority
+                    .header_builder(&committee)
+                    .payload(IndexMap::new())
+                    .signed(signer.keypair().private())
+                    .build();
+                fixture.certificate(&Header::V2(header))
+            })
+            .collect::<Vec<_>>();
+
+        let sub_dag = |round: Round, parents: &[Certificate]| {
+            let header = authorities[0]
+                .header_builder(&committee)
+                .round(round)
+                .payload(IndexMap::new())
+                .parents(parents.iter().map(|x| x.digest()).collect())
+                .signed(authorities[0].keypair().private())
+                .build();
+            let leader = fixture.certificate(&Header::V2(header));
+            let mut certificates = parents.to_vec();
+            certificates.push(leader.clone());
+            CommittedSubDag::new(certificates, leader, 1, ReputationScores::default(), None)
+        };
+
+        // A leader of round 2 combines the shares of the third and fourth authorities, which hold
+        // f+1 stake.
+        let valid = sub_dag(2, &parents);
+        let beacon = valid.verify_beacon(&committee).unwrap();
+        assert_eq!(valid.beacon(&committee), Some(beacon));
+
+        // The beacon is the same once the sub dag is restored from its commit.
+        let commit = ConsensusCommit::V2(ConsensusCommitV2::from_sub_dag(&valid));
+        let restored =
+            CommittedSubDag::from_commit(commit, valid.certificates.clone(), valid.leader.clone());
+        assert_eq!(restored.beacon(&committee), Some(beacon));
+
+        // The other parents picked by the leader do not change the beacon.
+        assert_eq!(
+            sub_dag(2, &parents[2..]).verify_beacon(&committee).unwrap(),
+            beacon
+        );
+
+        // Leaving one of the combined shares out leaves the commit without a beacon.
+        let missing = sub_dag(2, &parents[..3]);
+        assert_eq!(missing.beacon(&committee), None);
+        assert!(missing.verify_beacon(&committee).is_err());
+
+        // The shares must be computed by their author: a leader of round 5 combines the shares of
+        // the second and third authorities.
+        let invalid = sub_dag(5, &parents);
+        assert!(invalid.beacon(&committee).is_some());
+        assert!(invalid.verify_beacon(&committee).is_err());
+
+        // A share is unique for an author and a round: another header of the third authority
+        // leads to the same beacon.
+        let header = authorities[2]
+            .header_builder(&committee)
+            .payload(IndexMap::new())
+            .created_at(1)
+            .signed(authorities[2].keypair().private())
+            .build();
+        let other = sub_dag(
+            2,
+            &[fixture.certificate(&Header::V2(header)), parents[3].clone()],
+        );
+        assert_ne!(
+            other.certificates[0].digest(),
+            valid.certificates[2].digest()
+        );
+        assert_eq!(other.verify_beacon(&committee).unwrap(), beacon);
+
+        // The headers without a share computed by their author are rejected.
+        let header = authorities[2]
+            .header_builder(&committee)
+            .payload(IndexMap::new())
+            .build();
+        let unshared = sub_dag(
+            2,
+            &[fixture.certificate(&Header::V2(header)), parents[3].clone()],
+        );
+        assert!(unshared.verify_beacon(&committee).is_err());
+    }
 }
@@ -1,7 +1,7 @@
 // Copyright (c) 2021, Facebook, Inc. and its affiliates
 // Copyright (c) Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
-use crate::{CertificateDigest, HeaderDigest, Round, TimestampMs, VoteDigest};
+use crate::{CertificateDigest, HeaderDigest, Round, SequenceNumber, TimestampMs, VoteDigest};
 use anemo::PeerId;
 use config::Epoch;
 use crypto::Digest;
@@ -64,6 +64,18 @@ pub enum DagError {
     #[error("Header {0} has more than one parent certificate with the same authority")]
     HeaderHasDuplicateParentAuthorities(HeaderDigest),
 
+    #[error("Header {0} has weak links that are not older than its parents")]
+    HeaderHasInvalidWeakLinks(HeaderDigest),
+
+    #[error("Header {0} has too many weak links")]
+    HeaderHasTooManyWeakLinks(HeaderDigest),
+
+    #[error("Header {0} has an invalid randomness share")]
+    InvalidRandomnessShare(HeaderDigest),
+
+    #[error("Invalid randomness beacon for sub dag {0}")]
+    InvalidRandomnessBeacon(SequenceNumber),
+
     #[error("Received message from unknown authority {0}")]
     UnknownAuthority(String),
 
@@ -118,6 +130,9 @@ pub enum DagError {
     #[error("Too many certificates in the FetchCertificatesResponse {0} > {1}")]
     TooManyFetchedCertificatesReturned(usize, usize),
 
+    #[error("Invalid consensus snapshot: {0}")]
+    InvalidConsensusSnapshot(String),
+
     #[error("Network error: {0}")]
     NetworkError(String),
 
@@ -16,6 +16,9 @@ pub use primary::*;
 mod proto;
 pub use proto::*;
 
+mod reconfiguration;
+pub use reconfiguration::*;
+
 mod worker;
 pub use worker::*;
 
@@ -4,14 +4,17 @@
 use crate::{
     error::{DagError, DagResult},
     serde::NarwhalBitmap,
-    CertificateDigestProto,
+    CertificateDigestProto, ConsensusCommit, SequenceNumber, SystemTransaction,
 };
 use bytes::Bytes;
-use config::{AuthorityIdentifier, Committee, Epoch, Stake, WorkerCache, WorkerId, WorkerInfo};
+use config::{
+    AuthorityIdentifier, Committee, Epoch, ProtocolVersion, Stake, WorkerCache, WorkerId,
+    WorkerInfo,
+};
 use crypto::{
     to_intent_message, AggregateSignature, Digest, Hash, NarwhalAuthorityAggregateSignature,
     NarwhalAuthoritySignature, NetworkPublicKey, PrivateKey, PublicKey, Signature,
-    SignatureService,
+    SignatureService, VrfProof,
 };
 use dag::node_dag::Affiliated;
 use derive_builder::Builder;
@@ -209,6 +212,7 @@ impl Hash for BatchV1 {
 #[enum_dispatch(HeaderAPI)]
 pub enum Header {
     V1(HeaderV1),
+    V2(HeaderV2),
 }
 
 impl Header {
@@ -219,13 +223,20 @@ impl Header {
         epoch: Epoch,
         payload: IndexMap<BatchDigest, (WorkerId, TimestampMs)>,
         parents: BTreeSet<CertificateDigest>,
+        weak_links: BTreeMap<CertificateDigest, Round>,
+        signature_service: &SignatureService,
     ) -> Self {
-        Header::V1(HeaderV1 {
+        let randomness_share = signature_service
+            .request_vrf_proof(randomness_message(epoch, round))
+            .await;
+        Header::V2(HeaderV2 {
             author,
             round,
             epoch,
             payload,
             parents,
+            weak_links,
+            randomness_share,
             digest: Default::default(),
             created_at: now(),
         })
@@ -234,12 +245,14 @@ impl Header {
     pub fn digest(&self) -> HeaderDigest {
         match self {
             Header::V1(data) => data.digest(),
+            Header::V2(data) => data.digest(),
         }
     }
 
     pub fn validate(&self, committee: &Committee, worker_cache: &WorkerCache) -> DagResult<()> {
         match self {
             Header::V1(data) => data.validate(committee, worker_cache),
+            Header::V2(data) => data.validate(committee, worker_cache),
         }
     }
 }
@@ -250,6 +263,7 @@ impl Hash for Header {
     fn digest(&self) -> HeaderDigest {
         match self {
             Header::V1(data) => data.digest(),
+            Header::V2(data) => data.digest(),
         }
     }
 }
@@ -262,6 +276,8 @@ pub trait HeaderAPI {
     fn created_at(&self) -> &TimestampMs;
     fn payload(&self) -> &IndexMap<BatchDigest, (WorkerId, TimestampMs)>;
     fn parents(&self) -> &BTreeSet<CertificateDigest>;
+    fn weak_links(&self) -> &BTreeMap<CertificateDigest, Round>;
+    fn randomness_share(&self) -> Option<&VrfProof>;
 
     // Used for testing.
     fn update_payload(&mut self, new_payload: IndexMap<BatchDigest, (WorkerId, TimestampMs)>);
@@ -269,6 +285,13 @@ pub trait HeaderAPI {
     fn clear_parents(&mut self);
 }
 
+/// The maximum number of weak links a header can carry. Headers with more are rejected, whatever
+/// the `max_header_num_of_weak_links` parameter of the receiving node.
+pub const MAX_HEADER_NUM_OF_WEAK_LINKS: usize = 100;
+
+/// The weak links of the headers which cannot carry any.
+static NO_WEAK_LINKS: BTreeMap<CertificateDigest, Round> = BTreeMap::new();
+
 #[derive(Builder, Clone, Deserialize, Serialize)]
 #[builder(pattern = "owned", build_fn(skip))]
 pub struct HeaderV1 {
@@ -387,6 +410,12 @@ impl HeaderAPI for HeaderV1 {
     fn parents(&self) -> &BTreeSet<CertificateDigest> {
         &self.parents
     }
+    fn weak_links(&self) -> &BTreeMap<CertificateDigest, Round> {
+        &NO_WEAK_LINKS
+    }
+    fn randomness_share(&self) -> Option<&VrfProof> {
+        None
+    }
 
     // Used for testing.
     fn update_payload(&mut self, new_payload: IndexMap<BatchDigest, (WorkerId, TimestampMs)>) {
@@ -508,6 +537,284 @@ impl HeaderV1 {
             DagError::InvalidHeaderDigest
         );
 
+        // Once the committee requires the randomness shares, only the genesis headers are still
+        // version 1.
+        ensure!(
+            self.round == 0 || committee.protocol_version() < RANDOMNESS_PROTOCOL_VERSION,
+            DagError::InvalidRandomnessShare(self.digest())
+        );
+
+        // Ensure the authority has voting rights.
+        let voting_rights = committee.stake_by_id(self.author);
+        ensure!(
+            voting_rights > 0,
+            DagError::UnknownAuthority(self.author.to_string())
+        );
+
+        // Ensure all worker ids are correct.
+        for (worker_id, _) in self.payload.values() {
+            worker_cache
+                .worker(
+                    committee.authority(&self.author).unwrap().protocol_key(),
+                    worker_id,
+                )
+                .map_err(|_| DagError::HeaderHasBadWorkerIds(self.digest()))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Builder, Clone, Deserialize, Serialize)]
+#[builder(pattern = "owned", build_fn(skip))]
+pub struct HeaderV2 {
+    // Primary that created the header. Must be the same primary that broadcasted the header.
+    // Validation is at: https://github.com/MystenLabs/sui/blob/f0b80d9eeef44edd9fbe606cee16717622b68651/narwhal/primary/src/primary.rs#L713-L719
+    pub author: AuthorityIdentifier,
+    pub round: Round,
+    pub epoch: Epoch,
+    pub created_at: TimestampMs,
+    #[serde(with = "indexmap::map::serde_seq")]
+    pub payload: IndexMap<BatchDigest, (WorkerId, TimestampMs)>,
+    pub parents: BTreeSet<CertificateDigest>,
+    // Certificates from rounds older than the parents' that the author has not seen committed,
+    // along with their round. Consensus orders them with the sub-dag of this header, so the
+    // payload of slow authorities is not garbage collected before being committed. At most
+    // `MAX_HEADER_NUM_OF_WEAK_LINKS`.
+    pub weak_links: BTreeMap<CertificateDigest, Round>,
+    // The output of the verifiable random function of the author over the epoch and round,
+    // combined with the shares of other parents of a leader into the randomness beacon of its
+    // commit. See `randomness_message`.
+    #[builder(default)]
+    pub randomness_share: VrfProof,
+    #[serde(skip)]
+    digest: OnceCell<HeaderDigest>,
+    // TODO: Add signature
+}
+
+/// The protocol version from which every header but the genesis ones must carry the randomness
+/// share of its author. The committees running an older version still accept the version 1
+/// headers of the authorities that do not compute shares yet.
+pub const RANDOMNESS_PROTOCOL_VERSION: ProtocolVersion = 1;
+
+/// The message signed by every authority in its header of a round, as its share of the
+/// randomness beacon.
+pub fn randomness_message(epoch: Epoch, round: Round) -> Digest {
+    let mut hasher = crypto::DefaultHashFunction::new();
+    hasher.update(b"randomness");
+    hasher.update(epoch.to_le_bytes());
+    hasher.update(round.to_le_bytes());
+    hasher.finalize()
+}
+
+struct UnsignedHeaderV2 {
+    author: AuthorityIdentifier,
+    round: Round,
+    epoch: Epoch,
+    created_at: TimestampMs,
+    payload: IndexMap<BatchDigest, (WorkerId, TimestampMs)>,
+    parents: BTreeSet<CertificateDigest>,
+    weak_links: BTreeMap<CertificateDigest, Round>,
+    digest: OnceCell<HeaderDigest>,
+}
+
+impl Hash for UnsignedHeaderV2 {
+    type TypedDigest = HeaderDigest;
+
+    fn digest(&self) -> HeaderDigest {
+        let mut hasher = crypto::DefaultHashFunction::new();
+        hasher.update(self.author.0.to_le_bytes());
+        hasher.update(self.round.to_le_bytes());
+        hasher.update(self.epoch.to_le_bytes());
+        hasher.update(self.created_at.to_le_bytes());
+        for (x, (y, z)) in self.payload.iter() {
+            hasher.update(Digest::from(*x));
+            hasher.update(y.to_le_bytes());
+            hasher.update(z.to_le_bytes());
+        }
+        for x in self.parents.iter() {
+            hasher.update(Digest::from(*x))
+        }
+        hasher.update((self.weak_links.len() as u64).to_le_bytes());
+        for (x, round) in self.weak_links.iter() {
+            hasher.update(Digest::from(*x));
+            hasher.update(round.to_le_bytes());
+        }
+        HeaderDigest(hasher.finalize())
+    }
+}
+
+impl HeaderAPI for HeaderV2 {
+    fn author(&self) -> AuthorityIdentifier {
+        self.author
+    }
+    fn round(&self) -> Round {
+        self.round
+    }
+    fn epoch(&self) -> Epoch {
+        self.epoch
+    }
+    fn created_at(&self) -> &TimestampMs {
+        &self.created_at
+    }
+    fn payload(&self) -> &IndexMap<BatchDigest, (WorkerId, TimestampMs)> {
+        &self.payload
+    }
+    fn parents(&self) -> &BTreeSet<CertificateDigest> {
+        &self.parents
+    }
+    fn weak_links(&self) -> &BTreeMap<CertificateDigest, Round> {
+        &self.weak_links
+    }
+    fn randomness_share(&self) -> Option<&VrfProof> {
+        Some(&self.randomness_share)
+    }
+
+    // Used for testing.
+    fn update_payload(&mut self, new_payload: IndexMap<BatchDigest, (WorkerId, TimestampMs)>) {
+        self.payload = new_payload;
+    }
+    fn update_round(&mut self, new_round: Round) {
+        self.round = new_round;
+    }
+    fn clear_parents(&mut self) {
+        self.parents.clear();
+    }
+}
+
+impl HeaderV2Builder {
+    pub fn build(self) -> HeaderV2 {
+        let h = HeaderV2 {
+            author: self.author.unwrap(),
+            round: self.round.unwrap(),
+            epoch: self.epoch.unwrap(),
+            created_at: self.created_at.unwrap_or(now()),
+            payload: self.payload.unwrap(),
+            parents: self.parents.unwrap(),
+            weak_links: self.weak_links.unwrap_or_default(),
+            randomness_share: self.randomness_share.unwrap_or_default(),
+            digest: OnceCell::default(),
+            // TODO: Add signature
+            // signature: self.signature.expect("The header isn't signed"),
+        };
+        h.digest.set(Hash::digest(&h)).unwrap();
+        h
+    }
+
+    /// This should be the last method called on the builder before `build`.
+    pub fn signed(mut self, signer: &PrivateKey) -> Self {
+        let unsigned_header = UnsignedHeaderV2 {
+            author: self.author.unwrap_or_default(),
+            round: self.round.unwrap_or_default(),
+            epoch: self.epoch.unwrap_or_default(),
+            created_at: self.created_at.unwrap_or(now()),
+            payload: self.payload.clone().unwrap_or_default(),
+            parents: self.parents.clone().unwrap_or_default(),
+            weak_links: self.weak_links.clone().unwrap_or_default(),
+            digest: self.digest.unwrap_or_default(),
+        };
+        let digest = Hash::digest(&unsigned_header);
+        unsigned_header.digest.set(digest).unwrap();
+        self.digest = Some(digest.into());
+        let message = randomness_message(unsigned_header.epoch, unsigned_header.round);
+        self.randomness_share = Some(VrfProof::new(&message, signer));
+        // let signature = signer.sign_bytes(digest.0.as_ref(), &mut thread_rng()).expect("Signing failed");
+        // TODO: Add signature
+        // self.signature = Some(signature);
+        self
+    }
+
+    // helper method to set directly values to the payload
+    pub fn with_payload_batch(
+        mut self,
+        batch: Batch,
+        worker_id: WorkerId,
+        created_at: TimestampMs,
+    ) -> Self {
+        if self.payload.is_none() {
+            self.payload = Some(Default::default());
+        }
+        let payload = self.payload.as_mut().unwrap();
+
+        payload.insert(batch.digest(), (worker_id, created_at));
+
+        self
+    }
+}
+
+impl HeaderV2 {
+    pub async fn new(
+        author: AuthorityIdentifier,
+        round: Round,
+        epoch: Epoch,
+        payload: IndexMap<BatchDigest, (WorkerId, TimestampMs)>,
+        parents: BTreeSet<CertificateDigest>,
+        weak_links: BTreeMap<CertificateDigest, Round>,
+        signature_service: &SignatureService,
+    ) -> Self {
+        let header = UnsignedHeaderV2 {
+            author,
+            round,
+            epoch,
+            created_at: now(),
+            payload,
+            parents,
+            weak_links,
+            digest: OnceCell::default(),
+        };
+        let digest = Hash::digest(&header);
+        header.digest.set(digest).unwrap();
+        // let signature = signer.sign_bytes(digest.0.as_ref(), &mut thread_rng()).expect("Signing failed");
+        let randomness_share = signature_service
+            .request_vrf_proof(randomness_message(epoch, round))
+            .await;
+        Self {
+            author: header.author,
+            round: header.round,
+            epoch: header.epoch,
+            created_at: header.created_at,
+            payload: header.payload,
+            parents: header.parents,
+            weak_links: header.weak_links,
+            randomness_share,
+            digest: header.digest,
+            // TODO: Add signature
+        }
+    }
+
+    pub fn digest(&self) -> HeaderDigest {
+        *self.digest.get_or_init(|| Hash::digest(self))
+    }
+
+    pub fn validate(&self, committee: &Committee, worker_cache: &WorkerCache) -> DagResult<()> {
+        // Ensure the header is from the correct epoch.
+        ensure!(
+            self.epoch == committee.epoch(),
+            DagError::InvalidEpoch {
+                expected: committee.epoch(),
+                received: self.epoch
+            }
+        );
+
+        // Ensure the header digest is well formed.
+        ensure!(
+            Hash::digest(self) == self.digest(),
+            DagError::InvalidHeaderDigest
+        );
+
+        // Ensure the number of weak links is bounded and they point to certificates older than
+        // the parents.
+        ensure!(
+            self.weak_links.len() <= MAX_HEADER_NUM_OF_WEAK_LINKS,
+            DagError::HeaderHasTooManyWeakLinks(self.digest())
+        );
+        ensure!(
+            self.weak_links.iter().all(|(digest, round)| {
+                round + 1 < self.round && !self.parents.contains(digest)
+            }),
+            DagError::HeaderHasInvalidWeakLinks(self.digest())
+        );
+
         // Ensure the authority has voting rights.
         let voting_rights = committee.stake_by_id(self.author);
         ensure!(
@@ -515,6 +822,16 @@ impl HeaderV1 {
             DagError::UnknownAuthority(self.author.to_string())
         );
 
+        // Ensure the randomness share is computed by the author over the epoch and round.
+        let message = randomness_message(self.epoch, self.round);
+        ensure!(
+            self.randomness_share.verify(
+                &message,
+                committee.authority(&self.author).unwrap().protocol_key()
+            ),
+            DagError::InvalidRandomnessShare(self.digest())
+        );
+
         // Ensure all worker ids are correct.
         for (worker_id, _) in self.payload.values() {
             worker_cache
@@ -566,6 +883,16 @@ impl Hash for HeaderV1 {
     }
 }
 
+impl Hash for HeaderV2 {
+    type TypedDigest = HeaderDigest;
+
+    fn digest(&self) -> HeaderDigest {
+        let mut hasher = crypto::DefaultHashFunction::new();
+        hasher.update(bcs::to_bytes(&self).expect("Serialization should not fail"));
+        HeaderDigest(hasher.finalize())
+    }
+}
+
 impl fmt::Debug for Header {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
         match self {
@@ -580,6 +907,17 @@ impl fmt::Debug for Header {
                     data.payload.keys().map(|_| Digest::size()).sum::<usize>(),
                 )
             }
+            Self::V2(data) => {
+                write!(
+                    f,
+                    "{}: B{}({}, E{}, {}B)",
+                    data.digest.get().cloned().unwrap_or_default(),
+                    data.round,
+                    data.author,
+                    data.epoch,
+                    data.payload.keys().map(|_| Digest::size()).sum::<usize>(),
+                )
+            }
         }
     }
 }
@@ -590,6 +928,9 @@ impl fmt::Display for Header {
             Self::V1(data) => {
                 write!(f, "B{}({})", data.round, data.author)
             }
+            Self::V2(data) => {
+                write!(f, "B{}({})", data.round, data.author)
+            }
         }
     }
 }
@@ -598,6 +939,7 @@ impl PartialEq for Header {
     fn eq(&self, other: &Self) -> bool {
         match self {
             Self::V1(data) => data.digest() == other.digest(),
+            Self::V2(data) => data.digest() == other.digest(),
         }
     }
 }
@@ -947,6 +1289,7 @@ impl CertificateV1 {
                     created_at: Default::default(),
                     payload: Default::default(),
                     parents: Default::default(),
+                    weak_links: Default::default(),
                     digest: Default::default(),
                 };
                 Self {
@@ -1352,6 +1695,46 @@ pub struct FetchCertificatesResponse {
     pub certificates: Vec<Certificate>,
 }
 
+/// Used by a primary with an empty store to request the consensus state of its peers, instead
+/// of fetching the whole DAG from the first round. The primary first requests the digests of the
+/// recent sub dags from every peer, then the snapshot of a sub dag that f+1 of them agree on from
+/// a single peer.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct FetchConsensusSnapshotRequest {
+    /// The index of the last sub dag of the requested snapshot, or None to request the digests of
+    /// the recent sub dags.
+    pub sub_dag_index: Option<SequenceNumber>,
+    /// The maximum number of certificates of the snapshot.
+    pub max_items: usize,
+}
+
+/// The digest of a committed sub dag, along with its index and the round of its leader.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Eq, PartialEq, Hash)]
+pub struct SubDagDigest {
+    pub sub_dag_index: SequenceNumber,
+    pub leader_round: Round,
+    pub digest: Digest,
+}
+
+/// Used by the primary to reply to FetchConsensusSnapshotRequest.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct FetchConsensusSnapshotResponse {
+    /// The digests of the recent sub dags, sorted by index, in reply to a request without sub dag
+    /// index. They go back far enough to cover the snapshot of any of them. Empty if nothing has
+    /// been committed yet.
+    pub sub_dag_digests: Vec<SubDagDigest>,
+    /// The sub dags up to the requested one committed with a leader above its GC round, sorted by
+    /// index.
+    pub sub_dags: Vec<ConsensusCommit>,
+    /// The certificates of the sub dags above and the certificates above the GC round of the
+    /// requested sub dag, sorted from lower to higher rounds.
+    pub certificates: Vec<Certificate>,
+    /// The committee changes found in the consensus output up to the requested sub dag, by the
+    /// index of the sub dag they were found in. The syncing primary does not execute the sub dags
+    /// before the snapshot, so it learns them from here.
+    pub committee_changes: Vec<(SequenceNumber, SystemTransaction)>,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
 pub struct PayloadAvailabilityRequest {
     pub certificate_digests: Vec<CertificateDigest>,
@@ -1390,7 +1773,8 @@ pub struct FetchBatchesRequest {
     pub known_workers: HashSet<NetworkPublicKey>,
 }
 
-/// All batches requested by the primary.
+/// The batches requested by the primary that the worker could fetch. The batches no known
+/// worker returned are missing.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct FetchBatchesResponse {
     pub batches: HashMap<BatchDigest, Batch>,
@@ -1402,6 +1786,20 @@ pub struct WorkerDeleteBatchesMessage {
     pub digests: Vec<BatchDigest>,
 }
 
+/// Used by the primary to inform its workers of the rounds reached by consensus, and of the
+/// batches of the worker that are committed or still needed by the proposer.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct WorkerRoundUpdateMessage {
+    /// The last round committed by consensus.
+    pub committed_round: Round,
+    /// The round below which consensus no longer accepts certificates.
+    pub gc_round: Round,
+    /// The batches of the worker committed since the previous update, up to `committed_round`.
+    pub committed_batches: Vec<BatchDigest>,
+    /// The batches of the worker that our proposer has yet to include in a header or to commit.
+    pub pending_batches: Vec<BatchDigest>,
+}
+
 #[derive(Clone, Default, Debug, Eq, PartialEq)]
 pub struct BatchMessage {
     // TODO: revisit including the digest here [see #188]
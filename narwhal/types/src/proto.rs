@@ -36,10 +36,11 @@ pub use narwhal::{
     worker_to_worker_client::WorkerToWorkerClient,
     worker_to_worker_server::{MockWorkerToWorker, WorkerToWorker, WorkerToWorkerServer},
     CertificateDigest as CertificateDigestProto, Collection, CollectionError,
-    CollectionRetrievalResult, Empty, GetCollectionsRequest, GetCollectionsResponse,
-    GetPrimaryAddressResponse, MultiAddr as MultiAddrProto, NewEpochRequest, NewNetworkInfoRequest,
-    NodeReadCausalRequest, NodeReadCausalResponse, PublicKey as PublicKeyProto, ReadCausalRequest,
-    ReadCausalResponse, RemoveCollectionsRequest, RoundsRequest, RoundsResponse,
+    CollectionRetrievalResult, CommittedSubDag as CommittedSubDagProto, Empty,
+    GetCollectionsRequest, GetCollectionsResponse, GetPrimaryAddressResponse,
+    MultiAddr as MultiAddrProto, NewEpochRequest, NewNetworkInfoRequest, NodeReadCausalRequest,
+    NodeReadCausalResponse, PublicKey as PublicKeyProto, ReadCausalRequest, ReadCausalResponse,
+    ReadCommittedSubDagsRequest, RemoveCollectionsRequest, RoundsRequest, RoundsResponse,
     Transaction as TransactionProto, ValidatorData,
 };
 
@@ -0,0 +1,294 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use crate::{SequenceNumber, Transaction};
+use config::{
+    AuthorityIdentifier, Committee, CommitteeBuilder, Epoch, Stake, WorkerCache, WorkerIndex,
+};
+use crypto::{to_intent_message, Digest, NarwhalAuthoritySignature, NetworkPublicKey, PublicKey};
+use crypto::{PrivateKey, Signature};
+use mysten_network::Multiaddr;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use thiserror::Error;
+
+/// The prefix telling the system transactions apart from the transactions of the clients.
+pub const SYSTEM_TRANSACTION_PREFIX: &[u8] = b"\0narwhal::system\0";
+
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+pub enum ReconfigurationError {
+    #[error("The committee change is for epoch {received}, the current epoch is {expected}")]
+    InvalidEpoch { expected: Epoch, received: Epoch },
+
+    #[error("Authority {0} signed the committee change but is not in the committee")]
+    UnknownSigner(AuthorityIdentifier),
+
+    #[error("Authority {0} signed the committee change twice")]
+    DuplicateSigner(AuthorityIdentifier),
+
+    #[error("Invalid signature of authority {0} over the committee change")]
+    InvalidSignature(AuthorityIdentifier),
+
+    #[error("The committee change is signed by {0} stake, short of a quorum")]
+    NoQuorum(Stake),
+
+    #[error("Authority {0} is already in the committee")]
+    AlreadyInCommittee(String),
+
+    #[error("Authority {0} is not in the committee")]
+    NotInCommittee(String),
+
+    #[error("Authority {0} would have no stake")]
+    ZeroStake(String),
+
+    #[error("The committee would have no authority")]
+    EmptyCommittee,
+
+    #[error("The committee change is scheduled at sub dag {scheduled}, not after sub dag {found}")]
+    ScheduledTooEarly {
+        found: SequenceNumber,
+        scheduled: SequenceNumber,
+    },
+}
+
+/// A change to the authorities of the committee, or to their workers.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum CommitteeChange {
+    AddAuthority {
+        protocol_key: PublicKey,
+        stake: Stake,
+        primary_address: Multiaddr,
+        network_key: NetworkPublicKey,
+        workers: WorkerIndex,
+    },
+    RemoveAuthority {
+        protocol_key: PublicKey,
+    },
+    UpdateStake {
+        protocol_key: PublicKey,
+        stake: Stake,
+    },
+    UpdateWorkers {
+        protocol_key: PublicKey,
+        workers: WorkerIndex,
+    },
+}
+
+/// Changes to the committee of `epoch`, taking effect after the sub dag `sub_dag_index`. Narwhal
+/// does not switch committees by itself: the next committee is published once the sub dag is
+/// executed, and the nodes are then restarted with it. The sub dags the current committee commits
+/// in the meantime are still delivered, it is up to the execution state to discard them.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ScheduledCommitteeChange {
+    pub epoch: Epoch,
+    /// The index of the last sub dag committed by the current committee.
+    pub sub_dag_index: SequenceNumber,
+    pub changes: Vec<CommitteeChange>,
+}
+
+/// The committee and the workers of the next epoch, taking over after the sub dag
+/// `sub_dag_index`.
+#[derive(Clone, Debug)]
+pub struct Reconfiguration {
+    pub sub_dag_index: SequenceNumber,
+    pub committee: Committee,
+    pub worker_cache: WorkerCache,
+}
+
+impl ScheduledCommitteeChange {
+    /// The digest signed by the authorities approving the change.
+    pub fn digest(&self) -> Digest {
+        crypto::DefaultHashFunction::digest(
+            bcs::to_bytes(self).expect("Serialization should not fail"),
+        )
+    }
+
+    /// Signs the change on behalf of an authority of the current committee.
+    pub fn sign(&self, signer: &PrivateKey) -> Signature {
+        Signature::new_secure(&to_intent_message(self.digest()), signer)
+    }
+
+    /// Applies the changes to the committee and the worker cache of the current epoch, returning
+    /// those of the next epoch.
+    pub fn apply(
+        &self,
+        committee: &Committee,
+        worker_cache: &WorkerCache,
+    ) -> Result<Reconfiguration, ReconfigurationError> {
+        if self.epoch != committee.epoch() {
+            return Err(ReconfigurationError::InvalidEpoch {
+                expected: committee.epoch(),
+                received: self.epoch,
+            });
+        }
+
+        let mut authorities = committee
+            .authorities()
+            .map(|authority| {
+                (
+                    authority.protocol_key().clone(),
+                    (
+                        authority.stake(),
+                        authority.primary_address(),
+                        authority.network_key(),
+                    ),
+                )
+            })
+            .collect::<BTreeMap<_, _>>();
+        let mut workers = worker_cache.workers.clone();
+
+        for change in &self.changes {
+            match change {
+                CommitteeChange::AddAuthority {
+                    protocol_key,
+                    stake,
+                    primary_address,
+                    network_key,
+                    workers: index,
+                } => {
+                    if authorities.contains_key(protocol_key) {
+                        return Err(ReconfigurationError::AlreadyInCommittee(
+                            protocol_key.to_string(),
+                        ));
+                    }
+                    authorities.insert(
+                        protocol_key.clone(),
+                        (*stake, primary_address.clone(), network_key.clone()),
+                    );
+                    workers.insert(protocol_key.clone(), index.clone());
+                }
+                CommitteeChange::RemoveAuthority { protocol_key } => {
+                    if authorities.remove(protocol_key).is_none() {
+                        return Err(ReconfigurationError::NotInCommittee(
+                            protocol_key.to_string(),
+                        ));
+                    }
+                    workers.remove(protocol_key);
+                }
+                CommitteeChange::UpdateStake {
+                    protocol_key,
+                    stake,
+                } => {
+                    let (current, _, _) = authorities.get_mut(protocol_key).ok_or_else(|| {
+                        ReconfigurationError::NotInCommittee(protocol_key.to_string())
+                    })?;
+                    *current = *stake;
+                }
+                CommitteeChange::UpdateWorkers {
+                    protocol_key,
+                    workers: index,
+                } => {
+                    if !authorities.contains_key(protocol_key) {
+                        return Err(ReconfigurationError::NotInCommittee(
+                            protocol_key.to_string(),
+                        ));
+                    }
+                    workers.insert(protocol_key.clone(), index.clone());
+                }
+            }
+        }
+
+        if authorities.is_empty() {
+            return Err(ReconfigurationError::EmptyCommittee);
+        }
+        if let Some((protocol_key, _)) = authorities.iter().find(|(_, (stake, _, _))| *stake == 0) {
+            return Err(ReconfigurationError::ZeroStake(protocol_key.to_string()));
+        }
+
+        let epoch = committee.epoch() + 1;
+        let committee = authorities
+            .into_iter()
+            .fold(
+                CommitteeBuilder::new(epoch).protocol_version(committee.protocol_version()),
+                |builder, (protocol_key, (stake, primary_address, network_key))| {
+                    builder.add_authority(protocol_key, stake, primary_address, network_key)
+                },
+            )
+            .build();
+
+        Ok(Reconfiguration {
+            sub_dag_index: self.sub_dag_index,
+            committee,
+            worker_cache: WorkerCache { workers, epoch },
+        })
+    }
+}
+
+/// A transaction interpreted by Narwhal itself rather than by the execution state. It is sequenced
+/// like the transactions of the clients, with the `SYSTEM_TRANSACTION_PREFIX`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SystemTransaction {
+    /// Schedules a change of the committee, signed by a quorum of the current committee.
+    ScheduleCommitteeChange {
+        change: ScheduledCommitteeChange,
+        signatures: Vec<(AuthorityIdentifier, Signature)>,
+    },
+}
+
+impl SystemTransaction {
+    pub fn to_transaction(&self) -> Transaction {
+        let mut transaction = SYSTEM_TRANSACTION_PREFIX.to_vec();
+        transaction.extend(bcs::to_bytes(self).expect("Serialization should not fail"));
+        transaction
+    }
+
+    /// Decodes a system transaction, None for the transactions of the clients.
+    pub fn from_transaction(transaction: &[u8]) -> Option<Self> {
+        let bytes = transaction.strip_prefix(SYSTEM_TRANSACTION_PREFIX)?;
+        bcs::from_bytes(bytes).ok()
+    }
+
+    /// Verifies that the transaction is signed by a quorum of the committee.
+    pub fn verify(&self, committee: &Committee) -> Result<(), ReconfigurationError> {
+        match self {
+            SystemTransaction::ScheduleCommitteeChange { change, signatures } => {
+                if change.epoch != committee.epoch() {
+                    return Err(ReconfigurationError::InvalidEpoch {
+                        expected: committee.epoch(),
+                        received: change.epoch,
+                    });
+                }
+
+                let message = to_intent_message(change.digest());
+                let mut signers = HashSet::new();
+                let mut stake = 0;
+                for (id, signature) in signatures {
+                    let authority = committee
+                        .authority(id)
+                        .ok_or(ReconfigurationError::UnknownSigner(*id))?;
+                    if !signers.insert(*id) {
+                        return Err(ReconfigurationError::DuplicateSigner(*id));
+                    }
+                    if !signature.verify_secure(&message, authority.protocol_key()) {
+                        return Err(ReconfigurationError::InvalidSignature(*id));
+                    }
+                    stake += authority.stake();
+                }
+
+                if !committee.reached_quorum(stake) {
+                    return Err(ReconfigurationError::NoQuorum(stake));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Verifies the transaction found in the sub dag `sub_dag_index`: it must be signed by a
+    /// quorum of the committee, and its change must be scheduled after the sub dag and apply to
+    /// the committee.
+    pub fn verify_scheduled_after(
+        &self,
+        sub_dag_index: SequenceNumber,
+        committee: &Committee,
+        worker_cache: &WorkerCache,
+    ) -> Result<(), ReconfigurationError> {
+        self.verify(committee)?;
+        let SystemTransaction::ScheduleCommitteeChange { change, .. } = self;
+        if change.sub_dag_index <= sub_dag_index {
+            return Err(ReconfigurationError::ScheduledTooEarly {
+                found: sub_dag_index,
+                scheduled: change.sub_dag_index,
+            });
+        }
+        change.apply(committee, worker_cache).map(|_| ())
+    }
+}
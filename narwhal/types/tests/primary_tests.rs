@@ -1,12 +1,15 @@
 // Copyright (c) Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
 use config::{AuthorityIdentifier, Committee, Stake};
-use crypto::{PublicKey, Signature};
+use crypto::{Digest, PublicKey, Signature, SignatureService};
 use indexmap::IndexMap;
-use narwhal_types::{Certificate, Header, HeaderV1, Vote, VoteAPI};
+use narwhal_types::{
+    error::DagError, Certificate, CertificateDigest, Header, HeaderV1Builder, HeaderV2, Vote,
+    VoteAPI, MAX_HEADER_NUM_OF_WEAK_LINKS, RANDOMNESS_PROTOCOL_VERSION,
+};
 use rand::rngs::OsRng;
 use rand::seq::SliceRandom;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::num::NonZeroUsize;
 use test_utils::{AuthorityFixture, CommitteeFixture};
 
@@ -24,7 +27,16 @@ async fn test_certificate_singers_are_ordered() {
     // The authority that creates the Header
     let authority = authorities[0];
 
-    let header = HeaderV1::new(authority.id(), 1, 1, IndexMap::new(), BTreeSet::new()).await;
+    let header = HeaderV2::new(
+        authority.id(),
+        1,
+        1,
+        IndexMap::new(),
+        BTreeSet::new(),
+        BTreeMap::new(),
+        &SignatureService::new(*authority.keypair().private()),
+    )
+    .await;
 
     // WHEN
     let mut votes: Vec<(AuthorityIdentifier, Signature)> = Vec::new();
@@ -35,7 +47,7 @@ async fn test_certificate_singers_are_ordered() {
         sorted_singers.push(authority.keypair().public().clone());
 
         let vote = Vote::new_with_signer(
-            &Header::V1(header.clone()),
+            &Header::V2(header.clone()),
             &authority.id(),
             authority.keypair().private(),
         );
@@ -46,7 +58,7 @@ async fn test_certificate_singers_are_ordered() {
     votes.shuffle(&mut OsRng);
 
     // Create a certificate
-    let certificate = Certificate::new_unverified(&committee, Header::V1(header), votes).unwrap();
+    let certificate = Certificate::new_unverified(&committee, Header::V2(header), votes).unwrap();
 
     let (stake, signers) = certificate.signed_by(&committee);
 
@@ -58,3 +70,105 @@ async fn test_certificate_singers_are_ordered() {
 
     assert_eq!(stake, 9 as Stake);
 }
+
+#[test]
+fn test_header_with_too_many_weak_links_is_rejected() {
+    let fixture = CommitteeFixture::builder().build();
+    let committee = fixture.committee();
+    let worker_cache = fixture.worker_cache();
+    let authority = fixture.authorities().next().unwrap();
+
+    let weak_links = |n: usize| {
+        (0..n)
+            .map(|i| {
+                let mut digest = [0u8; 32];
+                digest[..8].copy_from_slice(&(i as u64).to_le_bytes());
+                (CertificateDigest::new(Digest::new(digest)), 1)
+            })
+            .collect::<BTreeMap<_, _>>()
+    };
+
+    // Up to the limit, the header is valid.
+    let header = Header::V2(
+        authority
+            .header_builder(&committee)
+            .round(3)
+            .payload(IndexMap::new())
+            .weak_links(weak_links(MAX_HEADER_NUM_OF_WEAK_LINKS))
+            .signed(authority.keypair().private())
+            .build(),
+    );
+    assert!(header.validate(&committee, &worker_cache).is_ok());
+
+    // Past the limit, it is rejected.
+    let header = Header::V2(
+        authority
+            .header_builder(&committee)
+            .round(3)
+            .payload(IndexMap::new())
+            .weak_links(weak_links(MAX_HEADER_NUM_OF_WEAK_LINKS + 1))
+            .signed(authority.keypair().private())
+            .build(),
+    );
+    assert!(matches!(
+        header.validate(&committee, &worker_cache),
+        Err(DagError::HeaderHasTooManyWeakLinks(_))
+    ));
+}
+
+#[test]
+fn test_header_without_randomness_share_is_rejected() {
+    let fixture = CommitteeFixture::builder()
+        .protocol_version(RANDOMNESS_PROTOCOL_VERSION)
+        .build();
+    let committee = fixture.committee();
+    let worker_cache = fixture.worker_cache();
+    let authorities = fixture.authorities().collect::<Vec<_>>();
+    let author = authorities[0];
+
+    // The share is computed by the author over the epoch and round of the header.
+    let header = Header::V2(
+        author
+            .header_builder(&committee)
+            .payload(IndexMap::new())
+            .signed(author.keypair().private())
+            .build(),
+    );
+    assert!(header.validate(&committee, &worker_cache).is_ok());
+
+    for builder in [
+        author.header_builder(&committee),
+        author
+            .header_builder(&committee)
+            .signed(authorities[1].keypair().private()),
+    ] {
+        let header = Header::V2(builder.payload(IndexMap::new()).build());
+        assert!(matches!(
+            header.validate(&committee, &worker_cache),
+            Err(DagError::InvalidRandomnessShare(_))
+        ));
+    }
+
+    // Only the genesis headers can be of version 1, without a share.
+    let header = Header::V1(
+        HeaderV1Builder::default()
+            .author(author.id())
+            .round(1)
+            .epoch(committee.epoch())
+            .payload(IndexMap::new())
+            .parents(BTreeSet::new())
+            .build(),
+    );
+    assert!(matches!(
+        header.validate(&committee, &worker_cache),
+        Err(DagError::InvalidRandomnessShare(_))
+    ));
+
+    // Unless the committee runs a protocol version that does not require the shares yet.
+    let fixture = CommitteeFixture::builder()
+        .protocol_version(RANDOMNESS_PROTOCOL_VERSION - 1)
+        .build();
+    assert!(header
+        .validate(&fixture.committee(), &fixture.worker_cache())
+        .is_ok());
+}
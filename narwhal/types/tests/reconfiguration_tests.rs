@@ -0,0 +1,210 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use narwhal_types::{
+    CommitteeChange, ReconfigurationError, ScheduledCommitteeChange, SystemTransaction,
+};
+use std::num::NonZeroUsize;
+use test_utils::CommitteeFixture;
+
+#[test]
+fn test_apply_committee_change() {
+    let fixture = CommitteeFixture::builder().build();
+    let committee = fixture.committee();
+    let worker_cache = fixture.worker_cache();
+    let authorities = fixture.authorities().collect::<Vec<_>>();
+
+    // An authority from another committee joins.
+    let other = CommitteeFixture::builder()
+        .committee_size(NonZeroUsize::new(1).unwrap())
+        .build();
+    let joining = other.authorities().next().unwrap();
+
+    let change = ScheduledCommitteeChange {
+        epoch: committee.epoch(),
+        sub_dag_index: 10,
+        changes: vec![
+            CommitteeChange::AddAuthority {
+                protocol_key: joining.public_key(),
+                stake: 2,
+                primary_address: joining.address().clone(),
+                network_key: joining.network_public_key(),
+                workers: joining.worker_index(),
+            },
+            CommitteeChange::RemoveAuthority {
+                protocol_key: authorities[0].public_key(),
+            },
+            CommitteeChange::UpdateStake {
+                protocol_key: authorities[1].public_key(),
+                stake: 3,
+            },
+            CommitteeChange::UpdateWorkers {
+                protocol_key: authorities[2].public_key(),
+                workers: joining.worker_index(),
+            },
+        ],
+    };
+
+    let reconfiguration = change.apply(&committee, &worker_cache).unwrap();
+    assert_eq!(reconfiguration.sub_dag_index, 10);
+
+    let next = reconfiguration.committee;
+    assert_eq!(next.epoch(), committee.epoch() + 1);
+    assert_eq!(next.size(), committee.size());
+    assert_eq!(next.stake(&joining.public_key()), 2);
+    assert!(next
+        .authority_by_key(&authorities[0].public_key())
+        .is_none());
+    assert_eq!(next.stake(&authorities[1].public_key()), 3);
+    assert_eq!(
+        next.stake(&authorities[3].public_key()),
+        committee.stake(&authorities[3].public_key())
+    );
+
+    let workers = reconfiguration.worker_cache;
+    assert_eq!(workers.epoch(), committee.epoch() + 1);
+    assert!(workers.workers.get(&authorities[0].public_key()).is_none());
+    let joining_worker = joining.worker(0).info().clone();
+    assert_eq!(
+        workers.worker(&joining.public_key(), &0).unwrap(),
+        joining_worker
+    );
+    assert_eq!(
+        workers.worker(&authorities[2].public_key(), &0).unwrap(),
+        joining_worker
+    );
+    assert_eq!(
+        workers.worker(&authorities[3].public_key(), &0).unwrap(),
+        worker_cache
+            .worker(&authorities[3].public_key(), &0)
+            .unwrap()
+    );
+}
+
+#[test]
+fn test_invalid_committee_change() {
+    let fixture = CommitteeFixture::builder().build();
+    let committee = fixture.committee();
+    let worker_cache = fixture.worker_cache();
+    let authority = fixture.authorities().next().unwrap();
+
+    let change = |epoch, changes| ScheduledCommitteeChange {
+        epoch,
+        sub_dag_index: 10,
+        changes,
+    };
+
+    assert_eq!(
+        change(committee.epoch() + 1, vec![])
+            .apply(&committee, &worker_cache)
+            .unwrap_err(),
+        ReconfigurationError::InvalidEpoch {
+            expected: committee.epoch(),
+            received: committee.epoch() + 1
+        }
+    );
+
+    let removed = vec![CommitteeChange::RemoveAuthority {
+        protocol_key: authority.public_key(),
+    }];
+    assert!(
+        change(committee.epoch(), [removed.clone(), removed].concat())
+            .apply(&committee, &worker_cache)
+            .is_err()
+    );
+
+    let zero_stake = vec![CommitteeChange::UpdateStake {
+        protocol_key: authority.public_key(),
+        stake: 0,
+    }];
+    assert_eq!(
+        change(committee.epoch(), zero_stake)
+            .apply(&committee, &worker_cache)
+            .unwrap_err(),
+        ReconfigurationError::ZeroStake(authority.public_key().to_string())
+    );
+
+    let everyone_removed = fixture
+        .authorities()
+        .map(|authority| CommitteeChange::RemoveAuthority {
+            protocol_key: authority.public_key(),
+        })
+        .collect();
+    assert_eq!(
+        change(committee.epoch(), everyone_removed)
+            .apply(&committee, &worker_cache)
+            .unwrap_err(),
+        ReconfigurationError::EmptyCommittee
+    );
+}
+
+#[test]
+fn test_system_transaction() {
+    let fixture = CommitteeFixture::builder().build();
+    let committee = fixture.committee();
+    let authorities = fixture.authorities().collect::<Vec<_>>();
+
+    let change = ScheduledCommitteeChange {
+        epoch: committee.epoch(),
+        sub_dag_index: 10,
+        changes: vec![CommitteeChange::UpdateStake {
+            protocol_key: authorities[0].public_key(),
+            stake: 2,
+        }],
+    };
+    let signed_by = |signers: &[usize]| SystemTransaction::ScheduleCommitteeChange {
+        change: change.clone(),
+        signatures: signers
+            .iter()
+            .map(|i| {
+                let authority = authorities[*i];
+                (authority.id(), change.sign(authority.keypair().private()))
+            })
+            .collect(),
+    };
+
+    // The transactions of the clients are not system transactions.
+    assert!(SystemTransaction::from_transaction(&[0u8; 32]).is_none());
+
+    // A quorum of signatures is required.
+    let transaction = signed_by(&[0, 1, 2]).to_transaction();
+    let decoded = SystemTransaction::from_transaction(&transaction).unwrap();
+    assert!(decoded.verify(&committee).is_ok());
+
+    assert_eq!(
+        signed_by(&[0, 1]).verify(&committee).unwrap_err(),
+        ReconfigurationError::NoQuorum(2)
+    );
+    assert_eq!(
+        signed_by(&[0, 1, 1]).verify(&committee).unwrap_err(),
+        ReconfigurationError::DuplicateSigner(authorities[1].id())
+    );
+
+    // The signatures must be over the change.
+    let SystemTransaction::ScheduleCommitteeChange { signatures, .. } = signed_by(&[0, 1, 2]);
+    let forged = SystemTransaction::ScheduleCommitteeChange {
+        change: ScheduledCommitteeChange {
+            sub_dag_index: 20,
+            ..change.clone()
+        },
+        signatures,
+    };
+    assert_eq!(
+        forged.verify(&committee).unwrap_err(),
+        ReconfigurationError::InvalidSignature(authorities[0].id())
+    );
+
+    // The change must be scheduled after the sub dag it is found in.
+    let worker_cache = fixture.worker_cache();
+    assert!(decoded
+        .verify_scheduled_after(9, &committee, &worker_cache)
+        .is_ok());
+    assert_eq!(
+        decoded
+            .verify_scheduled_after(10, &committee, &worker_cache)
+            .unwrap_err(),
+        ReconfigurationError::ScheduledTooEarly {
+            found: 10,
+            scheduled: 10
+        }
+    );
+}
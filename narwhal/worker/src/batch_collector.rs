@@ -0,0 +1,161 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use config::PruningParameters;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use storage::BatchStore;
+use store::TypedStoreError;
+use tokio::{sync::mpsc, task::JoinHandle, time::interval};
+use tracing::{debug, error, info};
+use types::{
+    now, BatchDigest, ConditionalBroadcastReceiver, Round, TimestampMs, WorkerRoundUpdateMessage,
+};
+
+#[cfg(test)]
+#[path = "tests/batch_collector_tests.rs"]
+pub mod batch_collector_tests;
+
+/// The batch collector deletes from the batch store the batches that are no longer needed, whether
+/// they were never certified or committed more than `retention_rounds` below the GC round of
+/// consensus.
+///
+/// The batches reported as committed by the primary are deleted once their commit round falls out
+/// of the retention window. The worker does not know the round at which the other batches were
+/// created, so the rounds are mapped to the local time at which the primary first reported them
+/// as committed, and the batches stored before the commit of the highest round of the retention
+/// window are deleted, unless they are still pending in the proposer or committed within the
+/// window. The batches stored since the worker started are the only ones it can place in time, so
+/// nothing is deleted that way until a round of the retention window has been reported.
+pub struct BatchCollector {
+    /// The pruning configuration.
+    parameters: PruningParameters,
+    /// The batches to collect.
+    store: BatchStore,
+    /// Receiver for shutdown.
+    rx_shutdown: ConditionalBroadcastReceiver,
+    /// Receives the rounds reached by consensus and the batches committed or pending, reported by
+    /// the primary.
+    rx_round_updates: mpsc::Receiver<WorkerRoundUpdateMessage>,
+    /// The highest committed round reported.
+    committed_round: Round,
+    /// The highest GC round reported.
+    gc_round: Round,
+    /// The time at which each committed round was first reported.
+    committed_at: BTreeMap<Round, TimestampMs>,
+    /// The commit round of the batches reported as committed, until they are deleted.
+    committed_batches: HashMap<BatchDigest, Round>,
+    /// The batches pending in the proposer, as of the update of the highest committed round.
+    pending_batches: HashSet<BatchDigest>,
+}
+
+impl BatchCollector {
+    #[must_use]
+    pub fn spawn(
+        parameters: PruningParameters,
+        store: BatchStore,
+        rx_shutdown: ConditionalBroadcastReceiver,
+        rx_round_updates: mpsc::Receiver<WorkerRoundUpdateMessage>,
+    ) -> JoinHandle<()> {
+        let collector = Self {
+            parameters,
+            store,
+            rx_shutdown,
+            rx_round_updates,
+            committed_round: 0,
+            gc_round: 0,
+            committed_at: BTreeMap::new(),
+            committed_batches: HashMap::new(),
+            pending_batches: HashSet::new(),
+        };
+        tokio::spawn(collector.run())
+    }
+
+    async fn run(mut self) {
+        info!(
+            "Batch collector started with a retention of {} rounds",
+            self.parameters.retention_rounds
+        );
+        let mut timer = interval(self.parameters.interval);
+        loop {
+            tokio::select! {
+                Some(update) = self.rx_round_updates.recv() => {
+                    self.handle_round_update(update);
+                }
+
+                _ = timer.tick() => {
+                    if let Err(e) = self.collect() {
+                        error!("Failed to collect the batches of the batch store: {e}");
+                    }
+                }
+
+                _ = self.rx_shutdown.receiver.recv() => {
+                    return
+                }
+            }
+        }
+    }
+
+    /// Records the rounds and the batches of an update. The updates may be delivered out of
+    /// order, so only the highest rounds and the latest pending batches are kept.
+    fn handle_round_update(&mut self, update: WorkerRoundUpdateMessage) {
+        self.committed_at
+            .entry(update.committed_round)
+            .or_insert_with(now);
+        for digest in update.committed_batches {
+            let round = self.committed_batches.entry(digest).or_default();
+            *round = (*round).max(update.committed_round);
+        }
+        self.gc_round = self.gc_round.max(update.gc_round);
+        if update.committed_round >= self.committed_round {
+            self.committed_round = update.committed_round;
+            self.pending_batches = update.pending_batches.into_iter().collect();
+        }
+    }
+
+    /// Deletes the batches committed out of the retention window, then the batches stored before
+    /// the commit of the highest reported round that is out of the retention window, in batches
+    /// of bounded size.
+    fn collect(&mut self) -> Result<(), TypedStoreError> {
+        let round = self
+            .gc_round
+            .saturating_sub(self.parameters.retention_rounds);
+        if round == 0 {
+            return Ok(());
+        }
+
+        let (expired, retained) = std::mem::take(&mut self.committed_batches)
+            .into_iter()
+            .partition::<HashMap<_, _>, _>(|(_, committed_round)| *committed_round <= round);
+        self.committed_batches = retained;
+        let expired: Vec<_> = expired.into_keys().collect();
+        for digests in expired.chunks(self.parameters.max_certificates_per_batch) {
+            self.store.multi_remove(digests)?;
+        }
+
+        let (committed_round, committed_at) = match self.committed_at.range(..=round).next_back() {
+            Some((&committed_round, &committed_at)) => (committed_round, committed_at),
+            None => return Ok(()),
+        };
+
+        debug!("Collecting the batches stored before the commit of round {committed_round}");
+        let retained: HashSet<_> = self
+            .pending_batches
+            .iter()
+            .chain(self.committed_batches.keys())
+            .copied()
+            .collect();
+        loop {
+            let removed = self.store.remove_stored_before(
+                committed_at,
+                self.parameters.max_certificates_per_batch,
+                &retained,
+            )?;
+            if removed == 0 {
+                break;
+            }
+        }
+
+        // The earlier rounds will not be needed anymore.
+        self.committed_at = self.committed_at.split_off(&committed_round);
+        Ok(())
+    }
+}
@@ -15,28 +15,26 @@ use futures::{stream::FuturesUnordered, FutureExt, StreamExt};
 use itertools::Itertools;
 use network::WorkerRpc;
 use rand::{rngs::ThreadRng, seq::SliceRandom};
-use store::{rocks::DBMap, Map};
+use storage::BatchStore;
 use tokio::{
     select,
     time::{sleep, sleep_until, Instant},
 };
-use tracing::debug;
+use tracing::{debug, warn};
 use types::{Batch, BatchDigest, RequestBatchesRequest, RequestBatchesResponse};
 
 const REMOTE_PARALLEL_FETCH_INTERVAL: Duration = Duration::from_secs(2);
+/// The number of times all the known workers are tried before giving up on the missing batches.
+const MAX_FETCH_PASSES: usize = 3;
 
 pub struct BatchFetcher {
     name: NetworkPublicKey,
     network: Arc<dyn RequestBatchesNetwork>,
-    batch_store: DBMap<BatchDigest, Batch>,
+    batch_store: BatchStore,
 }
 
 impl BatchFetcher {
-    pub fn new(
-        name: NetworkPublicKey,
-        network: Network,
-        batch_store: DBMap<BatchDigest, Batch>,
-    ) -> Self {
+    pub fn new(name: NetworkPublicKey, network: Network, batch_store: BatchStore) -> Self {
         Self {
             name,
             network: Arc::new(RequestBatchesNetworkImpl { network }),
@@ -45,7 +43,9 @@ impl BatchFetcher {
     }
 
     /// Bulk fetches payload from local storage and remote workers.
-    /// This function performs infinite retries and blocks until all batches are available.
+    /// The known workers are tried `MAX_FETCH_PASSES` times. The batches none of them returned
+    /// are missing from the result, and it is up to the caller to retry them, possibly from
+    /// other workers.
     pub async fn fetch(
         &self,
         digests: HashSet<BatchDigest>,
@@ -65,7 +65,7 @@ impl BatchFetcher {
             .filter(|worker| worker != &self.name)
             .collect_vec();
 
-        loop {
+        for _ in 0..MAX_FETCH_PASSES {
             if remaining_digests.is_empty() {
                 return fetched_batches;
             }
@@ -103,9 +103,7 @@ impl BatchFetcher {
                             let new_batches: HashMap<_, _> = remote_batches.iter().filter(|(d, _)| remaining_digests.remove(d)).collect();
                             fetched_batches.extend(new_batches.iter().map(|(d, b)| (**d, (*b).clone())));
                             // Also persist the batches, so they are available after restarts.
-                            let mut write_batch = self.batch_store.batch();
-                            write_batch.insert_batch(&self.batch_store, new_batches).unwrap();
-                            write_batch.write().unwrap();
+                            self.batch_store.multi_insert(new_batches).unwrap();
                             if remaining_digests.is_empty() {
                                 return fetched_batches;
                             }
@@ -118,6 +116,18 @@ impl BatchFetcher {
             // After all known remote workers have been tried, restart the outer loop to fetch
             // from local storage then remain workers again.
         }
+
+        // The batches may have been stored locally in the meantime.
+        fetched_batches.extend(self.fetch_local(remaining_digests.clone()).await);
+        remaining_digests.retain(|d| !fetched_batches.contains_key(d));
+        if !remaining_digests.is_empty() {
+            warn!(
+                "Failed to fetch {} batches from {} workers after {MAX_FETCH_PASSES} attempts",
+                remaining_digests.len(),
+                known_workers.len()
+            );
+        }
+        fetched_batches
     }
 
     async fn fetch_local(&self, digests: HashSet<BatchDigest>) -> HashMap<BatchDigest, Batch> {
@@ -422,8 +432,52 @@ mod tests {
         assert_eq!(fetched_batches, expected_batches);
     }
 
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    pub async fn test_fetcher_unresponsive_workers() {
+        let batch_store = test_utils::create_batch_store();
+        let batch1 = Batch::new(vec![vec![1]]);
+        let batch2 = Batch::new(vec![vec![2]]);
+        batch_store.insert(&batch1.digest(), &batch1).unwrap();
+        let fetcher = BatchFetcher {
+            name: test_pk(0),
+            network: Arc::new(UnresponsiveRequestBatchesNetwork),
+            batch_store,
+        };
+        let digests = HashSet::from_iter(vec![batch1.digest(), batch2.digest()]);
+
+        // The fetcher gives up on the batch no worker returns, instead of blocking forever.
+        let fetched_batches = fetcher
+            .fetch(digests.clone(), HashSet::from_iter(test_pks(&[1, 2, 3])))
+            .await;
+        assert_eq!(
+            fetched_batches,
+            HashMap::from_iter(vec![(batch1.digest(), batch1.clone())])
+        );
+
+        // Also when there is no other worker to ask.
+        let fetched_batches = fetcher.fetch(digests, HashSet::new()).await;
+        assert_eq!(
+            fetched_batches,
+            HashMap::from_iter(vec![(batch1.digest(), batch1)])
+        );
+    }
+
     // TODO: add test for timeouts, failures and retries.
 
+    struct UnresponsiveRequestBatchesNetwork;
+
+    #[async_trait]
+    impl RequestBatchesNetwork for UnresponsiveRequestBatchesNetwork {
+        async fn request_batches(
+            &self,
+            _digests: Vec<BatchDigest>,
+            _worker: NetworkPublicKey,
+            _timeout: Duration,
+        ) -> anyhow::Result<RequestBatchesResponse> {
+            futures::future::pending().await
+        }
+    }
+
     #[derive(Clone)]
     struct TestRequestBatchesNetwork {
         // Worker name -> batch digests it has -> batches.
@@ -7,7 +7,7 @@ use crypto::Hash;
 use futures::stream::FuturesUnordered;
 use futures::{Future, StreamExt};
 use network::{client::NetworkClient, WorkerToPrimaryClient};
-use store::{rocks::DBMap, Map};
+use storage::BatchStore;
 use tokio::{
     sync::mpsc::{Receiver, Sender},
     task::JoinHandle,
@@ -15,8 +15,8 @@ use tokio::{
 };
 use tracing::{error, warn};
 use types::{
-    error::DagError, now, Batch, BatchAPI, BatchDigest, ConditionalBroadcastReceiver, Transaction,
-    TxResponse, WorkerOurBatchMessage,
+    error::DagError, now, Batch, BatchAPI, ConditionalBroadcastReceiver, Transaction, TxResponse,
+    WorkerOurBatchMessage,
 };
 
 #[cfg(feature = "trace_transaction")]
@@ -51,7 +51,7 @@ pub struct BatchMaker {
     /// The network client to send our batches to the primary.
     client: NetworkClient,
     /// The batch store to store our own batches.
-    store: DBMap<BatchDigest, Batch>,
+    store: BatchStore,
 }
 
 impl BatchMaker {
@@ -64,7 +64,7 @@ impl BatchMaker {
         rx_batch_maker: Receiver<(Transaction, TxResponse)>,
         tx_quorum_waiter: Sender<(Batch, tokio::sync::oneshot::Sender<()>)>,
         client: NetworkClient,
-        store: DBMap<BatchDigest, Batch>,
+        store: BatchStore,
     ) -> JoinHandle<()> {
         tokio::spawn(async move {
             Self {
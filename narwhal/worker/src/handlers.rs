@@ -11,13 +11,13 @@ use itertools::Itertools;
 use network::{client::NetworkClient, WorkerToPrimaryClient};
 use rand::seq::SliceRandom;
 use std::{collections::HashSet, time::Duration};
-use store::{rocks::DBMap, Map};
-use tokio::time::sleep;
+use storage::BatchStore;
+use tokio::{sync::mpsc, time::sleep};
 use tracing::{debug, trace, warn};
 use types::{
-    Batch, BatchDigest, FetchBatchesRequest, FetchBatchesResponse, PrimaryToWorker,
-    RequestBatchRequest, RequestBatchResponse, RequestBatchesRequest, RequestBatchesResponse,
-    WorkerBatchMessage, WorkerDeleteBatchesMessage, WorkerOthersBatchMessage,
+    FetchBatchesRequest, FetchBatchesResponse, PrimaryToWorker, RequestBatchRequest,
+    RequestBatchResponse, RequestBatchesRequest, RequestBatchesResponse, WorkerBatchMessage,
+    WorkerDeleteBatchesMessage, WorkerOthersBatchMessage, WorkerRoundUpdateMessage,
     WorkerSynchronizeMessage, WorkerToWorker, WorkerToWorkerClient,
 };
 
@@ -32,7 +32,7 @@ pub mod handlers_tests;
 pub struct WorkerReceiverHandler<V> {
     pub id: WorkerId,
     pub client: NetworkClient,
-    pub store: DBMap<BatchDigest, Batch>,
+    pub store: BatchStore,
     pub validator: V,
 }
 
@@ -128,7 +128,7 @@ pub struct PrimaryReceiverHandler<V> {
     // The worker information cache.
     pub worker_cache: WorkerCache,
     // The batch store
-    pub store: DBMap<BatchDigest, Batch>,
+    pub store: BatchStore,
     // Timeout on RequestBatch RPC.
     pub request_batch_timeout: Duration,
     // Number of random nodes to query when retrying batch requests.
@@ -139,6 +139,8 @@ pub struct PrimaryReceiverHandler<V> {
     pub batch_fetcher: Option<BatchFetcher>,
     // Validate incoming batches
     pub validator: V,
+    // Forward the rounds reached by consensus to the batch collector.
+    pub tx_round_updates: mpsc::Sender<WorkerRoundUpdateMessage>,
 }
 
 #[async_trait]
@@ -342,4 +344,14 @@ impl<V: TransactionValidator> PrimaryToWorker for PrimaryReceiverHandler<V> {
         }
         Ok(anemo::Response::new(()))
     }
+
+    async fn update_round(
+        &self,
+        request: anemo::Request<WorkerRoundUpdateMessage>,
+    ) -> Result<anemo::Response<()>, anemo::rpc::Status> {
+        // Every update is forwarded, since each one carries the batches committed since the
+        // previous one. They are dropped when the batch collector is not running.
+        let _ = self.tx_round_updates.send(request.into_body()).await;
+        Ok(anemo::Response::new(()))
+    }
 }
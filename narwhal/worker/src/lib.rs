@@ -8,6 +8,7 @@
     rust_2021_compatibility
 )]
 
+mod batch_collector;
 mod batch_fetcher;
 mod batch_maker;
 mod client;
@@ -22,4 +23,4 @@ pub use crate::tx_validator::{TransactionValidator, TrivialTransactionValidator}
 pub use crate::worker::Worker;
 
 /// The number of shutdown receivers to create on startup. We need one per component loop.
-pub const NUM_SHUTDOWN_RECEIVERS: u64 = 26;
+pub const NUM_SHUTDOWN_RECEIVERS: u64 = 28;
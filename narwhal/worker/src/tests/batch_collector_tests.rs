@@ -0,0 +1,91 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use super::*;
+
+use crate::NUM_SHUTDOWN_RECEIVERS;
+use crypto::Hash;
+use std::time::Duration;
+use test_utils::{create_batch_store, fixture_batch_with_transactions};
+use tokio::time::sleep;
+use types::{BatchAPI, PreSubscribedBroadcastSender};
+
+#[tokio::test]
+async fn collect_batches_out_of_retention_window() {
+    let store = create_batch_store();
+    let mut tx_shutdown = PreSubscribedBroadcastSender::new(NUM_SHUTDOWN_RECEIVERS);
+    let (tx_round_updates, rx_round_updates) = mpsc::channel(10);
+
+    // Batches stored before the rounds are reported. The creation time set by the author of a
+    // batch does not keep it.
+    let mut old_batch = fixture_batch_with_transactions(1);
+    old_batch.metadata_mut().created_at = now() + 1_000_000;
+    let pending_batch = fixture_batch_with_transactions(2);
+    let committed_batch = fixture_batch_with_transactions(3);
+    for batch in [&old_batch, &pending_batch, &committed_batch] {
+        store.insert(&batch.digest(), batch).unwrap();
+    }
+    sleep(Duration::from_millis(10)).await;
+
+    let parameters = PruningParameters {
+        enabled: true,
+        retention_rounds: 5,
+        interval: Duration::from_millis(100),
+        max_certificates_per_batch: 1,
+    };
+    let _handle = BatchCollector::spawn(
+        parameters,
+        store.clone(),
+        tx_shutdown.subscribe(),
+        rx_round_updates,
+    );
+
+    // Round 10 is committed but is still in the retention window.
+    tx_round_updates
+        .send(WorkerRoundUpdateMessage {
+            committed_round: 10,
+            gc_round: 5,
+            committed_batches: vec![],
+            pending_batches: vec![],
+        })
+        .await
+        .unwrap();
+    sleep(Duration::from_millis(300)).await;
+    assert!(store.contains_key(&old_batch.digest()).unwrap());
+
+    // A batch stored after round 10 is reported.
+    let new_batch = fixture_batch_with_transactions(4);
+    store.insert(&new_batch.digest(), &new_batch).unwrap();
+
+    // Round 10 falls out of the retention window. The batches stored before are deleted, except
+    // the pending one and the one committed within the window.
+    tx_round_updates
+        .send(WorkerRoundUpdateMessage {
+            committed_round: 20,
+            gc_round: 15,
+            committed_batches: vec![committed_batch.digest()],
+            pending_batches: vec![pending_batch.digest()],
+        })
+        .await
+        .unwrap();
+    sleep(Duration::from_millis(300)).await;
+    assert!(!store.contains_key(&old_batch.digest()).unwrap());
+    assert!(store.contains_key(&pending_batch.digest()).unwrap());
+    assert!(store.contains_key(&committed_batch.digest()).unwrap());
+    assert!(store.contains_key(&new_batch.digest()).unwrap());
+
+    // Round 20 falls out of the retention window, with the batch committed at that round and the
+    // batch stored before it was reported.
+    tx_round_updates
+        .send(WorkerRoundUpdateMessage {
+            committed_round: 30,
+            gc_round: 25,
+            committed_batches: vec![],
+            pending_batches: vec![pending_batch.digest()],
+        })
+        .await
+        .unwrap();
+    sleep(Duration::from_millis(300)).await;
+    assert!(store.contains_key(&pending_batch.digest()).unwrap());
+    assert!(!store.contains_key(&committed_batch.digest()).unwrap());
+    assert!(!store.contains_key(&new_batch.digest()).unwrap());
+}
@@ -11,12 +11,10 @@ use consensus::dag::Dag;
 use crypto::Hash;
 use futures::stream::FuturesOrdered;
 use futures::StreamExt;
-use primary::{NetworkModel, Primary, CHANNEL_CAPACITY, NUM_SHUTDOWN_RECEIVERS};
+use primary::{NetworkModel, Primary, PrimaryNetwork, CHANNEL_CAPACITY, NUM_SHUTDOWN_RECEIVERS};
 use std::time::Duration;
 use storage::NodeStorage;
-use store::rocks;
-use store::rocks::ReadWriteOptions;
-use test_utils::{batch, temp_dir, test_network, transaction, CommitteeFixture};
+use test_utils::{batch, create_batch_store, test_network, transaction, CommitteeFixture};
 use tokio::sync::watch;
 use types::Certificate;
 use types::{
@@ -57,13 +55,7 @@ async fn reject_invalid_clients_transactions() {
     };
 
     // Create a new test store.
-    let batch_store = rocks::DBMap::<BatchDigest, Batch>::open(
-        temp_dir(),
-        None,
-        Some("batches"),
-        &ReadWriteOptions::default(),
-    )
-    .unwrap();
+    let batch_store = create_batch_store();
 
     let mut tx_shutdown = PreSubscribedBroadcastSender::new(NUM_SHUTDOWN_RECEIVERS);
 
@@ -147,13 +139,7 @@ async fn handle_remote_clients_transactions() {
     };
 
     // Create a new test store.
-    let batch_store = rocks::DBMap::<BatchDigest, Batch>::open(
-        temp_dir(),
-        None,
-        Some("batches"),
-        &ReadWriteOptions::default(),
-    )
-    .unwrap();
+    let batch_store = create_batch_store();
 
     let mut tx_shutdown = PreSubscribedBroadcastSender::new(NUM_SHUTDOWN_RECEIVERS);
 
@@ -260,13 +246,7 @@ async fn handle_local_clients_transactions() {
     };
 
     // Create a new test store.
-    let batch_store = rocks::DBMap::<BatchDigest, Batch>::open(
-        temp_dir(),
-        None,
-        Some("batches"),
-        &ReadWriteOptions::default(),
-    )
-    .unwrap();
+    let batch_store = create_batch_store();
 
     let mut tx_shutdown = PreSubscribedBroadcastSender::new(NUM_SHUTDOWN_RECEIVERS);
 
@@ -366,7 +346,7 @@ async fn get_network_peers_from_admin_server() {
     let genesis_certs = Certificate::genesis(&committee.clone(), signer_1);
 
     // Make the data store.
-    let store = NodeStorage::reopen(temp_dir());
+    let store = NodeStorage::new_for_tests();
 
     let (tx_new_certificates, rx_new_certificates) =
         test_utils::test_new_certificates_channel!(CHANNEL_CAPACITY);
@@ -379,7 +359,12 @@ async fn get_network_peers_from_admin_server() {
     Primary::spawn(
         authority_1.authority().clone(),
         authority_1.keypair().clone(),
-        authority_1.network_keypair().copy(),
+        PrimaryNetwork::bind(
+            authority_1.authority(),
+            authority_1.network_keypair().copy(),
+            &committee,
+            &client_1,
+        ),
         committee.clone(),
         worker_cache.clone(),
         primary_1_parameters.clone(),
@@ -389,6 +374,8 @@ async fn get_network_peers_from_admin_server() {
         store.proposer_store.clone(),
         store.payload_store.clone(),
         store.vote_digest_store.clone(),
+        store.consensus_store.clone(),
+        store.execution_store.clone(),
         tx_new_certificates,
         rx_feedback,
         rx_consensus_round_updates,
@@ -500,7 +487,12 @@ async fn get_network_peers_from_admin_server() {
     Primary::spawn(
         authority_2.authority().clone(),
         signer_2,
-        authority_2.network_keypair().copy(),
+        PrimaryNetwork::bind(
+            authority_2.authority(),
+            authority_2.network_keypair().copy(),
+            &committee,
+            &client_2,
+        ),
         committee.clone(),
         worker_cache.clone(),
         primary_2_parameters.clone(),
@@ -510,6 +502,8 @@ async fn get_network_peers_from_admin_server() {
         store.proposer_store.clone(),
         store.payload_store.clone(),
         store.vote_digest_store.clone(),
+        store.consensus_store.clone(),
+        store.execution_store.clone(),
         tx_new_certificates_2,
         rx_feedback_2,
         rx_consensus_round_updates,
@@ -2,6 +2,7 @@
 // Copyright (c) Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
 use crate::{
+    batch_collector::BatchCollector,
     batch_fetcher::BatchFetcher,
     batch_maker::BatchMaker,
     handlers::{PrimaryReceiverHandler, WorkerReceiverHandler},
@@ -12,7 +13,6 @@ use anemo::{codegen::InboundRequestLayer, types::Address};
 use anemo::{types::PeerInfo, Network, PeerId};
 use anemo_tower::{
     auth::{AllowedPeers, RequireAuthorizationLayer},
-    callback::CallbackLayer,
     set_header::SetRequestHeaderLayer,
     trace::{DefaultMakeSpan, DefaultOnFailure, TraceLayer},
 };
@@ -22,18 +22,19 @@ use crypto::{NetworkKeyPair, NetworkPublicKey};
 use mysten_network::{multiaddr::Protocol, Multiaddr};
 use network::client::NetworkClient;
 use network::epoch_filter::{AllowedEpoch, EPOCH_HEADER_KEY};
-use network::failpoints::FailpointsMakeCallbackHandler;
+#[cfg(feature = "fault-injection")]
+use network::failpoints::{Direction, FaultInjectionLayer};
 use std::collections::HashMap;
 use std::time::Duration;
 use std::{net::Ipv4Addr, sync::Arc, thread::sleep};
-use store::rocks::DBMap;
+use storage::BatchStore;
 use tap::TapFallible;
 use tokio::{sync::mpsc, task::JoinHandle};
 use tower::ServiceBuilder;
 use tracing::{error, info};
 use types::{
-    Batch, BatchDigest, ConditionalBroadcastReceiver, PreSubscribedBroadcastSender,
-    PrimaryToWorkerServer, WorkerToWorkerServer,
+    ConditionalBroadcastReceiver, PreSubscribedBroadcastSender, PrimaryToWorkerServer,
+    WorkerToWorkerServer,
 };
 
 #[cfg(test)]
@@ -59,7 +60,7 @@ pub struct Worker {
     /// The configuration parameters
     parameters: Parameters,
     /// The persistent storage.
-    store: DBMap<BatchDigest, Batch>,
+    store: BatchStore,
 }
 
 impl Worker {
@@ -72,7 +73,7 @@ impl Worker {
         parameters: Parameters,
         validator: impl TransactionValidator,
         client: NetworkClient,
-        store: DBMap<BatchDigest, Batch>,
+        store: BatchStore,
         tx_shutdown: &mut PreSubscribedBroadcastSender,
     ) -> Vec<JoinHandle<()>> {
         let worker_name = keypair.public();
@@ -116,6 +117,8 @@ impl Worker {
             ));
         }
 
+        let (tx_round_updates, rx_round_updates) = mpsc::channel(CHANNEL_CAPACITY);
+
         // Legacy RPC interface, only used by delete_batches() for external consensus.
         let primary_service = PrimaryToWorkerServer::new(PrimaryReceiverHandler {
             authority_id: worker.authority.id(),
@@ -128,6 +131,7 @@ impl Worker {
             network: None,
             batch_fetcher: None,
             validator: validator.clone(),
+            tx_round_updates: tx_round_updates.clone(),
         });
 
         // Receive incoming messages from other workers.
@@ -162,26 +166,36 @@ impl Worker {
             )))
             .merge(primary_to_worker_router);
 
-        let service = ServiceBuilder::new()
-            .layer(
-                TraceLayer::new_for_server_errors()
-                    .make_span_with(DefaultMakeSpan::new().level(tracing::Level::INFO))
-                    .on_failure(DefaultOnFailure::new().level(tracing::Level::WARN)),
-            )
-            .layer(CallbackLayer::new(FailpointsMakeCallbackHandler::new()))
+        let service = ServiceBuilder::new().layer(
+            TraceLayer::new_for_server_errors()
+                .make_span_with(DefaultMakeSpan::new().level(tracing::Level::INFO))
+                .on_failure(DefaultOnFailure::new().level(tracing::Level::WARN)),
+        );
+        #[cfg(feature = "fault-injection")]
+        let service = service.layer(FaultInjectionLayer::new(
+            worker_peer_id,
+            Direction::Inbound,
+            client.fault_injector(),
+        ));
+        let service = service
             .layer(SetResponseHeaderLayer::overriding(
                 EPOCH_HEADER_KEY.parse().unwrap(),
                 epoch_string.clone(),
             ))
             .service(routes);
 
-        let outbound_layer = ServiceBuilder::new()
-            .layer(
-                TraceLayer::new_for_client_and_server_errors()
-                    .make_span_with(DefaultMakeSpan::new().level(tracing::Level::INFO))
-                    .on_failure(DefaultOnFailure::new().level(tracing::Level::WARN)),
-            )
-            .layer(CallbackLayer::new(FailpointsMakeCallbackHandler::new()))
+        let outbound_layer = ServiceBuilder::new().layer(
+            TraceLayer::new_for_client_and_server_errors()
+                .make_span_with(DefaultMakeSpan::new().level(tracing::Level::INFO))
+                .on_failure(DefaultOnFailure::new().level(tracing::Level::WARN)),
+        );
+        #[cfg(feature = "fault-injection")]
+        let outbound_layer = outbound_layer.layer(FaultInjectionLayer::new(
+            worker_peer_id,
+            Direction::Outbound,
+            client.fault_injector(),
+        ));
+        let outbound_layer = outbound_layer
             .layer(SetRequestHeaderLayer::overriding(
                 EPOCH_HEADER_KEY.parse().unwrap(),
                 epoch_string,
@@ -262,6 +276,7 @@ impl Worker {
                 network: Some(network.clone()),
                 batch_fetcher: Some(batch_fetcher),
                 validator: validator.clone(),
+                tx_round_updates,
             }),
         );
 
@@ -322,11 +337,19 @@ impl Worker {
             id, network_admin_server_base_port
         );
 
+        #[cfg(not(feature = "fault-injection"))]
         let admin_handles = network::admin::start_admin_server(
             network_admin_server_base_port,
             network.clone(),
             shutdown_receivers.pop().unwrap(),
         );
+        #[cfg(feature = "fault-injection")]
+        let admin_handles = network::admin::start_admin_server_with_faults(
+            network_admin_server_base_port,
+            network.clone(),
+            client.fault_injector(),
+            shutdown_receivers.pop().unwrap(),
+        );
 
         let client_flow_handles = worker.handle_clients_transactions(
             vec![
@@ -356,6 +379,16 @@ impl Worker {
         let mut handles = vec![connection_monitor_handle, network_shutdown_handle];
         handles.extend(admin_handles);
         handles.extend(client_flow_handles);
+
+        // Delete the batches which fell out of the retention window.
+        if parameters.pruning.enabled {
+            handles.push(BatchCollector::spawn(
+                parameters.pruning.clone(),
+                worker.store.clone(),
+                shutdown_receivers.pop().unwrap(),
+                rx_round_updates,
+            ));
+        }
         handles
     }
 